@@ -0,0 +1,268 @@
+//! Description and validation of effectors' configuration sections
+
+use std::fmt;
+
+/// The type of a value expected in a configuration section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// An integer in the given inclusive range
+    Integer {
+        /// The smallest allowed value
+        min: i64,
+        /// The largest allowed value
+        max: i64,
+    },
+    /// A string
+    String,
+    /// An array of strings
+    StringArray,
+    /// A boolean
+    Boolean,
+}
+
+impl ValueType {
+    fn check(&self, value: &toml::Value) -> Result<(), String> {
+        match (self, value) {
+            (ValueType::Integer { min, max }, toml::Value::Integer(i)) => {
+                if i < min || i > max {
+                    Err(format!("must be between {} and {}, not {}", min, max, i))
+                } else {
+                    Ok(())
+                }
+            }
+            (ValueType::String, toml::Value::String(_)) => Ok(()),
+            (ValueType::Boolean, toml::Value::Boolean(_)) => Ok(()),
+            (ValueType::StringArray, toml::Value::Array(array)) => {
+                match array.iter().position(|item| !item.is_str()) {
+                    Some(i) => Err(format!(
+                        "must be an array of strings, but item {} is {}",
+                        i,
+                        value_type_name(&array[i])
+                    )),
+                    None => Ok(()),
+                }
+            }
+            (expected, found) => Err(format!(
+                "must be {}, not {}",
+                expected,
+                value_type_name(found)
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Integer { .. } => write!(f, "an integer"),
+            ValueType::String => write!(f, "a string"),
+            ValueType::StringArray => write!(f, "an array of strings"),
+            ValueType::Boolean => write!(f, "a boolean"),
+        }
+    }
+}
+
+fn value_type_name(value: &toml::Value) -> &'static str {
+    match value {
+        toml::Value::String(_) => "a string",
+        toml::Value::Integer(_) => "an integer",
+        toml::Value::Float(_) => "a float",
+        toml::Value::Boolean(_) => "a boolean",
+        toml::Value::Datetime(_) => "a datetime",
+        toml::Value::Array(_) => "an array",
+        toml::Value::Table(_) => "a table",
+    }
+}
+
+/// A key in a configuration section
+#[derive(Debug, Clone)]
+pub struct ConfigKey {
+    /// Name of the key
+    pub name: &'static str,
+    /// The type of the key's value
+    pub value_type: ValueType,
+    /// Whether the key must be present
+    pub required: bool,
+}
+
+/// A problem found when validating a configuration section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// Path to the offending key or section, e.g. `brightness.dim_percentage`
+    pub path: String,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Description of the configuration section of an effector
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSchema {
+    section_required: bool,
+    any_keys: bool,
+    keys: Vec<ConfigKey>,
+}
+
+impl ConfigSchema {
+    /// Create a schema of a section which may be omitted and has no keys
+    pub fn new() -> ConfigSchema {
+        ConfigSchema::default()
+    }
+
+    /// Require the section to be present
+    pub fn required(mut self) -> ConfigSchema {
+        self.section_required = true;
+        self
+    }
+
+    /// Add a key which must be present if the section is present
+    pub fn key(mut self, name: &'static str, value_type: ValueType) -> ConfigSchema {
+        self.keys.push(ConfigKey {
+            name,
+            value_type,
+            required: true,
+        });
+        self
+    }
+
+    /// Add a key which may be omitted
+    pub fn optional_key(mut self, name: &'static str, value_type: ValueType) -> ConfigSchema {
+        self.keys.push(ConfigKey {
+            name,
+            value_type,
+            required: false,
+        });
+        self
+    }
+
+    /// Accept keys which aren't described by the schema, for sections
+    /// validated elsewhere
+    pub fn allow_any_keys(mut self) -> ConfigSchema {
+        self.any_keys = true;
+        self
+    }
+
+    /// Get the keys of the section
+    pub fn keys(&self) -> &[ConfigKey] {
+        &self.keys
+    }
+
+    /// Validate the section called `section`, returning all the problems
+    /// found in it
+    pub fn validate(&self, section: &str, config: Option<&toml::Value>) -> Vec<SchemaViolation> {
+        let violation = |path: String, message: String| SchemaViolation { path, message };
+        let table = match config {
+            None if self.section_required => {
+                return vec![violation(
+                    section.to_owned(),
+                    format!("[{}] section must be provided in config", section),
+                )]
+            }
+            None => return Vec::new(),
+            Some(toml::Value::Table(table)) => table,
+            Some(other) => {
+                return vec![violation(
+                    section.to_owned(),
+                    format!("must be a table, not {}", value_type_name(other)),
+                )]
+            }
+        };
+        let mut violations = Vec::new();
+        for key in self.keys.iter() {
+            match table.get(key.name) {
+                Some(value) => {
+                    if let Err(message) = key.value_type.check(value) {
+                        violations.push(violation(format!("{}.{}", section, key.name), message));
+                    }
+                }
+                None if key.required => violations.push(violation(
+                    format!("{}.{}", section, key.name),
+                    "is required, but missing".to_owned(),
+                )),
+                None => {}
+            }
+        }
+        for name in table.keys() {
+            if !self.any_keys && !self.keys.iter().any(|key| key.name == name) {
+                let known: Vec<&str> = self.keys.iter().map(|key| key.name).collect();
+                let message = if known.is_empty() {
+                    "unknown key, this section has no keys".to_owned()
+                } else {
+                    format!("unknown key, known keys are {}", known.join(", "))
+                };
+                violations.push(violation(format!("{}.{}", section, name), message));
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema() -> ConfigSchema {
+        ConfigSchema::new()
+            .required()
+            .key("command", ValueType::String)
+            .optional_key("args", ValueType::StringArray)
+            .optional_key("percentage", ValueType::Integer { min: 0, max: 100 })
+    }
+
+    fn paths(violations: Vec<SchemaViolation>) -> Vec<String> {
+        violations.into_iter().map(|v| v.path).collect()
+    }
+
+    #[test]
+    fn test_valid_section() {
+        let config = toml::toml! {
+            command = "i3lock"
+            args = ["-n"]
+            percentage = 100
+        };
+        assert!(schema().validate("lock", Some(&config)).is_empty());
+        assert!(ConfigSchema::new().validate("dpms", None).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(paths(schema().validate("lock", None)), vec!["lock"]);
+        assert_eq!(
+            paths(schema().validate("lock", Some(&toml::Value::Integer(5)))),
+            vec!["lock"]
+        );
+        let config = toml::toml! {
+            args = ["-n", 5]
+            percentage = 101
+            comand = "i3lock"
+        };
+        let violations = schema().validate("lock", Some(&config));
+        assert_eq!(
+            paths(violations.clone()),
+            vec![
+                "lock.command",
+                "lock.args",
+                "lock.percentage",
+                "lock.comand"
+            ]
+        );
+        assert_eq!(
+            violations[2].to_string(),
+            "lock.percentage: must be between 0 and 100, not 101"
+        );
+        assert_eq!(
+            violations[3].to_string(),
+            "lock.comand: unknown key, known keys are command, args, percentage"
+        );
+        let wrong_type = toml::toml! { command = 5 };
+        assert_eq!(
+            schema().validate("lock", Some(&wrong_type))[0].to_string(),
+            "lock.command: must be a string, not an integer"
+        );
+    }
+}
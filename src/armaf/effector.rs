@@ -1,10 +1,7 @@
 //! Type definitions for implementation of Effectors.
 
-use super::ActorPort;
-use crate::external::{
-    brightness::BrightnessController, dependency_provider::DependencyProvider,
-    display_server::DisplayServer,
-};
+use super::{ActorPort, ConfigSchema};
+use crate::external::dependency_provider::DependencyProvider;
 use anyhow::Result;
 use async_trait::async_trait;
 use logind_zbus::manager::InhibitType;
@@ -18,17 +15,97 @@ pub enum EffectorMessage {
     Rollback,
     /// Get the number of currently applied effects
     CurrentlyAppliedEffects,
+    /// Make sure the effect is applied, checking the state of the system if
+    /// possible, and execute it only if it isn't. Unlike
+    /// [EffectorMessage::Execute], this doesn't fail if the effect is already
+    /// applied, e.g. by a previous schedule.
+    EnsureApplied,
+    /// Make sure the effect isn't applied, rolling it back only if it is.
+    /// Unlike [EffectorMessage::Rollback], this doesn't fail if the effect
+    /// isn't applied.
+    EnsureRolledBack,
+    /// Check whether the effect can be executed now, without executing it,
+    /// failing with the reason if it can't, e.g. because the locker isn't
+    /// installed
+    CanExecute,
+}
+
+/// What an effector can do, reported in each [EffectorResponse]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectorCapabilities {
+    /// Rolling back undoes the effect, e.g. restores the brightness
+    pub can_roll_back: bool,
+    /// Rolling back waits for the user or the system to undo the effect, e.g.
+    /// for the computer to be unlocked or woken up, so it may take arbitrarily
+    /// long
+    pub rollback_waits: bool,
+}
+
+impl Default for EffectorCapabilities {
+    fn default() -> Self {
+        EffectorCapabilities {
+            can_roll_back: true,
+            rollback_waits: false,
+        }
+    }
+}
+
+impl EffectorCapabilities {
+    /// Capabilities of effectors whose rollback waits for the effect to be
+    /// undone by someone else
+    pub fn waiting_rollback() -> EffectorCapabilities {
+        EffectorCapabilities {
+            can_roll_back: false,
+            rollback_waits: true,
+        }
+    }
+}
+
+/// The response of an effector to an [EffectorMessage]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectorResponse {
+    /// Number of the effector's effects which are currently applied
+    pub applied_effects: usize,
+    /// Human-readable description of the effector's state, if it has anything
+    /// to say beyond the number of applied effects
+    pub status: Option<String>,
+    /// What the effector can do
+    pub capabilities: EffectorCapabilities,
+}
+
+impl EffectorResponse {
+    /// Create a response with the given number of applied effects, without
+    /// a status and with default capabilities
+    pub fn new(applied_effects: usize) -> EffectorResponse {
+        EffectorResponse {
+            applied_effects,
+            status: None,
+            capabilities: EffectorCapabilities::default(),
+        }
+    }
+
+    /// Describe the state of the effector
+    pub fn with_status(mut self, status: impl Into<String>) -> EffectorResponse {
+        self.status = Some(status.into());
+        self
+    }
+
+    /// Report different capabilities than the default ones
+    pub fn with_capabilities(mut self, capabilities: EffectorCapabilities) -> EffectorResponse {
+        self.capabilities = capabilities;
+        self
+    }
 }
 
 /// The ActorPort used to control a runnning effector
 ///
 /// When an effect is successfully executed or rolled back, the effector should
-/// return the number of currently applied effects. Any error that occurs should
-/// be wrapped in an [anyhow::Error].
-pub type EffectorPort = ActorPort<EffectorMessage, usize, anyhow::Error>;
+/// return an [EffectorResponse] with the number of currently applied effects.
+/// Any error that occurs should be wrapped in an [anyhow::Error].
+pub type EffectorPort = ActorPort<EffectorMessage, EffectorResponse, anyhow::Error>;
 
 /// The way in which an effect should be rolled back
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RollbackStrategy {
     /// Roll the effect back after the user becomes active
     OnActivity,
@@ -39,6 +116,29 @@ pub enum RollbackStrategy {
     None,
 }
 
+impl RollbackStrategy {
+    /// Get the strategy with the given name, as used in the configuration
+    pub fn from_config_name(name: &str) -> Option<RollbackStrategy> {
+        match name {
+            "on_activity" => Some(RollbackStrategy::OnActivity),
+            "immediate" => Some(RollbackStrategy::Immediate),
+            "none" => Some(RollbackStrategy::None),
+            _ => None,
+        }
+    }
+}
+
+/// Get the logind inhibit type with the given name, as used in the
+/// configuration
+pub fn inhibit_type_from_config_name(name: &str) -> Option<InhibitType> {
+    match name {
+        "idle" => Some(InhibitType::Idle),
+        "sleep" => Some(InhibitType::Sleep),
+        "shutdown" => Some(InhibitType::Shutdown),
+        _ => None,
+    }
+}
+
 /// An action that an effector can perform
 #[derive(Debug, Clone)]
 pub struct Effect {
@@ -68,21 +168,25 @@ impl Effect {
 /// A descriptor of an effector, allows getting the available effects and spawning the effector
 #[async_trait]
 pub trait Effector: Send + Sync + 'static {
-    // The Self: Sized constraints on each method are to make this trait object-safe,
-    // since storing effectors as trait objects is its basic rationale
+    /// Name of the effector, which is also the name of its configuration
+    /// section
+    fn get_name(&self) -> String;
 
     /// Get a list of effects the effector can provide, in the order they will be applied
-    fn get_effects(&self) -> Vec<Effect>
-    where
-        Self: Sized;
+    fn get_effects(&self) -> Vec<Effect>;
+
+    /// Get the schema of the effector's configuration section, used to
+    /// validate the configuration before the effector gets spawned. Default
+    /// implementation describes a section without any keys.
+    fn get_config_schema(&self) -> ConfigSchema {
+        ConfigSchema::new()
+    }
 
     /// Parse the configuration of the effector, fetch its dependencies and
     /// spawn the Tokio task representing its actor
-    async fn spawn<B: BrightnessController, D: DisplayServer>(
+    async fn spawn(
         &self,
         config: Option<toml::Value>,
-        provider: &mut DependencyProvider<B, D>,
-    ) -> Result<EffectorPort>
-    where
-        Self: Sized;
+        provider: &mut DependencyProvider,
+    ) -> Result<EffectorPort>;
 }
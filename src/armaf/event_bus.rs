@@ -0,0 +1,98 @@
+//! Publishing of events to any number of interested actors
+
+use super::ActorPort;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{broadcast, oneshot};
+
+/// A set of [broadcast] channels, one for each type of event.
+///
+/// Actors publish events and subscribe to them by their type, so the
+/// publisher and the subscribers only have to share the bus instead of being
+/// wired to each other. The channel for an event type is created when it's
+/// first used and lives as long as the bus, so subscribing before the
+/// publisher is spawned is fine.
+///
+/// Like with any [broadcast] channel, a subscriber which doesn't keep up with
+/// the events loses the oldest ones. Values of which only the latest one is
+/// interesting, such as the power source, are better distributed through a
+/// [watch](tokio::sync::watch) channel.
+#[derive(Clone)]
+pub struct EventBus {
+    capacity: usize,
+    channels: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+}
+
+impl EventBus {
+    /// Create a new bus, whose channels can each hold the given number of
+    /// events not received by all subscribers yet
+    pub fn new(capacity: usize) -> EventBus {
+        EventBus {
+            capacity,
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Get a sender for events of the given type, for publishers which
+    /// expect a [broadcast::Sender]
+    pub fn sender<T: Clone + Send + 'static>(&self) -> broadcast::Sender<T> {
+        let mut channels = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+        channels
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(broadcast::channel::<T>(self.capacity).0))
+            .downcast_ref::<broadcast::Sender<T>>()
+            .expect("Event bus channel has a wrong type")
+            .clone()
+    }
+
+    /// Publish an event, returning the number of subscribers which will
+    /// receive it
+    pub fn publish<T: Clone + Send + 'static>(&self, event: T) -> usize {
+        self.sender().send(event).unwrap_or(0)
+    }
+
+    /// Subscribe to the events of the given type published from now on
+    pub fn subscribe<T: Clone + Send + 'static>(&self) -> broadcast::Receiver<T> {
+        self.sender().subscribe()
+    }
+
+    /// Send each event of the given type to an actor as a request, until the
+    /// returned [Subscription] is dropped
+    pub fn forward<T, E>(&self, destination_port: ActorPort<T, (), E>) -> Subscription
+    where
+        T: Clone + Send + 'static,
+        E: Send + 'static + Debug,
+    {
+        let mut receiver = self.subscribe::<T>();
+        let (drop_sender, mut drop_receiver) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = &mut drop_receiver => return,
+                    event = receiver.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        if let Err(e) = destination_port.request(event).await {
+                            log::error!("Destination actor returned an error: {:?}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        log::warn!("Destination actor missed {} events", count);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        Subscription { _stop: drop_sender }
+    }
+}
+
+/// Keeps events being forwarded to an actor, see [EventBus::forward]
+pub struct Subscription {
+    _stop: oneshot::Sender<()>,
+}
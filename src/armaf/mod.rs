@@ -12,9 +12,16 @@
 //! penalty. It will probably be negligible for your use-case, but there is
 //! still the option of working with [ActorPort]s directly.
 
+mod config_schema;
 mod effector;
+mod event_bus;
 mod ports;
+mod registry;
 mod server;
+mod shutdown;
+mod supervisor;
+mod tick;
+mod trace;
 
 #[doc(inline)]
 pub use ports::*;
@@ -25,8 +32,47 @@ pub use server::*;
 //#[doc(inline)]
 pub use effector::*;
 
+#[doc(inline)]
+pub use config_schema::*;
+
+#[doc(inline)]
+pub use event_bus::*;
+
+#[doc(inline)]
+pub use supervisor::*;
+
+#[doc(inline)]
+pub use tick::*;
+
+#[doc(inline)]
+pub use trace::*;
+
+#[doc(inline)]
+pub use registry::*;
+
+#[doc(inline)]
+pub use shutdown::*;
+
+#[cfg(test)]
+mod test_event_bus;
+
 #[cfg(test)]
 mod test_ports;
 
+#[cfg(test)]
+mod test_registry;
+
 #[cfg(test)]
 mod test_server;
+
+#[cfg(test)]
+mod test_shutdown;
+
+#[cfg(test)]
+mod test_supervisor;
+
+#[cfg(test)]
+mod test_tick;
+
+#[cfg(test)]
+mod test_trace;
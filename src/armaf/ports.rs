@@ -1,37 +1,207 @@
 //! Basic primitives for constructing a simple actor system on top of Tokio tasks.
 
+use super::TraceId;
 use std::{fmt::Debug, result::Result};
 use thiserror::Error;
 use tokio::sync::{mpsc, mpsc::error::SendError, oneshot, watch};
 
+/// Number of requests which can wait in an actor's queue before the
+/// requesters have to wait for a free slot
+const MAILBOX_SIZE: usize = 8;
+
 /// A shorthand type defining a [oneshot::Receiver] which is used to receive the
 /// results of an operation invoked by a [Request].
 type ResponseReceiver<R, E> = oneshot::Receiver<Result<R, E>>;
 
+/// Responses to the requests of a [FastPort], numbered by the request. [None]
+/// is sent if the request is dropped without a response.
+type ReusedResponse<R, E> = (u64, Option<Result<R, E>>);
+
+/// Allows the handler of a [Request] to find out that the requester is no
+/// longer interested in its result and may stop working on it.
+#[derive(Debug, Clone)]
+pub struct CancellationToken(Option<watch::Receiver<bool>>);
+
+impl CancellationToken {
+    /// A token of a request which can't be cancelled
+    pub fn never() -> CancellationToken {
+        CancellationToken(None)
+    }
+
+    /// Whether the request has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0
+            .as_ref()
+            .map(|receiver| *receiver.borrow())
+            .unwrap_or(false)
+    }
+
+    /// Wait until the request gets cancelled. Never returns for requests which
+    /// were completed or can't be cancelled, so it should be used within a
+    /// [tokio::select!] block.
+    pub async fn cancelled(&self) {
+        let mut receiver = match self.0.clone() {
+            Some(receiver) => receiver,
+            None => return std::future::pending().await,
+        };
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // The canceller was disarmed
+                return std::future::pending().await;
+            }
+        }
+    }
+}
+
+/// The requester's side of a [CancellationToken].
+///
+/// The request gets cancelled once the canceller is dropped, unless it was
+/// [disarmed](RequestCanceller::disarm) first.
+#[derive(Debug)]
+pub struct RequestCanceller(Option<watch::Sender<bool>>);
+
+impl RequestCanceller {
+    /// Cancel the request
+    pub fn cancel(self) {}
+
+    /// Keep the request from being cancelled, e.g. after its response was
+    /// received
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for RequestCanceller {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.take() {
+            let _ = sender.send(true);
+        }
+    }
+}
+
+/// The sending side of the channel on which the response to a [Request] is
+/// sent.
+///
+/// If it's dropped without a response being sent while the requester is still
+/// waiting, e.g. because the actor dropped the request while handling it or
+/// terminated with requests still in its queue, the request is logged as a
+/// dead letter.
+#[derive(Debug)]
+pub struct Responder<R, E> {
+    sender: Option<ResponseSender<R, E>>,
+    payload_type: &'static str,
+    actor_name: Option<String>,
+}
+
+#[derive(Debug)]
+enum ResponseSender<R, E> {
+    Oneshot(oneshot::Sender<Result<R, E>>),
+    /// The channel of a [FastPort] and the number of the request
+    Reused(mpsc::UnboundedSender<ReusedResponse<R, E>>, u64),
+}
+
+impl<R, E> Responder<R, E> {
+    fn new<P>(sender: ResponseSender<R, E>) -> Responder<R, E> {
+        Responder {
+            sender: Some(sender),
+            payload_type: std::any::type_name::<P>(),
+            actor_name: None,
+        }
+    }
+
+    /// Send the response, returning it back if the requester went away
+    pub fn send(mut self, response: Result<R, E>) -> Result<(), Result<R, E>> {
+        match self.sender.take().unwrap() {
+            ResponseSender::Oneshot(sender) => sender.send(response),
+            ResponseSender::Reused(sender, id) => sender
+                .send((id, Some(response)))
+                .map_err(|e| (e.0).1.unwrap()),
+        }
+    }
+
+    /// Whether the requester went away
+    pub fn is_closed(&self) -> bool {
+        match &self.sender {
+            Some(ResponseSender::Oneshot(sender)) => sender.is_closed(),
+            Some(ResponseSender::Reused(sender, _)) => sender.is_closed(),
+            None => true,
+        }
+    }
+
+    /// Set the name of the actor handling the request, which is logged if the
+    /// request turns into a dead letter
+    pub fn set_actor_name(&mut self, actor_name: &str) {
+        self.actor_name = Some(actor_name.to_owned());
+    }
+}
+
+impl<R, E> Drop for Responder<R, E> {
+    fn drop(&mut self) {
+        if self.is_closed() {
+            return;
+        }
+        log::warn!(
+            "Dead letter: {} dropped a {} request without responding to it",
+            self.actor_name.as_deref().unwrap_or("an actor"),
+            self.payload_type
+        );
+        // Unlike a oneshot channel, the reused one stays open
+        if let Some(ResponseSender::Reused(sender, id)) = self.sender.take() {
+            let _ = sender.send((id, None));
+        }
+    }
+}
+
 /// A request sent to an actor.
 ///
 /// A Request contains a generic payload which has to match the payload accepted
-/// by the [ActorPort] and a [oneshot] channel on which the result of the
-/// operation or an error will be returned.
+/// by the [ActorPort], a [oneshot] channel on which the result of the
+/// operation or an error will be returned and a [CancellationToken] through
+/// which the actor learns that the requester has given up on the request. The
+/// [TraceId] of the request allows following it in the logs.
 pub struct Request<P, R, E> {
     pub payload: P,
-    pub response_sender: oneshot::Sender<Result<R, E>>,
+    pub response_sender: Responder<R, E>,
+    pub cancellation: CancellationToken,
+    pub trace_id: TraceId,
 }
 
 impl<P, R, E> Request<P, R, E> {
     /// Creates a new [Request], populating the struct with the given payload and
-    /// constructing a correctly typed [oneshot] channel. The created
-    /// [oneshot::Sender] is stored inside the request, while the
-    /// [ResponseReceiver] is returned.
+    /// the current [TraceId] and constructing a correctly typed [oneshot]
+    /// channel. The created [Responder] is stored inside the request, while
+    /// the [ResponseReceiver] is returned.
     pub fn new(payload: P) -> (Request<P, R, E>, ResponseReceiver<R, E>) {
         let (response_sender, response_receiver) = oneshot::channel();
-        let request = Request {
+        let request = Request::with_responder(
             payload,
-            response_sender,
-        };
+            Responder::new::<P>(ResponseSender::Oneshot(response_sender)),
+        );
         (request, response_receiver)
     }
 
+    fn with_responder(payload: P, response_sender: Responder<R, E>) -> Request<P, R, E> {
+        Request {
+            payload,
+            response_sender,
+            cancellation: CancellationToken::never(),
+            trace_id: TraceId::current_or_new(),
+        }
+    }
+
+    /// Creates a new [Request] like [Request::new], which can be cancelled
+    /// using the returned [RequestCanceller].
+    pub fn cancellable(payload: P) -> (Request<P, R, E>, ResponseReceiver<R, E>, RequestCanceller) {
+        let (mut request, response_receiver) = Request::new(payload);
+        let (cancel_sender, cancel_receiver) = watch::channel(false);
+        request.cancellation = CancellationToken(Some(cancel_receiver));
+        (
+            request,
+            response_receiver,
+            RequestCanceller(Some(cancel_sender)),
+        )
+    }
+
     /// A convenience method for sending a response on the [Request]'s [oneshot]
     /// channel.
     pub fn respond(self, response: Result<R, E>) -> Result<(), Result<R, E>> {
@@ -112,7 +282,7 @@ impl<P, R, E: Debug> ActorPort<P, R, E> {
     /// actor initialization. The Receiver is moved into the [tokio::task] for
     /// the actor while the ActorPort is returned to the caller.
     pub fn make() -> (ActorPort<P, R, E>, ActorReceiver<P, R, E>) {
-        let (req_tx, req_rx) = mpsc::channel::<Request<P, R, E>>(8);
+        let (req_tx, req_rx) = mpsc::channel::<Request<P, R, E>>(MAILBOX_SIZE);
         let (shutdown_tx, shutdown_rx) = watch::channel(());
         (
             ActorPort::new(req_tx, shutdown_rx),
@@ -149,14 +319,17 @@ impl<P, R, E: Debug> ActorPort<P, R, E> {
 
     /// Constructs a [Request] with the given payload sends it on this port and
     /// waits for the actor's response.
-    /// Constructs a [Request] with the given payload sends it on this port and
-    /// waits for the actor's response.
+    ///
+    /// If the returned future is dropped before the response arrives, e.g.
+    /// because of a timeout, the request gets cancelled.
     pub async fn request(&self, payload: P) -> Result<R, ActorRequestError<E>> {
-        let (req, rx) = Request::new(payload);
+        let (req, rx, canceller) = Request::cancellable(payload);
         if self.raw_request(req).await.is_err() {
             return Err(ActorRequestError::Send);
         }
-        match rx.await {
+        let response = rx.await;
+        canceller.disarm();
+        match response {
             Err(_) => Err(ActorRequestError::Recv),
             Ok(inner_result) => match inner_result {
                 Ok(response) => Ok(response),
@@ -165,6 +338,34 @@ impl<P, R, E: Debug> ActorPort<P, R, E> {
         }
     }
 
+    /// Create a [FastPort] sending its requests through this port
+    pub fn fast(&self) -> FastPort<P, R, E> {
+        let (response_sender, response_receiver) = mpsc::unbounded_channel();
+        FastPort {
+            port: self.clone(),
+            response_sender,
+            response_receiver,
+            next_id: 0,
+        }
+    }
+
+    /// Number of requests waiting in the actor's queue
+    pub fn mailbox_depth(&self) -> usize {
+        MAILBOX_SIZE.saturating_sub(self.message_sender.capacity())
+    }
+
+    /// Whether the actor has dropped its [ActorReceiver], so that it won't
+    /// handle any more requests
+    pub fn is_closed(&self) -> bool {
+        self.message_sender.is_closed()
+    }
+
+    /// Wait until the actor drops its [ActorReceiver], either because it has
+    /// terminated or because it has crashed
+    pub async fn closed(&self) {
+        self.message_sender.closed().await
+    }
+
     /// Await actor termination
     ///
     /// Drops this port's message sender and waits until all the other clones of
@@ -185,6 +386,53 @@ impl<P, R, E: Debug> ActorPort<P, R, E> {
     }
 }
 
+/// A port for requests sent often, e.g. on each change of the user's
+/// idleness, which receives all the responses on a single channel instead of
+/// allocating one for each request.
+///
+/// It sends one request at a time, so it isn't Clone, create one for each
+/// requester with [ActorPort::fast]. Its requests can't be cancelled, an
+/// abandoned request is still handled and its response is skipped once it
+/// arrives.
+#[derive(Debug)]
+pub struct FastPort<P, R, E: Debug> {
+    port: ActorPort<P, R, E>,
+    response_sender: mpsc::UnboundedSender<ReusedResponse<R, E>>,
+    response_receiver: mpsc::UnboundedReceiver<ReusedResponse<R, E>>,
+    next_id: u64,
+}
+
+impl<P, R, E: Debug> FastPort<P, R, E> {
+    /// Send a request to the actor and wait for its response, like
+    /// [ActorPort::request]
+    pub async fn request(&mut self, payload: P) -> Result<R, ActorRequestError<E>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let responder =
+            Responder::new::<P>(ResponseSender::Reused(self.response_sender.clone(), id));
+        if self
+            .port
+            .raw_request(Request::with_responder(payload, responder))
+            .await
+            .is_err()
+        {
+            return Err(ActorRequestError::Send);
+        }
+        // The port holds a sender, so the channel never closes
+        while let Some((response_id, response)) = self.response_receiver.recv().await {
+            if response_id != id {
+                continue;
+            }
+            return match response {
+                Some(Ok(response)) => Ok(response),
+                Some(Err(actor_error)) => Err(ActorRequestError::Actor(actor_error)),
+                None => Err(ActorRequestError::Recv),
+            };
+        }
+        Err(ActorRequestError::Recv)
+    }
+}
+
 /// The receiving side of an [ActorPort].
 ///
 /// Contains a [mpsc::Receiver] which can either be used directly or which can
@@ -0,0 +1,108 @@
+//! Process-wide registry of running actors, allowing to see which parts of
+//! the program are alive
+
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+
+/// The state of a registered actor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorHealth {
+    /// The actor is running
+    Running,
+    /// The actor's task has ended without the actor being shut down, most
+    /// likely because it has panicked
+    Crashed,
+}
+
+impl ActorHealth {
+    /// Name of the state used in external APIs
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActorHealth::Running => "running",
+            ActorHealth::Crashed => "crashed",
+        }
+    }
+}
+
+/// Information about a registered actor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActorInfo {
+    /// Identifier unique within the process
+    pub id: u64,
+    /// Name of the actor, as returned by [get_name](super::Server::get_name)
+    /// for servers
+    pub name: String,
+    /// When the actor was started
+    pub started_at: SystemTime,
+    /// Whether the actor is running
+    pub health: ActorHealth,
+}
+
+struct Registry {
+    last_id: u64,
+    actors: BTreeMap<u64, ActorInfo>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    last_id: 0,
+    actors: BTreeMap::new(),
+});
+
+/// Keeps an actor registered while the actor is running.
+///
+/// An actor should register itself once it's initialized and keep the
+/// registration until it terminates. If the registration is dropped without
+/// [Registration::unregister] being called, e.g. while the actor's task is
+/// unwinding from a panic, the actor is marked as [ActorHealth::Crashed] and
+/// stays listed.
+#[derive(Debug)]
+pub struct Registration {
+    id: u64,
+    unregistered: bool,
+}
+
+impl Registration {
+    /// Register a running actor with the given name
+    pub fn new(name: &str) -> Registration {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.last_id += 1;
+        let id = registry.last_id;
+        registry.actors.insert(
+            id,
+            ActorInfo {
+                id,
+                name: name.to_owned(),
+                started_at: SystemTime::now(),
+                health: ActorHealth::Running,
+            },
+        );
+        Registration {
+            id,
+            unregistered: false,
+        }
+    }
+
+    /// Remove the actor from the registry, since it has terminated properly
+    pub fn unregister(mut self) {
+        self.unregistered = true;
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.actors.remove(&self.id);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if self.unregistered {
+            return;
+        }
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(info) = registry.actors.get_mut(&self.id) {
+            info.health = ActorHealth::Crashed;
+        }
+    }
+}
+
+/// List the registered actors, in the order in which they were started
+pub fn list_actors() -> Vec<ActorInfo> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.actors.values().cloned().collect()
+}
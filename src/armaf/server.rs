@@ -1,10 +1,27 @@
 //! Server abstraction on top of [super::ports]
 
-use super::ActorPort;
+use super::{ActorPort, CancellationToken, Registration, Request};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 use tokio::sync::oneshot;
 
+tokio::task_local! {
+    static CURRENT_ACTOR: String;
+}
+
+/// Name of the server whose task is currently running, if any
+pub fn current_actor_name() -> Option<String> {
+    CURRENT_ACTOR.try_with(|name| name.clone()).ok()
+}
+
 /// A trait which allows you to write server code for Server-like Actors (which
 /// just receive requests on their ActorPorts and then respond to them) in a
 /// structured way. Servers run in Tokio tasks and have three lifecycle phases.
@@ -13,7 +30,12 @@ use tokio::sync::oneshot;
 /// until it has finished, either successfully or with an error.
 ///
 /// Then, in handling phase, handle_message is invoked to process each request
-/// sent to the [ActorPort] returned by [spawn_server].
+/// sent to the [ActorPort] returned by [spawn_server]. Requests cancelled
+/// before their handling starts are answered with an error without being
+/// handled.
+///
+/// While running, the server is listed in the actor registry, see
+/// [list_actors](super::list_actors).
 ///
 /// After all [ActorPort]s for the server get dropped, the teardown phase is
 /// entered. Server can perform any asynchronous clean up tasks it needs to do.
@@ -79,7 +101,7 @@ use tokio::sync::oneshot;
 /// }
 /// ```
 #[async_trait]
-pub trait Server<P, R>: Send + 'static {
+pub trait Server<P: Send + 'static, R>: Send + 'static {
     /// Returns the name of the Server, which is used in logging messages
     fn get_name(&self) -> String;
 
@@ -89,6 +111,46 @@ pub trait Server<P, R>: Send + 'static {
     /// [super::Request<P, R, E>::respond] method.
     async fn handle_message(&mut self, payload: P) -> Result<R>;
 
+    /// The longest time the handling of a request may take, unless
+    /// [Server::get_deadline] says otherwise. Default implementation doesn't
+    /// limit the handling time.
+    fn get_default_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The longest time the handling of the given request may take. Once it
+    /// passes, the handling is aborted and the requester gets an error, so
+    /// the server should be prepared for its handler to stop at any await
+    /// point. Default implementation returns [Server::get_default_deadline].
+    fn get_deadline(&self, _payload: &P) -> Option<Duration> {
+        self.get_default_deadline()
+    }
+
+    /// Whether the server should keep handling requests after it panics while
+    /// handling one. The requester of the request always gets an error.
+    ///
+    /// By default, the server stops without being torn down, since its state
+    /// can't be trusted anymore. If it's [supervised](super::spawn_supervised),
+    /// it then gets restarted. Servers whose state can't be broken by an
+    /// interrupted handler may return true.
+    fn continue_after_panic(&self) -> bool {
+        false
+    }
+
+    /// Handle a request which the requester may cancel while it's being
+    /// handled.
+    ///
+    /// Servers whose requests take long to handle should override this method
+    /// and stop the work once the token gets cancelled. Default implementation
+    /// ignores the token and calls [Server::handle_message].
+    async fn handle_cancellable_message(
+        &mut self,
+        payload: P,
+        _cancellation: CancellationToken,
+    ) -> Result<R> {
+        self.handle_message(payload).await
+    }
+
     /// Performs server initialization tasks.
     ///
     /// An error in this method will cause
@@ -126,7 +188,7 @@ where
     log::debug!("{} spawning", name);
     let (port, mut rx) = ActorPort::make();
     let (initialization_sender, initialization_receiver) = oneshot::channel::<Result<()>>();
-    tokio::task::spawn(async move {
+    tokio::task::spawn(CURRENT_ACTOR.scope(name.clone(), async move {
         let name = server.get_name();
         let init_result = server.initialize().await;
         let had_init_error = init_result.is_err();
@@ -137,18 +199,19 @@ where
             return;
         }
         log::info!("{} initialized successfully", name);
+        let registration = Registration::new(&name);
         loop {
             match rx.recv().await {
                 Some(req) => {
-                    let res = server.handle_message(req.payload).await;
-                    if let Err(e) = &res {
-                        log::error!("{} message handler returned error: {}", name, e);
-                    }
-                    if req.response_sender.send(res).is_err() {
-                        log::error!(
-                            "{} failed to respond to request (requester went away?)",
-                            name
-                        );
+                    let trace_id = req.trace_id;
+                    let keep_running = trace_id
+                        .scope(handle_request(&mut server, &name, req))
+                        .await;
+                    if !keep_running {
+                        // Dropping the registration without unregistering
+                        // marks the server as crashed
+                        log::error!("{} stopped after a panic", name);
+                        return;
                     }
                 }
                 None => {
@@ -157,11 +220,12 @@ where
                         log::error!("{} failed to tear down: {}", name, e);
                     }
                     log::debug!("{} stopped", name);
+                    registration.unregister();
                     return;
                 }
             }
         }
-    });
+    }));
 
     match initialization_receiver.await {
         Ok(Ok(_)) => Ok(port),
@@ -172,3 +236,101 @@ where
         Err(e) => Err(anyhow!(e)),
     }
 }
+
+/// Handle the request and respond to it, returning whether the server should
+/// keep running
+async fn handle_request<P, R>(
+    server: &mut impl Server<P, R>,
+    name: &str,
+    mut req: Request<P, R, anyhow::Error>,
+) -> bool
+where
+    P: Send + 'static,
+    R: Send + 'static,
+{
+    req.response_sender.set_actor_name(name);
+    if req.cancellation.is_cancelled() {
+        log::debug!("{} skipping a cancelled request", name);
+        let _ = req
+            .response_sender
+            .send(Err(anyhow!("Request was cancelled")));
+        return true;
+    }
+    let cancellation = req.cancellation.clone();
+    let deadline = server.get_deadline(&req.payload);
+    let handling = server.handle_cancellable_message(req.payload, req.cancellation);
+    let handling = async move {
+        match deadline {
+            Some(deadline) => tokio::time::timeout(deadline, handling)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow!(
+                        "handling of {} request exceeded the deadline of {:?}",
+                        std::any::type_name::<P>(),
+                        deadline
+                    ))
+                }),
+            None => handling.await,
+        }
+    };
+    let outcome = CatchUnwind(Box::pin(handling)).await;
+    let (res, keep_running) = match outcome {
+        Ok(res) => {
+            if let Err(e) = &res {
+                log::error!("{} message handler returned error: {}", name, e);
+            }
+            (res, true)
+        }
+        Err(panic) => {
+            let message = panic_message(panic.as_ref());
+            log::error!(
+                "{} panicked while handling a {} request: {}",
+                name,
+                std::any::type_name::<P>(),
+                message
+            );
+            (
+                Err(anyhow!("{} panicked: {}", name, message)),
+                server.continue_after_panic(),
+            )
+        }
+    };
+    if req.response_sender.send(res).is_err() {
+        if cancellation.is_cancelled() {
+            log::debug!("{} handled a request cancelled in the meantime", name);
+        } else {
+            log::error!(
+                "{} failed to respond to request (requester went away?)",
+                name
+            );
+        }
+    }
+    keep_running
+}
+
+/// Resolves to the output of the wrapped future, or to the payload of the
+/// panic raised while polling it
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.0;
+        match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
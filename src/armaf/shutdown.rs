@@ -0,0 +1,112 @@
+//! Orderly termination of a system of actors
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+type ShutdownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Participant {
+    name: String,
+    dependencies: Vec<String>,
+    timeout: Duration,
+    shutdown: ShutdownFuture,
+}
+
+/// Shuts actors down in reverse dependency order.
+///
+/// Each actor is added with the names of the actors it depends on, i.e. whose
+/// ports or handles it holds. An actor is only shut down once all the actors
+/// which depend on it have terminated, so no actor loses its dependencies
+/// while it's still tearing down. Actors without dependencies between them
+/// are shut down in the reverse of the order in which they were added.
+///
+/// If an actor doesn't terminate within its timeout, the coordinator stops
+/// waiting for it and goes on with the others. Whether all actors have
+/// terminated is reported by [ShutdownCoordinator::shutdown], so that the
+/// program can abort the stuck ones.
+pub struct ShutdownCoordinator {
+    default_timeout: Duration,
+    participants: Vec<Participant>,
+}
+
+impl ShutdownCoordinator {
+    /// Create a new coordinator, waiting for at most the given time for each
+    /// actor unless a different timeout is given when adding it
+    pub fn new(default_timeout: Duration) -> ShutdownCoordinator {
+        ShutdownCoordinator {
+            default_timeout,
+            participants: Vec::new(),
+        }
+    }
+
+    /// Add an actor, given the future which shuts it down, usually the result
+    /// of calling `await_shutdown` on its port or [Handle](super::Handle)
+    pub fn add(
+        &mut self,
+        name: &str,
+        dependencies: &[&str],
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let timeout = self.default_timeout;
+        self.add_with_timeout(name, dependencies, timeout, shutdown);
+    }
+
+    /// Add an actor which may take a different time than the default to shut
+    /// down
+    pub fn add_with_timeout(
+        &mut self,
+        name: &str,
+        dependencies: &[&str],
+        timeout: Duration,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) {
+        self.participants.push(Participant {
+            name: name.to_owned(),
+            dependencies: dependencies.iter().map(|&d| d.to_owned()).collect(),
+            timeout,
+            shutdown: Box::pin(shutdown),
+        });
+    }
+
+    /// Shut all the added actors down, returning whether all of them have
+    /// terminated within their timeouts
+    pub async fn shutdown(mut self) -> bool {
+        let mut all_terminated = true;
+        while !self.participants.is_empty() {
+            let participant = self.participants.remove(self.next_index());
+            log::debug!("Shutting {} down", participant.name);
+            if tokio::time::timeout(participant.timeout, participant.shutdown)
+                .await
+                .is_err()
+            {
+                log::error!(
+                    "{} didn't shut down within {:?}, abandoning it",
+                    participant.name,
+                    participant.timeout
+                );
+                all_terminated = false;
+            }
+        }
+        all_terminated
+    }
+
+    /// Index of the last added participant on which no other remaining
+    /// participant depends
+    fn next_index(&self) -> usize {
+        let depended_on = |name: &str| {
+            self.participants
+                .iter()
+                .any(|p| p.dependencies.iter().any(|d| d == name))
+        };
+        match self
+            .participants
+            .iter()
+            .rposition(|p| !depended_on(&p.name))
+        {
+            Some(index) => index,
+            None => {
+                log::error!("Actors depend on each other in a cycle, shutting them down in reverse order of addition");
+                self.participants.len() - 1
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Restarting of actors which terminate unexpectedly
+
+use super::{ActorPort, Request};
+use anyhow::{anyhow, Result};
+use std::{future::Future, sync::Mutex, time::Duration};
+use thiserror::Error;
+use tokio::time::Instant;
+
+static GIVE_UP_HOOK: Mutex<Option<fn(&str)>> = Mutex::new(None);
+
+/// Set a function called with the actor's name whenever a supervisor gives
+/// up restarting it, e.g. to record the state of the program for a bug report
+pub fn set_give_up_hook(hook: fn(&str)) {
+    *GIVE_UP_HOOK.lock().unwrap_or_else(|e| e.into_inner()) = Some(hook);
+}
+
+/// The error answering requests sent to a supervised actor after its
+/// supervisor has given up restarting it
+#[derive(Debug, Error)]
+#[error("{0} is not running, restarting it has failed")]
+pub struct GaveUp(pub String);
+
+/// Describes how a supervisor restarts the actor it supervises
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// How many times the actor may be restarted in a row before the
+    /// supervisor gives up
+    pub max_restarts: u32,
+    /// Delay before the first restart, doubled for each further restart in a
+    /// row
+    pub initial_backoff: Duration,
+    /// The longest delay before a restart
+    pub max_backoff: Duration,
+    /// After running for this long, the actor is considered stable and the
+    /// count of restarts in a row is reset
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            stable_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    fn backoff(&self, restarts: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(restarts))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Call the function until it succeeds, waiting according to the policy
+/// between the attempts, e.g. to connect to services which come up later than
+/// Energia. The error of the last attempt is returned once the policy's
+/// restarts are used up, its `stable_after` isn't used.
+pub async fn retry_with_backoff<T, S, F>(
+    name: &str,
+    policy: &RestartPolicy,
+    mut attempt: S,
+) -> Result<T>
+where
+    S: FnMut() -> F,
+    F: Future<Output = Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if retries < policy.max_restarts => {
+                let backoff = policy.backoff(retries);
+                retries += 1;
+                log::warn!(
+                    "{} isn't available yet, retrying in {:?} (attempt {} of {}): {:#}",
+                    name,
+                    backoff,
+                    retries,
+                    policy.max_restarts,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Spawn an actor using the given function and restart it using the same
+/// function whenever it panics or otherwise terminates while its port is
+/// still in use.
+///
+/// The returned [ActorPort] stays valid across restarts, so it can be handed
+/// out freely. Requests are forwarded to the current instance of the actor.
+/// A request during whose handling the actor crashes is answered with an
+/// error, requests sent while the actor is restarting wait for the restart.
+/// Once the supervisor gives up restarting the actor, requests are answered
+/// with [GaveUp].
+///
+/// If the initial spawn fails, the error is returned. Once all the clones of
+/// the returned port are dropped, the actor is shut down and the supervisor
+/// terminates.
+pub async fn spawn_supervised<P, R, S, F>(
+    name: &str,
+    policy: RestartPolicy,
+    mut spawner: S,
+) -> Result<ActorPort<P, R, anyhow::Error>>
+where
+    P: Send + 'static,
+    R: Send + 'static,
+    S: FnMut() -> F + Send + 'static,
+    F: Future<Output = Result<ActorPort<P, R, anyhow::Error>>> + Send,
+{
+    let child = spawner().await?;
+    let (port, mut receiver) = ActorPort::make();
+    let mut supervisor = Supervisor {
+        name: name.to_owned(),
+        policy,
+        spawner,
+        child: Some(child),
+        restarts: 0,
+        started_at: Instant::now(),
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                request = receiver.recv() => match request {
+                    Some(request) => supervisor.forward(request).await,
+                    None => break,
+                },
+                _ = async { supervisor.child.as_ref().unwrap().closed().await }, if supervisor.child.is_some() => {
+                    log::error!("{} terminated unexpectedly", supervisor.name);
+                    supervisor.child = None;
+                    supervisor.restart().await;
+                }
+            }
+        }
+        if let Some(child) = supervisor.child.take() {
+            child.await_shutdown().await;
+        }
+        log::debug!("Supervisor of {} stopped", supervisor.name);
+    });
+    Ok(port)
+}
+
+struct Supervisor<P, R, S> {
+    name: String,
+    policy: RestartPolicy,
+    spawner: S,
+    child: Option<ActorPort<P, R, anyhow::Error>>,
+    restarts: u32,
+    started_at: Instant,
+}
+
+impl<P, R, S, F> Supervisor<P, R, S>
+where
+    S: FnMut() -> F,
+    F: Future<Output = Result<ActorPort<P, R, anyhow::Error>>>,
+{
+    async fn forward(&mut self, request: Request<P, R, anyhow::Error>) {
+        let Request {
+            payload,
+            response_sender,
+            cancellation,
+            trace_id,
+        } = request;
+        let mut payload = Some(payload);
+        // The child may have died without us noticing yet, so the request is
+        // retried once after a restart
+        for _ in 0..2 {
+            if self.child.is_none() && !self.restart().await {
+                break;
+            }
+            let (mut child_request, child_response) = Request::new(payload.take().unwrap());
+            child_request.cancellation = cancellation.clone();
+            child_request.trace_id = trace_id;
+            if let Err(send_error) = self
+                .child
+                .as_ref()
+                .unwrap()
+                .raw_request(child_request)
+                .await
+            {
+                payload = Some(send_error.0.payload);
+                log::error!("{} terminated unexpectedly", self.name);
+                self.child = None;
+                continue;
+            }
+            let response = match child_response.await {
+                Ok(response) => response,
+                Err(_) => {
+                    log::error!("{} crashed while handling a request", self.name);
+                    self.child = None;
+                    Err(anyhow!("{} crashed while handling the request", self.name))
+                }
+            };
+            let _ = response_sender.send(response);
+            return;
+        }
+        let _ = response_sender.send(Err(GaveUp(self.name.clone()).into()));
+    }
+
+    /// Restart the child according to the policy, returning whether it's
+    /// running
+    async fn restart(&mut self) -> bool {
+        if self.started_at.elapsed() >= self.policy.stable_after {
+            self.restarts = 0;
+        }
+        while self.restarts < self.policy.max_restarts {
+            let backoff = self.policy.backoff(self.restarts);
+            self.restarts += 1;
+            log::info!(
+                "Restarting {} in {:?} (attempt {} of {})",
+                self.name,
+                backoff,
+                self.restarts,
+                self.policy.max_restarts
+            );
+            tokio::time::sleep(backoff).await;
+            match (self.spawner)().await {
+                Ok(child) => {
+                    self.child = Some(child);
+                    self.started_at = Instant::now();
+                    return true;
+                }
+                Err(e) => log::error!("Couldn't restart {}: {}", self.name, e),
+            }
+        }
+        log::error!(
+            "{} has been restarted {} times in a row, giving up",
+            self.name,
+            self.restarts
+        );
+        let hook = *GIVE_UP_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(hook) = hook {
+            hook(&self.name);
+        }
+        false
+    }
+}
@@ -0,0 +1,39 @@
+use super::{event_bus::EventBus, ActorPort};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PowerEvent(u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SleepEvent(&'static str);
+
+#[tokio::test]
+async fn test_publish_subscribe() {
+    let bus = EventBus::new(4);
+    assert_eq!(bus.publish(PowerEvent(0)), 0);
+    let mut power_receiver = bus.subscribe::<PowerEvent>();
+    let mut sleep_receiver = bus.clone().subscribe::<SleepEvent>();
+    assert_eq!(bus.publish(PowerEvent(1)), 1);
+    bus.sender().send(SleepEvent("sleeping")).unwrap();
+    assert_eq!(bus.publish(PowerEvent(2)), 1);
+    assert_eq!(power_receiver.recv().await.unwrap(), PowerEvent(1));
+    assert_eq!(power_receiver.recv().await.unwrap(), PowerEvent(2));
+    assert_eq!(sleep_receiver.recv().await.unwrap(), SleepEvent("sleeping"));
+    assert!(power_receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_forwarding() {
+    let bus = EventBus::new(2);
+    let (port, mut request_receiver) = ActorPort::<i32, (), std::io::Error>::make();
+    let subscription = bus.forward(port);
+    bus.publish(1);
+    let req_1 = request_receiver.recv().await.unwrap();
+    assert_eq!(req_1.payload, 1);
+    req_1.respond(Ok(())).unwrap();
+    bus.publish(2);
+    let req_2 = request_receiver.recv().await.unwrap();
+    assert_eq!(req_2.payload, 2);
+    req_2.respond(Ok(())).unwrap();
+    drop(subscription);
+    assert!(request_receiver.recv().await.is_none());
+}
@@ -17,6 +17,25 @@ async fn test_request_response() {
     assert_eq!(response, Ok(true));
 }
 
+#[tokio::test]
+async fn test_dead_letter() {
+    let (port, mut receiver) = ports::ActorPort::<(), (), ()>::make();
+    let requester = tokio::spawn(async move { port.request(()).await });
+    let mut request = receiver.recv().await.unwrap();
+    request.response_sender.set_actor_name("test_actor");
+    assert!(!request.response_sender.is_closed());
+    // Logged as a dead letter, the requester gets an error
+    drop(request);
+    assert!(matches!(
+        requester.await.unwrap(),
+        Err(ports::ActorRequestError::Recv)
+    ));
+
+    let (request, response_receiver) = ports::Request::<(), (), ()>::new(());
+    drop(response_receiver);
+    assert!(request.response_sender.is_closed());
+}
+
 #[tokio::test]
 async fn test_actor_port() {
     let termination_flag = make_termination_flag();
@@ -81,6 +100,68 @@ async fn test_request_errors() {
     port.await_shutdown().await;
 }
 
+#[tokio::test]
+async fn test_fast_port() {
+    let termination_flag = make_termination_flag();
+    let port = spawn_two_increments_one_error(termination_flag.clone());
+    let mut fast_port = port.fast();
+    assert_eq!(
+        fast_port
+            .request(TestActorMessage::Increment)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        fast_port
+            .request(TestActorMessage::Increment)
+            .await
+            .unwrap(),
+        1
+    );
+    if let Err(ports::ActorRequestError::Actor(e)) =
+        fast_port.request(TestActorMessage::Increment).await
+    {
+        assert_eq!(e.to_string(), "Saturated");
+    } else {
+        panic!("An error from Actor is not translated correctly");
+    }
+    let recv_error = fast_port
+        .request(TestActorMessage::Terminate)
+        .await
+        .expect_err("Actor should drop the responder when terminating");
+    if let ports::ActorRequestError::Recv = recv_error {
+    } else {
+        panic!("A dropped responder is not translated correctly");
+    }
+    let send_error = fast_port
+        .request(TestActorMessage::Increment)
+        .await
+        .expect_err("Actor request channel is still sendable after actor termination");
+    if let ports::ActorRequestError::Send = send_error {
+    } else {
+        panic!("A SendError is not translated correctly");
+    }
+    port.await_shutdown().await;
+}
+
+#[tokio::test]
+async fn test_fast_port_abandoned_request() {
+    let (port, mut receiver) = ports::ActorPort::<usize, usize, ()>::make();
+    let mut fast_port = port.fast();
+    tokio::spawn(async move {
+        while let Some(req) = receiver.recv().await {
+            let payload = req.payload;
+            req.respond(Ok(payload)).unwrap();
+        }
+    });
+    // Abandon the first request after it's been sent, its response has to be
+    // skipped by the next one
+    let _ = tokio::time::timeout(std::time::Duration::ZERO, fast_port.request(1)).await;
+    assert_eq!(fast_port.request(2).await.unwrap(), 2);
+    assert_eq!(fast_port.request(3).await.unwrap(), 3);
+}
+
 enum TestActorMessage {
     Increment,
     // Don't use this in your code! Actors should terminate on their own, used just for testing.
@@ -121,6 +202,27 @@ fn spawn_two_increments_one_error(
     port
 }
 
+#[tokio::test]
+async fn test_mailbox_depth() {
+    let (port, mut receiver) = ports::ActorPort::<(), (), ()>::make();
+    assert_eq!(port.mailbox_depth(), 0);
+    let requests: Vec<_> = (0..3)
+        .map(|_| {
+            let port = port.clone();
+            tokio::spawn(async move { port.request(()).await })
+        })
+        .collect();
+    while port.mailbox_depth() < 3 {
+        tokio::task::yield_now().await;
+    }
+    receiver.recv().await.unwrap().respond(Ok(())).unwrap();
+    assert_eq!(port.mailbox_depth(), 2);
+    drop(receiver);
+    for request in requests {
+        let _ = request.await.unwrap();
+    }
+}
+
 #[tokio::test]
 async fn test_handle_drop() {
     let flag = make_termination_flag();
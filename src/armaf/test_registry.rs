@@ -0,0 +1,68 @@
+use super::{
+    registry::{list_actors, ActorHealth, Registration},
+    server::{spawn_server, Server},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+
+struct NamedServer(&'static str);
+
+#[async_trait]
+impl Server<bool, ()> for NamedServer {
+    fn get_name(&self) -> String {
+        self.0.to_owned()
+    }
+
+    async fn handle_message(&mut self, should_panic: bool) -> Result<()> {
+        if should_panic {
+            panic!("Forced panic");
+        }
+        Ok(())
+    }
+}
+
+fn health_of(name: &str) -> Vec<ActorHealth> {
+    list_actors()
+        .into_iter()
+        .filter(|info| info.name == name)
+        .map(|info| info.health)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_server_registration() {
+    let port = spawn_server(NamedServer("registry_test_server"))
+        .await
+        .unwrap();
+    assert_eq!(
+        health_of("registry_test_server"),
+        vec![ActorHealth::Running]
+    );
+    port.await_shutdown().await;
+    assert!(health_of("registry_test_server").is_empty());
+}
+
+#[tokio::test]
+async fn test_crash_detection() {
+    let port = spawn_server(NamedServer("registry_crashing_server"))
+        .await
+        .unwrap();
+    assert!(port.request(true).await.is_err());
+    port.closed().await;
+    assert_eq!(
+        health_of("registry_crashing_server"),
+        vec![ActorHealth::Crashed]
+    );
+}
+
+#[test]
+fn test_manual_registration() {
+    let registration = Registration::new("registry_manual_actor");
+    let info = list_actors()
+        .into_iter()
+        .find(|info| info.name == "registry_manual_actor")
+        .unwrap();
+    assert_eq!(info.health, ActorHealth::Running);
+    registration.unregister();
+    assert!(health_of("registry_manual_actor").is_empty());
+}
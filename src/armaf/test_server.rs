@@ -1,6 +1,10 @@
-use super::server::{spawn_server, Server};
+use super::{
+    server::{spawn_server, Server},
+    ActorRequestError,
+};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 struct TestServer {
@@ -85,3 +89,95 @@ async fn test_initialization_failure() {
     let (server, _) = TestServer::new(3, true);
     assert!(spawn_server(server).await.is_err());
 }
+
+/// Sleeps for the requested number of seconds, longer requests than 5
+/// seconds time out
+struct SleepingServer;
+
+#[async_trait]
+impl Server<u64, u64> for SleepingServer {
+    fn get_name(&self) -> String {
+        "sleeping_server".to_owned()
+    }
+
+    fn get_default_deadline(&self) -> Option<Duration> {
+        Some(Duration::from_secs(5))
+    }
+
+    fn get_deadline(&self, payload: &u64) -> Option<Duration> {
+        match payload {
+            0 => None,
+            _ => self.get_default_deadline(),
+        }
+    }
+
+    async fn handle_message(&mut self, seconds: u64) -> Result<u64> {
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+        Ok(seconds)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_deadline() {
+    let port = spawn_server(SleepingServer).await.unwrap();
+    assert_eq!(port.request(4).await.unwrap(), 4);
+    let start = tokio::time::Instant::now();
+    let error = port.request(60).await.unwrap_err();
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+    assert!(error.to_string().contains("deadline"));
+    assert_eq!(port.request(0).await.unwrap(), 0);
+    port.await_shutdown().await;
+}
+
+/// Panics when asked to
+struct PanickingServer {
+    continue_after_panic: bool,
+}
+
+#[async_trait]
+impl Server<bool, ()> for PanickingServer {
+    fn get_name(&self) -> String {
+        "panicking_server".to_owned()
+    }
+
+    fn continue_after_panic(&self) -> bool {
+        self.continue_after_panic
+    }
+
+    async fn handle_message(&mut self, should_panic: bool) -> Result<()> {
+        if should_panic {
+            panic!("Forced panic");
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_panic_capture() {
+    let port = spawn_server(PanickingServer {
+        continue_after_panic: true,
+    })
+    .await
+    .unwrap();
+    port.request(false).await.unwrap();
+    let error = port.request(true).await.unwrap_err();
+    assert!(matches!(error, ActorRequestError::Actor(_)));
+    assert!(error.to_string().contains("Forced panic"));
+    port.request(false).await.unwrap();
+    port.await_shutdown().await;
+
+    let port = spawn_server(PanickingServer {
+        continue_after_panic: false,
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        port.request(true).await,
+        Err(ActorRequestError::Actor(_))
+    ));
+    port.closed().await;
+    assert!(matches!(
+        port.request(false).await,
+        Err(ActorRequestError::Send)
+    ));
+}
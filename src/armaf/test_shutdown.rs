@@ -0,0 +1,60 @@
+use super::shutdown::ShutdownCoordinator;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+fn record(
+    order: &Arc<Mutex<Vec<&'static str>>>,
+    name: &'static str,
+) -> impl std::future::Future<Output = ()> + Send + 'static {
+    let order = order.clone();
+    async move {
+        order.lock().unwrap().push(name);
+    }
+}
+
+#[tokio::test]
+async fn test_dependency_order() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(1));
+    coordinator.add("inventory", &[], record(&order, "inventory"));
+    coordinator.add("controller", &["inventory"], record(&order, "controller"));
+    coordinator.add("sensor", &[], record(&order, "sensor"));
+    coordinator.add(
+        "dbus",
+        &["controller", "inventory", "missing"],
+        record(&order, "dbus"),
+    );
+    coordinator.add("watcher", &["controller"], record(&order, "watcher"));
+    assert!(coordinator.shutdown().await);
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec!["watcher", "dbus", "sensor", "controller", "inventory"]
+    );
+}
+
+#[tokio::test]
+async fn test_dependency_cycle() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(1));
+    coordinator.add("first", &["second"], record(&order, "first"));
+    coordinator.add("second", &["first"], record(&order, "second"));
+    assert!(coordinator.shutdown().await);
+    assert_eq!(*order.lock().unwrap(), vec!["second", "first"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timeouts() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_secs(1));
+    coordinator.add("inventory", &[], record(&order, "inventory"));
+    coordinator.add_with_timeout("slow", &["inventory"], Duration::from_secs(10), async {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    });
+    coordinator.add("stuck", &["inventory"], std::future::pending());
+    let start = tokio::time::Instant::now();
+    assert!(!coordinator.shutdown().await);
+    assert_eq!(start.elapsed(), Duration::from_secs(6));
+    assert_eq!(*order.lock().unwrap(), vec!["inventory"]);
+}
@@ -0,0 +1,123 @@
+use super::{
+    server::{spawn_server, Server},
+    supervisor::{retry_with_backoff, spawn_supervised, RestartPolicy},
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Counts requests, panicking on the given one
+struct CrashingServer {
+    current_number: usize,
+    panic_at: usize,
+}
+
+#[async_trait]
+impl Server<(), usize> for CrashingServer {
+    fn get_name(&self) -> String {
+        "crashing_server".to_owned()
+    }
+
+    async fn handle_message(&mut self, _: ()) -> Result<usize> {
+        self.current_number += 1;
+        if self.current_number == self.panic_at {
+            panic!("Forced panic");
+        }
+        Ok(self.current_number)
+    }
+}
+
+fn policy(max_restarts: u32) -> RestartPolicy {
+    RestartPolicy {
+        max_restarts,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(1),
+        stable_after: Duration::from_secs(60),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_restart_after_panic() {
+    let spawns = Arc::new(AtomicUsize::new(0));
+    let moved_spawns = spawns.clone();
+    let port = spawn_supervised("crashing_server", policy(3), move || {
+        moved_spawns.fetch_add(1, Ordering::SeqCst);
+        spawn_server(CrashingServer {
+            current_number: 0,
+            panic_at: 2,
+        })
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(port.request(()).await.unwrap(), 1);
+    assert!(port.request(()).await.is_err());
+    // The port keeps working with a fresh instance of the server
+    assert_eq!(port.request(()).await.unwrap(), 1);
+    assert_eq!(spawns.load(Ordering::SeqCst), 2);
+    port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_giving_up() {
+    let port = spawn_supervised("crashing_server", policy(2), || {
+        spawn_server(CrashingServer {
+            current_number: 0,
+            panic_at: 1,
+        })
+    })
+    .await
+    .unwrap();
+
+    for _ in 0..3 {
+        assert!(port.request(()).await.is_err());
+    }
+    let error = port.request(()).await.unwrap_err();
+    assert!(format!("{}", error).contains("restarting it has failed"));
+    port.await_shutdown().await;
+}
+
+#[tokio::test]
+async fn test_initial_spawn_failure() {
+    let result = spawn_supervised::<(), usize, _, _>("failing", policy(3), || async {
+        Err(anyhow!("Can't spawn"))
+    })
+    .await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_backoff() {
+    let policy = policy(10);
+    assert_eq!(policy.backoff(0), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(400));
+    assert_eq!(policy.backoff(9), Duration::from_secs(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_retry_with_backoff() {
+    let attempts = AtomicUsize::new(0);
+    let result = retry_with_backoff("flaky", &policy(3), || async {
+        match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Err(anyhow!("not yet")),
+            attempt => Ok(attempt),
+        }
+    })
+    .await;
+    assert_eq!(result.unwrap(), 2);
+
+    let attempts = AtomicUsize::new(0);
+    let result: Result<()> = retry_with_backoff("broken", &policy(3), || async {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        Err(anyhow!("never"))
+    })
+    .await;
+    assert_eq!(result.unwrap_err().to_string(), "never");
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+}
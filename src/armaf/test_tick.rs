@@ -0,0 +1,33 @@
+use super::tick::TickService;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn test_ticks() {
+    let service = TickService::new();
+    let start = Instant::now();
+    let mut fast = service.subscribe(Duration::from_secs(10));
+    let mut slow = service.subscribe(Duration::from_secs(30));
+
+    assert_eq!(fast.tick().await, start + Duration::from_secs(10));
+    assert_eq!(fast.tick().await, start + Duration::from_secs(20));
+    // Joins the running timer, in phase with the first subscriber
+    let mut late = service.subscribe(Duration::from_secs(10));
+    assert_eq!(late.tick().await, start + Duration::from_secs(30));
+    assert_eq!(slow.tick().await, start + Duration::from_secs(30));
+
+    // Ticks missed while busy are skipped
+    tokio::time::sleep(Duration::from_secs(25)).await;
+    assert_eq!(fast.tick().await, start + Duration::from_secs(50));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timer_restart() {
+    let service = TickService::new();
+    let ticks = service.subscribe(Duration::from_secs(10));
+    drop(ticks);
+    tokio::time::sleep(Duration::from_secs(15)).await;
+    let start = Instant::now();
+    let mut ticks = service.subscribe(Duration::from_secs(10));
+    assert_eq!(ticks.tick().await, start + Duration::from_secs(10));
+}
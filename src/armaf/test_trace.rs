@@ -0,0 +1,45 @@
+use super::{
+    server::{spawn_server, Server},
+    trace::TraceId,
+    ActorPort,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Responds with the trace of the request and the trace of a request it sends
+/// to the next server, if any
+struct TracingServer(Option<ActorPort<(), (Option<TraceId>, Option<TraceId>), anyhow::Error>>);
+
+#[async_trait]
+impl Server<(), (Option<TraceId>, Option<TraceId>)> for TracingServer {
+    fn get_name(&self) -> String {
+        "tracing_server".to_owned()
+    }
+
+    async fn handle_message(&mut self, _: ()) -> Result<(Option<TraceId>, Option<TraceId>)> {
+        let next_trace = match self.0.as_ref() {
+            Some(port) => port.request(()).await?.0,
+            None => None,
+        };
+        Ok((TraceId::current(), next_trace))
+    }
+}
+
+#[tokio::test]
+async fn test_trace_propagation() {
+    let last = spawn_server(TracingServer(None)).await.unwrap();
+    let first = spawn_server(TracingServer(Some(last))).await.unwrap();
+
+    assert_eq!(TraceId::current(), None);
+    let (first_trace, next_trace) = first.request(()).await.unwrap();
+    assert!(first_trace.is_some());
+    assert_eq!(first_trace, next_trace);
+    let (second_trace, _) = first.request(()).await.unwrap();
+    assert_ne!(first_trace, second_trace);
+
+    let trace = TraceId::new();
+    let (scoped_trace, next_trace) = trace.scope(first.request(())).await.unwrap();
+    assert_eq!(scoped_trace, Some(trace));
+    assert_eq!(next_trace, Some(trace));
+    first.await_shutdown().await;
+}
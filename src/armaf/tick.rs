@@ -0,0 +1,79 @@
+//! Periodic ticks for actors which need to do something regularly, e.g. poll
+//! a sensor
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast,
+    time::{Instant, MissedTickBehavior},
+};
+
+type Timers = Arc<Mutex<HashMap<Duration, broadcast::Sender<Instant>>>>;
+
+/// Shared timers delivering ticks to their subscribers.
+///
+/// All subscribers asking for the same period share a single timer, which
+/// stops once its last subscriber is gone. Since the timers use Tokio's
+/// clock, they advance along with it in tests which pause time.
+#[derive(Clone, Default)]
+pub struct TickService {
+    timers: Timers,
+}
+
+impl TickService {
+    /// Create a new TickService without any timers
+    pub fn new() -> TickService {
+        TickService::default()
+    }
+
+    /// Subscribe to ticks coming each `period`. The first tick comes after
+    /// at most one period, in phase with the other subscribers of the timer.
+    pub fn subscribe(&self, period: Duration) -> Ticks {
+        let mut timers = self.timers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(sender) = timers.get(&period) {
+            return Ticks(sender.subscribe());
+        }
+        let (sender, receiver) = broadcast::channel(1);
+        timers.insert(period, sender.clone());
+        tokio::spawn(run_timer(self.timers.clone(), period, sender));
+        Ticks(receiver)
+    }
+}
+
+async fn run_timer(timers: Timers, period: Duration, sender: broadcast::Sender<Instant>) {
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        let tick = interval.tick().await;
+        let mut timers = timers.lock().unwrap_or_else(|e| e.into_inner());
+        // Checked under the lock, so nobody can subscribe in the meantime
+        if sender.receiver_count() == 0 {
+            timers.remove(&period);
+            log::debug!("Timer with period {:?} stopped", period);
+            return;
+        }
+        let _ = sender.send(tick);
+    }
+}
+
+/// A subscription to periodic ticks, see [TickService::subscribe]
+pub struct Ticks(broadcast::Receiver<Instant>);
+
+impl Ticks {
+    /// Wait for the next tick, returning the time at which it was scheduled.
+    /// Ticks which came while the subscriber was busy are skipped.
+    pub async fn tick(&mut self) -> Instant {
+        loop {
+            match self.0.recv().await {
+                Ok(tick) => return tick,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                // The timer only stops without subscribers or when the
+                // runtime is shutting down
+                Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+            }
+        }
+    }
+}
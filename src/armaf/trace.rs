@@ -0,0 +1,62 @@
+//! Correlation of the requests caused by a single event, so that the event
+//! can be followed across the log lines of all the actors it passes through
+
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+tokio::task_local! {
+    static CURRENT_TRACE: TraceId;
+}
+
+static LAST_TRACE: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies all the requests caused by a single event.
+///
+/// Each [Request](super::Request) carries the trace of the task which created
+/// it, or a new one if the task isn't handling any traced work. Servers
+/// handle requests within their traces, so the requests they send further
+/// share the trace of the original request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(u64);
+
+impl TraceId {
+    /// Start a new trace
+    pub fn new() -> TraceId {
+        TraceId(LAST_TRACE.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    /// The trace of the work the current task is doing, if any
+    pub fn current() -> Option<TraceId> {
+        CURRENT_TRACE.try_with(|trace| *trace).ok()
+    }
+
+    /// The trace of the work the current task is doing, or a new one
+    pub fn current_or_new() -> TraceId {
+        TraceId::current().unwrap_or_else(TraceId::new)
+    }
+
+    /// Number of the trace, for machine-readable output
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Run the future within this trace
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT_TRACE.scope(self, future).await
+    }
+}
+
+impl Default for TraceId {
+    fn default() -> Self {
+        TraceId::new()
+    }
+}
+
+impl Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trace {}", self.0)
+    }
+}
@@ -0,0 +1,524 @@
+//! Validation of the configuration without starting the daemon
+
+use crate::{
+    config::ConfigSources,
+    control::{
+        effector_inventory as ei,
+        environment_controller::{
+            format_duration, has_builtin_schedule, has_day_variants, parse_fallbacks,
+            parse_grace_period, parse_low_battery_hysteresis, parse_low_battery_threshold,
+            parse_schedules, resolve_schedule_type, schedule_to_bunches, schedule_types,
+            schedules_for_day, DayType, FallbackChain, ScheduleType,
+        },
+        idleness_broker::parse_idleness_debounce,
+        inhibitor_policy::{parse_inhibitor_policy, InhibitionMode},
+        keepalive::parse_keepalive_interval,
+        remote_control::parse_triggerable_effects,
+        schedule_rules::{parse_rules, Condition},
+        sleep_controller::{parse_sleep_hooks, SleepHook},
+    },
+    external::dependency_provider::{parse_backends, Backends, BrightnessBackend},
+    system::plugin_effector::register_plugins,
+};
+use anyhow::Result;
+use std::{collections::HashSet, time::Duration};
+
+/// Result of checking a configuration
+pub struct Report {
+    /// Human-readable lines describing the resolved configuration
+    pub lines: Vec<String>,
+    /// Problems found in the configuration
+    pub errors: Vec<String>,
+}
+
+impl Report {
+    fn new() -> Report {
+        Report {
+            lines: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.lines.push(format!("  error: {}", message));
+        self.errors.push(message);
+    }
+}
+
+/// Check the configuration merged from the given sources, print a report about
+/// it and return the exit code with which the process should end
+pub async fn run(sources: &ConfigSources) -> i32 {
+    match sources.files().await {
+        Ok(files) => {
+            for file in files {
+                println!("Checking {}", file.display());
+            }
+        }
+        Err(e) => {
+            println!("error: {:?}", e);
+            return 1;
+        }
+    }
+    let config = match sources.load().await {
+        Ok(config) => config,
+        Err(e) => {
+            println!("error: {:?}", e);
+            return 1;
+        }
+    };
+    match register_plugins(&config) {
+        Ok(plugins) => {
+            for plugin in plugins {
+                println!("Registered plugin {}", plugin);
+            }
+        }
+        Err(e) => {
+            println!("error: {:?}", e);
+            return 1;
+        }
+    }
+    let report = check_config(&config);
+    for line in report.lines.iter() {
+        println!("{}", line);
+    }
+    if report.errors.is_empty() {
+        println!("Configuration is valid");
+        0
+    } else {
+        println!("Configuration contains {} problem(s)", report.errors.len());
+        1
+    }
+}
+
+/// Check an already parsed configuration
+pub fn check_config(config: &toml::Value) -> Report {
+    let mut report = Report::new();
+    match parse_fallbacks(config) {
+        Ok(fallbacks) if fallbacks == FallbackChain::default() => {}
+        Ok(fallbacks) => report.lines.push(format!("Fallbacks: {}", fallbacks)),
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    let used_effectors = match check_day_schedules(config, &mut report) {
+        Ok(used_effectors) => used_effectors,
+        Err(e) => {
+            report.error(format!("{:#}", e));
+            return report;
+        }
+    };
+
+    report.lines.push("Effectors:".to_owned());
+    let mut used_effectors: Vec<(String, ScheduleType)> = used_effectors.into_iter().collect();
+    used_effectors.sort_by(|(a_name, a_typ), (b_name, b_typ)| {
+        (a_name, a_typ.config_name()).cmp(&(b_name, b_typ.config_name()))
+    });
+    let mut checked_defaults = HashSet::new();
+    for (effector_name, typ) in used_effectors {
+        for day in ei::schedule_day_variants(config, &typ) {
+            let effector_config =
+                ei::effector_config_for_schedule(config, &effector_name, Some(&typ), day);
+            let description = if effector_config.as_ref() == config.get(&effector_name) {
+                // Schedules without overrides share the default configuration
+                if !checked_defaults.insert(effector_name.clone()) {
+                    continue;
+                }
+                effector_name.clone()
+            } else {
+                format!(
+                    "{} (schedule {})",
+                    effector_name,
+                    ei::describe_schedule(&typ, day)
+                )
+            };
+            let violations = ei::get_config_schema_for_effector(&effector_name)
+                .validate(&effector_name, effector_config.as_ref());
+            if violations.is_empty() {
+                report.lines.push(format!("  {}: ok", description));
+            }
+            for violation in violations {
+                if description == effector_name {
+                    report.error(violation.to_string());
+                } else {
+                    report.error(format!("{}: {}", description, violation));
+                }
+            }
+        }
+    }
+
+    match parse_rules(config) {
+        Ok(rules) => {
+            for rule in rules {
+                let conditions: Vec<String> =
+                    rule.conditions.iter().map(describe_condition).collect();
+                let conditions = if conditions.is_empty() {
+                    "always".to_owned()
+                } else {
+                    conditions.join(" and ")
+                };
+                report.lines.push(format!(
+                    "Rule: schedule {} when {}",
+                    rule.schedule.config_name(),
+                    conditions
+                ));
+            }
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+
+    match parse_triggerable_effects(config) {
+        Ok(triggerable) if triggerable.is_empty() => {}
+        Ok(triggerable) => {
+            let mut triggerable: Vec<String> = triggerable.into_iter().collect();
+            triggerable.sort();
+            report.lines.push(format!(
+                "Remotely triggerable effects: {}",
+                triggerable.join(", ")
+            ));
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+
+    match parse_sleep_hooks(config) {
+        Ok(hooks) => {
+            let describe = |hooks: &[SleepHook]| {
+                if hooks.is_empty() {
+                    return "nothing".to_owned();
+                }
+                hooks
+                    .iter()
+                    .map(|hook| hook.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            };
+            report
+                .lines
+                .push(format!("Before sleep: {}", describe(&hooks.before_sleep)));
+            report
+                .lines
+                .push(format!("After waking up: {}", describe(&hooks.after_wake)));
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+
+    match parse_inhibitor_policy(config) {
+        Ok(policy) => {
+            if policy.mode == InhibitionMode::Permissive {
+                report
+                    .lines
+                    .push("Inhibited effects don't hold back the rest of their bunch".to_owned());
+            }
+            if let Some(max_age) = policy.max_age {
+                report.lines.push(format!(
+                    "Inhibitors are ignored after {}",
+                    format_duration(max_age)
+                ));
+            }
+            for inhibitor_override in policy.overrides {
+                report.lines.push(format!(
+                    "Inhibitors of {} are ignored after {}",
+                    inhibitor_override.who,
+                    format_duration(inhibitor_override.after)
+                ));
+            }
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    match parse_idleness_debounce(config) {
+        Ok(debounce) if debounce.is_zero() => {}
+        Ok(debounce) => report.lines.push(format!(
+            "Changes of idleness are ignored unless they last for {}",
+            format_duration(debounce)
+        )),
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    match parse_keepalive_interval(config) {
+        Ok(Some(interval)) => report.lines.push(format!(
+            "Display server is kept active every {} while idleness is inhibited",
+            format_duration(interval)
+        )),
+        Ok(None) => {}
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    match ei::parse_eager_spawning(config) {
+        Ok(true) => report
+            .lines
+            .push("Effectors are spawned when Energia starts".to_owned()),
+        Ok(false) => {}
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    match parse_backends(config) {
+        Ok(backends) if backends == Backends::default() => {}
+        Ok(backends) => {
+            let BrightnessBackend::Logind { device } = &backends.brightness;
+            report.lines.push(format!(
+                "Brightness is controlled on backlight device {}",
+                device
+            ));
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    report
+}
+
+fn describe_condition(condition: &Condition) -> String {
+    match condition {
+        Condition::OnBattery(true) => "on battery".to_owned(),
+        Condition::OnBattery(false) => "on external power".to_owned(),
+        Condition::BatteryBelow(percentage) => format!("battery below {}%", percentage),
+        Condition::BatteryAbove(percentage) => format!("battery above {}%", percentage),
+        Condition::Docked(true) => "docked".to_owned(),
+        Condition::Docked(false) => "not docked".to_owned(),
+        Condition::ExternalMonitor(true) => "external monitor connected".to_owned(),
+        Condition::ExternalMonitor(false) => "no external monitor".to_owned(),
+        Condition::TemperatureAbove(temperature) => format!("above {}°C", temperature),
+        Condition::Profile(profile) => format!("profile {}", profile),
+    }
+}
+
+/// Check the schedules used on each type of day, if the schedules have
+/// variants for them
+fn check_day_schedules(
+    config: &toml::Value,
+    report: &mut Report,
+) -> Result<HashSet<(String, ScheduleType)>> {
+    if !has_day_variants(config) {
+        return check_schedules(config, report);
+    }
+    // The variants are only checked to be valid tables
+    parse_schedules(config)?;
+    let mut used_effectors = HashSet::new();
+    for day in DayType::ALL {
+        report
+            .lines
+            .push(format!("Schedules for {}:", day.config_name()));
+        used_effectors.extend(check_schedules(&schedules_for_day(config, day), report)?);
+    }
+    Ok(used_effectors)
+}
+
+fn check_schedules(
+    config: &toml::Value,
+    report: &mut Report,
+) -> Result<HashSet<(String, ScheduleType)>> {
+    let schedules = parse_schedules(config)?;
+    let mut used_effectors = HashSet::new();
+    if !has_builtin_schedule(&schedules) {
+        report.error(
+            "No schedule defined. Define either schedule.external or schedule.battery.".to_owned(),
+        );
+        return Ok(used_effectors);
+    }
+
+    // Invalid fallbacks are reported on their own
+    let fallbacks = parse_fallbacks(config).unwrap_or_default();
+    let effect_names_mapping = ei::resolve_effectors_for_effects();
+    for typ in schedule_types(&schedules) {
+        let resolution = resolve_schedule_type(typ.clone(), &schedules, &fallbacks);
+        if resolution.effective != typ {
+            let reason = if resolution.last_resort {
+                " (no defined fallback)"
+            } else {
+                ""
+            };
+            report.lines.push(format!(
+                "Schedule {}: not defined, falls back to {}{}",
+                typ.config_name(),
+                resolution.effective.config_name(),
+                reason
+            ));
+            continue;
+        }
+        report
+            .lines
+            .push(format!("Schedule {}:", typ.config_name()));
+        let grace_period = config
+            .get("schedule")
+            .and_then(|schedules| schedules.get(typ.config_name()))
+            .map(parse_grace_period)
+            .transpose()?
+            .unwrap_or(Duration::ZERO);
+        if !grace_period.is_zero() {
+            report
+                .lines
+                .push(format!("  grace period: {}", format_duration(grace_period)));
+        }
+        match schedule_to_bunches(&schedules[&typ], &effect_names_mapping) {
+            Ok(bunches) => {
+                for (delay, effects) in bunches {
+                    let names: Vec<&str> = effects.iter().map(|e| e.name.as_str()).collect();
+                    report.lines.push(format!(
+                        "  after {}: {}",
+                        format_duration(delay),
+                        names.join(", ")
+                    ));
+                    for effect in effects {
+                        used_effectors
+                            .insert((effect_names_mapping[&effect.name].0.clone(), typ.clone()));
+                    }
+                }
+            }
+            Err(e) => report.error(format!("schedule.{}: {:#}", typ.config_name(), e)),
+        }
+    }
+
+    match parse_low_battery_threshold(config) {
+        Ok(threshold) => report
+            .lines
+            .push(format!("Low battery schedule is used under {}%", threshold)),
+        Err(e) if schedules.contains_key(&ScheduleType::LowBattery) => report.error(format!(
+            "Low battery schedule is defined but {}, it will never be used",
+            e
+        )),
+        Err(_) => {}
+    }
+    match parse_low_battery_hysteresis(config) {
+        Ok(hysteresis) => {
+            if let Some(exit) = hysteresis.exit_percentage {
+                report
+                    .lines
+                    .push(format!("Low battery schedule is left above {}%", exit));
+            }
+            if !hysteresis.min_dwell.is_zero() {
+                report.lines.push(format!(
+                    "Battery schedules are switched at most once every {}",
+                    format_duration(hysteresis.min_dwell)
+                ));
+            }
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+    Ok(used_effectors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_valid_config() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+            screen_off = "2m"
+
+            [schedule.battery]
+            screen_dim = "30s"
+
+            [brightness]
+            dim_percentage = 30
+        };
+        let report = check_config(&config);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report
+            .lines
+            .contains(&"Schedule low_battery: not defined, falls back to battery".to_owned()));
+        assert!(report
+            .lines
+            .contains(&"  after 1m: screen_dim, idle_hint".to_owned()));
+        assert!(report.lines.contains(&"Before sleep: lock".to_owned()));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+            lock = "2m"
+
+            [schedule.low_battery]
+            explode = "10s"
+
+            [brightness]
+            dim_percentage = 130
+        };
+        let report = check_config(&config);
+        // Unknown effect, invalid brightness, missing lock configuration and
+        // missing low battery threshold
+        assert_eq!(report.errors.len(), 4, "{:?}", report.errors);
+        assert!(report
+            .errors
+            .contains(&"brightness.dim_percentage: must be between 0 and 100, not 130".to_owned()));
+    }
+
+    #[test]
+    fn test_schedule_overrides() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.battery]
+            screen_off = "1m"
+
+            [schedule.battery.screen_dim]
+            after = "30s"
+            dim_percentage = 120
+
+            [brightness]
+            dim_percentage = 30
+        };
+        let report = check_config(&config);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("brightness (schedule battery)"));
+        assert!(report.lines.contains(&"  brightness: ok".to_owned()));
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let config = toml::toml! {
+            fallbacks = ["low_battery:external"]
+
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.battery]
+            screen_dim = "30s"
+        };
+        let report = check_config(&config);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report
+            .lines
+            .contains(&"Schedule low_battery: not defined, falls back to external".to_owned()));
+
+        let report = check_config(&toml::toml! {
+            fallbacks = ["battery:external", "external:battery"]
+
+            [schedule.battery]
+            screen_dim = "30s"
+        });
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.lines.contains(
+            &"Schedule external: not defined, falls back to battery (no defined fallback)"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.presentation]
+            screen_off = "2h"
+
+            [[rules]]
+            schedule = "presentation"
+            profile = "presentation"
+        };
+        let report = check_config(&config);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.lines.contains(&"Schedule presentation:".to_owned()));
+        assert!(report
+            .lines
+            .contains(&"Rule: schedule presentation when profile presentation".to_owned()));
+    }
+
+    #[test]
+    fn test_unparseable_schedule() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1 minute"
+        };
+        let report = check_config(&config);
+        assert_eq!(report.errors.len(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+//! Loading of Energia's configuration file
+//!
+//! The configuration may be split into several layers which are merged
+//! together, in this order:
+//!
+//! 1. System-wide defaults in `/etc/energia/config.toml`
+//! 2. The user's configuration file, `~/.config/energia/config.toml` by default
+//! 3. Drop-in fragments, all `*.toml` files in the drop-in directory, in
+//!    lexicographical order of their names
+//!
+//! Later layers override earlier ones. Tables are merged key by key, other
+//! values (including arrays) are replaced. The only exception are schedules:
+//! each `schedule.<type>` table is replaced as a whole, so that a schedule
+//! defined in a later layer never inherits effects from an earlier one.
+//!
+//! Once merged, the strings in the configuration are preprocessed by
+//! [substitution], which expands environment variables and commands in them.
+
+pub mod substitution;
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
+
+/// Path to the system-wide configuration file
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/energia/config.toml";
+
+/// The loaded configuration, shared by the actors which read it instead of
+/// each keeping a copy. A reload replaces it as a whole, it's never modified.
+pub type SharedConfig = Arc<toml::Value>;
+
+/// The files from which the configuration is assembled
+#[derive(Debug, Clone)]
+pub struct ConfigSources {
+    /// System-wide defaults, skipped if the file doesn't exist
+    pub system_file: Option<PathBuf>,
+    /// The user's configuration file
+    pub user_file: PathBuf,
+    /// Whether a missing user file is an error, rather than being skipped
+    pub user_file_required: bool,
+    /// Directory containing drop-in configuration fragments
+    pub drop_in_dir: Option<PathBuf>,
+}
+
+impl ConfigSources {
+    /// Get the paths of the existing configuration files in the order in
+    /// which they should be merged
+    pub async fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        if let Some(system_file) = self.system_file.as_ref() {
+            if fs::metadata(system_file).await.is_ok() {
+                files.push(system_file.clone());
+            }
+        }
+        if self.user_file_required || fs::metadata(&self.user_file).await.is_ok() {
+            files.push(self.user_file.clone());
+        }
+        if let Some(drop_in_dir) = self.drop_in_dir.as_ref() {
+            let mut fragments = Vec::new();
+            let mut entries = fs::read_dir(drop_in_dir).await.with_context(|| {
+                format!(
+                    "Couldn't read configuration directory {}",
+                    drop_in_dir.display()
+                )
+            })?;
+            while let Some(entry) = entries.next_entry().await? {
+                if is_fragment(&entry.path()) {
+                    fragments.push(entry.path());
+                }
+            }
+            fragments.sort();
+            files.extend(fragments);
+        }
+        if files.is_empty() {
+            return Err(anyhow!(
+                "No configuration file found, create {}",
+                self.user_file.display()
+            ));
+        }
+        Ok(files)
+    }
+
+    /// Load all the configuration files, merge them into a single
+    /// configuration and expand the substitutions in it
+    pub async fn load(&self) -> Result<toml::Value> {
+        let mut config = toml::Value::Table(toml::value::Map::new());
+        for file in self.files().await? {
+            merge(&mut config, load(&file).await?);
+        }
+        // Command substitution runs processes, which would block the runtime
+        tokio::task::spawn_blocking(move || -> Result<toml::Value> {
+            substitution::substitute(&mut config)?;
+            Ok(config)
+        })
+        .await?
+    }
+
+    /// Check whether a change of the file at the given path may change the
+    /// resulting configuration
+    pub fn is_source(&self, path: &Path) -> bool {
+        self.system_file
+            .as_ref()
+            .map_or(false, |system_file| is_same_file(system_file, path))
+            || is_same_file(&self.user_file, path)
+            || self.drop_in_dir.as_ref().map_or(false, |drop_in_dir| {
+                directory_of(path) == *drop_in_dir && is_fragment(path)
+            })
+    }
+
+    /// Get the directories in which the configuration files reside
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = self
+            .system_file
+            .iter()
+            .chain(std::iter::once(&self.user_file))
+            .map(|file| directory_of(file))
+            .chain(self.drop_in_dir.iter().cloned())
+            .collect();
+        directories.sort();
+        directories.dedup();
+        directories
+    }
+}
+
+fn directory_of(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    a.file_name() == b.file_name() && directory_of(a) == directory_of(b)
+}
+
+fn is_fragment(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension == "toml")
+}
+
+/// Read the configuration file at the given path and parse it as TOML
+pub async fn load(path: impl AsRef<Path>) -> Result<toml::Value> {
+    let path = path.as_ref();
+    let contents = fs::read(path)
+        .await
+        .with_context(|| format!("Couldn't read configuration file {}", path.display()))?;
+    toml::from_slice(&contents)
+        .with_context(|| format!("Couldn't parse configuration file {}", path.display()))
+}
+
+/// Merge the `overlay` configuration layer into `base`
+pub fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    merge_at(base, overlay, &[]);
+}
+
+fn merge_at(base: &mut toml::Value, overlay: toml::Value, path: &[&str]) {
+    let replaces_whole = path.len() == 2 && path[0] == "schedule";
+    match (base, overlay) {
+        (toml::Value::Table(base_table), toml::Value::Table(overlay_table)) if !replaces_whole => {
+            for (key, value) in overlay_table {
+                match base_table.get_mut(&key) {
+                    Some(base_value) => {
+                        let mut key_path = path.to_vec();
+                        key_path.push(&key);
+                        merge_at(base_value, value, &key_path);
+                    }
+                    None => {
+                        base_table.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut base = toml::toml! {
+            [schedule.external]
+            screen_dim = "3m"
+            sleep = "10m"
+
+            [schedule.battery]
+            screen_dim = "1m"
+
+            [brightness]
+            dim_percentage = 50
+
+            [lock]
+            command = "i3lock"
+            args = ["-n", "-e"]
+        };
+        let overlay = toml::toml! {
+            [schedule.external]
+            screen_dim = "5m"
+
+            [lock]
+            args = ["-n"]
+
+            [battery]
+            low_battery_percentage = 20
+        };
+        merge(&mut base, overlay);
+        assert_eq!(
+            base,
+            toml::toml! {
+                [schedule.external]
+                screen_dim = "5m"
+
+                [schedule.battery]
+                screen_dim = "1m"
+
+                [brightness]
+                dim_percentage = 50
+
+                [lock]
+                command = "i3lock"
+                args = ["-n"]
+
+                [battery]
+                low_battery_percentage = 20
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layered_loading() {
+        let directory = std::env::temp_dir().join(format!(
+            "energia_config_layering_test_{}",
+            std::process::id()
+        ));
+        let drop_in_dir = directory.join("conf.d");
+        fs::create_dir_all(&drop_in_dir).await.unwrap();
+        let system_file = directory.join("system.toml");
+        fs::write(&system_file, "a = 1\nb = 1\nc = 1")
+            .await
+            .unwrap();
+        fs::write(drop_in_dir.join("20-second.toml"), "c = 3")
+            .await
+            .unwrap();
+        fs::write(drop_in_dir.join("10-first.toml"), "b = 2\nc = 2")
+            .await
+            .unwrap();
+        fs::write(drop_in_dir.join("ignored.conf"), "a = 5")
+            .await
+            .unwrap();
+
+        let mut sources = ConfigSources {
+            system_file: Some(system_file.clone()),
+            user_file: directory.join("missing.toml"),
+            user_file_required: false,
+            drop_in_dir: Some(drop_in_dir.clone()),
+        };
+        assert_eq!(
+            sources.load().await.unwrap(),
+            toml::toml! { a = 1 b = 2 c = 3 }
+        );
+        assert!(sources.is_source(&drop_in_dir.join("30-new.toml")));
+        assert!(!sources.is_source(&drop_in_dir.join("ignored.conf")));
+        assert!(sources.is_source(&system_file));
+
+        sources.user_file_required = true;
+        assert!(sources.load().await.is_err());
+
+        fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Expansion of environment variables and commands in configuration strings
+//!
+//! Every string value in the configuration (but not the keys) is expanded
+//! according to these rules:
+//!
+//! * `${NAME}` is replaced by the value of the environment variable `NAME`,
+//!   it's an error if the variable is not set
+//! * `${NAME:-default}` is replaced by the value of `NAME`, or by `default`
+//!   if the variable is not set or is empty
+//! * `$(command)` is replaced by the standard output of `command` run by
+//!   `sh -c`, without trailing newlines. Since running commands from the
+//!   configuration may be surprising, it's only allowed if the top-level
+//!   `command_substitution` key is set to `true`.
+//! * `$$` is replaced by a single `$`
+//! * `$` followed by anything else is kept as it is
+
+use anyhow::{anyhow, Context, Result};
+use std::{env, process::Command};
+
+/// The top-level key enabling command substitution
+const COMMAND_SUBSTITUTION_KEY: &str = "command_substitution";
+
+/// Expand the variables and commands in all the strings in the configuration
+pub fn substitute(config: &mut toml::Value) -> Result<()> {
+    let allow_commands = config
+        .get(COMMAND_SUBSTITUTION_KEY)
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false);
+    substitute_value(config, "", &|name| env::var(name).ok(), allow_commands)
+}
+
+fn substitute_value(
+    value: &mut toml::Value,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    allow_commands: bool,
+) -> Result<()> {
+    match value {
+        toml::Value::String(string) => {
+            *string = expand(string, lookup, allow_commands)
+                .with_context(|| format!("Couldn't expand the value of {}", path))?;
+        }
+        toml::Value::Array(array) => {
+            for (i, item) in array.iter_mut().enumerate() {
+                substitute_value(item, &format!("{}[{}]", path, i), lookup, allow_commands)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                let item_path = if path.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                substitute_value(item, &item_path, lookup, allow_commands)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Expand a single string
+pub fn expand(
+    string: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    allow_commands: bool,
+) -> Result<String> {
+    let mut result = String::with_capacity(string.len());
+    let mut chars = string.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('$') => {
+                chars.next();
+                result.push('$');
+            }
+            Some('{') => {
+                chars.next();
+                let mut expression = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => expression.push(c),
+                        None => return Err(anyhow!("${{ without a closing }}")),
+                    }
+                }
+                result.push_str(&expand_variable(&expression, lookup)?);
+            }
+            Some('(') => {
+                chars.next();
+                let mut command = String::new();
+                let mut depth = 1;
+                loop {
+                    match chars.next() {
+                        Some(')') if depth == 1 => break,
+                        Some(c) => {
+                            match c {
+                                '(' => depth += 1,
+                                ')' => depth -= 1,
+                                _ => {}
+                            }
+                            command.push(c);
+                        }
+                        None => return Err(anyhow!("$( without a closing )")),
+                    }
+                }
+                result.push_str(&run_command(&command, allow_commands)?);
+            }
+            _ => result.push('$'),
+        }
+    }
+    Ok(result)
+}
+
+fn expand_variable(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let (name, default) = match expression.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (expression, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(anyhow!("{} is not a valid environment variable name", name));
+    }
+    match (lookup(name), default) {
+        (Some(value), Some(default)) if value.is_empty() => Ok(default.to_owned()),
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => Ok(default.to_owned()),
+        (None, None) => Err(anyhow!("environment variable {} is not set", name)),
+    }
+}
+
+fn run_command(command: &str, allow_commands: bool) -> Result<String> {
+    if !allow_commands {
+        return Err(anyhow!(
+            "command substitution is disabled, set {} = true to enable it or write $$ for a literal $",
+            COMMAND_SUBSTITUTION_KEY
+        ));
+    }
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .with_context(|| format!("Couldn't run {}", command))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} failed with {}", command, output.status));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("Output of {} is not valid UTF-8", command))?;
+    Ok(stdout.trim_end_matches('\n').to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_owned()),
+            "EMPTY" => Some("".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            expand("${HOME}/.bin/lock", &lookup, false).unwrap(),
+            "/home/user/.bin/lock"
+        );
+        assert_eq!(
+            expand("${MISSING:-i3lock}", &lookup, false).unwrap(),
+            "i3lock"
+        );
+        assert_eq!(
+            expand("${EMPTY:-default}", &lookup, false).unwrap(),
+            "default"
+        );
+        assert_eq!(
+            expand("${HOME:-default}", &lookup, false).unwrap(),
+            "/home/user"
+        );
+        assert!(expand("${MISSING}", &lookup, false).is_err());
+        assert!(expand("${HOME", &lookup, false).is_err());
+        assert!(expand("${}", &lookup, false).is_err());
+        assert!(expand("${HO ME}", &lookup, false).is_err());
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(expand("$${HOME}", &lookup, false).unwrap(), "${HOME}");
+        assert_eq!(expand("$$(ls)", &lookup, false).unwrap(), "$(ls)");
+        assert_eq!(expand("cost: 5$", &lookup, false).unwrap(), "cost: 5$");
+        assert_eq!(expand("$HOME", &lookup, false).unwrap(), "$HOME");
+        assert_eq!(expand("$$$$", &lookup, false).unwrap(), "$$");
+    }
+
+    #[test]
+    fn test_commands() {
+        assert!(expand("$(echo hi)", &lookup, false).is_err());
+        assert_eq!(expand("$(echo hi)", &lookup, true).unwrap(), "hi");
+        assert_eq!(
+            expand("a $(echo $(echo nested)) b", &lookup, true).unwrap(),
+            "a nested b"
+        );
+        assert!(expand("$(exit 1)", &lookup, true).is_err());
+        assert!(expand("$(echo hi", &lookup, true).is_err());
+    }
+
+    #[test]
+    fn test_substitution_in_config() {
+        let mut config = toml::toml! {
+            [lock]
+            command = "$(echo i3lock)"
+            args = ["-i", "$${HOME}/wallpaper.png"]
+        };
+        let error = substitute_value(&mut config.clone(), "", &lookup, false).unwrap_err();
+        assert!(format!("{}", error).contains("lock.command"));
+
+        substitute_value(&mut config, "", &lookup, true).unwrap();
+        assert_eq!(
+            config,
+            toml::toml! {
+                [lock]
+                command = "i3lock"
+                args = ["-i", "${HOME}/wallpaper.png"]
+            }
+        );
+    }
+}
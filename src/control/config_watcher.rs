@@ -0,0 +1,123 @@
+//! Watches the configuration files and asks the [EnvironmentController] to
+//! reload the configuration once they change
+//!
+//! [EnvironmentController]: super::environment_controller::EnvironmentController
+
+use super::environment_controller::{EnvironmentCommand, EnvironmentPort};
+use crate::{
+    armaf::{Handle, HandleChild},
+    config::ConfigSources,
+};
+use anyhow::{anyhow, Result};
+use inotify::{EventStream, Inotify, WatchDescriptor, WatchMask};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use tokio_stream::StreamExt;
+
+/// Watches the configuration files using inotify and sends a
+/// [EnvironmentCommand::ReloadConfig] message with the newly merged
+/// configuration whenever one of them is written to.
+///
+/// The directories containing the files are watched instead of the files
+/// themselves, since many editors save files by writing a new file and renaming
+/// it over the old one, which would silently remove a watch placed on the file.
+/// Watching the directories also allows noticing newly created files, such as
+/// new drop-in fragments.
+pub struct ConfigWatcher {
+    sources: ConfigSources,
+    environment_controller: EnvironmentPort,
+    handle_child: Option<HandleChild>,
+}
+
+impl ConfigWatcher {
+    /// Create a new ConfigWatcher for the given configuration files
+    pub fn new(sources: ConfigSources, environment_controller: EnvironmentPort) -> ConfigWatcher {
+        ConfigWatcher {
+            sources,
+            environment_controller,
+            handle_child: None,
+        }
+    }
+
+    /// Start watching the configuration files
+    pub fn spawn(mut self) -> Result<Handle> {
+        let inotify = Inotify::init()?;
+        let mut watched_directories = HashMap::new();
+        for directory in self.sources.directories() {
+            match inotify
+                .watches()
+                .add(&directory, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+            {
+                Ok(descriptor) => {
+                    watched_directories.insert(descriptor, directory);
+                }
+                Err(e) => log::debug!("Not watching {}: {}", directory.display(), e),
+            }
+        }
+        if watched_directories.is_empty() {
+            return Err(anyhow!(
+                "None of the configuration directories can be watched"
+            ));
+        }
+        let events = inotify.into_event_stream([0u8; 1024])?;
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+        tokio::spawn(async move {
+            if let Err(e) = self.main_loop(events, watched_directories).await {
+                log::error!(
+                    "Configuration watcher failed, configuration will not be reloaded: {}",
+                    e
+                );
+            }
+        });
+        Ok(handle)
+    }
+
+    async fn main_loop(
+        &mut self,
+        mut events: EventStream<[u8; 1024]>,
+        watched_directories: HashMap<WatchDescriptor, PathBuf>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => {
+                    log::debug!("Handle dropped, terminating");
+                    return Ok(());
+                }
+                event = events.next() => {
+                    let event = event.ok_or_else(|| anyhow!("inotify event stream ended"))??;
+                    let path = match (watched_directories.get(&event.wd), event.name) {
+                        (Some(directory), Some(name)) => directory.join(name),
+                        _ => continue,
+                    };
+                    if self.sources.is_source(&path) {
+                        self.reload(path).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn reload(&self, changed_path: PathBuf) {
+        log::info!("Configuration file {} changed", changed_path.display());
+        reload_config(&self.sources, &self.environment_controller).await;
+    }
+}
+
+/// Load the configuration from the sources and send it to the
+/// [EnvironmentController](super::environment_controller::EnvironmentController),
+/// keeping the previous configuration if the new one can't be loaded
+pub async fn reload_config(sources: &ConfigSources, environment_controller: &EnvironmentPort) {
+    let new_config = match sources.load().await {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Not reloading configuration: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = environment_controller
+        .request(EnvironmentCommand::ReloadConfig(Arc::new(new_config)))
+        .await
+    {
+        log::error!("Configuration reload failed: {:?}", e);
+    }
+}
@@ -1,39 +1,327 @@
 //! Exposes a D-Bus API server and executes some specified effectors
 
-use crate::armaf::{EffectorMessage, EffectorPort, Handle};
+use super::{
+    effector_inventory::{request_effector, InventoryPort},
+    environment_controller::{EnvironmentCommand, EnvironmentPort},
+    event_history::EventHistory,
+    idleness_controller::{IdlenessMessage, IdlenessPort},
+    manager_state::{EffectEvent, EffectTransition, ManagerState},
+    remote_control::EffectTrigger,
+};
+use crate::{
+    armaf::{self, EffectorMessage, EffectorPort, Handle},
+    external::brightness::AnyBrightnessController,
+    system::{
+        brightness_effector::BrightnessAdjuster,
+        inhibition_sensor::{ApplicationInhibition, ApplicationInhibitions},
+        upower_sensor::{BatterySample, PowerStatus},
+    },
+};
+use logind_zbus::manager::InhibitType;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+use tokio_stream::StreamExt;
+use zbus::{MessageHeader, SignalContext};
 
-/// Connect to the session D-Bus as a server and present a simple API which can
-/// be used to lock the computer
+/// Well-known name of the freedesktop.org screensaver service
+const SCREENSAVER_NAME: &str = "org.freedesktop.ScreenSaver";
+
+/// Paths at which applications look for the screensaver interface
+const SCREENSAVER_PATHS: [&str; 2] = ["/org/freedesktop/ScreenSaver", "/ScreenSaver"];
+
+/// Well-known name of the legacy freedesktop.org power management service
+const POWER_MANAGEMENT_NAME: &str = "org.freedesktop.PowerManagement";
+
+/// Path of the legacy power management inhibition interface
+const POWER_MANAGEMENT_INHIBIT_PATH: &str = "/org/freedesktop/PowerManagement/Inhibit";
+
+/// A function resetting the user's idleness, as if they have just used the
+/// computer
+pub type ActivitySimulator = Arc<dyn Fn() -> anyhow::Result<()> + Send + Sync>;
+
+/// Connect to the session D-Bus as a server and present an API which can be
+/// used to lock the computer and to observe the state of the power management
 pub struct DBusController {
     path: String,
     name: String,
     lock_effector: Option<EffectorPort>,
+    effector_inventory: Option<InventoryPort>,
+    state: watch::Receiver<ManagerState>,
+    environment_controller: Option<EnvironmentPort>,
+    effect_trigger: Option<EffectTrigger>,
+    idleness_ports: Option<watch::Receiver<Option<IdlenessPort>>>,
+    effect_events: Option<broadcast::Receiver<EffectEvent>>,
+    event_history: Option<EventHistory>,
+    power_status: Option<watch::Receiver<PowerStatus>>,
+    battery_samples: Option<watch::Receiver<BatterySample>>,
+    brightness: Option<BrightnessAdjuster<AnyBrightnessController>>,
+    screensaver: Option<ScreenSaverInterface>,
+    power_management: Option<PowerManagementInterface>,
 }
 
 impl DBusController {
     /// Create a new DBusController
-    pub fn new(path: &str, name: &str, lock_effector: Option<EffectorPort>) -> DBusController {
+    pub fn new(
+        path: &str,
+        name: &str,
+        lock_effector: Option<EffectorPort>,
+        state: watch::Receiver<ManagerState>,
+    ) -> DBusController {
         DBusController {
             path: path.to_string(),
             name: name.to_string(),
             lock_effector,
+            effector_inventory: None,
+            state,
+            environment_controller: None,
+            effect_trigger: None,
+            idleness_ports: None,
+            effect_events: None,
+            event_history: None,
+            power_status: None,
+            battery_samples: None,
+            brightness: None,
+            screensaver: None,
+            power_management: None,
         }
     }
 
+    /// Get the lock effector from the given inventory whenever the computer
+    /// should be locked, instead of using the port passed to [Self::new].
+    /// The effector is spawned on the first use and respawned if it has died.
+    pub fn with_effector_inventory(mut self, effector_inventory: InventoryPort) -> DBusController {
+        self.effector_inventory = Some(effector_inventory);
+        self
+    }
+
+    /// Allow controlling the given [EnvironmentController], e.g. pausing the
+    /// schedule
+    ///
+    /// [EnvironmentController]: super::environment_controller::EnvironmentController
+    pub fn with_environment_controller(
+        mut self,
+        environment_controller: EnvironmentPort,
+    ) -> DBusController {
+        self.environment_controller = Some(environment_controller);
+        self
+    }
+
+    /// Allow applying and rolling back effects on demand through the given
+    /// [EffectTrigger]
+    pub fn with_effect_trigger(mut self, effect_trigger: EffectTrigger) -> DBusController {
+        self.effect_trigger = Some(effect_trigger);
+        self
+    }
+
+    /// Allow inspecting the state of the [IdlenessController] whose port is
+    /// published on the given channel
+    ///
+    /// [IdlenessController]: super::idleness_controller::IdlenessController
+    pub fn with_idleness_ports(
+        mut self,
+        idleness_ports: watch::Receiver<Option<IdlenessPort>>,
+    ) -> DBusController {
+        self.idleness_ports = Some(idleness_ports);
+        self
+    }
+
+    /// Emit the `EffectApplied` and `EffectRolledBack` signals for the events
+    /// received on the given channel
+    pub fn with_effect_events(
+        mut self,
+        effect_events: broadcast::Receiver<EffectEvent>,
+    ) -> DBusController {
+        self.effect_events = Some(effect_events);
+        self
+    }
+
+    /// Allow reading the recent state transitions from the given history
+    pub fn with_event_history(mut self, event_history: EventHistory) -> DBusController {
+        self.event_history = Some(event_history);
+        self
+    }
+
+    /// Expose the power source and the battery percentage received on the
+    /// given channel as properties
+    pub fn with_power_status(
+        mut self,
+        power_status: watch::Receiver<PowerStatus>,
+    ) -> DBusController {
+        self.power_status = Some(power_status);
+        self
+    }
+
+    /// Expose the battery percentage and the time until the battery is empty
+    /// from the given samples as properties
+    pub fn with_battery_samples(
+        mut self,
+        battery_samples: watch::Receiver<BatterySample>,
+    ) -> DBusController {
+        self.battery_samples = Some(battery_samples);
+        self
+    }
+
+    /// Allow changing the screen's brightness through the given adjuster,
+    /// which keeps the brightness effector from undoing the change
+    pub fn with_brightness(
+        mut self,
+        brightness: BrightnessAdjuster<AnyBrightnessController>,
+    ) -> DBusController {
+        self.brightness = Some(brightness);
+        self
+    }
+
+    /// Also implement the `org.freedesktop.ScreenSaver` interface, whose
+    /// inhibitions are added to the given [ApplicationInhibitions]
+    pub fn with_screensaver(
+        mut self,
+        inhibitions: ApplicationInhibitions,
+        activity_simulator: ActivitySimulator,
+    ) -> DBusController {
+        self.screensaver = Some(ScreenSaverInterface {
+            inhibitions,
+            activity_simulator,
+            state: self.state.clone(),
+        });
+        self
+    }
+
+    /// Also implement the legacy `org.freedesktop.PowerManagement.Inhibit`
+    /// interface, whose inhibitions are added to the given
+    /// [ApplicationInhibitions] as sleep inhibitions
+    pub fn with_power_management_inhibit(
+        mut self,
+        inhibitions: ApplicationInhibitions,
+    ) -> DBusController {
+        self.power_management = Some(PowerManagementInterface { inhibitions });
+        self
+    }
+
     /// Spawn the DBusController actor
-    pub async fn spawn(self) -> anyhow::Result<Handle> {
+    pub async fn spawn(mut self) -> anyhow::Result<Handle> {
         let (handle, mut handle_child) = Handle::new();
         let moved_path = self.path.clone();
-        let connection = zbus::ConnectionBuilder::session()?
-            .name(self.name.clone().as_str())?
-            .serve_at(moved_path.as_str(), self)?
-            .build()
-            .await?;
-
+        let mut state_receiver = self.state.clone();
+        let screensaver = self.screensaver.clone();
+        let power_management = self.power_management.clone();
+        let name = self.name.clone();
+        let mut effect_events = self.effect_events.take();
+        let mut power_status = self.power_status.clone();
+        let mut battery_samples = self.battery_samples.clone();
+        let mut builder =
+            zbus::ConnectionBuilder::session()?.serve_at(moved_path.as_str(), self)?;
+        if let Some(screensaver) = screensaver.as_ref() {
+            for path in SCREENSAVER_PATHS {
+                builder = builder.serve_at(path, screensaver.clone())?;
+            }
+        }
+        if let Some(power_management) = power_management.as_ref() {
+            builder = builder.serve_at(POWER_MANAGEMENT_INHIBIT_PATH, power_management.clone())?;
+        }
+        let connection = builder.build().await?;
         log::debug!("Bound to D-Bus");
+
+        let dbus_proxy = zbus::fdo::DBusProxy::new(&connection).await?;
+        let mut names_lost = dbus_proxy.receive_name_lost().await?;
+        let mut names_acquired = dbus_proxy.receive_name_acquired().await?;
+        Self::request_name(&dbus_proxy, &name).await?;
+
+        let screensaver_served = screensaver.is_some();
+        if screensaver_served {
+            Self::request_compatibility_name(&connection, SCREENSAVER_NAME).await;
+        }
+        if power_management.is_some() {
+            Self::request_compatibility_name(&connection, POWER_MANAGEMENT_NAME).await;
+        }
+        let inhibitions = screensaver
+            .map(|screensaver| screensaver.inhibitions)
+            .or_else(|| {
+                power_management
+                    .as_ref()
+                    .map(|power_management| power_management.inhibitions.clone())
+            });
+        let mut owner_changes = match inhibitions {
+            Some(_) => Some(dbus_proxy.receive_name_owner_changed().await?),
+            None => None,
+        };
+
         tokio::spawn(async move {
             let moved_connection = connection;
-            handle_child.should_terminate().await;
+            let mut state_open = true;
+            loop {
+                tokio::select! {
+                    _ = handle_child.should_terminate() => break,
+                    changed = state_receiver.changed(), if state_open => {
+                        if changed.is_err() {
+                            state_open = false;
+                            continue;
+                        }
+                        if let Err(e) = Self::announce_state_change(&moved_connection, &moved_path).await {
+                            log::error!("Failed to announce state change: {}", e);
+                        }
+                    }
+                    changed = async { power_status.as_mut().unwrap().changed().await }, if power_status.is_some() => {
+                        if changed.is_err() {
+                            power_status = None;
+                            continue;
+                        }
+                        if let Err(e) = Self::announce_power_change(&moved_connection, &moved_path).await {
+                            log::error!("Failed to announce power status change: {}", e);
+                        }
+                    }
+                    changed = async { battery_samples.as_mut().unwrap().changed().await }, if battery_samples.is_some() => {
+                        if changed.is_err() {
+                            battery_samples = None;
+                            continue;
+                        }
+                        if let Err(e) = Self::announce_power_change(&moved_connection, &moved_path).await {
+                            log::error!("Failed to announce battery change: {}", e);
+                        }
+                    }
+                    event = async { effect_events.as_mut().unwrap().recv().await }, if effect_events.is_some() => {
+                        match event {
+                            Ok(event) => {
+                                if let Err(e) = Self::announce_effect_event(&moved_connection, &moved_path, event).await {
+                                    log::error!("Failed to announce effect event: {}", e);
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Lagged(count)) => {
+                                log::warn!("Missed {} effect events, their signals won't be emitted", count);
+                            }
+                            Err(broadcast::error::RecvError::Closed) => effect_events = None,
+                        }
+                    }
+                    Some(signal) = names_acquired.next() => {
+                        if matches!(signal.args(), Ok(args) if args.name() == name.as_str()) {
+                            log::info!("Acquired {} on the session bus", name);
+                        }
+                    }
+                    Some(signal) = names_lost.next() => {
+                        if matches!(signal.args(), Ok(args) if args.name() == name.as_str()) {
+                            log::warn!("Lost {} on the session bus, queueing for it again", name);
+                            if let Err(e) = Self::request_name(&dbus_proxy, &name).await {
+                                log::error!("Failed to request {}: {}", name, e);
+                            }
+                        }
+                    }
+                    Some(signal) = async { owner_changes.as_mut()?.next().await }, if owner_changes.is_some() => {
+                        let inhibitions = inhibitions.as_ref().unwrap();
+                        let had_sleep_inhibition = inhibitions.has(InhibitType::Sleep);
+                        Self::handle_owner_change(signal, inhibitions);
+                        if power_management.is_some() && had_sleep_inhibition != inhibitions.has(InhibitType::Sleep) {
+                            if let Err(e) = PowerManagementInterface::announce_change(&moved_connection).await {
+                                log::error!("Failed to announce inhibition change: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
             if let Err(e) = moved_connection
                 .object_server()
                 .remove::<Self, String>(moved_path)
@@ -41,16 +329,200 @@ impl DBusController {
             {
                 log::error!("Failed to unregister server: {}", e);
             }
+            if power_management.is_some() {
+                if let Err(e) = moved_connection
+                    .object_server()
+                    .remove::<PowerManagementInterface, _>(POWER_MANAGEMENT_INHIBIT_PATH)
+                    .await
+                {
+                    log::error!("Failed to unregister power management interface: {}", e);
+                }
+            }
+            if screensaver_served {
+                for path in SCREENSAVER_PATHS {
+                    if let Err(e) = moved_connection
+                        .object_server()
+                        .remove::<ScreenSaverInterface, _>(path)
+                        .await
+                    {
+                        log::error!("Failed to unregister screensaver interface: {}", e);
+                    }
+                }
+            }
             log::debug!("Terminated");
         });
         Ok(handle)
     }
+
+    /// Request the well-known name of the service. If another instance owns
+    /// the name, wait in the bus' queue and get it once the instance exits,
+    /// which is announced by the `NameAcquired` signal. No flags are passed,
+    /// so nobody can take the name from us while we're running.
+    async fn request_name(proxy: &zbus::fdo::DBusProxy<'_>, name: &str) -> anyhow::Result<()> {
+        let well_known_name = zbus::names::WellKnownName::try_from(name)?;
+        match proxy
+            .request_name(well_known_name, Default::default())
+            .await?
+        {
+            zbus::fdo::RequestNameReply::PrimaryOwner => log::debug!("Requested {}", name),
+            zbus::fdo::RequestNameReply::AlreadyOwner => log::debug!("Already own {}", name),
+            zbus::fdo::RequestNameReply::InQueue => log::warn!(
+                "{} is owned by another program, probably another instance of Energia, waiting for it to be released",
+                name
+            ),
+            zbus::fdo::RequestNameReply::Exists => {
+                return Err(anyhow::anyhow!("{} is owned by another program", name))
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the well-known name of a service whose interface Energia
+    /// implements for compatibility, if nobody else has it
+    async fn request_compatibility_name(connection: &zbus::Connection, name: &str) {
+        if let Err(e) = connection.request_name(name).await {
+            log::warn!(
+                "Couldn't acquire {}, is another program providing it? Applications will not be able to inhibit idleness through it: {}",
+                name,
+                e
+            );
+        }
+    }
+
+    fn handle_owner_change(
+        signal: zbus::fdo::NameOwnerChanged,
+        inhibitions: &ApplicationInhibitions,
+    ) {
+        let args = match signal.args() {
+            Ok(args) => args,
+            Err(e) => {
+                log::error!("Couldn't parse NameOwnerChanged signal: {}", e);
+                return;
+            }
+        };
+        // Inhibitions are owned by unique names, which are never reused, so
+        // they can be dropped once the name loses its owner
+        if args.new_owner().is_none() {
+            let removed = inhibitions.remove_owner(args.name().as_str());
+            if removed > 0 {
+                log::info!(
+                    "{} disconnected, removed its {} inhibition(s)",
+                    args.name(),
+                    removed
+                );
+            }
+        }
+    }
+
+    async fn announce_state_change(connection: &zbus::Connection, path: &str) -> zbus::Result<()> {
+        let interface_ref = connection
+            .object_server()
+            .interface::<_, Self>(path)
+            .await?;
+        let context = interface_ref.signal_context();
+        let interface = interface_ref.get().await;
+        interface.schedule_type_changed(context).await?;
+        interface.current_bunch_changed(context).await?;
+        interface.applied_effects_changed(context).await?;
+        interface.paused_changed(context).await?;
+        Self::state_changed(
+            context,
+            &interface.schedule_type().await,
+            interface.current_bunch().await,
+            interface.applied_effects().await,
+        )
+        .await
+    }
+
+    async fn announce_power_change(connection: &zbus::Connection, path: &str) -> zbus::Result<()> {
+        let interface_ref = connection
+            .object_server()
+            .interface::<_, Self>(path)
+            .await?;
+        let context = interface_ref.signal_context();
+        let interface = interface_ref.get().await;
+        interface.power_source_changed(context).await?;
+        interface.battery_percentage_changed(context).await?;
+        interface.time_to_empty_changed(context).await
+    }
+
+    async fn announce_effect_event(
+        connection: &zbus::Connection,
+        path: &str,
+        event: EffectEvent,
+    ) -> zbus::Result<()> {
+        let context = SignalContext::new(connection, path)?;
+        let timestamp = micros_since_epoch(event.timestamp);
+        match event.transition {
+            EffectTransition::Applied => {
+                Self::effect_applied(&context, &event.effect_name, timestamp).await
+            }
+            EffectTransition::RolledBack => {
+                Self::effect_rolled_back(&context, &event.effect_name, timestamp).await
+            }
+            // Failures are only logged and counted
+            EffectTransition::ApplicationFailed | EffectTransition::RollbackFailed => Ok(()),
+        }
+    }
+
+    async fn send_environment_command(&self, command: EnvironmentCommand) -> zbus::fdo::Result<()> {
+        match self.environment_controller.as_ref() {
+            Some(port) => port
+                .request(command)
+                .await
+                .map_err(|e| zbus::fdo::Error::Failed(format!("{}", e))),
+            None => Err(zbus::fdo::Error::NotSupported(
+                "Schedule can't be controlled through this service".to_owned(),
+            )),
+        }
+    }
+
+    fn get_inhibitions(&self) -> Option<&ApplicationInhibitions> {
+        self.screensaver
+            .as_ref()
+            .map(|screensaver| &screensaver.inhibitions)
+            .or_else(|| {
+                self.power_management
+                    .as_ref()
+                    .map(|power_management| &power_management.inhibitions)
+            })
+    }
+
+    fn get_effect_trigger(&self) -> zbus::fdo::Result<&EffectTrigger> {
+        self.effect_trigger.as_ref().ok_or_else(|| {
+            zbus::fdo::Error::NotSupported(
+                "Effects can't be triggered through this service".to_owned(),
+            )
+        })
+    }
+
+    fn get_brightness(&self) -> zbus::fdo::Result<&BrightnessAdjuster<AnyBrightnessController>> {
+        self.brightness.as_ref().ok_or_else(|| {
+            zbus::fdo::Error::NotSupported(
+                "Brightness can't be controlled through this service".to_owned(),
+            )
+        })
+    }
+
+    async fn step_brightness(&self, delta: i64) -> zbus::fdo::Result<u32> {
+        self.get_brightness()?
+            .step(delta)
+            .await
+            .map(|brightness| brightness as u32)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))
+    }
 }
 
 #[zbus::dbus_interface(name = "org.energia.Manager")]
 impl DBusController {
     async fn lock(&self) -> zbus::fdo::Result<()> {
-        if let Some(port) = self.lock_effector.as_ref() {
+        if let Some(inventory) = self.effector_inventory.as_ref() {
+            log::info!("Locking system");
+            request_effector(inventory, "lock", EffectorMessage::Execute)
+                .await
+                .map(|_| ())
+                .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))
+        } else if let Some(port) = self.lock_effector.as_ref() {
             log::info!("Locking system");
             if let Err(e) = port.request(EffectorMessage::Execute).await {
                 Err(zbus::fdo::Error::Failed(format!("{}", e)))
@@ -63,4 +535,498 @@ impl DBusController {
             ))
         }
     }
+
+    /// Apply the named effect immediately, if the configuration allows it
+    async fn trigger_effect(&self, name: String) -> zbus::fdo::Result<()> {
+        self.get_effect_trigger()?
+            .trigger(&name)
+            .await
+            .map(|_| ())
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Roll the named effect back immediately, if the configuration allows it
+    async fn rollback_effect(&self, name: String) -> zbus::fdo::Result<()> {
+        self.get_effect_trigger()?
+            .rollback(&name)
+            .await
+            .map(|_| ())
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Get the number of effects applied by the effector of the named effect
+    /// and its human-readable status, which is empty if it has nothing to say
+    async fn get_effect_status(&self, name: String) -> zbus::fdo::Result<(u32, String)> {
+        let response = self
+            .get_effect_trigger()?
+            .status(&name)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))?;
+        Ok((
+            response.applied_effects as u32,
+            response.status.unwrap_or_default(),
+        ))
+    }
+
+    /// Raise the brightness by the given number of percentage points, returning
+    /// the new brightness
+    async fn brightness_up(&self, step: u32) -> zbus::fdo::Result<u32> {
+        self.step_brightness(step.into()).await
+    }
+
+    /// Lower the brightness by the given number of percentage points,
+    /// returning the new brightness
+    async fn brightness_down(&self, step: u32) -> zbus::fdo::Result<u32> {
+        self.step_brightness(-i64::from(step)).await
+    }
+
+    /// Set the brightness to the given percentage. While the screen is
+    /// dimmed, it will return to this brightness once the user is active.
+    async fn set_brightness(&self, percent: u32) -> zbus::fdo::Result<()> {
+        self.get_brightness()?
+            .set(percent as usize)
+            .await
+            .map(|_| ())
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Pause the schedule for the given number of seconds, or until resumed if
+    /// 0 is given. Effects rolled back on user activity are rolled back.
+    async fn pause(&self, seconds: u32) -> zbus::fdo::Result<()> {
+        let duration = match seconds {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds.into())),
+        };
+        log::info!("Pause requested over D-Bus");
+        self.send_environment_command(EnvironmentCommand::Pause(duration))
+            .await
+    }
+
+    /// Keep the next effects from being applied for the given number of
+    /// seconds, continuing with the schedule afterwards. 0 ends the hold.
+    async fn hold(&self, seconds: u32) -> zbus::fdo::Result<()> {
+        log::info!("Hold requested over D-Bus");
+        self.send_environment_command(EnvironmentCommand::HoldSchedule(Duration::from_secs(
+            seconds.into(),
+        )))
+        .await
+    }
+
+    /// Resume the paused schedule
+    async fn resume(&self) -> zbus::fdo::Result<()> {
+        log::info!("Resume requested over D-Bus");
+        self.send_environment_command(EnvironmentCommand::Resume)
+            .await
+    }
+
+    /// Inhibitions requested by applications through Energia's compatibility
+    /// interfaces, as (cookie, owner's unique bus name, application, reason,
+    /// what is inhibited) tuples. Inhibitors submitted to logind are not
+    /// included, use `systemd-inhibit --list` to see them.
+    async fn list_inhibitions(&self) -> Vec<(u32, String, String, String, String)> {
+        self.get_inhibitions()
+            .map(|inhibitions| inhibitions.list())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(cookie, inhibition)| {
+                (
+                    cookie,
+                    inhibition.owner,
+                    inhibition.application,
+                    inhibition.reason,
+                    format!("{:?}", inhibition.inhibit_type).to_lowercase(),
+                )
+            })
+            .collect()
+    }
+
+    /// Actors of the daemon, as (identifier, name, health, start time in
+    /// microseconds since the Unix epoch) tuples. Actors which have crashed
+    /// are listed with "crashed" health.
+    async fn list_actors(&self) -> Vec<(u64, String, String, u64)> {
+        armaf::list_actors()
+            .into_iter()
+            .map(|info| {
+                (
+                    info.id,
+                    info.name,
+                    info.health.name().to_owned(),
+                    micros_since_epoch(info.started_at),
+                )
+            })
+            .collect()
+    }
+
+    /// The most recent state transitions, oldest first, as (time in
+    /// microseconds since the Unix epoch, kind, details) tuples. The kind is
+    /// one of "idle", "awake", "effect_applied", "effect_rolled_back",
+    /// "effect_failed", "schedule_switched", "inhibited_by" and
+    /// "effect_unavailable".
+    async fn get_recent_events(&self) -> zbus::fdo::Result<Vec<(u64, String, String)>> {
+        let history = self.event_history.as_ref().ok_or_else(|| {
+            zbus::fdo::Error::NotSupported("Event history isn't kept by this service".to_owned())
+        })?;
+        Ok(history
+            .recent()
+            .into_iter()
+            .map(|entry| {
+                (
+                    micros_since_epoch(entry.timestamp),
+                    entry.event.kind().to_owned(),
+                    entry.event.details(),
+                )
+            })
+            .collect())
+    }
+
+    /// The state of the effect execution: the number of executed bunches, the
+    /// effects which will be rolled back on activity, the effects left over
+    /// from the previous schedule which will be applied with the next bunch
+    /// and rolled back on activity, and the effects which won't be applied
+    /// again until the next rollback
+    #[allow(clippy::type_complexity)]
+    async fn get_idleness_snapshot(
+        &self,
+    ) -> zbus::fdo::Result<(u32, Vec<String>, Vec<String>, Vec<String>, Vec<String>)> {
+        let port = self
+            .idleness_ports
+            .as_ref()
+            .ok_or_else(|| {
+                zbus::fdo::Error::NotSupported(
+                    "Effect execution can't be inspected through this service".to_owned(),
+                )
+            })?
+            .borrow()
+            .clone()
+            .ok_or_else(|| {
+                zbus::fdo::Error::Failed(
+                    "No schedule is being executed, e.g. because it is paused".to_owned(),
+                )
+            })?;
+        let snapshot = port
+            .request(IdlenessMessage::GetSnapshot)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{}", e)))?
+            .unwrap_or_default();
+        Ok((
+            snapshot.executed_bunches as u32,
+            snapshot.rollback_stack,
+            snapshot.pending_execution,
+            snapshot.pending_rollback,
+            snapshot.skipped_effects,
+        ))
+    }
+
+    /// Milliseconds until the next effect bunch gets applied, or -1 if it
+    /// depends on the user becoming idle or there are no more bunches
+    async fn time_until_next_effect(&self) -> i64 {
+        match self.state.borrow().next_effect_at {
+            Some(at) => at.saturating_duration_since(Instant::now()).as_millis() as i64,
+            None => -1,
+        }
+    }
+
+    /// Configuration name of the schedule type in use, empty before the first
+    /// schedule gets started
+    #[dbus_interface(property)]
+    async fn schedule_type(&self) -> String {
+        self.state
+            .borrow()
+            .schedule_type
+            .as_ref()
+            .map(|typ| typ.config_name().to_owned())
+            .unwrap_or_default()
+    }
+
+    /// Index of the next effect bunch, 0 while the user is active
+    #[dbus_interface(property)]
+    async fn current_bunch(&self) -> u32 {
+        self.state.borrow().current_bunch as u32
+    }
+
+    /// Effects which will be rolled back once the user becomes active
+    #[dbus_interface(property)]
+    async fn applied_effects(&self) -> Vec<String> {
+        self.state.borrow().applied_effects.clone()
+    }
+
+    /// Whether the schedule is paused
+    #[dbus_interface(property)]
+    async fn paused(&self) -> bool {
+        self.state.borrow().paused
+    }
+
+    /// Either `external` or `battery`, empty if the power source isn't known
+    #[dbus_interface(property)]
+    async fn power_source(&self) -> String {
+        let status = self.power_status.as_ref().map(|status| *status.borrow());
+        match status {
+            Some(PowerStatus::External) => "external".to_owned(),
+            Some(PowerStatus::Battery(_)) => "battery".to_owned(),
+            None => String::new(),
+        }
+    }
+
+    /// Charge of the battery in percent, -1 if it isn't known
+    #[dbus_interface(property)]
+    async fn battery_percentage(&self) -> f64 {
+        if let Some(samples) = self.battery_samples.as_ref() {
+            return samples.borrow().percentage;
+        }
+        match self.power_status.as_ref().map(|status| *status.borrow()) {
+            Some(PowerStatus::Battery(percentage)) => percentage as f64,
+            _ => -1.0,
+        }
+    }
+
+    /// Estimated seconds until the battery is empty, -1 if the computer
+    /// doesn't run on battery or the estimate isn't known
+    #[dbus_interface(property)]
+    async fn time_to_empty(&self) -> i64 {
+        self.battery_samples
+            .as_ref()
+            .and_then(|samples| samples.borrow().remaining_time())
+            .map_or(-1, |remaining| remaining.as_secs() as i64)
+    }
+
+    /// Emitted whenever the schedule type, the current bunch or the applied
+    /// effects change
+    #[dbus_interface(signal)]
+    async fn state_changed(
+        context: &SignalContext<'_>,
+        schedule_type: &str,
+        current_bunch: u32,
+        applied_effects: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    /// Emitted after an effect has been applied, with the time of application
+    /// in microseconds since the Unix epoch
+    #[dbus_interface(signal)]
+    async fn effect_applied(
+        context: &SignalContext<'_>,
+        effect_name: &str,
+        timestamp: u64,
+    ) -> zbus::Result<()>;
+
+    /// Emitted after an effect has been rolled back, with the time of rollback
+    /// in microseconds since the Unix epoch
+    #[dbus_interface(signal)]
+    async fn effect_rolled_back(
+        context: &SignalContext<'_>,
+        effect_name: &str,
+        timestamp: u64,
+    ) -> zbus::Result<()>;
+}
+
+fn micros_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Implementation of the `org.freedesktop.ScreenSaver` interface, through
+/// which applications like web browsers and video players inhibit idleness
+#[derive(Clone)]
+struct ScreenSaverInterface {
+    inhibitions: ApplicationInhibitions,
+    activity_simulator: ActivitySimulator,
+    state: watch::Receiver<ManagerState>,
+}
+
+/// Whole seconds elapsed since the given instant, 0 if it's [None]
+fn seconds_since(instant: Option<Instant>) -> u32 {
+    instant
+        .map(|instant| instant.elapsed().as_secs().try_into().unwrap_or(u32::MAX))
+        .unwrap_or(0)
+}
+
+/// Remove the inhibition if its owner has disconnected before it was added.
+///
+/// Inhibitions of disconnected clients are normally removed when their
+/// `NameOwnerChanged` signal is received, but a client may disconnect right
+/// after sending its request and the signal may arrive before the inhibition
+/// is added.
+async fn drop_if_owner_gone(
+    connection: &zbus::Connection,
+    inhibitions: &ApplicationInhibitions,
+    owner: &str,
+    cookie: u32,
+) {
+    match name_has_owner(connection, owner).await {
+        Ok(true) => {}
+        Ok(false) => {
+            log::info!(
+                "{} disconnected while inhibiting, removing its inhibition",
+                owner
+            );
+            inhibitions.remove(owner, cookie);
+        }
+        Err(e) => log::warn!("Couldn't check whether {} is still connected: {}", owner, e),
+    }
+}
+
+async fn name_has_owner(connection: &zbus::Connection, name: &str) -> anyhow::Result<bool> {
+    let name = zbus::names::BusName::try_from(name)?;
+    let proxy = zbus::fdo::DBusProxy::new(connection).await?;
+    Ok(proxy.name_has_owner(name).await?)
+}
+
+fn message_sender(header: &MessageHeader<'_>) -> zbus::fdo::Result<String> {
+    match header.sender() {
+        Ok(Some(sender)) => Ok(sender.to_string()),
+        _ => Err(zbus::fdo::Error::Failed(
+            "Couldn't determine the sender of the message".to_owned(),
+        )),
+    }
+}
+
+#[zbus::dbus_interface(name = "org.freedesktop.ScreenSaver")]
+impl ScreenSaverInterface {
+    async fn inhibit(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        application_name: String,
+        reason_for_inhibit: String,
+    ) -> zbus::fdo::Result<u32> {
+        let owner = message_sender(&header)?;
+        log::info!(
+            "{} ({}) inhibits idleness: {}",
+            application_name,
+            owner,
+            reason_for_inhibit
+        );
+        let cookie = self.inhibitions.add(ApplicationInhibition {
+            owner: owner.clone(),
+            application: application_name,
+            reason: reason_for_inhibit,
+            inhibit_type: InhibitType::Idle,
+        });
+        drop_if_owner_gone(connection, &self.inhibitions, &owner, cookie).await;
+        Ok(cookie)
+    }
+
+    async fn un_inhibit(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        cookie: u32,
+    ) -> zbus::fdo::Result<()> {
+        let owner = message_sender(&header)?;
+        match self.inhibitions.remove(&owner, cookie) {
+            Some(inhibition) => {
+                log::info!(
+                    "{} ({}) stopped inhibiting idleness",
+                    inhibition.application,
+                    owner
+                );
+                Ok(())
+            }
+            None => Err(zbus::fdo::Error::InvalidArgs(format!(
+                "No inhibition with cookie {} belongs to {}",
+                cookie, owner
+            ))),
+        }
+    }
+
+    async fn simulate_user_activity(&self) -> zbus::fdo::Result<()> {
+        (self.activity_simulator)().map_err(|e| zbus::fdo::Error::Failed(format!("{}", e)))
+    }
+
+    /// Seconds since the user became idle, 0 while they are active
+    async fn get_session_idle_time(&self) -> u32 {
+        seconds_since(self.state.borrow().idle_since)
+    }
+
+    /// Seconds since the first effect, usually dimming the screen, has been
+    /// applied, 0 while no effects are applied
+    async fn get_active_time(&self) -> u32 {
+        seconds_since(self.state.borrow().effects_since)
+    }
+
+    /// Whether any effects have been applied since the user became idle
+    async fn get_active(&self) -> bool {
+        self.state.borrow().effects_since.is_some()
+    }
+}
+
+/// Implementation of the legacy `org.freedesktop.PowerManagement.Inhibit`
+/// interface, used by some older applications to prevent the computer from
+/// going to sleep
+#[derive(Clone)]
+struct PowerManagementInterface {
+    inhibitions: ApplicationInhibitions,
+}
+
+impl PowerManagementInterface {
+    async fn announce_change(connection: &zbus::Connection) -> zbus::Result<()> {
+        let interface_ref = connection
+            .object_server()
+            .interface::<_, Self>(POWER_MANAGEMENT_INHIBIT_PATH)
+            .await?;
+        let has_inhibit = interface_ref.get().await.has_inhibit().await;
+        Self::has_inhibit_changed(interface_ref.signal_context(), has_inhibit).await
+    }
+}
+
+#[zbus::dbus_interface(name = "org.freedesktop.PowerManagement.Inhibit")]
+impl PowerManagementInterface {
+    async fn inhibit(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] context: SignalContext<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        application: String,
+        reason: String,
+    ) -> zbus::fdo::Result<u32> {
+        let owner = message_sender(&header)?;
+        log::info!("{} ({}) inhibits sleep: {}", application, owner, reason);
+        let had_inhibit = self.has_inhibit().await;
+        let cookie = self.inhibitions.add(ApplicationInhibition {
+            owner: owner.clone(),
+            application,
+            reason,
+            inhibit_type: InhibitType::Sleep,
+        });
+        drop_if_owner_gone(connection, &self.inhibitions, &owner, cookie).await;
+        if !had_inhibit && self.has_inhibit().await {
+            Self::has_inhibit_changed(&context, true).await?;
+        }
+        Ok(cookie)
+    }
+
+    async fn un_inhibit(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(signal_context)] context: SignalContext<'_>,
+        cookie: u32,
+    ) -> zbus::fdo::Result<()> {
+        let owner = message_sender(&header)?;
+        match self.inhibitions.remove(&owner, cookie) {
+            Some(inhibition) => {
+                log::info!(
+                    "{} ({}) stopped inhibiting sleep",
+                    inhibition.application,
+                    owner
+                );
+                if !self.has_inhibit().await {
+                    Self::has_inhibit_changed(&context, false).await?;
+                }
+                Ok(())
+            }
+            None => Err(zbus::fdo::Error::InvalidArgs(format!(
+                "No inhibition with cookie {} belongs to {}",
+                cookie, owner
+            ))),
+        }
+    }
+
+    async fn has_inhibit(&self) -> bool {
+        self.inhibitions.has(InhibitType::Sleep)
+    }
+
+    #[dbus_interface(signal)]
+    async fn has_inhibit_changed(
+        context: &SignalContext<'_>,
+        has_inhibit_changed: bool,
+    ) -> zbus::Result<()>;
 }
@@ -0,0 +1,152 @@
+//! Management of additional X displays, e.g. on thin clients and multiseat
+//! computers
+//!
+//! Each display configured in the `[displays.<name>]` section gets its own
+//! tree of actors - an [IdlenessBroker], an [EffectorInventory] and an
+//! [EnvironmentController] with its own schedules.
+
+use super::{
+    effector_inventory::{
+        parse_eager_spawning, spawn_scheduled_effectors, EffectorInventory, InventoryPort,
+    },
+    environment_controller::{EnvironmentController, EnvironmentPort},
+    idleness_broker::{parse_idleness_debounce, IdlenessBroker},
+};
+use crate::{
+    armaf::{spawn_server, Handle, ShutdownCoordinator},
+    config::SharedConfig,
+    external::dependency_provider::DependencyProvider,
+    system::{inhibition_sensor::InhibitionSensorPort, upower_sensor::PowerStatus},
+};
+use anyhow::{anyhow, Context, Result};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// Name of the configuration section listing the additional displays
+pub const DISPLAYS_SECTION: &str = "displays";
+
+/// An additional display and the configuration used for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayConfig {
+    pub name: String,
+    /// Name of the X display, e.g. `:1`
+    pub display: String,
+    /// The main configuration with the sections given in the display's table
+    /// replacing those of the same name
+    pub config: SharedConfig,
+}
+
+/// Parse the additional displays, sorted by their names
+pub fn parse_displays(config: &toml::Value) -> Result<Vec<DisplayConfig>> {
+    let section = match config.get(DISPLAYS_SECTION) {
+        Some(section) => section
+            .as_table()
+            .ok_or_else(|| anyhow!("{} is not a table", DISPLAYS_SECTION))?,
+        None => return Ok(Vec::new()),
+    };
+    let mut base_config = config.as_table().cloned().unwrap_or_default();
+    base_config.remove(DISPLAYS_SECTION);
+    let mut displays = section
+        .iter()
+        .map(|(name, display)| {
+            let table = display
+                .as_table()
+                .ok_or_else(|| anyhow!("{}.{} is not a table", DISPLAYS_SECTION, name))?;
+            let display = table
+                .get("display")
+                .and_then(|display| display.as_str())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{}.{}.display has to be the name of an X display, e.g. \":1\"",
+                        DISPLAYS_SECTION,
+                        name
+                    )
+                })?;
+            let mut config = base_config.clone();
+            for (key, value) in table.iter().filter(|(key, _)| *key != "display") {
+                config.insert(key.clone(), value.clone());
+            }
+            Ok(DisplayConfig {
+                name: name.clone(),
+                display: display.to_owned(),
+                config: Arc::new(toml::Value::Table(config)),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    displays.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(displays)
+}
+
+/// The actors managing an additional display
+pub struct DisplayTree {
+    name: String,
+    idleness_broker: Handle,
+    effector_inventory: InventoryPort,
+    environment_controller: EnvironmentPort,
+}
+
+impl DisplayTree {
+    /// Spawn the actors for the display whose services are provided by the
+    /// given [DependencyProvider]
+    pub async fn spawn(
+        display: &DisplayConfig,
+        dependencies: DependencyProvider,
+        inhibition_sensor: InhibitionSensorPort,
+        power_status: watch::Receiver<PowerStatus>,
+    ) -> Result<DisplayTree> {
+        let dependencies = dependencies.for_additional_display();
+        let ds_controller = dependencies.get_display_controller();
+        let idleness_broker = IdlenessBroker::new(dependencies.get_idleness_channel())
+            .with_display_controller(ds_controller.clone())
+            .with_debounce(parse_idleness_debounce(&display.config)?);
+        let idleness_channel = idleness_broker.subscribe();
+        let idleness_broker = idleness_broker.spawn();
+        let effector_inventory =
+            spawn_server(EffectorInventory::new(display.config.clone(), dependencies))
+                .await
+                .context("Couldn't spawn EffectorInventory")?;
+        if parse_eager_spawning(&display.config)? {
+            spawn_scheduled_effectors(&effector_inventory, &display.config).await?;
+        }
+        let environment_controller = EnvironmentController::new(
+            display.config.clone(),
+            effector_inventory.clone(),
+            inhibition_sensor,
+            ds_controller,
+            idleness_channel,
+            power_status,
+        )
+        .spawn()
+        .await
+        .context("Couldn't spawn environment controller")?;
+        Ok(DisplayTree {
+            name: display.name.clone(),
+            idleness_broker,
+            effector_inventory,
+            environment_controller,
+        })
+    }
+
+    /// Register the shutdown of the display's actors, named after the display
+    pub fn add_to_shutdown(self, shutdown: &mut ShutdownCoordinator) {
+        let inventory = format!("EffectorInventory ({})", self.name);
+        let environment_controller = format!("EnvironmentController ({})", self.name);
+        // Rolling effects back may run external commands
+        shutdown.add_with_timeout(
+            &inventory,
+            &[],
+            Duration::from_secs(15),
+            self.effector_inventory.await_shutdown(),
+        );
+        shutdown.add(
+            &environment_controller,
+            &[&inventory],
+            self.environment_controller.await_shutdown(),
+        );
+        shutdown.add(
+            &format!("IdlenessBroker ({})", self.name),
+            &[],
+            self.idleness_broker.await_shutdown(),
+        );
+    }
+}
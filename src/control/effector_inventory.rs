@@ -1,140 +1,683 @@
 //! Centralized storage of effector's ActorPorts and lazy spawning of effectors
-//!
-//! This module is a hack for working around Effector trait not being object-safe
-//! and it not being possible to make it object safe with the current
-//! architecture.
+//! found in the [effector registry](super::effector_registry)
 
+use super::{
+    effector_registry::{effectors, find_effector},
+    environment_controller::{
+        configured_schedule_types, has_builtin_schedule, parse_schedules, schedule_to_bunches,
+        schedule_types, schedules_for_day, DayType, ScheduleType, INHIBITED_BY_KEY, ROLLBACK_KEY,
+    },
+};
+#[cfg(any(test, feature = "simulation"))]
+use crate::system::simulated_effector::{SimulatedEffectorActor, SimulatedEvent};
 use crate::{
-    armaf::{Effect, Effector, EffectorPort, Server},
-    external::{
-        brightness::BrightnessController, dependency_provider::DependencyProvider,
-        display_server::DisplayServer,
+    armaf::{
+        self, spawn_supervised, ActorPort, ActorRequestError, ConfigSchema, Effect,
+        EffectorMessage, EffectorPort, EffectorResponse, GaveUp, RestartPolicy, Server,
+        TickService, Ticks,
     },
-    system,
+    config::SharedConfig,
+    external::dependency_provider::DependencyProvider,
 };
-use anyhow::Result;
-use std::collections::HashMap;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+#[cfg(any(test, feature = "simulation"))]
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
 
 /// Get a vector of the names of all known effectors
-pub fn get_known_effector_names() -> Vec<&'static str> {
-    vec!["brightness", "dpms", "session", "sleep", "lock"]
+pub fn get_known_effector_names() -> Vec<String> {
+    effectors()
+        .iter()
+        .map(|effector| effector.get_name())
+        .collect()
 }
 
 /// Get effects provided by the named effector
 pub fn get_effects_for_effector(effector_name: &str) -> Vec<Effect> {
-    match effector_name {
-        "brightness" => system::brightness_effector::BrightnessEffector.get_effects(),
-        "dpms" => system::dpms_effector::DPMSEffector.get_effects(),
-        "session" => system::session_effector::SessionEffector.get_effects(),
-        "sleep" => system::sleep_effector::SleepEffector.get_effects(),
-        "lock" => system::lock_effector::LockEffector.get_effects(),
-        _ => unreachable!(),
+    find_effector(effector_name)
+        .map(|effector| effector.get_effects())
+        .unwrap_or_else(|| unreachable!("unknown effector {}", effector_name))
+}
+
+/// Get the schema of the named effector's configuration section
+pub fn get_config_schema_for_effector(effector_name: &str) -> ConfigSchema {
+    find_effector(effector_name)
+        .map(|effector| effector.get_config_schema())
+        .unwrap_or_else(|| unreachable!("unknown effector {}", effector_name))
+}
+
+/// Check whether the configuration section of the named effector is valid,
+/// without spawning the effector.
+///
+/// The error lists all the problems found in the section.
+pub fn check_effector_config(effector_name: &str, config: Option<&toml::Value>) -> Result<()> {
+    if find_effector(effector_name).is_none() {
+        return Err(anyhow!("unknown effector"));
     }
+    let violations = get_config_schema_for_effector(effector_name).validate(effector_name, config);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        let messages: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        Err(anyhow!("{}", messages.join("; ")))
+    }
+}
+
+/// Check the configuration of all the known effectors which is present in the
+/// configuration, including the overrides in schedules
+fn check_present_effector_configs(config: &toml::Value) -> Result<()> {
+    for effector_name in get_known_effector_names() {
+        let effector_name = effector_name.as_str();
+        if let Some(section) = config.get(effector_name) {
+            check_effector_config(effector_name, Some(section))
+                .context("Invalid effector configuration")?;
+        }
+        for typ in configured_schedule_types(config) {
+            for day in schedule_day_variants(config, &typ) {
+                let schedule_config =
+                    effector_config_for_schedule(config, effector_name, Some(&typ), day);
+                if schedule_config.as_ref() != config.get(effector_name) {
+                    check_effector_config(effector_name, schedule_config.as_ref()).with_context(
+                        || {
+                            format!(
+                                "Invalid effector configuration in schedule {}",
+                                describe_schedule(&typ, day)
+                            )
+                        },
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the configuration with which the named effector should be spawned when
+/// used in the given schedule, in its variant for the given type of day if
+/// one is given.
+///
+/// Any keys other than `after`, `rollback` and `inhibited_by` in the tables of
+/// the schedule's effects which are provided by the effector override the keys
+/// in the effector's own configuration section.
+pub fn effector_config_for_schedule(
+    config: &toml::Value,
+    effector_name: &str,
+    schedule_type: Option<&ScheduleType>,
+    day: Option<DayType>,
+) -> Option<toml::Value> {
+    let base_config = config.get(effector_name).cloned();
+    let day_config = day.map(|day| schedules_for_day(config, day));
+    let schedule = match schedule_type.and_then(|typ| {
+        day_config
+            .as_ref()
+            .unwrap_or(config)
+            .get("schedule")
+            .and_then(|schedules| schedules.get(typ.config_name()))
+    }) {
+        Some(schedule) => schedule,
+        None => return base_config,
+    };
+    let mut overrides = toml::value::Map::new();
+    for effect in get_effects_for_effector(effector_name) {
+        if let Some(toml::Value::Table(effect_table)) = schedule.get(&effect.name) {
+            for (key, value) in effect_table {
+                if !["after", ROLLBACK_KEY, INHIBITED_BY_KEY].contains(&key.as_str()) {
+                    overrides.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    if overrides.is_empty() {
+        return base_config;
+    }
+    let mut effective_config =
+        base_config.unwrap_or_else(|| toml::Value::Table(toml::value::Map::new()));
+    if let toml::Value::Table(table) = &mut effective_config {
+        table.extend(overrides);
+    }
+    Some(effective_config)
+}
+
+/// Get the type of day if the given schedule has a variant for it, the
+/// schedules without one are the same on every day
+pub fn schedule_day_variant(
+    config: &toml::Value,
+    schedule_type: &ScheduleType,
+    day: DayType,
+) -> Option<DayType> {
+    config
+        .get("schedule")
+        .and_then(|schedules| schedules.get(schedule_type.config_name()))
+        .and_then(|schedule| schedule.get(day.config_name()))
+        .map(|_| day)
+}
+
+/// Get the day variants of the schedule, [None] standing for the schedule
+/// used on the days without a variant
+pub fn schedule_day_variants(
+    config: &toml::Value,
+    schedule_type: &ScheduleType,
+) -> Vec<Option<DayType>> {
+    let mut variants: Vec<Option<DayType>> = DayType::ALL
+        .iter()
+        .map(|day| schedule_day_variant(config, schedule_type, *day))
+        .collect();
+    variants.dedup();
+    variants
+}
+
+/// Describe the schedule along with its day variant
+pub fn describe_schedule(schedule_type: &ScheduleType, day: Option<DayType>) -> String {
+    match day {
+        Some(day) => format!("{} ({})", schedule_type.config_name(), day.config_name()),
+        None => schedule_type.config_name().to_owned(),
+    }
+}
+
+/// How long a running effector may take to answer a health probe before it's
+/// considered busy
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the running effectors are probed
+pub const HEALTH_CHECK_PERIOD: Duration = Duration::from_secs(60);
+
+/// Name of the configuration section choosing when the effectors are spawned
+pub const EFFECTORS_SECTION: &str = "effectors";
+
+/// Whether all the effectors used by the schedules should be spawned when
+/// Energia starts, instead of when their effects are first executed
+pub fn parse_eager_spawning(config: &toml::Value) -> Result<bool> {
+    let spawn = match config.get(EFFECTORS_SECTION).and_then(|s| s.get("spawn")) {
+        Some(spawn) => spawn,
+        None => return Ok(false),
+    };
+    match spawn.as_str() {
+        Some("eager") => Ok(true),
+        Some("lazy") => Ok(false),
+        _ => Err(anyhow!("{} is neither \"eager\" nor \"lazy\"", spawn))
+            .context("Invalid effectors.spawn"),
+    }
+}
+
+/// Get the effectors used by the defined schedules, along with the schedule
+/// using them and its day variant, if it has one
+pub fn scheduled_effectors(
+    config: &toml::Value,
+) -> Result<Vec<(String, ScheduleType, Option<DayType>)>> {
+    parse_schedules(config)?;
+    let effect_names_mapping = resolve_effectors_for_effects();
+    let mut used = Vec::new();
+    for day in DayType::ALL {
+        let schedules = parse_schedules(&schedules_for_day(config, day))?;
+        for typ in schedule_types(&schedules) {
+            if !has_builtin_schedule(&schedules) || !schedules.contains_key(&typ) {
+                continue;
+            }
+            for (_, effects) in schedule_to_bunches(&schedules[&typ], &effect_names_mapping)? {
+                for effect in effects {
+                    used.push((
+                        effect_names_mapping[&effect.name].0.clone(),
+                        typ.clone(),
+                        schedule_day_variant(config, &typ, day),
+                    ));
+                }
+            }
+        }
+    }
+    used.sort_by(|(a_name, a_typ, a_day), (b_name, b_typ, b_day)| {
+        let day_name = |day: &Option<DayType>| day.map(|day| day.config_name());
+        (a_name, a_typ.config_name(), day_name(a_day)).cmp(&(
+            b_name,
+            b_typ.config_name(),
+            day_name(b_day),
+        ))
+    });
+    used.dedup();
+    Ok(used)
+}
+
+/// Spawn all the effectors used by the schedules, so that problems with their
+/// configuration or dependencies show up right away. The error lists all the
+/// effectors which couldn't be spawned.
+pub async fn spawn_scheduled_effectors(
+    inventory: &InventoryPort,
+    config: &toml::Value,
+) -> Result<()> {
+    let mut failures = Vec::new();
+    for (effector_name, schedule_type, day) in scheduled_effectors(config)? {
+        if let Err(e) =
+            get_schedule_effector_port(inventory, &effector_name, schedule_type.clone(), day).await
+        {
+            failures.push(format!(
+                "{} (schedule {}): {:#}",
+                effector_name,
+                describe_schedule(&schedule_type, day),
+                e
+            ));
+        }
+    }
+    if !failures.is_empty() {
+        bail!("Couldn't spawn effectors: {}", failures.join("; "));
+    }
+    Ok(())
+}
+
+/// A message controlling an [EffectorInventory]
+#[derive(Debug, Clone)]
+pub enum InventoryMessage {
+    /// Resolve the correct effector according to the name passed in the
+    /// message and get its [EffectorPort].
+    ///
+    /// If the effector has not yet been spawned by the receiving
+    /// EffectorInventory, it gets spawned.
+    GetEffectorPort(String),
+    /// Like [InventoryMessage::GetEffectorPort], but the effector gets
+    /// configured with the overrides from the given schedule, in its variant
+    /// for the given type of day if it has one.
+    ///
+    /// If the schedule doesn't override the effector's configuration, the
+    /// same instance as for [InventoryMessage::GetEffectorPort] is returned.
+    GetScheduleEffectorPort(String, ScheduleType, Option<DayType>),
+    /// Replace the configuration from which the effectors are spawned.
+    ///
+    /// Running effectors whose configuration section has changed are
+    /// forgotten and will be respawned with the new configuration once their
+    /// port is requested again. The old instances keep running until all the
+    /// ports which were handed out for them get dropped, so that their effects
+    /// can still be rolled back.
+    ReloadConfig(SharedConfig),
+    /// Probe the running effectors and forget those which have died, e.g.
+    /// because their supervisor gave up restarting them, so that they get
+    /// respawned once their port is requested again
+    CheckHealth,
+}
+
+/// The port of an [EffectorInventory].
+///
+/// [InventoryMessage::GetEffectorPort] is answered with the requested port,
+/// other messages are answered with `None`.
+pub type InventoryPort = ActorPort<InventoryMessage, Option<EffectorPort>, anyhow::Error>;
+
+/// Request the port of the named effector from an [EffectorInventory]
+pub async fn get_effector_port(inventory: &InventoryPort, name: &str) -> Result<EffectorPort> {
+    inventory
+        .request(InventoryMessage::GetEffectorPort(name.to_string()))
+        .await?
+        .ok_or_else(|| anyhow!("EffectorInventory didn't return a port for {}", name))
 }
 
-/// Resolve the correct effector according to the name passed in the message and
-/// get its [EffectorPort].
+/// Request the port of the named effector configured for the given schedule
+/// and type of day from an [EffectorInventory]
+pub async fn get_schedule_effector_port(
+    inventory: &InventoryPort,
+    name: &str,
+    schedule_type: ScheduleType,
+    day: Option<DayType>,
+) -> Result<EffectorPort> {
+    inventory
+        .request(InventoryMessage::GetScheduleEffectorPort(
+            name.to_string(),
+            schedule_type,
+            day,
+        ))
+        .await?
+        .ok_or_else(|| anyhow!("EffectorInventory didn't return a port for {}", name))
+}
+
+/// Send the message to the named effector, spawning it if it isn't running.
 ///
-/// If the effector has not yet been spawned by the receiving EffectorInventory,
-/// it gets spawned.
-pub struct GetEffectorPort(pub String);
+/// If the request fails and the effector turns out to be dead, e.g. because
+/// its supervisor has given up restarting it, the inventory forgets it and
+/// the message is sent once more to a freshly spawned effector.
+pub async fn request_effector(
+    inventory: &InventoryPort,
+    name: &str,
+    message: EffectorMessage,
+) -> Result<EffectorResponse> {
+    let port = get_effector_port(inventory, name).await?;
+    let error = match port.request(message).await {
+        Ok(response) => return Ok(response),
+        Err(e) => e,
+    };
+    let probe = tokio::time::timeout(
+        HEALTH_PROBE_TIMEOUT,
+        port.request(EffectorMessage::CurrentlyAppliedEffects),
+    )
+    .await;
+    if !matches!(probe, Ok(Err(e)) if is_dead(&e)) {
+        return Err(error.into());
+    }
+    log::warn!("Effector {} has died, respawning it: {:?}", name, error);
+    inventory.request(InventoryMessage::CheckHealth).await?;
+    Ok(get_effector_port(inventory, name)
+        .await?
+        .request(message)
+        .await?)
+}
+
+/// Whether the error means that the effector's actor isn't running anymore,
+/// including when its supervisor has given up restarting it. Errors returned
+/// by the actor itself, e.g. when it can't read the current brightness, don't.
+fn is_dead(error: &ActorRequestError<anyhow::Error>) -> bool {
+    match error {
+        ActorRequestError::Send | ActorRequestError::Recv => true,
+        ActorRequestError::Actor(e) => e.is::<GaveUp>(),
+    }
+}
 
 /// An actor providing centralized storage of effector ports and name resolution
 /// for them
-pub struct EffectorInventory<B: BrightnessController, D: DisplayServer> {
-    config: toml::Value,
-    running_effectors: HashMap<String, EffectorPort>,
-    dependency_provider: DependencyProvider<B, D>,
+pub struct EffectorInventory {
+    config: SharedConfig,
+    running_effectors: HashMap<InstanceKey, RunningEffector>,
+    // Shared with the supervisors, which need it to restart crashed effectors
+    dependency_provider: Arc<Mutex<DependencyProvider>>,
+    #[cfg(any(test, feature = "simulation"))]
+    simulation_events: Option<mpsc::UnboundedSender<SimulatedEvent>>,
+    restart_policy: RestartPolicy,
+}
+
+/// The name of an effector along with the schedule whose overrides its
+/// instance is configured with and the schedule's day variant, if it has one
+type InstanceKey = (String, Option<(ScheduleType, Option<DayType>)>);
+
+struct RunningEffector {
+    config: Option<toml::Value>,
+    port: EffectorPort,
 }
 
-impl<B: BrightnessController, D: DisplayServer> EffectorInventory<B, D> {
+impl EffectorInventory {
     /// Create a new EffectorInventory
-    pub fn new(
-        config: toml::Value,
-        dependency_provider: DependencyProvider<B, D>,
-    ) -> EffectorInventory<B, D> {
+    pub fn new(config: SharedConfig, dependency_provider: DependencyProvider) -> EffectorInventory {
         EffectorInventory {
             config,
             running_effectors: HashMap::new(),
-            dependency_provider,
+            dependency_provider: Arc::new(Mutex::new(dependency_provider)),
+            #[cfg(any(test, feature = "simulation"))]
+            simulation_events: None,
+            restart_policy: RestartPolicy::default(),
         }
     }
+
+    /// Restart crashed effectors according to the given policy instead of the
+    /// default one
+    pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> EffectorInventory {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    /// Make the EffectorInventory spawn [SimulatedEffectorActor]s, which only
+    /// send the effects they would apply into the given channel, instead of
+    /// the real effectors
+    #[cfg(any(test, feature = "simulation"))]
+    pub fn with_simulation(
+        mut self,
+        events: mpsc::UnboundedSender<SimulatedEvent>,
+    ) -> EffectorInventory {
+        self.simulation_events = Some(events);
+        self
+    }
+
+    async fn get_effector_port(
+        &mut self,
+        effector_name: String,
+        schedule: Option<(ScheduleType, Option<DayType>)>,
+    ) -> Result<EffectorPort> {
+        // The days without a variant of the schedule share its instance
+        let schedule = schedule.map(|(typ, day)| {
+            let day = day.and_then(|day| schedule_day_variant(&self.config, &typ, day));
+            (typ, day)
+        });
+        let config = effector_config_for_schedule(
+            &self.config,
+            &effector_name,
+            schedule.as_ref().map(|(typ, _)| typ),
+            schedule.as_ref().and_then(|(_, day)| *day),
+        );
+        // Schedules which don't override anything share the default instance
+        let key = if schedule.is_some() && config != self.config.get(&effector_name).cloned() {
+            (effector_name, schedule)
+        } else {
+            (effector_name, None)
+        };
+        match self.running_effectors.get(&key) {
+            Some(running) if running.port.is_closed() => {
+                log::warn!(
+                    "{} has terminated, it will be respawned",
+                    describe_instance(&key)
+                );
+                self.running_effectors.remove(&key);
+            }
+            Some(running) => return Ok(running.port.clone()),
+            None => {}
+        }
+        check_effector_config(&key.0, config.as_ref())?;
+        let port = self.spawn_effector_instance(&key, config.clone()).await?;
+        self.running_effectors.insert(
+            key,
+            RunningEffector {
+                config,
+                port: port.clone(),
+            },
+        );
+        Ok(port)
+    }
+
+    /// Spawn the effector with the given configuration, or its simulation
+    async fn spawn_effector_instance(
+        &self,
+        key: &InstanceKey,
+        config: Option<toml::Value>,
+    ) -> Result<EffectorPort> {
+        #[cfg(any(test, feature = "simulation"))]
+        if let Some(events) = self.simulation_events.as_ref() {
+            return Ok(armaf::spawn_server(SimulatedEffectorActor::new(
+                &key.0,
+                get_effects_for_effector(&key.0),
+                events.clone(),
+            ))
+            .await?);
+        }
+        let dependency_provider = self.dependency_provider.clone();
+        let effector_name = key.0.clone();
+        Ok(spawn_supervised(
+            &describe_instance(key),
+            self.restart_policy.clone(),
+            move || {
+                let dependency_provider = dependency_provider.clone();
+                let effector_name = effector_name.clone();
+                let effector_config = config.clone();
+                async move {
+                    let mut dependency_provider = dependency_provider.lock().await;
+                    spawn_effector(
+                        &effector_name,
+                        &mut dependency_provider,
+                        effector_config.as_ref(),
+                    )
+                    .await
+                }
+            },
+        )
+        .await?)
+    }
+
+    /// Forget the effectors whose actors don't receive or answer a probe
+    /// anymore. The probes run concurrently, an effector busy with a long request, e.g.
+    /// waiting for the computer to be unlocked, is considered alive.
+    async fn forget_dead_effectors(&mut self) {
+        let probes: Vec<_> = self
+            .running_effectors
+            .iter()
+            .map(|(key, running)| {
+                let port = running.port.clone();
+                let probe = tokio::spawn(async move {
+                    match tokio::time::timeout(
+                        HEALTH_PROBE_TIMEOUT,
+                        port.request(EffectorMessage::CurrentlyAppliedEffects),
+                    )
+                    .await
+                    {
+                        Ok(Err(e)) if is_dead(&e) => Some(e),
+                        _ => None,
+                    }
+                });
+                (key.clone(), probe)
+            })
+            .collect();
+        for (key, probe) in probes {
+            if let Ok(Some(e)) = probe.await {
+                log::warn!(
+                    "{} failed its health check, it will be respawned: {:?}",
+                    describe_instance(&key),
+                    e
+                );
+                self.running_effectors.remove(&key);
+            }
+        }
+    }
+
+    fn reload_config(&mut self, new_config: SharedConfig) -> Result<()> {
+        check_present_effector_configs(&new_config)?;
+        let changed_effectors: Vec<InstanceKey> = self
+            .running_effectors
+            .iter()
+            .filter(|((name, schedule), running)| {
+                running.config
+                    != effector_config_for_schedule(
+                        &new_config,
+                        name,
+                        schedule.as_ref().map(|(typ, _)| typ),
+                        schedule.as_ref().and_then(|(_, day)| *day),
+                    )
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in changed_effectors {
+            log::info!(
+                "Configuration of {} changed, it will be respawned",
+                describe_instance(&key)
+            );
+            self.running_effectors.remove(&key);
+        }
+        self.config = new_config;
+        Ok(())
+    }
+}
+
+fn describe_instance(key: &InstanceKey) -> String {
+    match &key.1 {
+        Some((schedule_type, day)) => format!(
+            "{} (schedule {})",
+            key.0,
+            describe_schedule(schedule_type, *day)
+        ),
+        None => key.0.clone(),
+    }
 }
 
 #[async_trait::async_trait]
-impl<B: BrightnessController, D: DisplayServer> Server<GetEffectorPort, EffectorPort>
-    for EffectorInventory<B, D>
-{
+impl Server<InventoryMessage, Option<EffectorPort>> for EffectorInventory {
     fn get_name(&self) -> String {
         "EffectorInventory".to_string()
     }
 
-    async fn handle_message(&mut self, payload: GetEffectorPort) -> Result<EffectorPort> {
-        let GetEffectorPort(ref effector_name) = payload;
-        if self.running_effectors.contains_key(effector_name) {
-            return Ok(self.running_effectors[effector_name].clone());
+    async fn handle_message(&mut self, payload: InventoryMessage) -> Result<Option<EffectorPort>> {
+        match payload {
+            InventoryMessage::GetEffectorPort(effector_name) => {
+                Ok(Some(self.get_effector_port(effector_name, None).await?))
+            }
+            InventoryMessage::GetScheduleEffectorPort(effector_name, schedule_type, day) => {
+                Ok(Some(
+                    self.get_effector_port(effector_name, Some((schedule_type, day)))
+                        .await?,
+                ))
+            }
+            InventoryMessage::ReloadConfig(new_config) => {
+                self.reload_config(new_config)?;
+                Ok(None)
+            }
+            InventoryMessage::CheckHealth => {
+                self.forget_dead_effectors().await;
+                Ok(None)
+            }
         }
-        let config = self.config.get(effector_name);
-        let port = spawn_effector(effector_name, &mut self.dependency_provider, config).await?;
-        self.running_effectors.insert(payload.0, port.clone());
-        Ok(port)
+    }
+
+    async fn initialize(&mut self) -> Result<()> {
+        check_present_effector_configs(&self.config)
     }
 
     async fn tear_down(&mut self) -> Result<()> {
-        for (effector, port) in self.running_effectors.drain() {
-            log::info!("Terminating {}", effector);
-            port.await_shutdown().await;
+        for (key, running) in self.running_effectors.drain() {
+            log::info!("Terminating {}", describe_instance(&key));
+            running.port.await_shutdown().await;
         }
         Ok(())
     }
 }
 
-pub async fn spawn_effector<B: BrightnessController, D: DisplayServer>(
+pub async fn spawn_effector(
     effector_name: &str,
-    dependency_provider: &mut DependencyProvider<B, D>,
+    dependency_provider: &mut DependencyProvider,
     config: Option<&toml::Value>,
 ) -> Result<EffectorPort> {
-    let config_clone = config.cloned();
-    match effector_name {
-        "brightness" => {
-            system::brightness_effector::BrightnessEffector
-                .spawn(config_clone, dependency_provider)
-                .await
-        }
-        "dpms" => {
-            system::dpms_effector::DPMSEffector
-                .spawn(config_clone, dependency_provider)
-                .await
-        }
-        "session" => {
-            system::session_effector::SessionEffector
-                .spawn(config_clone, dependency_provider)
-                .await
-        }
-        "sleep" => {
-            system::sleep_effector::SleepEffector
-                .spawn(config_clone, dependency_provider)
-                .await
-        }
-        "lock" => {
-            system::lock_effector::LockEffector
-                .spawn(config_clone, dependency_provider)
-                .await
+    match find_effector(effector_name) {
+        Some(effector) => effector.spawn(config.cloned(), dependency_provider).await,
+        None => Err(anyhow::anyhow!("unknown effector")),
+    }
+}
+
+/// Periodically asks an [EffectorInventory] to
+/// [check the health](InventoryMessage::CheckHealth) of its effectors
+pub struct EffectorHealthCheck {
+    inventory: InventoryPort,
+    ticks: Ticks,
+    handle_child: Option<armaf::HandleChild>,
+}
+
+impl EffectorHealthCheck {
+    pub fn new(
+        inventory: InventoryPort,
+        tick_service: &TickService,
+        period: Duration,
+    ) -> EffectorHealthCheck {
+        EffectorHealthCheck {
+            inventory,
+            ticks: tick_service.subscribe(period),
+            handle_child: None,
         }
-        _ => Err(anyhow::anyhow!("unknown effector")),
+    }
+
+    pub fn spawn(mut self) -> armaf::Handle {
+        let (handle, handle_child) = armaf::Handle::new();
+        self.handle_child = Some(handle_child);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = self.handle_child.as_mut().unwrap().should_terminate() => return,
+                    _ = self.ticks.tick() => {
+                        if let Err(e) = self.inventory.request(InventoryMessage::CheckHealth).await {
+                            log::error!("Couldn't check the health of effectors: {:?}", e);
+                        }
+                    }
+                }
+            }
+        });
+        handle
     }
 }
 
 pub fn resolve_effectors_for_effects() -> HashMap<String, (String, usize)> {
     let mut m = HashMap::new();
-    for effector_name in get_known_effector_names().iter() {
-        for (i, effect) in get_effects_for_effector(effector_name).iter().enumerate() {
+    for effector in effectors() {
+        let effector_name = effector.get_name();
+        for (i, effect) in effector.get_effects().iter().enumerate() {
             log::trace!(
                 "Resolved effect {} to effector {}",
                 effect.name,
                 effector_name
             );
-            m.insert(effect.name.to_string(), (effector_name.to_string(), i));
+            m.insert(effect.name.to_string(), (effector_name.clone(), i));
         }
     }
     m
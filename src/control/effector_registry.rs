@@ -0,0 +1,151 @@
+//! Process-wide registry of the effectors which can be used in schedules
+//!
+//! The built-in effectors are always available, others can be registered
+//! before the daemon starts using them.
+
+use crate::{
+    armaf::Effector,
+    system::{
+        blank_effector::BlankEffector, brightness_effector::BrightnessEffector,
+        dpms_effector::DPMSEffector, lock_effector::LockEffector,
+        session_effector::SessionEffector, sleep_effector::SleepEffector,
+    },
+};
+use anyhow::{bail, Result};
+use std::sync::{Arc, RwLock};
+
+static REGISTERED: RwLock<Vec<Arc<dyn Effector>>> = RwLock::new(Vec::new());
+
+fn builtin_effectors() -> Vec<Arc<dyn Effector>> {
+    vec![
+        Arc::new(BrightnessEffector),
+        Arc::new(DPMSEffector),
+        Arc::new(BlankEffector),
+        Arc::new(SessionEffector),
+        Arc::new(SleepEffector),
+        Arc::new(LockEffector),
+    ]
+}
+
+/// All the known effectors, the built-in ones first and the registered ones
+/// in the order of their registration
+pub fn effectors() -> Vec<Arc<dyn Effector>> {
+    let mut effectors = builtin_effectors();
+    effectors.extend(
+        REGISTERED
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned(),
+    );
+    effectors
+}
+
+/// Find the effector with the given name
+pub fn find_effector(name: &str) -> Option<Arc<dyn Effector>> {
+    effectors()
+        .into_iter()
+        .find(|effector| effector.get_name() == name)
+}
+
+/// Make the effector available to the schedules. Neither the effector's name
+/// nor the names of its effects may be used by a known effector.
+pub fn register_effector(effector: Arc<dyn Effector>) -> Result<()> {
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    let name = effector.get_name();
+    let effects = effector.get_effects();
+    for known in builtin_effectors().iter().chain(registered.iter()) {
+        if known.get_name() == name {
+            bail!("effector {} is already registered", name);
+        }
+        for effect in known.get_effects() {
+            if effects.iter().any(|e| e.name == effect.name) {
+                bail!(
+                    "effect {} of effector {} is already provided by effector {}",
+                    effect.name,
+                    name,
+                    known.get_name()
+                );
+            }
+        }
+    }
+    registered.push(effector);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        armaf::{Effect, EffectorPort, RollbackStrategy},
+        external::dependency_provider::DependencyProvider,
+    };
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    struct TestEffector {
+        name: &'static str,
+        effect: &'static str,
+    }
+
+    #[async_trait]
+    impl Effector for TestEffector {
+        fn get_name(&self) -> String {
+            self.name.to_owned()
+        }
+
+        fn get_effects(&self) -> Vec<Effect> {
+            vec![Effect::new(
+                self.effect.to_owned(),
+                vec![],
+                RollbackStrategy::None,
+            )]
+        }
+
+        async fn spawn(
+            &self,
+            _: Option<toml::Value>,
+            _: &mut DependencyProvider,
+        ) -> Result<EffectorPort> {
+            Err(anyhow!("not spawnable"))
+        }
+    }
+
+    #[test]
+    fn test_register_effector() {
+        assert_eq!(find_effector("dpms").unwrap().get_name(), "dpms");
+        assert!(find_effector("registry_test").is_none());
+
+        register_effector(Arc::new(TestEffector {
+            name: "registry_test",
+            effect: "registry_test_effect",
+        }))
+        .unwrap();
+        assert_eq!(
+            find_effector("registry_test").unwrap().get_effects()[0].name,
+            "registry_test_effect"
+        );
+        assert!(effectors()
+            .iter()
+            .any(|effector| effector.get_name() == "registry_test"));
+
+        let error = register_effector(Arc::new(TestEffector {
+            name: "registry_test",
+            effect: "other_effect",
+        }))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "effector registry_test is already registered"
+        );
+        let error = register_effector(Arc::new(TestEffector {
+            name: "other_registry_test",
+            effect: "screen_off",
+        }))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "effect screen_off of effector other_registry_test is already provided by effector dpms"
+        );
+    }
+}
@@ -3,54 +3,259 @@
 //! [Sequencer] and [IdlenessController] up
 
 use super::{
-    effector_inventory::{self as ei, GetEffectorPort},
-    idleness_controller::{Action, IdlenessController},
+    effector_inventory::{self as ei, InventoryMessage, InventoryPort},
+    event_history::EventHistory,
+    idleness_controller::{
+        rollback_all, Action, BunchReplacement, IdlenessController, IdlenessPort,
+    },
+    inhibitor_policy::{parse_inhibitor_policy, InhibitorPolicy},
+    manager_state::{EffectEventSender, StateReporter},
+    schedule_rules::{parse_rules, select_schedule, Environment, Rule},
 };
 use crate::{
-    armaf::{spawn_server, ActorPort, Effect, EffectorPort, Handle, HandleChild},
+    armaf::{
+        inhibit_type_from_config_name, spawn_server, ActorPort, ActorReceiver, Effect,
+        EffectorMessage, Responder, RollbackStrategy,
+    },
+    config::SharedConfig,
     control::{
         idleness_controller::ReconciliationBunches,
-        sequencer::{GetRunningTime, Sequencer},
+        sequencer::{SequenceReplacement, Sequencer, SequencerCommand, SequencerPort},
+    },
+    external::{
+        display_server::{DisplayServerController, SystemState},
+        state_journal::StateJournal,
+    },
+    logging,
+    system::{
+        clock_sensor::ClockJump, environment_sensor::EnvironmentFacts,
+        inhibition_sensor::InhibitionSensorPort, sleep_sensor::SleepUpdate,
+        upower_sensor::PowerStatus,
     },
-    external::display_server::{DisplayServerController, SystemState},
-    system::{inhibition_sensor::GetInhibitions, upower_sensor::PowerStatus},
 };
 use anyhow::{anyhow, Context, Result};
-use logind_zbus::manager::Inhibitor;
+use logind_zbus::manager::InhibitType;
 use std::{
     collections::{HashMap, HashSet},
+    sync::Arc,
     time::Duration,
 };
 use thiserror::Error;
-use tokio::sync::watch;
+use tokio::{
+    sync::{broadcast, watch},
+    time::{sleep_until, Instant},
+};
 
 #[derive(Clone, Debug, Error)]
 #[error("{0} is not a valid configuration name for a schedule")]
-struct TryFromScheduleTypeError(String);
+pub struct TryFromScheduleTypeError(String);
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
-enum ScheduleType {
+/// The environmental conditions for which a schedule can be defined.
+///
+/// Besides the built-in schedules, chosen by the power source, schedules with
+/// any other names can be defined and chosen by
+/// [rules](super::schedule_rules). A schedule type is identified by its name
+/// in the configuration file.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub enum ScheduleType {
     ExternalPower,
     Battery,
     LowBattery,
+    /// A schedule chosen only by rules
+    Named(Arc<str>),
+}
+
+impl ScheduleType {
+    /// All built-in schedule types, in the order in which they are picked as
+    /// the last resort fallback
+    pub const ALL: [ScheduleType; 3] = [
+        ScheduleType::ExternalPower,
+        ScheduleType::Battery,
+        ScheduleType::LowBattery,
+    ];
+
+    /// Get the schedule type with the given name
+    pub fn named(name: &str) -> ScheduleType {
+        match name {
+            "external" => ScheduleType::ExternalPower,
+            "battery" => ScheduleType::Battery,
+            "low_battery" => ScheduleType::LowBattery,
+            _ => ScheduleType::Named(Arc::from(name)),
+        }
+    }
+
+    /// Get the name of the schedule type used in the configuration file
+    pub fn config_name(&self) -> &str {
+        match self {
+            ScheduleType::ExternalPower => "external",
+            ScheduleType::Battery => "battery",
+            ScheduleType::LowBattery => "low_battery",
+            ScheduleType::Named(name) => name,
+        }
+    }
+
+    /// Whether the schedule is chosen by the power source rather than only by
+    /// rules
+    pub fn is_builtin(&self) -> bool {
+        !matches!(self, ScheduleType::Named(_))
+    }
+}
+
+impl std::fmt::Debug for ScheduleType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.config_name())
+    }
 }
 
 impl TryFrom<&str> for ScheduleType {
     type Error = TryFromScheduleTypeError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "external" => Ok(ScheduleType::ExternalPower),
-            "battery" => Ok(ScheduleType::Battery),
-            "low_battery" => Ok(ScheduleType::LowBattery),
-            unknown => Err(TryFromScheduleTypeError(unknown.to_owned())),
+        if value.is_empty() || DayType::ALL.iter().any(|day| day.config_name() == value) {
+            Err(TryFromScheduleTypeError(value.to_owned()))
+        } else {
+            Ok(ScheduleType::named(value))
         }
     }
 }
 
-type Schedule = HashMap<String, Duration>;
+/// Get all the built-in schedule types, in the order of [ScheduleType::ALL],
+/// followed by the other types defined in the map, sorted by name
+pub fn schedule_types<T>(schedules: &HashMap<ScheduleType, T>) -> Vec<ScheduleType> {
+    let mut others: Vec<ScheduleType> = schedules
+        .keys()
+        .filter(|typ| !typ.is_builtin())
+        .cloned()
+        .collect();
+    others.sort_by(|a, b| a.config_name().cmp(b.config_name()));
+    ScheduleType::ALL.into_iter().chain(others).collect()
+}
+
+/// Get the types of the schedules named in the configuration, in the same
+/// order as [schedule_types], without checking the schedules
+pub fn configured_schedule_types(config: &toml::Value) -> Vec<ScheduleType> {
+    let named: HashMap<ScheduleType, ()> = config
+        .get("schedule")
+        .and_then(|schedules| schedules.as_table())
+        .map(|schedules| {
+            schedules
+                .keys()
+                .filter_map(|name| ScheduleType::try_from(name.as_str()).ok())
+                .map(|typ| (typ, ()))
+                .collect()
+        })
+        .unwrap_or_default();
+    schedule_types(&named)
+}
+
+/// The kinds of days which can have their own variants of the schedules,
+/// e.g. `[schedule.battery.weekend]`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum DayType {
+    /// Monday to Friday
+    Weekday,
+    /// Saturday and Sunday
+    Weekend,
+}
+
+impl DayType {
+    pub const ALL: [DayType; 2] = [DayType::Weekday, DayType::Weekend];
+
+    /// Get the name of the schedule variant used in the configuration file
+    pub fn config_name(&self) -> &'static str {
+        match self {
+            DayType::Weekday => "weekdays",
+            DayType::Weekend => "weekend",
+        }
+    }
+
+    /// Get the type of the day of the week with the given number, counted
+    /// from Sunday as in `struct tm`
+    pub fn from_weekday(weekday: i32) -> DayType {
+        match weekday.rem_euclid(7) {
+            0 | 6 => DayType::Weekend,
+            _ => DayType::Weekday,
+        }
+    }
+
+    /// Get the type of the current local day and the time remaining until
+    /// its end
+    pub fn today() -> (DayType, Duration) {
+        // SAFETY: localtime_r only writes into the given struct and time
+        // accepts a null pointer
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        let since_midnight = (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u64;
+        // Waking up a moment after midnight, so that the new day has begun
+        let until_end = Duration::from_secs(24 * 3600 - since_midnight.min(24 * 3600 - 1) + 1);
+        (DayType::from_weekday(tm.tm_wday), until_end)
+    }
+}
 
-fn parse_schedules(config: &toml::Value) -> Result<HashMap<ScheduleType, Schedule>> {
+/// Check whether any schedule has a variant for a specific type of day
+pub fn has_day_variants(config: &toml::Value) -> bool {
+    config
+        .get("schedule")
+        .and_then(|schedules| schedules.as_table())
+        .map_or(false, |schedules| {
+            schedules.values().any(|schedule| {
+                DayType::ALL
+                    .iter()
+                    .any(|day| schedule.get(day.config_name()).is_some())
+            })
+        })
+}
+
+/// Get the configuration with the schedules in effect on the given type of
+/// day. The entries of each schedule's variant for the day replace the
+/// entries of the schedule with the same names, variants for other days are
+/// removed.
+pub fn schedules_for_day(config: &toml::Value, day: DayType) -> toml::Value {
+    let mut config = config.clone();
+    if let Some(schedules) = config.get_mut("schedule").and_then(|s| s.as_table_mut()) {
+        for schedule in schedules.values_mut() {
+            if let Some(table) = schedule.as_table_mut() {
+                let variant = table.get(day.config_name()).cloned();
+                for day in DayType::ALL {
+                    table.remove(day.config_name());
+                }
+                if let Some(toml::Value::Table(variant)) = variant {
+                    table.extend(variant);
+                }
+            }
+        }
+    }
+    config
+}
+
+/// Delays after which the named effects should be executed
+pub type Schedule = HashMap<String, Duration>;
+
+/// Effects grouped into bunches by their delay, sorted from the earliest one
+pub type EffectBunches = Vec<(Duration, Vec<Effect>)>;
+
+/// The longest delay of the first bunch in a schedule, the first timeout is
+/// handled by the display server, which only supports 16-bit signed timeouts.
+const MAX_FIRST_DELAY: Duration = Duration::from_secs(i16::MAX as u64);
+
+/// Parse all schedules defined in the configuration, without their variants
+/// for specific types of days, which are only checked to be valid
+pub fn parse_schedules(config: &toml::Value) -> Result<HashMap<ScheduleType, Schedule>> {
+    let schedules = parse_schedule_tables(config)?;
+    if has_day_variants(config) {
+        for day in DayType::ALL {
+            parse_schedule_tables(&schedules_for_day(config, day))
+                .with_context(|| format!("Invalid schedules for {}", day.config_name()))?;
+        }
+    }
+    Ok(schedules)
+}
+
+fn parse_schedule_tables(config: &toml::Value) -> Result<HashMap<ScheduleType, Schedule>> {
     let mut schedules = HashMap::new();
 
     let empty_placeholder = toml::Value::Table(toml::value::Map::new());
@@ -65,7 +270,8 @@ fn parse_schedules(config: &toml::Value) -> Result<HashMap<ScheduleType, Schedul
         match schedule_type {
             Err(e) => log::error!("Problem when parsing a schedule: {}", e),
             Ok(typ) => {
-                let schedule = parse_schedule(&schedule_tables[key])?;
+                let schedule = parse_schedule(&schedule_tables[key])
+                    .with_context(|| format!("Invalid schedule.{}", key))?;
                 schedules.insert(typ, schedule);
             }
         }
@@ -74,147 +280,1110 @@ fn parse_schedules(config: &toml::Value) -> Result<HashMap<ScheduleType, Schedul
     Ok(schedules)
 }
 
-fn parse_duration(string: &str) -> Result<Duration> {
-    let mut seconds = 0;
+/// Parse a human-readable duration.
+///
+/// A duration is a sequence of components, each of them consisting of a
+/// number followed by a unit (`ms`, `s`, `m`, `h` or `d`). The components may
+/// be separated by whitespace (`1h 30m`) or written together (`1h30m`), their
+/// values are summed. A bare integer is interpreted as a number of seconds.
+pub fn parse_duration(string: &str) -> Result<Duration> {
+    let string = string.trim();
+    if string.is_empty() {
+        return Err(anyhow!("syntax error in duration: duration is empty"));
+    }
+    if let Ok(seconds) = string.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let mut total = Duration::ZERO;
     for substr in string.split_ascii_whitespace() {
-        seconds += match substr.chars().nth(substr.len() - 1) {
-            Some('s') => parse_duration_numeric(substr)?,
-            Some('m') => parse_duration_numeric(substr)? * 60,
-            Some('h') => parse_duration_numeric(substr)? * 3600,
-            Some(_) => {
-                return Err(anyhow!(
-                    "syntax error in duration: Duration compoment {} doesn't have a unit",
-                    substr
-                ))
-            }
-            None => {
-                return Err(anyhow!(
-                    "syntax error in duration: Duration compoment {} too short",
-                    substr
-                ))
-            }
+        let mut rest = substr;
+        while !rest.is_empty() {
+            let (component, remainder) = parse_duration_component(rest)
+                .with_context(|| format!("syntax error in duration component {}", substr))?;
+            total = total
+                .checked_add(component)
+                .ok_or_else(|| anyhow!("duration {} is too long", string))?;
+            rest = remainder;
         }
     }
-
-    Ok(Duration::from_secs(seconds))
+    Ok(total)
 }
 
-fn parse_duration_numeric(component: &str) -> Result<u64> {
-    component[0..component.len() - 1]
+/// Parse a single `<number><unit>` component from the start of the string,
+/// returning its value and the unparsed rest of the string
+fn parse_duration_component(string: &str) -> Result<(Duration, &str)> {
+    let digits_end = string
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("number {} doesn't have a unit", string))?;
+    if digits_end == 0 {
+        return Err(anyhow!("expected a number at {}", string));
+    }
+    let value: u64 = string[..digits_end]
         .parse()
-        .context("syntax error in duration: numeric component couldn't be parsed")
+        .context("numeric component couldn't be parsed")?;
+    let unit_end = string[digits_end..]
+        .find(|c: char| c.is_ascii_digit())
+        .map_or(string.len(), |i| digits_end + i);
+    let multiplier_ms: u64 = match &string[digits_end..unit_end] {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 3600 * 1000,
+        "d" => 24 * 3600 * 1000,
+        unit => return Err(anyhow!("unknown unit {}", unit)),
+    };
+    let millis = value
+        .checked_mul(multiplier_ms)
+        .ok_or_else(|| anyhow!("{} is too long", &string[..unit_end]))?;
+    Ok((Duration::from_millis(millis), &string[unit_end..]))
+}
+
+/// Format a [Duration] the way it would be written in a schedule, e.g. `3m 30s`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let components = [
+        (seconds / 86400, "d"),
+        ((seconds % 86400) / 3600, "h"),
+        ((seconds % 3600) / 60, "m"),
+        (seconds % 60, "s"),
+        (duration.subsec_millis() as u64, "ms"),
+    ];
+    let formatted: Vec<String> = components
+        .iter()
+        .filter(|(value, _)| *value != 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if formatted.is_empty() {
+        "0s".to_owned()
+    } else {
+        formatted.join(" ")
+    }
 }
 
-fn parse_schedule(schedule_config: &toml::Value) -> Result<Schedule> {
+/// Schedule key under which the schedule's grace period is set
+const GRACE_PERIOD_KEY: &str = "grace";
+
+/// Key of an effect's table in a schedule overriding the effect's
+/// [RollbackStrategy], e.g. `rollback = "immediate"`
+pub const ROLLBACK_KEY: &str = "rollback";
+
+/// Key of an effect's table in a schedule overriding the types of inhibitors
+/// which the effect respects, e.g. `inhibited_by = ["sleep"]`
+pub const INHIBITED_BY_KEY: &str = "inhibited_by";
+
+/// Properties of an effect which a schedule sets differently from the
+/// effect's own
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectOverride {
+    pub rollback_strategy: Option<RollbackStrategy>,
+    pub inhibited_by: Option<Vec<InhibitType>>,
+}
+
+/// Overrides of the effects' properties in a schedule, by effect name
+pub type EffectOverrides = HashMap<String, EffectOverride>;
+
+/// Effects which can be scheduled in stages using a single schedule entry,
+/// e.g. `display = ["2m:dim", "5m:off"]`.
+///
+/// Each staged effect has a name and a list of its stages with the effects
+/// they expand to. Stages have to be scheduled in the order in which they're
+/// listed here, since each stage builds upon the previous ones.
+pub const STAGED_EFFECTS: &[(&str, &[(&str, &str)])] =
+    &[("display", &[("dim", "screen_dim"), ("off", "screen_off")])];
+
+/// Parse a single schedule table, checking that the timeouts are valid
+pub fn parse_schedule(schedule_config: &toml::Value) -> Result<Schedule> {
     let table = schedule_config
         .as_table()
         .ok_or(anyhow!("Schedule should be a table, not a scalar or array"))?;
     let mut m = HashMap::new();
+    parse_grace_period(schedule_config)?;
+    parse_effect_overrides(schedule_config)?;
     for (key, value) in table {
-        if let Some(value_str) = value.as_str() {
-            m.insert(key.to_string(), parse_duration(value_str)?);
+        if key == GRACE_PERIOD_KEY {
+            continue;
+        }
+        if DayType::ALL.iter().any(|day| day.config_name() == key) {
+            if !value.is_table() {
+                return Err(anyhow!("{} variant of the schedule must be a table", key));
+            }
+            continue;
+        }
+        let entries = match value {
+            toml::Value::Array(stages) => parse_staged_effect(key, stages)?,
+            // Effects with per-schedule effector configuration are written as
+            // tables, with their timeout under the "after" key
+            toml::Value::Table(effect_table) => {
+                let timeout = effect_table.get("after").ok_or_else(|| {
+                    anyhow!(
+                        "{} has effector configuration, but its timeout isn't set in after",
+                        key
+                    )
+                })?;
+                vec![(key.to_string(), parse_timeout(key, timeout)?)]
+            }
+            _ => vec![(key.to_string(), parse_timeout(key, value)?)],
+        };
+        for (effect_name, delay) in entries {
+            if m.insert(effect_name.clone(), delay).is_some() {
+                return Err(anyhow!("{} is scheduled more than once", effect_name));
+            }
+        }
+    }
+    match m.values().min() {
+        None => Err(anyhow!("Schedule doesn't contain any effects")),
+        Some(first_delay) if *first_delay > MAX_FIRST_DELAY => Err(anyhow!(
+            "The first effect in a schedule must come after at most {}",
+            format_duration(MAX_FIRST_DELAY)
+        )),
+        // The display server measures the idleness before the first effect
+        Some(first_delay) if first_delay.subsec_nanos() != 0 => Err(anyhow!(
+            "The first effect in a schedule must come after a whole number of seconds, not {}",
+            format_duration(*first_delay)
+        )),
+        Some(_) => Ok(m),
+    }
+}
+
+/// Get the grace period of a schedule, zero if it's not set.
+///
+/// After the display server reports idleness, the system has to stay idle for
+/// the grace period before the first effects get executed.
+pub fn parse_grace_period(schedule_config: &toml::Value) -> Result<Duration> {
+    match schedule_config.get(GRACE_PERIOD_KEY) {
+        None => Ok(Duration::ZERO),
+        Some(toml::Value::String(grace)) => parse_duration(grace).context("invalid grace period"),
+        Some(_) => Err(anyhow!("grace period is not a string in duration format")),
+    }
+}
+
+/// Get the properties of the effects overridden by the `rollback` and
+/// `inhibited_by` keys of the tables of a schedule's effects
+pub fn parse_effect_overrides(schedule_config: &toml::Value) -> Result<EffectOverrides> {
+    let mut overrides = HashMap::new();
+    let table = match schedule_config.as_table() {
+        Some(table) => table,
+        None => return Ok(overrides),
+    };
+    for (effect_name, value) in table {
+        if DayType::ALL
+            .iter()
+            .any(|day| day.config_name() == effect_name)
+        {
+            continue;
+        }
+        let effect_table = match value.as_table() {
+            Some(effect_table) => effect_table,
+            None => continue,
+        };
+        let rollback_strategy = effect_table
+            .get(ROLLBACK_KEY)
+            .map(|rollback| {
+                rollback
+                    .as_str()
+                    .and_then(RollbackStrategy::from_config_name)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "rollback of {} must be on_activity, immediate or none, not {}",
+                            effect_name,
+                            rollback
+                        )
+                    })
+            })
+            .transpose()?;
+        let inhibited_by = effect_table
+            .get(INHIBITED_BY_KEY)
+            .map(|types| {
+                types
+                    .as_array()
+                    .and_then(|types| {
+                        types
+                            .iter()
+                            .map(|typ| typ.as_str().and_then(inhibit_type_from_config_name))
+                            .collect::<Option<Vec<_>>>()
+                    })
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "inhibited_by of {} must be an array of idle, sleep or shutdown, not {}",
+                            effect_name,
+                            types
+                        )
+                    })
+            })
+            .transpose()?;
+        if rollback_strategy.is_some() || inhibited_by.is_some() {
+            overrides.insert(
+                effect_name.clone(),
+                EffectOverride {
+                    rollback_strategy,
+                    inhibited_by,
+                },
+            );
+        }
+    }
+    Ok(overrides)
+}
+
+fn parse_timeout(key: &str, value: &toml::Value) -> Result<Duration> {
+    let value_str = value
+        .as_str()
+        .ok_or_else(|| anyhow!("timeout for {} is not a string in duration format", key))?;
+    let delay =
+        parse_duration(value_str).with_context(|| format!("invalid timeout for {}", key))?;
+    if delay.is_zero() {
+        return Err(anyhow!("timeout for {} must be longer than 0s", key));
+    }
+    Ok(delay)
+}
+
+/// Expand the stages of a staged effect, written as `"<timeout>:<stage>"`
+/// strings, into the effects they represent
+fn parse_staged_effect(key: &str, stages: &[toml::Value]) -> Result<Vec<(String, Duration)>> {
+    let known_stages = STAGED_EFFECTS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, stages)| *stages)
+        .ok_or_else(|| anyhow!("{} is not an effect which can be scheduled in stages", key))?;
+    let mut expanded: Vec<(String, Duration)> = Vec::new();
+    let mut last_stage_index = None;
+    for stage in stages {
+        let stage_str = stage
+            .as_str()
+            .ok_or_else(|| anyhow!("stages of {} must be strings", key))?;
+        let (timeout, stage_name) = stage_str.split_once(':').ok_or_else(|| {
+            anyhow!(
+                "stage {} of {} is not in the <timeout>:<stage> format",
+                stage_str,
+                key
+            )
+        })?;
+        let stage_name = stage_name.trim();
+        let stage_index = known_stages
+            .iter()
+            .position(|(name, _)| *name == stage_name)
+            .ok_or_else(|| anyhow!("{} has no stage called {}", key, stage_name))?;
+        let delay = parse_timeout(
+            &format!("{}:{}", key, stage_name),
+            &toml::Value::String(timeout.to_owned()),
+        )?;
+        if let Some(last_index) = last_stage_index {
+            if stage_index <= last_index {
+                return Err(anyhow!(
+                    "stages of {} must be listed in this order: {}",
+                    key,
+                    known_stages
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            if delay <= expanded[expanded.len() - 1].1 {
+                return Err(anyhow!(
+                    "stage {} of {} must come after the previous stage",
+                    stage_name,
+                    key
+                ));
+            }
+        }
+        last_stage_index = Some(stage_index);
+        expanded.push((known_stages[stage_index].1.to_owned(), delay));
+    }
+    if expanded.is_empty() {
+        return Err(anyhow!("{} doesn't have any stages", key));
+    }
+    Ok(expanded)
+}
+
+/// Get the battery percentage under which the low battery schedule is used
+pub fn parse_low_battery_threshold(config: &toml::Value) -> Result<u64, &'static str> {
+    config
+        .get("battery")
+        .ok_or("no battery table defined")
+        .and_then(|table| {
+            table
+                .get("low_battery_percentage")
+                .ok_or("low_battery_percentage key is not defined")
+        })
+        .and_then(|value| {
+            value
+                .as_integer()
+                .ok_or("battery.low_battery_percentage is not an integer")
+        })
+        .map(|threshold| threshold as u64)
+}
+
+/// Keeps the low battery schedule from being switched back and forth while
+/// the battery percentage hovers around the threshold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LowBatteryHysteresis {
+    /// Battery percentage above which the low battery schedule is left, the
+    /// low battery threshold if it's not set
+    pub exit_percentage: Option<u64>,
+    /// How long the battery or low battery schedule is used at least before
+    /// switching to the other one
+    pub min_dwell: Duration,
+}
+
+/// Get the hysteresis of the low battery schedule from the
+/// `low_battery_exit_percentage` and `min_dwell` keys of the `[battery]`
+/// section
+pub fn parse_low_battery_hysteresis(config: &toml::Value) -> Result<LowBatteryHysteresis> {
+    let section = match config.get("battery") {
+        Some(section) => section,
+        None => return Ok(LowBatteryHysteresis::default()),
+    };
+    let exit_percentage = match section.get("low_battery_exit_percentage") {
+        None => None,
+        Some(toml::Value::Integer(percentage)) if (0..=100).contains(percentage) => {
+            Some(*percentage as u64)
+        }
+        Some(_) => {
+            return Err(anyhow!(
+                "battery.low_battery_exit_percentage is not a percentage"
+            ))
+        }
+    };
+    if let (Some(exit), Ok(threshold)) = (exit_percentage, parse_low_battery_threshold(config)) {
+        if exit < threshold {
+            return Err(anyhow!(
+                "battery.low_battery_exit_percentage is lower than battery.low_battery_percentage"
+            ));
+        }
+    }
+    let min_dwell = match section.get("min_dwell") {
+        None => Duration::ZERO,
+        Some(toml::Value::String(dwell)) => {
+            parse_duration(dwell).context("Invalid battery.min_dwell")?
+        }
+        Some(_) => return Err(anyhow!("battery.min_dwell is not a duration string")),
+    };
+    Ok(LowBatteryHysteresis {
+        exit_percentage,
+        min_dwell,
+    })
+}
+
+/// Resolve the effects named in a schedule and group them into bunches sorted
+/// by their delay.
+///
+/// The `idle_hint` effect is automatically added to the first bunch, unless
+/// the schedule gives it a delay of its own.
+pub fn schedule_to_bunches(
+    schedule: &Schedule,
+    effect_names_mapping: &HashMap<String, (String, usize)>,
+) -> Result<EffectBunches> {
+    let mut m: HashMap<Duration, Vec<Effect>> = HashMap::new();
+    for (effect_name, delay) in schedule.iter() {
+        let effect = if effect_names_mapping.contains_key(effect_name) {
+            let mapping_result = &effect_names_mapping[effect_name];
+            ei::get_effects_for_effector(&mapping_result.0)[mapping_result.1].clone()
         } else {
+            return Err(anyhow!("Unknown effect name {}", effect_name));
+        };
+        m.entry(*delay).or_insert(vec![]).push(effect);
+    }
+
+    let mut bunches: EffectBunches = m.into_iter().collect();
+    bunches.sort_by_key(|bunch| bunch.0);
+    if !schedule.contains_key("idle_hint") {
+        bunches[0]
+            .1
+            .push(ei::get_effects_for_effector("session")[0].clone());
+    }
+    Ok(bunches)
+}
+
+/// Resolve the effect bunches of each schedule on each day type, with the
+/// schedule's effect overrides applied. Fails if any of the schedules is
+/// invalid, without spawning any effectors.
+fn resolve_day_bunches(
+    config: &toml::Value,
+    effect_names_mapping: &HashMap<String, (String, usize)>,
+) -> Result<HashMap<DayType, HashMap<ScheduleType, EffectBunches>>> {
+    parse_schedules(config)?;
+    let mut day_bunches = HashMap::new();
+    for day in DayType::ALL {
+        let day_config = schedules_for_day(config, day);
+        let schedules = parse_schedules(&day_config)?;
+        if !has_builtin_schedule(&schedules) {
             return Err(anyhow!(
-                "timeout for {} is not a string in duration format",
-                key
+                "No schedule defined. Define either schedule.external or schedule.battery."
             ));
         }
+        let mut bunches = HashMap::new();
+        for (source, schedule) in schedules {
+            let effect_overrides = match day_config
+                .get("schedule")
+                .and_then(|schedules| schedules.get(source.config_name()))
+            {
+                Some(schedule_config) => parse_effect_overrides(schedule_config)?,
+                None => EffectOverrides::new(),
+            };
+            let mut schedule_bunches = schedule_to_bunches(&schedule, effect_names_mapping)?;
+            for (_, effects) in schedule_bunches.iter_mut() {
+                apply_effect_overrides(effects, &effect_overrides)?;
+            }
+            bunches.insert(source, schedule_bunches);
+        }
+        day_bunches.insert(day, bunches);
+    }
+    Ok(day_bunches)
+}
+
+/// Name of the configuration key containing the fallback chain
+pub const FALLBACKS_KEY: &str = "fallbacks";
+
+/// Substitutes of the schedules which aren't defined, configured as
+/// `"<schedule>:<substitute>"` entries of the top-level `fallbacks` array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackChain(Vec<(ScheduleType, ScheduleType)>);
+
+impl Default for FallbackChain {
+    /// Low battery schedule is substituted by the battery schedule, battery
+    /// schedule by the external power one
+    fn default() -> Self {
+        FallbackChain(vec![
+            (ScheduleType::LowBattery, ScheduleType::Battery),
+            (ScheduleType::Battery, ScheduleType::ExternalPower),
+        ])
+    }
+}
+
+impl FallbackChain {
+    /// Get the schedule substituting the given one
+    pub fn substitute(&self, typ: &ScheduleType) -> Option<ScheduleType> {
+        self.0
+            .iter()
+            .find(|(original, _)| original == typ)
+            .map(|(_, substitute)| substitute.clone())
     }
-    Ok(m)
+}
+
+impl std::fmt::Display for FallbackChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<String> = self
+            .0
+            .iter()
+            .map(|(original, substitute)| {
+                format!("{}:{}", original.config_name(), substitute.config_name())
+            })
+            .collect();
+        f.write_str(&entries.join(", "))
+    }
+}
+
+/// Parse the fallback chain, which is [FallbackChain::default] if it's not
+/// configured. Each schedule can have a single substitute and following the
+/// substitutes must never lead back to a schedule.
+pub fn parse_fallbacks(config: &toml::Value) -> Result<FallbackChain> {
+    let entries = match config.get(FALLBACKS_KEY) {
+        Some(entries) => entries
+            .as_array()
+            .ok_or_else(|| anyhow!("must be an array of strings"))
+            .context("Invalid fallbacks")?,
+        None => return Ok(FallbackChain::default()),
+    };
+    let mut chain = FallbackChain(Vec::new());
+    for entry in entries {
+        let (original, substitute) = entry
+            .as_str()
+            .and_then(|entry| entry.split_once(':'))
+            .ok_or_else(|| anyhow!("{} is not in the form \"<schedule>:<substitute>\"", entry))
+            .context("Invalid fallbacks")?;
+        let original = ScheduleType::try_from(original.trim()).context("Invalid fallbacks")?;
+        let substitute = ScheduleType::try_from(substitute.trim()).context("Invalid fallbacks")?;
+        if chain.substitute(&original).is_some() {
+            return Err(anyhow!(
+                "Invalid fallbacks: schedule {} has more than one fallback",
+                original.config_name()
+            ));
+        }
+        chain.0.push((original, substitute));
+    }
+    for (start, _) in chain.0.iter() {
+        let mut path = vec![start.clone()];
+        let mut current = start.clone();
+        while let Some(substitute) = chain.substitute(&current) {
+            path.push(substitute.clone());
+            if substitute == *start {
+                let names: Vec<&str> = path.iter().map(|typ| typ.config_name()).collect();
+                return Err(anyhow!(
+                    "Invalid fallbacks: {} form a cycle",
+                    names.join(" -> ")
+                ));
+            }
+            current = substitute;
+        }
+    }
+    Ok(chain)
+}
+
+/// The schedule used in place of a schedule type and how it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleResolution {
+    pub typ: ScheduleType,
+    pub effective: ScheduleType,
+    /// Fallbacks which were tried but aren't defined either
+    pub undefined_fallbacks: Vec<ScheduleType>,
+    /// Whether the fallback chain didn't lead to a defined schedule, so the
+    /// first defined built-in schedule is used
+    pub last_resort: bool,
+}
+
+impl std::fmt::Display for ScheduleResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.effective == self.typ {
+            return write!(f, "schedule {} is defined", self.typ.config_name());
+        }
+        write!(f, "schedule {} is not defined", self.typ.config_name())?;
+        if !self.undefined_fallbacks.is_empty() {
+            let names: Vec<&str> = self
+                .undefined_fallbacks
+                .iter()
+                .map(|typ| typ.config_name())
+                .collect();
+            write!(f, ", neither is its fallback {}", names.join(" -> "))?;
+        }
+        if self.last_resort {
+            write!(
+                f,
+                ", using the first defined built-in schedule {}",
+                self.effective.config_name()
+            )
+        } else {
+            write!(f, ", using its fallback {}", self.effective.config_name())
+        }
+    }
+}
+
+/// Find the schedule which is used when the computer is in the environment
+/// represented by `typ`, in case its own schedule is not defined.
+///
+/// The substitutes from the fallback chain are followed until a defined
+/// schedule is found. If there's none, the first defined schedule from
+/// [ScheduleType::ALL] is used.
+pub fn resolve_schedule_type<T>(
+    typ: ScheduleType,
+    schedules: &HashMap<ScheduleType, T>,
+    fallbacks: &FallbackChain,
+) -> ScheduleResolution {
+    let mut undefined_fallbacks = Vec::new();
+    let mut current = typ.clone();
+    loop {
+        if schedules.contains_key(&current) {
+            return ScheduleResolution {
+                typ,
+                effective: current,
+                undefined_fallbacks,
+                last_resort: false,
+            };
+        }
+        match fallbacks.substitute(&current) {
+            // The chain is checked for cycles, this only guards a chain
+            // constructed by hand
+            Some(substitute) if substitute != typ && !undefined_fallbacks.contains(&substitute) => {
+                let previous = std::mem::replace(&mut current, substitute);
+                if previous != typ {
+                    undefined_fallbacks.push(previous);
+                }
+            }
+            _ => break,
+        }
+    }
+    if current != typ {
+        undefined_fallbacks.push(current);
+    }
+    let effective = ScheduleType::ALL
+        .into_iter()
+        .find(|t| schedules.contains_key(t))
+        .expect("No schedule is defined");
+    ScheduleResolution {
+        typ,
+        effective,
+        undefined_fallbacks,
+        last_resort: true,
+    }
+}
+
+/// Pick the schedule type whose schedule is used in place of `typ`, see
+/// [resolve_schedule_type]
+pub fn effective_schedule_type<T>(
+    typ: ScheduleType,
+    schedules: &HashMap<ScheduleType, T>,
+    fallbacks: &FallbackChain,
+) -> ScheduleType {
+    resolve_schedule_type(typ, schedules, fallbacks).effective
+}
+
+/// Check whether any of the built-in schedules, which [effective_schedule_type]
+/// falls back to, is defined
+pub fn has_builtin_schedule<T>(schedules: &HashMap<ScheduleType, T>) -> bool {
+    schedules.keys().any(|typ| typ.is_builtin())
 }
 
 type Sequence = Vec<(Duration, Vec<Action>)>;
 
+/// Wait for a change of the power status, never returning if there's no
+/// power sensor
+async fn power_status_changed(
+    receiver: Option<&mut watch::Receiver<PowerStatus>>,
+) -> Result<(), watch::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for a change of the environment facts, never returning if they aren't
+/// gathered
+async fn facts_changed(
+    receiver: Option<&mut watch::Receiver<EnvironmentFacts>>,
+) -> Result<(), watch::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Replace the properties of the effects with the ones overridden in the
+/// schedule. Effects which can't be rolled back by their effectors can't be
+/// made to roll back.
+pub fn apply_effect_overrides(effects: &mut [Effect], overrides: &EffectOverrides) -> Result<()> {
+    for effect in effects.iter_mut() {
+        let effect_override = match overrides.get(&effect.name) {
+            Some(effect_override) => effect_override,
+            None => continue,
+        };
+        if let Some(strategy) = effect_override.rollback_strategy {
+            if effect.rollback_strategy == RollbackStrategy::None
+                && strategy != RollbackStrategy::None
+            {
+                return Err(anyhow!("{} can't be rolled back", effect.name));
+            }
+            effect.rollback_strategy = strategy;
+        }
+        if let Some(inhibited_by) = effect_override.inhibited_by.as_ref() {
+            effect.inhibited_by = inhibited_by.clone();
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the next jump of the clock, never returning if they aren't
+/// watched
+async fn next_clock_jump(
+    receiver: Option<&mut broadcast::Receiver<ClockJump>>,
+) -> Result<ClockJump, broadcast::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A command changing the behavior of a running [EnvironmentController]
+#[derive(Debug, Clone)]
+pub enum EnvironmentCommand {
+    /// Replace the configuration used by [EnvironmentController] with the one
+    /// contained in the message.
+    ///
+    /// The schedules get re-parsed, the sequences are rebuilt and effectors
+    /// whose configuration has changed are respawned. The running effects are
+    /// then reconciled with the new sequence the same way they are when the
+    /// power source changes. If the new configuration is invalid, an error is
+    /// returned and the old configuration stays in use.
+    ReloadConfig(SharedConfig),
+    /// Stop processing the schedule and roll back the effects which would be
+    /// rolled back on user activity, either for the given time or until
+    /// [EnvironmentCommand::Resume] is received.
+    ///
+    /// Pausing an already paused controller replaces the pause's duration.
+    Pause(Option<Duration>),
+    /// Start processing the schedule again after a pause, from its beginning.
+    /// Does nothing if the controller is not paused.
+    Resume,
+    /// Go back to the beginning of the schedule, rolling back the applied
+    /// effects as if the user became active. Used after the system resumes
+    /// from sleep, when the display server may not report the activity.
+    /// Does nothing while paused.
+    ResetSchedule,
+    /// Don't apply the next effects of the schedule for the given time, then
+    /// continue with it as usual, see [SequencerCommand::Hold]. The hold
+    /// carries over to the sequencers started for other schedules and a zero
+    /// duration ends it. Does nothing while paused.
+    HoldSchedule(Duration),
+    /// Roll the applied effects back for the given time, then apply them
+    /// again unless the user has become active, see [SequencerCommand::Wake].
+    /// Does nothing while paused, when the effects are rolled back anyway.
+    Wake(Duration),
+    /// Choose the profile which [rules](super::schedule_rules) can match, or
+    /// clear it. The schedule is switched the same way it is when the power
+    /// source changes.
+    SetProfile(Option<String>),
+    /// Do nothing, just respond, to show that the controller isn't stuck
+    Ping,
+}
+
+/// Port through which [EnvironmentCommand]s are sent to an
+/// [EnvironmentController]
+pub type EnvironmentPort = ActorPort<EnvironmentCommand, (), anyhow::Error>;
+
 /// Parses the schedule configuration, receives notifications about power source
 /// changes and initializes [Sequencer] and [IdlenessController] for the given
 /// schedule
 pub struct EnvironmentController<D: DisplayServerController> {
-    config: toml::Value,
-    sequences: HashMap<ScheduleType, Sequence>,
-    effector_inventory: ActorPort<GetEffectorPort, EffectorPort, anyhow::Error>,
-    inhibition_sensor: ActorPort<GetInhibitions, Vec<Inhibitor>, anyhow::Error>,
+    config: SharedConfig,
+    sequences: HashMap<DayType, HashMap<ScheduleType, Sequence>>,
+    day_type: DayType,
+    effector_inventory: InventoryPort,
+    inhibition_sensor: InhibitionSensorPort,
+    inhibitor_policy: InhibitorPolicy,
     ds_controller: D,
     idleness_channel: watch::Receiver<SystemState>,
-    handle_child: Option<HandleChild>,
-    power_status_receiver: watch::Receiver<PowerStatus>,
-    low_power_treshold: Option<u64>,
+    command_receiver: Option<ActorReceiver<EnvironmentCommand, (), anyhow::Error>>,
+    /// [None] once the power sensor has terminated, external power is assumed
+    /// then
+    power_status_receiver: Option<watch::Receiver<PowerStatus>>,
+    low_power_threshold: Option<u64>,
+    low_battery_hysteresis: LowBatteryHysteresis,
+    /// Whether the battery has last been considered low and since when
+    battery_low: Option<(bool, Instant)>,
+    rules: Vec<Rule>,
+    fallbacks: FallbackChain,
+    facts_receiver: Option<watch::Receiver<EnvironmentFacts>>,
+    profile: Option<String>,
+    state_reporter: Option<StateReporter>,
+    effect_events: Option<EffectEventSender>,
+    event_history: Option<EventHistory>,
+    idleness_ports: Option<watch::Sender<Option<IdlenessPort>>>,
+    state_journal: StateJournal,
+    sleep_updates: Option<broadcast::Sender<SleepUpdate>>,
+    clock_jumps: Option<broadcast::Receiver<ClockJump>>,
 }
 
 impl<D: DisplayServerController> EnvironmentController<D> {
     /// Creates a new EnvironmentController
     pub fn new(
-        config: &toml::Value,
-        effector_inventory: ActorPort<GetEffectorPort, EffectorPort, anyhow::Error>,
-        inhibition_sensor: ActorPort<GetInhibitions, Vec<Inhibitor>, anyhow::Error>,
+        config: SharedConfig,
+        effector_inventory: InventoryPort,
+        inhibition_sensor: InhibitionSensorPort,
         ds_controller: D,
         idleness_channel: watch::Receiver<SystemState>,
         power_status_receiver: watch::Receiver<PowerStatus>,
     ) -> EnvironmentController<D> {
         EnvironmentController {
-            config: config.clone(),
+            config,
             sequences: HashMap::new(),
+            day_type: DayType::today().0,
             effector_inventory,
             inhibition_sensor,
+            inhibitor_policy: InhibitorPolicy::default(),
             ds_controller,
             idleness_channel,
-            handle_child: None,
-            power_status_receiver,
-            low_power_treshold: None,
+            command_receiver: None,
+            power_status_receiver: Some(power_status_receiver),
+            low_power_threshold: None,
+            low_battery_hysteresis: LowBatteryHysteresis::default(),
+            battery_low: None,
+            rules: Vec::new(),
+            fallbacks: FallbackChain::default(),
+            facts_receiver: None,
+            profile: None,
+            state_reporter: None,
+            effect_events: None,
+            event_history: None,
+            idleness_ports: None,
+            state_journal: StateJournal::in_memory(),
+            sleep_updates: None,
+            clock_jumps: None,
         }
     }
 
-    /// Consumes the EnvironmentController struct and spawns its actual actor
-    pub async fn spawn(mut self) -> Result<Handle> {
-        let session_effector_port = self.get_effector("session").await?;
-        let schedules = parse_schedules(&self.config)?;
-        if schedules.is_empty() {
-            return Err(anyhow!(
-                "No schedule defined. Define either schedule.external or schedule.battery."
-            ));
-        }
-        let effect_names_mapping = ei::resolve_effectors_for_effects();
-        let mut sequences = HashMap::new();
-        for (source, schedule) in schedules {
-            sequences.insert(
-                source,
-                self.sequence_for_schedule(
-                    &schedule,
-                    &effect_names_mapping,
-                    &session_effector_port,
-                )
-                .await?,
-            );
+    /// Report the selected schedule and the progress through it to the given
+    /// reporter
+    pub fn with_state_reporter(
+        mut self,
+        state_reporter: StateReporter,
+    ) -> EnvironmentController<D> {
+        self.state_reporter = Some(state_reporter);
+        self
+    }
+
+    /// Publish each successful execution and rollback of an effect on the
+    /// given channel
+    pub fn with_effect_events(
+        mut self,
+        effect_events: EffectEventSender,
+    ) -> EnvironmentController<D> {
+        self.effect_events = Some(effect_events);
+        self
+    }
+
+    /// Let the [Sequencer]s know about the system's sleep published on the
+    /// given channel, so that the time spent suspended counts towards the
+    /// timeouts
+    pub fn with_sleep_updates(
+        mut self,
+        sleep_updates: broadcast::Sender<SleepUpdate>,
+    ) -> EnvironmentController<D> {
+        self.sleep_updates = Some(sleep_updates);
+        self
+    }
+
+    /// Find out the type of the day again when the clock or the time zone
+    /// jumps, rather than at the end of the day as the clock was before
+    pub fn with_clock_jumps(
+        mut self,
+        clock_jumps: broadcast::Receiver<ClockJump>,
+    ) -> EnvironmentController<D> {
+        self.clock_jumps = Some(clock_jumps);
+        self
+    }
+
+    /// Record the inhibitors keeping effects from being applied in the given
+    /// history
+    pub fn with_event_history(mut self, event_history: EventHistory) -> EnvironmentController<D> {
+        self.event_history = Some(event_history);
+        self
+    }
+
+    /// Publish the port of the currently running [IdlenessController] on the
+    /// given channel, so that its state can be inspected. [None] is published
+    /// before the controller is shut down, since it only terminates once all
+    /// the clones of its port are dropped.
+    pub fn with_idleness_ports(
+        mut self,
+        idleness_ports: watch::Sender<Option<IdlenessPort>>,
+    ) -> EnvironmentController<D> {
+        self.idleness_ports = Some(idleness_ports);
+        self
+    }
+
+    /// Record the applied effects and the changed idleness timeout in the
+    /// given journal
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> EnvironmentController<D> {
+        self.state_journal = state_journal;
+        self
+    }
+
+    /// Evaluate the rules against the facts received on the given channel
+    pub fn with_environment_facts(
+        mut self,
+        facts_receiver: watch::Receiver<EnvironmentFacts>,
+    ) -> EnvironmentController<D> {
+        self.facts_receiver = Some(facts_receiver);
+        self
+    }
+
+    fn publish_idleness_port(&self, port: Option<IdlenessPort>) {
+        if let Some(idleness_ports) = self.idleness_ports.as_ref() {
+            let _ = idleness_ports.send(port);
         }
-        self.sequences = sequences;
-        self.get_low_power_treshold();
-        let (handle, receiver) = Handle::new();
-        self.handle_child = Some(receiver);
+    }
+
+    /// Consumes the EnvironmentController struct and spawns its actual actor
+    pub async fn spawn(mut self) -> Result<EnvironmentPort> {
+        let config = self.config.clone();
+        self.sequences = self.build_sequences(&config).await?;
+        self.inhibitor_policy = parse_inhibitor_policy(&config)?;
+        self.rules = parse_rules(&config)?;
+        self.fallbacks = parse_fallbacks(&config)?;
+        self.low_battery_hysteresis = parse_low_battery_hysteresis(&config)?;
+        self.get_low_power_threshold();
+        let (port, receiver) = ActorPort::make();
+        self.command_receiver = Some(receiver);
         tokio::spawn(async move {
             if let Err(e) = self.main_loop().await {
                 log::error!("Error in environment controller: {}", e);
             }
         });
-        Ok(handle)
-    }
-
-    fn get_low_power_treshold(&mut self) {
-        let config_result = self
-            .config
-            .get("battery")
-            .ok_or("no battery table defined")
-            .and_then(|table| {
-                table
-                    .get("low_battery_percentage")
-                    .ok_or("low_battery_percentage key is not defined")
-            })
-            .and_then(|value| {
-                value
-                    .as_integer()
-                    .ok_or("battery.low_battery_percentage is not an integer")
-            });
-        let low_power_schedule_defined = self.sequences.contains_key(&ScheduleType::LowBattery);
-        match config_result {
-            Ok(treshold) => self.low_power_treshold = Some(treshold as u64),
+        Ok(port)
+    }
+
+    /// Build the sequences of all the schedules for each type of day
+    async fn build_sequences(
+        &mut self,
+        config: &toml::Value,
+    ) -> Result<HashMap<DayType, HashMap<ScheduleType, Sequence>>> {
+        let effect_names_mapping = ei::resolve_effectors_for_effects();
+        let mut day_sequences = HashMap::new();
+        for (day, day_bunches) in resolve_day_bunches(config, &effect_names_mapping)? {
+            let mut sequences = HashMap::new();
+            for (source, bunches) in day_bunches {
+                let sequence = self
+                    .sequence_for_schedule(&source, day, bunches, &effect_names_mapping)
+                    .await?;
+                sequences.insert(source, sequence);
+            }
+            day_sequences.insert(day, sequences);
+        }
+        Ok(day_sequences)
+    }
+
+    /// The sequences of the schedules used on the current day
+    fn current_sequences(&self) -> &HashMap<ScheduleType, Sequence> {
+        &self.sequences[&self.day_type]
+    }
+
+    async fn reload_config(&mut self, new_config: SharedConfig) -> Result<()> {
+        // Validate the schedules before touching the inventory, so that an
+        // invalid configuration doesn't cause any respawns
+        resolve_day_bunches(&new_config, &ei::resolve_effectors_for_effects())?;
+        let inhibitor_policy = parse_inhibitor_policy(&new_config)?;
+        let rules = parse_rules(&new_config)?;
+        let fallbacks = parse_fallbacks(&new_config)?;
+        let low_battery_hysteresis = parse_low_battery_hysteresis(&new_config)?;
+        self.effector_inventory
+            .request(InventoryMessage::ReloadConfig(new_config.clone()))
+            .await?;
+        // The sequences hold the ports of the effectors spawned with the new
+        // configuration, so they can only be built after the inventory has
+        // switched to it
+        let sequences = match self.build_sequences(&new_config).await {
+            Ok(sequences) => sequences,
+            Err(e) => {
+                let restored = self
+                    .effector_inventory
+                    .request(InventoryMessage::ReloadConfig(self.config.clone()))
+                    .await;
+                if let Err(restore_error) = restored {
+                    log::error!(
+                        "Couldn't switch the effectors back to the old configuration: {:?}",
+                        restore_error
+                    );
+                }
+                return Err(e);
+            }
+        };
+        self.sequences = sequences;
+        self.config = new_config;
+        self.inhibitor_policy = inhibitor_policy;
+        self.rules = rules;
+        self.fallbacks = fallbacks;
+        self.low_battery_hysteresis = low_battery_hysteresis;
+        self.low_power_threshold = None;
+        self.get_low_power_threshold();
+        Ok(())
+    }
+
+    /// Reload the configuration, respond to the requester and return whether
+    /// the new configuration is in use
+    async fn handle_reload(
+        &mut self,
+        new_config: SharedConfig,
+        response_sender: Responder<(), anyhow::Error>,
+    ) -> bool {
+        log::info!("Reloading configuration");
+        let reload_result = self.reload_config(new_config).await;
+        let reloaded = reload_result.is_ok();
+        if let Err(e) = &reload_result {
+            log::error!("Couldn't reload configuration, keeping the old one: {}", e);
+        }
+        respond(response_sender, reload_result);
+        reloaded
+    }
+
+    /// Wait until the pause ends, handling the commands and power source
+    /// changes in the meantime. Returns false if the controller should
+    /// terminate instead of resuming.
+    async fn wait_while_paused(
+        &mut self,
+        duration: Option<Duration>,
+        schedule_type: &mut ScheduleType,
+    ) -> bool {
+        let mut deadline = duration.map(|duration| Instant::now() + duration);
+        match duration {
+            Some(duration) => log::info!("Pausing the schedule for {:?}", duration),
+            None => log::info!("Pausing the schedule until resumed"),
+        }
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    log::info!("Pause expired, resuming the schedule");
+                    return true;
+                }
+                command = self.command_receiver.as_mut().unwrap().recv() => {
+                    let request = match command {
+                        Some(request) => request,
+                        None => {
+                            log::info!("All ports dropped, terminating");
+                            return false;
+                        }
+                    };
+                    match request.payload {
+                        EnvironmentCommand::ReloadConfig(new_config) => {
+                            if self.handle_reload(new_config, request.response_sender).await {
+                                let power_status = self.power_status();
+                                *schedule_type = self.select_schedule_type(power_status);
+                            }
+                        }
+                        EnvironmentCommand::Pause(duration) => {
+                            log::info!("Pause extended to {:?}", duration);
+                            deadline = duration.map(|duration| Instant::now() + duration);
+                            respond(request.response_sender, Ok(()));
+                        }
+                        EnvironmentCommand::Resume => {
+                            log::info!("Resuming the schedule");
+                            respond(request.response_sender, Ok(()));
+                            return true;
+                        }
+                        EnvironmentCommand::ResetSchedule => {
+                            log::debug!("Paused, ignoring schedule reset");
+                            respond(request.response_sender, Ok(()));
+                        }
+                        EnvironmentCommand::HoldSchedule(_) => {
+                            log::debug!("Paused, ignoring schedule hold");
+                            respond(request.response_sender, Ok(()));
+                        }
+                        EnvironmentCommand::Wake(_) => {
+                            log::debug!("Paused, ignoring wake");
+                            respond(request.response_sender, Ok(()));
+                        }
+                        EnvironmentCommand::SetProfile(profile) => {
+                            self.set_profile(profile);
+                            respond(request.response_sender, Ok(()));
+                            let power_status = self.power_status();
+                            *schedule_type = self.select_schedule_type(power_status);
+                        }
+                        EnvironmentCommand::Ping => respond(request.response_sender, Ok(())),
+                    }
+                }
+                result = power_status_changed(self.power_status_receiver.as_mut()) => {
+                    if result.is_err() {
+                        self.lose_power_sensor();
+                    }
+                    let power_status = self.power_status();
+                    *schedule_type = self.select_schedule_type(power_status);
+                }
+                result = facts_changed(self.facts_receiver.as_mut()) => {
+                    if result.is_err() {
+                        self.facts_receiver = None;
+                    }
+                    let power_status = self.power_status();
+                    *schedule_type = self.select_schedule_type(power_status);
+                }
+            }
+        }
+    }
+
+    /// The current power status, external power if there's no power sensor
+    fn power_status(&self) -> PowerStatus {
+        self.power_status_receiver
+            .as_ref()
+            .map_or(PowerStatus::External, |receiver| *receiver.borrow())
+    }
+
+    fn lose_power_sensor(&mut self) {
+        log::warn!("Power sensor isn't available, assuming external power");
+        self.power_status_receiver = None;
+    }
+
+    fn get_low_power_threshold(&mut self) {
+        let low_power_schedule_defined = self
+            .current_sequences()
+            .contains_key(&ScheduleType::LowBattery);
+        match parse_low_battery_threshold(&self.config) {
+            Ok(threshold) => self.low_power_threshold = Some(threshold),
             Err(e) if low_power_schedule_defined => {
                 log::error!("Low power schedule is defined but {} in configuration. Schedule will never be used.", e);
             }
@@ -223,137 +1392,401 @@ impl<D: DisplayServerController> EnvironmentController<D> {
     }
 
     async fn main_loop(&mut self) -> Result<()> {
-        let power_status = *self.power_status_receiver.borrow_and_update();
-        let mut schedule_type = self.power_status_to_schedule_type(power_status);
+        let power_status = self.power_status();
+        let mut schedule_type = self.select_schedule_type(power_status);
         log::info!("Will use schedule for {:?}", schedule_type);
-        let mut sequence = self.sequence_for_schedule_type(schedule_type);
-        let mut reconciliation_context = ReconciliationContext::empty();
+        let mut sequence = self.sequence_for_schedule_type(&schedule_type);
+        // A previous run may have left some effects applied
+        let applied_effects = currently_applied_effects(&sequence).await;
+        if !applied_effects.is_empty() {
+            log::info!("Effects {:?} are already applied", applied_effects);
+        }
+        let mut reconciliation_context =
+            ReconciliationContext::from_applied_effects(&sequence, &applied_effects);
+        let mut hold_until = None;
+        let mut day_end = Instant::now() + DayType::today().1;
         loop {
             // New actors' initialization
             let (durations, actions) = sequence.clone().into_iter().unzip();
 
-            let idleness_controller = IdlenessController::new(
+            let mut idleness_controller = IdlenessController::new(
                 actions,
                 reconciliation_context.starting_bunch,
                 reconciliation_context.reconciliation_bunches,
                 self.inhibition_sensor.clone(),
-            );
-            let sequencer = Sequencer::new(
-                spawn_server(idleness_controller).await?,
+            )
+            .with_state_journal(self.state_journal.clone())
+            .with_inhibitor_policy(self.inhibitor_policy.clone());
+            logging::set_schedule(Some(schedule_type.config_name()));
+            if let Some(reporter) = self.state_reporter.as_ref() {
+                reporter.update(|state| state.schedule_type = Some(schedule_type.clone()));
+                idleness_controller = idleness_controller.with_state_reporter(reporter.clone());
+            }
+            if let Some(effect_events) = self.effect_events.as_ref() {
+                idleness_controller = idleness_controller.with_effect_events(effect_events.clone());
+            }
+            if let Some(event_history) = self.event_history.as_ref() {
+                idleness_controller = idleness_controller.with_event_history(event_history.clone());
+            }
+            let idleness_port = spawn_server(idleness_controller).await?;
+            self.publish_idleness_port(Some(idleness_port.clone()));
+            let mut sequencer = Sequencer::new(
+                idleness_port,
                 self.ds_controller.clone(),
                 self.idleness_channel.clone(),
                 &durations_to_timeouts(&durations),
                 reconciliation_context.starting_bunch,
                 reconciliation_context.initial_sleep_shorten,
-            );
+            )
+            .with_grace_period(self.grace_period_for_schedule_type(&schedule_type))
+            .with_hold_until(hold_until)
+            .with_state_journal(self.state_journal.clone());
+            if let Some(reporter) = self.state_reporter.as_ref() {
+                sequencer = sequencer.with_state_reporter(reporter.clone());
+            }
+            if let Some(sleep_updates) = self.sleep_updates.as_ref() {
+                sequencer = sequencer.with_sleep_updates(sleep_updates.subscribe());
+            }
             let sequencer_port = sequencer.spawn().await?;
 
-            // Waiting for termination or schedule change
-            loop {
-                tokio::select! {
-                    _ = self.handle_child.as_mut().unwrap().should_terminate() => {
-                        log::info!("Handle dropped, terminating");
-                        sequencer_port.await_shutdown().await;
-                        return Ok(());
-                    }
-                    _ = self.power_status_receiver.changed() => {
-                        let power_status = *self.power_status_receiver.borrow_and_update();
-                        let new_schedule_type = self.power_status_to_schedule_type(power_status);
-                        if new_schedule_type != schedule_type {
-                            schedule_type = new_schedule_type;
-                            break;
+            let mut pause = None;
+            let running_time = loop {
+                // Waiting for termination, configuration reload, pause or schedule change
+                loop {
+                    let day_variants = has_day_variants(&self.config);
+                    tokio::select! {
+                        _ = sleep_until(day_end), if day_variants => {
+                            let (day_type, until_end) = DayType::today();
+                            day_end = Instant::now() + until_end;
+                            if day_type != self.day_type {
+                                log::info!("Switching to the schedules for {}", day_type.config_name());
+                                self.day_type = day_type;
+                                break;
+                            }
+                        }
+                        command = self.command_receiver.as_mut().unwrap().recv() => {
+                            let request = match command {
+                                Some(request) => request,
+                                None => {
+                                    log::info!("All ports dropped, terminating");
+                                    self.publish_idleness_port(None);
+                                    sequencer_port.await_shutdown().await;
+                                    return Ok(());
+                                }
+                            };
+                            match request.payload {
+                                EnvironmentCommand::ReloadConfig(new_config) => {
+                                    let reloaded = self.handle_reload(new_config, request.response_sender).await;
+                                    if reloaded {
+                                        let power_status = self.power_status();
+                                        schedule_type = self.select_schedule_type(power_status);
+                                        break;
+                                    }
+                                }
+                                EnvironmentCommand::Pause(duration) => {
+                                    respond(request.response_sender, Ok(()));
+                                    pause = Some(duration);
+                                    break;
+                                }
+                                EnvironmentCommand::Resume => {
+                                    log::debug!("Not paused, ignoring resume");
+                                    respond(request.response_sender, Ok(()));
+                                }
+                                EnvironmentCommand::ResetSchedule => {
+                                    let result = sequencer_port
+                                        .request(SequencerCommand::Reset)
+                                        .await
+                                        .map(|_| ())
+                                        .map_err(|e| anyhow!("Sequencer couldn't reset: {:?}", e));
+                                    respond(request.response_sender, result);
+                                }
+                                EnvironmentCommand::HoldSchedule(duration) => {
+                                    hold_until = Some(Instant::now() + duration);
+                                    let result = sequencer_port
+                                        .request(SequencerCommand::Hold(duration))
+                                        .await
+                                        .map(|_| ())
+                                        .map_err(|e| anyhow!("Sequencer couldn't hold the schedule: {:?}", e));
+                                    respond(request.response_sender, result);
+                                }
+                                EnvironmentCommand::Wake(duration) => {
+                                    let result = sequencer_port
+                                        .request(SequencerCommand::Wake(duration))
+                                        .await
+                                        .map(|_| ())
+                                        .map_err(|e| anyhow!("Sequencer couldn't wake: {:?}", e));
+                                    respond(request.response_sender, result);
+                                }
+                                EnvironmentCommand::SetProfile(profile) => {
+                                    self.set_profile(profile);
+                                    respond(request.response_sender, Ok(()));
+                                    let power_status = self.power_status();
+                                    let new_schedule_type = self.select_schedule_type(power_status);
+                                    if new_schedule_type != schedule_type {
+                                        schedule_type = new_schedule_type;
+                                        break;
+                                    }
+                                }
+                                EnvironmentCommand::Ping => respond(request.response_sender, Ok(())),
+                            }
                         }
+                        result = power_status_changed(self.power_status_receiver.as_mut()) => {
+                            if result.is_err() {
+                                self.lose_power_sensor();
+                            }
+                            let power_status = self.power_status();
+                            let new_schedule_type = self.select_schedule_type(power_status);
+                            if new_schedule_type != schedule_type {
+                                schedule_type = new_schedule_type;
+                                break;
+                            }
+                        }
+                        result = facts_changed(self.facts_receiver.as_mut()) => {
+                            if result.is_err() {
+                                log::warn!("Environment facts are no longer gathered");
+                                self.facts_receiver = None;
+                            }
+                            let power_status = self.power_status();
+                            let new_schedule_type = self.select_schedule_type(power_status);
+                            if new_schedule_type != schedule_type {
+                                schedule_type = new_schedule_type;
+                                break;
+                            }
+                        }
+                        jump = next_clock_jump(self.clock_jumps.as_mut()) => match jump {
+                            Ok(jump) => {
+                                log::debug!("Local time jumped by {}s, checking the day", jump.local_offset());
+                                // The end of the day was computed from the old time
+                                day_end = Instant::now();
+                            }
+                            Err(broadcast::error::RecvError::Lagged(_)) => day_end = Instant::now(),
+                            Err(broadcast::error::RecvError::Closed) => self.clock_jumps = None,
+                        },
+                    }
+                }
+
+                // Generating the reconciliation context and switching the actors
+                // to the new sequence
+                let running_time = match sequencer_port
+                    .request(SequencerCommand::GetRunningTime)
+                    .await
+                {
+                    Ok(time) => time,
+                    Err(e) => {
+                        log::error!("Couldn't get running time from sequencer, assuming system is awakened: {:?}", e);
+                        Duration::ZERO
                     }
+                };
+                if pause.is_some() {
+                    break running_time;
+                }
+                log::info!("Will use schedule for {:?}", schedule_type);
+                let new_sequence = self.sequence_for_schedule_type(&schedule_type);
+                reconciliation_context =
+                    ReconciliationContext::calculate(&sequence, &new_sequence, running_time);
+                log::debug!("Reconciliation context is {:?}", reconciliation_context);
+                sequence = new_sequence;
+                let replaced = self
+                    .replace_sequence(
+                        &sequencer_port,
+                        &schedule_type,
+                        &sequence,
+                        reconciliation_context.clone(),
+                    )
+                    .await;
+                if let Err(e) = replaced {
+                    log::error!(
+                        "Couldn't switch to the new schedule in place, restarting it: {}",
+                        e
+                    );
+                    break running_time;
+                }
+            };
+
+            // Shutting down the old actors
+            self.publish_idleness_port(None);
+            sequencer_port.await_shutdown().await;
+            if let Some(duration) = pause {
+                rollback_executed_actions(&sequence, running_time, self.effect_events.as_ref())
+                    .await;
+                self.state_journal
+                    .update(|state| state.applied_effects.clear());
+                if let Some(reporter) = self.state_reporter.as_ref() {
+                    reporter.update(|state| {
+                        state.paused = true;
+                        state.current_bunch = 0;
+                        state.applied_effects.clear();
+                        state.next_effect_at = None;
+                        state.idle_since = None;
+                        state.effects_since = None;
+                        state.held_until = None;
+                    });
                 }
+                let resumed = self.wait_while_paused(duration, &mut schedule_type).await;
+                if let Some(reporter) = self.state_reporter.as_ref() {
+                    reporter.update(|state| state.paused = false);
+                }
+                if !resumed {
+                    return Ok(());
+                }
+                self.day_type = DayType::today().0;
+                sequence = self.sequence_for_schedule_type(&schedule_type);
+                reconciliation_context = ReconciliationContext::empty();
             }
+        }
+    }
 
-            // Generating the reconciliation context and shutting down old actors
-            log::info!("Will use schedule for {:?}", schedule_type);
-            let running_time = match sequencer_port.request(GetRunningTime).await {
-                Ok(time) => time,
-                Err(e) => {
-                    log::error!("Couldn't get running time from sequencer, assuming system is awakened: {:?}", e);
-                    Duration::ZERO
-                }
-            };
-            sequencer_port.await_shutdown().await;
-            let new_sequence = self.sequence_for_schedule_type(schedule_type);
-            reconciliation_context =
-                ReconciliationContext::calculate(&sequence, &new_sequence, running_time);
-            log::debug!("Reconciliation context is {:?}", reconciliation_context);
-            sequence = new_sequence;
+    /// Switch the running Sequencer and IdlenessController to the given
+    /// sequence
+    async fn replace_sequence(
+        &self,
+        sequencer_port: &SequencerPort,
+        schedule_type: &ScheduleType,
+        sequence: &Sequence,
+        reconciliation_context: ReconciliationContext,
+    ) -> Result<()> {
+        let (durations, action_bunches): (Vec<Duration>, Vec<Vec<Action>>) =
+            sequence.clone().into_iter().unzip();
+        let replacement = SequenceReplacement {
+            timeouts: durations_to_timeouts(&durations),
+            starting_position: reconciliation_context.starting_bunch,
+            shorten_initial_sleep_by: reconciliation_context.initial_sleep_shorten,
+            grace_period: self.grace_period_for_schedule_type(schedule_type),
+            bunches: BunchReplacement {
+                action_bunches,
+                starting_bunch: reconciliation_context.starting_bunch,
+                reconciliation_bunches: reconciliation_context.reconciliation_bunches,
+                inhibitor_policy: self.inhibitor_policy.clone(),
+            },
+        };
+        sequencer_port
+            .request(SequencerCommand::ReplaceSequence(Box::new(replacement)))
+            .await
+            .map_err(|e| anyhow!("Sequencer couldn't replace the sequence: {:?}", e))?;
+        logging::set_schedule(Some(schedule_type.config_name()));
+        if let Some(reporter) = self.state_reporter.as_ref() {
+            reporter.update(|state| state.schedule_type = Some(schedule_type.clone()));
         }
+        Ok(())
     }
 
-    fn power_status_to_schedule_type(&self, status: PowerStatus) -> ScheduleType {
-        match (status, self.low_power_treshold) {
-            (PowerStatus::External, _) => ScheduleType::ExternalPower,
+    fn set_profile(&mut self, profile: Option<String>) {
+        match profile.as_deref() {
+            Some(profile) => log::info!("Switching to profile {}", profile),
+            None => log::info!("Clearing the profile"),
+        }
+        self.profile = profile;
+    }
+
+    /// Pick the schedule chosen by the first matching rule or, if none of them
+    /// matches, the built-in schedule for the power source
+    fn select_schedule_type(&mut self, status: PowerStatus) -> ScheduleType {
+        let facts = self
+            .facts_receiver
+            .as_ref()
+            .map(|receiver| receiver.borrow().clone())
+            .unwrap_or_default();
+        let environment = Environment {
+            power_status: status,
+            facts: &facts,
+            profile: self.profile.as_deref(),
+        };
+        if let Some(typ) = select_schedule(&self.rules, &environment) {
+            return typ;
+        }
+        match (status, self.low_power_threshold) {
+            (PowerStatus::External, _) => {
+                self.battery_low = None;
+                ScheduleType::ExternalPower
+            }
             (PowerStatus::Battery(_), None) => ScheduleType::Battery,
-            (PowerStatus::Battery(percentage), Some(treshold)) => {
-                if percentage > treshold {
-                    ScheduleType::Battery
-                } else {
+            (PowerStatus::Battery(percentage), Some(threshold)) => {
+                if self.is_battery_low(percentage, threshold) {
                     ScheduleType::LowBattery
+                } else {
+                    ScheduleType::Battery
                 }
             }
         }
     }
 
-    fn sequence_for_schedule_type(&self, typ: ScheduleType) -> Sequence {
-        if self.sequences.contains_key(&typ) {
-            return self.sequences[&typ].clone();
-        }
-        log::warn!(
-            "Schedule of type {:?} is not defined, using a fallback schedule.",
-            typ
-        );
-        let schedule_substitutions = vec![
-            (ScheduleType::LowBattery, ScheduleType::Battery),
-            (ScheduleType::Battery, ScheduleType::ExternalPower),
-        ];
-        for (original_type, substitution_type) in schedule_substitutions.iter() {
-            if typ == *original_type && self.sequences.contains_key(substitution_type) {
-                return self.sequences[substitution_type].clone();
+    /// Decide whether the battery is low, leaving the low battery schedule
+    /// only above the exit percentage and keeping either schedule for the
+    /// minimum dwell time
+    fn is_battery_low(&mut self, percentage: u64, threshold: u64) -> bool {
+        let now = Instant::now();
+        let (was_low, since) = match self.battery_low {
+            Some(battery_low) => battery_low,
+            None => {
+                let low = percentage <= threshold;
+                self.battery_low = Some((low, now));
+                return low;
             }
+        };
+        let low = if was_low {
+            let exit = self
+                .low_battery_hysteresis
+                .exit_percentage
+                .unwrap_or(threshold);
+            percentage <= exit
+        } else {
+            percentage <= threshold
+        };
+        if low == was_low {
+            return low;
+        }
+        if now.duration_since(since) < self.low_battery_hysteresis.min_dwell {
+            log::debug!(
+                "Battery is at {}%, but the schedule has been switched only recently",
+                percentage
+            );
+            return was_low;
         }
+        self.battery_low = Some((low, now));
+        low
+    }
+
+    fn grace_period_for_schedule_type(&self, typ: &ScheduleType) -> Duration {
+        let effective_type =
+            effective_schedule_type(typ.clone(), self.current_sequences(), &self.fallbacks);
+        schedules_for_day(&self.config, self.day_type)
+            .get("schedule")
+            .and_then(|schedules| schedules.get(effective_type.config_name()))
+            .and_then(|schedule| parse_grace_period(schedule).ok())
+            .unwrap_or(Duration::ZERO)
+    }
 
-        self.sequences.iter().next().unwrap().1.clone()
+    fn sequence_for_schedule_type(&self, typ: &ScheduleType) -> Sequence {
+        let resolution =
+            resolve_schedule_type(typ.clone(), self.current_sequences(), &self.fallbacks);
+        if resolution.effective != *typ {
+            log::warn!("The {}", resolution);
+        }
+        self.current_sequences()[&resolution.effective].clone()
     }
 
     async fn sequence_for_schedule(
         &mut self,
-        schedule: &Schedule,
+        schedule_type: &ScheduleType,
+        day: DayType,
+        bunches: EffectBunches,
         effect_names_mapping: &HashMap<String, (String, usize)>,
-        session_effector: &EffectorPort,
     ) -> Result<Sequence> {
-        let mut m: HashMap<Duration, Vec<Effect>> = HashMap::new();
-        for (effect_name, delay) in schedule.iter() {
-            let effect = if effect_names_mapping.contains_key(effect_name) {
-                let mapping_result = &effect_names_mapping[effect_name];
-                ei::get_effects_for_effector(&mapping_result.0)[mapping_result.1].clone()
-            } else {
-                return Err(anyhow!("Unknown effect name {}", effect_name));
-            };
-            m.entry(*delay).or_insert(vec![]).push(effect);
-        }
-
         let mut action_bunches: Sequence = Vec::new();
-        for (timeout, effects) in m.into_iter() {
+        for (timeout, effects) in bunches {
             action_bunches.push((
                 timeout,
-                self.bunch_to_actions(&effects, effect_names_mapping)
+                self.bunch_to_actions(schedule_type, day, &effects, effect_names_mapping)
                     .await?,
             ))
         }
-        action_bunches.sort_by_key(|bunch| bunch.0);
-        action_bunches[0]
-            .1
-            .push(self.idle_hint_action(session_effector.clone()));
         Ok(action_bunches)
     }
 
     async fn bunch_to_actions(
         &mut self,
+        schedule_type: &ScheduleType,
+        day: DayType,
         bunch: &Vec<Effect>,
         effect_names_mapping: &HashMap<String, (String, usize)>,
     ) -> Result<Vec<Action>> {
@@ -363,28 +1796,20 @@ impl<D: DisplayServerController> EnvironmentController<D> {
             let effector_name = effect_names_mapping.get(&effect.name).unwrap().0.as_ref();
             actions.push(Action::new(
                 effect.clone(),
-                self.get_effector(effector_name).await?,
+                ei::get_schedule_effector_port(
+                    &self.effector_inventory,
+                    effector_name,
+                    schedule_type.clone(),
+                    Some(day),
+                )
+                .await?,
             ));
         }
         Ok(actions)
     }
-
-    fn idle_hint_action(&self, session_effector: EffectorPort) -> Action {
-        Action::new(
-            ei::get_effects_for_effector("session")[0].clone(),
-            session_effector,
-        )
-    }
-
-    async fn get_effector(&self, name: &str) -> Result<EffectorPort> {
-        Ok(self
-            .effector_inventory
-            .request(GetEffectorPort(name.to_string()))
-            .await?)
-    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct ReconciliationContext {
     pub starting_bunch: usize,
     pub initial_sleep_shorten: Duration,
@@ -423,9 +1848,12 @@ impl ReconciliationContext {
         let (executed_old_bunches, _) = Self::passed_bunch_count(old_sequence, running_time);
         let (provisional_starting_bunch, provisional_sleep_shorten) =
             Self::passed_bunch_count(new_sequence, running_time);
-        // If the system is already idle, we don't want it to wake up on power source change
+        // If the system is already idle, we don't want it to wake up on power
+        // source change. Starting from the first bunch would roll the old
+        // effects back immediately, no matter how many bunches of the old
+        // sequence were executed, and apply them again once its timeout passes.
         let (new_starting_bunch, sleep_shorten) =
-            if executed_old_bunches == 1 && provisional_starting_bunch == 0 {
+            if executed_old_bunches > 0 && provisional_starting_bunch == 0 {
                 (1, Duration::ZERO)
             } else {
                 (provisional_starting_bunch, provisional_sleep_shorten)
@@ -442,22 +1870,60 @@ impl ReconciliationContext {
             .iter()
             .flat_map(|bunch| &bunch.1)
             .collect();
+        let persistent_skip_effects =
+            Self::persistent_skip_effects(&executed_actions, new_sequence);
         let reconciliation_bunches =
-            Self::reconciliation_bunches(executed_actions, missed_actions, future_actions);
+            Self::reconciliation_bunches(executed_actions, missed_actions, future_actions)
+                .with_persistent_skip_effects(persistent_skip_effects);
         Self::new(new_starting_bunch, sleep_shorten, reconciliation_bunches)
     }
 
+    /// Continue the sequence after the last bunch containing an already
+    /// applied effect, applying the effects of the passed bunches which aren't
+    /// applied with the next bunch and rolling the applied ones back on
+    /// activity
+    pub fn from_applied_effects(
+        sequence: &Sequence,
+        applied_effects: &HashSet<String>,
+    ) -> ReconciliationContext {
+        let is_applied = |action: &&Action| applied_effects.contains(&action.effect.name);
+        let starting_bunch = match sequence
+            .iter()
+            .rposition(|bunch| bunch.1.iter().any(|action| is_applied(&action)))
+        {
+            Some(last_applied) => last_applied + 1,
+            None => return Self::empty(),
+        };
+        let (applied, missed): (Vec<&Action>, Vec<&Action>) = sequence[0..starting_bunch]
+            .iter()
+            .flat_map(|bunch| &bunch.1)
+            .partition(is_applied);
+        let into_bunch = |actions: Vec<&Action>| {
+            Some(actions.into_iter().cloned().collect::<Vec<Action>>())
+                .filter(|actions| !actions.is_empty())
+        };
+        Self::new(
+            starting_bunch,
+            Duration::ZERO,
+            ReconciliationBunches::new(into_bunch(missed), into_bunch(applied), HashSet::new()),
+        )
+    }
+
+    /// Count the bunches of the sequence whose delay has passed, returning
+    /// the count along with the time since the last of them has passed
     fn passed_bunch_count(sequence: &Sequence, running_time: Duration) -> (usize, Duration) {
-        let mut executed = 0;
-        let mut countdown = running_time;
-        for bunch in sequence {
-            if countdown >= bunch.0 {
-                executed += 1;
-                countdown = countdown.saturating_sub(bunch.0);
-            }
-        }
+        // The delays are measured from the start of the idleness, so they are
+        // compared with the running time directly
+        let executed = sequence
+            .iter()
+            .take_while(|bunch| running_time >= bunch.0)
+            .count();
+        let since_last_bunch = match executed {
+            0 => running_time,
+            executed => running_time - sequence[executed - 1].0,
+        };
 
-        (executed, countdown)
+        (executed, since_last_bunch)
     }
 
     fn reconciliation_bunches(
@@ -485,9 +1951,9 @@ impl ReconciliationContext {
         // We need to rollback everything that the old controller executed,
         // since the idleness controller doesn't initialize its rollback stack
         // by itself.
-        let ports_to_rollback: Vec<EffectorPort> = executed_actions
+        let actions_to_rollback: Vec<Action> = executed_actions
             .iter()
-            .map(|action| action.recipient.clone())
+            .map(|action| (*action).clone())
             .collect();
 
         let execute = if !actions_to_execute.is_empty() {
@@ -496,8 +1962,8 @@ impl ReconciliationContext {
             None
         };
 
-        let rollback = if !ports_to_rollback.is_empty() {
-            Some(ports_to_rollback)
+        let rollback = if !actions_to_rollback.is_empty() {
+            Some(actions_to_rollback)
         } else {
             None
         };
@@ -524,6 +1990,26 @@ impl ReconciliationContext {
             .collect()
     }
 
+    /// Effects executed by the old sequence which are never rolled back and
+    /// which the new sequence would execute again in any of its
+    /// bunches, also after the next rollback
+    fn persistent_skip_effects(
+        executed_actions: &[&Action],
+        new_sequence: &Sequence,
+    ) -> HashSet<String> {
+        let lasting: HashSet<&String> = executed_actions
+            .iter()
+            .filter(|action| action.effect.rollback_strategy == RollbackStrategy::None)
+            .map(|action| &action.effect.name)
+            .collect();
+        new_sequence
+            .iter()
+            .flat_map(|bunch| &bunch.1)
+            .filter(|action| lasting.contains(&action.effect.name))
+            .map(|action| action.effect.name.clone())
+            .collect()
+    }
+
     fn effect_names_from_actions(actions: &[&Action]) -> Vec<String> {
         actions
             .iter()
@@ -532,18 +2018,73 @@ impl ReconciliationContext {
     }
 }
 
+fn respond(response_sender: Responder<(), anyhow::Error>, result: Result<()>) {
+    if response_sender.send(result).is_err() {
+        log::error!("Couldn't respond to command, requester went away");
+    }
+}
+
+/// Ask the effectors of the sequence which of its effects are applied. An
+/// effect is considered applied if its effector reports any applied effects.
+async fn currently_applied_effects(sequence: &Sequence) -> HashSet<String> {
+    let mut applied = HashSet::new();
+    for action in sequence.iter().flat_map(|bunch| &bunch.1) {
+        match action
+            .recipient
+            .request_with_timeout(
+                Duration::from_secs(2),
+                EffectorMessage::CurrentlyAppliedEffects,
+            )
+            .await
+        {
+            Ok(response) if response.applied_effects > 0 => {
+                applied.insert(action.effect.name.clone());
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!(
+                "Couldn't find out whether {} is applied, assuming it isn't: {:?}",
+                action.effect.name,
+                e
+            ),
+        }
+    }
+    applied
+}
+
+/// Roll back the effects of the sequence which have been applied during the
+/// given running time and would be rolled back on user activity
+async fn rollback_executed_actions(
+    sequence: &Sequence,
+    running_time: Duration,
+    effect_events: Option<&EffectEventSender>,
+) {
+    let (executed_bunches, _) = ReconciliationContext::passed_bunch_count(sequence, running_time);
+    let mut actions: Vec<Action> = sequence[0..executed_bunches]
+        .iter()
+        .flat_map(|bunch| &bunch.1)
+        .filter(|action| {
+            matches!(
+                action.effect.rollback_strategy,
+                RollbackStrategy::OnActivity
+            )
+        })
+        .cloned()
+        .collect();
+    rollback_all(&mut actions, effect_events).await;
+}
+
 /// Convert a [Vec] of durations into a [Vec] of second timeouts, each one
 /// representing the offset from the previous one.
 ///
 /// ```
 /// let durations = vec![Duration::from_secs(5), Duration::from_secs(30), Duration::from_secs(60), Duration::from_secs(3600)];
 /// let timeouts = durations_to_timeouts(&durations);
-/// assert_eq!(timeouts, vec![5, 25, 30, 3540]);
+/// assert_eq!(timeouts, vec![5, 25, 30, 3540].into_iter().map(Duration::from_secs).collect::<Vec<_>>());
 /// ```
-fn durations_to_timeouts(durations: &Vec<Duration>) -> Vec<u64> {
-    let mut timeouts = vec![durations[0].as_secs()];
+fn durations_to_timeouts(durations: &Vec<Duration>) -> Vec<Duration> {
+    let mut timeouts = vec![durations[0]];
     for (i, duration) in durations[1..].iter().enumerate() {
-        timeouts.push(duration.saturating_sub(durations[i]).as_secs());
+        timeouts.push(duration.saturating_sub(durations[i]));
     }
     timeouts
 }
@@ -565,9 +2106,442 @@ mod test {
             parse_duration("5m 1h").unwrap(),
             Duration::from_secs(65 * 60)
         );
-        assert!(parse_duration("5m6h").is_err());
+        assert_eq!(
+            parse_duration("5m6h").unwrap(),
+            Duration::from_secs(6 * 3600 + 5 * 60)
+        );
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(
+            parse_duration("1d 2h").unwrap(),
+            Duration::from_secs(26 * 3600)
+        );
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(
+            parse_duration("1s500ms").unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration(" 2m ").unwrap(), Duration::from_secs(120));
         assert!(parse_duration("5mh").is_err());
-        assert!(parse_duration("5m 6d").is_err());
+        assert!(parse_duration("5m 6w").is_err());
+        assert!(parse_duration("5m 30").is_err());
+        assert!(parse_duration("m5").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("99999999999999999999d").is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_duration_format_roundtrip(millis in 1u64..(400 * 24 * 3600 * 1000)) {
+            let duration = Duration::from_millis(millis);
+            proptest::prop_assert_eq!(parse_duration(&format_duration(duration)).unwrap(), duration);
+        }
+
+        #[test]
+        fn test_duration_compact_equals_spaced(
+            components in proptest::collection::vec(
+                (0u64..10000, proptest::sample::select(vec!["ms", "s", "m", "h", "d"])),
+                1..6
+            )
+        ) {
+            let spaced: Vec<String> = components.iter().map(|(v, u)| format!("{}{}", v, u)).collect();
+            proptest::prop_assert_eq!(
+                parse_duration(&spaced.join(" ")).unwrap(),
+                parse_duration(&spaced.join("")).unwrap()
+            );
+        }
+
+        #[test]
+        fn test_bare_seconds(seconds in 0u64..u32::MAX as u64) {
+            proptest::prop_assert_eq!(
+                parse_duration(&seconds.to_string()).unwrap(),
+                Duration::from_secs(seconds)
+            );
+        }
+
+        #[test]
+        fn test_duration_parsing_doesnt_panic(string in "\\PC*") {
+            let _ = parse_duration(&string);
+        }
+    }
+
+    #[test]
+    fn test_duration_formatting() {
+        assert_eq!(format_duration(Duration::from_secs(54)), "54s");
+        assert_eq!(format_duration(Duration::from_secs(150)), "2m 30s");
+        assert_eq!(format_duration(Duration::from_secs(3630)), "1h 30s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(
+            parse_duration(&format_duration(Duration::from_secs(5432))).unwrap(),
+            Duration::from_secs(5432)
+        );
+    }
+
+    #[test]
+    fn test_schedule_validation() {
+        let schedule = parse_schedule(&toml::toml! { screen_dim = "1m" dpms_off = "2m" }).unwrap();
+        assert_eq!(schedule["dpms_off"], Duration::from_secs(120));
+        assert!(parse_schedule(&toml::toml! { screen_dim = "0s" }).is_err());
+        assert!(parse_schedule(&toml::toml! { screen_dim = "10h" }).is_err());
+        assert!(parse_schedule(&toml::toml! { screen_dim = "1m" sleep = "10h" }).is_ok());
+        assert!(parse_schedule(&toml::toml! { screen_dim = 5 }).is_err());
+        let schedule =
+            parse_schedule(&toml::toml! { screen_dim = "10s" screen_off = "10s 500ms" }).unwrap();
+        assert_eq!(schedule["screen_off"], Duration::from_millis(10500));
+        assert!(
+            parse_schedule(&toml::toml! { screen_dim = "9s 500ms" screen_off = "1m" }).is_err()
+        );
+        assert!(parse_schedule(&toml::Value::Table(toml::value::Map::new())).is_err());
+    }
+
+    #[test]
+    fn test_grace_period() {
+        let schedule_config = toml::toml! {
+            grace = "1s 500ms"
+            screen_dim = "1m"
+        };
+        let schedule = parse_schedule(&schedule_config).unwrap();
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(
+            parse_grace_period(&schedule_config).unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            parse_grace_period(&toml::toml! { screen_dim = "1m" }).unwrap(),
+            Duration::ZERO
+        );
+        assert!(parse_schedule(&toml::toml! { grace = 5 screen_dim = "1m" }).is_err());
+        assert!(parse_schedule(&toml::toml! { grace = "5s" }).is_err());
+    }
+
+    #[test]
+    fn test_staged_effects() {
+        let schedule = parse_schedule(&toml::toml! {
+            display = ["2m:dim", "5m:off"]
+            lock = "2m"
+        })
+        .unwrap();
+        assert_eq!(schedule.len(), 3);
+        assert_eq!(schedule["screen_dim"], Duration::from_secs(120));
+        assert_eq!(schedule["screen_off"], Duration::from_secs(300));
+
+        let only_off = parse_schedule(&toml::toml! { display = ["5m:off"] }).unwrap();
+        assert_eq!(only_off.len(), 1);
+
+        assert!(parse_schedule(&toml::toml! { display = ["5m:off", "6m:dim"] }).is_err());
+        assert!(parse_schedule(&toml::toml! { display = ["5m:dim", "5m:off"] }).is_err());
+        assert!(parse_schedule(&toml::toml! { display = ["5m:dim", "5m:dim"] }).is_err());
+        assert!(parse_schedule(&toml::toml! { display = ["5m:blink"] }).is_err());
+        assert!(parse_schedule(&toml::toml! { display = ["5m"] }).is_err());
+        assert!(parse_schedule(&toml::toml! { display = [] }).is_err());
+        assert!(parse_schedule(&toml::toml! { sleep = ["5m:off"] }).is_err());
+        assert!(parse_schedule(&toml::toml! {
+            display = ["2m:dim", "5m:off"]
+            screen_dim = "1m"
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_schedule_to_bunches() {
+        let mapping = ei::resolve_effectors_for_effects();
+        let schedule = HashMap::from([
+            ("screen_off".to_owned(), Duration::from_secs(120)),
+            ("screen_dim".to_owned(), Duration::from_secs(60)),
+            ("lock".to_owned(), Duration::from_secs(120)),
+        ]);
+        let bunches = schedule_to_bunches(&schedule, &mapping).unwrap();
+        assert_eq!(bunches.len(), 2);
+        assert_eq!(bunches[0].0, Duration::from_secs(60));
+        let first_names: Vec<&str> = bunches[0].1.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(first_names, vec!["screen_dim", "idle_hint"]);
+        assert_eq!(bunches[1].1.len(), 2);
+
+        let delayed_hint = HashMap::from([
+            ("screen_dim".to_owned(), Duration::from_secs(60)),
+            ("idle_hint".to_owned(), Duration::from_secs(300)),
+        ]);
+        let bunches = schedule_to_bunches(&delayed_hint, &mapping).unwrap();
+        let names: Vec<Vec<&str>> = bunches
+            .iter()
+            .map(|bunch| bunch.1.iter().map(|e| e.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["screen_dim"], vec!["idle_hint"]]);
+
+        let unknown = HashMap::from([("explode".to_owned(), Duration::from_secs(60))]);
+        assert!(schedule_to_bunches(&unknown, &mapping).is_err());
+    }
+
+    #[test]
+    fn test_effect_overrides() {
+        let schedule_config = toml::toml! {
+            lock = "5m"
+
+            [screen_dim]
+            after = "1m"
+            rollback = "immediate"
+            inhibited_by = []
+        };
+        assert_eq!(parse_schedule(&schedule_config).unwrap().len(), 2);
+        let overrides = parse_effect_overrides(&schedule_config).unwrap();
+        assert_eq!(
+            overrides,
+            HashMap::from([(
+                "screen_dim".to_owned(),
+                EffectOverride {
+                    rollback_strategy: Some(RollbackStrategy::Immediate),
+                    inhibited_by: Some(vec![]),
+                }
+            )])
+        );
+
+        let mapping = ei::resolve_effectors_for_effects();
+        let schedule = parse_schedule(&schedule_config).unwrap();
+        let mut bunches = schedule_to_bunches(&schedule, &mapping).unwrap();
+        apply_effect_overrides(&mut bunches[0].1, &overrides).unwrap();
+        assert_eq!(bunches[0].1[0].name, "screen_dim");
+        assert_eq!(
+            bunches[0].1[0].rollback_strategy,
+            RollbackStrategy::Immediate
+        );
+        assert!(bunches[0].1[0].inhibited_by.is_empty());
+        // Other schedules keep the effect's own properties
+        let unchanged = schedule_to_bunches(&schedule, &mapping).unwrap();
+        assert_eq!(
+            unchanged[0].1[0].rollback_strategy,
+            RollbackStrategy::OnActivity
+        );
+        assert!(unchanged[0].1[0].inhibited_by.contains(&InhibitType::Idle));
+
+        let mut lock = schedule_to_bunches(&schedule, &mapping)
+            .unwrap()
+            .remove(1)
+            .1;
+        let only_sleep = parse_effect_overrides(&toml::toml! {
+            [lock]
+            after = "5m"
+            inhibited_by = ["sleep"]
+        })
+        .unwrap();
+        apply_effect_overrides(&mut lock, &only_sleep).unwrap();
+        assert_eq!(lock[0].inhibited_by, vec![InhibitType::Sleep]);
+        // The lock can't be undone by Energia
+        let lock_rollback = HashMap::from([(
+            "lock".to_owned(),
+            EffectOverride {
+                rollback_strategy: Some(RollbackStrategy::OnActivity),
+                inhibited_by: None,
+            },
+        )]);
+        assert!(apply_effect_overrides(&mut lock, &lock_rollback).is_err());
+
+        assert!(parse_schedule(&toml::toml! {
+            [screen_dim]
+            after = "1m"
+            rollback = "later"
+        })
+        .is_err());
+        assert!(parse_schedule(&toml::toml! {
+            [screen_dim]
+            after = "1m"
+            inhibited_by = "idle"
+        })
+        .is_err());
+        assert!(parse_schedule(&toml::toml! {
+            [screen_dim]
+            after = "1m"
+            inhibited_by = ["handle-lid-switch"]
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_schedule_fallbacks() {
+        let fallbacks = FallbackChain::default();
+        let all = HashMap::from([
+            (ScheduleType::ExternalPower, ()),
+            (ScheduleType::Battery, ()),
+            (ScheduleType::LowBattery, ()),
+        ]);
+        for typ in ScheduleType::ALL {
+            assert_eq!(effective_schedule_type(typ.clone(), &all, &fallbacks), typ);
+        }
+        let external = HashMap::from([(ScheduleType::ExternalPower, ())]);
+        assert_eq!(
+            effective_schedule_type(ScheduleType::LowBattery, &external, &fallbacks),
+            ScheduleType::ExternalPower
+        );
+        let battery = HashMap::from([(ScheduleType::Battery, ())]);
+        assert_eq!(
+            effective_schedule_type(ScheduleType::LowBattery, &battery, &fallbacks),
+            ScheduleType::Battery
+        );
+        assert_eq!(
+            effective_schedule_type(ScheduleType::ExternalPower, &battery, &fallbacks),
+            ScheduleType::Battery
+        );
+        let low_battery = HashMap::from([(ScheduleType::LowBattery, ())]);
+        assert_eq!(
+            effective_schedule_type(ScheduleType::Battery, &low_battery, &fallbacks),
+            ScheduleType::LowBattery
+        );
+    }
+
+    #[test]
+    fn test_configured_fallbacks() {
+        assert_eq!(
+            parse_fallbacks(&toml::toml! { [lock] command = "i3lock" }).unwrap(),
+            FallbackChain::default()
+        );
+        let fallbacks = parse_fallbacks(&toml::toml! {
+            fallbacks = ["low_battery:external", "battery:low_battery", "presentation:battery"]
+        })
+        .unwrap();
+        assert_eq!(
+            fallbacks.substitute(&ScheduleType::Battery),
+            Some(ScheduleType::LowBattery)
+        );
+
+        let external = HashMap::from([(ScheduleType::ExternalPower, ())]);
+        let resolution =
+            resolve_schedule_type(ScheduleType::named("presentation"), &external, &fallbacks);
+        assert_eq!(resolution.effective, ScheduleType::ExternalPower);
+        assert_eq!(
+            resolution.undefined_fallbacks,
+            vec![ScheduleType::Battery, ScheduleType::LowBattery]
+        );
+        assert!(!resolution.last_resort);
+        assert_eq!(
+            resolution.to_string(),
+            "schedule presentation is not defined, neither is its fallback battery -> low_battery, using its fallback external"
+        );
+
+        let battery = HashMap::from([(ScheduleType::Battery, ())]);
+        let resolution = resolve_schedule_type(ScheduleType::ExternalPower, &battery, &fallbacks);
+        assert_eq!(resolution.effective, ScheduleType::Battery);
+        assert!(resolution.last_resort);
+
+        assert!(parse_fallbacks(&toml::toml! {
+            fallbacks = ["low_battery:battery", "battery:external", "external:low_battery"]
+        })
+        .is_err());
+        assert!(parse_fallbacks(&toml::toml! { fallbacks = ["battery:battery"] }).is_err());
+        assert!(parse_fallbacks(&toml::toml! {
+            fallbacks = ["battery:external", "battery:low_battery"]
+        })
+        .is_err());
+        assert!(parse_fallbacks(&toml::toml! { fallbacks = ["battery"] }).is_err());
+        assert!(parse_fallbacks(&toml::toml! { fallbacks = "battery:external" }).is_err());
+    }
+
+    #[test]
+    fn test_named_schedules() {
+        assert_eq!(
+            ScheduleType::try_from("battery").unwrap(),
+            ScheduleType::Battery
+        );
+        let presentation = ScheduleType::try_from("presentation").unwrap();
+        assert_eq!(presentation, ScheduleType::named("presentation"));
+        assert_eq!(presentation.config_name(), "presentation");
+        assert!(!presentation.is_builtin());
+        assert!(ScheduleType::try_from("weekend").is_err());
+        assert!(ScheduleType::try_from("").is_err());
+
+        let schedules = parse_schedules(&toml::toml! {
+            [schedule.presentation]
+            screen_off = "2h"
+            [schedule.docked]
+            screen_dim = "10m"
+            [schedule.external]
+            screen_dim = "1m"
+        })
+        .unwrap();
+        assert_eq!(
+            schedule_types(&schedules),
+            vec![
+                ScheduleType::ExternalPower,
+                ScheduleType::Battery,
+                ScheduleType::LowBattery,
+                ScheduleType::named("docked"),
+                presentation,
+            ]
+        );
+        // Named schedules fall back to the built-in ones when undefined
+        let fallbacks = FallbackChain::default();
+        assert_eq!(
+            effective_schedule_type(ScheduleType::named("gaming"), &schedules, &fallbacks),
+            ScheduleType::ExternalPower
+        );
+    }
+
+    #[test]
+    fn test_day_variants() {
+        assert_eq!(DayType::from_weekday(0), DayType::Weekend);
+        assert_eq!(DayType::from_weekday(1), DayType::Weekday);
+        assert_eq!(DayType::from_weekday(5), DayType::Weekday);
+        assert_eq!(DayType::from_weekday(6), DayType::Weekend);
+
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.battery]
+            screen_dim = "2m"
+            sleep = "10m"
+
+            [schedule.battery.weekend]
+            screen_dim = "5m"
+            grace = "10s"
+        };
+        assert!(has_day_variants(&config));
+        assert!(!has_day_variants(&schedules_for_day(
+            &config,
+            DayType::Weekend
+        )));
+        let schedules = parse_schedules(&config).unwrap();
+        assert_eq!(
+            schedules[&ScheduleType::Battery]["screen_dim"],
+            Duration::from_secs(120)
+        );
+
+        let weekend = schedules_for_day(&config, DayType::Weekend);
+        let schedules = parse_schedules(&weekend).unwrap();
+        assert_eq!(
+            schedules[&ScheduleType::Battery],
+            HashMap::from([
+                ("screen_dim".to_owned(), Duration::from_secs(300)),
+                ("sleep".to_owned(), Duration::from_secs(600)),
+            ])
+        );
+        assert_eq!(
+            parse_grace_period(&weekend["schedule"]["battery"]).unwrap(),
+            Duration::from_secs(10)
+        );
+        let weekdays = schedules_for_day(&config, DayType::Weekday);
+        assert_eq!(
+            parse_schedules(&weekdays).unwrap()[&ScheduleType::Battery]["screen_dim"],
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            parse_grace_period(&weekdays["schedule"]["battery"]).unwrap(),
+            Duration::ZERO
+        );
+
+        assert!(parse_schedules(&toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+            weekend = "5m"
+        })
+        .is_err());
+        assert!(parse_schedules(&toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.external.weekdays]
+            screen_dim = "0s"
+        })
+        .is_err());
     }
 
     #[test]
@@ -580,7 +2554,16 @@ mod test {
             Duration::from_secs(3600),
         ];
         let timeouts = durations_to_timeouts(&durations);
-        assert_eq!(timeouts, vec![5, 25, 0, 29, 3540]);
+        assert_eq!(
+            timeouts,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_secs(25),
+                Duration::from_millis(50),
+                Duration::from_millis(29950),
+                Duration::from_secs(3540),
+            ]
+        );
     }
 
     fn empty_action(bunch: usize, effect: usize) -> Action {
@@ -636,11 +2619,11 @@ mod test {
     fn test_reconciliation_rollback() {
         let seq1 = make_sequence(&vec![
             (Duration::from_secs(30), 3),
-            (Duration::from_secs(30), 2),
+            (Duration::from_secs(60), 2),
         ]);
         let seq2 = make_sequence(&vec![
             (Duration::from_secs(40), 2),
-            (Duration::from_secs(10), 5),
+            (Duration::from_secs(50), 5),
         ]);
         let context = ReconciliationContext::calculate(&seq1, &seq2, Duration::from_secs(45));
         assert_eq!(context.initial_sleep_shorten, Duration::from_secs(5));
@@ -654,12 +2637,12 @@ mod test {
     fn test_reconciliation_basic() {
         let seq1 = make_sequence(&vec![
             (Duration::from_secs(30), 3),
-            (Duration::from_secs(30), 3),
-            (Duration::from_secs(30), 2),
+            (Duration::from_secs(60), 3),
+            (Duration::from_secs(90), 2),
         ]);
         let seq2 = make_sequence(&vec![
             (Duration::from_secs(40), 5),
-            (Duration::from_secs(60), 5),
+            (Duration::from_secs(100), 5),
         ]);
         let context = ReconciliationContext::calculate(&seq1, &seq2, Duration::from_secs(65));
         assert_eq!(context.initial_sleep_shorten, Duration::from_secs(25));
@@ -695,4 +2678,96 @@ mod test {
         assert_eq!(context.reconciliation_bunches.rollback.unwrap().len(), 3);
         assert_eq!(context.reconciliation_bunches.skip_effects.len(), 0);
     }
+
+    #[test]
+    fn test_reconciliation_of_applied_effects() {
+        let sequence = make_sequence(&vec![
+            (Duration::from_secs(10), 2),
+            (Duration::from_secs(10), 2),
+            (Duration::from_secs(10), 2),
+        ]);
+        let context = ReconciliationContext::from_applied_effects(&sequence, &HashSet::new());
+        assert_eq!(context.starting_bunch, 0);
+        assert!(context.reconciliation_bunches.execute.is_none());
+        assert!(context.reconciliation_bunches.rollback.is_none());
+
+        let context = ReconciliationContext::from_applied_effects(
+            &sequence,
+            &HashSet::from(["0-1".to_owned(), "1-0".to_owned()]),
+        );
+        assert_eq!(context.starting_bunch, 2);
+        assert_eq!(context.initial_sleep_shorten, Duration::ZERO);
+        assert_eq!(
+            action_names(context.reconciliation_bunches.execute.unwrap()),
+            vec!["0-0", "1-1"]
+        );
+        assert_eq!(
+            action_names(context.reconciliation_bunches.rollback.unwrap()),
+            vec!["0-1", "1-0"]
+        );
+        assert!(context.reconciliation_bunches.skip_effects.is_empty());
+    }
+
+    #[test]
+    fn test_reconciliation_stays_in_idle_after_multiple_bunches() {
+        let seq1 = make_sequence(&vec![
+            (Duration::from_secs(10), 2),
+            (Duration::from_secs(20), 3),
+            (Duration::from_secs(80), 1),
+        ]);
+        let seq2 = make_sequence(&vec![
+            (Duration::from_secs(30), 2),
+            (Duration::from_secs(40), 4),
+            (Duration::from_secs(70), 1),
+        ]);
+        let context = ReconciliationContext::calculate(&seq1, &seq2, Duration::from_secs(25));
+        assert_eq!(context.initial_sleep_shorten, Duration::ZERO);
+        assert_eq!(context.starting_bunch, 1);
+        assert!(context.reconciliation_bunches.execute.is_none());
+        assert_eq!(context.reconciliation_bunches.rollback.unwrap().len(), 5);
+        assert_eq!(
+            context.reconciliation_bunches.skip_effects,
+            HashSet::from(["1-0".to_owned(), "1-1".to_owned(), "1-2".to_owned()])
+        );
+        assert!(context
+            .reconciliation_bunches
+            .persistent_skip_effects
+            .is_empty());
+    }
+
+    #[test]
+    fn test_passed_bunch_count() {
+        let sequence = make_sequence(&vec![
+            (Duration::from_secs(10), 1),
+            (Duration::from_secs(20), 1),
+            (Duration::from_secs(60), 1),
+        ]);
+        let count =
+            |secs| ReconciliationContext::passed_bunch_count(&sequence, Duration::from_secs(secs));
+        assert_eq!(count(5), (0, Duration::from_secs(5)));
+        assert_eq!(count(10), (1, Duration::ZERO));
+        assert_eq!(count(25), (2, Duration::from_secs(5)));
+        assert_eq!(count(90), (3, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_reconciliation_skips_lasting_effects_after_rollback() {
+        let mut seq1 = make_sequence(&vec![
+            (Duration::from_secs(10), 2),
+            (Duration::from_secs(20), 1),
+        ]);
+        seq1[0].1[1].effect.rollback_strategy = RollbackStrategy::None;
+        let mut seq2 = make_sequence(&vec![
+            (Duration::from_secs(30), 2),
+            (Duration::from_secs(40), 2),
+        ]);
+        seq2[0].1[1].effect.rollback_strategy = RollbackStrategy::None;
+        let context = ReconciliationContext::calculate(&seq1, &seq2, Duration::from_secs(15));
+        assert_eq!(context.starting_bunch, 1);
+        assert!(context.reconciliation_bunches.execute.is_none());
+        assert_eq!(
+            context.reconciliation_bunches.persistent_skip_effects,
+            HashSet::from(["0-1".to_owned()])
+        );
+    }
 }
@@ -0,0 +1,273 @@
+//! The most recent state transitions, kept in memory so that users can find
+//! out why something happened, e.g. why the screen got locked, without
+//! reading the logs
+
+use super::{
+    environment_controller::ScheduleType,
+    manager_state::{EffectEvent, EffectTransition, ManagerState},
+};
+use crate::{
+    armaf::{Handle, HandleChild},
+    external::display_server::SystemState,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::sync::{broadcast, watch};
+
+/// Number of events kept by default
+pub const DEFAULT_HISTORY_SIZE: usize = 200;
+
+/// A state transition worth remembering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEvent {
+    /// The user became idle
+    Idle,
+    /// The user became active
+    Awakened,
+    EffectApplied(String),
+    EffectRolledBack(String),
+    /// The effect couldn't be applied or rolled back
+    EffectFailed(String),
+    ScheduleSwitched(ScheduleType),
+    /// The effect wasn't applied because of an inhibitor
+    InhibitedBy {
+        effect: String,
+        who: String,
+        why: String,
+    },
+    /// The effect was skipped, since its effector reported that it can't
+    /// execute it
+    EffectUnavailable {
+        effect: String,
+        reason: String,
+    },
+}
+
+impl HistoryEvent {
+    /// Name of the kind of the event
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HistoryEvent::Idle => "idle",
+            HistoryEvent::Awakened => "awake",
+            HistoryEvent::EffectApplied(_) => "effect_applied",
+            HistoryEvent::EffectRolledBack(_) => "effect_rolled_back",
+            HistoryEvent::EffectFailed(_) => "effect_failed",
+            HistoryEvent::ScheduleSwitched(_) => "schedule_switched",
+            HistoryEvent::InhibitedBy { .. } => "inhibited_by",
+            HistoryEvent::EffectUnavailable { .. } => "effect_unavailable",
+        }
+    }
+
+    /// Human-readable details of the event, empty if the kind says it all
+    pub fn details(&self) -> String {
+        match self {
+            HistoryEvent::Idle | HistoryEvent::Awakened => String::new(),
+            HistoryEvent::EffectApplied(effect)
+            | HistoryEvent::EffectRolledBack(effect)
+            | HistoryEvent::EffectFailed(effect) => effect.clone(),
+            HistoryEvent::ScheduleSwitched(schedule_type) => schedule_type.config_name().to_owned(),
+            HistoryEvent::InhibitedBy { effect, who, why } => {
+                format!("{} inhibited by {}: {}", effect, who, why)
+            }
+            HistoryEvent::EffectUnavailable { effect, reason } => {
+                format!("{}: {}", effect, reason)
+            }
+        }
+    }
+}
+
+/// An event and the time at which it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub timestamp: SystemTime,
+    pub event: HistoryEvent,
+}
+
+/// A ring buffer of the most recent events, shared by all its clones
+#[derive(Debug, Clone)]
+pub struct EventHistory {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<HistoryEntry>>>,
+}
+
+impl EventHistory {
+    /// Create a history keeping at most the given number of events
+    pub fn new(capacity: usize) -> EventHistory {
+        EventHistory {
+            capacity,
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Record an event which has just happened, forgetting the oldest one if
+    /// the history is full
+    pub fn record(&self, event: HistoryEvent) {
+        self.record_at(SystemTime::now(), event);
+    }
+
+    /// Record an event which happened at the given time
+    pub fn record_at(&self, timestamp: SystemTime, event: HistoryEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(HistoryEntry { timestamp, event });
+    }
+
+    /// The recorded events, oldest first
+    pub fn recent(&self) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Records the changes of idleness, the effect events and the switches of
+/// the schedule into an [EventHistory]
+pub struct HistoryRecorder {
+    history: EventHistory,
+    idleness_channel: watch::Receiver<SystemState>,
+    effect_events: broadcast::Receiver<EffectEvent>,
+    state: watch::Receiver<ManagerState>,
+    schedule_type: Option<ScheduleType>,
+    handle_child: Option<HandleChild>,
+}
+
+impl HistoryRecorder {
+    pub fn new(
+        history: EventHistory,
+        idleness_channel: watch::Receiver<SystemState>,
+        effect_events: broadcast::Receiver<EffectEvent>,
+        state: watch::Receiver<ManagerState>,
+    ) -> HistoryRecorder {
+        let schedule_type = state.borrow().schedule_type.clone();
+        HistoryRecorder {
+            history,
+            idleness_channel,
+            effect_events,
+            state,
+            schedule_type,
+            handle_child: None,
+        }
+    }
+
+    pub fn spawn(mut self) -> Handle {
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+            log::debug!("Terminated");
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        let mut idleness_open = true;
+        let mut state_open = true;
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => return,
+                changed = self.idleness_channel.changed(), if idleness_open => {
+                    if changed.is_err() {
+                        idleness_open = false;
+                        continue;
+                    }
+                    let event = match *self.idleness_channel.borrow_and_update() {
+                        SystemState::Idle => HistoryEvent::Idle,
+                        SystemState::Awakened => HistoryEvent::Awakened,
+                    };
+                    self.history.record(event);
+                }
+                event = self.effect_events.recv() => match event {
+                    Ok(event) => self.record_effect_event(event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("History missed {} effect events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                changed = self.state.changed(), if state_open => {
+                    if changed.is_err() {
+                        state_open = false;
+                        continue;
+                    }
+                    let schedule_type = self.state.borrow_and_update().schedule_type.clone();
+                    if schedule_type != self.schedule_type {
+                        self.schedule_type = schedule_type.clone();
+                        if let Some(schedule_type) = schedule_type {
+                            self.history.record(HistoryEvent::ScheduleSwitched(schedule_type));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn record_effect_event(&self, event: EffectEvent) {
+        let history_event = match event.transition {
+            EffectTransition::Applied => HistoryEvent::EffectApplied(event.effect_name),
+            EffectTransition::RolledBack => HistoryEvent::EffectRolledBack(event.effect_name),
+            EffectTransition::ApplicationFailed | EffectTransition::RollbackFailed => {
+                HistoryEvent::EffectFailed(event.effect_name)
+            }
+        };
+        self.history.record_at(event.timestamp, history_event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let history = EventHistory::new(2);
+        history.record(HistoryEvent::Idle);
+        history.record(HistoryEvent::EffectApplied("lock".to_owned()));
+        history.clone().record(HistoryEvent::Awakened);
+        let events: Vec<HistoryEvent> = history
+            .recent()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                HistoryEvent::EffectApplied("lock".to_owned()),
+                HistoryEvent::Awakened
+            ]
+        );
+
+        let empty = EventHistory::new(0);
+        empty.record(HistoryEvent::Idle);
+        assert!(empty.recent().is_empty());
+    }
+
+    #[test]
+    fn test_details() {
+        let event = HistoryEvent::InhibitedBy {
+            effect: "screen_off".to_owned(),
+            who: "mpv".to_owned(),
+            why: "Playing video".to_owned(),
+        };
+        assert_eq!(event.kind(), "inhibited_by");
+        assert_eq!(
+            event.details(),
+            "screen_off inhibited by mpv: Playing video"
+        );
+        assert_eq!(
+            HistoryEvent::ScheduleSwitched(ScheduleType::LowBattery).details(),
+            "low_battery"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Re-applies the effects changing the state of the screens when a monitor
+//! is connected, since a new monitor starts turned on and at full brightness
+//! even while the others are turned off or dimmed
+
+use super::{
+    effector_inventory::{request_effector, resolve_effectors_for_effects, InventoryPort},
+    manager_state::ManagerState,
+};
+use crate::{
+    armaf::{self, EffectorMessage},
+    system::output_sensor::{newly_connected, ConnectedOutputs},
+};
+use tokio::sync::watch;
+
+/// Effects whose state has to be extended to newly connected monitors
+const REAPPLIED_EFFECTS: [&str; 3] = ["screen_off", "screen_blank", "screen_dim"];
+
+/// Ensures that the applied [REAPPLIED_EFFECTS] are applied again whenever a
+/// new output is connected
+pub struct HotplugController {
+    outputs: watch::Receiver<ConnectedOutputs>,
+    effector_inventory: InventoryPort,
+    state: watch::Receiver<ManagerState>,
+    handle_child: Option<armaf::HandleChild>,
+}
+
+impl HotplugController {
+    pub fn new(
+        outputs: watch::Receiver<ConnectedOutputs>,
+        effector_inventory: InventoryPort,
+        state: watch::Receiver<ManagerState>,
+    ) -> HotplugController {
+        HotplugController {
+            outputs,
+            effector_inventory,
+            state,
+            handle_child: None,
+        }
+    }
+
+    pub fn spawn(mut self) -> armaf::Handle {
+        let (handle, handle_child) = armaf::Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        let mut previous = self.outputs.borrow_and_update().clone();
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => {
+                    return;
+                }
+                changed = self.outputs.changed() => {
+                    if changed.is_err() {
+                        log::warn!("Output sensor has stopped, monitor hotplug won't be handled");
+                        self.handle_child.as_mut().unwrap().should_terminate().await;
+                        return;
+                    }
+                    let current = self.outputs.borrow_and_update().clone();
+                    let connected = newly_connected(&previous, &current);
+                    if !connected.is_empty() {
+                        log::info!("Outputs {:?} connected", connected);
+                        self.reapply_effects().await;
+                    }
+                    previous = current;
+                }
+            }
+        }
+    }
+
+    async fn reapply_effects(&self) {
+        let applied: Vec<String> = self
+            .state
+            .borrow()
+            .applied_effects
+            .iter()
+            .filter(|effect| REAPPLIED_EFFECTS.contains(&effect.as_str()))
+            .cloned()
+            .collect();
+        let mapping = resolve_effectors_for_effects();
+        for effect in applied {
+            let effector = match mapping.get(&effect) {
+                Some((effector, _)) => effector,
+                None => continue,
+            };
+            log::debug!("Applying {} to the newly connected outputs", effect);
+            if let Err(e) = request_effector(
+                &self.effector_inventory,
+                effector,
+                EffectorMessage::EnsureApplied,
+            )
+            .await
+            {
+                log::error!(
+                    "Couldn't apply {} to the newly connected outputs: {:#}",
+                    effect,
+                    e
+                );
+            }
+        }
+    }
+}
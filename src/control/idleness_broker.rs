@@ -0,0 +1,252 @@
+//! Owner of the canonical idle/awake state of the user
+//!
+//! The [IdlenessBroker] merges the states reported by the display server and
+//! any other idleness sources, together with the activity reported by
+//! applications, and publishes the result to all its subscribers.
+//!
+//! Changes of the merged state can be debounced, configured by the
+//! `debounce` key of the `[idleness]` section, so that some input devices
+//! briefly waking the user up don't make the effectors execute and roll back
+//! over and over.
+
+use super::environment_controller::parse_duration;
+use crate::{
+    armaf::{Handle, HandleChild},
+    external::display_server::{AnyDisplayServerController, DisplayServerController, SystemState},
+};
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+
+/// Name of the configuration section of the idleness broker
+pub const IDLENESS_SECTION: &str = "idleness";
+
+/// Parse how long a change of the user's state has to last before it's
+/// published, zero if changes shouldn't be debounced
+pub fn parse_idleness_debounce(config: &toml::Value) -> Result<Duration> {
+    match config
+        .get(IDLENESS_SECTION)
+        .and_then(|section| section.get("debounce"))
+    {
+        None => Ok(Duration::ZERO),
+        Some(toml::Value::String(debounce)) => {
+            parse_duration(debounce).context("Invalid idleness.debounce")
+        }
+        Some(debounce) => Err(anyhow!("{} is not a duration string", debounce))
+            .context("Invalid idleness.debounce"),
+    }
+}
+
+/// Reports that the user has just used the computer to an [IdlenessBroker]
+#[derive(Clone)]
+pub struct ActivityReporter(mpsc::UnboundedSender<()>);
+
+impl ActivityReporter {
+    pub fn report(&self) -> Result<()> {
+        self.0
+            .send(())
+            .map_err(|_| anyhow!("the idleness broker has terminated"))
+    }
+}
+
+/// Merges the idleness sources into a single state.
+///
+/// The user becomes idle once all the sources report idleness and is awake
+/// as soon as any of them reports activity or an activity is
+/// [reported](ActivityReporter) directly.
+pub struct IdlenessBroker {
+    sources: Vec<(String, watch::Receiver<SystemState>)>,
+    display_controller: Option<AnyDisplayServerController>,
+    debounce: Duration,
+    state_sender: watch::Sender<SystemState>,
+    state_receiver: watch::Receiver<SystemState>,
+    activity_sender: mpsc::UnboundedSender<()>,
+    activity_receiver: mpsc::UnboundedReceiver<()>,
+    handle_child: Option<HandleChild>,
+}
+
+impl IdlenessBroker {
+    /// Create a broker whose only source is the display server's idleness
+    /// channel
+    pub fn new(display_server: watch::Receiver<SystemState>) -> IdlenessBroker {
+        let initial_state = *display_server.borrow();
+        let (state_sender, state_receiver) = watch::channel(initial_state);
+        let (activity_sender, activity_receiver) = mpsc::unbounded_channel();
+        IdlenessBroker {
+            sources: vec![("display server".to_owned(), display_server)],
+            display_controller: None,
+            debounce: Duration::ZERO,
+            state_sender,
+            state_receiver,
+            activity_sender,
+            activity_receiver,
+            handle_child: None,
+        }
+    }
+
+    /// Also take the states reported by the named source into account
+    pub fn with_source(
+        mut self,
+        name: &str,
+        source: watch::Receiver<SystemState>,
+    ) -> IdlenessBroker {
+        self.sources.push((name.to_owned(), source));
+        let state = merge_states(self.sources.iter().map(|(_, source)| *source.borrow()));
+        let _ = self.state_sender.send(state);
+        self
+    }
+
+    /// Force activity on the display server when an activity is reported, so
+    /// that its idleness timer restarts
+    pub fn with_display_controller(
+        mut self,
+        controller: AnyDisplayServerController,
+    ) -> IdlenessBroker {
+        self.display_controller = Some(controller);
+        self
+    }
+
+    /// Publish a change of the sources' merged state only once it has lasted
+    /// for the given duration. Activity [reported](ActivityReporter) directly
+    /// is published right away.
+    pub fn with_debounce(mut self, debounce: Duration) -> IdlenessBroker {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Get a channel which receives the merged idleness state
+    pub fn subscribe(&self) -> watch::Receiver<SystemState> {
+        self.state_receiver.clone()
+    }
+
+    /// Get a reporter of the user's activity, e.g. for the D-Bus
+    /// `SimulateUserActivity` method
+    pub fn activity_reporter(&self) -> ActivityReporter {
+        ActivityReporter(self.activity_sender.clone())
+    }
+
+    pub fn spawn(mut self) -> Handle {
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+        tokio::spawn(async move {
+            self.main_loop().await;
+            log::debug!("Terminated");
+        });
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        let (update_sender, mut updates) = mpsc::unbounded_channel();
+        let mut states = Vec::with_capacity(self.sources.len());
+        let mut forwarders = Vec::with_capacity(self.sources.len());
+        for (index, (name, source)) in self.sources.iter().enumerate() {
+            states.push(*source.borrow());
+            forwarders.push(forward_changes(
+                index,
+                name.clone(),
+                source.clone(),
+                update_sender.clone(),
+            ));
+        }
+        drop(update_sender);
+        // The sources may have changed since the broker has been created
+        self.publish(merge_states(states.iter().copied()));
+
+        // The state waiting to be published once the debounce period passes
+        let mut pending: Option<(SystemState, Instant)> = None;
+        loop {
+            let deadline = pending.map_or_else(Instant::now, |(_, deadline)| deadline);
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => break,
+                Some((index, state)) = updates.recv() => {
+                    states[index] = state;
+                    let merged = match state {
+                        SystemState::Awakened => SystemState::Awakened,
+                        SystemState::Idle => merge_states(states.iter().copied()),
+                    };
+                    pending = self.debounce(merged, pending);
+                }
+                _ = tokio::time::sleep_until(deadline), if pending.is_some() => {
+                    let (state, _) = pending.take().unwrap();
+                    self.publish(state);
+                }
+                Some(()) = self.activity_receiver.recv() => {
+                    pending = None;
+                    self.force_display_activity().await;
+                    self.publish(SystemState::Awakened);
+                }
+            }
+        }
+        for forwarder in forwarders {
+            forwarder.abort();
+        }
+    }
+
+    /// Publish the state right away if changes aren't debounced, otherwise
+    /// return the state which should be published after the debounce period
+    fn debounce(
+        &self,
+        state: SystemState,
+        pending: Option<(SystemState, Instant)>,
+    ) -> Option<(SystemState, Instant)> {
+        if self.debounce.is_zero() {
+            self.publish(state);
+            return None;
+        }
+        if *self.state_receiver.borrow() == state {
+            if let Some((pending_state, _)) = pending {
+                log::debug!("User has been {:?} only briefly, ignoring", pending_state);
+            }
+            return None;
+        }
+        match pending {
+            Some((pending_state, _)) if pending_state == state => pending,
+            _ => Some((state, Instant::now() + self.debounce)),
+        }
+    }
+
+    fn publish(&self, state: SystemState) {
+        if *self.state_receiver.borrow() != state {
+            log::debug!("User is now {:?}", state);
+            let _ = self.state_sender.send(state);
+        }
+    }
+
+    async fn force_display_activity(&self) {
+        if let Some(controller) = &self.display_controller {
+            if let Err(e) = controller.force_activity().await {
+                log::error!("Couldn't force activity on display server: {}", e);
+            }
+        }
+    }
+}
+
+/// The user is idle only if all the sources consider them idle
+fn merge_states(mut states: impl Iterator<Item = SystemState>) -> SystemState {
+    if states.all(|state| state == SystemState::Idle) {
+        SystemState::Idle
+    } else {
+        SystemState::Awakened
+    }
+}
+
+fn forward_changes(
+    index: usize,
+    name: String,
+    mut source: watch::Receiver<SystemState>,
+    updates: mpsc::UnboundedSender<(usize, SystemState)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while source.changed().await.is_ok() {
+            let state = *source.borrow_and_update();
+            if updates.send((index, state)).is_err() {
+                return;
+            }
+        }
+        log::warn!("Idleness source {} has been closed", name);
+    })
+}
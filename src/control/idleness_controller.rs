@@ -1,15 +1,28 @@
 //! Executes and rolls back bunches of effects
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
+use super::{
+    event_history::{EventHistory, HistoryEvent},
+    inhibitor_policy::{InhibitionMode, InhibitorPolicy},
+    manager_state::{publish_effect_event, EffectEventSender, EffectTransition, StateReporter},
+};
 use crate::{
-    armaf::{ActorPort, Effect, EffectorMessage, EffectorPort, RollbackStrategy, Server},
-    external::display_server::SystemState,
-    system::inhibition_sensor::GetInhibitions,
+    armaf::{
+        ActorPort, ActorRequestError, CancellationToken, Effect, EffectorMessage, EffectorPort,
+        EffectorResponse, RollbackStrategy, Server,
+    },
+    external::{display_server::SystemState, state_journal::StateJournal},
+    logging,
+    system::inhibition_sensor::{GetInhibitions, InhibitionSensorPort},
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use logind_zbus::manager::{InhibitType, Inhibitor, Mode};
 
+/// How long an effector may take to tell whether it can execute its effect,
+/// effects of effectors which don't answer in time are applied anyway
+const CAN_EXECUTE_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Contains the description of an effect and the port of the actor which needs
 /// to be messaged to execute or roll back the effect.
 #[derive(Debug, Clone)]
@@ -36,33 +49,127 @@ impl Action {
 #[derive(Debug, Clone)]
 pub struct ReconciliationBunches {
     pub execute: Option<Vec<Action>>,
-    pub rollback: Option<Vec<EffectorPort>>,
+    pub rollback: Option<Vec<Action>>,
     pub skip_effects: HashSet<String>,
+    /// Effects applied by the previous schedule which are never rolled back,
+    /// so they are skipped even after the next rollback, until the bunches are
+    /// replaced again
+    pub persistent_skip_effects: HashSet<String>,
 }
 
 impl ReconciliationBunches {
     pub fn new(
         execute: Option<Vec<Action>>,
-        rollback: Option<Vec<EffectorPort>>,
+        rollback: Option<Vec<Action>>,
         skip_effects: HashSet<String>,
     ) -> ReconciliationBunches {
         ReconciliationBunches {
             execute,
             rollback,
             skip_effects,
+            persistent_skip_effects: HashSet::new(),
         }
     }
+
+    /// Skip the given effects until the bunches are replaced, not only until
+    /// the next rollback
+    pub fn with_persistent_skip_effects(
+        mut self,
+        persistent_skip_effects: HashSet<String>,
+    ) -> ReconciliationBunches {
+        self.persistent_skip_effects = persistent_skip_effects;
+        self
+    }
+
+    fn skips(&self, effect_name: &str) -> bool {
+        self.skip_effects.contains(effect_name)
+            || self.persistent_skip_effects.contains(effect_name)
+    }
+}
+
+/// Bunches of a new schedule, replacing the ones of an [IdlenessController]
+/// without respawning it
+#[derive(Debug, Clone)]
+pub struct BunchReplacement {
+    pub action_bunches: Vec<Vec<Action>>,
+    pub starting_bunch: usize,
+    pub reconciliation_bunches: ReconciliationBunches,
+    pub inhibitor_policy: InhibitorPolicy,
+}
+
+/// A message handled by [IdlenessController]
+#[derive(Debug, Clone)]
+pub enum IdlenessMessage {
+    /// The user has become idle or active, execute the next bunch or roll
+    /// all the effects back. Answered with [None].
+    SystemState(SystemState),
+    /// Get an [IdlenessSnapshot] of the controller's state
+    GetSnapshot,
+    /// Continue with the bunches of another schedule, reconciling the
+    /// effects applied by the current one. Answered with [None].
+    ReplaceBunches(Box<BunchReplacement>),
+    /// Roll the effects back for a while without going back to the first
+    /// bunch, e.g. to show a notification. Answered with [None].
+    Wake,
+    /// Apply the effects rolled back by [IdlenessMessage::Wake] again.
+    /// Answered with [None].
+    EndWake,
+}
+
+impl From<SystemState> for IdlenessMessage {
+    fn from(state: SystemState) -> Self {
+        IdlenessMessage::SystemState(state)
+    }
+}
+
+/// The state of an [IdlenessController] at the time it was asked for it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdlenessSnapshot {
+    /// Number of bunches executed since the user was last active, the next
+    /// bunch to be executed has this index
+    pub executed_bunches: usize,
+    /// Effects which will be rolled back once the user becomes active, in the
+    /// order in which they were applied
+    pub rollback_stack: Vec<String>,
+    /// Effects left over by the previous schedule or inhibited in permissive
+    /// mode, which will be applied along with the next bunch
+    pub pending_execution: Vec<String>,
+    /// Effects left over by the previous schedule which will be rolled back
+    /// once the user becomes active
+    pub pending_rollback: Vec<String>,
+    /// Effects already applied by the previous schedule, which won't be
+    /// applied again until the next rollback or, if they are never rolled
+    /// back, until the bunches are replaced, sorted by name
+    pub skipped_effects: Vec<String>,
 }
 
+/// Port through which [IdlenessMessage]s are sent to an [IdlenessController].
+/// Only [IdlenessMessage::GetSnapshot] is answered with [Some] snapshot.
+pub type IdlenessPort = ActorPort<IdlenessMessage, Option<IdlenessSnapshot>, anyhow::Error>;
+
 /// IdlenessController waits for messages about user idleness and either
 /// gradually executes bunches of [Action]s or rolls all effects back
 pub struct IdlenessController {
     action_bunches: Vec<Vec<Action>>,
     current_bunch: usize,
-    rollback_stack: Vec<EffectorPort>,
+    rollback_stack: Vec<Action>,
+    /// Actions rolled back by [IdlenessMessage::Wake], in the order in which
+    /// they were applied
+    woken: Vec<Action>,
+    /// Effects whose rollback waits for the user, e.g. for the session to be
+    /// unlocked, as reported by their effectors. [IdlenessMessage::Wake]
+    /// leaves them applied.
+    waiting_rollbacks: HashSet<String>,
+    /// Inhibited actions waiting for the next bunch, in permissive mode
+    deferred: Vec<(Action, EffectorMessage)>,
 
-    inhibition_sensor: ActorPort<GetInhibitions, Vec<Inhibitor>, anyhow::Error>,
+    inhibition_sensor: InhibitionSensorPort,
+    inhibitor_policy: InhibitorPolicy,
     reconciliation_bunches: ReconciliationBunches,
+    state_reporter: Option<StateReporter>,
+    effect_events: Option<EffectEventSender>,
+    event_history: Option<EventHistory>,
+    state_journal: StateJournal,
 }
 
 impl IdlenessController {
@@ -71,66 +178,193 @@ impl IdlenessController {
         action_bunches: Vec<Vec<Action>>,
         initial_bunch: usize,
         reconciliation_bunches: ReconciliationBunches,
-        inhibition_sensor: ActorPort<GetInhibitions, Vec<Inhibitor>, anyhow::Error>,
+        inhibition_sensor: InhibitionSensorPort,
     ) -> IdlenessController {
         IdlenessController {
             action_bunches,
             current_bunch: initial_bunch,
             inhibition_sensor,
+            inhibitor_policy: InhibitorPolicy::default(),
             reconciliation_bunches,
             rollback_stack: Vec::new(),
+            woken: Vec::new(),
+            waiting_rollbacks: HashSet::new(),
+            deferred: Vec::new(),
+            state_reporter: None,
+            effect_events: None,
+            event_history: None,
+            state_journal: StateJournal::in_memory(),
+        }
+    }
+
+    /// Report the current bunch and the applied effects to the given reporter
+    pub fn with_state_reporter(mut self, state_reporter: StateReporter) -> IdlenessController {
+        self.state_reporter = Some(state_reporter);
+        self
+    }
+
+    /// Publish each successful execution and rollback of an effect on the
+    /// given channel
+    pub fn with_effect_events(mut self, effect_events: EffectEventSender) -> IdlenessController {
+        self.effect_events = Some(effect_events);
+        self
+    }
+
+    /// Record the inhibitors keeping effects from being applied in the given
+    /// history
+    pub fn with_event_history(mut self, event_history: EventHistory) -> IdlenessController {
+        self.event_history = Some(event_history);
+        self
+    }
+
+    /// Record the effects waiting for a rollback in the given journal
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> IdlenessController {
+        self.state_journal = state_journal;
+        self
+    }
+
+    /// Ignore the block inhibitors overridden by the given policy
+    pub fn with_inhibitor_policy(
+        mut self,
+        inhibitor_policy: InhibitorPolicy,
+    ) -> IdlenessController {
+        self.inhibitor_policy = inhibitor_policy;
+        self
+    }
+
+    fn report_state(&self, newly_applied: Vec<String>, rolled_back: bool) {
+        if let Some(reporter) = self.state_reporter.as_ref() {
+            reporter.update(|state| {
+                state.current_bunch = self.current_bunch;
+                if rolled_back {
+                    state.applied_effects.clear();
+                }
+                state.applied_effects.extend(newly_applied);
+            });
         }
+        let applied_effects = self
+            .rollback_stack
+            .iter()
+            .chain(self.reconciliation_bunches.rollback.iter().flatten())
+            .map(|action| action.effect.name.clone())
+            .collect();
+        self.state_journal
+            .update(|state| state.applied_effects = applied_effects);
     }
 
-    async fn handle_idleness(&mut self) -> Result<()> {
+    async fn handle_idleness(&mut self, cancellation: &CancellationToken) -> Result<()> {
         if self.current_bunch == self.action_bunches.len() {
             return Err(anyhow!("No more action bunches to execute."));
         }
-        if self.current_bunch_inhibited().await {
+        // The previous controller may have already applied some of the
+        // reconciliation effects, so they only need to converge to the
+        // applied state
+        let upcoming: Vec<(Action, EffectorMessage)> = self
+            .deferred
+            .iter()
+            .cloned()
+            .chain(
+                self.reconciliation_bunches
+                    .execute
+                    .iter()
+                    .flatten()
+                    .map(|action| (action.clone(), EffectorMessage::EnsureApplied)),
+            )
+            .chain(
+                self.action_bunches[self.current_bunch]
+                    .iter()
+                    .map(|action| (action.clone(), EffectorMessage::Execute)),
+            )
+            .collect();
+        let inhibited = self.find_inhibited_effects(&upcoming).await;
+        let bunch_inhibited = match self.inhibitor_policy.mode {
+            InhibitionMode::Strict => !inhibited.is_empty(),
+            // Nothing would be applied, so the bunch is retried as a whole
+            InhibitionMode::Permissive => {
+                !inhibited.is_empty()
+                    && upcoming
+                        .iter()
+                        .all(|(action, _)| inhibited.contains(&action.effect.name))
+            }
+        };
+        if bunch_inhibited {
             return Err(anyhow!("Upcoming bunch is inhibited"));
         }
+        self.reconciliation_bunches.execute = None;
+        self.deferred.clear();
+        let unavailable = self.find_unavailable_effects(&upcoming, &inhibited).await;
 
-        let reconciliation = self
-            .reconciliation_bunches
-            .execute
-            .take()
-            .unwrap_or_default();
-        let action_iter = reconciliation
-            .iter()
-            .chain(self.action_bunches[self.current_bunch].iter());
-
-        let mut immediate_rollback_ports: Vec<EffectorPort> = Vec::new();
+        let mut immediate_rollback_actions: Vec<Action> = Vec::new();
+        let mut applied_effects = Vec::new();
 
-        for action in action_iter {
-            if self
-                .reconciliation_bunches
-                .skip_effects
-                .contains(&action.effect.name)
-            {
-                log::debug!("Skipping {} until the next rollback", action.effect.name);
+        for (action, message) in upcoming {
+            if cancellation.is_cancelled() {
+                log::info!("User became active, not applying the rest of the bunch");
+                break;
+            }
+            if inhibited.contains(&action.effect.name) {
+                log::info!(
+                    "Deferring inhibited effect {} to the next bunch",
+                    action.effect.name
+                );
+                self.deferred.push((action, message));
                 continue;
             }
-            log::debug!("Applying effect {}", action.effect.name);
-            if let Err(e) = action
-                .recipient
-                .request_with_timeout(std::time::Duration::from_secs(2), EffectorMessage::Execute)
-                .await
-            {
-                log::error!("Failed to apply effect {}: {:?}", action.effect.name, e);
+            if self.reconciliation_bunches.skips(&action.effect.name) {
+                log::debug!("Skipping {}, it's already applied", action.effect.name);
+                continue;
+            }
+            if unavailable.contains(&action.effect.name) {
+                continue;
+            }
+            let applied = logging::effect_scope(&action.effect.name, async {
+                log::debug!("Applying effect {}", action.effect.name);
+                match action
+                    .recipient
+                    .request_with_timeout(std::time::Duration::from_secs(2), message)
+                    .await
+                {
+                    Ok(response) => {
+                        log_response(&action.effect.name, &response);
+                        Some(response.capabilities.rollback_waits)
+                    }
+                    Err(e) => {
+                        log::error!("Failed to apply effect {}: {:?}", action.effect.name, e);
+                        None
+                    }
+                }
+            })
+            .await;
+            let applied = match applied {
+                Some(rollback_waits) => {
+                    self.record_rollback_waits(&action.effect.name, rollback_waits);
+                    true
+                }
+                None => false,
+            };
+            let transition = if applied {
+                EffectTransition::Applied
+            } else {
+                EffectTransition::ApplicationFailed
+            };
+            publish_effect_event(self.effect_events.as_ref(), &action.effect.name, transition);
+            if !applied {
                 continue;
             }
             match action.effect.rollback_strategy {
-                RollbackStrategy::OnActivity => self.rollback_stack.push(action.recipient.clone()),
-                RollbackStrategy::Immediate => {
-                    immediate_rollback_ports.push(action.recipient.clone())
+                RollbackStrategy::OnActivity => {
+                    self.rollback_stack.push(action.clone());
+                    applied_effects.push(action.effect.name.clone());
                 }
+                RollbackStrategy::Immediate => immediate_rollback_actions.push(action.clone()),
                 RollbackStrategy::None => {}
             }
         }
 
-        rollback_all(&mut immediate_rollback_ports).await;
+        rollback_all(&mut immediate_rollback_actions, self.effect_events.as_ref()).await;
 
         self.current_bunch += 1;
+        self.report_state(applied_effects, false);
         Ok(())
     }
 
@@ -149,73 +383,280 @@ impl IdlenessController {
         // Delay inhibitors are handled automatically by systemd
         inhibitors
             .into_iter()
-            .filter(|i| i.mode() == Mode::Block)
+            .filter(|i| i.inhibitor.mode() == Mode::Block)
+            .filter(|i| {
+                let overridden = self.inhibitor_policy.is_overridden(i);
+                if overridden {
+                    log::info!(
+                        "Ignoring inhibitor of {} ({}) held for {:?}",
+                        i.inhibitor.who(),
+                        i.inhibitor.why(),
+                        i.age
+                    );
+                }
+                !overridden
+            })
+            .map(|i| i.inhibitor)
             .collect()
     }
 
-    async fn current_bunch_inhibited(&mut self) -> bool {
+    /// Find the names of the upcoming effects inhibited by a block inhibitor
+    async fn find_inhibited_effects(
+        &mut self,
+        upcoming: &[(Action, EffectorMessage)],
+    ) -> HashSet<String> {
         let inhibitors = self.get_inhibitors().await;
-        let upcoming_inhibition_types: Vec<InhibitType> = dedup_inhibit_types(
-            &self.action_bunches[self.current_bunch]
+        let mut inhibited = HashSet::new();
+        for (action, _) in upcoming {
+            for t in dedup_inhibit_types(&action.effect.inhibited_by) {
+                for i in find_inhibitors_with_type(&inhibitors, t) {
+                    inhibited.insert(action.effect.name.clone());
+                    log::info!(
+                        "Effect {} is inhibited, {:?} inhibited by {} with reason {}",
+                        action.effect.name,
+                        t,
+                        i.who(),
+                        i.why(),
+                    );
+                    if let Some(history) = self.event_history.as_ref() {
+                        history.record(HistoryEvent::InhibitedBy {
+                            effect: action.effect.name.clone(),
+                            who: i.who().to_owned(),
+                            why: i.why().to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+        inhibited
+    }
+
+    /// Ask the effectors of the upcoming effects which aren't inhibited
+    /// whether they can execute them, returning the names of the effects
+    /// which can't be executed
+    async fn find_unavailable_effects(
+        &self,
+        upcoming: &[(Action, EffectorMessage)],
+        inhibited: &HashSet<String>,
+    ) -> HashSet<String> {
+        let mut unavailable = HashSet::new();
+        for (action, _) in upcoming {
+            if inhibited.contains(&action.effect.name) {
+                continue;
+            }
+            let reason = match action
+                .recipient
+                .request_with_timeout(CAN_EXECUTE_TIMEOUT, EffectorMessage::CanExecute)
+                .await
+            {
+                Err(ActorRequestError::Actor(e)) => format!("{:#}", e),
+                // Failures to reach the effector show up when executing
+                _ => continue,
+            };
+            log::warn!(
+                "Skipping effect {}, it can't be executed: {}",
+                action.effect.name,
+                reason
+            );
+            if let Some(history) = self.event_history.as_ref() {
+                history.record(HistoryEvent::EffectUnavailable {
+                    effect: action.effect.name.clone(),
+                    reason,
+                });
+            }
+            unavailable.insert(action.effect.name.clone());
+        }
+        unavailable
+    }
+
+    fn snapshot(&self) -> IdlenessSnapshot {
+        let names = |actions: &Option<Vec<Action>>| {
+            actions
+                .iter()
+                .flatten()
+                .map(|action| action.effect.name.clone())
+                .collect()
+        };
+        let mut skipped_effects: Vec<String> = self
+            .reconciliation_bunches
+            .skip_effects
+            .union(&self.reconciliation_bunches.persistent_skip_effects)
+            .cloned()
+            .collect();
+        skipped_effects.sort();
+        IdlenessSnapshot {
+            executed_bunches: self.current_bunch,
+            rollback_stack: self
+                .rollback_stack
+                .iter()
+                .map(|action| action.effect.name.clone())
+                .collect(),
+            pending_execution: self
+                .deferred
                 .iter()
+                .map(|(action, _)| action.effect.name.clone())
                 .chain(
                     self.reconciliation_bunches
                         .execute
-                        .as_ref()
-                        .unwrap_or(&Vec::new())
-                        .iter(),
+                        .iter()
+                        .flatten()
+                        .map(|action| action.effect.name.clone()),
                 )
-                .flat_map(|e| e.effect.inhibited_by.clone())
                 .collect(),
-        );
-
-        let mut is_inhibited = false;
+            pending_rollback: names(&self.reconciliation_bunches.rollback),
+            skipped_effects,
+        }
+    }
 
-        for t in upcoming_inhibition_types {
-            for i in find_inhibitors_with_type(&inhibitors, t) {
-                is_inhibited = true;
-                log::info!(
-                    "Not moving to next idleness level, {:?} inhibited by {} with reason {}",
-                    t,
-                    i.who(),
-                    i.why(),
-                );
-            }
+    /// Roll back the effects left over by the previous schedule if the
+    /// controller starts at its beginning, and report the starting state
+    async fn start(&mut self) {
+        let rolled_back = self.current_bunch == 0 && self.reconciliation_bunches.rollback.is_some();
+        if rolled_back {
+            ensure_rolled_back(
+                &mut self.reconciliation_bunches.rollback.take().unwrap(),
+                self.effect_events.as_ref(),
+            )
+            .await;
         }
-        is_inhibited
+        self.report_state(Vec::new(), rolled_back);
+    }
+
+    async fn replace_bunches(&mut self, replacement: BunchReplacement) {
+        log::info!(
+            "Replacing the action bunches, continuing from bunch {}",
+            replacement.starting_bunch
+        );
+        self.action_bunches = replacement.action_bunches;
+        self.current_bunch = replacement.starting_bunch;
+        self.reconciliation_bunches = replacement.reconciliation_bunches;
+        self.inhibitor_policy = replacement.inhibitor_policy;
+        // The reconciliation bunches take over the effects applied so far
+        self.rollback_stack.clear();
+        self.woken.clear();
+        self.deferred.clear();
+        self.start().await;
     }
 
     async fn handle_wakeup(&mut self) -> Result<()> {
         log::info!("System awakened, rolling back all effects");
         self.reconciliation_bunches.skip_effects.clear();
+        self.deferred.clear();
+        // The effects rolled back by a wake stay rolled back
+        self.woken.clear();
         if let Some(mut reconciliation) = self.reconciliation_bunches.rollback.take() {
-            rollback_all(&mut reconciliation).await;
+            ensure_rolled_back(&mut reconciliation, self.effect_events.as_ref()).await;
         }
-        rollback_all(&mut self.rollback_stack).await;
+        rollback_all(&mut self.rollback_stack, self.effect_events.as_ref()).await;
         self.current_bunch = 0;
+        self.report_state(Vec::new(), true);
         Ok(())
     }
+
+    fn record_rollback_waits(&mut self, effect_name: &str, rollback_waits: bool) {
+        if rollback_waits {
+            self.waiting_rollbacks.insert(effect_name.to_owned());
+        } else {
+            self.waiting_rollbacks.remove(effect_name);
+        }
+    }
+
+    async fn handle_wake(&mut self) {
+        if !self.woken.is_empty() {
+            log::debug!("Already woken, nothing to roll back");
+            return;
+        }
+        log::info!("Waking temporarily, rolling back effects");
+        // Rolling back e.g. the lock would block until the user unlocks the
+        // session, so such effects stay applied
+        let (kept, mut woken): (Vec<Action>, Vec<Action>) =
+            std::mem::take(&mut self.rollback_stack)
+                .into_iter()
+                .partition(|action| self.waiting_rollbacks.contains(&action.effect.name));
+        self.rollback_stack = kept;
+        self.woken = woken.clone();
+        rollback_all(&mut woken, self.effect_events.as_ref()).await;
+        let still_applied = self
+            .rollback_stack
+            .iter()
+            .map(|action| action.effect.name.clone())
+            .collect();
+        self.report_state(still_applied, true);
+    }
+
+    async fn handle_wake_end(&mut self) {
+        log::info!("Temporary wake ended, applying the effects again");
+        let mut applied_effects = Vec::new();
+        for action in std::mem::take(&mut self.woken) {
+            let applied = logging::effect_scope(&action.effect.name, async {
+                match action
+                    .recipient
+                    .request(EffectorMessage::EnsureApplied)
+                    .await
+                {
+                    Ok(response) => {
+                        log_response(&action.effect.name, &response);
+                        true
+                    }
+                    Err(e) => {
+                        log::error!("Failed to apply effect {}: {:?}", action.effect.name, e);
+                        false
+                    }
+                }
+            })
+            .await;
+            let transition = if applied {
+                EffectTransition::Applied
+            } else {
+                EffectTransition::ApplicationFailed
+            };
+            publish_effect_event(self.effect_events.as_ref(), &action.effect.name, transition);
+            if applied {
+                applied_effects.push(action.effect.name.clone());
+                self.rollback_stack.push(action);
+            }
+        }
+        self.report_state(applied_effects, false);
+    }
 }
 
 #[async_trait]
-impl Server<SystemState, ()> for IdlenessController {
+impl Server<IdlenessMessage, Option<IdlenessSnapshot>> for IdlenessController {
     fn get_name(&self) -> String {
         "IdlenessController".to_owned()
     }
 
     async fn initialize(&mut self) -> Result<()> {
-        if self.current_bunch == 0 && self.reconciliation_bunches.rollback.is_some() {
-            rollback_all(&mut self.reconciliation_bunches.rollback.take().unwrap()).await;
-        }
+        self.start().await;
         Ok(())
     }
 
-    async fn handle_message(&mut self, system_state: SystemState) -> Result<()> {
-        match system_state {
-            SystemState::Awakened => self.handle_wakeup().await?,
-            SystemState::Idle => self.handle_idleness().await?,
+    async fn handle_message(
+        &mut self,
+        message: IdlenessMessage,
+    ) -> Result<Option<IdlenessSnapshot>> {
+        self.handle_cancellable_message(message, CancellationToken::never())
+            .await
+    }
+
+    async fn handle_cancellable_message(
+        &mut self,
+        message: IdlenessMessage,
+        cancellation: CancellationToken,
+    ) -> Result<Option<IdlenessSnapshot>> {
+        match message {
+            IdlenessMessage::SystemState(SystemState::Awakened) => self.handle_wakeup().await?,
+            IdlenessMessage::SystemState(SystemState::Idle) => {
+                self.handle_idleness(&cancellation).await?
+            }
+            IdlenessMessage::GetSnapshot => return Ok(Some(self.snapshot())),
+            IdlenessMessage::ReplaceBunches(replacement) => {
+                self.replace_bunches(*replacement).await
+            }
+            IdlenessMessage::Wake => self.handle_wake().await,
+            IdlenessMessage::EndWake => self.handle_wake_end().await,
         }
-        Ok(())
+        Ok(None)
     }
 }
 
@@ -242,10 +683,61 @@ fn dedup_inhibit_types(duplicated: &Vec<InhibitType>) -> Vec<InhibitType> {
     deduped
 }
 
-async fn rollback_all(rollback_vec: &mut Vec<EffectorPort>) {
-    while let Some(port) = rollback_vec.pop() {
-        if let Err(e) = port.request(EffectorMessage::Rollback).await {
-            log::error!("Error on rollback: {:?}", e);
-        }
+fn log_response(effect_name: &str, response: &EffectorResponse) {
+    log::debug!(
+        "Effector of {} has {} effects applied{}",
+        effect_name,
+        response.applied_effects,
+        response
+            .status
+            .as_ref()
+            .map(|status| format!(", {}", status))
+            .unwrap_or_default()
+    );
+}
+
+/// Roll back the effects of the given actions, starting with the last one, and
+/// publish each successful rollback on the given channel
+pub async fn rollback_all(
+    rollback_vec: &mut Vec<Action>,
+    effect_events: Option<&EffectEventSender>,
+) {
+    send_rollbacks(rollback_vec, EffectorMessage::Rollback, effect_events).await
+}
+
+/// Like [rollback_all], but doesn't fail for effects which aren't applied
+/// anymore, e.g. the effects left over by a previous controller
+async fn ensure_rolled_back(
+    rollback_vec: &mut Vec<Action>,
+    effect_events: Option<&EffectEventSender>,
+) {
+    send_rollbacks(
+        rollback_vec,
+        EffectorMessage::EnsureRolledBack,
+        effect_events,
+    )
+    .await
+}
+
+async fn send_rollbacks(
+    rollback_vec: &mut Vec<Action>,
+    message: EffectorMessage,
+    effect_events: Option<&EffectEventSender>,
+) {
+    while let Some(action) = rollback_vec.pop() {
+        let transition = logging::effect_scope(&action.effect.name, async {
+            match action.recipient.request(message).await {
+                Ok(response) => {
+                    log_response(&action.effect.name, &response);
+                    EffectTransition::RolledBack
+                }
+                Err(e) => {
+                    log::error!("Error on rollback of {}: {:?}", action.effect.name, e);
+                    EffectTransition::RollbackFailed
+                }
+            }
+        })
+        .await;
+        publish_effect_event(effect_events, &action.effect.name, transition);
     }
 }
@@ -0,0 +1,218 @@
+//! Rules for ignoring block inhibitors which applications forget to release
+//! and for applying the effects they don't inhibit, configured in the
+//! `[inhibitors]` configuration section
+
+use super::environment_controller::parse_duration;
+use crate::system::inhibition_sensor::ActiveInhibitor;
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+
+/// Name of the configuration section containing the inhibitor policy
+pub const INHIBITORS_SECTION: &str = "inhibitors";
+
+/// Ignore the inhibitors of an application once they are older than `after`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InhibitorOverride {
+    /// Name of the application, compared case-insensitively with the `who`
+    /// of the inhibitor
+    pub who: String,
+    pub after: Duration,
+}
+
+/// How an inhibited effect affects the rest of its bunch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InhibitionMode {
+    /// The whole bunch waits until none of its effects is inhibited
+    Strict,
+    /// The effects which aren't inhibited are applied, the inhibited ones are
+    /// retried with the following bunches
+    Permissive,
+}
+
+impl Default for InhibitionMode {
+    fn default() -> Self {
+        InhibitionMode::Strict
+    }
+}
+
+/// Decides which block inhibitors are ignored and how the respected ones
+/// are applied. By default, all of them are respected and block whole
+/// bunches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InhibitorPolicy {
+    pub mode: InhibitionMode,
+    /// Maximum age of any inhibitor
+    pub max_age: Option<Duration>,
+    pub overrides: Vec<InhibitorOverride>,
+}
+
+impl InhibitorPolicy {
+    /// Whether the inhibitor should be ignored
+    pub fn is_overridden(&self, inhibitor: &ActiveInhibitor) -> bool {
+        if matches!(self.max_age, Some(max_age) if inhibitor.age >= max_age) {
+            return true;
+        }
+        let who = inhibitor.inhibitor.who();
+        self.overrides
+            .iter()
+            .any(|o| o.who.eq_ignore_ascii_case(who) && inhibitor.age >= o.after)
+    }
+}
+
+/// Parse the inhibitor policy, which respects all inhibitors if the section
+/// is missing
+pub fn parse_inhibitor_policy(config: &toml::Value) -> Result<InhibitorPolicy> {
+    let section = match config.get(INHIBITORS_SECTION) {
+        Some(section) => section,
+        None => return Ok(InhibitorPolicy::default()),
+    };
+    let mode = match section.get("mode").map(|mode| mode.as_str()) {
+        None => InhibitionMode::Strict,
+        Some(Some("strict")) => InhibitionMode::Strict,
+        Some(Some("permissive")) => InhibitionMode::Permissive,
+        Some(_) => {
+            return Err(anyhow!("must be either \"strict\" or \"permissive\""))
+                .context("Invalid inhibitors.mode")
+        }
+    };
+    let max_age = section
+        .get("max_age")
+        .map(parse_duration_value)
+        .transpose()
+        .context("Invalid inhibitors.max_age")?;
+    let overrides = match section.get("overrides") {
+        Some(overrides) => overrides
+            .as_array()
+            .ok_or_else(|| anyhow!("must be an array of tables"))
+            .and_then(|overrides| overrides.iter().map(parse_override).collect())
+            .context("Invalid inhibitors.overrides")?,
+        None => Vec::new(),
+    };
+    Ok(InhibitorPolicy {
+        mode,
+        max_age,
+        overrides,
+    })
+}
+
+fn parse_override(value: &toml::Value) -> Result<InhibitorOverride> {
+    let who = value
+        .get("who")
+        .and_then(|who| who.as_str())
+        .ok_or_else(|| anyhow!("{} doesn't contain an application name in who", value))?;
+    let after = value
+        .get("after")
+        .map(parse_duration_value)
+        .transpose()?
+        .unwrap_or(Duration::ZERO);
+    Ok(InhibitorOverride {
+        who: who.to_owned(),
+        after,
+    })
+}
+
+fn parse_duration_value(value: &toml::Value) -> Result<Duration> {
+    match value {
+        toml::Value::String(s) => parse_duration(s),
+        toml::Value::Integer(i) if *i >= 0 => Ok(Duration::from_secs(*i as u64)),
+        _ => Err(anyhow!("{} is not a valid duration", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use logind_zbus::manager::{InhibitType, InhibitTypes, Inhibitor, Mode};
+
+    fn inhibitor(who: &str, age: Duration) -> ActiveInhibitor {
+        ActiveInhibitor {
+            inhibitor: Inhibitor::new(
+                InhibitTypes::new(&vec![InhibitType::Idle]),
+                who.to_owned(),
+                "Playing video".to_owned(),
+                Mode::Block,
+                1000,
+                1234,
+            ),
+            age,
+        }
+    }
+
+    #[test]
+    fn test_parse_inhibitor_policy() {
+        assert_eq!(
+            parse_inhibitor_policy(&toml::toml! { [lock] command = "i3lock" }).unwrap(),
+            InhibitorPolicy::default()
+        );
+        let config = toml::toml! {
+            [inhibitors]
+            max_age = "4h"
+            overrides = [{ who = "Chromium", after = "2h" }, { who = "Steam" }]
+        };
+        let policy = parse_inhibitor_policy(&config).unwrap();
+        assert_eq!(policy.mode, InhibitionMode::Strict);
+        assert_eq!(policy.max_age, Some(Duration::from_secs(4 * 3600)));
+        assert_eq!(
+            policy.overrides,
+            vec![
+                InhibitorOverride {
+                    who: "Chromium".to_owned(),
+                    after: Duration::from_secs(2 * 3600),
+                },
+                InhibitorOverride {
+                    who: "Steam".to_owned(),
+                    after: Duration::ZERO,
+                },
+            ]
+        );
+        assert_eq!(
+            parse_inhibitor_policy(&toml::toml! {
+                [inhibitors]
+                mode = "permissive"
+            })
+            .unwrap()
+            .mode,
+            InhibitionMode::Permissive
+        );
+        assert!(parse_inhibitor_policy(&toml::toml! {
+            [inhibitors]
+            mode = "lenient"
+        })
+        .is_err());
+        assert!(parse_inhibitor_policy(&toml::toml! {
+            [inhibitors]
+            max_age = "forever"
+        })
+        .is_err());
+        assert!(parse_inhibitor_policy(&toml::toml! {
+            [inhibitors]
+            overrides = [{ after = "2h" }]
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_is_overridden() {
+        let hour = Duration::from_secs(3600);
+        let policy = InhibitorPolicy {
+            mode: InhibitionMode::Strict,
+            max_age: Some(4 * hour),
+            overrides: vec![
+                InhibitorOverride {
+                    who: "Chromium".to_owned(),
+                    after: 2 * hour,
+                },
+                InhibitorOverride {
+                    who: "Steam".to_owned(),
+                    after: Duration::ZERO,
+                },
+            ],
+        };
+        assert!(!policy.is_overridden(&inhibitor("chromium", hour)));
+        assert!(policy.is_overridden(&inhibitor("chromium", 2 * hour)));
+        assert!(policy.is_overridden(&inhibitor("Steam", Duration::ZERO)));
+        assert!(!policy.is_overridden(&inhibitor("mpv", 3 * hour)));
+        assert!(policy.is_overridden(&inhibitor("mpv", 4 * hour)));
+        assert!(!InhibitorPolicy::default().is_overridden(&inhibitor("mpv", 100 * hour)));
+    }
+}
@@ -0,0 +1,176 @@
+//! Keeps the display server active while idleness is inhibited, so that
+//! other screensavers or the compositor's own blanker don't turn the screen
+//! off behind Energia's back, configured in the `[keepalive]` section
+
+use super::{environment_controller::parse_duration, inhibitor_policy::InhibitorPolicy};
+use crate::{
+    armaf::{self, TickService, Ticks},
+    control::manager_state::ManagerState,
+    external::display_server::DisplayServerController,
+    system::inhibition_sensor::{GetInhibitions, InhibitionSensorPort},
+};
+use anyhow::{anyhow, Context, Result};
+use logind_zbus::manager::{InhibitType, Mode};
+use std::time::Duration;
+use tokio::{sync::watch, time::Instant};
+
+/// Name of the configuration section enabling the keepalive
+pub const KEEPALIVE_SECTION: &str = "keepalive";
+
+/// Parse how often the activity should be simulated, [None] if the keepalive
+/// isn't enabled
+pub fn parse_keepalive_interval(config: &toml::Value) -> Result<Option<Duration>> {
+    let section = match config.get(KEEPALIVE_SECTION) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+    let interval = section
+        .get("interval")
+        .ok_or_else(|| anyhow!("interval is missing"))
+        .and_then(|interval| {
+            interval
+                .as_str()
+                .ok_or_else(|| anyhow!("{} is not a duration string", interval))
+        })
+        .and_then(parse_duration)
+        .context("Invalid keepalive.interval")?;
+    if interval.is_zero() {
+        return Err(anyhow!("must be longer than 0")).context("Invalid keepalive.interval");
+    }
+    Ok(Some(interval))
+}
+
+/// Periodically forces the display server to be active while the user has
+/// paused or held the schedule, or an application inhibits idleness
+pub struct Keepalive<C: DisplayServerController> {
+    ds_controller: C,
+    ticks: Ticks,
+    state: watch::Receiver<ManagerState>,
+    inhibition_sensor: InhibitionSensorPort,
+    inhibitor_policy: InhibitorPolicy,
+    handle_child: Option<armaf::HandleChild>,
+}
+
+impl<C: DisplayServerController> Keepalive<C> {
+    pub fn new(
+        ds_controller: C,
+        tick_service: &TickService,
+        interval: Duration,
+        state: watch::Receiver<ManagerState>,
+        inhibition_sensor: InhibitionSensorPort,
+    ) -> Keepalive<C> {
+        Keepalive {
+            ds_controller,
+            ticks: tick_service.subscribe(interval),
+            state,
+            inhibition_sensor,
+            inhibitor_policy: InhibitorPolicy::default(),
+            handle_child: None,
+        }
+    }
+
+    /// Don't keep the display server active because of the inhibitors which
+    /// the policy ignores
+    pub fn with_inhibitor_policy(mut self, inhibitor_policy: InhibitorPolicy) -> Keepalive<C> {
+        self.inhibitor_policy = inhibitor_policy;
+        self
+    }
+
+    pub fn spawn(mut self) -> armaf::Handle {
+        let (handle, handle_child) = armaf::Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => {
+                    return;
+                }
+                _ = self.ticks.tick() => {
+                    if let Some(reason) = self.inhibition_reason().await {
+                        log::debug!("Keeping the display server active, {}", reason);
+                        self.force_activity().await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Describe why idleness is inhibited, [None] if it isn't
+    async fn inhibition_reason(&self) -> Option<String> {
+        {
+            let state = self.state.borrow();
+            if state.paused {
+                return Some("schedule is paused".to_owned());
+            }
+            if matches!(state.held_until, Some(until) if until > Instant::now()) {
+                return Some("schedule is held".to_owned());
+            }
+        }
+        let inhibitors = match self.inhibition_sensor.request(GetInhibitions).await {
+            Ok(inhibitors) => inhibitors,
+            Err(e) => {
+                log::error!(
+                    "Couldn't get inhibitions, not keeping the display server active: {:?}",
+                    e
+                );
+                return None;
+            }
+        };
+        inhibitors
+            .into_iter()
+            .filter(|i| i.inhibitor.mode() == Mode::Block)
+            .filter(|i| i.inhibitor.what().types().contains(&InhibitType::Idle))
+            .find(|i| !self.inhibitor_policy.is_overridden(i))
+            .map(|i| format!("idleness is inhibited by {}", i.inhibitor.who()))
+    }
+
+    async fn force_activity(&self) {
+        if let Err(e) = self.ds_controller.force_activity().await {
+            log::error!("Couldn't force activity on display server: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_keepalive_interval() {
+        assert_eq!(
+            parse_keepalive_interval(&toml::toml! { [lock] command = "i3lock" }).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_keepalive_interval(&toml::toml! {
+                [keepalive]
+                interval = "30s"
+            })
+            .unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert!(parse_keepalive_interval(&toml::toml! {
+            [keepalive]
+            interval = "0s"
+        })
+        .is_err());
+        assert!(parse_keepalive_interval(&toml::toml! {
+            [keepalive]
+            interval = 30
+        })
+        .is_err());
+        assert!(parse_keepalive_interval(&toml::toml! {
+            [keepalive]
+            interval_secs = 30
+        })
+        .is_err());
+    }
+}
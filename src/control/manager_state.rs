@@ -0,0 +1,118 @@
+//! Observable state of the power management, shared by the controllers which
+//! change it and published to anyone interested, e.g. the D-Bus API
+
+use super::environment_controller::ScheduleType;
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+
+/// Snapshot of what the power management is currently doing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManagerState {
+    /// The schedule type selected according to the power source, [None]
+    /// before the first schedule is started
+    pub schedule_type: Option<ScheduleType>,
+    /// Index of the next effect bunch to be applied, 0 while the user is active
+    pub current_bunch: usize,
+    /// Names of the effects which are applied and will be rolled back on
+    /// user activity
+    pub applied_effects: Vec<String>,
+    /// When the next effect bunch is going to be applied, [None] if it
+    /// depends on the user becoming idle or there are no more bunches
+    pub next_effect_at: Option<Instant>,
+    /// Whether processing of the schedule is paused
+    pub paused: bool,
+    /// Since when the user is idle, as counted by the running time of the
+    /// sequencer, [None] while they are active
+    pub idle_since: Option<Instant>,
+    /// When the first effect bunch was applied, [None] while the user is
+    /// active
+    pub effects_since: Option<Instant>,
+    /// Until when the schedule is held at its current position, [None] if it
+    /// isn't held
+    pub held_until: Option<Instant>,
+}
+
+/// Updates the [ManagerState] and notifies the receivers about the changes.
+///
+/// Cloned reporters update the same state, so each actor can get its own
+/// reporter.
+#[derive(Debug, Clone)]
+pub struct StateReporter {
+    state: Arc<Mutex<ManagerState>>,
+    sender: Arc<watch::Sender<ManagerState>>,
+}
+
+impl StateReporter {
+    /// Create a new reporter and a receiver of the state it reports
+    pub fn new() -> (StateReporter, watch::Receiver<ManagerState>) {
+        let (sender, receiver) = watch::channel(ManagerState::default());
+        let reporter = StateReporter {
+            state: Arc::new(Mutex::new(ManagerState::default())),
+            sender: Arc::new(sender),
+        };
+        (reporter, receiver)
+    }
+
+    /// Modify the state and, if it has changed, notify the receivers
+    pub fn update(&self, modify: impl FnOnce(&mut ManagerState)) {
+        let mut state = self.state.lock().unwrap();
+        let previous = state.clone();
+        modify(&mut state);
+        if *state != previous {
+            // Nobody may be interested in the state, that's fine
+            let _ = self.sender.send(state.clone());
+        }
+    }
+
+    /// Get a copy of the current state
+    pub fn get(&self) -> ManagerState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// What has happened to an effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectTransition {
+    /// The effect has been applied
+    Applied,
+    /// The effect has been rolled back
+    RolledBack,
+    /// The effect couldn't be applied
+    ApplicationFailed,
+    /// The effect couldn't be rolled back
+    RollbackFailed,
+}
+
+/// Notification about an execution or rollback of an effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectEvent {
+    pub effect_name: String,
+    pub transition: EffectTransition,
+    pub timestamp: SystemTime,
+}
+
+/// Sending half of the channel on which [EffectEvent]s are published
+pub type EffectEventSender = broadcast::Sender<EffectEvent>;
+
+/// Publish an [EffectEvent] about the named effect, if anyone may be
+/// interested in it
+pub fn publish_effect_event(
+    sender: Option<&EffectEventSender>,
+    effect_name: &str,
+    transition: EffectTransition,
+) {
+    if let Some(sender) = sender {
+        // Nobody may be subscribed at the moment, that's fine
+        let _ = sender.send(EffectEvent {
+            effect_name: effect_name.to_owned(),
+            transition,
+            timestamp: SystemTime::now(),
+        });
+    }
+}
@@ -0,0 +1,490 @@
+//! Exposes counters and gauges describing what Energia does in the
+//! OpenMetrics text format over HTTP, so that they can be scraped by
+//! Prometheus or a compatible collector
+
+use super::{
+    environment_controller::ScheduleType,
+    manager_state::{EffectEvent, EffectTransition, ManagerState},
+};
+use crate::{
+    armaf::{ActorPort, Handle},
+    external::display_server::SystemState,
+    system::upower_sensor::PowerStatus,
+};
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fmt::Debug, fmt::Write, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, watch},
+};
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A family of samples exported by [MetricsExporter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Metric {
+    IdleTransitions,
+    EffectsApplied,
+    EffectsRolledBack,
+    EffectFailures,
+    OnBattery,
+    BatteryPercentage,
+    Schedule,
+    MailboxDepth,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::IdleTransitions,
+        Metric::EffectsApplied,
+        Metric::EffectsRolledBack,
+        Metric::EffectFailures,
+        Metric::OnBattery,
+        Metric::BatteryPercentage,
+        Metric::Schedule,
+        Metric::MailboxDepth,
+    ];
+
+    /// Name of the metric family
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::IdleTransitions => "energia_idle_transitions",
+            Metric::EffectsApplied => "energia_effects_applied",
+            Metric::EffectsRolledBack => "energia_effects_rolled_back",
+            Metric::EffectFailures => "energia_effect_failures",
+            Metric::OnBattery => "energia_on_battery",
+            Metric::BatteryPercentage => "energia_battery_percentage",
+            Metric::Schedule => "energia_schedule",
+            Metric::MailboxDepth => "energia_actor_mailbox_depth",
+        }
+    }
+
+    fn is_counter(&self) -> bool {
+        matches!(
+            self,
+            Metric::IdleTransitions
+                | Metric::EffectsApplied
+                | Metric::EffectsRolledBack
+                | Metric::EffectFailures
+        )
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Metric::IdleTransitions => "Times the display server reported the user becoming idle",
+            Metric::EffectsApplied => "Effects applied successfully",
+            Metric::EffectsRolledBack => "Effects rolled back successfully",
+            Metric::EffectFailures => "Effects which failed to be applied or rolled back",
+            Metric::OnBattery => "Whether the computer runs on battery",
+            Metric::BatteryPercentage => "Battery charge while the computer runs on battery",
+            Metric::Schedule => "Whether the schedule of the given type is in use",
+            Metric::MailboxDepth => "Requests waiting in the queue of an actor",
+        }
+    }
+}
+
+type SampleKey = (Metric, Vec<(String, String)>);
+
+/// Current values of the exported samples
+#[derive(Debug, Default)]
+pub struct Metrics {
+    samples: BTreeMap<SampleKey, u64>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Increment the counter with the given labels
+    pub fn increment(&mut self, metric: Metric, labels: &[(&str, &str)]) {
+        *self.samples.entry(sample_key(metric, labels)).or_insert(0) += 1;
+    }
+
+    /// Set the value of the gauge with the given labels
+    pub fn set(&mut self, metric: Metric, labels: &[(&str, &str)], value: u64) {
+        self.samples.insert(sample_key(metric, labels), value);
+    }
+
+    /// Stop exporting the sample with the given labels
+    pub fn remove(&mut self, metric: Metric, labels: &[(&str, &str)]) {
+        self.samples.remove(&sample_key(metric, labels));
+    }
+
+    /// Render the samples in the OpenMetrics text format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for metric in Metric::ALL {
+            let kind = if metric.is_counter() {
+                "counter"
+            } else {
+                "gauge"
+            };
+            let _ = writeln!(output, "# TYPE {} {}", metric.name(), kind);
+            let _ = writeln!(output, "# HELP {} {}", metric.name(), metric.help());
+            let suffix = if metric.is_counter() { "_total" } else { "" };
+            for ((_, labels), value) in self.samples.iter().filter(|((m, _), _)| *m == metric) {
+                let _ = writeln!(
+                    output,
+                    "{}{}{} {}",
+                    metric.name(),
+                    suffix,
+                    render_labels(labels),
+                    value
+                );
+            }
+        }
+        output.push_str("# EOF\n");
+        output
+    }
+}
+
+fn sample_key(metric: Metric, labels: &[(&str, &str)]) -> SampleKey {
+    (
+        metric,
+        labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    )
+}
+
+fn render_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+type MailboxProbe = Box<dyn Fn() -> usize + Send>;
+
+/// Collects the metrics from the channels on which the controllers and
+/// sensors publish their state and serves them at `/metrics`
+pub struct MetricsExporter {
+    address: SocketAddr,
+    metrics: Metrics,
+    idleness_channel: Option<watch::Receiver<SystemState>>,
+    effect_events: Option<broadcast::Receiver<EffectEvent>>,
+    power_status: Option<watch::Receiver<PowerStatus>>,
+    state: Option<watch::Receiver<ManagerState>>,
+    /// Schedule types which have been reported so far, built-in or not
+    reported_schedules: Vec<ScheduleType>,
+    mailboxes: Vec<(String, MailboxProbe)>,
+}
+
+impl MetricsExporter {
+    /// Create a new MetricsExporter listening on the given address
+    pub fn new(address: SocketAddr) -> MetricsExporter {
+        let mut metrics = Metrics::new();
+        metrics.set(Metric::IdleTransitions, &[], 0);
+        MetricsExporter {
+            address,
+            metrics,
+            idleness_channel: None,
+            effect_events: None,
+            power_status: None,
+            state: None,
+            reported_schedules: ScheduleType::ALL.to_vec(),
+            mailboxes: Vec::new(),
+        }
+    }
+
+    /// Count the times the display server reports the user becoming idle
+    pub fn with_idleness_channel(
+        mut self,
+        idleness_channel: watch::Receiver<SystemState>,
+    ) -> MetricsExporter {
+        self.idleness_channel = Some(idleness_channel);
+        self
+    }
+
+    /// Count the effects applied, rolled back and failed
+    pub fn with_effect_events(
+        mut self,
+        effect_events: broadcast::Receiver<EffectEvent>,
+    ) -> MetricsExporter {
+        self.effect_events = Some(effect_events);
+        self
+    }
+
+    /// Export the power source and battery percentage
+    pub fn with_power_status(
+        mut self,
+        power_status: watch::Receiver<PowerStatus>,
+    ) -> MetricsExporter {
+        self.power_status = Some(power_status);
+        self
+    }
+
+    /// Export the schedule type in use
+    pub fn with_state(mut self, state: watch::Receiver<ManagerState>) -> MetricsExporter {
+        self.state = Some(state);
+        self
+    }
+
+    /// Export the number of requests waiting in the queue of the actor
+    /// behind the port. The port is kept until the exporter terminates.
+    pub fn with_mailbox<P, R, E>(
+        mut self,
+        actor_name: &str,
+        port: ActorPort<P, R, E>,
+    ) -> MetricsExporter
+    where
+        P: Send + 'static,
+        R: Send + 'static,
+        E: Debug + Send + 'static,
+    {
+        let probe: MailboxProbe = Box::new(move || port.mailbox_depth());
+        self.mailboxes.push((actor_name.to_owned(), probe));
+        self
+    }
+
+    /// Spawn the MetricsExporter actor
+    pub async fn spawn(mut self) -> Result<Handle> {
+        let listener = TcpListener::bind(self.address)
+            .await
+            .with_context(|| format!("Couldn't listen for metrics requests on {}", self.address))?;
+        log::debug!("Serving metrics on {}", self.address);
+        let (handle, mut handle_child) = Handle::new();
+        tokio::spawn(async move {
+            self.update_power_status();
+            self.update_schedule();
+            loop {
+                tokio::select! {
+                    _ = handle_child.should_terminate() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            let body = self.render();
+                            tokio::spawn(serve_connection(stream, body));
+                        }
+                        Err(e) => log::error!("Couldn't accept metrics connection: {}", e),
+                    },
+                    result = changed(self.idleness_channel.as_mut()) => {
+                        if result.is_err() {
+                            self.idleness_channel = None;
+                        } else if *self.idleness_channel.as_mut().unwrap().borrow_and_update() == SystemState::Idle {
+                            self.metrics.increment(Metric::IdleTransitions, &[]);
+                        }
+                    }
+                    event = recv_event(self.effect_events.as_mut()) => match event {
+                        Ok(event) => self.count_effect_event(&event),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::warn!("Metrics missed {} effect events", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => self.effect_events = None,
+                    },
+                    result = changed(self.power_status.as_mut()) => {
+                        if result.is_err() {
+                            self.power_status = None;
+                        } else {
+                            self.update_power_status();
+                        }
+                    }
+                    result = changed(self.state.as_mut()) => {
+                        if result.is_err() {
+                            self.state = None;
+                        } else {
+                            self.update_schedule();
+                        }
+                    }
+                }
+            }
+            log::debug!("Terminated");
+        });
+        Ok(handle)
+    }
+
+    fn render(&mut self) -> String {
+        for (actor_name, probe) in self.mailboxes.iter() {
+            self.metrics.set(
+                Metric::MailboxDepth,
+                &[("actor", actor_name.as_str())],
+                probe() as u64,
+            );
+        }
+        self.metrics.render()
+    }
+
+    fn count_effect_event(&mut self, event: &EffectEvent) {
+        let labels = [("effect", event.effect_name.as_str())];
+        match event.transition {
+            EffectTransition::Applied => self.metrics.increment(Metric::EffectsApplied, &labels),
+            EffectTransition::RolledBack => {
+                self.metrics.increment(Metric::EffectsRolledBack, &labels)
+            }
+            EffectTransition::ApplicationFailed => self
+                .metrics
+                .increment(Metric::EffectFailures, &[labels[0], ("operation", "apply")]),
+            EffectTransition::RollbackFailed => self.metrics.increment(
+                Metric::EffectFailures,
+                &[labels[0], ("operation", "rollback")],
+            ),
+        }
+    }
+
+    fn update_power_status(&mut self) {
+        let power_status = match self.power_status.as_mut() {
+            Some(receiver) => *receiver.borrow_and_update(),
+            None => return,
+        };
+        match power_status {
+            PowerStatus::Battery(percentage) => {
+                self.metrics.set(Metric::OnBattery, &[], 1);
+                self.metrics.set(Metric::BatteryPercentage, &[], percentage);
+            }
+            PowerStatus::External => {
+                self.metrics.set(Metric::OnBattery, &[], 0);
+                self.metrics.remove(Metric::BatteryPercentage, &[]);
+            }
+        }
+    }
+
+    fn update_schedule(&mut self) {
+        let schedule_type = match self.state.as_mut() {
+            Some(receiver) => receiver.borrow_and_update().schedule_type.clone(),
+            None => return,
+        };
+        if let Some(typ) = &schedule_type {
+            if !self.reported_schedules.contains(typ) {
+                self.reported_schedules.push(typ.clone());
+            }
+        }
+        for typ in self.reported_schedules.iter() {
+            self.metrics.set(
+                Metric::Schedule,
+                &[("schedule_type", typ.config_name())],
+                (schedule_type.as_ref() == Some(typ)) as u64,
+            );
+        }
+    }
+}
+
+/// Wait for a change of the channel, never returning if there's no channel
+async fn changed<T>(
+    receiver: Option<&mut watch::Receiver<T>>,
+) -> Result<(), watch::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for an event, never returning if there's no channel
+async fn recv_event(
+    receiver: Option<&mut broadcast::Receiver<EffectEvent>>,
+) -> Result<EffectEvent, broadcast::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Answer a single HTTP request and close the connection
+async fn serve_connection(stream: TcpStream, body: String) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request_line = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await
+    {
+        Ok(Ok(request_line)) => request_line,
+        Ok(Err(e)) => {
+            log::debug!("Couldn't read metrics request: {}", e);
+            return;
+        }
+        Err(_) => {
+            log::debug!("Metrics client didn't send its request in time");
+            return;
+        }
+    };
+    let response = match parse_request_line(&request_line) {
+        Some(("GET", "/metrics")) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        Some((_, "/metrics")) => {
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+        }
+        Some(_) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+        None => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+    };
+    if let Err(e) = writer.write_all(response.as_bytes()).await {
+        log::debug!("Couldn't send metrics: {}", e);
+    }
+}
+
+/// Read the request line and skip the headers
+async fn read_request<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<String> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            return Ok(request_line);
+        }
+    }
+}
+
+/// Get the method and path of an HTTP request line, ignoring the query
+fn parse_request_line(request_line: &str) -> Option<(&str, &str)> {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    parts.next()?.strip_prefix("HTTP/")?;
+    Some((method, target.split('?').next().unwrap_or(target)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::new();
+        metrics.increment(Metric::EffectsApplied, &[("effect", "screen_dim")]);
+        metrics.increment(Metric::EffectsApplied, &[("effect", "screen_dim")]);
+        metrics.increment(Metric::EffectsApplied, &[("effect", "lock")]);
+        metrics.set(Metric::BatteryPercentage, &[], 80);
+        metrics.set(Metric::BatteryPercentage, &[], 75);
+        metrics.set(Metric::MailboxDepth, &[("actor", "Say \"hi\"")], 2);
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE energia_effects_applied counter\n"));
+        assert!(rendered.contains("energia_effects_applied_total{effect=\"lock\"} 1\n"));
+        assert!(rendered.contains("energia_effects_applied_total{effect=\"screen_dim\"} 2\n"));
+        assert!(rendered.contains("# TYPE energia_battery_percentage gauge\n"));
+        assert!(rendered.contains("energia_battery_percentage 75\n"));
+        assert!(rendered.contains("energia_actor_mailbox_depth{actor=\"Say \\\"hi\\\"\"} 2\n"));
+        assert!(rendered.ends_with("# EOF\n"));
+
+        metrics.remove(Metric::BatteryPercentage, &[]);
+        assert!(!metrics.render().contains("energia_battery_percentage 75"));
+    }
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(
+            parse_request_line("GET /metrics HTTP/1.1\r\n"),
+            Some(("GET", "/metrics"))
+        );
+        assert_eq!(
+            parse_request_line("GET /metrics?name[]=x HTTP/1.0\r\n"),
+            Some(("GET", "/metrics"))
+        );
+        assert_eq!(parse_request_line("GET /metrics\r\n"), None);
+        assert_eq!(parse_request_line(""), None);
+    }
+}
@@ -1,12 +1,28 @@
 //! Control-layer actors - controllers and filters
 
-mod broadcast_adapter;
+pub mod config_watcher;
 pub mod dbus_controller;
+pub mod displays;
 pub mod effector_inventory;
+pub mod effector_registry;
 pub mod environment_controller;
+pub mod event_history;
+pub mod hotplug_controller;
+pub mod idleness_broker;
 pub mod idleness_controller;
+pub mod inhibitor_policy;
+pub mod keepalive;
+pub mod manager_state;
+pub mod metrics;
+pub mod remote_control;
+pub mod schedule_rules;
 pub mod sequencer;
+pub mod signal_handler;
 pub mod sleep_controller;
+pub mod socket_controller;
+pub mod statistics;
+pub mod wake_triggers;
+pub mod watchdog;
 
 #[cfg(test)]
 mod test;
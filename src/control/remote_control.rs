@@ -0,0 +1,167 @@
+//! Execution of effects on demand of other programs, limited by the policy in
+//! the `[remote_control]` configuration section
+
+use super::effector_inventory::{self as ei, InventoryPort};
+use crate::armaf::{EffectorMessage, EffectorPort, EffectorResponse};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+
+/// Name of the configuration section containing the remote control policy
+pub const REMOTE_CONTROL_SECTION: &str = "remote_control";
+
+/// Parse the names of the effects which other programs may trigger and roll
+/// back. No effects are triggerable if the section is missing.
+pub fn parse_triggerable_effects(config: &toml::Value) -> Result<HashSet<String>> {
+    let names = match config
+        .get(REMOTE_CONTROL_SECTION)
+        .and_then(|section| section.get("triggerable_effects"))
+    {
+        Some(names) => names,
+        None => return Ok(HashSet::new()),
+    };
+    let names = names
+        .as_array()
+        .ok_or_else(|| anyhow!("must be an array of effect names"))
+        .context("Invalid remote_control.triggerable_effects")?;
+    let known_effects = ei::resolve_effectors_for_effects();
+    let mut triggerable = HashSet::new();
+    for name in names {
+        let name = name
+            .as_str()
+            .ok_or_else(|| anyhow!("{} is not a string", name))
+            .context("Invalid remote_control.triggerable_effects")?;
+        if !known_effects.contains_key(name) {
+            return Err(anyhow!("unknown effect {}", name))
+                .context("Invalid remote_control.triggerable_effects");
+        }
+        triggerable.insert(name.to_owned());
+    }
+    Ok(triggerable)
+}
+
+/// Executes and rolls back the triggerable effects and reports the state of
+/// the effectors of any effect
+#[derive(Clone)]
+pub struct EffectTrigger {
+    inventory: InventoryPort,
+    triggerable: HashSet<String>,
+}
+
+impl EffectTrigger {
+    /// Create a new EffectTrigger, allowing only the given effects to be
+    /// triggered
+    pub fn new(inventory: InventoryPort, triggerable: HashSet<String>) -> EffectTrigger {
+        EffectTrigger {
+            inventory,
+            triggerable,
+        }
+    }
+
+    /// Apply the named effect, returning the response of its effector
+    pub async fn trigger(&self, effect_name: &str) -> Result<EffectorResponse> {
+        self.send(effect_name, EffectorMessage::Execute).await
+    }
+
+    /// Roll the named effect back, returning the response of its effector
+    pub async fn rollback(&self, effect_name: &str) -> Result<EffectorResponse> {
+        self.send(effect_name, EffectorMessage::Rollback).await
+    }
+
+    /// Get the state of the effector of the named effect. Unlike triggering,
+    /// this is allowed for all effects.
+    pub async fn status(&self, effect_name: &str) -> Result<EffectorResponse> {
+        let port = self.get_port(effect_name).await?;
+        Ok(port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await?)
+    }
+
+    async fn send(&self, effect_name: &str, message: EffectorMessage) -> Result<EffectorResponse> {
+        if !self.triggerable.contains(effect_name) {
+            return Err(anyhow!(
+                "Effect {} is not allowed to be controlled remotely, add it to remote_control.triggerable_effects",
+                effect_name
+            ));
+        }
+        let port = self.get_port(effect_name).await?;
+        log::info!("Remotely requested {:?} of {}", message, effect_name);
+        Ok(port.request(message).await?)
+    }
+
+    async fn get_port(&self, effect_name: &str) -> Result<EffectorPort> {
+        let mapping = ei::resolve_effectors_for_effects();
+        let (effector_name, _) = mapping
+            .get(effect_name)
+            .ok_or_else(|| anyhow!("Unknown effect {}", effect_name))?;
+        ei::get_effector_port(&self.inventory, effector_name).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        armaf::spawn_server, control::effector_inventory::EffectorInventory,
+        external::dependency_provider::DependencyProvider,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_triggerable_effects() {
+        assert!(
+            parse_triggerable_effects(&toml::toml! { [lock] command = "i3lock" })
+                .unwrap()
+                .is_empty()
+        );
+        let config = toml::toml! {
+            [remote_control]
+            triggerable_effects = ["screen_off", "lock"]
+        };
+        let triggerable = parse_triggerable_effects(&config).unwrap();
+        assert_eq!(triggerable.len(), 2);
+        assert!(triggerable.contains("screen_off"));
+        assert!(parse_triggerable_effects(&toml::toml! {
+            [remote_control]
+            triggerable_effects = ["explode"]
+        })
+        .is_err());
+        assert!(parse_triggerable_effects(&toml::toml! {
+            [remote_control]
+            triggerable_effects = "lock"
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_effect_trigger() {
+        let inventory = spawn_server(EffectorInventory::new(
+            Arc::new(toml::Value::Table(toml::value::Map::new())),
+            DependencyProvider::make_mock(None),
+        ))
+        .await
+        .unwrap();
+        let trigger = EffectTrigger::new(inventory, HashSet::from(["screen_off".to_owned()]));
+        assert_eq!(
+            trigger.trigger("screen_off").await.unwrap().applied_effects,
+            1
+        );
+        assert_eq!(
+            trigger.status("screen_off").await.unwrap().applied_effects,
+            1
+        );
+        assert_eq!(
+            trigger
+                .rollback("screen_off")
+                .await
+                .unwrap()
+                .applied_effects,
+            0
+        );
+        assert!(trigger.trigger("screen_dim").await.is_err());
+        assert_eq!(
+            trigger.status("screen_dim").await.unwrap().applied_effects,
+            0
+        );
+        assert!(trigger.status("explode").await.is_err());
+    }
+}
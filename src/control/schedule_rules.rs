@@ -0,0 +1,384 @@
+//! Rules choosing a named schedule from the conditions the computer is in,
+//! configured as an array of `[[rules]]` tables.
+//!
+//! The first rule whose conditions all hold picks the schedule. If none of
+//! them does, the built-in schedule for the power source is used.
+
+use super::environment_controller::{configured_schedule_types, ScheduleType};
+use crate::system::{environment_sensor::EnvironmentFacts, upower_sensor::PowerStatus};
+use anyhow::{anyhow, Context, Result};
+
+/// Name of the configuration section containing the rules
+pub const RULES_SECTION: &str = "rules";
+
+/// State of the computer against which the rules are evaluated
+#[derive(Debug, Clone, Copy)]
+pub struct Environment<'a> {
+    pub power_status: PowerStatus,
+    pub facts: &'a EnvironmentFacts,
+    /// Profile chosen manually by the user
+    pub profile: Option<&'a str>,
+}
+
+/// A single condition of a rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The computer runs on battery (`true`) or on external power (`false`)
+    OnBattery(bool),
+    /// The computer runs on battery charged below the percentage
+    BatteryBelow(u64),
+    /// The computer runs on battery charged above the percentage
+    BatteryAbove(u64),
+    /// The computer is or isn't connected to a dock
+    Docked(bool),
+    /// A monitor other than the built-in panel is or isn't connected
+    ExternalMonitor(bool),
+    /// The hottest thermal zone is above the temperature in degrees Celsius
+    TemperatureAbove(f64),
+    /// The user has chosen the profile with the given name
+    Profile(String),
+}
+
+impl Condition {
+    /// Whether the condition holds in the environment. Conditions on facts
+    /// which aren't known never hold.
+    pub fn matches(&self, environment: &Environment) -> bool {
+        match (self, environment.power_status) {
+            (Condition::OnBattery(on_battery), status) => {
+                *on_battery == matches!(status, PowerStatus::Battery(_))
+            }
+            (Condition::BatteryBelow(threshold), PowerStatus::Battery(percentage)) => {
+                percentage < *threshold
+            }
+            (Condition::BatteryAbove(threshold), PowerStatus::Battery(percentage)) => {
+                percentage > *threshold
+            }
+            (Condition::BatteryBelow(_) | Condition::BatteryAbove(_), PowerStatus::External) => {
+                false
+            }
+            (Condition::Docked(docked), _) => environment.facts.docked == Some(*docked),
+            (Condition::ExternalMonitor(connected), _) => {
+                environment.facts.external_monitor == Some(*connected)
+            }
+            (Condition::TemperatureAbove(threshold), _) => {
+                matches!(environment.facts.temperature, Some(t) if t > *threshold)
+            }
+            (Condition::Profile(profile), _) => environment.profile == Some(profile.as_str()),
+        }
+    }
+
+    /// Whether the condition needs facts gathered by an
+    /// [EnvironmentSensor](crate::system::environment_sensor::EnvironmentSensor)
+    pub fn needs_facts(&self) -> bool {
+        matches!(
+            self,
+            Condition::Docked(_) | Condition::ExternalMonitor(_) | Condition::TemperatureAbove(_)
+        )
+    }
+}
+
+/// Picks the schedule when all of its conditions hold
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub schedule: ScheduleType,
+    pub conditions: Vec<Condition>,
+}
+
+impl Rule {
+    pub fn matches(&self, environment: &Environment) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(environment))
+    }
+}
+
+/// Get the schedule chosen by the first matching rule
+pub fn select_schedule(rules: &[Rule], environment: &Environment) -> Option<ScheduleType> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(environment))
+        .map(|rule| rule.schedule.clone())
+}
+
+/// Whether any of the rules needs facts gathered by an
+/// [EnvironmentSensor](crate::system::environment_sensor::EnvironmentSensor)
+pub fn rules_need_facts(rules: &[Rule]) -> bool {
+    rules
+        .iter()
+        .flat_map(|rule| rule.conditions.iter())
+        .any(Condition::needs_facts)
+}
+
+/// Parse the rules, checking that the schedules they choose are defined.
+/// There are no rules if the section is missing.
+pub fn parse_rules(config: &toml::Value) -> Result<Vec<Rule>> {
+    let rules = match config.get(RULES_SECTION) {
+        Some(rules) => rules
+            .as_array()
+            .ok_or_else(|| anyhow!("must be an array of tables"))
+            .and_then(|rules| rules.iter().map(parse_rule).collect::<Result<Vec<Rule>>>())
+            .context("Invalid rules")?,
+        None => return Ok(Vec::new()),
+    };
+    let defined = configured_schedule_types(config);
+    for rule in rules.iter() {
+        if !defined.contains(&rule.schedule) {
+            return Err(anyhow!(
+                "Rule chooses schedule {}, which is not defined",
+                rule.schedule.config_name()
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+fn parse_rule(value: &toml::Value) -> Result<Rule> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| anyhow!("{} is not a table", value))?;
+    let schedule = table
+        .get("schedule")
+        .and_then(|schedule| schedule.as_str())
+        .ok_or_else(|| anyhow!("{} doesn't name a schedule in schedule", value))?;
+    let schedule = ScheduleType::try_from(schedule)?;
+    let mut conditions = Vec::new();
+    for (key, condition) in table.iter() {
+        let condition = match key.as_str() {
+            "schedule" => continue,
+            "power" => match condition.as_str() {
+                Some("battery") => Condition::OnBattery(true),
+                Some("external") => Condition::OnBattery(false),
+                _ => return Err(anyhow!("power must be either \"battery\" or \"external\"")),
+            },
+            "battery_below" => Condition::BatteryBelow(parse_percentage(key, condition)?),
+            "battery_above" => Condition::BatteryAbove(parse_percentage(key, condition)?),
+            "docked" => Condition::Docked(
+                condition
+                    .as_bool()
+                    .ok_or_else(|| anyhow!("docked must be a boolean"))?,
+            ),
+            "external_monitor" => Condition::ExternalMonitor(
+                condition
+                    .as_bool()
+                    .ok_or_else(|| anyhow!("external_monitor must be a boolean"))?,
+            ),
+            "temperature_above" => Condition::TemperatureAbove(
+                condition
+                    .as_float()
+                    .or_else(|| condition.as_integer().map(|t| t as f64))
+                    .ok_or_else(|| anyhow!("temperature_above must be a number"))?,
+            ),
+            "profile" => Condition::Profile(
+                condition
+                    .as_str()
+                    .ok_or_else(|| anyhow!("profile must be a string"))?
+                    .to_owned(),
+            ),
+            _ => return Err(anyhow!("unknown condition {}", key)),
+        };
+        conditions.push(condition);
+    }
+    Ok(Rule {
+        schedule,
+        conditions,
+    })
+}
+
+fn parse_percentage(key: &str, value: &toml::Value) -> Result<u64> {
+    match value.as_integer() {
+        Some(percentage) if (0..=100).contains(&percentage) => Ok(percentage as u64),
+        _ => Err(anyhow!("{} must be a percentage between 0 and 100", key)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn environment<'a>(
+        power_status: PowerStatus,
+        facts: &'a EnvironmentFacts,
+        profile: Option<&'a str>,
+    ) -> Environment<'a> {
+        Environment {
+            power_status,
+            facts,
+            profile,
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert!(parse_rules(&toml::toml! { [schedule.external] })
+            .unwrap()
+            .is_empty());
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.presentation]
+            screen_dim = "1h"
+
+            [[rules]]
+            schedule = "presentation"
+            profile = "presentation"
+            power = "external"
+
+            [[rules]]
+            schedule = "battery"
+            docked = false
+            battery_below = 50
+        };
+        let rules = parse_rules(&config).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].schedule, ScheduleType::named("presentation"));
+        assert!(rules[0]
+            .conditions
+            .contains(&Condition::Profile("presentation".to_owned())));
+        assert!(rules[0].conditions.contains(&Condition::OnBattery(false)));
+        assert!(rules[1].conditions.contains(&Condition::Docked(false)));
+        assert!(rules[1].conditions.contains(&Condition::BatteryBelow(50)));
+        assert!(rules_need_facts(&rules));
+    }
+
+    #[test]
+    fn test_external_monitor() {
+        let config = toml::toml! {
+            [schedule.docked]
+            screen_dim = "10m"
+
+            [[rules]]
+            schedule = "docked"
+            external_monitor = true
+        };
+        let rules = parse_rules(&config).unwrap();
+        assert!(rules_need_facts(&rules));
+        let facts = |external_monitor| EnvironmentFacts {
+            external_monitor,
+            ..Default::default()
+        };
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::External, &facts(Some(true)), None)
+            ),
+            Some(ScheduleType::named("docked"))
+        );
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::External, &facts(Some(false)), None)
+            ),
+            None
+        );
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::External, &facts(None), None)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let undefined = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [[rules]]
+            schedule = "gaming"
+            profile = "gaming"
+        };
+        assert!(parse_rules(&undefined).is_err());
+        let unknown_condition = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [[rules]]
+            schedule = "external"
+            moon = "full"
+        };
+        assert!(parse_rules(&unknown_condition).is_err());
+        let invalid_percentage = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [[rules]]
+            schedule = "external"
+            battery_below = 150
+        };
+        assert!(parse_rules(&invalid_percentage).is_err());
+    }
+
+    #[test]
+    fn test_select_schedule() {
+        let presentation = ScheduleType::named("presentation");
+        let hot = ScheduleType::named("hot");
+        let rules = vec![
+            Rule {
+                schedule: presentation.clone(),
+                conditions: vec![Condition::Profile("presentation".to_owned())],
+            },
+            Rule {
+                schedule: hot.clone(),
+                conditions: vec![
+                    Condition::TemperatureAbove(80.0),
+                    Condition::OnBattery(true),
+                ],
+            },
+            Rule {
+                schedule: ScheduleType::LowBattery,
+                conditions: vec![Condition::BatteryBelow(30), Condition::Docked(false)],
+            },
+        ];
+        let unknown = EnvironmentFacts::default();
+        let hot_undocked = EnvironmentFacts {
+            docked: Some(false),
+            temperature: Some(85.0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::External, &hot_undocked, Some("presentation"))
+            ),
+            Some(presentation)
+        );
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::External, &hot_undocked, None)
+            ),
+            None
+        );
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::Battery(20), &hot_undocked, None)
+            ),
+            Some(hot)
+        );
+        // Unknown facts don't match
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::Battery(20), &unknown, None)
+            ),
+            None
+        );
+        let cool_undocked = EnvironmentFacts {
+            docked: Some(false),
+            temperature: Some(40.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_schedule(
+                &rules,
+                &environment(PowerStatus::Battery(20), &cool_undocked, None)
+            ),
+            Some(ScheduleType::LowBattery)
+        );
+    }
+}
@@ -1,16 +1,71 @@
 //! Notifies a [Server](crate::armaf::Server) when the system goes idle, a series of timeouts pass and when the system stops being idle
+use super::{
+    idleness_controller::{BunchReplacement, IdlenessMessage, IdlenessPort, IdlenessSnapshot},
+    manager_state::StateReporter,
+};
 use crate::{
     armaf,
-    external::display_server::{DisplayServerController, SystemState},
+    external::{
+        display_server::{DisplayServerController, SystemState},
+        state_journal::StateJournal,
+    },
+    system::sleep_sensor::{ReadyToSleep, SleepUpdate, SuspendClock},
 };
 use anyhow::{Context, Result};
 use log;
 use std::time::Duration;
 use thiserror::Error;
-use tokio::{select, sync::watch, time::Instant};
+use tokio::{
+    select,
+    sync::{broadcast, watch},
+    time::{sleep_until, Instant},
+};
 
-#[derive(Debug, Copy, Clone)]
-pub struct GetRunningTime;
+/// A new sequence for a [Sequencer], along with the bunches its
+/// [IdlenessController](super::idleness_controller::IdlenessController) should
+/// switch to
+#[derive(Debug, Clone)]
+pub struct SequenceReplacement {
+    pub timeouts: Vec<Duration>,
+    pub starting_position: usize,
+    /// Time by which the wait for the position after the starting one is
+    /// shortened, since a part of it has already passed
+    pub shorten_initial_sleep_by: Duration,
+    pub grace_period: Duration,
+    pub bunches: BunchReplacement,
+}
+
+/// A command handled by [Sequencer], answered with the time for which the
+/// system has been idle
+#[derive(Debug, Clone)]
+pub enum SequencerCommand {
+    GetRunningTime,
+    /// Go back to the beginning of the sequence as if the user became active,
+    /// e.g. after the system resumes from sleep
+    Reset,
+    /// Continue with another sequence without respawning the Sequencer and
+    /// its IdlenessController, e.g. after the power source changes. Answered
+    /// with an error if the IdlenessController couldn't switch to the new
+    /// bunches, in which case the current sequence stays in use.
+    ReplaceSequence(Box<SequenceReplacement>),
+    /// Don't advance the position until the given time passes, e.g. to keep
+    /// the screen on for the next 45 minutes. If the system goes idle in the
+    /// meantime, the position advances once the hold expires, after whatever
+    /// is left of the grace period. Replaces any previous hold, a zero
+    /// duration ends it.
+    Hold(Duration),
+    /// Roll the applied effects back for the given time without going back
+    /// to the beginning of the sequence, e.g. so that a notification can be
+    /// read. Unless the user becomes active in the meantime, the effects are
+    /// applied again once the time passes. The time until the next position
+    /// keeps running, so if it passes while awake, the next position is
+    /// reached right after. Does nothing at the beginning of the sequence,
+    /// waking again extends the time.
+    Wake(Duration),
+}
+
+/// Port through which [SequencerCommand]s are sent to a [Sequencer]
+pub type SequencerPort = armaf::ActorPort<SequencerCommand, Duration, ()>;
 
 #[derive(Debug, Copy, Clone, Error)]
 #[error("Sequencer's port dropped, actor must terminate")]
@@ -23,24 +78,43 @@ enum PositionChange {
 }
 
 pub struct Sequencer<C: DisplayServerController> {
-    timeout_sequence: Vec<u64>,
+    /// Time to wait for each position. Only the first one is handled by the
+    /// display server, which counts in whole seconds, the others are timed
+    /// internally with millisecond resolution.
+    timeout_sequence: Vec<Duration>,
     current_position: usize,
     controller: C,
     state_channel: watch::Receiver<SystemState>,
     position_changed_at: Instant,
     original_timeout: Option<i16>,
-    child_port: armaf::ActorPort<SystemState, (), anyhow::Error>,
-    command_receiver: Option<armaf::ActorReceiver<GetRunningTime, Duration, ()>>,
+    child_port: IdlenessPort,
+    /// Sends the activity, which may come in quick succession when the user
+    /// keeps waking the computer, without allocating a channel each time
+    activity_port: armaf::FastPort<IdlenessMessage, Option<IdlenessSnapshot>, anyhow::Error>,
+    command_receiver: Option<armaf::ActorReceiver<SequencerCommand, Duration, ()>>,
     initial_position_dirty: bool,
     shorten_initial_sleep_by: Duration,
+    grace_period: Duration,
+    in_grace_period: bool,
+    sequence_replaced: bool,
+    hold_until: Option<Instant>,
+    /// When the system became idle, or the position's timeout passed, while
+    /// the position was held
+    idle_during_hold: Option<Instant>,
+    /// Until when the effects are rolled back by [SequencerCommand::Wake]
+    wake_until: Option<Instant>,
+    state_reporter: Option<StateReporter>,
+    state_journal: StateJournal,
+    sleep_updates: Option<broadcast::Receiver<SleepUpdate>>,
+    suspend_clock: SuspendClock,
 }
 
 impl<C: DisplayServerController> Sequencer<C> {
     pub fn new(
-        child_port: armaf::ActorPort<SystemState, (), anyhow::Error>,
+        child_port: IdlenessPort,
         ds_controller: C,
         state_channel: watch::Receiver<SystemState>,
-        timeout_sequence: &[u64],
+        timeout_sequence: &[Duration],
         starting_position: usize,
         shorten_initial_sleep_by: Duration,
     ) -> Sequencer<C> {
@@ -51,14 +125,64 @@ impl<C: DisplayServerController> Sequencer<C> {
             state_channel,
             position_changed_at: Instant::now(),
             original_timeout: None,
+            activity_port: child_port.fast(),
             child_port,
             command_receiver: None,
             initial_position_dirty: false,
             shorten_initial_sleep_by,
+            grace_period: Duration::ZERO,
+            in_grace_period: false,
+            sequence_replaced: false,
+            hold_until: None,
+            idle_during_hold: None,
+            wake_until: None,
+            state_reporter: None,
+            state_journal: StateJournal::in_memory(),
+            sleep_updates: None,
+            suspend_clock: SuspendClock::default(),
         }
     }
 
-    pub async fn spawn(mut self) -> Result<armaf::ActorPort<GetRunningTime, Duration, ()>> {
+    /// Set the grace period during which the system has to stay idle after
+    /// the display server reports idleness, before the first position is
+    /// reached. Any activity during the grace period cancels the transition.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Sequencer<C> {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Report when the next position is going to be reached to the given
+    /// reporter
+    pub fn with_state_reporter(mut self, state_reporter: StateReporter) -> Sequencer<C> {
+        self.state_reporter = Some(state_reporter);
+        self
+    }
+
+    /// Start with the position held until the given time, see
+    /// [SequencerCommand::Hold]
+    pub fn with_hold_until(mut self, hold_until: Option<Instant>) -> Sequencer<C> {
+        self.hold_until = hold_until;
+        self
+    }
+
+    /// Record the display server's original idleness timeout in the given
+    /// journal while the Sequencer is changing it
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> Sequencer<C> {
+        self.state_journal = state_journal;
+        self
+    }
+
+    /// Count the time the system spends suspended, which tokio's timers
+    /// don't, towards the timeouts of the internally handled positions
+    pub fn with_sleep_updates(
+        mut self,
+        sleep_updates: broadcast::Receiver<SleepUpdate>,
+    ) -> Sequencer<C> {
+        self.sleep_updates = Some(sleep_updates);
+        self
+    }
+
+    pub async fn spawn(mut self) -> Result<SequencerPort> {
         let (command_port, command_receiver) = armaf::ActorPort::make();
         self.command_receiver = Some(command_receiver);
         self.initialize().await?;
@@ -84,6 +208,9 @@ impl<C: DisplayServerController> Sequencer<C> {
                 None
             }
         };
+        let original_timeout = self.original_timeout.unwrap_or(-1i16);
+        self.state_journal
+            .update(|state| state.original_idleness_timeout = Some(original_timeout));
         self.initial_position_dirty =
             self.current_position != 0 && *self.state_channel.borrow() == SystemState::Awakened;
         log::debug!("Initial position dirty? {}", self.initial_position_dirty);
@@ -92,20 +219,18 @@ impl<C: DisplayServerController> Sequencer<C> {
         } else {
             0
         };
-        self.set_ds_timeout(self.timeout_sequence[initial_timeout_index] as i16)
+        self.set_ds_timeout(ds_timeout(self.timeout_sequence[initial_timeout_index]))
             .await
             .context("Failed to set initial timeout on the display server")?;
         Ok(())
     }
 
     async fn get_current_ds_timeout(&self) -> Result<i16> {
-        let sent_controller = self.controller.clone();
-        tokio::task::spawn_blocking(move || sent_controller.get_idleness_timeout()).await?
+        self.controller.get_idleness_timeout().await
     }
 
     async fn set_ds_timeout(&self, timeout: i16) -> Result<()> {
-        let sent_controller = self.controller.clone();
-        tokio::task::spawn_blocking(move || sent_controller.set_idleness_timeout(timeout)).await?
+        self.controller.set_idleness_timeout(timeout).await
     }
 
     async fn main_loop(&mut self) {
@@ -114,12 +239,15 @@ impl<C: DisplayServerController> Sequencer<C> {
         // will just get ignored and eventually reset. If the initial position
         // is internally handled, this will ensure it fires.
         let sleep = tokio::time::sleep(
-            Duration::from_secs(self.timeout_sequence[self.current_position])
+            self.timeout_sequence[self.current_position]
                 .saturating_sub(self.shorten_initial_sleep_by),
         );
         tokio::pin!(sleep);
+        let grace_sleep = tokio::time::sleep(self.grace_period);
+        tokio::pin!(grace_sleep);
+        self.report_next_position(sleep.deadline(), grace_sleep.deadline());
         loop {
-            let was_state_change = match self.loop_iteration(&mut sleep).await {
+            let was_state_change = match self.loop_iteration(&mut sleep, &mut grace_sleep).await {
                 Err(e) => {
                     if Self::is_terminating_error(e) {
                         return;
@@ -132,13 +260,28 @@ impl<C: DisplayServerController> Sequencer<C> {
                 }
                 Ok(was_state_change) => was_state_change,
             };
+            if self.sequence_replaced {
+                self.sequence_replaced = false;
+                if self.position_handleable_by_sleep() {
+                    sleep.as_mut().reset(
+                        Instant::now()
+                            + self.timeout_sequence[self.current_position]
+                                .saturating_sub(self.shorten_initial_sleep_by),
+                    );
+                }
+                self.report_next_position(sleep.deadline(), grace_sleep.deadline());
+                continue;
+            }
             // We started within the sequence while the system was active, so
             // the current display server timeout is not associated with
             // position 0. Also, the last command wasn't a control command,
             // so we have actually advanced our position
             if self.initial_position_dirty && was_state_change {
                 log::debug!("Undirtying initial position");
-                if let Err(e) = self.set_ds_timeout(self.timeout_sequence[0] as i16).await {
+                if let Err(e) = self
+                    .set_ds_timeout(ds_timeout(self.timeout_sequence[0]))
+                    .await
+                {
                     log::error!("Couldn't set display server timeout, first effect bunch may be executed at unexpected times: {}", e);
                 } else {
                     self.initial_position_dirty = false;
@@ -148,37 +291,118 @@ impl<C: DisplayServerController> Sequencer<C> {
                 log::debug!("Resetting the sleep future");
                 sleep.as_mut().reset(
                     Instant::now()
-                        .checked_add(Duration::from_secs(
-                            self.timeout_sequence[self.current_position],
-                        ))
+                        .checked_add(self.timeout_sequence[self.current_position])
                         .unwrap(),
                 )
             }
+            self.report_next_position(sleep.deadline(), grace_sleep.deadline());
         }
     }
 
     async fn loop_iteration(
         &mut self,
         sleep: &mut std::pin::Pin<&mut tokio::time::Sleep>,
+        grace_sleep: &mut std::pin::Pin<&mut tokio::time::Sleep>,
     ) -> Result<bool> {
         select! {
             // Sleep futures are not fused, they will reinitialize every time
             // you await them, so we need to handle the condition here
-            _ = sleep.as_mut(), if self.position_handleable_by_sleep() => {
+            _ = sleep.as_mut(), if self.position_handleable_by_sleep() && self.idle_during_hold.is_none() && self.wake_until.is_none() => {
                 log::debug!("Sleep future fired");
+                if self.is_held() {
+                    log::debug!("Position is held, waiting for the hold to expire");
+                    self.idle_during_hold = Some(sleep.deadline());
+                    return Ok(false);
+                }
+                self.change_position_and_notify(PositionChange::Increment).await?;
+                Ok(true)
+            }
+            _ = grace_sleep.as_mut(), if self.in_grace_period => {
+                log::debug!("Grace period passed without activity");
+                self.in_grace_period = false;
+                if self.is_held() {
+                    log::debug!("Position is held, waiting for the hold to expire");
+                    let grace_started_at = grace_sleep.deadline().checked_sub(self.grace_period);
+                    self.idle_during_hold = Some(grace_started_at.unwrap_or_else(Instant::now));
+                    return Ok(false);
+                }
+                self.change_position_and_notify(PositionChange::Increment).await?;
+                Ok(true)
+            }
+            _ = sleep_until(self.hold_until.unwrap_or_else(Instant::now)), if self.idle_during_hold.is_some() => {
+                log::info!("Hold expired while the system was idle");
+                let idle_since = self.idle_during_hold.take().unwrap();
+                self.hold_until = None;
+                let remaining_grace = if self.current_position == 0 {
+                    (idle_since + self.grace_period).saturating_duration_since(Instant::now())
+                } else {
+                    Duration::ZERO
+                };
+                if !remaining_grace.is_zero() {
+                    log::debug!("Continuing the grace period for {:?}", remaining_grace);
+                    self.in_grace_period = true;
+                    grace_sleep.as_mut().reset(Instant::now() + remaining_grace);
+                    return Ok(false);
+                }
                 self.change_position_and_notify(PositionChange::Increment).await?;
                 Ok(true)
             }
+            _ = sleep_until(self.wake_until.unwrap_or_else(Instant::now)), if self.wake_until.is_some() => {
+                self.wake_until = None;
+                self.child_port.request(IdlenessMessage::EndWake).await?;
+                Ok(false)
+            }
+            update = next_sleep_update(self.sleep_updates.as_mut()) => {
+                match update {
+                    Ok(SleepUpdate::GoingToSleep(confirmation_sender, _)) => {
+                        self.suspend_clock.going_to_sleep();
+                        if let Err(e) = confirmation_sender.try_send(ReadyToSleep) {
+                            log::error!("Couldn't confirm readiness for sleep: {}", e);
+                        }
+                    }
+                    Ok(SleepUpdate::WokenUp) => {
+                        let suspended = self.suspend_clock.woken_up();
+                        if !suspended.is_zero() {
+                            self.account_for_suspend(suspended, sleep, grace_sleep);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => self.sleep_updates = None,
+                }
+                Ok(false)
+            }
             change_result = self.state_channel.changed() => {
                 log::debug!("Display server channel fired");
                 change_result?;
                 let new_state = *self.state_channel.borrow_and_update();
+                let was_idle_during_hold = new_state == SystemState::Awakened
+                    && self.idle_during_hold.take().is_some();
                 let ds_position = if self.initial_position_dirty {
                     self.current_position
                 } else {
                     0
                 };
                 match (self.current_position, new_state) {
+                    (0, SystemState::Awakened) if self.in_grace_period => {
+                        log::debug!("Activity during grace period, not going idle");
+                        self.in_grace_period = false;
+                        Ok(false)
+                    }
+                    (position, SystemState::Awakened) if position == ds_position && was_idle_during_hold => {
+                        log::debug!("Activity during hold, not going idle");
+                        Ok(false)
+                    }
+                    (position, SystemState::Idle) if position == ds_position && self.is_held() => {
+                        log::debug!("Position is held, waiting for the hold to expire");
+                        self.idle_during_hold = Some(Instant::now());
+                        Ok(false)
+                    }
+                    (0, SystemState::Idle) if !self.grace_period.is_zero() => {
+                        log::debug!("Starting grace period of {:?}", self.grace_period);
+                        self.in_grace_period = true;
+                        grace_sleep.as_mut().reset(Instant::now() + self.grace_period);
+                        Ok(false)
+                    }
                     (position, SystemState::Awakened) if position == ds_position => {
                         log::error!("Received an unexpected awake from display server, is something else setting the timeouts?");
                         Ok(false)
@@ -201,16 +425,40 @@ impl<C: DisplayServerController> Sequencer<C> {
             },
             res = self.command_receiver.as_mut().unwrap().recv() => {
                 log::debug!("Command receiver fired");
-                match res {
+                let req = match res {
                     None => return Err(anyhow::Error::new(PortDropped)),
-                    Some(req) => {
-                        if req.respond(Ok(self.get_running_time())).is_err() {
-                            log::error!("Couldn't respond to actor request, actor is probably dead. Terminating.");
-                            return Err(anyhow::Error::new(PortDropped));
-                        }
+                    Some(req) => req,
+                };
+                let (was_state_change, result) = match req.payload {
+                    SequencerCommand::GetRunningTime => (false, Ok(())),
+                    SequencerCommand::Reset => (self.reset().await?, Ok(())),
+                    SequencerCommand::ReplaceSequence(replacement) => (
+                        false,
+                        self.replace_sequence(*replacement)
+                            .await
+                            .context("Couldn't replace the sequence"),
+                    ),
+                    SequencerCommand::Hold(duration) => {
+                        self.hold(duration);
+                        (false, Ok(()))
                     }
+                    SequencerCommand::Wake(duration) => (
+                        false,
+                        self.wake(duration).await.context("Couldn't wake"),
+                    ),
                 };
-                Ok(false)
+                let response = match result {
+                    Ok(()) => Ok(self.get_running_time()),
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        Err(())
+                    }
+                };
+                if req.respond(response).is_err() {
+                    log::error!("Couldn't respond to actor request, actor is probably dead. Terminating.");
+                    return Err(anyhow::Error::new(PortDropped));
+                }
+                Ok(was_state_change)
             }
         }
     }
@@ -220,17 +468,147 @@ impl<C: DisplayServerController> Sequencer<C> {
         let reset_result = self
             .set_ds_timeout(self.original_timeout.unwrap_or(-1i16))
             .await;
+        if reset_result.is_ok() {
+            self.state_journal
+                .update(|state| state.original_idleness_timeout = None);
+        }
         self.child_port.await_shutdown().await;
         log::debug!("Stopped");
         reset_result
     }
 
+    fn report_next_position(&self, sleep_deadline: Instant, grace_deadline: Instant) {
+        let next_position_at = if self.idle_during_hold.is_some() {
+            self.hold_until
+        } else if self.in_grace_period {
+            Some(grace_deadline)
+        } else if self.position_handleable_by_sleep() {
+            Some(sleep_deadline)
+        } else {
+            None
+        };
+        let idle_since = self.idle_since();
+        let effects_since = idle_since.map(|since| since + self.timeout_sequence[0]);
+        if let Some(reporter) = self.state_reporter.as_ref() {
+            reporter.update(|state| {
+                state.next_effect_at = next_position_at;
+                state.idle_since = idle_since;
+                state.effects_since = effects_since;
+                state.held_until = self.hold_until.filter(|_| self.is_held());
+            });
+        }
+    }
+
     fn position_handleable_by_sleep(&self) -> bool {
         self.current_position != 0
             && self.current_position < self.timeout_sequence.len()
             && !self.initial_position_dirty
     }
 
+    fn is_held(&self) -> bool {
+        matches!(self.hold_until, Some(until) if until > Instant::now())
+    }
+
+    fn hold(&mut self, duration: Duration) {
+        if duration.is_zero() {
+            log::info!("Hold ended");
+        } else {
+            log::info!("Holding the position for {:?}", duration);
+        }
+        self.hold_until = Some(Instant::now() + duration);
+    }
+
+    /// Move the deadlines earlier by the time the system spent suspended,
+    /// during which tokio's timers were stopped
+    fn account_for_suspend(
+        &mut self,
+        suspended: Duration,
+        sleep: &mut std::pin::Pin<&mut tokio::time::Sleep>,
+        grace_sleep: &mut std::pin::Pin<&mut tokio::time::Sleep>,
+    ) {
+        log::debug!("System was suspended for {:?}", suspended);
+        let earlier = |instant: Instant| instant.checked_sub(suspended).unwrap_or(instant);
+        self.position_changed_at = earlier(self.position_changed_at);
+        if self.position_handleable_by_sleep() {
+            sleep.as_mut().reset(earlier(sleep.deadline()));
+        }
+        if self.in_grace_period {
+            grace_sleep.as_mut().reset(earlier(grace_sleep.deadline()));
+        }
+        self.hold_until = self.hold_until.map(earlier);
+        self.idle_during_hold = self.idle_during_hold.map(earlier);
+        self.wake_until = self.wake_until.map(earlier);
+    }
+
+    async fn wake(&mut self, duration: Duration) -> Result<()> {
+        if self.current_position == 0 {
+            log::debug!("Wake requested at the beginning of the sequence, nothing to wake");
+            return Ok(());
+        }
+        if self.wake_until.is_none() {
+            self.child_port.request(IdlenessMessage::Wake).await?;
+        }
+        log::info!("Waking for {:?}", duration);
+        self.wake_until = Some(Instant::now() + duration);
+        Ok(())
+    }
+
+    /// Go back to position 0, rolling the effects back, unless the sequence
+    /// is already there. Returns whether the position has changed.
+    async fn reset(&mut self) -> Result<bool> {
+        self.in_grace_period = false;
+        self.idle_during_hold = None;
+        if self.current_position == 0 {
+            log::debug!("Reset requested, already at the beginning of the sequence");
+            return Ok(false);
+        }
+        log::info!("Reset requested, going back to the beginning of the sequence");
+        self.change_position_and_notify(PositionChange::Reset)
+            .await?;
+        Ok(true)
+    }
+
+    /// Switch the IdlenessController to the new bunches and continue with the
+    /// new sequence from its starting position
+    async fn replace_sequence(&mut self, replacement: SequenceReplacement) -> Result<()> {
+        let SequenceReplacement {
+            timeouts,
+            starting_position,
+            shorten_initial_sleep_by,
+            grace_period,
+            bunches,
+        } = replacement;
+        self.child_port
+            .request(IdlenessMessage::ReplaceBunches(Box::new(bunches)))
+            .await?;
+        log::info!(
+            "Replacing the sequence, continuing from position {}",
+            starting_position
+        );
+        self.timeout_sequence = timeouts;
+        // The new bunches don't know about the effects rolled back by a wake
+        self.wake_until = None;
+        if self.current_position != starting_position {
+            self.idle_during_hold = None;
+        }
+        self.current_position = starting_position;
+        self.position_changed_at = Instant::now();
+        self.shorten_initial_sleep_by = shorten_initial_sleep_by;
+        self.grace_period = grace_period;
+        self.sequence_replaced = true;
+        // Like on spawn, the display server keeps timing the current position
+        // until the user becomes active
+        self.initial_position_dirty =
+            self.current_position != 0 && *self.state_channel.borrow() == SystemState::Awakened;
+        let ds_position = if self.initial_position_dirty {
+            self.current_position
+        } else {
+            0
+        };
+        self.set_ds_timeout(ds_timeout(self.timeout_sequence[ds_position]))
+            .await
+    }
+
     async fn change_position_and_notify(&mut self, change: PositionChange) -> Result<()> {
         // This method may seem needlessly complicated - why can't we just send
         // the result to actor and if it's successful, change the position and
@@ -239,7 +617,7 @@ impl<C: DisplayServerController> Sequencer<C> {
         // In single-threaded runtimes, like the one used in tests, the task
         // would not necessarily run further after calling ActorPort#request,
         // meaning the position and time wouldn't get updated and the tests
-        // would not be able to test GetRunningTime functionality. Also, since
+        // would not be able to test SequencerCommand::GetRunningTime functionality. Also, since
         // the tests use tokio's time shifting functionality, they cannot use
         // a multi-threaded runtime.
         let original_position = self.current_position;
@@ -250,13 +628,35 @@ impl<C: DisplayServerController> Sequencer<C> {
             }
             PositionChange::Reset => {
                 self.current_position = 0;
+                // Effects rolled back by a wake stay rolled back
+                self.wake_until = None;
                 SystemState::Awakened
             }
         };
         assert!(self.current_position <= self.timeout_sequence.len());
         self.position_changed_at = Instant::now();
 
-        if let Err(e) = self.child_port.request(message_for_actor).await {
+        // Requests sent while handling the change share its trace
+        let trace_id = armaf::TraceId::new();
+        log::debug!("Sending {:?} in {}", message_for_actor, trace_id);
+        let message = IdlenessMessage::SystemState(message_for_actor);
+        let result = match change {
+            // Effects can take a while to apply, the user shouldn't have to
+            // wait for all of them before they get rolled back
+            PositionChange::Increment => {
+                let request = trace_id.scope(self.child_port.request(message));
+                let mut activity = self.state_channel.clone();
+                select! {
+                    result = request => result,
+                    _ = wait_for_activity(&mut activity) => {
+                        log::info!("Activity while the position was changing, cancelling the change");
+                        Ok(None)
+                    }
+                }
+            }
+            PositionChange::Reset => trace_id.scope(self.activity_port.request(message)).await,
+        };
+        if let Err(e) = result {
             self.current_position = original_position;
             self.position_changed_at = Instant::now();
             Err(anyhow::Error::new(e))
@@ -275,18 +675,35 @@ impl<C: DisplayServerController> Sequencer<C> {
         if self.current_position == 0 {
             return Duration::ZERO;
         }
-        let step_times: u64 = self.timeout_sequence[0..self.current_position].iter().sum();
+        let step_times: Duration = self.timeout_sequence[0..self.current_position].iter().sum();
         log::debug!(
-            "Step time sum: {}, additionally elapsed: {:?}",
+            "Step time sum: {:?}, additionally elapsed: {:?}",
             step_times,
             self.position_changed_at.elapsed()
         );
-        Duration::from_secs(step_times).saturating_add(self.position_changed_at.elapsed())
+        // The grace period is deliberately not counted, so that the running
+        // time stays comparable with the positions of other sequences
+        step_times.saturating_add(self.position_changed_at.elapsed())
+    }
+
+    /// The instant at which the running time started, [None] while the user
+    /// is active. Unlike `Instant::now() - running_time`, it doesn't change
+    /// between calls, so it can be reported without spurious updates.
+    fn idle_since(&self) -> Option<Instant> {
+        if self.current_position == 0 {
+            return None;
+        }
+        let step_times: Duration = self.timeout_sequence[0..self.current_position].iter().sum();
+        Some(
+            self.position_changed_at
+                .checked_sub(step_times)
+                .unwrap_or(self.position_changed_at),
+        )
     }
 
     async fn force_activity(&mut self) {
         log::debug!("Recovering from actor error by forcing display server to be active");
-        if let Err(e) = self.controller.force_activity() {
+        if let Err(e) = self.controller.force_activity().await {
             log::error!(
                 "Couldn't force activity on display server, effects will be stopped until next awake-idle cycle: {}",
             e);
@@ -325,3 +742,35 @@ impl<C: DisplayServerController> Sequencer<C> {
         }
     }
 }
+
+/// Receive the next sleep update, never finishing without a subscription
+async fn next_sleep_update(
+    updates: Option<&mut broadcast::Receiver<SleepUpdate>>,
+) -> Result<SleepUpdate, broadcast::error::RecvError> {
+    match updates {
+        Some(updates) => updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Convert a timeout to the display server's idleness timeout, which is in
+/// whole seconds. Fractions of a second are rounded up, so that the display
+/// server doesn't report idleness before the timeout passes.
+fn ds_timeout(timeout: Duration) -> i16 {
+    let mut seconds = timeout.as_secs();
+    if timeout.subsec_nanos() != 0 {
+        seconds += 1;
+    }
+    seconds.min(i16::MAX as u64) as i16
+}
+
+async fn wait_for_activity(state_channel: &mut watch::Receiver<SystemState>) {
+    loop {
+        if state_channel.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+        if *state_channel.borrow() == SystemState::Awakened {
+            return;
+        }
+    }
+}
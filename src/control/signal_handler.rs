@@ -0,0 +1,126 @@
+//! Reacts to the Unix signals sent to the daemon - terminates on SIGINT and
+//! SIGTERM, reloads the configuration on SIGHUP and logs diagnostics on
+//! SIGUSR1
+
+use super::{
+    config_watcher::reload_config, environment_controller::EnvironmentPort,
+    manager_state::ManagerState,
+};
+use crate::{
+    armaf::{list_actors, ActorInfo},
+    config::ConfigSources,
+};
+use anyhow::{Context, Result};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+    time::Instant,
+};
+
+/// Listens for signals until the daemon should terminate.
+///
+/// The signals are registered when the handler is created, so that signals
+/// arriving during the rest of the startup aren't lost and SIGTERM doesn't
+/// kill the daemon without rolling the effects back.
+pub struct SignalHandler {
+    sources: ConfigSources,
+    environment_controller: EnvironmentPort,
+    state: watch::Receiver<ManagerState>,
+    interrupt: Signal,
+    terminate: Signal,
+    hangup: Signal,
+    user_defined: Signal,
+}
+
+impl SignalHandler {
+    /// Register the handled signals, reloading the configuration from the
+    /// sources and reporting the state received from the receiver
+    pub fn new(
+        sources: ConfigSources,
+        environment_controller: EnvironmentPort,
+        state: watch::Receiver<ManagerState>,
+    ) -> Result<SignalHandler> {
+        Ok(SignalHandler {
+            sources,
+            environment_controller,
+            state,
+            interrupt: register(SignalKind::interrupt(), "SIGINT")?,
+            terminate: register(SignalKind::terminate(), "SIGTERM")?,
+            hangup: register(SignalKind::hangup(), "SIGHUP")?,
+            user_defined: register(SignalKind::user_defined1(), "SIGUSR1")?,
+        })
+    }
+
+    /// Handle SIGHUP and SIGUSR1 until SIGINT or SIGTERM is received
+    pub async fn wait_for_termination(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.interrupt.recv() => {
+                    log::info!("Received SIGINT, shutting down");
+                    return;
+                }
+                _ = self.terminate.recv() => {
+                    log::info!("Received SIGTERM, shutting down");
+                    return;
+                }
+                _ = self.hangup.recv() => {
+                    log::info!("Received SIGHUP, reloading configuration");
+                    reload_config(&self.sources, &self.environment_controller).await;
+                }
+                _ = self.user_defined.recv() => {
+                    let state = self.state.borrow().clone();
+                    for line in status_report(&list_actors(), &state, Instant::now()) {
+                        log::info!("{}", line);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn register(kind: SignalKind, name: &str) -> Result<Signal> {
+    signal(kind).with_context(|| format!("Couldn't register a handler of {}", name))
+}
+
+/// Describe the actors and the power management's state, one line of the log
+/// at a time
+pub fn status_report(actors: &[ActorInfo], state: &ManagerState, now: Instant) -> Vec<String> {
+    let in_future = |at: Option<Instant>| match at {
+        Some(at) => format!("in {:?}", at.saturating_duration_since(now)),
+        None => "never".to_owned(),
+    };
+    let since = |at: Option<Instant>| match at {
+        Some(at) => format!("for {:?}", now.saturating_duration_since(at)),
+        None => "no".to_owned(),
+    };
+    let mut report = vec![format!("Status report, {} actors:", actors.len())];
+    for actor in actors {
+        report.push(format!(
+            "  actor {} {} is {}",
+            actor.id,
+            actor.name,
+            actor.health.name()
+        ));
+    }
+    report.push(format!(
+        "  schedule {}, next bunch {}, paused: {}",
+        state
+            .schedule_type
+            .as_ref()
+            .map(|schedule_type| format!("{:?}", schedule_type))
+            .unwrap_or_else(|| "not started".to_owned()),
+        state.current_bunch,
+        state.paused
+    ));
+    report.push(format!(
+        "  applied effects: [{}]",
+        state.applied_effects.join(", ")
+    ));
+    report.push(format!(
+        "  idle: {}, next effect: {}, hold ends: {}",
+        since(state.idle_since),
+        in_future(state.next_effect_at),
+        in_future(state.held_until)
+    ));
+    report
+}
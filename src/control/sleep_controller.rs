@@ -1,16 +1,151 @@
-//! Once notified about the system going to sleep, locks the computer
-use tokio::sync::{broadcast, mpsc};
+//! Once notified about the system going to sleep, runs the configured hooks,
+//! by default locking the computer. After waking up, wakes the display and
+//! restarts the schedule.
+use std::time::Duration;
 
+use anyhow::{anyhow, Context, Result};
+use tokio::{
+    process::Command,
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
+
+use super::{
+    effector_inventory::{self as ei, InventoryPort},
+    environment_controller::{EnvironmentCommand, EnvironmentPort},
+};
 use crate::{
-    armaf,
+    armaf::{self, RollbackStrategy},
     external::display_server::DisplayServerController,
     system::sleep_sensor::{ReadyToSleep, SleepUpdate},
 };
 
+/// Name of the configuration section containing the sleep hooks
+pub const SLEEP_HOOKS_SECTION: &str = "sleep_hooks";
+
+/// How long a hook's command may run, logind only delays sleep for a few
+/// seconds
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Time left for confirming the sleep readiness once the deadline for the
+/// hooks passes
+const CONFIRMATION_MARGIN: Duration = Duration::from_millis(200);
+
+/// Something to do before the system goes to sleep or after it wakes up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SleepHook {
+    /// Apply the named effect before sleep, roll it back after waking up
+    Effect(String),
+    /// Run a command and wait for it to finish
+    Command { command: String, args: Vec<String> },
+}
+
+impl std::fmt::Display for SleepHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SleepHook::Effect(name) => write!(f, "{}", name),
+            SleepHook::Command { command, .. } => write!(f, "command {}", command),
+        }
+    }
+}
+
+/// Hooks run by [SleepController], in the order in which they're listed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SleepHooks {
+    pub before_sleep: Vec<SleepHook>,
+    pub after_wake: Vec<SleepHook>,
+}
+
+impl Default for SleepHooks {
+    /// Just lock the computer before sleep, as when there's no
+    /// `[sleep_hooks]` section
+    fn default() -> Self {
+        SleepHooks {
+            before_sleep: vec![SleepHook::Effect("lock".to_owned())],
+            after_wake: Vec::new(),
+        }
+    }
+}
+
+/// Parse the `[sleep_hooks]` configuration section. A missing list of hooks
+/// in an existing section means no hooks.
+pub fn parse_sleep_hooks(config: &toml::Value) -> Result<SleepHooks> {
+    let section = match config.get(SLEEP_HOOKS_SECTION) {
+        Some(section) => section,
+        None => return Ok(SleepHooks::default()),
+    };
+    Ok(SleepHooks {
+        before_sleep: parse_hook_list(section, "before_sleep", false)?,
+        after_wake: parse_hook_list(section, "after_wake", true)?,
+    })
+}
+
+fn parse_hook_list(section: &toml::Value, key: &str, rolled_back: bool) -> Result<Vec<SleepHook>> {
+    let context = || format!("Invalid {}.{}", SLEEP_HOOKS_SECTION, key);
+    let hooks = match section.get(key) {
+        Some(hooks) => hooks,
+        None => return Ok(Vec::new()),
+    };
+    let hooks = hooks
+        .as_array()
+        .ok_or_else(|| anyhow!("must be an array of effect names and commands"))
+        .with_context(context)?;
+    let known_effects = ei::resolve_effectors_for_effects();
+    hooks
+        .iter()
+        .map(|hook| parse_hook(hook, &known_effects, rolled_back))
+        .collect::<Result<Vec<SleepHook>>>()
+        .with_context(context)
+}
+
+fn parse_hook(
+    hook: &toml::Value,
+    known_effects: &std::collections::HashMap<String, (String, usize)>,
+    rolled_back: bool,
+) -> Result<SleepHook> {
+    if let Some(name) = hook.as_str() {
+        let (effector_name, index) = known_effects
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown effect {}", name))?;
+        let effect = &ei::get_effects_for_effector(effector_name)[*index];
+        if rolled_back && matches!(effect.rollback_strategy, RollbackStrategy::None) {
+            return Err(anyhow!("effect {} can't be rolled back", name));
+        }
+        return Ok(SleepHook::Effect(name.to_owned()));
+    }
+    let command = hook
+        .get("command")
+        .and_then(|command| command.as_str())
+        .ok_or_else(|| {
+            anyhow!(
+                "{} is neither an effect name nor a table with a command",
+                hook
+            )
+        })?;
+    let args = match hook.get("args") {
+        None => Vec::new(),
+        Some(args) => args
+            .as_array()
+            .and_then(|args| {
+                args.iter()
+                    .map(|arg| arg.as_str().map(str::to_owned))
+                    .collect::<Option<Vec<String>>>()
+            })
+            .ok_or_else(|| anyhow!("args of {} must be an array of strings", command))?,
+    };
+    Ok(SleepHook::Command {
+        command: command.to_owned(),
+        args,
+    })
+}
+
 pub struct SleepController<C: DisplayServerController> {
     sleep_channel: broadcast::Receiver<SleepUpdate>,
     lock_effector: Option<armaf::EffectorPort>,
     ds_controller: C,
+    hooks: SleepHooks,
+    inventory: Option<InventoryPort>,
+    environment_controller: Option<EnvironmentPort>,
     handle_child: Option<armaf::HandleChild>,
 }
 
@@ -24,10 +159,32 @@ impl<C: DisplayServerController> SleepController<C> {
             sleep_channel,
             lock_effector,
             ds_controller,
+            hooks: SleepHooks::default(),
+            inventory: None,
+            environment_controller: None,
             handle_child: None,
         }
     }
 
+    /// Run the given hooks instead of just locking the computer. Effects
+    /// other than `lock` are looked up in the given inventory.
+    pub fn with_hooks(mut self, hooks: SleepHooks, inventory: InventoryPort) -> SleepController<C> {
+        self.hooks = hooks;
+        self.inventory = Some(inventory);
+        self
+    }
+
+    /// Reset the schedule of the given controller after waking up, so that
+    /// it starts from its beginning even if the display server doesn't
+    /// report the activity
+    pub fn with_environment_controller(
+        mut self,
+        environment_controller: EnvironmentPort,
+    ) -> SleepController<C> {
+        self.environment_controller = Some(environment_controller);
+        self
+    }
+
     pub async fn spawn(mut self) -> armaf::Handle {
         let (handle, handle_child) = armaf::Handle::new();
         self.handle_child = Some(handle_child);
@@ -53,9 +210,11 @@ impl<C: DisplayServerController> SleepController<C> {
                         }
                         Ok(SleepUpdate::WokenUp) => {
                             self.force_activity().await;
+                            self.reset_schedule().await;
+                            self.run_hooks(false).await;
                         }
-                        Ok(SleepUpdate::GoingToSleep(ack_channel)) => {
-                            self.handle_sleep(ack_channel).await;
+                        Ok(SleepUpdate::GoingToSleep(ack_channel, deadline)) => {
+                            self.handle_sleep(ack_channel, deadline).await;
                         }
                     }
                 }
@@ -63,22 +222,160 @@ impl<C: DisplayServerController> SleepController<C> {
         }
     }
 
-    async fn handle_sleep(&mut self, ack_channel: mpsc::Sender<ReadyToSleep>) {
-        if let Some(ref effector) = self.lock_effector {
-            if let Err(e) = effector.request(armaf::EffectorMessage::Execute).await {
-                log::error!("Failed to lock system before going to sleep: {}", e);
-            }
+    /// Run the hooks before sleep, waiting e.g. for the locker to lock the
+    /// session, but only until shortly before the system goes to sleep anyway
+    async fn handle_sleep(&mut self, ack_channel: mpsc::Sender<ReadyToSleep>, deadline: Instant) {
+        let hooks_deadline = deadline
+            .checked_sub(CONFIRMATION_MARGIN)
+            .unwrap_or(deadline);
+        if tokio::time::timeout_at(hooks_deadline, self.run_hooks(true))
+            .await
+            .is_err()
+        {
+            log::warn!("Hooks didn't finish before the system goes to sleep");
         }
         if let Err(e) = ack_channel.send(ReadyToSleep).await {
             log::error!("Acknowledging sleep readiness failed: {}", e);
         }
     }
 
+    /// Run the hooks for going to sleep or waking up. A failed hook is
+    /// logged and the rest still run, sleep mustn't be held up by them.
+    async fn run_hooks(&self, before_sleep: bool) {
+        let hooks = if before_sleep {
+            &self.hooks.before_sleep
+        } else {
+            &self.hooks.after_wake
+        };
+        for hook in hooks {
+            log::debug!("Running sleep hook {}", hook);
+            let result = match hook {
+                SleepHook::Effect(name) => self.run_effect_hook(name, before_sleep).await,
+                SleepHook::Command { command, args } => run_command_hook(command, args).await,
+            };
+            if let Err(e) = result {
+                log::error!("Sleep hook {} failed: {:?}", hook, e);
+            }
+        }
+    }
+
+    async fn run_effect_hook(&self, effect_name: &str, before_sleep: bool) -> Result<()> {
+        // The effect may already be applied by the schedule or rolled back
+        // by the user
+        let message = if before_sleep {
+            armaf::EffectorMessage::EnsureApplied
+        } else {
+            armaf::EffectorMessage::EnsureRolledBack
+        };
+        match self.effect_port(effect_name).await? {
+            Some(port) => {
+                port.request(message).await?;
+            }
+            None => log::debug!("Locker is not configured, not running {}", effect_name),
+        }
+        Ok(())
+    }
+
+    /// Get the port of the effector providing the effect, [None] for the lock
+    /// effector if it isn't configured
+    async fn effect_port(&self, effect_name: &str) -> Result<Option<armaf::EffectorPort>> {
+        let mapping = ei::resolve_effectors_for_effects();
+        let (effector_name, _) = mapping
+            .get(effect_name)
+            .ok_or_else(|| anyhow!("Unknown effect {}", effect_name))?;
+        match (
+            effector_name.as_str(),
+            self.lock_effector.as_ref(),
+            self.inventory.as_ref(),
+        ) {
+            ("lock", lock_effector, _) => Ok(lock_effector.cloned()),
+            (_, _, Some(inventory)) => {
+                Ok(Some(ei::get_effector_port(inventory, effector_name).await?))
+            }
+            _ => Err(anyhow!("Effector {} is not available", effector_name)),
+        }
+    }
+
+    async fn reset_schedule(&self) {
+        if let Some(environment_controller) = self.environment_controller.as_ref() {
+            if let Err(e) = environment_controller
+                .request(EnvironmentCommand::ResetSchedule)
+                .await
+            {
+                log::error!("Couldn't reset the schedule after waking up: {:?}", e);
+            }
+        }
+    }
+
     async fn force_activity(&mut self) {
-        let sent_controller = self.ds_controller.clone();
-        if let Err(e) = tokio::task::spawn_blocking(move || sent_controller.force_activity()).await
-        {
+        if let Err(e) = self.ds_controller.force_activity().await {
             log::error!("Couldn't force activate display server: {}", e);
         }
     }
 }
+
+async fn run_command_hook(command: &str, args: &[String]) -> Result<()> {
+    let mut child = Command::new(command)
+        .args(args)
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Couldn't run {}", command))?;
+    let status = tokio::time::timeout(COMMAND_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| anyhow!("{} didn't finish within {:?}", command, COMMAND_TIMEOUT))??;
+    if !status.success() {
+        return Err(anyhow!("{} failed with {}", command, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sleep_hooks() {
+        assert_eq!(
+            parse_sleep_hooks(&toml::toml! { [lock] command = "i3lock" }).unwrap(),
+            SleepHooks::default()
+        );
+        let config = toml::toml! {
+            [sleep_hooks]
+            before_sleep = ["lock", { command = "nmcli", args = ["networking", "off"] }]
+        };
+        assert_eq!(
+            parse_sleep_hooks(&config).unwrap(),
+            SleepHooks {
+                before_sleep: vec![
+                    SleepHook::Effect("lock".to_owned()),
+                    SleepHook::Command {
+                        command: "nmcli".to_owned(),
+                        args: vec!["networking".to_owned(), "off".to_owned()]
+                    }
+                ],
+                after_wake: Vec::new(),
+            }
+        );
+        assert!(parse_sleep_hooks(&toml::toml! {
+            [sleep_hooks]
+            after_wake = ["explode"]
+        })
+        .is_err());
+        // Rolling the lock back would wait until the user unlocks
+        assert!(parse_sleep_hooks(&toml::toml! {
+            [sleep_hooks]
+            after_wake = ["lock"]
+        })
+        .is_err());
+        assert!(parse_sleep_hooks(&toml::toml! {
+            [sleep_hooks]
+            before_sleep = [{ args = ["off"] }]
+        })
+        .is_err());
+        assert!(parse_sleep_hooks(&toml::toml! {
+            [sleep_hooks]
+            before_sleep = "lock"
+        })
+        .is_err());
+    }
+}
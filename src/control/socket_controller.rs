@@ -0,0 +1,430 @@
+//! Exposes the control API over a Unix socket, for systems which don't run a
+//! session D-Bus
+//!
+//! Each line sent to the socket is a JSON object with a `command` key and the
+//! command's arguments, e.g. `{"command": "pause", "seconds": 600}`. Each
+//! request is answered with a single line, either `{"ok": true, "result": ...}`
+//! or `{"ok": false, "error": "..."}`.
+
+use super::{
+    environment_controller::{EnvironmentCommand, EnvironmentPort},
+    manager_state::ManagerState,
+    remote_control::EffectTrigger,
+};
+use crate::{
+    armaf::{self, EffectorMessage, EffectorPort, EffectorResponse, Handle},
+    system::inhibition_sensor::{ApplicationInhibition, ApplicationInhibitions},
+};
+use anyhow::{anyhow, Context, Result};
+use logind_zbus::manager::InhibitType;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, watch},
+    time::Instant,
+};
+
+/// A command received on the socket
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum SocketRequest {
+    /// Get the state of the power management
+    Status,
+    /// List the actors of the daemon and their health
+    Actors,
+    /// Lock the computer
+    Lock,
+    /// Pause the schedule for the given number of seconds, or until resumed
+    /// if 0 or nothing is given
+    Pause {
+        #[serde(default)]
+        seconds: u64,
+    },
+    /// Resume the paused schedule
+    Resume,
+    /// Keep the next effects from being applied for the given number of
+    /// seconds, ending the hold if 0 is given
+    Hold { seconds: u64 },
+    /// Choose the profile matched by the schedule rules, clearing it if no
+    /// name is given
+    Profile {
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Apply a triggerable effect
+    TriggerEffect { effect: String },
+    /// Roll a triggerable effect back
+    RollbackEffect { effect: String },
+    /// Get the state of the effector of an effect
+    EffectStatus { effect: String },
+    /// Inhibit idleness until the inhibition is removed or the client
+    /// disconnects
+    Inhibit { application: String, reason: String },
+    /// Remove an inhibition created on the same connection
+    UnInhibit { cookie: u32 },
+}
+
+/// Listen on a Unix socket and execute the commands of the programs which
+/// connect to it
+pub struct SocketController {
+    path: PathBuf,
+    lock_effector: Option<EffectorPort>,
+    state: watch::Receiver<ManagerState>,
+    environment_controller: Option<EnvironmentPort>,
+    effect_trigger: Option<EffectTrigger>,
+    inhibitions: Option<ApplicationInhibitions>,
+}
+
+impl SocketController {
+    /// Create a new SocketController listening on the given path
+    pub fn new(
+        path: &Path,
+        lock_effector: Option<EffectorPort>,
+        state: watch::Receiver<ManagerState>,
+    ) -> SocketController {
+        SocketController {
+            path: path.to_owned(),
+            lock_effector,
+            state,
+            environment_controller: None,
+            effect_trigger: None,
+            inhibitions: None,
+        }
+    }
+
+    /// Allow controlling the given [EnvironmentController], e.g. pausing the
+    /// schedule
+    ///
+    /// [EnvironmentController]: super::environment_controller::EnvironmentController
+    pub fn with_environment_controller(
+        mut self,
+        environment_controller: EnvironmentPort,
+    ) -> SocketController {
+        self.environment_controller = Some(environment_controller);
+        self
+    }
+
+    /// Allow applying and rolling back effects on demand through the given
+    /// [EffectTrigger]
+    pub fn with_effect_trigger(mut self, effect_trigger: EffectTrigger) -> SocketController {
+        self.effect_trigger = Some(effect_trigger);
+        self
+    }
+
+    /// Allow the clients to inhibit idleness, adding their inhibitions to the
+    /// given [ApplicationInhibitions]
+    pub fn with_application_inhibitions(
+        mut self,
+        inhibitions: ApplicationInhibitions,
+    ) -> SocketController {
+        self.inhibitions = Some(inhibitions);
+        self
+    }
+
+    /// Spawn the SocketController actor
+    pub async fn spawn(self) -> Result<Handle> {
+        // A socket left over by a crashed instance would prevent binding
+        if self.path.exists() {
+            if std::os::unix::net::UnixStream::connect(&self.path).is_ok() {
+                return Err(anyhow!("Another program is listening on {:?}", self.path));
+            }
+            std::fs::remove_file(&self.path)
+                .with_context(|| format!("Couldn't remove stale socket {:?}", self.path))?;
+        }
+        let listener = UnixListener::bind(&self.path)
+            .with_context(|| format!("Couldn't bind control socket {:?}", self.path))?;
+        // Any process able to connect could put the computer to sleep
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))?;
+        log::debug!("Listening on {:?}", self.path);
+
+        let (handle, mut handle_child) = Handle::new();
+        let controller = Arc::new(self);
+        tokio::spawn(async move {
+            let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+            // Each connection holds a sender, so we know when all of them are gone
+            let (connections_sender, mut connections_receiver) = mpsc::channel::<()>(1);
+            let mut connection_count = 0u64;
+            loop {
+                tokio::select! {
+                    _ = handle_child.should_terminate() => break,
+                    accepted = listener.accept() => {
+                        let stream = match accepted {
+                            Ok((stream, _)) => stream,
+                            Err(e) => {
+                                log::error!("Couldn't accept connection: {}", e);
+                                continue;
+                            }
+                        };
+                        connection_count += 1;
+                        let owner = format!("socket:{}", connection_count);
+                        let controller = controller.clone();
+                        let shutdown_receiver = shutdown_receiver.clone();
+                        let connections_sender = connections_sender.clone();
+                        tokio::spawn(async move {
+                            controller.serve_connection(stream, &owner, shutdown_receiver).await;
+                            drop(connections_sender);
+                        });
+                    }
+                }
+            }
+            let _ = shutdown_sender.send(true);
+            drop(connections_sender);
+            connections_receiver.recv().await;
+            if let Err(e) = std::fs::remove_file(&controller.path) {
+                log::error!("Couldn't remove control socket: {}", e);
+            }
+            log::debug!("Terminated");
+        });
+        Ok(handle)
+    }
+
+    async fn serve_connection(
+        &self,
+        stream: UnixStream,
+        owner: &str,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        log::debug!("{} connected", owner);
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            let line = tokio::select! {
+                _ = shutdown.changed() => break,
+                line = lines.next_line() => line,
+            };
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Couldn't read from {}: {}", owner, e);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match self.handle_line(&line, owner).await {
+                Ok(result) => json!({ "ok": true, "result": result }),
+                Err(e) => json!({ "ok": false, "error": format!("{:#}", e) }),
+            };
+            if let Err(e) = writer.write_all(format!("{}\n", response).as_bytes()).await {
+                log::warn!("Couldn't respond to {}: {}", owner, e);
+                break;
+            }
+        }
+        if let Some(inhibitions) = self.inhibitions.as_ref() {
+            let removed = inhibitions.remove_owner(owner);
+            if removed > 0 {
+                log::info!(
+                    "{} disconnected, removed its {} inhibition(s)",
+                    owner,
+                    removed
+                );
+            }
+        }
+        log::debug!("{} disconnected", owner);
+    }
+
+    async fn handle_line(&self, line: &str, owner: &str) -> Result<Value> {
+        let request: SocketRequest = serde_json::from_str(line).context("Invalid request")?;
+        self.handle_request(request, owner).await
+    }
+
+    async fn handle_request(&self, request: SocketRequest, owner: &str) -> Result<Value> {
+        match request {
+            SocketRequest::Status => Ok(self.status()),
+            SocketRequest::Actors => Ok(armaf::list_actors()
+                .into_iter()
+                .map(|info| {
+                    json!({
+                        "id": info.id,
+                        "name": info.name,
+                        "health": info.health.name(),
+                        "started_at": info
+                            .started_at
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                    })
+                })
+                .collect()),
+            SocketRequest::Lock => {
+                let port = self
+                    .lock_effector
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Lock effector is not configured"))?;
+                log::info!("Locking system");
+                port.request(EffectorMessage::Execute).await?;
+                Ok(Value::Null)
+            }
+            SocketRequest::Pause { seconds } => {
+                let duration = match seconds {
+                    0 => None,
+                    seconds => Some(Duration::from_secs(seconds)),
+                };
+                log::info!("Pause requested over control socket");
+                self.send_environment_command(EnvironmentCommand::Pause(duration))
+                    .await
+            }
+            SocketRequest::Hold { seconds } => {
+                log::info!("Hold requested over control socket");
+                self.send_environment_command(EnvironmentCommand::HoldSchedule(
+                    Duration::from_secs(seconds),
+                ))
+                .await
+            }
+            SocketRequest::Resume => {
+                log::info!("Resume requested over control socket");
+                self.send_environment_command(EnvironmentCommand::Resume)
+                    .await
+            }
+            SocketRequest::Profile { name } => {
+                log::info!("Profile change requested over control socket");
+                self.send_environment_command(EnvironmentCommand::SetProfile(name))
+                    .await
+            }
+            SocketRequest::TriggerEffect { effect } => Ok(effector_response_json(
+                self.get_effect_trigger()?.trigger(&effect).await?,
+            )),
+            SocketRequest::RollbackEffect { effect } => Ok(effector_response_json(
+                self.get_effect_trigger()?.rollback(&effect).await?,
+            )),
+            SocketRequest::EffectStatus { effect } => Ok(effector_response_json(
+                self.get_effect_trigger()?.status(&effect).await?,
+            )),
+            SocketRequest::Inhibit {
+                application,
+                reason,
+            } => {
+                log::info!("{} ({}) inhibits idleness: {}", application, owner, reason);
+                let cookie = self.get_inhibitions()?.add(ApplicationInhibition {
+                    owner: owner.to_owned(),
+                    application,
+                    reason,
+                    inhibit_type: InhibitType::Idle,
+                });
+                Ok(json!({ "cookie": cookie }))
+            }
+            SocketRequest::UnInhibit { cookie } => {
+                match self.get_inhibitions()?.remove(owner, cookie) {
+                    Some(inhibition) => {
+                        log::info!(
+                            "{} ({}) stopped inhibiting idleness",
+                            inhibition.application,
+                            owner
+                        );
+                        Ok(Value::Null)
+                    }
+                    None => Err(anyhow!(
+                        "No inhibition with cookie {} belongs to this connection",
+                        cookie
+                    )),
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> Value {
+        let state = self.state.borrow();
+        json!({
+            "schedule_type": state.schedule_type.as_ref().map(|typ| typ.config_name()),
+            "current_bunch": state.current_bunch,
+            "applied_effects": state.applied_effects,
+            "paused": state.paused,
+            "time_until_next_effect_ms": state
+                .next_effect_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_millis() as u64),
+            "hold_remaining_ms": state
+                .held_until
+                .map(|at| at.saturating_duration_since(Instant::now()).as_millis() as u64),
+        })
+    }
+
+    async fn send_environment_command(&self, command: EnvironmentCommand) -> Result<Value> {
+        let port = self
+            .environment_controller
+            .as_ref()
+            .ok_or_else(|| anyhow!("Schedule can't be controlled through this socket"))?;
+        port.request(command).await?;
+        Ok(Value::Null)
+    }
+
+    fn get_effect_trigger(&self) -> Result<&EffectTrigger> {
+        self.effect_trigger
+            .as_ref()
+            .ok_or_else(|| anyhow!("Effects can't be triggered through this socket"))
+    }
+
+    fn get_inhibitions(&self) -> Result<&ApplicationInhibitions> {
+        self.inhibitions
+            .as_ref()
+            .ok_or_else(|| anyhow!("Idleness can't be inhibited through this socket"))
+    }
+}
+
+fn effector_response_json(response: EffectorResponse) -> Value {
+    json!({
+        "applied_effects": response.applied_effects,
+        "status": response.status,
+        "can_roll_back": response.capabilities.can_roll_back,
+        "rollback_waits": response.capabilities.rollback_waits,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_parsing() {
+        let parse = |line: &str| serde_json::from_str::<SocketRequest>(line);
+        assert_eq!(
+            parse(r#"{"command": "status"}"#).unwrap(),
+            SocketRequest::Status
+        );
+        assert_eq!(
+            parse(r#"{"command": "actors"}"#).unwrap(),
+            SocketRequest::Actors
+        );
+        assert_eq!(
+            parse(r#"{"command": "pause"}"#).unwrap(),
+            SocketRequest::Pause { seconds: 0 }
+        );
+        assert_eq!(
+            parse(r#"{"command": "hold", "seconds": 2700}"#).unwrap(),
+            SocketRequest::Hold { seconds: 2700 }
+        );
+        assert_eq!(
+            parse(r#"{"command": "profile", "name": "presentation"}"#).unwrap(),
+            SocketRequest::Profile {
+                name: Some("presentation".to_owned())
+            }
+        );
+        assert_eq!(
+            parse(r#"{"command": "profile"}"#).unwrap(),
+            SocketRequest::Profile { name: None }
+        );
+        assert_eq!(
+            parse(r#"{"command": "trigger_effect", "effect": "screen_off"}"#).unwrap(),
+            SocketRequest::TriggerEffect {
+                effect: "screen_off".to_owned()
+            }
+        );
+        assert_eq!(
+            parse(r#"{"command": "effect_status", "effect": "screen_dim"}"#).unwrap(),
+            SocketRequest::EffectStatus {
+                effect: "screen_dim".to_owned()
+            }
+        );
+        assert!(parse(r#"{"command": "explode"}"#).is_err());
+        assert!(parse(r#"{"command": "un_inhibit"}"#).is_err());
+    }
+}
@@ -0,0 +1,717 @@
+//! Daily totals of the time the user spent active and idle, the time each
+//! effect was applied and the time the computer was suspended, along with the
+//! energy drawn from the battery meanwhile
+
+use super::{
+    environment_controller::format_duration,
+    manager_state::{EffectEvent, EffectTransition},
+};
+use crate::{
+    armaf::{Handle, HandleChild, TickService, Ticks},
+    external::display_server::SystemState,
+    system::{
+        clock_sensor::ClockJump,
+        sleep_sensor::{ReadyToSleep, SleepUpdate},
+        upower_sensor::BatterySample,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{broadcast, watch};
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+/// How often the totals are written to the store while Energia runs
+const FLUSH_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+/// What the time is accounted to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Active,
+    Idle,
+    Suspended,
+    Effect(String),
+}
+
+impl Category {
+    fn name(&self) -> String {
+        match self {
+            Category::Active => "active".to_owned(),
+            Category::Idle => "idle".to_owned(),
+            Category::Suspended => "suspended".to_owned(),
+            Category::Effect(name) => format!("effect:{}", name),
+        }
+    }
+
+    fn parse(name: &str) -> Option<Category> {
+        match name {
+            "active" => Some(Category::Active),
+            "idle" => Some(Category::Idle),
+            "suspended" => Some(Category::Suspended),
+            _ => name
+                .strip_prefix("effect:")
+                .map(|effect| Category::Effect(effect.to_owned())),
+        }
+    }
+}
+
+/// Minimal time on battery from which the average power draw is reported
+const MIN_MEASURED_TIME: Duration = Duration::from_secs(60);
+
+/// Time accounted to a category and the energy drawn from the battery during
+/// it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    pub time: Duration,
+    /// Part of the time during which the power draw from the battery was known
+    pub battery_time: Duration,
+    /// Energy drawn from the battery during the battery time, in joules
+    pub energy: f64,
+}
+
+impl Totals {
+    /// Average power drawn from the battery in watts, if it has been measured
+    /// for long enough
+    pub fn average_power(&self) -> Option<f64> {
+        if self.battery_time < MIN_MEASURED_TIME {
+            return None;
+        }
+        Some(self.energy / self.battery_time.as_secs_f64())
+    }
+}
+
+impl AddAssign for Totals {
+    fn add_assign(&mut self, other: Totals) {
+        self.time += other.time;
+        self.battery_time += other.battery_time;
+        self.energy += other.energy;
+    }
+}
+
+/// Totals of each category for every day (in UTC, formatted as `YYYY-MM-DD`),
+/// kept in a CSV file with `date,category,seconds,battery_seconds,energy_joules`
+/// rows
+#[derive(Debug, Default)]
+pub struct StatisticsStore {
+    path: Option<PathBuf>,
+    days: BTreeMap<String, BTreeMap<Category, Totals>>,
+}
+
+impl StatisticsStore {
+    /// `$XDG_STATE_HOME/energia/statistics.csv`, with `$XDG_STATE_HOME`
+    /// defaulting to `~/.local/state`
+    pub fn default_path() -> PathBuf {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .unwrap_or_else(|| {
+                PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/state")
+            });
+        state_home.join("energia").join("statistics.csv")
+    }
+
+    /// Open the store at the given path, loading the totals recorded in it
+    pub fn open(path: &Path) -> Result<StatisticsStore> {
+        let mut store = StatisticsStore {
+            path: Some(path.to_owned()),
+            days: BTreeMap::new(),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(store),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Couldn't read statistics {:?}", path))
+            }
+        };
+        for (number, line) in contents.lines().enumerate().skip(1) {
+            let (date, category, totals) = parse_row(line)
+                .with_context(|| format!("Invalid row {} in statistics {:?}", number + 1, path))?;
+            *store
+                .days
+                .entry(date.to_owned())
+                .or_default()
+                .entry(category)
+                .or_default() += totals;
+        }
+        Ok(store)
+    }
+
+    /// Totals of each category, by day
+    pub fn days(&self) -> &BTreeMap<String, BTreeMap<Category, Totals>> {
+        &self.days
+    }
+
+    /// Account the time between the two instants to the category, splitting
+    /// it among the days it spans. If the power drawn from the battery
+    /// meanwhile is given in watts, the energy is accounted as well.
+    pub fn add(
+        &mut self,
+        category: Category,
+        from: SystemTime,
+        to: SystemTime,
+        power: Option<f64>,
+    ) {
+        let mut from = seconds_since_epoch(from);
+        let to = seconds_since_epoch(to);
+        while from < to {
+            let day = (from / SECONDS_PER_DAY as f64).floor();
+            let day_end = (day + 1.0) * SECONDS_PER_DAY as f64;
+            let end = to.min(day_end);
+            let time = Duration::from_secs_f64(end - from);
+            *self
+                .days
+                .entry(format_date(day as i64))
+                .or_default()
+                .entry(category.clone())
+                .or_default() += Totals {
+                time,
+                battery_time: power.map(|_| time).unwrap_or_default(),
+                energy: power.map(|power| power * (end - from)).unwrap_or_default(),
+            };
+            from = end;
+        }
+    }
+
+    /// Write the totals to the store's file
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut contents = String::from("date,category,seconds,battery_seconds,energy_joules\n");
+        for (date, day_totals) in self.days.iter() {
+            for (category, totals) in day_totals.iter() {
+                contents.push_str(&format!(
+                    "{},{},{:.3},{:.3},{:.1}\n",
+                    date,
+                    category.name(),
+                    totals.time.as_secs_f64(),
+                    totals.battery_time.as_secs_f64(),
+                    totals.energy,
+                ));
+            }
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Couldn't create state directory {:?}", dir))?;
+        }
+        let temporary_path = path.with_extension("csv.tmp");
+        fs::write(&temporary_path, contents)?;
+        fs::rename(&temporary_path, path)?;
+        Ok(())
+    }
+}
+
+/// Parse a row, which may lack the battery columns if it has been written by
+/// an older version
+fn parse_row(line: &str) -> Result<(&str, Category, Totals)> {
+    let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+    if columns.len() != 3 && columns.len() != 5 {
+        return Err(anyhow!("Expected 3 or 5 columns"));
+    }
+    let category =
+        Category::parse(columns[1]).ok_or_else(|| anyhow!("Unknown category {}", columns[1]))?;
+    let numbers = columns[2..]
+        .iter()
+        .map(|column| match column.parse::<f64>() {
+            Ok(number) if number.is_finite() && number >= 0.0 => Ok(number),
+            _ => Err(anyhow!("Invalid number {}", column)),
+        })
+        .collect::<Result<Vec<f64>>>()?;
+    let totals = Totals {
+        time: Duration::from_secs_f64(numbers[0]),
+        battery_time: Duration::from_secs_f64(numbers.get(1).copied().unwrap_or_default()),
+        energy: numbers.get(2).copied().unwrap_or_default(),
+    };
+    Ok((columns[0], category, totals))
+}
+
+fn seconds_since_epoch(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Format the day with the given number since the Unix epoch as `YYYY-MM-DD`
+fn format_date(days_since_epoch: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = days_since_epoch + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Accounts the time to the categories as the user becomes idle or active,
+/// effects get applied and rolled back and the computer sleeps, writing the
+/// totals to a [StatisticsStore] regularly and on termination
+pub struct StatisticsCollector {
+    store: StatisticsStore,
+    idleness_channel: watch::Receiver<SystemState>,
+    effect_events: broadcast::Receiver<EffectEvent>,
+    sleep_updates: Option<broadcast::Receiver<SleepUpdate>>,
+    clock_jumps: Option<broadcast::Receiver<ClockJump>>,
+    battery_samples: Option<watch::Receiver<BatterySample>>,
+    /// Power currently drawn from the battery, in watts
+    power: Option<f64>,
+    ticks: Ticks,
+    activity: SystemState,
+    activity_since: SystemTime,
+    applied_since: HashMap<String, SystemTime>,
+    suspended_since: Option<SystemTime>,
+    handle_child: Option<HandleChild>,
+}
+
+impl StatisticsCollector {
+    pub fn new(
+        store: StatisticsStore,
+        idleness_channel: watch::Receiver<SystemState>,
+        effect_events: broadcast::Receiver<EffectEvent>,
+        tick_service: &TickService,
+    ) -> StatisticsCollector {
+        let activity = *idleness_channel.borrow();
+        StatisticsCollector {
+            store,
+            idleness_channel,
+            effect_events,
+            sleep_updates: None,
+            clock_jumps: None,
+            battery_samples: None,
+            power: None,
+            ticks: tick_service.subscribe(FLUSH_PERIOD),
+            activity,
+            activity_since: SystemTime::now(),
+            applied_since: HashMap::new(),
+            suspended_since: None,
+            handle_child: None,
+        }
+    }
+
+    /// Account the time the computer spends sleeping
+    pub fn with_sleep_updates(
+        mut self,
+        sleep_updates: broadcast::Receiver<SleepUpdate>,
+    ) -> StatisticsCollector {
+        self.sleep_updates = Some(sleep_updates);
+        self
+    }
+
+    /// Keep the running intervals consistent when the system clock jumps,
+    /// so that a jump isn't accounted as time spent in the current categories
+    pub fn with_clock_jumps(
+        mut self,
+        clock_jumps: broadcast::Receiver<ClockJump>,
+    ) -> StatisticsCollector {
+        self.clock_jumps = Some(clock_jumps);
+        self
+    }
+
+    /// Account the energy drawn from the battery, as sampled on the given
+    /// channel
+    pub fn with_battery_samples(
+        mut self,
+        battery_samples: watch::Receiver<BatterySample>,
+    ) -> StatisticsCollector {
+        self.power = battery_samples.borrow().power_draw();
+        self.battery_samples = Some(battery_samples);
+        self
+    }
+
+    pub fn spawn(mut self) -> Handle {
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+            self.account(SystemTime::now());
+            self.save();
+            log::debug!("Terminated");
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => return,
+                _ = self.ticks.tick() => {
+                    self.account(SystemTime::now());
+                    self.save();
+                }
+                changed = self.idleness_channel.changed() => {
+                    if changed.is_err() {
+                        log::error!("Idleness channel closed, statistics won't be collected");
+                        return;
+                    }
+                    // Account the time to the state which has just ended
+                    self.account(SystemTime::now());
+                    self.activity = *self.idleness_channel.borrow_and_update();
+                }
+                event = self.effect_events.recv() => match event {
+                    Ok(event) => self.record_effect_event(event),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Statistics missed {} effect events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        log::error!("Effect events closed, statistics won't be collected");
+                        return;
+                    }
+                },
+                update = async { self.sleep_updates.as_mut().unwrap().recv().await }, if self.sleep_updates.is_some() => match update {
+                    Ok(update) => self.record_sleep_update(update),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Statistics missed {} sleep updates", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => self.sleep_updates = None,
+                },
+                jump = async { self.clock_jumps.as_mut().unwrap().recv().await }, if self.clock_jumps.is_some() => match jump {
+                    Ok(jump) => self.record_clock_jump(jump),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Statistics missed {} clock jumps", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => self.clock_jumps = None,
+                },
+                changed = async { self.battery_samples.as_mut().unwrap().changed().await }, if self.battery_samples.is_some() => {
+                    if changed.is_err() {
+                        self.battery_samples = None;
+                        self.power = None;
+                        continue;
+                    }
+                    // The time until now was spent drawing the previous power
+                    self.account(SystemTime::now());
+                    self.power = self.battery_samples.as_mut().unwrap().borrow_and_update().power_draw();
+                }
+            }
+        }
+    }
+
+    /// Account the time since the last accounting up to now to the current
+    /// categories
+    fn account(&mut self, now: SystemTime) {
+        if let Some(suspended_since) = self.suspended_since {
+            self.store
+                .add(Category::Suspended, suspended_since, now, None);
+            self.suspended_since = Some(now);
+            return;
+        }
+        let activity = match self.activity {
+            SystemState::Awakened => Category::Active,
+            SystemState::Idle => Category::Idle,
+        };
+        self.store
+            .add(activity, self.activity_since, now, self.power);
+        self.activity_since = now;
+        for (effect_name, applied_since) in self.applied_since.iter_mut() {
+            self.store.add(
+                Category::Effect(effect_name.clone()),
+                *applied_since,
+                now,
+                self.power,
+            );
+            *applied_since = now;
+        }
+    }
+
+    fn record_effect_event(&mut self, event: EffectEvent) {
+        if let Some(samples) = self.battery_samples.as_ref() {
+            let sample = *samples.borrow();
+            if sample.on_battery {
+                log::debug!(
+                    "{:?} {} at {:.0}% battery, drawing {:.1} W",
+                    event.transition,
+                    event.effect_name,
+                    sample.percentage,
+                    sample.energy_rate
+                );
+            }
+        }
+        match event.transition {
+            EffectTransition::Applied => {
+                self.applied_since
+                    .entry(event.effect_name)
+                    .or_insert(event.timestamp);
+            }
+            EffectTransition::RolledBack => {
+                if let Some(applied_since) = self.applied_since.remove(&event.effect_name) {
+                    // Time spent suspended has been accounted already
+                    if self.suspended_since.is_none() {
+                        self.store.add(
+                            Category::Effect(event.effect_name),
+                            applied_since,
+                            event.timestamp,
+                            self.power,
+                        );
+                    }
+                }
+            }
+            EffectTransition::ApplicationFailed | EffectTransition::RollbackFailed => {}
+        }
+    }
+
+    fn record_sleep_update(&mut self, update: SleepUpdate) {
+        let now = SystemTime::now();
+        match update {
+            SleepUpdate::GoingToSleep(confirmation_sender, _) => {
+                if self.suspended_since.is_none() {
+                    self.account(now);
+                    self.suspended_since = Some(now);
+                }
+                self.save();
+                if let Err(e) = confirmation_sender.try_send(ReadyToSleep) {
+                    log::error!("Couldn't confirm readiness for sleep: {}", e);
+                }
+            }
+            SleepUpdate::WokenUp => {
+                if let Some(suspended_since) = self.suspended_since.take() {
+                    self.store
+                        .add(Category::Suspended, suspended_since, now, None);
+                }
+                self.activity_since = now;
+                for applied_since in self.applied_since.values_mut() {
+                    *applied_since = now;
+                }
+            }
+        }
+    }
+
+    /// Move the starts of the running intervals along with the clock, the
+    /// time zone doesn't matter since the timestamps are in UTC
+    fn record_clock_jump(&mut self, jump: ClockJump) {
+        let shift = |time: SystemTime| {
+            let offset = Duration::from_secs(jump.offset.unsigned_abs());
+            if jump.offset >= 0 {
+                time.checked_add(offset)
+            } else {
+                time.checked_sub(offset)
+            }
+            .unwrap_or(time)
+        };
+        self.activity_since = shift(self.activity_since);
+        self.suspended_since = self.suspended_since.map(shift);
+        for applied_since in self.applied_since.values_mut() {
+            *applied_since = shift(*applied_since);
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.store.save() {
+            log::error!("Couldn't save statistics: {:?}", e);
+        }
+    }
+}
+
+/// Print the totals of the last given number of days from the store at the
+/// given path and return the exit code with which the process should end
+pub fn print_report(path: &Path, days: usize) -> i32 {
+    let store = match StatisticsStore::open(path) {
+        Ok(store) => store,
+        Err(e) => {
+            println!("error: {:?}", e);
+            return 1;
+        }
+    };
+    if store.days().is_empty() {
+        println!("No statistics have been recorded in {} yet", path.display());
+        return 0;
+    }
+    let skipped = store.days().len().saturating_sub(days);
+    let mut overall: BTreeMap<&Category, Totals> = BTreeMap::new();
+    for (date, day_totals) in store.days().iter().skip(skipped) {
+        println!("{}", date);
+        for (category, totals) in day_totals.iter() {
+            println!(
+                "  {:<24} {}",
+                category_label(category),
+                format_duration(Duration::from_secs(totals.time.as_secs()))
+            );
+            *overall.entry(category).or_default() += *totals;
+        }
+    }
+    let lines = power_report(&overall);
+    if !lines.is_empty() {
+        println!("Average power drawn from the battery");
+        for line in lines {
+            println!("  {}", line);
+        }
+    }
+    0
+}
+
+fn category_label(category: &Category) -> String {
+    match category {
+        Category::Active => "Active".to_owned(),
+        Category::Idle => "Idle".to_owned(),
+        Category::Suspended => "Suspended".to_owned(),
+        Category::Effect(name) => format!("Effect {}", name),
+    }
+}
+
+/// Describe the measured power draw of each category, along with the power
+/// each effect saves compared to active use
+fn power_report(totals: &BTreeMap<&Category, Totals>) -> Vec<String> {
+    let active_power = totals
+        .get(&Category::Active)
+        .and_then(Totals::average_power);
+    totals
+        .iter()
+        .filter_map(|(category, totals)| {
+            let power = totals.average_power()?;
+            let mut line = format!("{:<24} {:.1} W", category_label(category), power);
+            if let (Category::Effect(_), Some(active_power)) = (category, active_power) {
+                line.push_str(&format!(", saves ~{:.1} W", active_power - power));
+            }
+            Some(line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(59), "1970-03-01");
+        assert_eq!(format_date(11016), "2000-02-29");
+        assert_eq!(format_date(20376), "2025-10-15");
+    }
+
+    #[test]
+    fn test_add_splits_days() {
+        let mut store = StatisticsStore::default();
+        let midnight = 20376 * SECONDS_PER_DAY;
+        store.add(
+            Category::Idle,
+            at(midnight - 600),
+            at(midnight + 60),
+            Some(5.0),
+        );
+        store.add(Category::Idle, at(midnight + 120), at(midnight + 180), None);
+        let days = store.days();
+        assert_eq!(
+            days["2025-10-14"][&Category::Idle],
+            Totals {
+                time: Duration::from_secs(600),
+                battery_time: Duration::from_secs(600),
+                energy: 3000.0,
+            }
+        );
+        assert_eq!(
+            days["2025-10-15"][&Category::Idle],
+            Totals {
+                time: Duration::from_secs(120),
+                battery_time: Duration::from_secs(60),
+                energy: 300.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let path = env::temp_dir().join(format!("energia-statistics-{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = StatisticsStore::open(&path).unwrap();
+        assert!(store.days().is_empty());
+        store.add(Category::Active, at(0), at(3600), None);
+        store.add(
+            Category::Effect("screen_off".to_owned()),
+            at(60),
+            at(90),
+            Some(2.5),
+        );
+        store.save().unwrap();
+
+        let reopened = StatisticsStore::open(&path).unwrap();
+        assert_eq!(reopened.days(), store.days());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "date,category,seconds,battery_seconds,energy_joules\n\
+             1970-01-01,active,3600.000,0.000,0.0\n\
+             1970-01-01,effect:screen_off,30.000,30.000,75.0\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_row() {
+        assert_eq!(
+            parse_row("1970-01-01,idle,12.5").unwrap().2,
+            Totals {
+                time: Duration::from_secs_f64(12.5),
+                ..Totals::default()
+            }
+        );
+        assert_eq!(
+            parse_row("1970-01-01,idle,12.5,10,50").unwrap().2,
+            Totals {
+                time: Duration::from_secs_f64(12.5),
+                battery_time: Duration::from_secs(10),
+                energy: 50.0,
+            }
+        );
+        assert!(parse_row("1970-01-01,idle,12.5,10").is_err());
+        assert!(parse_row("1970-01-01,sleeping,12.5").is_err());
+        assert!(parse_row("1970-01-01,idle,-1").is_err());
+        assert!(parse_row("1970-01-01,idle").is_err());
+    }
+
+    #[test]
+    fn test_power_report() {
+        let active = Category::Active;
+        let screen_off = Category::Effect("screen_off".to_owned());
+        let suspended = Category::Suspended;
+        let mut totals = BTreeMap::new();
+        totals.insert(
+            &active,
+            Totals {
+                time: Duration::from_secs(7200),
+                battery_time: Duration::from_secs(3600),
+                energy: 36000.0,
+            },
+        );
+        totals.insert(
+            &screen_off,
+            Totals {
+                time: Duration::from_secs(600),
+                battery_time: Duration::from_secs(600),
+                energy: 3480.0,
+            },
+        );
+        totals.insert(
+            &suspended,
+            Totals {
+                time: Duration::from_secs(600),
+                ..Totals::default()
+            },
+        );
+        assert_eq!(
+            power_report(&totals),
+            vec![
+                "Active                   10.0 W".to_owned(),
+                "Effect screen_off        5.8 W, saves ~4.2 W".to_owned(),
+            ]
+        );
+    }
+}
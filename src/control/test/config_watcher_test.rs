@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use crate::{
+    armaf::ActorPort,
+    config::ConfigSources,
+    control::{config_watcher::ConfigWatcher, environment_controller::EnvironmentCommand},
+};
+
+fn reloaded_config(command: &EnvironmentCommand) -> toml::Value {
+    match command {
+        EnvironmentCommand::ReloadConfig(config) => config.as_ref().clone(),
+        other => panic!("Unexpected command {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_reload_on_write() {
+    let directory = std::env::temp_dir().join(format!(
+        "energia_config_watcher_test_{}",
+        std::process::id()
+    ));
+    tokio::fs::create_dir_all(&directory).await.unwrap();
+    let path = directory.join("config.toml");
+    tokio::fs::write(&path, "timeout = 1").await.unwrap();
+
+    let (port, mut receiver) = ActorPort::<EnvironmentCommand, (), anyhow::Error>::make();
+    let sources = ConfigSources {
+        system_file: None,
+        user_file: path.clone(),
+        user_file_required: true,
+        drop_in_dir: None,
+    };
+    let handle = ConfigWatcher::new(sources, port).spawn().unwrap();
+
+    // Files other than the configuration file are ignored
+    tokio::fs::write(directory.join("other.toml"), "timeout = 3")
+        .await
+        .unwrap();
+    // Invalid configurations are not sent to the controller
+    tokio::fs::write(&path, "timeout = ").await.unwrap();
+    tokio::fs::write(&path, "timeout = 2").await.unwrap();
+
+    let request = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("No reload request received")
+        .unwrap();
+    assert_eq!(
+        reloaded_config(&request.payload),
+        toml::toml! { timeout = 2 }
+    );
+    request.respond(Ok(())).unwrap();
+
+    // The watcher may still be waiting for a response to a request caused by
+    // the invalid write, if it read the file only after the second write
+    drop(receiver);
+    handle.await_shutdown().await;
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_on_new_fragment() {
+    let directory = std::env::temp_dir().join(format!(
+        "energia_config_watcher_fragment_test_{}",
+        std::process::id()
+    ));
+    let drop_in_dir = directory.join("conf.d");
+    tokio::fs::create_dir_all(&drop_in_dir).await.unwrap();
+    let path = directory.join("config.toml");
+    tokio::fs::write(&path, "timeout = 1\nfoo = 1")
+        .await
+        .unwrap();
+
+    let (port, mut receiver) = ActorPort::<EnvironmentCommand, (), anyhow::Error>::make();
+    let sources = ConfigSources {
+        system_file: None,
+        user_file: path.clone(),
+        user_file_required: true,
+        drop_in_dir: Some(drop_in_dir.clone()),
+    };
+    let handle = ConfigWatcher::new(sources, port).spawn().unwrap();
+
+    tokio::fs::write(drop_in_dir.join("10-timeout.toml"), "timeout = 2")
+        .await
+        .unwrap();
+
+    let request = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("No reload request received")
+        .unwrap();
+    assert_eq!(
+        reloaded_config(&request.payload),
+        toml::toml! { timeout = 2 foo = 1 }
+    );
+    request.respond(Ok(())).unwrap();
+
+    drop(receiver);
+    handle.await_shutdown().await;
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
+}
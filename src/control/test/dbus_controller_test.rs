@@ -1,6 +1,23 @@
+use logind_zbus::manager::InhibitType;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+
 use crate::{
     armaf::ActorPort,
-    control::{dbus_controller::DBusController, test::effects_counter::EffectsCounter},
+    control::{
+        dbus_controller::DBusController, environment_controller::ScheduleType,
+        manager_state::StateReporter, test::effects_counter::EffectsCounter,
+    },
+    system::{
+        inhibition_sensor::ApplicationInhibitions,
+        upower_sensor::{BatterySample, PowerStatus},
+    },
 };
 
 #[tokio::test]
@@ -9,7 +26,8 @@ async fn test_locking() {
     let path = "/org/energia/test_dbus_locking";
     let name = "org.energia.lock_test.Manager";
     let ec = EffectsCounter::new();
-    let dbus_controller = DBusController::new(path, name, Some(ec.get_port()));
+    let dbus_controller =
+        DBusController::new(path, name, Some(ec.get_port()), StateReporter::new().1);
     let handle = dbus_controller
         .spawn()
         .await
@@ -46,7 +64,7 @@ async fn test_errors() {
     let path = "/org/energia/test_dbus_errors";
     let name = "org.energia.errors_test.Manager";
     let (port, _) = ActorPort::make();
-    let dbus_controller = DBusController::new(path, name, Some(port));
+    let dbus_controller = DBusController::new(path, name, Some(port), StateReporter::new().1);
     let handle = dbus_controller
         .spawn()
         .await
@@ -65,7 +83,7 @@ async fn test_errors() {
 async fn test_without_locker() {
     let path = "/org/energia/test_dbus_no_locker";
     let name = "org.energia.no_locker_test.Manager";
-    let dbus_controller = DBusController::new(path, name, None);
+    let dbus_controller = DBusController::new(path, name, None, StateReporter::new().1);
     let handle = dbus_controller
         .spawn()
         .await
@@ -78,3 +96,239 @@ async fn test_without_locker() {
     assert!(result.is_err());
     handle.await_shutdown().await;
 }
+
+#[tokio::test]
+#[ignore]
+async fn test_state_properties() {
+    let path = "/org/energia/test_dbus_state";
+    let name = "org.energia.state_test.Manager";
+    let (reporter, state) = StateReporter::new();
+    let dbus_controller = DBusController::new(path, name, None, state);
+    let handle = dbus_controller
+        .spawn()
+        .await
+        .expect("Couldn't start controller");
+    reporter.update(|state| {
+        state.schedule_type = Some(ScheduleType::Battery);
+        state.applied_effects = vec!["screen_dim".to_owned()];
+    });
+
+    let our_connection = zbus::Connection::session().await.unwrap();
+    let properties = zbus::fdo::PropertiesProxy::builder(&our_connection)
+        .destination(name)
+        .unwrap()
+        .path(path)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let schedule_type = properties
+        .get("org.energia.Manager", "ScheduleType")
+        .await
+        .unwrap();
+    assert_eq!(String::try_from(schedule_type).unwrap(), "battery");
+    let applied_effects = properties
+        .get("org.energia.Manager", "AppliedEffects")
+        .await
+        .unwrap();
+    assert_eq!(
+        Vec::<String>::try_from(applied_effects).unwrap(),
+        vec!["screen_dim".to_owned()]
+    );
+    handle.await_shutdown().await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_power_properties() {
+    let path = "/org/energia/test_dbus_power";
+    let name = "org.energia.power_test.Manager";
+    let (power_status_sender, power_status) = watch::channel(PowerStatus::External);
+    let (_samples_sender, samples) = watch::channel(BatterySample {
+        on_battery: true,
+        percentage: 42.5,
+        energy_rate: 10.0,
+        time_to_empty: Duration::from_secs(5400),
+    });
+    let dbus_controller = DBusController::new(path, name, None, StateReporter::new().1)
+        .with_power_status(power_status)
+        .with_battery_samples(samples);
+    let handle = dbus_controller
+        .spawn()
+        .await
+        .expect("Couldn't start controller");
+
+    let our_connection = zbus::Connection::session().await.unwrap();
+    let properties = zbus::fdo::PropertiesProxy::builder(&our_connection)
+        .destination(name)
+        .unwrap()
+        .path(path)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let get = |property: &'static str| {
+        let properties = properties.clone();
+        async move {
+            properties
+                .get("org.energia.Manager", property)
+                .await
+                .unwrap()
+        }
+    };
+    assert_eq!(
+        String::try_from(get("PowerSource").await).unwrap(),
+        "external"
+    );
+    assert_eq!(f64::try_from(get("BatteryPercentage").await).unwrap(), 42.5);
+    assert_eq!(i64::try_from(get("TimeToEmpty").await).unwrap(), 5400);
+
+    power_status_sender.send(PowerStatus::Battery(42)).unwrap();
+    assert_eq!(
+        String::try_from(get("PowerSource").await).unwrap(),
+        "battery"
+    );
+    handle.await_shutdown().await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_screensaver_inhibitions() {
+    let path = "/org/energia/test_dbus_screensaver";
+    let name = "org.energia.screensaver_test.Manager";
+    let inhibitions = ApplicationInhibitions::new();
+    let activity_count = Arc::new(AtomicUsize::new(0));
+    let moved_count = activity_count.clone();
+    let dbus_controller = DBusController::new(path, name, None, StateReporter::new().1)
+        .with_screensaver(
+            inhibitions.clone(),
+            Arc::new(move || {
+                moved_count.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+        );
+    let handle = dbus_controller
+        .spawn()
+        .await
+        .expect("Couldn't start controller");
+
+    let client = zbus::Connection::session().await.unwrap();
+    let cookie: u32 = client
+        .call_method(
+            Some(name),
+            "/org/freedesktop/ScreenSaver",
+            Some("org.freedesktop.ScreenSaver"),
+            "Inhibit",
+            &("video player", "Playing video"),
+        )
+        .await
+        .unwrap()
+        .body()
+        .unwrap();
+    assert_eq!(inhibitions.list()[0].1.application, "video player");
+    let listed: Vec<(u32, String, String, String, String)> = client
+        .call_method(
+            Some(name),
+            path,
+            Some("org.energia.Manager"),
+            "ListInhibitions",
+            &(),
+        )
+        .await
+        .unwrap()
+        .body()
+        .unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].0, cookie);
+    assert_eq!(listed[0].1, client.unique_name().unwrap().as_str());
+    assert_eq!(listed[0].2, "video player");
+    assert_eq!(listed[0].4, "idle");
+
+    // Other clients can't remove the inhibition
+    let other_client = zbus::Connection::session().await.unwrap();
+    let result = other_client
+        .call_method(
+            Some(name),
+            "/org/freedesktop/ScreenSaver",
+            Some("org.freedesktop.ScreenSaver"),
+            "UnInhibit",
+            &cookie,
+        )
+        .await;
+    assert!(result.is_err());
+
+    other_client
+        .call_method(
+            Some(name),
+            "/ScreenSaver",
+            Some("org.freedesktop.ScreenSaver"),
+            "SimulateUserActivity",
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(activity_count.load(Ordering::SeqCst), 1);
+
+    // Inhibitions of disconnected clients are removed
+    drop(client);
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert!(inhibitions.list().is_empty());
+    handle.await_shutdown().await;
+}
+
+async fn call_power_management<B>(
+    client: &zbus::Connection,
+    name: &str,
+    method: &str,
+    body: &B,
+) -> zbus::Result<std::sync::Arc<zbus::Message>>
+where
+    B: serde::Serialize + zvariant::Type,
+{
+    client
+        .call_method(
+            Some(name),
+            "/org/freedesktop/PowerManagement/Inhibit",
+            Some("org.freedesktop.PowerManagement.Inhibit"),
+            method,
+            body,
+        )
+        .await
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_power_management_inhibitions() {
+    let path = "/org/energia/test_dbus_power_management";
+    let name = "org.energia.power_management_test.Manager";
+    let inhibitions = ApplicationInhibitions::new();
+    let dbus_controller = DBusController::new(path, name, None, StateReporter::new().1)
+        .with_power_management_inhibit(inhibitions.clone());
+    let handle = dbus_controller
+        .spawn()
+        .await
+        .expect("Couldn't start controller");
+
+    let client = zbus::Connection::session().await.unwrap();
+    let cookie: u32 = call_power_management(&client, name, "Inhibit", &("game", "Playing"))
+        .await
+        .unwrap()
+        .body()
+        .unwrap();
+    assert!(inhibitions.has(InhibitType::Sleep));
+    let has_inhibit: bool = call_power_management(&client, name, "HasInhibit", &())
+        .await
+        .unwrap()
+        .body()
+        .unwrap();
+    assert!(has_inhibit);
+
+    call_power_management(&client, name, "UnInhibit", &cookie)
+        .await
+        .unwrap();
+    assert!(!inhibitions.has(InhibitType::Sleep));
+    assert!(call_power_management(&client, name, "UnInhibit", &cookie)
+        .await
+        .is_err());
+    handle.await_shutdown().await;
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::{sync::watch, time::sleep};
+
+use crate::{
+    armaf::{spawn_server, Server, ShutdownCoordinator},
+    control::displays::{parse_displays, DisplayTree},
+    external::{
+        brightness::BrightnessController, dependency_provider::DependencyProvider,
+        display_server::SystemState,
+    },
+    system::{
+        inhibition_sensor::{ActiveInhibitor, GetInhibitions},
+        upower_sensor::PowerStatus,
+    },
+};
+
+struct NoInhibitions;
+
+#[async_trait]
+impl Server<GetInhibitions, Vec<ActiveInhibitor>> for NoInhibitions {
+    fn get_name(&self) -> String {
+        "NoInhibitions".to_owned()
+    }
+
+    async fn handle_message(&mut self, _: GetInhibitions) -> anyhow::Result<Vec<ActiveInhibitor>> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_parse_displays() {
+    let config = toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+
+        [brightness]
+        dim_percentage = 50
+
+        [displays.second]
+        display = ":1"
+
+        [displays.kiosk]
+        display = ":2"
+        [displays.kiosk.schedule.external]
+        screen_off = "5m"
+    };
+    let displays = parse_displays(&config).unwrap();
+    assert_eq!(displays.len(), 2);
+
+    assert_eq!(displays[0].name, "kiosk");
+    assert_eq!(displays[0].display, ":2");
+    assert_eq!(
+        *displays[0].config,
+        toml::toml! {
+            [schedule.external]
+            screen_off = "5m"
+
+            [brightness]
+            dim_percentage = 50
+        }
+    );
+
+    assert_eq!(displays[1].name, "second");
+    assert_eq!(displays[1].display, ":1");
+    assert_eq!(
+        *displays[1].config,
+        toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [brightness]
+            dim_percentage = 50
+        }
+    );
+
+    assert!(parse_displays(&toml::toml! { [brightness] })
+        .unwrap()
+        .is_empty());
+    let error = parse_displays(&toml::toml! {
+        [displays.second]
+        screen_dim = "1m"
+    })
+    .unwrap_err();
+    assert!(error.to_string().contains("displays.second.display"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_separate_schedules() {
+    let config = toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+
+        [displays.first]
+        display = ":1"
+
+        [displays.second]
+        display = ":2"
+        [displays.second.schedule.external]
+        screen_dim = "10m"
+    };
+    let displays = parse_displays(&config).unwrap();
+    let inhibition_sensor = spawn_server(NoInhibitions).await.unwrap();
+    let (_power_status_sender, power_status) = watch::channel(PowerStatus::External);
+
+    let mut display_servers = Vec::new();
+    let mut brightness_controllers = Vec::new();
+    let mut trees = Vec::new();
+    for display in &displays {
+        let (dependencies, display_server) =
+            DependencyProvider::make_mock_with_display_server(None);
+        brightness_controllers.push(dependencies.get_brightness_controller());
+        display_servers.push(display_server);
+        trees.push(
+            DisplayTree::spawn(
+                display,
+                dependencies,
+                inhibition_sensor.clone(),
+                power_status.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+    }
+
+    // Each display has its own timeout and only its own effects are applied
+    sleep(Duration::from_secs(60)).await;
+    display_servers[0]
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        brightness_controllers[0].get_brightness().await.unwrap(),
+        25
+    );
+    assert_eq!(
+        brightness_controllers[1].get_brightness().await.unwrap(),
+        50
+    );
+
+    let mut shutdown = ShutdownCoordinator::new(Duration::from_secs(5));
+    for tree in trees {
+        tree.add_to_shutdown(&mut shutdown);
+    }
+    assert!(shutdown.shutdown().await);
+}
@@ -0,0 +1,515 @@
+use crate::{
+    armaf::{
+        spawn_server, ActorPort, Effect, Effector, EffectorMessage, EffectorPort, EffectorResponse,
+        RestartPolicy, RollbackStrategy,
+    },
+    control::{
+        effector_inventory::{
+            get_effector_port, get_schedule_effector_port, parse_eager_spawning, request_effector,
+            scheduled_effectors, spawn_scheduled_effectors, EffectorInventory, InventoryMessage,
+        },
+        effector_registry::register_effector,
+        environment_controller::{DayType, ScheduleType},
+    },
+    external::{brightness::BrightnessController, dependency_provider::DependencyProvider},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[tokio::test]
+async fn test_effector_reuse() {
+    let dp = DependencyProvider::make_mock(None);
+    let config = toml::Value::Table(toml::value::Map::new());
+    let inventory = spawn_server(EffectorInventory::new(Arc::new(config), dp))
+        .await
+        .unwrap();
+    let first_port = get_effector_port(&inventory, "dpms").await.unwrap();
+    let second_port = get_effector_port(&inventory, "dpms").await.unwrap();
+    first_port.request(EffectorMessage::Execute).await.unwrap();
+    assert_eq!(
+        second_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        1
+    );
+    get_effector_port(&inventory, "unknown")
+        .await
+        .expect_err("Unknown effector spawned");
+}
+
+#[tokio::test]
+async fn test_config_reload() {
+    let dp = DependencyProvider::make_mock(None);
+    let brightness = dp.get_brightness_controller();
+    let inventory = spawn_server(EffectorInventory::new(
+        Arc::new(toml::toml! {
+            [brightness]
+            dim_percentage = 50
+        }),
+        dp,
+    ))
+    .await
+    .unwrap();
+
+    let old_brightness_port = get_effector_port(&inventory, "brightness").await.unwrap();
+    let old_dpms_port = get_effector_port(&inventory, "dpms").await.unwrap();
+    old_brightness_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    old_dpms_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 25);
+
+    inventory
+        .request(InventoryMessage::ReloadConfig(Arc::new(toml::toml! {
+            [brightness]
+            dim_percentage = 20
+        })))
+        .await
+        .unwrap();
+
+    // Effector with unchanged configuration is kept
+    let new_dpms_port = get_effector_port(&inventory, "dpms").await.unwrap();
+    assert_eq!(
+        new_dpms_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        1
+    );
+
+    // Effector with changed configuration is respawned, old one still works
+    let new_brightness_port = get_effector_port(&inventory, "brightness").await.unwrap();
+    assert_eq!(
+        new_brightness_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        0
+    );
+    old_brightness_port
+        .request(EffectorMessage::Rollback)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 50);
+    new_brightness_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn test_schedule_overrides() {
+    let dp = DependencyProvider::make_mock(None);
+    let brightness = dp.get_brightness_controller();
+    let inventory = spawn_server(EffectorInventory::new(
+        Arc::new(toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+
+            [schedule.battery.screen_dim]
+            after = "1m"
+            dim_percentage = 20
+
+            [brightness]
+            dim_percentage = 50
+        }),
+        dp,
+    ))
+    .await
+    .unwrap();
+
+    let default_port = get_effector_port(&inventory, "brightness").await.unwrap();
+    let external_port =
+        get_schedule_effector_port(&inventory, "brightness", ScheduleType::ExternalPower, None)
+            .await
+            .unwrap();
+    let battery_port =
+        get_schedule_effector_port(&inventory, "brightness", ScheduleType::Battery, None)
+            .await
+            .unwrap();
+
+    // Schedule without overrides shares the default instance
+    default_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(
+        external_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        1
+    );
+    assert_eq!(brightness.get_brightness().await.unwrap(), 25);
+    external_port
+        .request(EffectorMessage::Rollback)
+        .await
+        .unwrap();
+
+    // Schedule with overrides gets its own instance
+    assert_eq!(
+        battery_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        0
+    );
+    battery_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn test_day_schedule_overrides() {
+    let dp = DependencyProvider::make_mock(None);
+    let brightness = dp.get_brightness_controller();
+    let config = toml::toml! {
+        [schedule.battery.screen_dim]
+        after = "1m"
+        dim_percentage = 20
+
+        [schedule.battery.weekend.screen_dim]
+        after = "1m"
+        dim_percentage = 80
+    };
+    assert_eq!(
+        scheduled_effectors(&config).unwrap(),
+        vec![
+            ("brightness".to_owned(), ScheduleType::Battery, None),
+            (
+                "brightness".to_owned(),
+                ScheduleType::Battery,
+                Some(DayType::Weekend)
+            ),
+            ("session".to_owned(), ScheduleType::Battery, None),
+            (
+                "session".to_owned(),
+                ScheduleType::Battery,
+                Some(DayType::Weekend)
+            ),
+        ]
+    );
+    let inventory = spawn_server(EffectorInventory::new(Arc::new(config), dp))
+        .await
+        .unwrap();
+
+    let weekday_port = get_schedule_effector_port(
+        &inventory,
+        "brightness",
+        ScheduleType::Battery,
+        Some(DayType::Weekday),
+    )
+    .await
+    .unwrap();
+    let weekend_port = get_schedule_effector_port(
+        &inventory,
+        "brightness",
+        ScheduleType::Battery,
+        Some(DayType::Weekend),
+    )
+    .await
+    .unwrap();
+
+    weekday_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 10);
+    weekday_port
+        .request(EffectorMessage::Rollback)
+        .await
+        .unwrap();
+
+    // The weekend variant of the schedule gets its own instance
+    assert_eq!(
+        weekend_port
+            .request(EffectorMessage::CurrentlyAppliedEffects)
+            .await
+            .unwrap()
+            .applied_effects,
+        0
+    );
+    weekend_port
+        .request(EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 40);
+}
+
+#[tokio::test]
+async fn test_invalid_config() {
+    let invalid_config = toml::toml! {
+        [lock]
+        comand = "i3lock"
+    };
+    spawn_server(EffectorInventory::new(
+        Arc::new(invalid_config.clone()),
+        DependencyProvider::make_mock(None),
+    ))
+    .await
+    .expect_err("Inventory started with invalid configuration");
+
+    let inventory = spawn_server(EffectorInventory::new(
+        Arc::new(toml::toml! {
+            [brightness]
+            dim_percentage = 50
+        }),
+        DependencyProvider::make_mock(None),
+    ))
+    .await
+    .unwrap();
+    let error = inventory
+        .request(InventoryMessage::ReloadConfig(Arc::new(invalid_config)))
+        .await
+        .expect_err("Invalid configuration reloaded");
+    assert!(format!("{:#}", error).contains("lock.comand: unknown key"));
+    let error = inventory
+        .request(InventoryMessage::ReloadConfig(Arc::new(toml::toml! {
+            [schedule.battery.screen_dim]
+            after = "1m"
+            dim_percentage = "low"
+        })))
+        .await
+        .expect_err("Invalid schedule override reloaded");
+    assert!(format!("{:#}", error).contains("schedule battery"));
+
+    // Previous configuration is kept
+    get_effector_port(&inventory, "brightness").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_eager_spawning() {
+    let config = toml::toml! {
+        [effectors]
+        spawn = "eager"
+
+        [schedule.external]
+        screen_dim = "1m"
+        sleep = "5m"
+
+        [schedule.battery]
+        screen_off = "1m"
+    };
+    assert!(parse_eager_spawning(&config).unwrap());
+    assert_eq!(
+        scheduled_effectors(&config).unwrap(),
+        vec![
+            ("brightness".to_owned(), ScheduleType::ExternalPower, None),
+            ("dpms".to_owned(), ScheduleType::Battery, None),
+            // Added to the first bunch of every schedule
+            ("session".to_owned(), ScheduleType::Battery, None),
+            ("session".to_owned(), ScheduleType::ExternalPower, None),
+            ("sleep".to_owned(), ScheduleType::ExternalPower, None),
+        ]
+    );
+
+    // The sleep effector can't be spawned without D-Bus
+    let inventory = spawn_server(EffectorInventory::new(
+        Arc::new(config.clone()),
+        DependencyProvider::make_mock(None),
+    ))
+    .await
+    .unwrap();
+    let error = spawn_scheduled_effectors(&inventory, &config)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("sleep (schedule external)"), "{}", error);
+    assert!(!error.contains("brightness"), "{}", error);
+}
+
+/// Effector whose actors terminate after handling a single request, responding
+/// with the number of actors spawned so far
+struct DyingEffector {
+    name: &'static str,
+    spawned: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Effector for DyingEffector {
+    fn get_name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn get_effects(&self) -> Vec<Effect> {
+        vec![Effect::new(
+            format!("{}_effect", self.name),
+            vec![],
+            RollbackStrategy::None,
+        )]
+    }
+
+    async fn spawn(
+        &self,
+        _: Option<toml::Value>,
+        _: &mut DependencyProvider,
+    ) -> Result<EffectorPort> {
+        let spawned = self.spawned.fetch_add(1, Ordering::SeqCst) + 1;
+        let (port, mut receiver) = ActorPort::make();
+        tokio::spawn(async move {
+            if let Some(request) = receiver.recv().await {
+                let _ = request.respond(Ok(EffectorResponse::new(spawned)));
+            }
+        });
+        Ok(port)
+    }
+}
+
+#[tokio::test]
+async fn test_dead_effector_respawn() {
+    let spawned = Arc::new(AtomicUsize::new(0));
+    register_effector(Arc::new(DyingEffector {
+        name: "dying",
+        spawned: spawned.clone(),
+    }))
+    .unwrap();
+    let config = toml::Value::Table(toml::value::Map::new());
+    let inventory = spawn_server(
+        EffectorInventory::new(Arc::new(config), DependencyProvider::make_mock(None))
+            .with_restart_policy(RestartPolicy {
+                max_restarts: 0,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                stable_after: Duration::from_secs(300),
+            }),
+    )
+    .await
+    .unwrap();
+
+    let port = get_effector_port(&inventory, "dying").await.unwrap();
+    let response = port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(response.applied_effects, 1);
+    port.request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .expect_err("Dead effector responded");
+
+    inventory
+        .request(InventoryMessage::CheckHealth)
+        .await
+        .unwrap();
+    let port = get_effector_port(&inventory, "dying").await.unwrap();
+    let response = port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(response.applied_effects, 2);
+    assert_eq!(spawned.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_request_respawns_dead_effector() {
+    let spawned = Arc::new(AtomicUsize::new(0));
+    register_effector(Arc::new(DyingEffector {
+        name: "dying_on_request",
+        spawned: spawned.clone(),
+    }))
+    .unwrap();
+    let config = toml::Value::Table(toml::value::Map::new());
+    let inventory = spawn_server(
+        EffectorInventory::new(Arc::new(config), DependencyProvider::make_mock(None))
+            .with_restart_policy(RestartPolicy {
+                max_restarts: 0,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                stable_after: Duration::from_secs(300),
+            }),
+    )
+    .await
+    .unwrap();
+
+    for expected in 1..=2 {
+        let response = request_effector(
+            &inventory,
+            "dying_on_request",
+            EffectorMessage::CurrentlyAppliedEffects,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.applied_effects, expected);
+    }
+    assert_eq!(spawned.load(Ordering::SeqCst), 2);
+}
+
+/// Effector whose actors keep running, but fail every request
+struct FailingEffector {
+    spawned: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Effector for FailingEffector {
+    fn get_name(&self) -> String {
+        "failing".to_owned()
+    }
+
+    fn get_effects(&self) -> Vec<Effect> {
+        vec![Effect::new(
+            "failing_effect".to_owned(),
+            vec![],
+            RollbackStrategy::None,
+        )]
+    }
+
+    async fn spawn(
+        &self,
+        _: Option<toml::Value>,
+        _: &mut DependencyProvider,
+    ) -> Result<EffectorPort> {
+        self.spawned.fetch_add(1, Ordering::SeqCst);
+        let (port, mut receiver) = ActorPort::make();
+        tokio::spawn(async move {
+            while let Some(request) = receiver.recv().await {
+                let _ = request.respond(Err(anyhow::anyhow!("Couldn't read the state")));
+            }
+        });
+        Ok(port)
+    }
+}
+
+#[tokio::test]
+async fn test_failing_effector_kept() {
+    let spawned = Arc::new(AtomicUsize::new(0));
+    register_effector(Arc::new(FailingEffector {
+        spawned: spawned.clone(),
+    }))
+    .unwrap();
+    let config = toml::Value::Table(toml::value::Map::new());
+    let inventory = spawn_server(EffectorInventory::new(
+        Arc::new(config),
+        DependencyProvider::make_mock(None),
+    ))
+    .await
+    .unwrap();
+
+    request_effector(&inventory, "failing", EffectorMessage::Execute)
+        .await
+        .expect_err("Failing effector succeeded");
+    inventory
+        .request(InventoryMessage::CheckHealth)
+        .await
+        .unwrap();
+    get_effector_port(&inventory, "failing").await.unwrap();
+    assert_eq!(spawned.load(Ordering::SeqCst), 1);
+}
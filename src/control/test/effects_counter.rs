@@ -3,7 +3,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::armaf::{ActorPort, EffectorPort};
+use crate::armaf::{ActorPort, EffectorCapabilities, EffectorPort, EffectorResponse};
 
 pub struct EffectsCounter {
     running_effects: Arc<Mutex<Cell<isize>>>,
@@ -12,6 +12,11 @@ pub struct EffectsCounter {
 
 impl EffectsCounter {
     pub fn new() -> EffectsCounter {
+        EffectsCounter::with_capabilities(EffectorCapabilities::default())
+    }
+
+    /// Create a counter reporting the given capabilities in its responses
+    pub fn with_capabilities(capabilities: EffectorCapabilities) -> EffectsCounter {
         let our_running_effects = Arc::new(Mutex::new(Cell::new(0)));
         let running_effects = our_running_effects.clone();
 
@@ -19,13 +24,21 @@ impl EffectsCounter {
 
         tokio::spawn(async move {
             while let Some(req) = rx.recv().await {
+                // The counter stands for many effects, so it can't tell
+                // whether the one it's asked to ensure is applied
                 let delta = match req.payload {
-                    crate::armaf::EffectorMessage::Execute => 1,
-                    crate::armaf::EffectorMessage::Rollback => -1,
-                    crate::armaf::EffectorMessage::CurrentlyAppliedEffects => 0,
+                    crate::armaf::EffectorMessage::Execute
+                    | crate::armaf::EffectorMessage::EnsureApplied => 1,
+                    crate::armaf::EffectorMessage::Rollback
+                    | crate::armaf::EffectorMessage::EnsureRolledBack => -1,
+                    crate::armaf::EffectorMessage::CurrentlyAppliedEffects
+                    | crate::armaf::EffectorMessage::CanExecute => 0,
                 };
                 *running_effects.lock().unwrap().get_mut() += delta;
-                req.respond(Ok(running_effects.lock().unwrap().get() as usize))
+                req.respond(Ok(EffectorResponse::new(
+                    running_effects.lock().unwrap().get() as usize,
+                )
+                .with_capabilities(capabilities)))
                     .unwrap();
             }
         });
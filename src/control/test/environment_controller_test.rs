@@ -0,0 +1,396 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, sync::watch, time::sleep};
+
+use crate::{
+    armaf::{spawn_server, ActorPort, Server},
+    control::{
+        effector_inventory::{EffectorInventory, InventoryMessage},
+        environment_controller::{EnvironmentCommand, EnvironmentController, ScheduleType},
+        manager_state::StateReporter,
+    },
+    external::{
+        dependency_provider::DependencyProvider,
+        display_server::{DisplayServer, DisplayServerController, SystemState},
+    },
+    system::{
+        environment_sensor::EnvironmentFacts,
+        inhibition_sensor::{ActiveInhibitor, GetInhibitions},
+        simulated_effector::{SimulatedAction, SimulatedEvent},
+        upower_sensor::PowerStatus,
+    },
+};
+
+struct NoInhibitions;
+
+#[async_trait]
+impl Server<GetInhibitions, Vec<ActiveInhibitor>> for NoInhibitions {
+    fn get_name(&self) -> String {
+        "NoInhibitions".to_owned()
+    }
+
+    async fn handle_message(&mut self, _: GetInhibitions) -> anyhow::Result<Vec<ActiveInhibitor>> {
+        Ok(Vec::new())
+    }
+}
+
+fn received_actions(
+    events: &mut mpsc::UnboundedReceiver<SimulatedEvent>,
+) -> Vec<(String, SimulatedAction)> {
+    let mut actions = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        actions.push((event.effect_name, event.action));
+    }
+    actions
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pause() {
+    let config = Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+    });
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let effector_inventory = spawn_server(
+        EffectorInventory::new(config.clone(), dependencies).with_simulation(events_sender),
+    )
+    .await
+    .unwrap();
+    let (_power_status_sender, power_status_receiver) = watch::channel(PowerStatus::External);
+    let (reporter, state) = StateReporter::new();
+    let port = EnvironmentController::new(
+        config.clone(),
+        effector_inventory.clone(),
+        spawn_server(NoInhibitions).await.unwrap(),
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .with_state_reporter(reporter)
+    .spawn()
+    .await
+    .unwrap();
+
+    sleep(Duration::from_secs(60)).await;
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events)
+        .contains(&("screen_dim".to_owned(), SimulatedAction::Executed)));
+
+    // Pausing rolls the applied effects back
+    port.request(EnvironmentCommand::Pause(Some(Duration::from_secs(600))))
+        .await
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events)
+        .contains(&("screen_dim".to_owned(), SimulatedAction::RolledBack)));
+    assert!(state.borrow().paused);
+
+    // Idleness is ignored while paused
+    display_server
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    sleep(Duration::from_secs(120)).await;
+    assert!(received_actions(&mut events).is_empty());
+
+    // The pause expires on its own
+    display_server
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    sleep(Duration::from_secs(600)).await;
+    assert!(!state.borrow().paused);
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events)
+        .contains(&("screen_dim".to_owned(), SimulatedAction::Executed)));
+
+    // Pause until resumed
+    port.request(EnvironmentCommand::Pause(None)).await.unwrap();
+    sleep(Duration::from_secs(3600)).await;
+    assert!(state.borrow().paused);
+    port.request(EnvironmentCommand::Resume).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(!state.borrow().paused);
+
+    port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_reset_schedule() {
+    let config = Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+    });
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let effector_inventory = spawn_server(
+        EffectorInventory::new(config.clone(), dependencies).with_simulation(events_sender),
+    )
+    .await
+    .unwrap();
+    let (_power_status_sender, power_status_receiver) = watch::channel(PowerStatus::External);
+    let port = EnvironmentController::new(
+        config.clone(),
+        effector_inventory.clone(),
+        spawn_server(NoInhibitions).await.unwrap(),
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .spawn()
+    .await
+    .unwrap();
+
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events)
+        .contains(&("screen_dim".to_owned(), SimulatedAction::Executed)));
+
+    // The display server hasn't reported any activity, e.g. after resume
+    port.request(EnvironmentCommand::ResetSchedule)
+        .await
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events)
+        .contains(&("screen_dim".to_owned(), SimulatedAction::RolledBack)));
+
+    // Nothing to roll back anymore
+    port.request(EnvironmentCommand::ResetSchedule)
+        .await
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert!(received_actions(&mut events).is_empty());
+
+    port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_schedule_rules() {
+    let config = Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+
+        [schedule.presentation]
+        screen_off = "2h"
+
+        [schedule.docked]
+        screen_dim = "10m"
+
+        [[rules]]
+        schedule = "presentation"
+        profile = "presentation"
+
+        [[rules]]
+        schedule = "docked"
+        docked = true
+    });
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let (events_sender, _events) = mpsc::unbounded_channel();
+    let effector_inventory = spawn_server(
+        EffectorInventory::new(config.clone(), dependencies).with_simulation(events_sender),
+    )
+    .await
+    .unwrap();
+    let (_power_status_sender, power_status_receiver) = watch::channel(PowerStatus::External);
+    let (facts_sender, facts_receiver) = watch::channel(EnvironmentFacts::default());
+    let (reporter, state) = StateReporter::new();
+    let port = EnvironmentController::new(
+        config.clone(),
+        effector_inventory.clone(),
+        spawn_server(NoInhibitions).await.unwrap(),
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .with_state_reporter(reporter)
+    .with_environment_facts(facts_receiver)
+    .spawn()
+    .await
+    .unwrap();
+    let schedule_type = || state.borrow().schedule_type.clone();
+
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::ExternalPower));
+
+    facts_sender
+        .send(EnvironmentFacts {
+            docked: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::named("docked")));
+    assert_eq!(
+        display_server
+            .get_controller()
+            .get_idleness_timeout()
+            .await
+            .unwrap(),
+        600
+    );
+
+    // Earlier rules take precedence
+    port.request(EnvironmentCommand::SetProfile(Some(
+        "presentation".to_owned(),
+    )))
+    .await
+    .unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::named("presentation")));
+
+    port.request(EnvironmentCommand::SetProfile(None))
+        .await
+        .unwrap();
+    facts_sender.send(EnvironmentFacts::default()).unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::ExternalPower));
+
+    port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_low_battery_hysteresis() {
+    let config = Arc::new(toml::toml! {
+        [schedule.battery]
+        screen_dim = "1m"
+
+        [schedule.low_battery]
+        screen_dim = "10s"
+
+        [battery]
+        low_battery_percentage = 20
+        low_battery_exit_percentage = 25
+        min_dwell = "5m"
+    });
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let (events_sender, _events) = mpsc::unbounded_channel();
+    let effector_inventory = spawn_server(
+        EffectorInventory::new(config.clone(), dependencies).with_simulation(events_sender),
+    )
+    .await
+    .unwrap();
+    let (power_status_sender, power_status_receiver) = watch::channel(PowerStatus::Battery(30));
+    let (reporter, state) = StateReporter::new();
+    let port = EnvironmentController::new(
+        config.clone(),
+        effector_inventory.clone(),
+        spawn_server(NoInhibitions).await.unwrap(),
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .with_state_reporter(reporter)
+    .spawn()
+    .await
+    .unwrap();
+    let schedule_type = || state.borrow().schedule_type.clone();
+
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::Battery));
+    power_status_sender.send(PowerStatus::Battery(20)).unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::LowBattery));
+
+    // Above the low battery threshold, but not above the exit percentage
+    power_status_sender.send(PowerStatus::Battery(22)).unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::LowBattery));
+
+    // Above the exit percentage, but the schedule has been switched recently
+    power_status_sender.send(PowerStatus::Battery(26)).unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::LowBattery));
+
+    sleep(Duration::from_secs(5 * 60)).await;
+    power_status_sender.send(PowerStatus::Battery(27)).unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(schedule_type(), Some(ScheduleType::Battery));
+
+    port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_invalid_reload() {
+    let config = Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+    });
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let effector_inventory = spawn_server(EffectorInventory::new(config.clone(), dependencies))
+        .await
+        .unwrap();
+    // Forwards the requests to the inventory, counting the reloads
+    let reloads = Arc::new(AtomicUsize::new(0));
+    let (inventory_port, mut requests) = ActorPort::make();
+    let counted_reloads = reloads.clone();
+    let inventory = effector_inventory.clone();
+    tokio::spawn(async move {
+        while let Some(request) = requests.recv().await {
+            if matches!(request.payload, InventoryMessage::ReloadConfig(_)) {
+                counted_reloads.fetch_add(1, Ordering::SeqCst);
+            }
+            let response = inventory
+                .request(request.payload)
+                .await
+                .map_err(anyhow::Error::from);
+            let _ = request.respond(response);
+        }
+    });
+    let (_power_status_sender, power_status_receiver) = watch::channel(PowerStatus::External);
+    let port = EnvironmentController::new(
+        config.clone(),
+        inventory_port,
+        spawn_server(NoInhibitions).await.unwrap(),
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .spawn()
+    .await
+    .unwrap();
+
+    // The effectors aren't reconfigured before the schedules are found invalid
+    port.request(EnvironmentCommand::ReloadConfig(Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "1m"
+        explode = "2m"
+
+        [brightness]
+        dim_percentage = 10
+    })))
+    .await
+    .expect_err("Unknown effect accepted");
+    assert_eq!(reloads.load(Ordering::SeqCst), 0);
+
+    port.request(EnvironmentCommand::ReloadConfig(Arc::new(toml::toml! {
+        [schedule.external]
+        screen_dim = "2m"
+    })))
+    .await
+    .unwrap();
+    assert_eq!(reloads.load(Ordering::SeqCst), 1);
+
+    port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+}
@@ -0,0 +1,66 @@
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::{broadcast, watch};
+
+use crate::{
+    control::{
+        environment_controller::ScheduleType,
+        event_history::{EventHistory, HistoryEvent, HistoryRecorder},
+        manager_state::{EffectEvent, EffectTransition, StateReporter},
+    },
+    external::display_server::SystemState,
+};
+
+/// Wait until the history contains the given number of events
+async fn wait_for_events(history: &EventHistory, count: usize) -> Vec<HistoryEvent> {
+    for _ in 0..100 {
+        let events: Vec<HistoryEvent> = history
+            .recent()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        if events.len() >= count {
+            return events;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("History doesn't contain {} events", count);
+}
+
+#[tokio::test]
+async fn test_history_recorder() {
+    let history = EventHistory::new(16);
+    let (idleness_sender, idleness_channel) = watch::channel(SystemState::Awakened);
+    let (event_sender, effect_events) = broadcast::channel(16);
+    let (reporter, state) = StateReporter::new();
+    reporter.update(|state| state.schedule_type = Some(ScheduleType::ExternalPower));
+    let handle =
+        HistoryRecorder::new(history.clone(), idleness_channel, effect_events, state).spawn();
+
+    idleness_sender.send(SystemState::Idle).unwrap();
+    wait_for_events(&history, 1).await;
+    event_sender
+        .send(EffectEvent {
+            effect_name: "lock".to_owned(),
+            transition: EffectTransition::Applied,
+            timestamp: SystemTime::now(),
+        })
+        .unwrap();
+    wait_for_events(&history, 2).await;
+    // Changes of the state which keep the schedule aren't recorded
+    reporter.update(|state| state.current_bunch = 1);
+    reporter.update(|state| state.schedule_type = Some(ScheduleType::Battery));
+    wait_for_events(&history, 3).await;
+    idleness_sender.send(SystemState::Awakened).unwrap();
+
+    assert_eq!(
+        wait_for_events(&history, 4).await,
+        vec![
+            HistoryEvent::Idle,
+            HistoryEvent::EffectApplied("lock".to_owned()),
+            HistoryEvent::ScheduleSwitched(ScheduleType::Battery),
+            HistoryEvent::Awakened,
+        ]
+    );
+    handle.await_shutdown().await;
+}
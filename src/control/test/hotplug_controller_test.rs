@@ -0,0 +1,54 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::watch, time::sleep};
+
+use crate::{
+    armaf::{spawn_server, EffectorMessage},
+    control::{
+        effector_inventory::{request_effector, EffectorInventory},
+        hotplug_controller::HotplugController,
+        manager_state::StateReporter,
+    },
+    external::{brightness::BrightnessController, dependency_provider::DependencyProvider},
+};
+
+fn outputs(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[tokio::test]
+async fn test_reapply_on_hotplug() {
+    let dependencies = DependencyProvider::make_mock(None);
+    let brightness = dependencies.get_brightness_controller();
+    let config = toml::Value::Table(toml::value::Map::new());
+    let inventory = spawn_server(EffectorInventory::new(Arc::new(config), dependencies))
+        .await
+        .unwrap();
+    let (reporter, state) = StateReporter::new();
+    let (outputs_sender, outputs_receiver) = watch::channel(outputs(&["eDP-1"]));
+    let handle = HotplugController::new(outputs_receiver, inventory.clone(), state).spawn();
+
+    request_effector(&inventory, "brightness", EffectorMessage::Execute)
+        .await
+        .unwrap();
+    assert_eq!(brightness.get_brightness().await.unwrap(), 25);
+    // Connecting a monitor resets the brightness
+    brightness.set_brightness(50).await.unwrap();
+
+    // Effects which aren't applied according to the state are left alone
+    outputs_sender.send(outputs(&["HDMI-1", "eDP-1"])).unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(brightness.get_brightness().await.unwrap(), 50);
+
+    reporter.update(|state| state.applied_effects = vec!["screen_dim".to_owned()]);
+    // Disconnecting a monitor doesn't need anything applied
+    outputs_sender.send(outputs(&["eDP-1"])).unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(brightness.get_brightness().await.unwrap(), 50);
+
+    outputs_sender.send(outputs(&["DP-1", "eDP-1"])).unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(brightness.get_brightness().await.unwrap(), 25);
+
+    drop(handle);
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::{
+    control::idleness_broker::IdlenessBroker,
+    external::display_server::{mock, AnyDisplayServerController, DisplayServer, SystemState},
+};
+
+/// Wait until the channel reports the given state
+async fn expect_state(channel: &mut watch::Receiver<SystemState>, expected: SystemState) {
+    tokio::time::timeout(Duration::from_secs(1), channel.changed())
+        .await
+        .expect("State hasn't changed")
+        .unwrap();
+    assert_eq!(*channel.borrow_and_update(), expected);
+}
+
+#[tokio::test]
+async fn test_merged_sources() {
+    let display_server = mock::Interface::new(600);
+    let (input_sender, input_channel) = watch::channel(SystemState::Awakened);
+    let broker = IdlenessBroker::new(display_server.get_idleness_channel())
+        .with_source("input devices", input_channel);
+    let mut state = broker.subscribe();
+    let handle = broker.spawn();
+
+    // Idle only after all the sources are idle
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    input_sender.send(SystemState::Idle).unwrap();
+    expect_state(&mut state, SystemState::Idle).await;
+
+    // Activity on any source wakes the user up
+    input_sender.send(SystemState::Awakened).unwrap();
+    expect_state(&mut state, SystemState::Awakened).await;
+    input_sender.send(SystemState::Idle).unwrap();
+    expect_state(&mut state, SystemState::Idle).await;
+
+    handle.await_shutdown().await;
+}
+
+#[tokio::test]
+async fn test_reported_activity() {
+    let display_server = mock::Interface::new(600);
+    let mut display_channel = display_server.get_idleness_channel();
+    let broker = IdlenessBroker::new(display_server.get_idleness_channel())
+        .with_display_controller(AnyDisplayServerController::Mock(
+            display_server.get_controller(),
+        ));
+    let mut state = broker.subscribe();
+    let reporter = broker.activity_reporter();
+    let handle = broker.spawn();
+
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    expect_state(&mut state, SystemState::Idle).await;
+    display_channel.borrow_and_update();
+
+    reporter.report().unwrap();
+    expect_state(&mut state, SystemState::Awakened).await;
+    // The display server's idleness timer has been restarted
+    expect_state(&mut display_channel, SystemState::Awakened).await;
+
+    handle.await_shutdown().await;
+    reporter
+        .report()
+        .expect_err("Activity reported to a terminated broker");
+}
+
+#[tokio::test]
+async fn test_debounce() {
+    let display_server = mock::Interface::new(600);
+    let broker = IdlenessBroker::new(display_server.get_idleness_channel())
+        .with_debounce(Duration::from_millis(200));
+    let mut state = broker.subscribe();
+    let handle = broker.spawn();
+
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(*state.borrow(), SystemState::Awakened);
+    expect_state(&mut state, SystemState::Idle).await;
+
+    // A brief activity is ignored
+    display_server
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    display_server
+        .notify_state_transition(SystemState::Idle)
+        .unwrap();
+    tokio::time::timeout(Duration::from_millis(400), state.changed())
+        .await
+        .expect_err("Brief activity has been published");
+
+    display_server
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    expect_state(&mut state, SystemState::Awakened).await;
+
+    handle.await_shutdown().await;
+}
@@ -2,21 +2,34 @@ use std::{
     cell::Cell,
     collections::HashSet,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use logind_zbus::manager::{InhibitType, InhibitTypes, Inhibitor, Mode};
+use tokio::sync::broadcast;
 
 use crate::{
-    armaf::{spawn_server, ActorPort, Effect, EffectorMessage, EffectorPort, RollbackStrategy},
-    control::idleness_controller::{Action, IdlenessController, ReconciliationBunches},
+    armaf::{
+        spawn_server, ActorPort, Effect, EffectorCapabilities, EffectorMessage, EffectorPort,
+        RollbackStrategy,
+    },
+    control::{
+        event_history::{EventHistory, HistoryEvent},
+        idleness_controller::{
+            Action, BunchReplacement, IdlenessController, IdlenessMessage, IdlenessSnapshot,
+            ReconciliationBunches,
+        },
+        inhibitor_policy::{InhibitionMode, InhibitorOverride, InhibitorPolicy},
+        manager_state::{EffectTransition, StateReporter},
+    },
     external::display_server::SystemState,
-    system::inhibition_sensor::GetInhibitions,
+    system::inhibition_sensor::{ActiveInhibitor, InhibitionSensorPort},
 };
 
 use super::effects_counter::EffectsCounter;
 
 struct MockInhibitionSensor {
-    inhibitors: Arc<Mutex<Cell<Vec<Inhibitor>>>>,
+    inhibitors: Arc<Mutex<Cell<Vec<ActiveInhibitor>>>>,
 }
 
 impl MockInhibitionSensor {
@@ -27,6 +40,10 @@ impl MockInhibitionSensor {
     }
 
     fn add_inhibitor_with_types(&self, mode: Mode, ts: &Vec<InhibitType>) {
+        self.add_aged_inhibitor(mode, ts, Duration::ZERO);
+    }
+
+    fn add_aged_inhibitor(&self, mode: Mode, ts: &Vec<InhibitType>, age: Duration) {
         let inhibit_types = InhibitTypes::new(ts);
         let inhibitor_count = self.inhibitors.lock().unwrap().get_mut().len();
         let inhibitor = Inhibitor::new(
@@ -37,14 +54,18 @@ impl MockInhibitionSensor {
             0,
             0,
         );
-        self.inhibitors.lock().unwrap().get_mut().push(inhibitor);
+        self.inhibitors
+            .lock()
+            .unwrap()
+            .get_mut()
+            .push(ActiveInhibitor { inhibitor, age });
     }
 
     fn reset(&self) {
         *self.inhibitors.lock().unwrap().get_mut() = Vec::new();
     }
 
-    fn spawn(&self) -> ActorPort<GetInhibitions, Vec<Inhibitor>, anyhow::Error> {
+    fn spawn(&self) -> InhibitionSensorPort {
         let (port, mut rx) = ActorPort::make();
 
         let inhibitors = self.inhibitors.clone();
@@ -102,14 +123,17 @@ async fn test_without_inhibitors() {
     );
     let controller_port = spawn_server(idleness_controller).await.unwrap();
     // Moving to bunch 0
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
     assert_eq!(ec3.ongoing_effect_count(), 0);
 
     // Rolling back
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -117,26 +141,35 @@ async fn test_without_inhibitors() {
     assert_eq!(ec3.ongoing_effect_count(), 0);
 
     // Moving to bunch 0
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
     assert_eq!(ec3.ongoing_effect_count(), 0);
 
     // Moving to bunch 1
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 2);
     assert_eq!(ec2.ongoing_effect_count(), 1);
     assert_eq!(ec3.ongoing_effect_count(), 0);
 
     // Moving to bunch 2
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 2);
     assert_eq!(ec2.ongoing_effect_count(), 2);
     assert_eq!(ec3.ongoing_effect_count(), 1);
 
     // Rolling back
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -180,13 +213,16 @@ async fn test_inhibitions() {
     inhibition_sensor.add_inhibitor_with_types(Mode::Delay, &vec![InhibitType::Sleep]);
     inhibition_sensor
         .add_inhibitor_with_types(Mode::Delay, &vec![InhibitType::Shutdown, InhibitType::Idle]);
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
 
     // Rolling back
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -196,7 +232,7 @@ async fn test_inhibitions() {
     inhibition_sensor.reset();
     inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::Sleep]);
     controller_port
-        .request(SystemState::Idle)
+        .request(SystemState::Idle.into())
         .await
         .expect_err("Bunch applied even when inhibited");
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -208,7 +244,7 @@ async fn test_inhibitions() {
     inhibition_sensor
         .add_inhibitor_with_types(Mode::Block, &vec![InhibitType::Sleep, InhibitType::Idle]);
     controller_port
-        .request(SystemState::Idle)
+        .request(SystemState::Idle.into())
         .await
         .expect_err("Bunch applied even when inhibited");
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -218,7 +254,10 @@ async fn test_inhibitions() {
     inhibition_sensor.reset();
     inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::HandleHibernateKey]);
     inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::HandleLidSwitch]);
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
 
@@ -226,10 +265,206 @@ async fn test_inhibitions() {
     inhibition_sensor.reset();
     inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::Sleep]);
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    assert_eq!(ec2.ongoing_effect_count(), 0);
+}
+
+#[tokio::test]
+async fn test_overridden_inhibitors() {
+    let ec = EffectsCounter::new();
+    let action_bunches = vec![vec![Action::new(
+        Effect::new(
+            "1-1".to_owned(),
+            vec![InhibitType::Idle],
+            RollbackStrategy::OnActivity,
+        ),
+        ec.get_port(),
+    )]];
+
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let policy = InhibitorPolicy {
+        mode: InhibitionMode::Strict,
+        max_age: None,
+        overrides: vec![InhibitorOverride {
+            who: "inhibitor0".to_owned(),
+            after: Duration::from_secs(3600),
+        }],
+    };
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        inhibition_sensor.spawn(),
+    )
+    .with_inhibitor_policy(policy);
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    // The inhibitor isn't old enough to be ignored
+    inhibition_sensor.add_aged_inhibitor(
+        Mode::Block,
+        &vec![InhibitType::Idle],
+        Duration::from_secs(60),
+    );
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .expect_err("Bunch applied even when inhibited");
+    assert_eq!(ec.ongoing_effect_count(), 0);
+
+    inhibition_sensor.reset();
+    inhibition_sensor.add_aged_inhibitor(
+        Mode::Block,
+        &vec![InhibitType::Idle],
+        Duration::from_secs(7200),
+    );
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    assert_eq!(ec.ongoing_effect_count(), 1);
+}
+
+#[tokio::test]
+async fn test_permissive_inhibitions() {
+    let ec1 = EffectsCounter::new();
+    let ec2 = EffectsCounter::new();
+    let ec3 = EffectsCounter::new();
+    let action_bunches = vec![
+        vec![
+            Action::new(
+                Effect::new(
+                    "1-1".to_owned(),
+                    vec![InhibitType::Idle],
+                    RollbackStrategy::OnActivity,
+                ),
+                ec1.get_port(),
+            ),
+            Action::new(
+                Effect::new("1-2".to_owned(), vec![], RollbackStrategy::OnActivity),
+                ec2.get_port(),
+            ),
+        ],
+        vec![Action::new(
+            Effect::new(
+                "2-1".to_owned(),
+                vec![InhibitType::Idle],
+                RollbackStrategy::OnActivity,
+            ),
+            ec3.get_port(),
+        )],
+    ];
+
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let policy = InhibitorPolicy {
+        mode: InhibitionMode::Permissive,
+        ..InhibitorPolicy::default()
+    };
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        inhibition_sensor.spawn(),
+    )
+    .with_inhibitor_policy(policy);
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    // The effect which isn't inhibited is applied, the other one waits
+    inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::Idle]);
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    assert_eq!(ec2.ongoing_effect_count(), 1);
+    let snapshot = controller_port
+        .request(IdlenessMessage::GetSnapshot)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(snapshot.executed_bunches, 1);
+    assert_eq!(snapshot.pending_execution, vec!["1-1".to_owned()]);
+
+    // Everything upcoming is inhibited, so the bunch is retried as a whole
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .expect_err("Bunch applied even when inhibited");
+    assert_eq!(ec3.ongoing_effect_count(), 0);
+
+    // The deferred effect is applied along with the next bunch
+    inhibition_sensor.reset();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 1);
+    assert_eq!(ec3.ongoing_effect_count(), 1);
+
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    assert_eq!(ec2.ongoing_effect_count(), 0);
+    assert_eq!(ec3.ongoing_effect_count(), 0);
+}
+
+#[tokio::test]
+async fn test_replace_bunches() {
+    let ec1 = EffectsCounter::new();
+    let ec2 = EffectsCounter::new();
+    let old_action = Action::new(
+        Effect::new("1-1".to_owned(), vec![], RollbackStrategy::OnActivity),
+        ec1.get_port(),
+    );
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let idleness_controller = IdlenessController::new(
+        vec![vec![old_action.clone()]],
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        inhibition_sensor.spawn(),
+    );
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 1);
+
+    // The new schedule starts from its beginning, so the effect applied by
+    // the old one is rolled back right away
+    let replacement = BunchReplacement {
+        action_bunches: vec![vec![Action::new(
+            Effect::new("2-1".to_owned(), vec![], RollbackStrategy::OnActivity),
+            ec2.get_port(),
+        )]],
+        starting_bunch: 0,
+        reconciliation_bunches: ReconciliationBunches::new(
+            None,
+            Some(vec![old_action]),
+            HashSet::new(),
+        ),
+        inhibitor_policy: Default::default(),
+    };
+    controller_port
+        .request(IdlenessMessage::ReplaceBunches(Box::new(replacement)))
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+
+    controller_port
+        .request(SystemState::Idle.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
+    assert_eq!(ec2.ongoing_effect_count(), 1);
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
     assert_eq!(ec2.ongoing_effect_count(), 0);
 }
 
@@ -278,7 +513,12 @@ async fn test_reconciliation() {
             ),
             make_action(1, 2, rec1.get_port(), RollbackStrategy::OnActivity),
         ]),
-        Some(vec![rec2.get_port()]),
+        Some(vec![make_action(
+            0,
+            1,
+            rec2.get_port(),
+            RollbackStrategy::OnActivity,
+        )]),
         HashSet::new(),
     );
 
@@ -293,7 +533,7 @@ async fn test_reconciliation() {
 
     inhibition_sensor.add_inhibitor_with_types(Mode::Block, &vec![InhibitType::Idle]);
     controller_port
-        .request(SystemState::Idle)
+        .request(SystemState::Idle.into())
         .await
         .expect_err("Bunch applied even when inhibited");
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -301,18 +541,24 @@ async fn test_reconciliation() {
     assert_eq!(rec2.ongoing_effect_count(), 1);
 
     inhibition_sensor.reset();
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(rec1.ongoing_effect_count(), 2);
     assert_eq!(rec2.ongoing_effect_count(), 1);
 
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 2);
     assert_eq!(rec1.ongoing_effect_count(), 2);
     assert_eq!(rec2.ongoing_effect_count(), 1);
 
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
@@ -332,8 +578,16 @@ async fn test_rollback_on_zero_position() {
         RollbackStrategy::OnActivity,
     )]];
 
-    let reconciliation =
-        ReconciliationBunches::new(None, Some(vec![rec1.get_port()]), HashSet::new());
+    let reconciliation = ReconciliationBunches::new(
+        None,
+        Some(vec![make_action(
+            0,
+            1,
+            rec1.get_port(),
+            RollbackStrategy::OnActivity,
+        )]),
+        HashSet::new(),
+    );
 
     rec1.get_port()
         .request(EffectorMessage::Execute)
@@ -375,26 +629,435 @@ async fn test_effect_skipping() {
     );
     let controller_port = spawn_server(idleness_controller).await.unwrap();
 
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
     assert_eq!(ec2.ongoing_effect_count(), 1);
 
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
 
     controller_port
-        .request(SystemState::Awakened)
+        .request(SystemState::Awakened.into())
         .await
         .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 0);
     assert_eq!(ec2.ongoing_effect_count(), 0);
 
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 1);
     assert_eq!(ec2.ongoing_effect_count(), 1);
 
-    controller_port.request(SystemState::Idle).await.unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
     assert_eq!(ec1.ongoing_effect_count(), 2);
     assert_eq!(ec2.ongoing_effect_count(), 2);
 }
+
+#[tokio::test]
+async fn test_persistent_effect_skipping() {
+    let ec1 = EffectsCounter::new();
+    let ec2 = EffectsCounter::new();
+
+    let action_bunches = vec![
+        vec![make_action(
+            1,
+            1,
+            ec1.get_port(),
+            RollbackStrategy::OnActivity,
+        )],
+        vec![make_action(2, 1, ec2.get_port(), RollbackStrategy::None)],
+    ];
+
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let reconciliation = ReconciliationBunches::new(None, None, HashSet::new())
+        .with_persistent_skip_effects(HashSet::from(["2-1".to_owned()]));
+    let idleness_controller =
+        IdlenessController::new(action_bunches, 0, reconciliation, inhibition_sensor.spawn());
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    for _ in 0..2 {
+        controller_port
+            .request(SystemState::Idle.into())
+            .await
+            .unwrap();
+        controller_port
+            .request(SystemState::Idle.into())
+            .await
+            .unwrap();
+        assert_eq!(ec1.ongoing_effect_count(), 1);
+        assert_eq!(ec2.ongoing_effect_count(), 0);
+
+        controller_port
+            .request(SystemState::Awakened.into())
+            .await
+            .unwrap();
+        assert_eq!(ec1.ongoing_effect_count(), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_state_reporting() {
+    let ec = EffectsCounter::new();
+    let action_bunches = vec![
+        vec![make_action(
+            1,
+            1,
+            ec.get_port(),
+            RollbackStrategy::OnActivity,
+        )],
+        vec![
+            make_action(2, 1, ec.get_port(), RollbackStrategy::Immediate),
+            make_action(2, 2, ec.get_port(), RollbackStrategy::OnActivity),
+        ],
+    ];
+    let (reporter, state) = StateReporter::new();
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        MockInhibitionSensor::new().spawn(),
+    )
+    .with_state_reporter(reporter);
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    assert_eq!(state.borrow().current_bunch, 2);
+    // Immediately rolled back effects are not reported as applied
+    assert_eq!(state.borrow().applied_effects, vec!["1-1", "2-2"]);
+
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    assert_eq!(state.borrow().current_bunch, 0);
+    assert!(state.borrow().applied_effects.is_empty());
+}
+
+#[tokio::test]
+async fn test_effect_events() {
+    let ec = EffectsCounter::new();
+    let action_bunches = vec![vec![
+        make_action(1, 1, ec.get_port(), RollbackStrategy::OnActivity),
+        make_action(1, 2, ec.get_port(), RollbackStrategy::Immediate),
+        make_action(1, 3, ec.get_port(), RollbackStrategy::None),
+    ]];
+    let (sender, mut events) = broadcast::channel(16);
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        MockInhibitionSensor::new().spawn(),
+    )
+    .with_effect_events(sender);
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push((event.effect_name, event.transition));
+    }
+    assert_eq!(
+        received,
+        vec![
+            ("1-1".to_owned(), EffectTransition::Applied),
+            ("1-2".to_owned(), EffectTransition::Applied),
+            ("1-3".to_owned(), EffectTransition::Applied),
+            ("1-2".to_owned(), EffectTransition::RolledBack),
+            ("1-1".to_owned(), EffectTransition::RolledBack),
+        ]
+    );
+}
+
+/// Port of an effector which can never execute its effect, recording the
+/// messages it receives
+fn unavailable_effector() -> (EffectorPort, Arc<Mutex<Vec<EffectorMessage>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let (port, mut rx) = ActorPort::make();
+    let recorded = received.clone();
+    tokio::spawn(async move {
+        while let Some(req) = rx.recv().await {
+            recorded.lock().unwrap().push(req.payload);
+            let response = match req.payload {
+                EffectorMessage::CanExecute => Err(anyhow::anyhow!("locker isn't installed")),
+                _ => Ok(crate::armaf::EffectorResponse::new(1)),
+            };
+            req.respond(response).unwrap();
+        }
+    });
+    (port, received)
+}
+
+#[tokio::test]
+async fn test_unavailable_effects() {
+    let ec = EffectsCounter::new();
+    let (unavailable_port, received) = unavailable_effector();
+    let action_bunches = vec![vec![
+        make_action(1, 1, ec.get_port(), RollbackStrategy::OnActivity),
+        make_action(1, 2, unavailable_port, RollbackStrategy::OnActivity),
+    ]];
+    let history = EventHistory::new(16);
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        MockInhibitionSensor::new().spawn(),
+    )
+    .with_event_history(history.clone());
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+
+    controller_port
+        .request(SystemState::Idle.into())
+        .await
+        .unwrap();
+    // The rest of the bunch is applied
+    assert_eq!(ec.ongoing_effect_count(), 1);
+    assert_eq!(*received.lock().unwrap(), vec![EffectorMessage::CanExecute]);
+    assert!(history.recent().iter().any(|entry| entry.event
+        == HistoryEvent::EffectUnavailable {
+            effect: "1-2".to_owned(),
+            reason: "locker isn't installed".to_owned(),
+        }));
+
+    // The skipped effect isn't rolled back
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    assert_eq!(ec.ongoing_effect_count(), 0);
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_snapshot() {
+    let ec = EffectsCounter::new();
+    let action_bunches = vec![
+        vec![make_action(
+            1,
+            1,
+            ec.get_port(),
+            RollbackStrategy::OnActivity,
+        )],
+        vec![
+            make_action(2, 1, ec.get_port(), RollbackStrategy::OnActivity),
+            make_action(2, 2, ec.get_port(), RollbackStrategy::OnActivity),
+        ],
+    ];
+    let reconciliation = ReconciliationBunches::new(
+        Some(vec![make_action(
+            1,
+            2,
+            ec.get_port(),
+            RollbackStrategy::OnActivity,
+        )]),
+        Some(vec![make_action(
+            0,
+            1,
+            ec.get_port(),
+            RollbackStrategy::OnActivity,
+        )]),
+        HashSet::from(["2-2".to_owned()]),
+    );
+    let idleness_controller = IdlenessController::new(
+        action_bunches,
+        1,
+        reconciliation,
+        MockInhibitionSensor::new().spawn(),
+    );
+    let controller_port = spawn_server(idleness_controller).await.unwrap();
+    let names = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect::<Vec<_>>();
+
+    assert_eq!(
+        controller_port
+            .request(IdlenessMessage::GetSnapshot)
+            .await
+            .unwrap(),
+        Some(IdlenessSnapshot {
+            executed_bunches: 1,
+            rollback_stack: vec![],
+            pending_execution: names(&["1-2"]),
+            pending_rollback: names(&["0-1"]),
+            skipped_effects: names(&["2-2"]),
+        })
+    );
+
+    assert_eq!(
+        controller_port
+            .request(SystemState::Idle.into())
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        controller_port
+            .request(IdlenessMessage::GetSnapshot)
+            .await
+            .unwrap(),
+        Some(IdlenessSnapshot {
+            executed_bunches: 2,
+            rollback_stack: names(&["1-2", "2-1"]),
+            pending_execution: vec![],
+            pending_rollback: names(&["0-1"]),
+            skipped_effects: names(&["2-2"]),
+        })
+    );
+
+    controller_port
+        .request(SystemState::Awakened.into())
+        .await
+        .unwrap();
+    assert_eq!(
+        controller_port
+            .request(IdlenessMessage::GetSnapshot)
+            .await
+            .unwrap(),
+        Some(IdlenessSnapshot::default())
+    );
+}
+
+#[tokio::test]
+async fn test_wake() {
+    let ec1 = EffectsCounter::new();
+    let ec2 = EffectsCounter::new();
+    let action_bunches = vec![
+        vec![make_action(
+            1,
+            1,
+            ec1.get_port(),
+            RollbackStrategy::OnActivity,
+        )],
+        vec![
+            make_action(2, 1, ec1.get_port(), RollbackStrategy::OnActivity),
+            make_action(2, 2, ec2.get_port(), RollbackStrategy::OnActivity),
+        ],
+    ];
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let controller_port = spawn_server(IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        inhibition_sensor.spawn(),
+    ))
+    .await
+    .unwrap();
+
+    controller_port
+        .request(IdlenessMessage::SystemState(SystemState::Idle))
+        .await
+        .unwrap();
+    controller_port
+        .request(IdlenessMessage::Wake)
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    // Waking again doesn't roll anything back twice
+    controller_port
+        .request(IdlenessMessage::Wake)
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    controller_port
+        .request(IdlenessMessage::EndWake)
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 1);
+
+    // The position in the bunches is kept
+    controller_port
+        .request(IdlenessMessage::SystemState(SystemState::Idle))
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 2);
+    assert_eq!(ec2.ongoing_effect_count(), 1);
+    let snapshot = controller_port
+        .request(IdlenessMessage::GetSnapshot)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(snapshot.executed_bunches, 2);
+    assert_eq!(snapshot.rollback_stack, vec!["1-1", "2-1", "2-2"]);
+
+    // Activity during the wake leaves the effects rolled back
+    controller_port
+        .request(IdlenessMessage::Wake)
+        .await
+        .unwrap();
+    controller_port
+        .request(IdlenessMessage::SystemState(SystemState::Awakened))
+        .await
+        .unwrap();
+    controller_port
+        .request(IdlenessMessage::EndWake)
+        .await
+        .unwrap();
+    assert_eq!(ec1.ongoing_effect_count(), 0);
+    assert_eq!(ec2.ongoing_effect_count(), 0);
+}
+
+#[tokio::test]
+async fn test_wake_keeps_waiting_effects() {
+    let screen = EffectsCounter::new();
+    let lock = EffectsCounter::with_capabilities(EffectorCapabilities::waiting_rollback());
+    let action_bunches = vec![vec![
+        make_action(1, 1, screen.get_port(), RollbackStrategy::OnActivity),
+        make_action(1, 2, lock.get_port(), RollbackStrategy::OnActivity),
+    ]];
+    let inhibition_sensor = MockInhibitionSensor::new();
+    let controller_port = spawn_server(IdlenessController::new(
+        action_bunches,
+        0,
+        ReconciliationBunches::new(None, None, HashSet::new()),
+        inhibition_sensor.spawn(),
+    ))
+    .await
+    .unwrap();
+
+    controller_port
+        .request(IdlenessMessage::SystemState(SystemState::Idle))
+        .await
+        .unwrap();
+    controller_port
+        .request(IdlenessMessage::Wake)
+        .await
+        .unwrap();
+    assert_eq!(screen.ongoing_effect_count(), 0);
+    assert_eq!(lock.ongoing_effect_count(), 1);
+    controller_port
+        .request(IdlenessMessage::EndWake)
+        .await
+        .unwrap();
+    assert_eq!(screen.ongoing_effect_count(), 1);
+    assert_eq!(lock.ongoing_effect_count(), 1);
+
+    controller_port
+        .request(IdlenessMessage::SystemState(SystemState::Awakened))
+        .await
+        .unwrap();
+    assert_eq!(screen.ongoing_effect_count(), 0);
+    assert_eq!(lock.ongoing_effect_count(), 0);
+}
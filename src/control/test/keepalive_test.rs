@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use logind_zbus::manager::{InhibitType, InhibitTypes, Inhibitor, Mode};
+use tokio::sync::watch;
+
+use crate::{
+    armaf::{ActorPort, TickService},
+    control::{
+        inhibitor_policy::{InhibitorOverride, InhibitorPolicy},
+        keepalive::Keepalive,
+        manager_state::{ManagerState, StateReporter},
+    },
+    external::display_server::{mock, DisplayServer, SystemState},
+    system::inhibition_sensor::{ActiveInhibitor, InhibitionSensorPort},
+};
+
+const INTERVAL: Duration = Duration::from_secs(30);
+
+fn inhibitor(who: &str, mode: Mode, inhibit_type: InhibitType) -> ActiveInhibitor {
+    ActiveInhibitor {
+        inhibitor: Inhibitor::new(
+            InhibitTypes::new(&vec![inhibit_type]),
+            who.to_owned(),
+            "Testing".to_owned(),
+            mode,
+            0,
+            0,
+        ),
+        age: Duration::ZERO,
+    }
+}
+
+fn spawn_inhibition_sensor(inhibitors: Vec<ActiveInhibitor>) -> InhibitionSensorPort {
+    let (port, mut rx) = ActorPort::make();
+    tokio::spawn(async move {
+        while let Some(req) = rx.recv().await {
+            req.respond(Ok(inhibitors.clone())).unwrap();
+        }
+    });
+    port
+}
+
+/// Let the system go idle and wait whether the keepalive wakes it up
+async fn is_kept_active(iface: &mock::Interface, keepalive: Keepalive<mock::Controller>) -> bool {
+    let mut idleness_channel = iface.get_idleness_channel();
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    idleness_channel.borrow_and_update();
+    let handle = keepalive.spawn();
+    let woken_up = tokio::time::timeout(4 * INTERVAL, idleness_channel.changed())
+        .await
+        .is_ok();
+    handle.await_shutdown().await;
+    woken_up && *idleness_channel.borrow() == SystemState::Awakened
+}
+
+fn make_keepalive(
+    iface: &mock::Interface,
+    state: watch::Receiver<ManagerState>,
+    inhibitors: Vec<ActiveInhibitor>,
+) -> Keepalive<mock::Controller> {
+    Keepalive::new(
+        iface.get_controller(),
+        &TickService::new(),
+        INTERVAL,
+        state,
+        spawn_inhibition_sensor(inhibitors),
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_keepalive_while_paused_or_held() {
+    let iface = mock::Interface::new(600);
+    let (reporter, state) = StateReporter::new();
+
+    assert!(!is_kept_active(&iface, make_keepalive(&iface, state.clone(), Vec::new())).await);
+
+    reporter.update(|state| state.paused = true);
+    assert!(is_kept_active(&iface, make_keepalive(&iface, state.clone(), Vec::new())).await);
+
+    reporter.update(|state| {
+        state.paused = false;
+        state.held_until = Some(tokio::time::Instant::now() + 10 * INTERVAL);
+    });
+    assert!(is_kept_active(&iface, make_keepalive(&iface, state.clone(), Vec::new())).await);
+
+    // An expired hold doesn't keep the system active
+    reporter.update(|state| state.held_until = Some(tokio::time::Instant::now()));
+    assert!(!is_kept_active(&iface, make_keepalive(&iface, state, Vec::new())).await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_keepalive_while_inhibited() {
+    let iface = mock::Interface::new(600);
+    let (_reporter, state) = StateReporter::new();
+
+    let not_inhibiting_idleness = vec![
+        inhibitor("Sleeper", Mode::Block, InhibitType::Sleep),
+        inhibitor("Delayer", Mode::Delay, InhibitType::Idle),
+    ];
+    assert!(
+        !is_kept_active(
+            &iface,
+            make_keepalive(&iface, state.clone(), not_inhibiting_idleness)
+        )
+        .await
+    );
+
+    let inhibitors = vec![inhibitor("mpv", Mode::Block, InhibitType::Idle)];
+    assert!(
+        is_kept_active(
+            &iface,
+            make_keepalive(&iface, state.clone(), inhibitors.clone())
+        )
+        .await
+    );
+
+    // Ignored inhibitors don't keep the system active either
+    let policy = InhibitorPolicy {
+        overrides: vec![InhibitorOverride {
+            who: "mpv".to_owned(),
+            after: Duration::ZERO,
+        }],
+        ..InhibitorPolicy::default()
+    };
+    let keepalive = make_keepalive(&iface, state, inhibitors).with_inhibitor_policy(policy);
+    assert!(!is_kept_active(&iface, keepalive).await);
+}
@@ -0,0 +1,125 @@
+use std::{net::SocketAddr, time::Duration, time::SystemTime};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{broadcast, watch},
+};
+
+use crate::{
+    armaf::{ActorPort, ActorReceiver},
+    control::{
+        environment_controller::ScheduleType,
+        manager_state::{EffectEvent, EffectTransition, StateReporter},
+        metrics::MetricsExporter,
+    },
+    external::display_server::SystemState,
+    system::upower_sensor::PowerStatus,
+};
+
+fn free_address() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+async fn get(address: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Scrape the metrics until all the expected lines are present
+async fn wait_for_metrics(address: SocketAddr, expected: &[&str]) -> String {
+    let mut response = String::new();
+    for _ in 0..50 {
+        response = get(address, "/metrics").await;
+        if expected.iter().all(|line| response.contains(line)) {
+            return response;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Metrics {} don't contain {:?}", response, expected);
+}
+
+fn effect_event(effect_name: &str, transition: EffectTransition) -> EffectEvent {
+    EffectEvent {
+        effect_name: effect_name.to_owned(),
+        transition,
+        timestamp: SystemTime::now(),
+    }
+}
+
+#[tokio::test]
+async fn test_metrics_exporter() {
+    let address = free_address();
+    let (idleness_sender, idleness_channel) = watch::channel(SystemState::Awakened);
+    let (power_sender, power_status) = watch::channel(PowerStatus::Battery(80));
+    let (event_sender, effect_events) = broadcast::channel(16);
+    let (reporter, state) = StateReporter::new();
+    // Requests are queued, but never answered
+    let (port, _receiver): (_, ActorReceiver<(), (), ()>) = ActorPort::make();
+    for _ in 0..2 {
+        let port = port.clone();
+        tokio::spawn(async move { port.request(()).await });
+    }
+
+    let handle = MetricsExporter::new(address)
+        .with_idleness_channel(idleness_channel)
+        .with_effect_events(effect_events)
+        .with_power_status(power_status)
+        .with_state(state)
+        .with_mailbox("Hung", port)
+        .spawn()
+        .await
+        .unwrap();
+
+    let response = wait_for_metrics(
+        address,
+        &[
+            "energia_idle_transitions_total 0\n",
+            "energia_on_battery 1\n",
+            "energia_battery_percentage 80\n",
+            "energia_schedule{schedule_type=\"battery\"} 0\n",
+            "energia_actor_mailbox_depth{actor=\"Hung\"} 2\n",
+        ],
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response
+        .contains("Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n"));
+    assert!(response.ends_with("# EOF\n"));
+
+    idleness_sender.send(SystemState::Idle).unwrap();
+    event_sender
+        .send(effect_event("screen_dim", EffectTransition::Applied))
+        .unwrap();
+    event_sender
+        .send(effect_event("lock", EffectTransition::ApplicationFailed))
+        .unwrap();
+    power_sender.send(PowerStatus::External).unwrap();
+    reporter.update(|state| state.schedule_type = Some(ScheduleType::ExternalPower));
+    let response = wait_for_metrics(
+        address,
+        &[
+            "energia_idle_transitions_total 1\n",
+            "energia_effects_applied_total{effect=\"screen_dim\"} 1\n",
+            "energia_effect_failures_total{effect=\"lock\",operation=\"apply\"} 1\n",
+            "energia_on_battery 0\n",
+            "energia_schedule{schedule_type=\"external\"} 1\n",
+            "energia_schedule{schedule_type=\"battery\"} 0\n",
+        ],
+    )
+    .await;
+    assert!(!response.contains("energia_battery_percentage 80"));
+
+    assert!(get(address, "/")
+        .await
+        .starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    handle.await_shutdown().await;
+}
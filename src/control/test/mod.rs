@@ -1,6 +1,20 @@
 mod effects_counter;
 
+mod config_watcher_test;
 mod dbus_controller_test;
+mod displays_test;
+mod effector_inventory_test;
+mod environment_controller_test;
+mod event_history_test;
+mod hotplug_controller_test;
+mod idleness_broker_test;
 mod idleness_controller_test;
+mod keepalive_test;
+mod metrics_test;
 mod sequencer_test;
+mod signal_handler_test;
 mod sleep_controller_test;
+mod socket_controller_test;
+mod statistics_test;
+mod wake_triggers_test;
+mod watchdog_test;
@@ -2,16 +2,31 @@ use std::time::Duration;
 
 use crate::{
     armaf::{self, ActorPort},
-    control::sequencer::{GetRunningTime, Sequencer},
+    control::{
+        idleness_controller::{
+            BunchReplacement, IdlenessMessage, IdlenessSnapshot, ReconciliationBunches,
+        },
+        manager_state::StateReporter,
+        sequencer::{SequenceReplacement, Sequencer, SequencerCommand, SequencerPort},
+    },
     external::display_server::{mock, DisplayServer, DisplayServerController, SystemState},
+    system::sleep_sensor::SleepUpdate,
 };
 use anyhow::{anyhow, Result};
-use tokio;
+use tokio::{
+    self,
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
+
+fn secs(timeouts: &[u64]) -> Vec<Duration> {
+    timeouts.iter().copied().map(Duration::from_secs).collect()
+}
 
 #[tokio::test(start_paused = true)]
 async fn test_complete_sequence() {
     let iface = mock::Interface::new(600);
-    let sequence = vec![5, 5, 2];
+    let sequence = secs(&[5, 5, 2]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -28,7 +43,10 @@ async fn test_complete_sequence() {
 
     assert!(receiver.request_receiver.try_recv().is_err());
     assert_elapsed_time(&sequencer_port, 0).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 5);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        5
+    );
 
     iface.notify_state_transition(SystemState::Idle).unwrap();
     assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
@@ -56,13 +74,16 @@ async fn test_complete_sequence() {
 
     drop(receiver);
     sequencer_port.await_shutdown().await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 600);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        600
+    );
 }
 
 #[tokio::test(start_paused = true)]
 async fn test_interruptions() {
     let iface = mock::Interface::new(600);
-    let sequence = vec![5, 5, 2];
+    let sequence = secs(&[5, 5, 2]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -77,7 +98,10 @@ async fn test_interruptions() {
         .await
         .expect("Sequencer failed to initialize");
 
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 5);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        5
+    );
     assert_elapsed_time(&sequencer_port, 0).await;
 
     iface.notify_state_transition(SystemState::Idle).unwrap();
@@ -91,7 +115,10 @@ async fn test_interruptions() {
     assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
     assert_elapsed_time(&sequencer_port, 0).await;
 
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 5);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        5
+    );
 
     iface.notify_state_transition(SystemState::Idle).unwrap();
     assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
@@ -108,13 +135,16 @@ async fn test_interruptions() {
 
     drop(receiver);
     sequencer_port.await_shutdown().await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 600);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        600
+    );
 }
 
 #[tokio::test(start_paused = true)]
 async fn test_actor_errors() {
     let iface = mock::Interface::new(600);
-    let sequence = vec![5, 5, 5, 2];
+    let sequence = secs(&[5, 5, 5, 2]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -129,7 +159,10 @@ async fn test_actor_errors() {
         .await
         .expect("Sequencer failed to initialize");
 
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 5);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        5
+    );
     assert_elapsed_time(&sequencer_port, 0).await;
 
     iface.notify_state_transition(SystemState::Idle).unwrap();
@@ -172,13 +205,16 @@ async fn test_actor_errors() {
 
     drop(receiver);
     sequencer_port.await_shutdown().await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 600);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        600
+    );
 }
 
 #[tokio::test(start_paused = true)]
 async fn test_initial_position_from_awakened() {
     let iface = mock::Interface::new(600);
-    let sequence = vec![1, 2, 3, 4];
+    let sequence = secs(&[1, 2, 3, 4]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -193,30 +229,42 @@ async fn test_initial_position_from_awakened() {
         .await
         .expect("Sequencer failed to initialize");
 
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 2);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        2
+    );
     assert_elapsed_time(&sequencer_port, 1).await;
 
     iface.notify_state_transition(SystemState::Idle).unwrap();
     assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
     assert_elapsed_time(&sequencer_port, 3).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
 
     idleness_step(4, &mut receiver, Ok(()), &sequencer_port, 6).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
 
     iface
         .notify_state_transition(SystemState::Awakened)
         .unwrap();
     assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
     assert_elapsed_time(&sequencer_port, 0).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
 }
 
 #[tokio::test(start_paused = true)]
 async fn test_initial_position_from_idle() {
     let iface = mock::Interface::new(600);
     iface.notify_state_transition(SystemState::Idle).unwrap();
-    let sequence = vec![1, 2, 3, 4];
+    let sequence = secs(&[1, 2, 3, 4]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -231,26 +279,35 @@ async fn test_initial_position_from_idle() {
         .await
         .expect("Sequencer failed to initialize");
 
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
     assert_elapsed_time(&sequencer_port, 1).await;
 
     idleness_step(3, &mut receiver, Ok(()), &sequencer_port, 3).await;
     idleness_step(4, &mut receiver, Ok(()), &sequencer_port, 6).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
 
     iface
         .notify_state_transition(SystemState::Awakened)
         .unwrap();
     assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
     assert_elapsed_time(&sequencer_port, 0).await;
-    assert_eq!(iface.get_controller().get_idleness_timeout().unwrap(), 1);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        1
+    );
 }
 
 #[tokio::test(start_paused = true)]
 async fn test_shortened_initial_sleep() {
     let iface = mock::Interface::new(600);
     iface.notify_state_transition(SystemState::Idle).unwrap();
-    let sequence = vec![10];
+    let sequence = secs(&[10]);
     let (port, mut receiver) = ActorPort::make();
     let sequencer = Sequencer::new(
         port,
@@ -268,14 +325,541 @@ async fn test_shortened_initial_sleep() {
     idleness_step(6, &mut receiver, Ok(()), &sequencer_port, 10).await;
 }
 
+#[tokio::test(start_paused = true)]
+async fn test_grace_period() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .with_grace_period(Duration::from_secs(2));
+    let sequencer_port = sequencer
+        .spawn()
+        .await
+        .expect("Sequencer failed to initialize");
+
+    // Activity during the grace period cancels the transition
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    advance_by_secs(1).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    advance_by_secs(5).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    assert_elapsed_time(&sequencer_port, 0).await;
+
+    // Without activity, first position is reached once the grace period passes
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    advance_by_secs(1).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    advance_by_secs(1).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    assert_elapsed_time(&sequencer_port, 5).await;
+
+    idleness_step(5, &mut receiver, Ok(()), &sequencer_port, 10).await;
+
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_idle_time_reporting() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5]);
+    let (port, mut receiver) = ActorPort::make();
+    let (reporter, state) = StateReporter::new();
+    let sequencer = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .with_state_reporter(reporter);
+    let sequencer_port = sequencer
+        .spawn()
+        .await
+        .expect("Sequencer failed to initialize");
+    assert_eq!(state.borrow().idle_since, None);
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    advance_by_secs(1).await;
+    // The display server reports idleness only after the first timeout
+    assert_eq!(
+        state.borrow().idle_since.unwrap().elapsed(),
+        Duration::from_secs(6)
+    );
+    assert_eq!(
+        state.borrow().effects_since.unwrap().elapsed(),
+        Duration::from_secs(1)
+    );
+
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+    advance_by_secs(1).await;
+    assert_eq!(state.borrow().idle_since, None);
+    assert_eq!(state.borrow().effects_since, None);
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_activity_cancels_idleness() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    );
+    let sequencer_port = sequencer
+        .spawn()
+        .await
+        .expect("Sequencer failed to initialize");
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    let idle_request = receiver.recv().await.unwrap();
+    assert!(matches!(
+        idle_request.payload,
+        IdlenessMessage::SystemState(SystemState::Idle)
+    ));
+    assert!(!idle_request.cancellation.is_cancelled());
+
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    idle_request.cancellation.cancelled().await;
+    assert!(idle_request.respond(Ok(None)).is_err());
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+    assert_elapsed_time(&sequencer_port, 0).await;
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_reset() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5, 2]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+
+    // Nothing to reset at the beginning of the sequence
+    let running_time = sequencer_port
+        .request(SequencerCommand::Reset)
+        .await
+        .unwrap();
+    assert_eq!(running_time, Duration::ZERO);
+    assert!(receiver.request_receiver.try_recv().is_err());
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    idleness_step(5, &mut receiver, Ok(()), &sequencer_port, 10).await;
+
+    // The display server still considers the system idle, e.g. after resume
+    let reset = tokio::spawn({
+        let sequencer_port = sequencer_port.clone();
+        async move { sequencer_port.request(SequencerCommand::Reset).await }
+    });
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+    assert_eq!(reset.await.unwrap().unwrap(), Duration::ZERO);
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        5
+    );
+
+    // The sequence starts over once the display server reports idleness
+    advance_by_secs(20).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_sub_second_timeouts() {
+    let iface = mock::Interface::new(600);
+    let sequence = vec![Duration::from_secs(10), Duration::from_millis(500)];
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        10
+    );
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    tokio::time::advance(Duration::from_millis(400)).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    tokio::time::advance(Duration::from_millis(100)).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    let running_time = sequencer_port
+        .request(SequencerCommand::GetRunningTime)
+        .await
+        .unwrap();
+    assert_eq!(running_time, Duration::from_millis(10500));
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_replace_sequence() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5, 2]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    let replacement = SequenceReplacement {
+        timeouts: secs(&[3, 4]),
+        starting_position: 1,
+        shorten_initial_sleep_by: Duration::from_secs(1),
+        grace_period: Duration::ZERO,
+        bunches: BunchReplacement {
+            action_bunches: vec![vec![], vec![]],
+            starting_bunch: 1,
+            reconciliation_bunches: ReconciliationBunches::new(None, None, Default::default()),
+            inhibitor_policy: Default::default(),
+        },
+    };
+    let replace = tokio::spawn({
+        let sequencer_port = sequencer_port.clone();
+        async move {
+            sequencer_port
+                .request(SequencerCommand::ReplaceSequence(Box::new(replacement)))
+                .await
+        }
+    });
+    let req = receiver.recv().await.unwrap();
+    match &req.payload {
+        IdlenessMessage::ReplaceBunches(bunches) => assert_eq!(bunches.starting_bunch, 1),
+        payload => panic!("Unexpected message {:?}", payload),
+    }
+    req.respond(Ok(None)).unwrap();
+    assert_eq!(replace.await.unwrap().unwrap(), Duration::from_secs(3));
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        3
+    );
+
+    // The wait for the next position is shortened by the time which has
+    // already passed
+    advance_by_secs(2).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    idleness_step(1, &mut receiver, Ok(()), &sequencer_port, 7).await;
+
+    // The old sequence stays in use if the controller can't switch
+    let replacement = SequenceReplacement {
+        timeouts: secs(&[1]),
+        starting_position: 0,
+        shorten_initial_sleep_by: Duration::ZERO,
+        grace_period: Duration::ZERO,
+        bunches: BunchReplacement {
+            action_bunches: vec![vec![]],
+            starting_bunch: 0,
+            reconciliation_bunches: ReconciliationBunches::new(None, None, Default::default()),
+            inhibitor_policy: Default::default(),
+        },
+    };
+    let replace = tokio::spawn({
+        let sequencer_port = sequencer_port.clone();
+        async move {
+            sequencer_port
+                .request(SequencerCommand::ReplaceSequence(Box::new(replacement)))
+                .await
+        }
+    });
+    let req = receiver.recv().await.unwrap();
+    req.respond(Err(anyhow!("Can't switch"))).unwrap();
+    assert!(replace.await.unwrap().is_err());
+    assert_eq!(
+        iface.get_controller().get_idleness_timeout().await.unwrap(),
+        3
+    );
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hold() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 5]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+
+    sequencer_port
+        .request(SequencerCommand::Hold(Duration::from_secs(60)))
+        .await
+        .unwrap();
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    advance_by_secs(30).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+
+    // Activity during the hold doesn't need to roll anything back
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    advance_by_secs(10).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+
+    // Once the hold expires, the idle system continues with the schedule
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    advance_by_secs(19).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    advance_by_secs(1).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    assert_elapsed_time(&sequencer_port, 5).await;
+
+    // Internally timed positions are held too
+    sequencer_port
+        .request(SequencerCommand::Hold(Duration::from_secs(10)))
+        .await
+        .unwrap();
+    advance_by_secs(5).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    advance_by_secs(5).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+
+    // Ending the hold early applies the effects right away
+    sequencer_port
+        .request(SequencerCommand::Hold(Duration::from_secs(60)))
+        .await
+        .unwrap();
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    advance_by_secs(10).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    sequencer_port
+        .request(SequencerCommand::Hold(Duration::ZERO))
+        .await
+        .unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_wake() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 20]);
+    let (port, mut receiver) = ActorPort::make();
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+
+    // Nothing to wake while active
+    sequencer_port
+        .request(SequencerCommand::Wake(Duration::from_secs(10)))
+        .await
+        .unwrap();
+    assert!(receiver.request_receiver.try_recv().is_err());
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    let wake = tokio::spawn({
+        let sequencer_port = sequencer_port.clone();
+        async move {
+            sequencer_port
+                .request(SequencerCommand::Wake(Duration::from_secs(10)))
+                .await
+        }
+    });
+    assert_message_came(&mut receiver, IdlenessMessage::Wake).await;
+    wake.await.unwrap().unwrap();
+    advance_by_secs(9).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    advance_by_secs(1).await;
+    assert_message_came(&mut receiver, IdlenessMessage::EndWake).await;
+    // The time spent awake counts towards the next position
+    advance_by_secs(10).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    // Activity during the wake rolls the effects back for good
+    let wake = tokio::spawn({
+        let sequencer_port = sequencer_port.clone();
+        async move {
+            sequencer_port
+                .request(SequencerCommand::Wake(Duration::from_secs(10)))
+                .await
+        }
+    });
+    assert_message_came(&mut receiver, IdlenessMessage::Wake).await;
+    wake.await.unwrap().unwrap();
+    iface
+        .notify_state_transition(SystemState::Awakened)
+        .unwrap();
+    assert_request_came(&mut receiver, SystemState::Awakened, Ok(())).await;
+    advance_by_secs(20).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_suspend() {
+    let iface = mock::Interface::new(600);
+    let sequence = secs(&[5, 10]);
+    let (port, mut receiver) = ActorPort::make();
+    let (sleep_sender, sleep_receiver) = broadcast::channel(4);
+    let sequencer_port = Sequencer::new(
+        port,
+        iface.get_controller(),
+        iface.get_idleness_channel(),
+        &sequence,
+        0,
+        Duration::ZERO,
+    )
+    .with_sleep_updates(sleep_receiver)
+    .spawn()
+    .await
+    .expect("Sequencer failed to initialize");
+
+    iface.notify_state_transition(SystemState::Idle).unwrap();
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+    advance_by_secs(2).await;
+
+    let (confirmation_sender, mut confirmation_receiver) = mpsc::channel(1);
+    sleep_sender
+        .send(SleepUpdate::GoingToSleep(
+            confirmation_sender,
+            Instant::now(),
+        ))
+        .unwrap();
+    confirmation_receiver.recv().await.unwrap();
+    // Tokio's paused clock stands still, like the monotonic clock does while
+    // the system is suspended
+    std::thread::sleep(Duration::from_millis(500));
+    sleep_sender.send(SleepUpdate::WokenUp).unwrap();
+    tokio::task::yield_now().await;
+
+    let running_time = sequencer_port
+        .request(SequencerCommand::GetRunningTime)
+        .await
+        .unwrap();
+    assert!(
+        running_time >= Duration::from_millis(7500),
+        "{:?}",
+        running_time
+    );
+    // Without the suspend, the next position would be 8 seconds away
+    tokio::time::advance(Duration::from_millis(7500)).await;
+    assert_request_came(&mut receiver, SystemState::Idle, Ok(())).await;
+
+    drop(receiver);
+    sequencer_port.await_shutdown().await;
+}
+
+async fn assert_message_came(
+    receiver: &mut armaf::ActorReceiver<IdlenessMessage, Option<IdlenessSnapshot>, anyhow::Error>,
+    expected: IdlenessMessage,
+) {
+    let req = receiver.recv().await.unwrap();
+    assert_eq!(
+        std::mem::discriminant(&req.payload),
+        std::mem::discriminant(&expected),
+        "{:?} isn't a {:?} message",
+        req.payload,
+        expected
+    );
+    req.respond(Ok(None)).unwrap();
+}
+
 async fn assert_request_came(
-    receiver: &mut armaf::ActorReceiver<SystemState, (), anyhow::Error>,
+    receiver: &mut armaf::ActorReceiver<IdlenessMessage, Option<IdlenessSnapshot>, anyhow::Error>,
     expected_state: SystemState,
     response: Result<()>,
 ) {
     let req = receiver.recv().await.unwrap();
-    assert_eq!(req.payload, expected_state);
-    req.respond(response).unwrap();
+    assert!(
+        matches!(req.payload, IdlenessMessage::SystemState(state) if state == expected_state),
+        "{:?} isn't a {:?} message",
+        req.payload,
+        expected_state
+    );
+    req.respond(response.map(|_| None)).unwrap();
 }
 
 async fn advance_by_secs(seconds: u64) {
@@ -283,12 +867,9 @@ async fn advance_by_secs(seconds: u64) {
     tokio::time::advance(Duration::from_secs(seconds)).await
 }
 
-async fn assert_elapsed_time(
-    port: &ActorPort<GetRunningTime, Duration, ()>,
-    expected_seconds: u64,
-) {
+async fn assert_elapsed_time(port: &SequencerPort, expected_seconds: u64) {
     let res = port
-        .request(GetRunningTime)
+        .request(SequencerCommand::GetRunningTime)
         .await
         .expect("couldn't get running time from Sequencer");
     assert_eq!(res, Duration::from_secs(expected_seconds));
@@ -296,9 +877,9 @@ async fn assert_elapsed_time(
 
 async fn idleness_step(
     advance_secs: u64,
-    receiver: &mut armaf::ActorReceiver<SystemState, (), anyhow::Error>,
+    receiver: &mut armaf::ActorReceiver<IdlenessMessage, Option<IdlenessSnapshot>, anyhow::Error>,
     response: Result<()>,
-    sequencer_port: &ActorPort<GetRunningTime, Duration, ()>,
+    sequencer_port: &SequencerPort,
     expected_seconds: u64,
 ) {
     advance_by_secs(advance_secs).await;
@@ -0,0 +1,90 @@
+use std::time::{Duration, SystemTime};
+
+use tokio::{sync::watch, time::Instant};
+
+use crate::{
+    armaf::{ActorHealth, ActorInfo, ActorPort},
+    config::ConfigSources,
+    control::{
+        environment_controller::{EnvironmentCommand, ScheduleType},
+        manager_state::ManagerState,
+        signal_handler::{status_report, SignalHandler},
+    },
+};
+
+#[tokio::test]
+async fn test_reload_on_sighup() {
+    let directory = std::env::temp_dir().join(format!(
+        "energia_signal_handler_test_{}",
+        std::process::id()
+    ));
+    tokio::fs::create_dir_all(&directory).await.unwrap();
+    let path = directory.join("config.toml");
+    tokio::fs::write(&path, "timeout = 1").await.unwrap();
+
+    let (port, mut receiver) = ActorPort::<EnvironmentCommand, (), anyhow::Error>::make();
+    let sources = ConfigSources {
+        system_file: None,
+        user_file: path.clone(),
+        user_file_required: true,
+        drop_in_dir: None,
+    };
+    let (_state_sender, state_receiver) = watch::channel(ManagerState::default());
+    let mut handler = SignalHandler::new(sources, port, state_receiver).unwrap();
+    let handler_task = tokio::spawn(async move { handler.wait_for_termination().await });
+
+    // SAFETY: the handler of SIGHUP has already been registered
+    unsafe { libc::raise(libc::SIGHUP) };
+    let request = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("No reload request received")
+        .unwrap();
+    match &request.payload {
+        EnvironmentCommand::ReloadConfig(config) => {
+            assert_eq!(config.as_ref(), &toml::toml! { timeout = 1 })
+        }
+        other => panic!("Unexpected command {:?}", other),
+    }
+    request.respond(Ok(())).unwrap();
+
+    handler_task.abort();
+    tokio::fs::remove_dir_all(&directory).await.unwrap();
+}
+
+#[test]
+fn test_status_report() {
+    let now = Instant::now();
+    let actors = vec![
+        ActorInfo {
+            id: 1,
+            name: "Sequencer".to_owned(),
+            started_at: SystemTime::now(),
+            health: ActorHealth::Running,
+        },
+        ActorInfo {
+            id: 2,
+            name: "DpmsEffector".to_owned(),
+            started_at: SystemTime::now(),
+            health: ActorHealth::Crashed,
+        },
+    ];
+    let state = ManagerState {
+        schedule_type: Some(ScheduleType::Battery),
+        current_bunch: 2,
+        applied_effects: vec!["screen_dim".to_owned(), "lock".to_owned()],
+        next_effect_at: Some(now + Duration::from_secs(30)),
+        idle_since: Some(now - Duration::from_secs(90)),
+        ..Default::default()
+    };
+    assert_eq!(
+        status_report(&actors, &state, now),
+        vec![
+            "Status report, 2 actors:",
+            "  actor 1 Sequencer is running",
+            "  actor 2 DpmsEffector is crashed",
+            "  schedule battery, next bunch 2, paused: false",
+            "  applied effects: [screen_dim, lock]",
+            "  idle: for 90s, next effect: in 30s, hold ends: never",
+        ]
+    );
+}
@@ -1,11 +1,23 @@
 use crate::{
-    control::sleep_controller::SleepController,
-    external::display_server::{mock, DisplayServer, SystemState},
+    armaf::{spawn_server, EffectorMessage},
+    control::{
+        effector_inventory::{get_effector_port, EffectorInventory},
+        environment_controller::EnvironmentCommand,
+        sleep_controller::{SleepController, SleepHook, SleepHooks},
+    },
+    external::{
+        dependency_provider::DependencyProvider,
+        display_server::{mock, DisplayServer, SystemState},
+    },
     system::sleep_sensor::SleepUpdate,
 };
 
 use super::effects_counter::EffectsCounter;
 
+fn sleep_deadline() -> tokio::time::Instant {
+    tokio::time::Instant::now() + std::time::Duration::from_secs(5)
+}
+
 #[tokio::test]
 async fn test_with_locker() {
     let lock_ec = EffectsCounter::new();
@@ -24,7 +36,10 @@ async fn test_with_locker() {
 
     let (confirmation_sender, mut confirmation_receiver) = tokio::sync::mpsc::channel(1);
     sleep_sender
-        .send(SleepUpdate::GoingToSleep(confirmation_sender))
+        .send(SleepUpdate::GoingToSleep(
+            confirmation_sender,
+            sleep_deadline(),
+        ))
         .unwrap();
 
     confirmation_receiver.recv().await.unwrap();
@@ -52,7 +67,10 @@ async fn test_without_locker() {
 
     let (confirmation_sender, mut confirmation_receiver) = tokio::sync::mpsc::channel(1);
     sleep_sender
-        .send(SleepUpdate::GoingToSleep(confirmation_sender))
+        .send(SleepUpdate::GoingToSleep(
+            confirmation_sender,
+            sleep_deadline(),
+        ))
         .unwrap();
 
     confirmation_receiver.recv().await.unwrap();
@@ -66,3 +84,113 @@ async fn test_without_locker() {
 
     sleep_controller_handle.await_shutdown().await;
 }
+
+#[tokio::test]
+async fn test_hooks() {
+    let lock_ec = EffectsCounter::new();
+    let inventory = spawn_server(EffectorInventory::new(
+        std::sync::Arc::new(toml::Value::Table(toml::value::Map::new())),
+        DependencyProvider::make_mock(None),
+    ))
+    .await
+    .unwrap();
+    let marker = std::env::temp_dir().join(format!("energia-sleep-hook-{}", std::process::id()));
+    let shell_hook = |script: &str| SleepHook::Command {
+        command: "sh".to_owned(),
+        args: vec!["-c".to_owned(), format!("{} {}", script, marker.display())],
+    };
+    let hooks = SleepHooks {
+        before_sleep: vec![
+            SleepHook::Effect("lock".to_owned()),
+            SleepHook::Effect("screen_off".to_owned()),
+            shell_hook("touch"),
+        ],
+        after_wake: vec![SleepHook::Effect("screen_off".to_owned()), shell_hook("rm")],
+    };
+    let (sleep_sender, sleep_receiver) = tokio::sync::broadcast::channel(1);
+    let ds = mock::Interface::new(10);
+    let sleep_controller_handle = SleepController::new(
+        sleep_receiver,
+        Some(lock_ec.get_port()),
+        ds.get_controller(),
+    )
+    .with_hooks(hooks, inventory.clone())
+    .spawn()
+    .await;
+    let dpms_port = get_effector_port(&inventory, "dpms").await.unwrap();
+    ds.notify_state_transition(SystemState::Idle).unwrap();
+
+    let (confirmation_sender, mut confirmation_receiver) = tokio::sync::mpsc::channel(1);
+    sleep_sender
+        .send(SleepUpdate::GoingToSleep(
+            confirmation_sender,
+            sleep_deadline(),
+        ))
+        .unwrap();
+    confirmation_receiver.recv().await.unwrap();
+    assert_eq!(lock_ec.ongoing_effect_count(), 1);
+    let status = dpms_port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(status.applied_effects, 1);
+    assert!(marker.exists());
+
+    let mut idleness_channel = ds.get_idleness_channel();
+    idleness_channel.borrow_and_update();
+    sleep_sender.send(SleepUpdate::WokenUp).unwrap();
+    idleness_channel.changed().await.unwrap();
+    // The hooks run after the activity is forced
+    while marker.exists() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let status = dpms_port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(status.applied_effects, 0);
+    assert_eq!(lock_ec.ongoing_effect_count(), 1);
+
+    sleep_controller_handle.await_shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stuck_locker() {
+    // The locker never reports that it has locked the session
+    let (lock_port, _lock_receiver) = crate::armaf::ActorPort::make();
+    let (sleep_sender, sleep_receiver) = tokio::sync::broadcast::channel(1);
+    let ds = mock::Interface::new(10);
+    let sleep_controller_handle =
+        SleepController::new(sleep_receiver, Some(lock_port), ds.get_controller())
+            .spawn()
+            .await;
+
+    let start = tokio::time::Instant::now();
+    let deadline = start + std::time::Duration::from_secs(1);
+    let (confirmation_sender, mut confirmation_receiver) = tokio::sync::mpsc::channel(1);
+    sleep_sender
+        .send(SleepUpdate::GoingToSleep(confirmation_sender, deadline))
+        .unwrap();
+    confirmation_receiver.recv().await.unwrap();
+    assert!(tokio::time::Instant::now() < deadline);
+
+    sleep_controller_handle.await_shutdown().await;
+}
+
+#[tokio::test]
+async fn test_schedule_reset_after_wake_up() {
+    let (environment_port, mut environment_receiver) = crate::armaf::ActorPort::make();
+    let (sleep_sender, sleep_receiver) = tokio::sync::broadcast::channel(1);
+    let ds = mock::Interface::new(10);
+    let sleep_controller_handle = SleepController::new(sleep_receiver, None, ds.get_controller())
+        .with_environment_controller(environment_port)
+        .spawn()
+        .await;
+
+    sleep_sender.send(SleepUpdate::WokenUp).unwrap();
+    let request = environment_receiver.recv().await.unwrap();
+    assert!(matches!(request.payload, EnvironmentCommand::ResetSchedule));
+    request.respond(Ok(())).unwrap();
+
+    sleep_controller_handle.await_shutdown().await;
+}
@@ -0,0 +1,86 @@
+use logind_zbus::manager::InhibitType;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{unix::OwnedReadHalf, unix::OwnedWriteHalf, UnixStream},
+};
+
+use crate::{
+    control::{
+        manager_state::StateReporter, socket_controller::SocketController,
+        test::effects_counter::EffectsCounter,
+    },
+    system::inhibition_sensor::ApplicationInhibitions,
+};
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(path: &std::path::Path) -> Client {
+        let (reader, writer) = UnixStream::connect(path).await.unwrap().into_split();
+        Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn call(&mut self, request: &str) -> Value {
+        self.writer
+            .write_all(format!("{}\n", request).as_bytes())
+            .await
+            .unwrap();
+        let response = self.lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_socket_control() {
+    let path = std::env::temp_dir().join(format!("energia-test-{}.sock", std::process::id()));
+    let ec = EffectsCounter::new();
+    let (reporter, state) = StateReporter::new();
+    reporter.update(|state| state.applied_effects = vec!["screen_dim".to_owned()]);
+    let inhibitions = ApplicationInhibitions::new();
+    let handle = SocketController::new(&path, Some(ec.get_port()), state)
+        .with_application_inhibitions(inhibitions.clone())
+        .spawn()
+        .await
+        .unwrap();
+
+    let mut client = Client::connect(&path).await;
+    let status = client.call(r#"{"command": "status"}"#).await;
+    assert_eq!(status["ok"], true);
+    assert_eq!(status["result"]["applied_effects"][0], "screen_dim");
+    assert_eq!(status["result"]["time_until_next_effect_ms"], Value::Null);
+
+    assert_eq!(client.call(r#"{"command": "lock"}"#).await["ok"], true);
+    assert_eq!(ec.ongoing_effect_count(), 1);
+
+    let unsupported = client.call(r#"{"command": "pause"}"#).await;
+    assert_eq!(unsupported["ok"], false);
+    let invalid = client.call("not json").await;
+    assert_eq!(invalid["ok"], false);
+
+    let inhibited = client
+        .call(r#"{"command": "inhibit", "application": "mpv", "reason": "Playing"}"#)
+        .await;
+    assert!(inhibited["result"]["cookie"].is_u64());
+    assert!(inhibitions.has(InhibitType::Idle));
+
+    // Inhibitions are dropped together with the connection
+    drop(client);
+    for _ in 0..100 {
+        if !inhibitions.has(InhibitType::Idle) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(!inhibitions.has(InhibitType::Idle));
+
+    handle.await_shutdown().await;
+    assert!(!path.exists());
+}
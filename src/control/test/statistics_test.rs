@@ -0,0 +1,141 @@
+use std::{
+    env,
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::{
+    armaf::TickService,
+    control::{
+        manager_state::{EffectEvent, EffectTransition},
+        statistics::{Category, StatisticsCollector, StatisticsStore},
+    },
+    external::display_server::SystemState,
+    system::{clock_sensor::ClockJump, sleep_sensor::SleepUpdate},
+};
+
+fn total(store: &StatisticsStore, category: &Category) -> Option<Duration> {
+    store
+        .days()
+        .values()
+        .filter_map(|totals| totals.get(category).map(|totals| totals.time))
+        .reduce(|a, b| a + b)
+}
+
+fn effect_event(transition: EffectTransition, timestamp: SystemTime) -> EffectEvent {
+    EffectEvent {
+        effect_name: "screen_off".to_owned(),
+        transition,
+        timestamp,
+    }
+}
+
+#[tokio::test]
+async fn test_statistics_collector() {
+    let path = env::temp_dir().join(format!(
+        "energia-statistics-collector-{}.csv",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let (idleness_sender, idleness_channel) = watch::channel(SystemState::Awakened);
+    let (event_sender, effect_events) = broadcast::channel(16);
+    let (sleep_sender, sleep_updates) = broadcast::channel(4);
+    let handle = StatisticsCollector::new(
+        StatisticsStore::open(&path).unwrap(),
+        idleness_channel,
+        effect_events,
+        &TickService::new(),
+    )
+    .with_sleep_updates(sleep_updates)
+    .spawn();
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    idleness_sender.send(SystemState::Idle).unwrap();
+    let now = SystemTime::now();
+    event_sender
+        .send(effect_event(
+            EffectTransition::Applied,
+            now - Duration::from_secs(120),
+        ))
+        .unwrap();
+    event_sender
+        .send(effect_event(
+            EffectTransition::RolledBack,
+            now - Duration::from_secs(60),
+        ))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let (confirmation_sender, mut confirmations) = mpsc::channel(1);
+    sleep_sender
+        .send(SleepUpdate::GoingToSleep(
+            confirmation_sender,
+            tokio::time::Instant::now() + Duration::from_secs(5),
+        ))
+        .unwrap();
+    assert!(confirmations.recv().await.is_some());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    sleep_sender.send(SleepUpdate::WokenUp).unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    handle.await_shutdown().await;
+
+    let store = StatisticsStore::open(&path).unwrap();
+    assert!(total(&store, &Category::Active).unwrap() >= Duration::from_millis(20));
+    assert!(total(&store, &Category::Idle).unwrap() >= Duration::from_millis(20));
+    assert!(total(&store, &Category::Suspended).unwrap() >= Duration::from_millis(20));
+    assert_eq!(
+        total(&store, &Category::Effect("screen_off".to_owned())),
+        Some(Duration::from_secs(60))
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_clock_jump() {
+    let path = env::temp_dir().join(format!(
+        "energia-statistics-clock-jump-{}.csv",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let (_idleness_sender, idleness_channel) = watch::channel(SystemState::Awakened);
+    let (event_sender, effect_events) = broadcast::channel(16);
+    let (jump_sender, clock_jumps) = broadcast::channel(4);
+    let handle = StatisticsCollector::new(
+        StatisticsStore::open(&path).unwrap(),
+        idleness_channel,
+        effect_events,
+        &TickService::new(),
+    )
+    .with_clock_jumps(clock_jumps)
+    .spawn();
+
+    let now = SystemTime::now();
+    event_sender
+        .send(effect_event(EffectTransition::Applied, now))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    // The clock is set an hour forward while the effect is applied
+    jump_sender
+        .send(ClockJump {
+            offset: 3600,
+            utc_offset_change: 0,
+        })
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    event_sender
+        .send(effect_event(
+            EffectTransition::RolledBack,
+            now + Duration::from_secs(3600 + 60),
+        ))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    handle.await_shutdown().await;
+
+    let store = StatisticsStore::open(&path).unwrap();
+    assert_eq!(
+        total(&store, &Category::Effect("screen_off".to_owned())),
+        Some(Duration::from_secs(60))
+    );
+    std::fs::remove_file(&path).unwrap();
+}
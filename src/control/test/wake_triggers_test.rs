@@ -0,0 +1,41 @@
+use std::{fs, time::Duration};
+
+use crate::{
+    armaf::ActorPort,
+    control::{
+        environment_controller::EnvironmentCommand,
+        wake_triggers::{WakeController, WakeTriggers},
+    },
+};
+
+#[tokio::test]
+async fn test_wake_on_input() {
+    let device = std::env::temp_dir().join(format!("energia-input-{}", std::process::id()));
+    // A key press and the synchronization event following it
+    fs::write(
+        &device,
+        vec![0u8; 2 * std::mem::size_of::<libc::input_event>()],
+    )
+    .unwrap();
+    let (port, mut receiver) = ActorPort::make();
+    let triggers = WakeTriggers {
+        duration: Duration::from_secs(15),
+        notifications: false,
+        input_devices: vec![device.clone()],
+    };
+    let _handle = WakeController::new(triggers, port).spawn();
+
+    let request = tokio::time::timeout(Duration::from_secs(1), receiver.recv())
+        .await
+        .expect("Input didn't wake the screen")
+        .unwrap();
+    assert!(matches!(
+        request.payload,
+        EnvironmentCommand::Wake(duration) if duration == Duration::from_secs(15)
+    ));
+    request.respond(Ok(())).unwrap();
+    // Events of the same key press wake the screen only once
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(receiver.request_receiver.try_recv().is_err());
+    fs::remove_file(&device).unwrap();
+}
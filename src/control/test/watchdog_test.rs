@@ -0,0 +1,80 @@
+use std::{env, os::unix::net::UnixDatagram, path::PathBuf, time::Duration};
+
+use crate::{
+    armaf::{ActorPort, ActorReceiver, TickService},
+    control::watchdog::Watchdog,
+    external::systemd::SystemdNotifier,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn bind_socket(name: &str) -> (PathBuf, UnixDatagram) {
+    let path = env::temp_dir().join(format!("energia-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    (path, socket)
+}
+
+fn pings_received(socket: &UnixDatagram) -> usize {
+    let mut buffer = [0u8; 64];
+    let mut pings = 0;
+    while let Ok(received) = socket.recv(&mut buffer) {
+        assert_eq!(&buffer[..received], b"WATCHDOG=1");
+        pings += 1;
+    }
+    pings
+}
+
+fn spawn_responsive_port() -> ActorPort<(), (), ()> {
+    let (port, mut receiver) = ActorPort::make();
+    tokio::spawn(async move {
+        while let Some(request) = receiver.recv().await {
+            request.respond(Ok(())).unwrap();
+        }
+    });
+    port
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pings_while_healthy() {
+    let (path, socket) = bind_socket("watchdog-healthy");
+    let handle = Watchdog::new(
+        SystemdNotifier::new(path.clone(), Some(TIMEOUT)),
+        TIMEOUT,
+        &TickService::new(),
+    )
+    .with_port_check("Responsive", spawn_responsive_port(), ())
+    .spawn();
+
+    tokio::time::sleep(TIMEOUT / 4).await;
+    assert_eq!(pings_received(&socket), 0);
+    tokio::time::sleep(TIMEOUT / 2).await;
+    assert_eq!(pings_received(&socket), 1);
+    tokio::time::sleep(TIMEOUT / 2).await;
+    assert_eq!(pings_received(&socket), 1);
+
+    handle.await_shutdown().await;
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_no_pings_when_hung() {
+    let (path, socket) = bind_socket("watchdog-hung");
+    // Requests are queued, but never answered
+    let (hung_port, _receiver): (_, ActorReceiver<(), (), ()>) = ActorPort::make();
+    let handle = Watchdog::new(
+        SystemdNotifier::new(path.clone(), Some(TIMEOUT)),
+        TIMEOUT,
+        &TickService::new(),
+    )
+    .with_port_check("Responsive", spawn_responsive_port(), ())
+    .with_port_check("Hung", hung_port, ())
+    .spawn();
+
+    tokio::time::sleep(2 * TIMEOUT).await;
+    assert_eq!(pings_received(&socket), 0);
+
+    handle.await_shutdown().await;
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,306 @@
+//! Wakes the screen for a while when something the user may want to see
+//! happens, e.g. a notification pops up, configured in the `[wake]` section.
+//!
+//! Unlike activity, waking doesn't return to the beginning of the schedule.
+//! The applied effects are rolled back and, unless the user becomes active,
+//! applied again once the wake ends, see [EnvironmentCommand::Wake].
+
+use super::environment_controller::{parse_duration, EnvironmentCommand, EnvironmentPort};
+use crate::armaf;
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{io::AsyncReadExt, sync::mpsc, time::Instant};
+use tokio_stream::StreamExt;
+
+/// Name of the configuration section enabling the wake triggers
+pub const WAKE_SECTION: &str = "wake";
+
+/// How long the screen stays awake if the configuration doesn't say
+const DEFAULT_WAKE_DURATION: Duration = Duration::from_secs(10);
+
+/// Triggers arriving sooner than this after a wake don't wake the screen
+/// again, an input device sends several events for each key press
+const MIN_WAKE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Match rule for the calls showing a notification
+const NOTIFY_MATCH_RULE: &str =
+    "type='method_call',interface='org.freedesktop.Notifications',member='Notify'";
+
+/// Arguments of the `Notify` call: application name, ID of the replaced
+/// notification, icon, summary, body, actions, hints and timeout
+type NotifyArguments = (
+    String,
+    u32,
+    String,
+    String,
+    String,
+    Vec<String>,
+    HashMap<String, zvariant::OwnedValue>,
+    i32,
+);
+
+/// What wakes the screen and for how long
+#[derive(Debug, Clone, PartialEq)]
+pub struct WakeTriggers {
+    pub duration: Duration,
+    /// Wake when an application shows a notification
+    pub notifications: bool,
+    /// Wake on any event from these `/dev/input` devices
+    pub input_devices: Vec<PathBuf>,
+}
+
+/// Parse the wake triggers, [None] if the section is missing
+pub fn parse_wake_triggers(config: &toml::Value) -> Result<Option<WakeTriggers>> {
+    let section = match config.get(WAKE_SECTION) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+    let duration = match section.get("duration") {
+        Some(duration) => duration
+            .as_str()
+            .ok_or_else(|| anyhow!("{} is not a duration string", duration))
+            .and_then(parse_duration)
+            .context("Invalid wake.duration")?,
+        None => DEFAULT_WAKE_DURATION,
+    };
+    if duration.is_zero() {
+        return Err(anyhow!("must be longer than 0")).context("Invalid wake.duration");
+    }
+    let notifications = match section.get("notifications") {
+        Some(notifications) => notifications
+            .as_bool()
+            .ok_or_else(|| anyhow!("must be a boolean"))
+            .context("Invalid wake.notifications")?,
+        None => false,
+    };
+    let input_devices = match section.get("input_devices") {
+        Some(devices) => devices
+            .as_array()
+            .and_then(|devices| {
+                devices
+                    .iter()
+                    .map(|device| device.as_str().map(PathBuf::from))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| anyhow!("must be an array of paths"))
+            .context("Invalid wake.input_devices")?,
+        None => Vec::new(),
+    };
+    if !notifications && input_devices.is_empty() {
+        return Err(anyhow!(
+            "Invalid wake section: enable notifications or list input_devices"
+        ));
+    }
+    Ok(Some(WakeTriggers {
+        duration,
+        notifications,
+        input_devices,
+    }))
+}
+
+/// Listens for the configured triggers and asks the [EnvironmentController]
+/// to wake the screen when one of them fires
+///
+/// [EnvironmentController]: super::environment_controller::EnvironmentController
+pub struct WakeController {
+    triggers: WakeTriggers,
+    environment_controller: EnvironmentPort,
+    handle_child: Option<armaf::HandleChild>,
+}
+
+impl WakeController {
+    pub fn new(triggers: WakeTriggers, environment_controller: EnvironmentPort) -> WakeController {
+        WakeController {
+            triggers,
+            environment_controller,
+            handle_child: None,
+        }
+    }
+
+    pub fn spawn(mut self) -> armaf::Handle {
+        let (handle, handle_child) = armaf::Handle::new();
+        self.handle_child = Some(handle_child);
+        let (trigger_sender, trigger_receiver) = mpsc::channel(8);
+        if self.triggers.notifications {
+            let sender = trigger_sender.clone();
+            tokio::spawn(async move {
+                if let Err(e) = watch_notifications(sender).await {
+                    log::error!("{:#}, notifications won't wake the screen", e);
+                }
+            });
+        }
+        for device in self.triggers.input_devices.iter().cloned() {
+            let sender = trigger_sender.clone();
+            tokio::spawn(async move {
+                if let Err(e) = watch_input_device(&device, sender).await {
+                    log::error!(
+                        "{:#}, input from {} won't wake the screen",
+                        e,
+                        device.display()
+                    );
+                }
+            });
+        }
+
+        tokio::spawn(async move {
+            self.main_loop(trigger_receiver).await;
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self, mut triggers: mpsc::Receiver<String>) {
+        let mut last_wake: Option<Instant> = None;
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => {
+                    return;
+                }
+                Some(trigger) = triggers.recv() => {
+                    if matches!(last_wake, Some(at) if at.elapsed() < MIN_WAKE_INTERVAL) {
+                        continue;
+                    }
+                    last_wake = Some(Instant::now());
+                    log::info!("Waking the screen for {:?}, {}", self.triggers.duration, trigger);
+                    if let Err(e) = self
+                        .environment_controller
+                        .request(EnvironmentCommand::Wake(self.triggers.duration))
+                        .await
+                    {
+                        log::error!("Couldn't wake the screen: {:?}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Report the notifications shown by any application on the session bus,
+/// observed through a monitoring connection
+async fn watch_notifications(sender: mpsc::Sender<String>) -> Result<()> {
+    let connection = zbus::Connection::session()
+        .await
+        .context("Couldn't connect to the session bus")?;
+    connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus.Monitoring"),
+            "BecomeMonitor",
+            &(vec![NOTIFY_MATCH_RULE], 0u32),
+        )
+        .await
+        .context("Couldn't monitor the notifications")?;
+    let mut messages = zbus::MessageStream::from(&connection);
+    while let Some(message) = messages.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Couldn't receive a monitored message: {}", e);
+                continue;
+            }
+        };
+        if !matches!(message.member(), Ok(Some(member)) if member.as_str() == "Notify") {
+            continue;
+        }
+        let application = message
+            .body::<NotifyArguments>()
+            .map(|arguments| arguments.0)
+            .unwrap_or_default();
+        if sender
+            .send(format!("notification from {}", application))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+    Err(anyhow!("Monitoring connection closed"))
+}
+
+/// Report each event read from the input device
+async fn watch_input_device(device: &Path, sender: mpsc::Sender<String>) -> Result<()> {
+    let mut file = tokio::fs::File::open(device)
+        .await
+        .with_context(|| format!("Couldn't open {}", device.display()))?;
+    let mut event = vec![0u8; std::mem::size_of::<libc::input_event>()];
+    loop {
+        file.read_exact(&mut event)
+            .await
+            .with_context(|| format!("Couldn't read from {}", device.display()))?;
+        if sender
+            .send(format!("input from {}", device.display()))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_wake_triggers() {
+        assert_eq!(
+            parse_wake_triggers(&toml::toml! { [lock] command = "i3lock" }).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_wake_triggers(&toml::toml! {
+                [wake]
+                notifications = true
+            })
+            .unwrap(),
+            Some(WakeTriggers {
+                duration: DEFAULT_WAKE_DURATION,
+                notifications: true,
+                input_devices: Vec::new(),
+            })
+        );
+        assert_eq!(
+            parse_wake_triggers(&toml::toml! {
+                [wake]
+                duration = "30s"
+                input_devices = ["/dev/input/event5"]
+            })
+            .unwrap(),
+            Some(WakeTriggers {
+                duration: Duration::from_secs(30),
+                notifications: false,
+                input_devices: vec![PathBuf::from("/dev/input/event5")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_wake_triggers() {
+        assert!(parse_wake_triggers(&toml::toml! {
+            [wake]
+            duration = "10s"
+        })
+        .is_err());
+        assert!(parse_wake_triggers(&toml::toml! {
+            [wake]
+            notifications = "yes"
+        })
+        .is_err());
+        assert!(parse_wake_triggers(&toml::toml! {
+            [wake]
+            notifications = true
+            duration = "0s"
+        })
+        .is_err());
+        assert!(parse_wake_triggers(&toml::toml! {
+            [wake]
+            input_devices = "/dev/input/event5"
+        })
+        .is_err());
+    }
+}
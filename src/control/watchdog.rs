@@ -0,0 +1,114 @@
+//! Pings the systemd watchdog as long as the key actors respond, so that a
+//! hung daemon gets restarted by the service manager
+
+use crate::{
+    armaf::{self, ActorPort, TickService, Ticks},
+    external::systemd::SystemdNotifier,
+};
+use anyhow::{anyhow, Result};
+use std::{fmt::Debug, future::Future, pin::Pin, time::Duration};
+
+type HealthCheck = Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// Sends `WATCHDOG=1` to systemd at half of the watchdog timeout, unless one
+/// of the checked actors doesn't respond in time
+pub struct Watchdog {
+    notifier: SystemdNotifier,
+    ticks: Ticks,
+    check_timeout: Duration,
+    checks: Vec<(String, HealthCheck)>,
+    handle_child: Option<armaf::HandleChild>,
+}
+
+impl Watchdog {
+    pub fn new(
+        notifier: SystemdNotifier,
+        watchdog_timeout: Duration,
+        tick_service: &TickService,
+    ) -> Watchdog {
+        Watchdog {
+            notifier,
+            ticks: tick_service.subscribe(watchdog_timeout / 2),
+            // The checks run one after another, even a few slow ones mustn't
+            // delay the ping past the timeout
+            check_timeout: watchdog_timeout / 8,
+            checks: Vec::new(),
+            handle_child: None,
+        }
+    }
+
+    /// Check that the actor behind the port responds to the given request
+    /// before each ping
+    pub fn with_port_check<P, R, E>(
+        mut self,
+        name: &str,
+        port: ActorPort<P, R, E>,
+        payload: P,
+    ) -> Watchdog
+    where
+        P: Clone + Send + Sync + 'static,
+        R: Send + 'static,
+        E: Debug + Send + 'static,
+    {
+        let check: HealthCheck = Box::new(move || {
+            let port = port.clone();
+            let payload = payload.clone();
+            Box::pin(async move {
+                port.request(payload)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| anyhow!("{:?}", e))
+            })
+        });
+        self.checks.push((name.to_owned(), check));
+        self
+    }
+
+    pub fn spawn(mut self) -> armaf::Handle {
+        let (handle, handle_child) = armaf::Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => {
+                    return;
+                }
+                _ = self.ticks.tick() => {
+                    match self.check_health().await {
+                        Ok(()) => {
+                            if let Err(e) = self.notifier.watchdog() {
+                                log::error!("Couldn't ping the watchdog: {:?}", e);
+                            }
+                        }
+                        Err(e) => log::error!("Not pinging the watchdog: {:#}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        for (name, check) in self.checks.iter() {
+            match tokio::time::timeout(self.check_timeout, check()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(anyhow!("{} failed its health check: {}", name, e)),
+                Err(_) => {
+                    return Err(anyhow!(
+                        "{} didn't respond within {:?}",
+                        name,
+                        self.check_timeout
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Crash bundles, collecting what's needed for a useful bug report when
+//! Energia fails fatally
+
+use crate::{armaf::list_actors, logging::LOG_BASENAME};
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Number of lines copied from the end of the log
+const LOG_TAIL_LINES: usize = 200;
+/// Configuration keys containing any of these are replaced in the snapshot
+const SECRET_KEY_PARTS: [&str; 5] = ["password", "secret", "token", "key", "credential"];
+const REDACTED: &str = "<redacted>";
+
+/// What's known about the running daemon at the time of the crash
+#[derive(Debug)]
+struct CrashContext {
+    log_directory: Option<PathBuf>,
+    /// The configuration, already stripped of secrets
+    config: Option<toml::Value>,
+}
+
+static CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext {
+    log_directory: None,
+    config: None,
+});
+
+/// Directory into which the bundles are written
+pub fn default_directory() -> PathBuf {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| {
+            PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/state")
+        });
+    state_home.join("energia").join("crashes")
+}
+
+/// Record the directory the logs are written into, so that their tail is
+/// included in the bundles
+pub fn set_log_directory(log_directory: PathBuf) {
+    CONTEXT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .log_directory = Some(log_directory);
+}
+
+/// Record the configuration in use, so that it's included in the bundles
+pub fn set_config(config: &toml::Value) {
+    let mut config = config.clone();
+    strip_secrets(&mut config);
+    CONTEXT.lock().unwrap_or_else(|e| e.into_inner()).config = Some(config);
+}
+
+/// Write a bundle whenever the main task panics, after running the hook
+/// installed until now. Panics of actors are left to their supervisors.
+pub fn install_panic_hook() {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous_hook(info);
+        if std::thread::current().name() == Some("main") {
+            write_bundle(&format!("Fatal error: {}", info));
+        }
+    }));
+}
+
+/// Write a bundle about a supervisor giving up on restarting the actor, to
+/// be set as the supervisors' give up hook
+pub fn report_supervisor_give_up(actor_name: &str) {
+    write_bundle(&format!(
+        "{} kept crashing and its supervisor gave up restarting it",
+        actor_name
+    ));
+}
+
+fn write_bundle(reason: &str) {
+    let context = CONTEXT.lock().unwrap_or_else(|e| e.into_inner());
+    match write_bundle_to(&default_directory(), reason, &context, SystemTime::now()) {
+        Ok(path) => {
+            log::error!("Crash report written to {}", path.display());
+            eprintln!(
+                "Energia has crashed, a report has been written to {}",
+                path.display()
+            );
+        }
+        Err(e) => log::error!("Couldn't write crash report: {}", e),
+    }
+}
+
+/// Write the bundle into a new directory inside the given one, returning
+/// the bundle's path
+fn write_bundle_to(
+    directory: &Path,
+    reason: &str,
+    context: &CrashContext,
+    now: SystemTime,
+) -> std::io::Result<PathBuf> {
+    let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let bundle = directory.join(format!("crash-{}-{}", timestamp, std::process::id()));
+    fs::create_dir_all(&bundle)?;
+    fs::write(bundle.join("reason.txt"), format!("{}\n", reason))?;
+    fs::write(bundle.join("versions.txt"), versions())?;
+    fs::write(bundle.join("actors.txt"), actors())?;
+    if let Some(config) = context.config.as_ref() {
+        fs::write(
+            bundle.join("config.toml"),
+            toml::to_string_pretty(config).unwrap_or_default(),
+        )?;
+    }
+    if let Some(log_directory) = context.log_directory.as_ref() {
+        let log_file = log_directory.join(format!("{}_rCURRENT.log", LOG_BASENAME));
+        if let Ok(log) = fs::read_to_string(log_file) {
+            fs::write(bundle.join("log_tail.txt"), tail(&log, LOG_TAIL_LINES))?;
+        }
+    }
+    Ok(bundle)
+}
+
+/// Replace the values of the keys which look like they hold secrets
+fn strip_secrets(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_KEY_PARTS.iter().any(|part| key.contains(part)) {
+                    *value = toml::Value::String(REDACTED.to_owned());
+                } else {
+                    strip_secrets(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(strip_secrets),
+        _ => {}
+    }
+}
+
+fn tail(text: &str, lines: usize) -> String {
+    let all_lines: Vec<&str> = text.lines().collect();
+    let mut tail = all_lines[all_lines.len().saturating_sub(lines)..].join("\n");
+    tail.push('\n');
+    tail
+}
+
+fn versions() -> String {
+    let mut versions = format!("energia {}\n", env!("CARGO_PKG_VERSION"));
+    if let Ok(kernel) = fs::read_to_string("/proc/version") {
+        versions.push_str(&kernel);
+    }
+    if let Ok(os_release) = fs::read_to_string("/etc/os-release") {
+        if let Some(name) = os_release
+            .lines()
+            .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        {
+            let _ = writeln!(versions, "{}", name.trim_matches('"'));
+        }
+    }
+    versions
+}
+
+fn actors() -> String {
+    let mut actors = String::new();
+    for actor in list_actors() {
+        let started_at = actor
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let _ = writeln!(
+            actors,
+            "{} {} {} started at {}",
+            actor.id,
+            actor.name,
+            actor.health.name(),
+            started_at
+        );
+    }
+    actors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_secrets() {
+        let mut config: toml::Value = toml::from_str(
+            r#"
+            [lock]
+            command = "i3lock"
+            args = ["-c", "000000"]
+
+            [[notifications]]
+            url = "https://example.com"
+            api_token = "abc"
+            "#,
+        )
+        .unwrap();
+        strip_secrets(&mut config);
+        assert_eq!(config["lock"]["command"].as_str(), Some("i3lock"));
+        assert_eq!(
+            config["notifications"][0]["url"].as_str(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            config["notifications"][0]["api_token"].as_str(),
+            Some(REDACTED)
+        );
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let directory = env::temp_dir().join(format!("energia-crash-{}", std::process::id()));
+        let log_directory = directory.join("log");
+        fs::create_dir_all(&log_directory).unwrap();
+        let log: String = (0..300).map(|i| format!("line {}\n", i)).collect();
+        fs::write(log_directory.join("energia_rCURRENT.log"), log).unwrap();
+        let context = CrashContext {
+            log_directory: Some(log_directory),
+            config: Some(toml::from_str("[lock]\ncommand = \"i3lock\"").unwrap()),
+        };
+
+        let bundle =
+            write_bundle_to(&directory, "Fatal error", &context, SystemTime::now()).unwrap();
+        assert_eq!(
+            fs::read_to_string(bundle.join("reason.txt")).unwrap(),
+            "Fatal error\n"
+        );
+        assert!(fs::read_to_string(bundle.join("config.toml"))
+            .unwrap()
+            .contains("i3lock"));
+        let log_tail = fs::read_to_string(bundle.join("log_tail.txt")).unwrap();
+        assert_eq!(log_tail.lines().count(), LOG_TAIL_LINES);
+        assert!(log_tail.starts_with("line 100\n"));
+        assert!(fs::read_to_string(bundle.join("versions.txt"))
+            .unwrap()
+            .starts_with("energia "));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
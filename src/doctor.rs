@@ -0,0 +1,277 @@
+//! Diagnostics of the environment Energia runs in, checking that everything
+//! the daemon needs is available before it's started
+
+use crate::{
+    config::ConfigSources,
+    external::{
+        dbus::ConnectionFactory,
+        dependency_provider::{parse_backends, Backends, BrightnessBackend},
+        display_server::x11::X11Interface,
+    },
+    system::lock_effector::LockEffector,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    env,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use upower_dbus::UPowerProxy;
+
+/// Outcome of a single check
+pub struct Diagnosis {
+    pub name: &'static str,
+    /// What was found if the check passed or the problem if it failed
+    pub outcome: Result<String>,
+    /// What the user can do to fix a failed check
+    pub advice: &'static str,
+}
+
+impl Diagnosis {
+    fn new(name: &'static str, outcome: Result<String>, advice: &'static str) -> Diagnosis {
+        Diagnosis {
+            name,
+            outcome,
+            advice,
+        }
+    }
+
+    /// Human-readable lines describing the outcome
+    pub fn lines(&self) -> Vec<String> {
+        match &self.outcome {
+            Ok(found) => vec![format!("ok: {}: {}", self.name, found)],
+            Err(e) => vec![
+                format!("error: {}: {:#}", self.name, e),
+                format!("  hint: {}", self.advice),
+            ],
+        }
+    }
+}
+
+/// Check the environment, print a report about it and return the exit code
+/// with which the process should end
+pub async fn run(sources: &ConfigSources) -> i32 {
+    let diagnoses = diagnose(sources).await;
+    for diagnosis in diagnoses.iter() {
+        for line in diagnosis.lines() {
+            println!("{}", line);
+        }
+    }
+    let failed = diagnoses.iter().filter(|d| d.outcome.is_err()).count();
+    if failed == 0 {
+        println!("Everything Energia needs is available");
+        0
+    } else {
+        println!("{} check(s) failed", failed);
+        1
+    }
+}
+
+async fn diagnose(sources: &ConfigSources) -> Vec<Diagnosis> {
+    let mut dbus_factory = ConnectionFactory::new();
+    let mut diagnoses = vec![Diagnosis::new(
+        "X11 screensaver extension",
+        X11Interface::probe(None).map(|_| format!("available on {}", display_name())),
+        "Energia has to run inside an X11 session with DISPLAY set, \
+         and the X server must support the MIT-SCREEN-SAVER extension",
+    )];
+
+    let system_connection = dbus_factory.get_system().await;
+    diagnoses.push(Diagnosis::new(
+        "System D-Bus",
+        system_connection
+            .as_ref()
+            .map(|_| "reachable".to_owned())
+            .map_err(|e| anyhow!("{}", e)),
+        "Make sure the system D-Bus daemon is running",
+    ));
+    diagnoses.push(Diagnosis::new(
+        "Session D-Bus",
+        dbus_factory
+            .get_session()
+            .await
+            .map(|_| "reachable".to_owned())
+            .map_err(|e| anyhow!("{}", e)),
+        "Start Energia from within your graphical session, \
+         so that DBUS_SESSION_BUS_ADDRESS is set",
+    ));
+
+    match system_connection {
+        Ok(connection) => {
+            diagnoses.push(Diagnosis::new(
+                "logind session",
+                check_logind_session(&connection).await,
+                "Energia must run in a session registered with systemd-logind, \
+                 e.g. one started by your display manager through pam_systemd",
+            ));
+            diagnoses.push(Diagnosis::new(
+                "UPower",
+                check_upower(&connection).await,
+                "Install UPower and make sure its D-Bus service can be activated, \
+                 otherwise Energia can't tell whether the computer runs on battery",
+            ));
+        }
+        Err(_) => {
+            for name in ["logind session", "UPower"] {
+                diagnoses.push(Diagnosis::new(
+                    name,
+                    Err(anyhow!("not checked, the system D-Bus isn't reachable")),
+                    "Fix the connection to the system D-Bus first",
+                ));
+            }
+        }
+    }
+
+    let backends = match sources.load().await {
+        Ok(config) => parse_backends(&config).unwrap_or_default(),
+        Err(_) => Backends::default(),
+    };
+    let BrightnessBackend::Logind { device } = &backends.brightness;
+    diagnoses.push(Diagnosis::new(
+        "Backlight device",
+        check_backlight(Path::new("/sys/class/backlight"), device).await,
+        "Set [backends] backlight_device to one of the devices in /sys/class/backlight, \
+         brightness effects won't work without it",
+    ));
+    diagnoses.push(Diagnosis::new(
+        "Lock command",
+        check_lock_command(sources).await,
+        "Set [lock] command to a locker installed on this computer, \
+         either as an absolute path or a program in PATH",
+    ));
+    diagnoses
+}
+
+fn display_name() -> String {
+    env::var("DISPLAY").unwrap_or_else(|_| "the default display".to_owned())
+}
+
+async fn check_logind_session(connection: &zbus::Connection) -> Result<String> {
+    let manager = logind_zbus::manager::ManagerProxy::new(connection).await?;
+    let session_path = manager
+        .get_session_by_PID(std::process::id())
+        .await
+        .context("this process doesn't belong to any session")?;
+    Ok(format!("registered as {}", session_path.as_str()))
+}
+
+async fn check_upower(connection: &zbus::Connection) -> Result<String> {
+    let upower = UPowerProxy::new(connection).await?;
+    let on_battery = upower
+        .on_battery()
+        .await
+        .context("UPower daemon doesn't respond")?;
+    Ok(if on_battery {
+        "running on battery".to_owned()
+    } else {
+        "running on external power".to_owned()
+    })
+}
+
+async fn check_backlight(class_directory: &Path, device: &str) -> Result<String> {
+    let device_path = class_directory.join(device);
+    if tokio::fs::metadata(&device_path).await.is_err() {
+        let mut available = Vec::new();
+        if let Ok(mut entries) = tokio::fs::read_dir(class_directory).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                available.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        available.sort();
+        if available.is_empty() {
+            bail!("{} doesn't exist", device_path.display());
+        }
+        bail!(
+            "{} doesn't exist, available devices: {}",
+            device_path.display(),
+            available.join(", ")
+        );
+    }
+    for file in ["brightness", "max_brightness"] {
+        tokio::fs::read_to_string(device_path.join(file))
+            .await
+            .with_context(|| format!("couldn't read {}", device_path.join(file).display()))?;
+    }
+    Ok(format!("{} is readable", device_path.display()))
+}
+
+async fn check_lock_command(sources: &ConfigSources) -> Result<String> {
+    let config = sources
+        .load()
+        .await
+        .context("couldn't read the configuration")?;
+    let lock_config = match config.get("lock") {
+        Some(lock_config) => lock_config.clone(),
+        #[cfg(feature = "builtin-locker")]
+        None => return Ok("not configured, the built-in locker will be used".to_owned()),
+        #[cfg(not(feature = "builtin-locker"))]
+        None => return Ok("not configured, the lock effect can't be used".to_owned()),
+    };
+    let command = LockEffector::parse_config(Some(lock_config))?;
+    let program = find_executable(command.program(), env::var_os("PATH").as_deref())?;
+    Ok(format!("{} found", program.display()))
+}
+
+/// Find the program the way the shell would, returning its full path
+pub fn find_executable(program: &str, path: Option<&std::ffi::OsStr>) -> Result<PathBuf> {
+    if program.contains('/') {
+        let program = PathBuf::from(program);
+        return if is_executable(&program) {
+            Ok(program)
+        } else {
+            Err(anyhow!("{} isn't an executable file", program.display()))
+        };
+    }
+    path.into_iter()
+        .flat_map(env::split_paths)
+        .map(|directory| directory.join(program))
+        .find(|candidate| is_executable(candidate))
+        .ok_or_else(|| anyhow!("{} wasn't found in PATH", program))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::external::brightness::logind::DEFAULT_BACKLIGHT_DEVICE;
+
+    #[test]
+    fn test_find_executable() {
+        let path = std::ffi::OsString::from("/nonexistent:/bin:/usr/bin");
+        assert!(find_executable("sh", Some(&path)).unwrap().ends_with("sh"));
+        assert!(find_executable("/bin/sh", None).is_ok());
+        assert!(find_executable("energia-missing-locker", Some(&path)).is_err());
+        assert!(find_executable("/etc/passwd", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_backlight() {
+        let class_directory =
+            env::temp_dir().join(format!("energia-doctor-backlight-{}", std::process::id()));
+        let other_device = class_directory.join("acpi_video0");
+        std::fs::create_dir_all(&other_device).unwrap();
+        let error = check_backlight(&class_directory, DEFAULT_BACKLIGHT_DEVICE)
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("available devices: acpi_video0"));
+
+        let device = class_directory.join(DEFAULT_BACKLIGHT_DEVICE);
+        std::fs::create_dir_all(&device).unwrap();
+        std::fs::write(device.join("brightness"), "100").unwrap();
+        std::fs::write(device.join("max_brightness"), "255").unwrap();
+        assert!(check_backlight(&class_directory, DEFAULT_BACKLIGHT_DEVICE)
+            .await
+            .is_ok());
+        assert!(check_backlight(&class_directory, "acpi_video0")
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&class_directory).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! A [BrightnessController] over the available backends, so that the backend
+//! can be chosen at runtime
+
+use super::BrightnessController;
+use super::{logind::LogindBrightnessController, mock::MockBrightnessController};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// One of the [BrightnessController] implementations
+#[derive(Clone)]
+pub enum AnyBrightnessController {
+    Logind(LogindBrightnessController),
+    Mock(MockBrightnessController),
+}
+
+impl From<LogindBrightnessController> for AnyBrightnessController {
+    fn from(controller: LogindBrightnessController) -> Self {
+        AnyBrightnessController::Logind(controller)
+    }
+}
+
+impl From<MockBrightnessController> for AnyBrightnessController {
+    fn from(controller: MockBrightnessController) -> Self {
+        AnyBrightnessController::Mock(controller)
+    }
+}
+
+#[async_trait]
+impl BrightnessController for AnyBrightnessController {
+    async fn get_brightness(&self) -> Result<usize> {
+        match self {
+            AnyBrightnessController::Logind(controller) => controller.get_brightness().await,
+            AnyBrightnessController::Mock(controller) => controller.get_brightness().await,
+        }
+    }
+
+    async fn set_brightness(&self, percentage: usize) -> Result<()> {
+        match self {
+            AnyBrightnessController::Logind(controller) => {
+                controller.set_brightness(percentage).await
+            }
+            AnyBrightnessController::Mock(controller) => {
+                controller.set_brightness(percentage).await
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
 
 /// A trait allowing to set display brightness
 #[async_trait]
@@ -12,3 +13,20 @@ pub trait BrightnessController: Send + Sync + Clone + 'static {
     /// Set the current display brightness
     async fn set_brightness(&self, percentage: usize) -> Result<()>;
 }
+
+/// Brightness to which the screen returns once it's undimmed, shared by all
+/// the actors changing the brightness of the same screen, so that undimming
+/// doesn't revert the brightness the user has chosen in the meantime
+#[derive(Debug, Clone, Default)]
+pub struct DimState(Arc<Mutex<Option<usize>>>);
+
+impl DimState {
+    /// The brightness before dimming, [None] if the screen isn't dimmed
+    pub fn original_brightness(&self) -> Option<usize> {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_original_brightness(&self, brightness: Option<usize>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = brightness;
+    }
+}
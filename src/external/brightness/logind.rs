@@ -9,32 +9,42 @@ use std::path::Path;
 use tokio::{fs, io::AsyncReadExt};
 use zbus::{self, zvariant::OwnedObjectPath};
 
+/// The backlight device whose brightness is controlled
+pub const DEFAULT_BACKLIGHT_DEVICE: &str = "intel_backlight";
+
 /// A [BrightnessController] which uses the kernel's /sys/class/backlight device
 /// class to control the display brightness.
 ///
 /// The brightness is read directly from the filesystem but writing is mediated
 /// via logind Session's SetBrightness method, to allow root-less brightness
-/// setting.
+/// setting. Without a session, the brightness is written to the filesystem
+/// directly, which needs write access to the device.
 #[derive(Debug, Clone)]
 pub struct LogindBrightnessController {
     device: String,
     device_path: String,
     max_brightness: usize,
-    proxy: SessionProxy<'static>,
+    proxy: Option<SessionProxy<'static>>,
 }
 
 impl LogindBrightnessController {
     /// Create a new controller which will set the brightness on the device
-    /// under /sys/class/backlight/{device}.
+    /// under /sys/class/backlight/{device}, through the given logind session
+    /// if there's one.
     pub async fn new(
         device: &str,
         connection: zbus::Connection,
-        session_path: OwnedObjectPath,
+        session_path: Option<OwnedObjectPath>,
     ) -> Result<LogindBrightnessController> {
-        let proxy = SessionProxy::builder(&connection)
-            .path(session_path)?
-            .build()
-            .await?;
+        let proxy = match session_path {
+            Some(session_path) => Some(
+                SessionProxy::builder(&connection)
+                    .path(session_path)?
+                    .build()
+                    .await?,
+            ),
+            None => None,
+        };
 
         let device_path = format!("/sys/class/backlight/{}", device);
         let max_brightness =
@@ -61,10 +71,16 @@ impl BrightnessController for LogindBrightnessController {
         }
         let resulting_brightness =
             (self.max_brightness as f64 * (percentage as f64 / 100.0)) as u32;
-        Ok(self
-            .proxy
-            .set_brightness("backlight", &self.device, resulting_brightness)
-            .await?)
+        match &self.proxy {
+            Some(proxy) => Ok(proxy
+                .set_brightness("backlight", &self.device, resulting_brightness)
+                .await?),
+            None => Ok(fs::write(
+                format!("{}/{}", self.device_path, "brightness"),
+                resulting_brightness.to_string(),
+            )
+            .await?),
+        }
     }
 }
 
@@ -1,9 +1,11 @@
 //! Implements APIs for controlling the display backlight
 
+pub mod backend;
 pub mod interface;
 pub mod logind;
 pub mod mock;
 
+pub use backend::AnyBrightnessController;
 pub use interface::*;
 
 #[cfg(test)]
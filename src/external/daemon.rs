@@ -0,0 +1,218 @@
+//! Detaches Energia from the terminal it has been started from, for users
+//! who don't run it as a systemd service or from their window manager
+//!
+//! Daemonizing forks the process, so it has to happen before the Tokio
+//! runtime starts any threads. The original process waits until the daemon
+//! reports that it has started, so that startup failures still get to the
+//! terminal and to the exit status.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+};
+
+/// Sent by the daemon to the original process once it has started
+const STARTED: u8 = b'1';
+
+/// File holding the PID of the running daemon, removed on drop
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Path of the PID file of the instance running on the display, in
+    /// $XDG_RUNTIME_DIR or in the temporary directory if it's not set
+    pub fn default_path(display: &str) -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+        {
+            Some(runtime_dir) => runtime_dir.join(format!("energia-{}.pid", display)),
+            None => {
+                // SAFETY: getuid can't fail
+                let uid = unsafe { libc::getuid() };
+                env::temp_dir().join(format!("energia-{}-{}.pid", uid, display))
+            }
+        }
+    }
+
+    /// Fail if the file exists and contains the PID of a running process.
+    /// Files left behind by processes which have exited are ignored.
+    pub fn check(path: &Path) -> Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        };
+        let pid = match contents.trim().parse::<libc::pid_t>() {
+            Ok(pid) if pid > 0 => pid,
+            _ => return Ok(()),
+        };
+        // SAFETY: signal 0 only checks whether the process exists
+        let exists = unsafe { libc::kill(pid, 0) } == 0
+            || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+        if exists {
+            Err(anyhow!(
+                "Energia is already running with PID {} according to {}",
+                pid,
+                path.display()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the PID of the current process into the file
+    pub fn create(path: &Path) -> Result<PidFile> {
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Couldn't write PID file {}", path.display()))?;
+        Ok(PidFile {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::error!("Couldn't remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The process detached by [daemonize]. Its PID file is removed when it's
+/// dropped.
+#[derive(Debug)]
+pub struct Daemon {
+    _pid_file: PidFile,
+    startup_pipe: Option<File>,
+}
+
+impl Daemon {
+    /// Let the original process exit successfully, once the daemon has
+    /// started. If the daemon exits without reporting it, the original
+    /// process exits with an error.
+    pub fn report_started(&mut self) {
+        if let Some(mut pipe) = self.startup_pipe.take() {
+            if let Err(e) = pipe.write_all(&[STARTED]) {
+                log::error!("Couldn't report finished startup: {}", e);
+            }
+        }
+    }
+}
+
+/// Detach the process from its terminal and session by forking twice,
+/// redirect the standard streams to /dev/null and write the daemon's PID into
+/// the file.
+///
+/// Only the daemon returns from the function. The original process waits
+/// until the daemon calls [Daemon::report_started] and exits with an error if
+/// the daemon exits before that, removing the PID file left behind. The
+/// working directory is kept, so that relative paths given on the command
+/// line keep working.
+pub fn daemonize(pid_file: &Path) -> Result<Daemon> {
+    // Checked in the original process, so that the error gets to the terminal
+    PidFile::check(pid_file)?;
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("Couldn't open /dev/null")?;
+    let (read_end, write_end) = pipe()?;
+    if fork()? != 0 {
+        drop(write_end);
+        wait_for_startup(read_end, pid_file);
+    }
+    drop(read_end);
+    // SAFETY: the child isn't a process group leader, so setsid can't fail
+    unsafe { libc::setsid() };
+    // The session leader exits, so that the daemon can never acquire a
+    // controlling terminal again
+    if fork()? != 0 {
+        // SAFETY: exiting without running destructors or flushing the
+        // buffers shared with the child is what the parent of a fork should do
+        unsafe { libc::_exit(0) };
+    }
+    for stream in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are valid
+        if unsafe { libc::dup2(dev_null.as_raw_fd(), stream) } < 0 {
+            return Err(io::Error::last_os_error()).context("Couldn't detach the standard streams");
+        }
+    }
+    Ok(Daemon {
+        _pid_file: PidFile::create(pid_file)?,
+        startup_pipe: Some(write_end),
+    })
+}
+
+/// Wait in the original process until the daemon has started, exiting with
+/// an error if it exits before that
+fn wait_for_startup(mut startup_pipe: File, pid_file: &Path) -> ! {
+    let mut status = [0u8; 1];
+    // Reading ends once the daemon has written the status or exited
+    if matches!(startup_pipe.read(&mut status), Ok(1) if status[0] == STARTED) {
+        std::process::exit(0);
+    }
+    eprintln!("Energia has failed to start, its log contains the details");
+    // The daemon doesn't remove its PID file if it exits forcibly
+    if PidFile::check(pid_file).is_ok() {
+        let _ = fs::remove_file(pid_file);
+    }
+    std::process::exit(1);
+}
+
+/// Create a pipe whose ends aren't inherited by the programs Energia runs,
+/// returning its read and write end
+fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: the array has room for both descriptors
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error()).context("Couldn't create a pipe");
+    }
+    // SAFETY: the descriptors have just been created and aren't owned by
+    // anything else
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+fn fork() -> Result<libc::pid_t> {
+    // SAFETY: no other threads are running yet, so the child can't inherit
+    // locks held by them
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Couldn't fork"),
+        pid => Ok(pid),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let path = env::temp_dir().join(format!("energia_pid_file_test_{}", std::process::id()));
+        PidFile::check(&path).expect("Missing PID file reported as running");
+
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        let error = PidFile::check(&path).unwrap_err();
+        assert!(error.to_string().contains("already running"));
+
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // PIDs can't be this high, so the process has exited
+        fs::write(&path, format!("{}\n", libc::pid_t::MAX)).unwrap();
+        PidFile::check(&path).expect("Stale PID file reported as running");
+        fs::write(&path, "garbage").unwrap();
+        PidFile::check(&path).expect("Invalid PID file reported as running");
+        fs::remove_file(&path).unwrap();
+    }
+}
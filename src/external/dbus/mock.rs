@@ -0,0 +1,157 @@
+//! A mock of UPower served on a private peer-to-peer D-Bus connection, so
+//! that the components reading the power supply can be tested without the
+//! real service
+
+use super::ConnectionFactory;
+use std::sync::{Arc, Mutex, MutexGuard};
+use zbus::{dbus_interface, zvariant::OwnedObjectPath, ConnectionBuilder, Guid};
+
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+const DISPLAY_DEVICE_PATH: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+/// What the mocked UPower reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct UPowerState {
+    pub on_battery: bool,
+    pub percentage: f64,
+    /// Power drawn from the battery, in watts
+    pub energy_rate: f64,
+    /// Seconds until the battery is empty, 0 if unknown
+    pub time_to_empty: i64,
+}
+
+impl Default for UPowerState {
+    fn default() -> Self {
+        UPowerState {
+            on_battery: false,
+            percentage: 100.0,
+            energy_rate: 0.0,
+            time_to_empty: 0,
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<UPowerState>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, UPowerState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct UPower {
+    state: SharedState,
+}
+
+#[dbus_interface(name = "org.freedesktop.UPower")]
+impl UPower {
+    async fn get_display_device(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(DISPLAY_DEVICE_PATH).unwrap()
+    }
+
+    #[dbus_interface(property)]
+    async fn on_battery(&self) -> bool {
+        lock(&self.state).on_battery
+    }
+}
+
+struct Device {
+    state: SharedState,
+}
+
+#[dbus_interface(name = "org.freedesktop.UPower.Device")]
+impl Device {
+    #[dbus_interface(property)]
+    async fn percentage(&self) -> f64 {
+        lock(&self.state).percentage
+    }
+
+    #[dbus_interface(property)]
+    async fn energy_rate(&self) -> f64 {
+        lock(&self.state).energy_rate
+    }
+
+    #[dbus_interface(property)]
+    async fn time_to_empty(&self) -> i64 {
+        lock(&self.state).time_to_empty
+    }
+}
+
+/// The mocked UPower, which keeps serving until it's dropped
+pub struct MockUPower {
+    state: SharedState,
+    server: zbus::Connection,
+    client: zbus::Connection,
+}
+
+impl MockUPower {
+    pub async fn start() -> zbus::Result<MockUPower> {
+        let state = SharedState::default();
+        let (server_stream, client_stream) = tokio::net::UnixStream::pair()?;
+        let guid = Guid::generate();
+        let server = ConnectionBuilder::unix_stream(server_stream)
+            .server(&guid)
+            .p2p()
+            .serve_at(
+                UPOWER_PATH,
+                UPower {
+                    state: state.clone(),
+                },
+            )?
+            .serve_at(
+                DISPLAY_DEVICE_PATH,
+                Device {
+                    state: state.clone(),
+                },
+            )?
+            .build();
+        let client = ConnectionBuilder::unix_stream(client_stream).p2p().build();
+        let (server, client) = tokio::try_join!(server, client)?;
+        Ok(MockUPower {
+            state,
+            server,
+            client,
+        })
+    }
+
+    /// A connection factory whose system bus is connected to the mock
+    pub fn connection_factory(&self) -> ConnectionFactory {
+        ConnectionFactory::with_connections(Some(self.client.clone()), None)
+    }
+
+    pub fn state(&self) -> UPowerState {
+        lock(&self.state).clone()
+    }
+
+    /// Change the power source and the battery's charge, announcing the
+    /// changes of the properties
+    pub async fn set_power(&self, on_battery: bool, percentage: f64) {
+        {
+            let mut state = lock(&self.state);
+            state.on_battery = on_battery;
+            state.percentage = percentage;
+        }
+        let upower = self
+            .server
+            .object_server()
+            .interface::<_, UPower>(UPOWER_PATH)
+            .await
+            .unwrap();
+        upower
+            .get()
+            .await
+            .on_battery_changed(upower.signal_context())
+            .await
+            .unwrap();
+        let device = self
+            .server
+            .object_server()
+            .interface::<_, Device>(DISPLAY_DEVICE_PATH)
+            .await
+            .unwrap();
+        device
+            .get()
+            .await
+            .percentage_changed(device.signal_context())
+            .await
+            .unwrap();
+    }
+}
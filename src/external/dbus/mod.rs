@@ -3,6 +3,9 @@
 use log::info;
 use zbus;
 
+#[cfg(test)]
+pub mod mock;
+
 /// Handles initialization and cloning of [zbus::Connection]s. These are
 /// clone-able and handle their own refcounts internally. This struct will
 /// either create or provide clones of connections.
@@ -22,6 +25,16 @@ impl ConnectionFactory {
         }
     }
 
+    /// Create a factory handing out the given connections, e.g. ones
+    /// connected to fake services in tests
+    #[cfg(test)]
+    pub fn with_connections(
+        system: Option<zbus::Connection>,
+        session: Option<zbus::Connection>,
+    ) -> ConnectionFactory {
+        ConnectionFactory { system, session }
+    }
+
     /// Get a connection to the system-wide D-Bus
     pub async fn get_system(&mut self) -> zbus::Result<zbus::Connection> {
         if let Some(c) = &self.system {
@@ -2,33 +2,181 @@
 
 use super::{
     brightness::{
-        logind::LogindBrightnessController, mock::MockBrightnessController, BrightnessController,
+        logind::{LogindBrightnessController, DEFAULT_BACKLIGHT_DEVICE},
+        mock::MockBrightnessController,
+        AnyBrightnessController, DimState,
     },
     dbus,
-    display_server::{self, x11::X11Interface, DisplayServer, SystemState},
+    display_server::{
+        self, x11::X11Interface, AnyDisplayServer, AnyDisplayServerController, DisplayServer,
+        SystemState,
+    },
+    state_journal::StateJournal,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use tokio::sync::watch;
 
-pub struct DependencyProvider<B: BrightnessController, D: DisplayServer> {
+/// Errors returned by the bus when logind isn't running, e.g. because it
+/// hasn't been started yet
+const LOGIND_UNAVAILABLE_ERRORS: [&str; 2] = [
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+];
+
+/// Name of the configuration section choosing the backends
+pub const BACKENDS_SECTION: &str = "backends";
+
+/// The display server Energia talks to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayServerBackend {
+    X11,
+}
+
+/// The way the brightness of the screen is controlled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrightnessBackend {
+    /// Through logind, on the given /sys/class/backlight device
+    Logind { device: String },
+}
+
+/// The backends chosen in the `[backends]` section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backends {
+    pub display_server: DisplayServerBackend,
+    pub brightness: BrightnessBackend,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Backends {
+            display_server: DisplayServerBackend::X11,
+            brightness: BrightnessBackend::Logind {
+                device: DEFAULT_BACKLIGHT_DEVICE.to_owned(),
+            },
+        }
+    }
+}
+
+/// Parse the backends to be used, the defaults being used for those which
+/// aren't configured
+pub fn parse_backends(config: &toml::Value) -> Result<Backends> {
+    let mut backends = Backends::default();
+    let section = match config.get(BACKENDS_SECTION) {
+        Some(section) => section,
+        None => return Ok(backends),
+    };
+    let get_string = |key: &str| -> Result<Option<&str>> {
+        section
+            .get(key)
+            .map(|value| {
+                value
+                    .as_str()
+                    .ok_or_else(|| anyhow!("{} is not a string", value))
+            })
+            .transpose()
+            .with_context(|| format!("Invalid {}.{}", BACKENDS_SECTION, key))
+    };
+    match get_string("display_server")? {
+        Some("x11") | None => {}
+        Some(other) => {
+            return Err(anyhow!("unknown display server {}, expected x11", other))
+                .context("Invalid backends.display_server")
+        }
+    }
+    match get_string("brightness")? {
+        Some("logind") | None => {}
+        Some(other) => {
+            return Err(anyhow!(
+                "unknown brightness backend {}, expected logind",
+                other
+            ))
+            .context("Invalid backends.brightness")
+        }
+    }
+    if let Some(device) = get_string("backlight_device")? {
+        backends.brightness = BrightnessBackend::Logind {
+            device: device.to_owned(),
+        };
+    }
+    Ok(backends)
+}
+
+pub struct DependencyProvider {
     dbus_factory: Option<dbus::ConnectionFactory>,
-    display_server: D,
-    brightness_controller: B,
+    display_server: AnyDisplayServer,
+    brightness_controller: AnyBrightnessController,
+    dim_state: DimState,
+    state_journal: StateJournal,
+    /// Why the effectors depending on a logind session are unavailable,
+    /// [None] if they are available
+    no_session_reason: Option<&'static str>,
+    display_name: Option<String>,
 }
 
-impl<B: BrightnessController, D: DisplayServer> DependencyProvider<B, D> {
+impl DependencyProvider {
     pub fn new(
         dbus_factory: Option<dbus::ConnectionFactory>,
-        brightness_controller: B,
-        display_server: D,
-    ) -> DependencyProvider<B, D> {
+        brightness_controller: impl Into<AnyBrightnessController>,
+        display_server: impl Into<AnyDisplayServer>,
+    ) -> DependencyProvider {
         DependencyProvider {
             dbus_factory,
-            display_server,
-            brightness_controller,
+            display_server: display_server.into(),
+            brightness_controller: brightness_controller.into(),
+            dim_state: DimState::default(),
+            state_journal: StateJournal::in_memory(),
+            no_session_reason: None,
+            display_name: None,
         }
     }
 
+    /// Use the given journal to record the changes made by the actors, instead
+    /// of keeping them only in memory
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> Self {
+        self.state_journal = state_journal;
+        self
+    }
+
+    /// Record that the process doesn't belong to a logind session, so the
+    /// effectors depending on one are unavailable
+    pub fn without_logind_session(mut self) -> Self {
+        self.no_session_reason = Some(Self::NO_LOGIND_SESSION);
+        self
+    }
+
+    /// Provide the services of an additional display. The process' logind
+    /// session belongs to the main display, so the effectors depending on it
+    /// are unavailable.
+    pub fn for_additional_display(mut self) -> Self {
+        self.no_session_reason = Some(Self::ADDITIONAL_DISPLAY);
+        self
+    }
+
+    /// Reason why the effectors depending on a logind session are unavailable
+    pub const NO_LOGIND_SESSION: &'static str = "Energia isn't running in a logind session";
+
+    /// Reason why the effectors depending on a logind session are unavailable
+    /// on additional displays
+    pub const ADDITIONAL_DISPLAY: &'static str =
+        "Energia's logind session belongs to its main display";
+
+    /// Whether the process belongs to a logind session of the display, whose
+    /// hints can be set and through which the computer can be put to sleep
+    pub fn has_logind_session(&self) -> bool {
+        self.no_session_reason.is_none()
+    }
+
+    /// Why the effectors depending on a logind session are unavailable
+    pub fn no_logind_session_reason(&self) -> &'static str {
+        self.no_session_reason.unwrap_or(Self::NO_LOGIND_SESSION)
+    }
+
+    /// Name of the X display the services are provided for, [None] for the
+    /// one given by the environment
+    pub fn get_display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
     pub async fn get_dbus_system_connection(&mut self) -> Result<zbus::Connection> {
         if let Some(factory) = self.dbus_factory.as_mut() {
             Ok(factory.get_system().await?)
@@ -53,44 +201,99 @@ impl<B: BrightnessController, D: DisplayServer> DependencyProvider<B, D> {
         self.display_server.get_idleness_channel()
     }
 
-    pub fn get_display_controller(&self) -> D::Controller {
+    pub fn get_display_controller(&self) -> AnyDisplayServerController {
         self.display_server.get_controller()
     }
 
-    pub fn get_brightness_controller(&self) -> B {
+    pub fn get_brightness_controller(&self) -> AnyBrightnessController {
         self.brightness_controller.clone()
     }
+
+    pub fn get_dim_state(&self) -> DimState {
+        self.dim_state.clone()
+    }
+
+    pub fn get_state_journal(&self) -> StateJournal {
+        self.state_journal.clone()
+    }
 }
 
-impl DependencyProvider<LogindBrightnessController, X11Interface> {
-    pub async fn make_system() -> Result<Self> {
+impl DependencyProvider {
+    /// A provider of the system's services, using the given backends. The
+    /// display server backend connects to the named display, or to the one
+    /// given by the environment.
+    ///
+    /// If the process doesn't belong to a logind session, e.g. when started
+    /// from an SSH shell, the provider runs in a degraded mode, without the
+    /// effectors depending on the session. So it does if logind isn't
+    /// running, unless `wait_for_logind` is set, in which case an error is
+    /// returned, so that the caller can try again once logind has started.
+    pub async fn make_system(
+        backends: &Backends,
+        display_name: Option<&str>,
+        wait_for_logind: bool,
+    ) -> Result<Self> {
         let mut dbus_factory = dbus::ConnectionFactory::new();
         let connection = dbus_factory.get_system().await?;
         let manager_proxy = logind_zbus::manager::ManagerProxy::new(&connection).await?;
-        let path = manager_proxy.get_session_by_PID(std::process::id()).await?;
-        let brightness_controller =
-            LogindBrightnessController::new("intel_backlight", connection, path).await?;
-        Ok(DependencyProvider::new(
-            Some(dbus_factory),
-            brightness_controller,
-            X11Interface::new(None)?,
-        ))
+        let session = match manager_proxy.get_session_by_PID(std::process::id()).await {
+            Ok(session) => Some(session),
+            Err(zbus::Error::MethodError(name, _, _))
+                if wait_for_logind && LOGIND_UNAVAILABLE_ERRORS.contains(&name.as_str()) =>
+            {
+                return Err(anyhow!("logind isn't available: {}", name.as_str()));
+            }
+            Err(e) => {
+                log::warn!(
+                    "Energia isn't running in a logind session ({}), the session, lock hint and sleep effects won't be available",
+                    e
+                );
+                None
+            }
+        };
+        let has_logind_session = session.is_some();
+        let brightness_controller = match &backends.brightness {
+            BrightnessBackend::Logind { device } => {
+                LogindBrightnessController::new(device, connection, session).await?
+            }
+        };
+        let display_server = match backends.display_server {
+            DisplayServerBackend::X11 => X11Interface::new(display_name)?,
+        };
+        let mut provider =
+            DependencyProvider::new(Some(dbus_factory), brightness_controller, display_server);
+        provider.display_name = display_name.map(str::to_owned);
+        Ok(if has_logind_session {
+            provider
+        } else {
+            provider.without_logind_session()
+        })
     }
-}
 
-impl DependencyProvider<MockBrightnessController, display_server::mock::Interface> {
     pub fn make_mock(dbus_factory: Option<dbus::ConnectionFactory>) -> Self {
-        DependencyProvider::new(
+        DependencyProvider::make_mock_with_display_server(dbus_factory).0
+    }
+
+    /// A mock provider, along with its display server, through which the
+    /// user's activity can be simulated
+    pub fn make_mock_with_display_server(
+        dbus_factory: Option<dbus::ConnectionFactory>,
+    ) -> (Self, display_server::mock::Interface) {
+        let display_server = display_server::mock::Interface::new(60);
+        let provider = DependencyProvider::new(
             dbus_factory,
             MockBrightnessController::new(50),
-            display_server::mock::Interface::new(60),
-        )
+            display_server.clone(),
+        );
+        (provider, display_server)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::external::display_server::DisplayServerController;
+    use crate::external::{
+        brightness::BrightnessController, display_server::DisplayServerController,
+    };
 
     use super::*;
 
@@ -117,6 +320,7 @@ mod test {
             provider
                 .get_display_controller()
                 .get_idleness_timeout()
+                .await
                 .unwrap(),
             60
         );
@@ -125,4 +329,22 @@ mod test {
             SystemState::Awakened
         );
     }
+
+    #[test]
+    fn test_parse_backends() {
+        let parse = |config: &str| parse_backends(&toml::from_str(config).unwrap());
+        assert_eq!(parse("").unwrap(), Backends::default());
+        assert_eq!(
+            parse("[backends]\ndisplay_server = \"x11\"\nbacklight_device = \"amdgpu_bl0\"")
+                .unwrap(),
+            Backends {
+                display_server: DisplayServerBackend::X11,
+                brightness: BrightnessBackend::Logind {
+                    device: "amdgpu_bl0".to_owned()
+                },
+            }
+        );
+        assert!(parse("[backends]\ndisplay_server = \"wayland\"").is_err());
+        assert!(parse("[backends]\nbrightness = 3").is_err());
+    }
 }
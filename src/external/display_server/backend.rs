@@ -0,0 +1,111 @@
+//! A [DisplayServer] over the available backends, so that the backend can be
+//! chosen at runtime
+
+use super::{
+    mock,
+    x11::{X11DisplayServerController, X11Interface},
+    DPMSLevel, DPMSTimeouts, DisplayServer, DisplayServerController, SystemState,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::watch::Receiver;
+
+/// One of the [DisplayServer] implementations
+pub enum AnyDisplayServer {
+    X11(X11Interface),
+    Mock(mock::Interface),
+}
+
+impl From<X11Interface> for AnyDisplayServer {
+    fn from(interface: X11Interface) -> Self {
+        AnyDisplayServer::X11(interface)
+    }
+}
+
+impl From<mock::Interface> for AnyDisplayServer {
+    fn from(interface: mock::Interface) -> Self {
+        AnyDisplayServer::Mock(interface)
+    }
+}
+
+impl DisplayServer for AnyDisplayServer {
+    type Controller = AnyDisplayServerController;
+
+    fn get_idleness_channel(&self) -> Receiver<SystemState> {
+        match self {
+            AnyDisplayServer::X11(interface) => interface.get_idleness_channel(),
+            AnyDisplayServer::Mock(interface) => interface.get_idleness_channel(),
+        }
+    }
+
+    fn get_controller(&self) -> Self::Controller {
+        match self {
+            AnyDisplayServer::X11(interface) => {
+                AnyDisplayServerController::X11(interface.get_controller())
+            }
+            AnyDisplayServer::Mock(interface) => {
+                AnyDisplayServerController::Mock(interface.get_controller())
+            }
+        }
+    }
+}
+
+/// The [DisplayServerController] of an [AnyDisplayServer]
+#[derive(Clone)]
+pub enum AnyDisplayServerController {
+    X11(X11DisplayServerController),
+    Mock(mock::Controller),
+}
+
+/// Call the method on whichever controller is wrapped
+macro_rules! delegate {
+    ($self:ident, $method:ident($($argument:expr),*)) => {
+        match $self {
+            AnyDisplayServerController::X11(controller) => controller.$method($($argument),*).await,
+            AnyDisplayServerController::Mock(controller) => controller.$method($($argument),*).await,
+        }
+    };
+}
+
+#[async_trait]
+impl DisplayServerController for AnyDisplayServerController {
+    async fn set_idleness_timeout(&self, timeout_in_seconds: i16) -> Result<()> {
+        delegate!(self, set_idleness_timeout(timeout_in_seconds))
+    }
+
+    async fn get_idleness_timeout(&self) -> Result<i16> {
+        delegate!(self, get_idleness_timeout())
+    }
+
+    async fn force_activity(&self) -> Result<()> {
+        delegate!(self, force_activity())
+    }
+
+    async fn is_dpms_capable(&self) -> Result<bool> {
+        delegate!(self, is_dpms_capable())
+    }
+
+    async fn get_dpms_level(&self) -> Result<Option<DPMSLevel>> {
+        delegate!(self, get_dpms_level())
+    }
+
+    async fn set_dpms_level(&self, level: DPMSLevel) -> Result<()> {
+        delegate!(self, set_dpms_level(level))
+    }
+
+    async fn set_dpms_state(&self, enabled: bool) -> Result<()> {
+        delegate!(self, set_dpms_state(enabled))
+    }
+
+    async fn get_dpms_timeouts(&self) -> Result<DPMSTimeouts> {
+        delegate!(self, get_dpms_timeouts())
+    }
+
+    async fn set_dpms_timeouts(&self, timeouts: DPMSTimeouts) -> Result<()> {
+        delegate!(self, set_dpms_timeouts(timeouts))
+    }
+
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()> {
+        delegate!(self, set_screen_blanked(blanked))
+    }
+}
@@ -1,6 +1,8 @@
 //! Common types for abstracting over the APIs of different display servers
 
 use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio::sync::watch::Receiver;
 
 /// Represents a change in the idleness state of the system.
@@ -18,7 +20,7 @@ pub enum SystemState {
 /// Represents the power saving level of the system's screens
 ///
 /// Enum variant documentation copied from https://www.x.org/releases/X11R7.7/doc/xextproto/dpms.html
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DPMSLevel {
     /// In use
     On,
@@ -30,7 +32,7 @@ pub enum DPMSLevel {
     Off,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DPMSTimeouts {
     pub standby: u16,
     pub suspend: u16,
@@ -60,35 +62,42 @@ pub trait DisplayServer: Send + 'static {
 }
 
 /// Control for the system's display server
+#[async_trait]
 pub trait DisplayServerController: 'static + Send + Sync + Clone {
     /// Set the time of user's inactivity after which the display server should
     /// notify about user's idleness
-    fn set_idleness_timeout(&self, timeout_in_seconds: i16) -> Result<()>;
+    async fn set_idleness_timeout(&self, timeout_in_seconds: i16) -> Result<()>;
 
     /// Get the time of inactivity after which the system is considered idle
-    fn get_idleness_timeout(&self) -> Result<i16>;
+    async fn get_idleness_timeout(&self) -> Result<i16>;
 
     /// Force the system into active state, as if the user has just performed activity
-    fn force_activity(&self) -> Result<()>;
+    async fn force_activity(&self) -> Result<()>;
 
     /// Get the system's support for DPMS
-    fn is_dpms_capable(&self) -> Result<bool>;
+    async fn is_dpms_capable(&self) -> Result<bool>;
 
     /// Get the power saving level of the system's screens.
     /// If DPMS is disabled, None is returned.
-    fn get_dpms_level(&self) -> Result<Option<DPMSLevel>>;
+    async fn get_dpms_level(&self) -> Result<Option<DPMSLevel>>;
 
     /// Set the power saving level of the system's screens
-    fn set_dpms_level(&self, level: DPMSLevel) -> Result<()>;
+    async fn set_dpms_level(&self, level: DPMSLevel) -> Result<()>;
 
     /// Enable or disable DPMS on the system's displays.
     /// To get the state, check the Option variant returned
     /// by [DisplayServerController::get_dpms_level]
-    fn set_dpms_state(&self, enabled: bool) -> Result<()>;
+    async fn set_dpms_state(&self, enabled: bool) -> Result<()>;
 
     /// Get the timeouts after which the screen transitions into different DPMS levels
-    fn get_dpms_timeouts(&self) -> Result<DPMSTimeouts>;
+    async fn get_dpms_timeouts(&self) -> Result<DPMSTimeouts>;
 
     /// Set the timeouts after which the screen transitions into different DPMS levels
-    fn set_dpms_timeouts(&self, timeouts: DPMSTimeouts) -> Result<()>;
+    async fn set_dpms_timeouts(&self, timeouts: DPMSTimeouts) -> Result<()>;
+
+    /// Cover the system's screens with black, without turning them off, or
+    /// uncover them. Works where DPMS isn't supported, e.g. in virtual
+    /// machines. Blanking already blanked screens raises the cover above
+    /// newly opened windows and extends it to newly connected ones.
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()>;
 }
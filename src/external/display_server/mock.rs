@@ -2,6 +2,7 @@
 
 use super::{DisplayServer, DisplayServerController, SystemState};
 use anyhow::Result;
+use async_trait::async_trait;
 use std::{
     cell::RefCell,
     io::{Error, ErrorKind},
@@ -12,13 +13,16 @@ use tokio::sync::watch;
 struct SharedState {
     timeout: i16,
     should_fail: bool,
+    dpms_capable: bool,
     dpms_enabled: bool,
     dpms_level: super::DPMSLevel,
     dpms_timeouts: super::DPMSTimeouts,
+    screen_blanked: bool,
     sender: watch::Sender<SystemState>,
 }
 
 /// A mock [DisplayServer], usable for testing
+#[derive(Clone)]
 pub struct Interface {
     receiver: watch::Receiver<SystemState>,
     shared_state: Arc<Mutex<RefCell<SharedState>>>,
@@ -31,9 +35,11 @@ impl Interface {
             shared_state: Arc::new(Mutex::new(RefCell::new(SharedState {
                 timeout,
                 should_fail: false,
+                dpms_capable: true,
                 dpms_enabled: true,
                 dpms_level: super::DPMSLevel::On,
                 dpms_timeouts: super::DPMSTimeouts::new(10, 20, 30),
+                screen_blanked: false,
                 sender,
             }))),
             receiver,
@@ -44,6 +50,18 @@ impl Interface {
         self.shared_state.lock().unwrap().borrow_mut().should_fail = fail;
     }
 
+    pub fn set_dpms_capability(&self, capable: bool) {
+        self.shared_state.lock().unwrap().borrow_mut().dpms_capable = capable;
+    }
+
+    pub fn is_screen_blanked(&self) -> bool {
+        self.shared_state
+            .lock()
+            .unwrap()
+            .borrow_mut()
+            .screen_blanked
+    }
+
     pub fn notify_state_transition(&self, new_state: SystemState) -> Result<()> {
         Ok(self
             .shared_state
@@ -74,8 +92,9 @@ pub struct Controller {
     state: Arc<Mutex<RefCell<SharedState>>>,
 }
 
+#[async_trait]
 impl DisplayServerController for Controller {
-    fn set_idleness_timeout(&self, timeout_in_seconds: i16) -> Result<()> {
+    async fn set_idleness_timeout(&self, timeout_in_seconds: i16) -> Result<()> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -84,7 +103,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn get_idleness_timeout(&self) -> Result<i16> {
+    async fn get_idleness_timeout(&self) -> Result<i16> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -92,7 +111,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn force_activity(&self) -> Result<()> {
+    async fn force_activity(&self) -> Result<()> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -106,15 +125,15 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn is_dpms_capable(&self) -> Result<bool> {
+    async fn is_dpms_capable(&self) -> Result<bool> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
-            Ok(true)
+            Ok(self.state.lock().unwrap().borrow_mut().dpms_capable)
         }
     }
 
-    fn get_dpms_level(&self) -> Result<Option<super::DPMSLevel>> {
+    async fn get_dpms_level(&self) -> Result<Option<super::DPMSLevel>> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else if self.state.lock().unwrap().borrow_mut().dpms_enabled {
@@ -124,7 +143,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn set_dpms_level(&self, level: super::DPMSLevel) -> Result<()> {
+    async fn set_dpms_level(&self, level: super::DPMSLevel) -> Result<()> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -133,7 +152,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn set_dpms_state(&self, enabled: bool) -> Result<()> {
+    async fn set_dpms_state(&self, enabled: bool) -> Result<()> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -142,7 +161,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn get_dpms_timeouts(&self) -> Result<super::DPMSTimeouts> {
+    async fn get_dpms_timeouts(&self) -> Result<super::DPMSTimeouts> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -150,7 +169,7 @@ impl DisplayServerController for Controller {
         }
     }
 
-    fn set_dpms_timeouts(&self, timeouts: super::DPMSTimeouts) -> Result<()> {
+    async fn set_dpms_timeouts(&self, timeouts: super::DPMSTimeouts) -> Result<()> {
         if self.state.lock().unwrap().borrow_mut().should_fail {
             Err(make_error())
         } else {
@@ -158,6 +177,15 @@ impl DisplayServerController for Controller {
             Ok(())
         }
     }
+
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()> {
+        if self.state.lock().unwrap().borrow_mut().should_fail {
+            Err(make_error())
+        } else {
+            self.state.lock().unwrap().borrow_mut().screen_blanked = blanked;
+            Ok(())
+        }
+    }
 }
 
 fn make_error() -> anyhow::Error {
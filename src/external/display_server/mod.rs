@@ -1,7 +1,10 @@
 //! Implements APIs for interacting with display servers
 
+mod backend;
 mod interface;
+mod x11_worker;
 
+pub use backend::{AnyDisplayServer, AnyDisplayServerController};
 pub use interface::*;
 
 pub mod mock;
@@ -2,60 +2,72 @@ use crate::external::display_server::{
     mock, DPMSLevel, DPMSTimeouts, DisplayServer, DisplayServerController, SystemState,
 };
 
-#[test]
-fn test_setting_and_getting_timeout() {
+#[tokio::test]
+async fn test_setting_and_getting_timeout() {
     let interface = mock::Interface::new(10);
 
     let controller = interface.get_controller();
     assert_eq!(
         controller
             .get_idleness_timeout()
+            .await
             .expect("Failing even when failure mode is false"),
         10
     );
 
     controller
         .set_idleness_timeout(2)
+        .await
         .expect("Failing even when failure mode is false");
     assert_eq!(
         controller
             .get_idleness_timeout()
+            .await
             .expect("Failing even when failure mode is false"),
         2
     );
 }
 
-#[test]
-fn test_failure_mode() {
+#[tokio::test]
+async fn test_failure_mode() {
     let interface = mock::Interface::new(10);
     let controller = interface.get_controller();
     interface.set_failure_mode(true);
     controller
         .get_idleness_timeout()
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .set_idleness_timeout(10)
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .force_activity()
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .is_dpms_capable()
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .get_dpms_level()
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .set_dpms_level(DPMSLevel::On)
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .set_dpms_state(false)
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .get_dpms_timeouts()
+        .await
         .expect_err("No failure even when failure mode is true");
     controller
         .set_dpms_timeouts(DPMSTimeouts::new(1, 2, 3))
+        .await
         .expect_err("No failure even when failure mode is true");
 }
 
@@ -71,8 +83,8 @@ fn test_idleness_channel() {
     assert_eq!(*chan.borrow_and_update(), SystemState::Idle);
 }
 
-#[test]
-fn test_activity_forcing() {
+#[tokio::test]
+async fn test_activity_forcing() {
     let interface = mock::Interface::new(10);
     let mut chan = interface.get_idleness_channel();
     assert_eq!(*chan.borrow_and_update(), SystemState::Awakened);
@@ -84,27 +96,28 @@ fn test_activity_forcing() {
     let controller = interface.get_controller();
     controller
         .force_activity()
+        .await
         .expect("Failed to force activity");
     assert_eq!(*chan.borrow_and_update(), SystemState::Awakened);
 }
 
-#[test]
-fn test_dpms_state_control() {
+#[tokio::test]
+async fn test_dpms_state_control() {
     let interface = mock::Interface::new(10);
     let writing_controller = interface.get_controller();
     let reading_controller = interface.get_controller();
 
-    writing_controller.set_dpms_state(false).unwrap();
-    assert_eq!(reading_controller.get_dpms_level().unwrap(), None);
-    writing_controller.set_dpms_state(true).unwrap();
+    writing_controller.set_dpms_state(false).await.unwrap();
+    assert_eq!(reading_controller.get_dpms_level().await.unwrap(), None);
+    writing_controller.set_dpms_state(true).await.unwrap();
     assert_eq!(
-        reading_controller.get_dpms_level().unwrap(),
+        reading_controller.get_dpms_level().await.unwrap(),
         Some(DPMSLevel::On)
     );
 }
 
-#[test]
-fn test_dpms_levels() {
+#[tokio::test]
+async fn test_dpms_levels() {
     let interface = mock::Interface::new(10);
     let writing_controller = interface.get_controller();
     let reading_controller = interface.get_controller();
@@ -115,21 +128,27 @@ fn test_dpms_levels() {
         DPMSLevel::Off,
         DPMSLevel::On,
     ] {
-        writing_controller.set_dpms_level(level).unwrap();
-        assert_eq!(reading_controller.get_dpms_level().unwrap(), Some(level));
+        writing_controller.set_dpms_level(level).await.unwrap();
+        assert_eq!(
+            reading_controller.get_dpms_level().await.unwrap(),
+            Some(level)
+        );
     }
 }
 
-#[test]
-fn test_dpms_timeouts() {
+#[tokio::test]
+async fn test_dpms_timeouts() {
     let interface = mock::Interface::new(10);
     let writing_controller = interface.get_controller();
     let reading_controller = interface.get_controller();
 
     let test_timeouts = DPMSTimeouts::new(42, 43, 44);
-    writing_controller.set_dpms_timeouts(test_timeouts).unwrap();
+    writing_controller
+        .set_dpms_timeouts(test_timeouts)
+        .await
+        .unwrap();
     assert_eq!(
-        reading_controller.get_dpms_timeouts().unwrap(),
+        reading_controller.get_dpms_timeouts().await.unwrap(),
         test_timeouts
     );
 }
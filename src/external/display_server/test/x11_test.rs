@@ -3,6 +3,7 @@ use crate::external::display_server::{
     DPMSLevel, DPMSTimeouts, DisplayServer, DisplayServerController, SystemState,
 };
 use std::{
+    future::Future,
     io,
     process::{Child, Command},
     sync::atomic::{AtomicUsize, Ordering},
@@ -36,31 +37,34 @@ fn connect_to_xvfb(display_addr: Option<&str>) -> (RustConnection, usize) {
     RustConnection::connect(display_addr).expect("Couldn't create test connection to Xvfb")
 }
 
-fn with_xvfb<F>(func: F)
+async fn with_xvfb<F, Fut>(func: F)
 where
-    F: FnOnce(x11::X11Interface, RustConnection, usize),
+    F: FnOnce(x11::X11Interface, RustConnection, usize) -> Fut,
+    Fut: Future<Output = ()>,
 {
     let (addr, mut child) = initialize_xvfb(true).expect("Xvfb initialization failed");
     let iface = x11::X11Interface::new(Some(&addr)).expect("Couldn't create X11 interface");
     let (connection, screen_num) = connect_to_xvfb(Some(&addr));
-    func(iface, connection, screen_num);
+    func(iface, connection, screen_num).await;
     child.wait().expect("Xvfb didn't even start");
 }
 
-fn with_system_x11<F>(func: F)
+async fn with_system_x11<F, Fut>(func: F)
 where
-    F: FnOnce(x11::X11Interface, RustConnection, usize),
+    F: FnOnce(x11::X11Interface, RustConnection, usize) -> Fut,
+    Fut: Future<Output = ()>,
 {
     let iface = x11::X11Interface::new(None).expect("Couldn't create X11 interface");
     let (connection, screen_num) =
         RustConnection::connect(None).expect("Couldn't create test connection to system X11");
-    func(iface, connection, screen_num);
+    func(iface, connection, screen_num).await;
 }
 #[tokio::test]
 async fn test_xvfb_init() {
-    with_xvfb(|_, connection, _| {
+    with_xvfb(|_, connection, _| async move {
         assert_eq!(connection.setup().roots_len(), 1);
-    });
+    })
+    .await;
 }
 
 #[tokio::test]
@@ -77,51 +81,59 @@ async fn test_error_without_extension() {
 
 #[tokio::test]
 async fn test_termination() {
-    with_xvfb(|iface, _, _| {
+    with_xvfb(|iface, _, _| async move {
         iface
             .terminate_watcher()
             .expect("Error when terminating watcher");
         iface
             .uninstall_screensaver()
             .expect("Error when uninstalling screensaver");
-    });
+    })
+    .await;
 }
 
 #[tokio::test]
 async fn test_setting_and_getting_timeout() {
-    with_xvfb(|iface, _, _| {
+    with_xvfb(|iface, _, _| async move {
         let controller = iface.get_controller();
         let default = controller
             .get_idleness_timeout()
+            .await
             .expect("Couldn't get idleness timeout");
         controller
             .set_idleness_timeout(2)
+            .await
             .expect("Couldn't set idleness timeout");
         assert_eq!(
             controller
                 .get_idleness_timeout()
+                .await
                 .expect("Couldn't get idleness timeout"),
             2
         );
         controller
             .set_idleness_timeout(-1)
+            .await
             .expect("Couldn't set idleness timeout");
         assert_eq!(
             controller
                 .get_idleness_timeout()
+                .await
                 .expect("Couldn't get idleness timeout"),
             default
         );
-    });
+    })
+    .await;
 }
 
 #[tokio::test]
 async fn test_basic_flow() {
-    with_xvfb(|iface, connection, screen_num| {
+    with_xvfb(|iface, connection, screen_num| async move {
         let root = connection.setup().roots[screen_num].root;
         let controller = iface.get_controller();
         controller
             .set_idleness_timeout(2)
+            .await
             .expect("Failed to set Idleness timeout");
         let mut receiver = iface.get_idleness_channel();
         sleep(Duration::from_secs(3));
@@ -140,17 +152,20 @@ async fn test_basic_flow() {
         assert_eq!(*receiver.borrow_and_update(), SystemState::Awakened);
         controller
             .set_idleness_timeout(-1)
+            .await
             .expect("Failed to reset screensaver timeout");
-    });
+    })
+    .await;
 }
 
 #[tokio::test]
 async fn test_activity_forcing() {
-    with_xvfb(|iface, connection, screen_num| {
+    with_xvfb(|iface, connection, screen_num| async move {
         let _ = connection.setup().roots[screen_num].root;
         let controller = iface.get_controller();
         controller
             .set_idleness_timeout(2)
+            .await
             .expect("Failed to set Idleness timeout");
         let mut receiver = iface.get_idleness_channel();
         sleep(Duration::from_secs(3));
@@ -158,11 +173,13 @@ async fn test_activity_forcing() {
         assert_eq!(*receiver.borrow_and_update(), SystemState::Idle);
         controller
             .force_activity()
+            .await
             .expect("Failed to force activity");
         sleep(Duration::from_secs(1));
         assert!(receiver.has_changed().expect("Failure in receive channel"));
         assert_eq!(*receiver.borrow_and_update(), SystemState::Awakened);
-    });
+    })
+    .await;
 }
 
 // Since this needs to use system's X11 due to dummy X11 driver and XVfb not
@@ -174,24 +191,29 @@ async fn test_activity_forcing() {
 #[tokio::test]
 #[ignore]
 async fn test_dpms() {
-    with_system_x11(|iface, _, _| {
-        test_dpms_state_control(iface.get_controller());
-        test_dpms_levels(iface.get_controller());
-        test_dpms_timeouts(iface.get_controller());
-    });
+    with_system_x11(|iface, _, _| async move {
+        test_dpms_state_control(iface.get_controller()).await;
+        test_dpms_levels(iface.get_controller()).await;
+        test_dpms_timeouts(iface.get_controller()).await;
+    })
+    .await;
 }
 
-fn test_dpms_state_control(controller: X11DisplayServerController) {
-    assert!(controller.is_dpms_capable().unwrap());
-    controller.set_dpms_state(false).unwrap();
-    assert_eq!(controller.get_dpms_level().unwrap(), None);
-    controller.set_dpms_state(true).unwrap();
-    assert_eq!(controller.get_dpms_level().unwrap(), Some(DPMSLevel::On));
+async fn test_dpms_state_control(controller: X11DisplayServerController) {
+    assert!(controller.is_dpms_capable().await.unwrap());
+    controller.set_dpms_state(false).await.unwrap();
+    assert_eq!(controller.get_dpms_level().await.unwrap(), None);
+    controller.set_dpms_state(true).await.unwrap();
+    assert_eq!(
+        controller.get_dpms_level().await.unwrap(),
+        Some(DPMSLevel::On)
+    );
 }
 
-fn test_dpms_levels(controller: X11DisplayServerController) {
+async fn test_dpms_levels(controller: X11DisplayServerController) {
     controller
         .set_dpms_state(true)
+        .await
         .expect("Couldn't enable DPMS");
     for level in vec![
         DPMSLevel::Standby,
@@ -201,26 +223,31 @@ fn test_dpms_levels(controller: X11DisplayServerController) {
     ] {
         controller
             .set_dpms_level(level)
+            .await
             .expect("Failed to set DPMS level");
-        assert_eq!(controller.get_dpms_level().unwrap(), Some(level));
+        assert_eq!(controller.get_dpms_level().await.unwrap(), Some(level));
     }
 }
 
-fn test_dpms_timeouts(controller: X11DisplayServerController) {
+async fn test_dpms_timeouts(controller: X11DisplayServerController) {
     let original_timeouts = controller
         .get_dpms_timeouts()
+        .await
         .expect("Couldn't get current DPMS timeouts");
     let test_timeouts = DPMSTimeouts::new(10, 20, 30);
     controller
         .set_dpms_timeouts(test_timeouts)
+        .await
         .expect("Couldn't set DPMS timeouts");
     assert_eq!(
         controller
             .get_dpms_timeouts()
+            .await
             .expect("Couldn't get DPMS timeouts"),
         test_timeouts
     );
     controller
         .set_dpms_timeouts(original_timeouts)
+        .await
         .expect("Couldn't reset DPMS timeouts");
 }
@@ -5,19 +5,22 @@ use std::sync::Arc;
 
 use super::{
     interface::{DPMSLevel, DPMSTimeouts, DisplayServer, SystemState},
+    x11_worker::{Reply, Request, X11Worker},
     DisplayServerController,
 };
 use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use log::{debug, error};
 use tokio::sync::watch;
 use x11rb::{
     connection::{Connection, RequestConnection},
     protocol::{
-        dpms::{self, ConnectionExt as _},
+        dpms,
+        randr::{self, ConnectionExt as _},
         screensaver::{self, ConnectionExt as _, State},
         xproto::{
-            AtomEnum, Blanking, ConnectionExt as _, CreateWindowAux, EventMask, Exposures,
-            PropMode, Screen, ScreenSaver, Window, WindowClass,
+            AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, PropMode, Screen, Window,
+            WindowClass,
         },
         Event,
     },
@@ -46,17 +49,13 @@ pub struct X11Interface {
     /// X11 atom representing the screensaver attached to the root window
     screensaver_atom: u32,
     screen_num: usize,
+    /// Carries out the requests of the controllers on the command connection
+    worker: X11Worker,
 }
 
 impl X11Interface {
     pub fn new(display_name: Option<&str>) -> Result<X11Interface> {
-        let command_connection = Arc::new(RustConnection::connect(display_name)?.0);
-        if command_connection
-            .extension_information(screensaver::X11_EXTENSION_NAME)?
-            .is_none()
-        {
-            return Err(anyhow!("screensaver X11 extension unsupported"));
-        }
+        let command_connection = Arc::new(Self::connect_with_screensaver(display_name)?);
         let (receiver_connection, screen_num) = RustConnection::connect(display_name)?;
         let screen = receiver_connection.setup().roots[screen_num].clone();
         let screensaver_atom = Self::install_screensaver(&receiver_connection, &screen)?;
@@ -70,9 +69,27 @@ impl X11Interface {
             control_window_id,
             screensaver_atom,
             screen_num,
+            worker: X11Worker::spawn(command_connection.clone(), screen_num),
         })
     }
 
+    /// Check that the X server can be connected to and supports the
+    /// screensaver extension, without installing anything
+    pub fn probe(display_name: Option<&str>) -> Result<()> {
+        Self::connect_with_screensaver(display_name).map(|_| ())
+    }
+
+    fn connect_with_screensaver(display_name: Option<&str>) -> Result<RustConnection> {
+        let connection = RustConnection::connect(display_name)?.0;
+        if connection
+            .extension_information(screensaver::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            return Err(anyhow!("screensaver X11 extension unsupported"));
+        }
+        Ok(connection)
+    }
+
     fn install_screensaver(connection: &RustConnection, screen: &Screen) -> Result<u32> {
         // Screensaver installation code from xss-lock's register_screensaver function,
         // translated to x11rb with event registration bits ripped out.
@@ -201,6 +218,111 @@ impl X11Interface {
         });
         Ok(rx)
     }
+
+    /// Watch for changes of the screen configuration, e.g. monitors being
+    /// connected or disconnected, on a separate connection. The value in the
+    /// channel is incremented with each change. The watcher stops after the
+    /// first change which happens once all the receivers are dropped.
+    pub fn watch_screen_changes(display_name: Option<&str>) -> Result<watch::Receiver<u64>> {
+        let (connection, screen_num) = RustConnection::connect(display_name)?;
+        let root = connection.setup().roots[screen_num].root;
+        connection
+            .randr_query_version(1, 2)?
+            .reply()
+            .context("RandR X11 extension unsupported")?;
+        connection
+            .randr_select_input(root, randr::NotifyMask::SCREEN_CHANGE)?
+            .check()
+            .context("Couldn't select screen change events")?;
+        let (tx, rx) = watch::channel(0);
+        // Not a blocking task, which would keep the runtime from shutting down
+        std::thread::spawn(move || {
+            let mut changes = 0;
+            loop {
+                match connection.wait_for_event() {
+                    Ok(Event::RandrScreenChangeNotify(_)) => {
+                        changes += 1;
+                        debug!("Screen configuration changed");
+                        if tx.send(changes).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        error!("Error received when waiting for screen changes: {:?}", err);
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Watch which outputs have a monitor connected, on a separate
+    /// connection. The channel contains the names of the connected outputs,
+    /// e.g. `eDP-1` and `HDMI-1`, and is updated whenever they change. The
+    /// watcher stops after the first change which happens once all the
+    /// receivers are dropped.
+    pub fn watch_outputs(display_name: Option<&str>) -> Result<watch::Receiver<Vec<String>>> {
+        let (connection, screen_num) = RustConnection::connect(display_name)?;
+        let root = connection.setup().roots[screen_num].root;
+        connection
+            .randr_query_version(1, 3)?
+            .reply()
+            .context("RandR 1.3 X11 extension unsupported")?;
+        connection
+            .randr_select_input(
+                root,
+                randr::NotifyMask::OUTPUT_CHANGE | randr::NotifyMask::SCREEN_CHANGE,
+            )?
+            .check()
+            .context("Couldn't select output change events")?;
+        let (tx, rx) = watch::channel(Self::connected_outputs(&connection, root)?);
+        // Not a blocking task, which would keep the runtime from shutting down
+        std::thread::spawn(move || loop {
+            match connection.wait_for_event() {
+                Ok(Event::RandrNotify(_)) | Ok(Event::RandrScreenChangeNotify(_)) => {
+                    let outputs = match Self::connected_outputs(&connection, root) {
+                        Ok(outputs) => outputs,
+                        Err(err) => {
+                            error!("Couldn't get the connected outputs: {:?}", err);
+                            continue;
+                        }
+                    };
+                    if *tx.borrow() == outputs {
+                        continue;
+                    }
+                    debug!("Connected outputs changed to {:?}", outputs);
+                    if tx.send(outputs).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Error received when waiting for output changes: {:?}", err);
+                    return;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    fn connected_outputs(connection: &RustConnection, root: Window) -> Result<Vec<String>> {
+        let resources = connection
+            .randr_get_screen_resources_current(root)?
+            .reply()?;
+        let mut outputs = Vec::new();
+        for output in resources.outputs {
+            let info = connection
+                .randr_get_output_info(output, resources.config_timestamp)?
+                .reply()?;
+            if info.connection == randr::Connection::CONNECTED {
+                outputs.push(String::from_utf8_lossy(&info.name).into_owned());
+            }
+        }
+        outputs.sort();
+        Ok(outputs)
+    }
 }
 
 impl DisplayServer for X11Interface {
@@ -212,7 +334,7 @@ impl DisplayServer for X11Interface {
 
     fn get_controller(&self) -> Self::Controller {
         X11DisplayServerController {
-            connection: self.command_connection.clone(),
+            worker: self.worker.clone(),
         }
     }
 }
@@ -227,74 +349,81 @@ impl Drop for X11Interface {
 
 #[derive(Debug, Clone)]
 pub struct X11DisplayServerController {
-    connection: Arc<RustConnection>,
+    worker: X11Worker,
+}
+
+impl X11DisplayServerController {
+    async fn request(&self, request: Request) -> Result<()> {
+        self.worker.request(request).await.map(|_| ())
+    }
+}
+
+fn unexpected_reply(reply: Reply) -> anyhow::Error {
+    anyhow!("Unexpected reply from X11 worker: {:?}", reply)
 }
 
+#[async_trait]
 impl DisplayServerController for X11DisplayServerController {
-    fn set_idleness_timeout(&self, timeout: i16) -> Result<()> {
+    async fn set_idleness_timeout(&self, timeout: i16) -> Result<()> {
         debug!("Setting idleness timeout to {}", timeout);
-        Ok(self
-            .connection
-            .set_screen_saver(timeout, 0, Blanking::NOT_PREFERRED, Exposures::DEFAULT)?
-            .check()?)
+        self.request(Request::SetIdlenessTimeout(timeout)).await
     }
 
-    fn get_idleness_timeout(&self) -> Result<i16> {
+    async fn get_idleness_timeout(&self) -> Result<i16> {
         debug!("Fetching idleness timeout");
-        Ok(self.connection.get_screen_saver()?.reply()?.timeout as i16)
+        match self.worker.request(Request::GetIdlenessTimeout).await? {
+            Reply::IdlenessTimeout(timeout) => Ok(timeout),
+            reply => Err(unexpected_reply(reply)),
+        }
     }
 
-    fn force_activity(&self) -> Result<()> {
+    async fn force_activity(&self) -> Result<()> {
         debug!("Force resetting the screensaver timeout");
-        Ok(self
-            .connection
-            .force_screen_saver(ScreenSaver::RESET)?
-            .check()?)
+        self.request(Request::ForceActivity).await
     }
 
-    fn is_dpms_capable(&self) -> Result<bool> {
+    async fn is_dpms_capable(&self) -> Result<bool> {
         debug!("Fetching DPMS capability");
-        Ok(self.connection.dpms_capable()?.reply()?.capable)
+        match self.worker.request(Request::IsDpmsCapable).await? {
+            Reply::DpmsCapable(capable) => Ok(capable),
+            reply => Err(unexpected_reply(reply)),
+        }
     }
 
-    fn get_dpms_level(&self) -> Result<Option<super::DPMSLevel>> {
+    async fn get_dpms_level(&self) -> Result<Option<DPMSLevel>> {
         debug!("Fetching DPMS level");
-        let info = self.connection.dpms_info()?.reply()?;
-        if info.state {
-            Ok(Some(DPMSLevel::from(info.power_level)))
-        } else {
-            Ok(None)
+        match self.worker.request(Request::GetDpmsLevel).await? {
+            Reply::DpmsLevel(level) => Ok(level),
+            reply => Err(unexpected_reply(reply)),
         }
     }
 
-    fn set_dpms_level(&self, level: DPMSLevel) -> Result<()> {
+    async fn set_dpms_level(&self, level: DPMSLevel) -> Result<()> {
         debug!("Setting DPMS level");
-        Ok(self
-            .connection
-            .dpms_force_level(dpms::DPMSMode::from(level))?
-            .check()?)
+        self.request(Request::SetDpmsLevel(level)).await
     }
 
-    fn set_dpms_state(&self, enabled: bool) -> Result<()> {
+    async fn set_dpms_state(&self, enabled: bool) -> Result<()> {
         debug!("Setting DPMS state");
-        if enabled {
-            Ok(self.connection.dpms_enable()?.check()?)
-        } else {
-            Ok(self.connection.dpms_disable()?.check()?)
-        }
+        self.request(Request::SetDpmsState(enabled)).await
     }
 
-    fn get_dpms_timeouts(&self) -> Result<super::DPMSTimeouts> {
+    async fn get_dpms_timeouts(&self) -> Result<DPMSTimeouts> {
         debug!("Fetching DPMS timeouts");
-        Ok(self.connection.dpms_get_timeouts()?.reply()?.into())
+        match self.worker.request(Request::GetDpmsTimeouts).await? {
+            Reply::DpmsTimeouts(timeouts) => Ok(timeouts),
+            reply => Err(unexpected_reply(reply)),
+        }
     }
 
-    fn set_dpms_timeouts(&self, timeouts: super::DPMSTimeouts) -> Result<()> {
+    async fn set_dpms_timeouts(&self, timeouts: DPMSTimeouts) -> Result<()> {
         debug!("Setting DPMS timeouts");
-        Ok(self
-            .connection
-            .dpms_set_timeouts(timeouts.standby, timeouts.suspend, timeouts.off)?
-            .check()?)
+        self.request(Request::SetDpmsTimeouts(timeouts)).await
+    }
+
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()> {
+        debug!("Setting screen blanking to {}", blanked);
+        self.request(Request::SetScreenBlanked(blanked)).await
     }
 }
 
@@ -0,0 +1,243 @@
+//! A thread owning the X11 connection used to control the display server
+//!
+//! The [X11DisplayServerController]s send their requests to the worker
+//! instead of blocking a thread of their own for each call. The requests
+//! which queue up while the worker waits for a reply are sent together and
+//! their replies are waited for afterwards, so that e.g. restoring the DPMS
+//! level and timeouts takes a single round trip.
+//!
+//! [X11DisplayServerController]: super::x11::X11DisplayServerController
+
+use super::interface::{DPMSLevel, DPMSTimeouts};
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use x11rb::{
+    connection::Connection,
+    cookie::{Cookie, VoidCookie},
+    protocol::{
+        dpms::{self, ConnectionExt as _},
+        xproto::{
+            Blanking, ConfigureWindowAux, ConnectionExt as _, CreateWindowAux, Exposures,
+            GetScreenSaverReply, ScreenSaver, StackMode, Window, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+    COPY_DEPTH_FROM_PARENT,
+};
+
+/// A request for the display server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    SetIdlenessTimeout(i16),
+    GetIdlenessTimeout,
+    ForceActivity,
+    IsDpmsCapable,
+    GetDpmsLevel,
+    SetDpmsLevel(DPMSLevel),
+    SetDpmsState(bool),
+    GetDpmsTimeouts,
+    SetDpmsTimeouts(DPMSTimeouts),
+    SetScreenBlanked(bool),
+}
+
+/// The reply to a [Request]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// The request has been carried out and has nothing to return
+    Done,
+    IdlenessTimeout(i16),
+    DpmsCapable(bool),
+    DpmsLevel(Option<DPMSLevel>),
+    DpmsTimeouts(DPMSTimeouts),
+}
+
+type Job = (Request, oneshot::Sender<Result<Reply>>);
+
+/// Sends [Request]s to the worker thread, which terminates once all the
+/// clones are dropped
+#[derive(Debug, Clone)]
+pub struct X11Worker {
+    sender: mpsc::UnboundedSender<Job>,
+}
+
+impl X11Worker {
+    pub fn spawn(connection: Arc<RustConnection>, screen_num: usize) -> X11Worker {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            WorkerThread {
+                connection,
+                screen_num,
+                blank_window: None,
+            }
+            .run(receiver)
+        });
+        X11Worker { sender }
+    }
+
+    pub async fn request(&self, request: Request) -> Result<Reply> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        self.sender
+            .send((request, reply_sender))
+            .map_err(|_| anyhow!("X11 worker has terminated"))?;
+        reply_receiver.await.context("X11 worker has terminated")?
+    }
+}
+
+/// A request which has been sent to the X server and waits for its reply
+enum Pending<'c> {
+    Void(VoidCookie<'c, RustConnection>),
+    ScreenSaver(Cookie<'c, RustConnection, GetScreenSaverReply>),
+    DpmsCapable(Cookie<'c, RustConnection, dpms::CapableReply>),
+    DpmsInfo(Cookie<'c, RustConnection, dpms::InfoReply>),
+    DpmsTimeouts(Cookie<'c, RustConnection, dpms::GetTimeoutsReply>),
+    /// The request needed its replies right away and is carried out already
+    Done,
+}
+
+impl Pending<'_> {
+    fn wait(self) -> Result<Reply> {
+        Ok(match self {
+            Pending::Void(cookie) => {
+                cookie.check()?;
+                Reply::Done
+            }
+            Pending::ScreenSaver(cookie) => Reply::IdlenessTimeout(cookie.reply()?.timeout as i16),
+            Pending::DpmsCapable(cookie) => Reply::DpmsCapable(cookie.reply()?.capable),
+            Pending::DpmsInfo(cookie) => {
+                let info = cookie.reply()?;
+                Reply::DpmsLevel(if info.state {
+                    Some(DPMSLevel::from(info.power_level))
+                } else {
+                    None
+                })
+            }
+            Pending::DpmsTimeouts(cookie) => Reply::DpmsTimeouts(cookie.reply()?.into()),
+            Pending::Done => Reply::Done,
+        })
+    }
+}
+
+struct WorkerThread {
+    connection: Arc<RustConnection>,
+    screen_num: usize,
+    /// The window covering the screen while it's blanked
+    blank_window: Option<Window>,
+}
+
+impl WorkerThread {
+    fn run(mut self, mut jobs: mpsc::UnboundedReceiver<Job>) {
+        while let Some(job) = jobs.blocking_recv() {
+            let mut batch = vec![job];
+            while let Ok(job) = jobs.try_recv() {
+                batch.push(job);
+            }
+            log::trace!("Sending a batch of {} X11 requests", batch.len());
+            let connection = self.connection.clone();
+            let pending: Vec<_> = batch
+                .into_iter()
+                .map(|(request, reply)| (self.send(&connection, request), reply))
+                .collect();
+            // A failed flush is reported when waiting for the replies
+            let _ = connection.flush();
+            for (pending, reply) in pending {
+                // The requester may not be interested in the reply anymore
+                let _ = reply.send(pending.and_then(Pending::wait));
+            }
+        }
+        log::debug!("All X11 controllers dropped, stopping worker");
+    }
+
+    fn send<'c>(
+        &mut self,
+        connection: &'c RustConnection,
+        request: Request,
+    ) -> Result<Pending<'c>> {
+        Ok(match request {
+            Request::SetIdlenessTimeout(timeout) => Pending::Void(connection.set_screen_saver(
+                timeout,
+                0,
+                Blanking::NOT_PREFERRED,
+                Exposures::DEFAULT,
+            )?),
+            Request::GetIdlenessTimeout => Pending::ScreenSaver(connection.get_screen_saver()?),
+            Request::ForceActivity => {
+                Pending::Void(connection.force_screen_saver(ScreenSaver::RESET)?)
+            }
+            Request::IsDpmsCapable => Pending::DpmsCapable(connection.dpms_capable()?),
+            Request::GetDpmsLevel => Pending::DpmsInfo(connection.dpms_info()?),
+            Request::SetDpmsLevel(level) => {
+                Pending::Void(connection.dpms_force_level(dpms::DPMSMode::from(level))?)
+            }
+            Request::SetDpmsState(true) => Pending::Void(connection.dpms_enable()?),
+            Request::SetDpmsState(false) => Pending::Void(connection.dpms_disable()?),
+            Request::GetDpmsTimeouts => Pending::DpmsTimeouts(connection.dpms_get_timeouts()?),
+            Request::SetDpmsTimeouts(timeouts) => Pending::Void(connection.dpms_set_timeouts(
+                timeouts.standby,
+                timeouts.suspend,
+                timeouts.off,
+            )?),
+            Request::SetScreenBlanked(blanked) => {
+                self.set_screen_blanked(connection, blanked)?;
+                Pending::Done
+            }
+        })
+    }
+
+    fn set_screen_blanked(&mut self, connection: &RustConnection, blanked: bool) -> Result<()> {
+        let screen = &connection.setup().roots[self.screen_num];
+        match (self.blank_window, blanked) {
+            (None, true) => {
+                self.blank_window = Some(create_blank_window(connection, self.screen_num)?);
+            }
+            // Windows may have been opened above the blanking window and
+            // the screen may have grown since it was blanked
+            (Some(window), true) => {
+                let size = connection.get_geometry(screen.root)?.reply()?;
+                connection
+                    .configure_window(
+                        window,
+                        &ConfigureWindowAux::default()
+                            .width(size.width as u32)
+                            .height(size.height as u32)
+                            .stack_mode(StackMode::ABOVE),
+                    )?
+                    .check()?;
+            }
+            (Some(window), false) => {
+                connection.destroy_window(window)?.check()?;
+                self.blank_window = None;
+            }
+            (None, false) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Cover the whole screen with a black window, which doesn't take any input
+/// focus, so that the screensaver still notices activity
+fn create_blank_window(connection: &RustConnection, screen_num: usize) -> Result<Window> {
+    let screen = &connection.setup().roots[screen_num];
+    let window = connection.generate_id()?;
+    let aux_values = CreateWindowAux::default()
+        .background_pixel(screen.black_pixel)
+        .override_redirect(1);
+    connection
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            screen.width_in_pixels,
+            screen.height_in_pixels,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &aux_values,
+        )?
+        .check()
+        .context("Couldn't create the blanking window")?;
+    connection.map_window(window)?.check()?;
+    Ok(window)
+}
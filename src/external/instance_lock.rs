@@ -0,0 +1,56 @@
+//! Makes sure that only one instance of Energia manages a display, so that
+//! two instances don't fight over the X11 screensaver and its timeouts
+//!
+//! The lock is a Unix socket in the abstract namespace, which the kernel
+//! removes as soon as its owner exits, even after a crash.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::ErrorKind,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixListener},
+    },
+};
+
+/// Held by the instance of Energia managing a display, released on drop
+#[derive(Debug)]
+pub struct InstanceLock {
+    _socket: UnixListener,
+}
+
+impl InstanceLock {
+    /// Take the lock of the display for the current user, failing if another
+    /// instance holds it
+    pub fn acquire(display: &str) -> Result<InstanceLock> {
+        // SAFETY: getuid can't fail
+        let uid = unsafe { libc::getuid() };
+        let name = format!("energia-{}-{}", uid, display);
+        let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+        match UnixListener::bind_addr(&address) {
+            Ok(socket) => Ok(InstanceLock { _socket: socket }),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => Err(anyhow!(
+                "Another instance of Energia is already running on display {}",
+                display
+            )),
+            Err(e) => Err(e).context("Couldn't make sure that Energia runs only once"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_single_instance() {
+        let display = format!(":test-{}", std::process::id());
+        let lock = InstanceLock::acquire(&display).unwrap();
+        let error = InstanceLock::acquire(&display).unwrap_err();
+        assert!(error.to_string().contains("already running"));
+        InstanceLock::acquire(&format!("{}.1", display)).expect("Other displays aren't locked");
+
+        drop(lock);
+        InstanceLock::acquire(&display).expect("Lock hasn't been released");
+    }
+}
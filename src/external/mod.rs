@@ -1,6 +1,10 @@
 //! Provides abstractions over the APIs of various system components
 
 pub mod brightness;
+pub mod daemon;
 pub mod dbus;
 pub mod dependency_provider;
 pub mod display_server;
+pub mod instance_lock;
+pub mod state_journal;
+pub mod systemd;
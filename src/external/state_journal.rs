@@ -0,0 +1,248 @@
+//! Crash-safe record of the system settings changed by Energia, so that they
+//! can be restored after it terminates without restoring them itself
+
+use super::{
+    brightness::BrightnessController,
+    display_server::{DPMSLevel, DPMSTimeouts, DisplayServerController},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// DPMS configuration of the display server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DPMSConfiguration {
+    /// [None] if DPMS is disabled
+    pub level: Option<DPMSLevel>,
+    pub timeouts: DPMSTimeouts,
+}
+
+/// The changes Energia has made to the system which haven't been undone yet
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalState {
+    /// Names of the effects which are applied
+    pub applied_effects: BTreeSet<String>,
+    /// Brightness of the screen before it was dimmed
+    pub original_brightness: Option<usize>,
+    /// DPMS configuration before the display effector has taken it over
+    pub original_dpms: Option<DPMSConfiguration>,
+    /// Idleness timeout of the display server before it was changed to
+    /// follow the schedule
+    pub original_idleness_timeout: Option<i16>,
+}
+
+impl JournalState {
+    /// Whether there are no changes to undo
+    pub fn is_clean(&self) -> bool {
+        *self == JournalState::default()
+    }
+
+    /// Restore the original settings of the system
+    pub async fn restore<B: BrightnessController, C: DisplayServerController>(
+        &self,
+        brightness_controller: &B,
+        ds_controller: &C,
+    ) -> Result<()> {
+        if let Some(brightness) = self.original_brightness {
+            log::info!("Restoring screen brightness to {}", brightness);
+            brightness_controller.set_brightness(brightness).await?;
+        }
+        if let Some(dpms) = self.original_dpms {
+            log::info!("Restoring DPMS configuration to {:?}", dpms);
+            match dpms.level {
+                Some(level) => {
+                    ds_controller.set_dpms_state(true).await?;
+                    ds_controller.set_dpms_level(level).await?;
+                }
+                None => ds_controller.set_dpms_state(false).await?,
+            }
+            ds_controller.set_dpms_timeouts(dpms.timeouts).await?;
+        }
+        if let Some(timeout) = self.original_idleness_timeout {
+            log::info!("Restoring idleness timeout to {}s", timeout);
+            ds_controller.set_idleness_timeout(timeout).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A [JournalState] written to a file on each change.
+///
+/// The file is small and written rarely, so it's written synchronously,
+/// replacing the old one atomically. A journal without a file is kept just
+/// in memory, for use in tests and simulations.
+#[derive(Clone, Default)]
+pub struct StateJournal {
+    path: Option<Arc<PathBuf>>,
+    state: Arc<Mutex<JournalState>>,
+}
+
+impl StateJournal {
+    /// `$XDG_STATE_HOME/energia/state-<display>.json`, with `$XDG_STATE_HOME`
+    /// defaulting to `~/.local/state`. Each display has its own journal, like
+    /// it has its own [InstanceLock](super::instance_lock::InstanceLock), so
+    /// that instances on different displays don't restore each other's
+    /// changes. Without a display, the journal is `state.json`.
+    pub fn default_path(display: &str) -> PathBuf {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .unwrap_or_else(|| {
+                PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/state")
+            });
+        let file_name = if display.is_empty() {
+            "state.json".to_owned()
+        } else {
+            format!("state-{}.json", display)
+        };
+        state_home.join("energia").join(file_name)
+    }
+
+    /// Create a journal which isn't written anywhere
+    pub fn in_memory() -> StateJournal {
+        StateJournal::default()
+    }
+
+    /// Open the journal at the given path, returning the state left there by
+    /// a previous run which didn't terminate cleanly. The journal itself
+    /// starts clean and overwrites the old state on the first change.
+    pub fn open(path: &Path) -> Result<(StateJournal, JournalState)> {
+        let leftover = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                log::warn!("Ignoring corrupted state journal {:?}: {}", path, e);
+                JournalState::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => JournalState::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Couldn't read state journal {:?}", path))
+            }
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Couldn't create state directory {:?}", dir))?;
+        }
+        let journal = StateJournal {
+            path: Some(Arc::new(path.to_owned())),
+            state: Arc::default(),
+        };
+        Ok((journal, leftover))
+    }
+
+    /// Get the current state
+    pub fn state(&self) -> JournalState {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Change the state and write it to the journal's file
+    pub fn update(&self, change: impl FnOnce(&mut JournalState)) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let old_state = state.clone();
+        change(&mut state);
+        if *state == old_state {
+            return;
+        }
+        if let Some(path) = self.path.as_ref() {
+            if let Err(e) = write_atomically(path, &state) {
+                log::error!("Couldn't write state journal: {:?}", e);
+            }
+        }
+    }
+
+    /// Remove the journal's file, once all the changes have been undone
+    /// during a clean shutdown
+    pub fn remove(&self) {
+        if let Some(path) = self.path.as_ref() {
+            match fs::remove_file(path.as_path()) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::error!("Couldn't remove state journal: {}", e),
+            }
+        }
+    }
+}
+
+fn write_atomically(path: &Path, state: &JournalState) -> Result<()> {
+    let temporary_path = path.with_extension("json.tmp");
+    fs::write(&temporary_path, serde_json::to_vec(state)?)?;
+    fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::external::{
+        brightness::mock::MockBrightnessController,
+        display_server::{mock, DisplayServer},
+    };
+
+    #[test]
+    fn test_default_path() {
+        let path = StateJournal::default_path(":1");
+        assert_eq!(path.file_name().unwrap(), "state-:1.json");
+        assert_eq!(path.parent(), StateJournal::default_path(":0").parent());
+        assert_eq!(
+            StateJournal::default_path("").file_name().unwrap(),
+            "state.json"
+        );
+    }
+
+    #[test]
+    fn test_journal_file() {
+        let path = env::temp_dir()
+            .join(format!("energia-journal-test-{}", std::process::id()))
+            .join("state.json");
+        let (journal, leftover) = StateJournal::open(&path).unwrap();
+        assert!(leftover.is_clean());
+
+        journal.update(|state| {
+            state.applied_effects.insert("screen_dim".to_owned());
+            state.original_brightness = Some(80);
+        });
+        let (reopened, leftover) = StateJournal::open(&path).unwrap();
+        assert_eq!(leftover, journal.state());
+        assert!(reopened.state().is_clean());
+
+        journal.remove();
+        let (_, leftover) = StateJournal::open(&path).unwrap();
+        assert!(leftover.is_clean());
+
+        fs::write(&path, "{").unwrap();
+        let (_, leftover) = StateJournal::open(&path).unwrap();
+        assert!(leftover.is_clean());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let brightness = MockBrightnessController::new(40);
+        let display = mock::Interface::new(5);
+        let ds_controller = display.get_controller();
+        let state = JournalState {
+            applied_effects: BTreeSet::from(["screen_dim".to_owned()]),
+            original_brightness: Some(80),
+            original_dpms: Some(DPMSConfiguration {
+                level: Some(DPMSLevel::Standby),
+                timeouts: DPMSTimeouts::new(10, 20, 30),
+            }),
+            original_idleness_timeout: Some(600),
+        };
+        state.restore(&brightness, &ds_controller).await.unwrap();
+        assert_eq!(brightness.get_brightness().await.unwrap(), 80);
+        assert_eq!(
+            ds_controller.get_dpms_level().await.unwrap(),
+            Some(DPMSLevel::Standby)
+        );
+        assert_eq!(
+            ds_controller.get_dpms_timeouts().await.unwrap(),
+            DPMSTimeouts::new(10, 20, 30)
+        );
+        assert_eq!(ds_controller.get_idleness_timeout().await.unwrap(), 600);
+    }
+}
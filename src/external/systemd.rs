@@ -0,0 +1,143 @@
+//! Notifications sent to the systemd service manager through the socket given
+//! in `$NOTIFY_SOCKET`, as described in sd_notify(3)
+
+use anyhow::{anyhow, Context, Result};
+use std::{env, os::unix::net::UnixDatagram, path::PathBuf, time::Duration};
+
+/// Sends status updates to systemd when Energia runs as its service
+#[derive(Debug, Clone)]
+pub struct SystemdNotifier {
+    socket_path: PathBuf,
+    watchdog_timeout: Option<Duration>,
+}
+
+impl SystemdNotifier {
+    /// Create a notifier from the environment set by systemd, [None] if
+    /// Energia isn't running as a service which is expected to notify it.
+    ///
+    /// The variables are removed from the environment, so that the commands
+    /// run by Energia don't send notifications in its name.
+    pub fn from_env() -> Option<SystemdNotifier> {
+        let socket = env::var("NOTIFY_SOCKET").ok();
+        let watchdog_usec = env::var("WATCHDOG_USEC").ok();
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        for variable in ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"] {
+            env::remove_var(variable);
+        }
+        let socket = socket?;
+        if socket.starts_with('@') {
+            log::warn!(
+                "Notification socket {} is in the abstract namespace, which isn't supported",
+                socket
+            );
+            return None;
+        }
+        let watchdog_timeout = parse_watchdog_timeout(
+            watchdog_usec.as_deref(),
+            watchdog_pid.as_deref(),
+            std::process::id(),
+        );
+        Some(SystemdNotifier::new(
+            PathBuf::from(socket),
+            watchdog_timeout,
+        ))
+    }
+
+    /// Create a notifier sending the notifications to the given socket
+    pub fn new(socket_path: PathBuf, watchdog_timeout: Option<Duration>) -> SystemdNotifier {
+        SystemdNotifier {
+            socket_path,
+            watchdog_timeout,
+        }
+    }
+
+    /// Time after which systemd considers the service hung if it doesn't
+    /// receive a watchdog ping, [None] if the watchdog isn't enabled
+    pub fn watchdog_timeout(&self) -> Option<Duration> {
+        self.watchdog_timeout
+    }
+
+    /// Tell systemd that the startup has finished
+    pub fn ready(&self) -> Result<()> {
+        self.notify("READY=1")
+    }
+
+    /// Tell systemd that the service is still alive
+    pub fn watchdog(&self) -> Result<()> {
+        self.notify("WATCHDOG=1")
+    }
+
+    /// Tell systemd that the service is shutting down
+    pub fn stopping(&self) -> Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Send newline-separated `KEY=value` assignments to systemd
+    pub fn notify(&self, state: &str) -> Result<()> {
+        let socket = UnixDatagram::unbound().context("Couldn't create notification socket")?;
+        let sent = socket
+            .send_to(state.as_bytes(), &self.socket_path)
+            .with_context(|| format!("Couldn't notify systemd through {:?}", self.socket_path))?;
+        if sent != state.len() {
+            return Err(anyhow!("Notification {} was only sent partially", state));
+        }
+        Ok(())
+    }
+}
+
+/// Parse the watchdog timeout from the values of `$WATCHDOG_USEC` and
+/// `$WATCHDOG_PID`, which is only meant for the process with the given PID
+fn parse_watchdog_timeout(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    match usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog_timeout() {
+        assert_eq!(
+            parse_watchdog_timeout(Some("30000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog_timeout(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog_timeout(Some("30000000"), Some("41"), 42),
+            None
+        );
+        assert_eq!(parse_watchdog_timeout(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog_timeout(Some("soon"), None, 42), None);
+        assert_eq!(parse_watchdog_timeout(None, Some("42"), 42), None);
+    }
+
+    #[test]
+    fn test_notify() {
+        let path = env::temp_dir().join(format!("energia-notify-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = SystemdNotifier::new(path.clone(), None);
+
+        notifier.ready().unwrap();
+        notifier.watchdog().unwrap();
+        let mut buffer = [0u8; 64];
+        let received = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"READY=1");
+        let received = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"WATCHDOG=1");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(notifier.stopping().is_err());
+    }
+}
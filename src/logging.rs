@@ -0,0 +1,352 @@
+//! Formats of the log lines and the context they're enriched with
+
+use crate::armaf::{current_actor_name, Handle, HandleChild, TickService, Ticks, TraceId};
+use clap::ArgEnum;
+use flexi_logger::{Cleanup, Criterion, DeferredNow};
+use std::{
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Base name of the log files
+pub const LOG_BASENAME: &str = "energia";
+/// How often [LogCleaner] looks for old log files
+const CLEANUP_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+tokio::task_local! {
+    static CURRENT_EFFECT: String;
+}
+
+static CURRENT_SCHEDULE: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Format in which the log lines are written
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// A JSON object per line, for ingestion by journald or log collectors
+    Json,
+}
+
+impl LogFormat {
+    /// The flexi_logger format function producing this format
+    pub fn format_function(&self) -> flexi_logger::FormatFunction {
+        match self {
+            LogFormat::Text => text_format,
+            LogFormat::Json => json_format,
+        }
+    }
+
+    /// The format function for lines written to stderr, which systemd passes
+    /// to the journal
+    pub fn journal_format_function(&self) -> flexi_logger::FormatFunction {
+        match self {
+            LogFormat::Text => journal_format,
+            LogFormat::Json => json_format,
+        }
+    }
+}
+
+/// When the log file gets rotated and how many of the rotated files are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Size in bytes above which the log file is rotated
+    pub max_size: u64,
+    /// Number of rotated files kept, older ones are deleted by flexi_logger
+    pub keep_files: usize,
+    /// Whether to gzip the rotated files
+    pub compress: bool,
+}
+
+impl LogRotation {
+    pub fn criterion(&self) -> Criterion {
+        Criterion::Size(self.max_size)
+    }
+
+    pub fn cleanup(&self) -> Cleanup {
+        if self.compress {
+            Cleanup::KeepCompressedFiles(self.keep_files)
+        } else {
+            Cleanup::KeepLogFiles(self.keep_files)
+        }
+    }
+}
+
+/// Run the future with the lines it logs attributed to the named effect
+pub async fn effect_scope<F: Future>(effect_name: &str, future: F) -> F::Output {
+    CURRENT_EFFECT.scope(effect_name.to_owned(), future).await
+}
+
+fn current_effect_name() -> Option<String> {
+    CURRENT_EFFECT.try_with(|name| name.clone()).ok()
+}
+
+/// Record the name of the schedule in use, which is included in the lines
+/// logged from then on
+pub fn set_schedule(schedule_name: Option<&'static str>) {
+    *CURRENT_SCHEDULE.lock().unwrap_or_else(|e| e.into_inner()) = schedule_name;
+}
+
+fn current_schedule() -> Option<&'static str> {
+    *CURRENT_SCHEDULE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Like [flexi_logger::opt_format], with the trace of the logging task's
+/// request, so that a single event can be followed across actors
+pub fn text_format(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    write!(
+        w,
+        "[{}] {} [{}:{}] ",
+        now.format("%Y-%m-%d %H:%M:%S%.6f"),
+        record.level(),
+        record.file().unwrap_or("<unnamed>"),
+        record.line().unwrap_or(0),
+    )?;
+    if let Some(trace_id) = TraceId::current() {
+        write!(w, "[{}] ", trace_id)?;
+    }
+    write!(w, "{}", record.args())
+}
+
+/// Like [text_format], without the timestamp, which the journal adds itself,
+/// and prefixed with the line's priority as described in sd-daemon(3)
+pub fn journal_format(
+    w: &mut dyn Write,
+    _now: &mut DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let priority = match record.level() {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    };
+    write!(
+        w,
+        "<{}>[{}:{}] ",
+        priority,
+        record.file().unwrap_or("<unnamed>"),
+        record.line().unwrap_or(0),
+    )?;
+    if let Some(trace_id) = TraceId::current() {
+        write!(w, "[{}] ", trace_id)?;
+    }
+    write!(w, "{}", record.args())
+}
+
+/// A JSON object with the message, its origin and the trace, actor, effect
+/// and schedule it was logged in. Fields without a value are left out.
+pub fn json_format(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let mut entry = serde_json::Map::new();
+    entry.insert(
+        "timestamp".to_owned(),
+        now.format("%Y-%m-%dT%H:%M:%S%.6f%:z").to_string().into(),
+    );
+    entry.insert("level".to_owned(), record.level().as_str().into());
+    entry.insert("message".to_owned(), record.args().to_string().into());
+    if let Some(module) = record.module_path() {
+        entry.insert("module".to_owned(), module.into());
+    }
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        entry.insert("file".to_owned(), file.into());
+        entry.insert("line".to_owned(), line.into());
+    }
+    if let Some(trace_id) = TraceId::current() {
+        entry.insert("trace".to_owned(), trace_id.as_u64().into());
+    }
+    if let Some(actor) = current_actor_name() {
+        entry.insert("actor".to_owned(), actor.into());
+    }
+    if let Some(effect) = current_effect_name() {
+        entry.insert("effect".to_owned(), effect.into());
+    }
+    if let Some(schedule) = current_schedule() {
+        entry.insert("schedule".to_owned(), schedule.into());
+    }
+    serde_json::to_writer(&mut *w, &entry)?;
+    Ok(())
+}
+
+/// Periodically deletes log files older than the maximum age from the log
+/// directory, including the ones left there by earlier versions which didn't
+/// rotate their logs and so aren't cleaned up by flexi_logger
+pub struct LogCleaner {
+    directory: PathBuf,
+    max_age: Duration,
+    ticks: Ticks,
+    handle_child: Option<HandleChild>,
+}
+
+impl LogCleaner {
+    pub fn new(directory: PathBuf, max_age: Duration, tick_service: &TickService) -> LogCleaner {
+        LogCleaner {
+            directory,
+            max_age,
+            ticks: tick_service.subscribe(CLEANUP_PERIOD),
+            handle_child: None,
+        }
+    }
+
+    pub fn spawn(mut self) -> Handle {
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+            log::debug!("Terminated");
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        loop {
+            self.clean_up().await;
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => return,
+                _ = self.ticks.tick() => {}
+            }
+        }
+    }
+
+    async fn clean_up(&self) {
+        let directory = self.directory.clone();
+        let max_age = self.max_age;
+        let removal = tokio::task::spawn_blocking(move || {
+            remove_old_logs(&directory, max_age, SystemTime::now())
+        })
+        .await;
+        match removal {
+            Ok(Ok(0)) => {}
+            Ok(Ok(removed)) => log::info!("Removed {} old log files", removed),
+            Ok(Err(e)) => log::error!("Couldn't clean up the log directory: {}", e),
+            Err(e) => log::error!("Log cleanup failed: {}", e),
+        }
+    }
+}
+
+/// Whether the file is a log file written by energia which isn't being
+/// written to anymore
+fn is_inactive_log(file_name: &str) -> bool {
+    file_name.starts_with(&format!("{}_", LOG_BASENAME))
+        && (file_name.ends_with(".log") || file_name.ends_with(".log.gz"))
+        && !file_name.contains("_rCURRENT")
+}
+
+/// Delete the inactive log files last modified before `now - max_age`,
+/// returning their count
+fn remove_old_logs(directory: &Path, max_age: Duration, now: SystemTime) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let file_name = entry.file_name();
+        if !metadata.is_file() || !file_name.to_str().map_or(false, is_inactive_log) {
+            continue;
+        }
+        let age = now
+            .duration_since(metadata.modified()?)
+            .unwrap_or(Duration::ZERO);
+        if age > max_age {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format_json(record: &log::Record) -> serde_json::Value {
+        let mut output = Vec::new();
+        json_format(&mut output, &mut DeferredNow::new(), record).unwrap();
+        serde_json::from_slice(&output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_format() {
+        let record = log::Record::builder()
+            .args(format_args!("Applying \"lock\""))
+            .level(log::Level::Info)
+            .module_path(Some("energia::control::idleness_controller"))
+            .file(Some("src/control/idleness_controller.rs"))
+            .line(Some(42))
+            .build();
+        let entry = format_json(&record);
+        assert_eq!(entry["level"], "INFO");
+        assert_eq!(entry["message"], "Applying \"lock\"");
+        assert_eq!(entry["module"], "energia::control::idleness_controller");
+        assert_eq!(entry["line"], 42);
+        assert!(entry["timestamp"].is_string());
+        assert!(entry.get("effect").is_none());
+        assert!(entry.get("trace").is_none());
+
+        let trace_id = TraceId::new();
+        let entry = trace_id
+            .scope(effect_scope("lock", async { format_json(&record) }))
+            .await;
+        assert_eq!(entry["effect"], "lock");
+        assert_eq!(entry["trace"], trace_id.as_u64());
+    }
+
+    #[test]
+    fn test_journal_format() {
+        let record = log::Record::builder()
+            .args(format_args!("Couldn't lock"))
+            .level(log::Level::Error)
+            .file(Some("src/system/lock_effector.rs"))
+            .line(Some(7))
+            .build();
+        let mut output = Vec::new();
+        journal_format(&mut output, &mut DeferredNow::new(), &record).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<3>[src/system/lock_effector.rs:7] Couldn't lock"
+        );
+    }
+
+    #[test]
+    fn test_remove_old_logs() {
+        let directory =
+            std::env::temp_dir().join(format!("energia-log-cleanup-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let files = [
+            ("energia_2022-03-01_10-00-00.log", 40 * day),
+            ("energia_r00001.log.gz", 40 * day),
+            ("energia_r00002.log", day),
+            ("energia_rCURRENT.log", 40 * day),
+            ("config.toml", 40 * day),
+        ];
+        for (name, age) in files {
+            let file = std::fs::File::create(directory.join(name)).unwrap();
+            file.set_modified(now - age).unwrap();
+        }
+
+        assert_eq!(remove_old_logs(&directory, 30 * day, now).unwrap(), 2);
+        let mut remaining: Vec<String> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec!["config.toml", "energia_r00002.log", "energia_rCURRENT.log"]
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -3,25 +3,80 @@
 //! A modern power manager for Linux
 
 mod armaf;
+mod check;
+mod config;
 mod control;
+mod crash;
+mod doctor;
 mod external;
+mod logging;
+#[cfg(feature = "simulation")]
+mod simulation;
 mod system;
 
-use clap::Parser;
-use control::{dbus_controller::DBusController, environment_controller::EnvironmentController};
-use external::dependency_provider::DependencyProvider;
-use flexi_logger::{FileSpec, Logger};
-use std::env;
-use tokio::{self, fs};
+use clap::{Parser, Subcommand};
+use config::ConfigSources;
+use control::{
+    dbus_controller::DBusController,
+    environment_controller::{EnvironmentCommand, EnvironmentController},
+    event_history::{EventHistory, HistoryRecorder, DEFAULT_HISTORY_SIZE},
+    metrics::MetricsExporter,
+    socket_controller::SocketController,
+    statistics::{self, StatisticsCollector, StatisticsStore},
+    watchdog::Watchdog,
+};
+use external::{
+    daemon::{self, Daemon, PidFile},
+    dependency_provider::{parse_backends, DependencyProvider},
+    display_server::DisplayServerController,
+    instance_lock::InstanceLock,
+    state_journal::StateJournal,
+    systemd::SystemdNotifier,
+};
+use flexi_logger::{FileSpec, Logger, Naming};
+use std::{
+    collections::HashSet,
+    env,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::watch;
 
 use crate::{
-    armaf::spawn_server,
+    armaf::{
+        retry_with_backoff, spawn_server, spawn_supervised, EventBus, RestartPolicy,
+        ShutdownCoordinator, TickService,
+    },
     control::{
-        effector_inventory::{EffectorInventory, GetEffectorPort},
-        sleep_controller::SleepController,
+        config_watcher::ConfigWatcher,
+        displays::{parse_displays, DisplayTree},
+        effector_inventory::{self, EffectorHealthCheck, EffectorInventory, HEALTH_CHECK_PERIOD},
+        hotplug_controller::HotplugController,
+        idleness_broker::{parse_idleness_debounce, IdlenessBroker},
+        inhibitor_policy::parse_inhibitor_policy,
+        keepalive::{parse_keepalive_interval, Keepalive},
+        manager_state::StateReporter,
+        remote_control::{parse_triggerable_effects, EffectTrigger},
+        schedule_rules::{parse_rules, rules_need_facts},
+        signal_handler::SignalHandler,
+        sleep_controller::{parse_sleep_hooks, SleepController, SleepHooks},
+        wake_triggers::{parse_wake_triggers, WakeController},
     },
+    logging::{LogCleaner, LogFormat, LogRotation},
     system::{
-        inhibition_sensor::InhibitionSensor, sleep_sensor::SleepSensor, upower_sensor::UPowerSensor,
+        brightness_effector::BrightnessAdjuster,
+        clock_sensor::ClockSensor,
+        environment_sensor::{EnvironmentSensor, ENVIRONMENT_SAMPLING_PERIOD},
+        inhibition_sensor::{ApplicationInhibitions, GetInhibitions, InhibitionSensor},
+        output_sensor::spawn_output_sensor,
+        plugin_effector::register_plugins,
+        sleep_sensor::SleepSensor,
+        sysfs_power_sensor::{
+            SysfsPowerSensor, POWER_SUPPLY_DIRECTORY, POWER_SUPPLY_POLLING_PERIOD,
+        },
+        upower_sensor::{BatterySampler, PowerStatus, UPowerSensor},
     },
 };
 
@@ -37,119 +92,787 @@ struct Args {
     #[clap(long)]
     log_directory: Option<String>,
 
+    /// Size in MiB above which the log file is rotated
+    #[clap(long, default_value_t = 10)]
+    log_max_size: u64,
+
+    /// Number of rotated log files to keep
+    #[clap(long, default_value_t = 5)]
+    log_keep_files: usize,
+
+    /// Compress the rotated log files with gzip
+    #[clap(long)]
+    log_compress: bool,
+
+    /// Delete log files older than this many days from the log directory, 0 keeps them forever
+    #[clap(long, default_value_t = 30)]
+    log_max_age_days: u64,
+
+    /// Format of the log lines, json writes one JSON object with the actor, effect and schedule context per line
+    #[clap(long, arg_enum, default_value = "text")]
+    log_format: LogFormat,
+
     /// Path to the configuration file. Defaults to ~/.config/energia/config.toml
     #[clap(long, short)]
     config_file: Option<String>,
+
+    /// Directory with drop-in configuration fragments (*.toml), merged over the configuration file
+    #[clap(long)]
+    config_dir: Option<String>,
+
+    /// Don't load the system-wide defaults from /etc/energia/config.toml
+    #[clap(long)]
+    no_system_config: bool,
+
+    /// Also accept JSON commands on a Unix socket at this path, e.g. on systems without a session D-Bus
+    #[clap(long)]
+    control_socket: Option<String>,
+
+    /// Don't start the daemon, simulate the schedules on a virtual clock with mock effectors and print when each effect would be applied
+    #[cfg(feature = "simulation")]
+    #[clap(long)]
+    simulate: bool,
+
+    /// Run as a systemd user service, logging to stderr for the journal instead of into log files
+    #[clap(long, conflicts_with = "daemonize")]
+    systemd: bool,
+
+    /// Detach from the terminal and run in the background, writing the daemon's PID into the PID file
+    #[clap(long, overrides_with = "foreground")]
+    daemonize: bool,
+
+    /// Stay attached to the terminal (the default), overriding an earlier --daemonize
+    #[clap(long, overrides_with = "daemonize")]
+    foreground: bool,
+
+    /// PID file written with --daemonize. Defaults to $XDG_RUNTIME_DIR/energia-$DISPLAY.pid
+    #[clap(long, requires = "daemonize")]
+    pid_file: Option<PathBuf>,
+
+    /// Serve metrics in the OpenMetrics format at http://ADDRESS/metrics, e.g. 127.0.0.1:9464
+    #[clap(long)]
+    metrics_address: Option<SocketAddr>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the configuration file, print the resolved schedules and exit
+    Check,
+    /// Check that the X server, D-Bus, logind, UPower, the backlight and the locker are usable and exit
+    Doctor,
+    /// Lock the screen with the built-in locker until the user's password is entered
+    #[cfg(feature = "builtin-locker")]
+    Lock,
+    /// Print the daily totals of active and idle time, time spent in each effect and time suspended
+    Stats {
+        /// Number of most recent days to print
+        #[clap(long, default_value_t = 7)]
+        days: usize,
+    },
 }
 
 fn get_user_home() -> String {
     env::var("HOME").unwrap_or("".to_owned())
 }
 
+fn get_log_directory(args: &Args) -> PathBuf {
+    args.log_directory
+        .clone()
+        .unwrap_or_else(|| format!("{}/.config/energia/log", get_user_home()))
+        .into()
+}
+
 fn initialize_logging(args: &Args) -> anyhow::Result<flexi_logger::LoggerHandle> {
-    let default_dir = format!("{}/.config/energia/log", get_user_home());
-    let log_dir = args.log_directory.as_ref().unwrap_or(&default_dir);
+    if args.systemd {
+        return Ok(Logger::try_with_str(&args.log_level)?
+            .log_to_stderr()
+            .format(args.log_format.journal_format_function())
+            .start()?);
+    }
+    let rotation = LogRotation {
+        max_size: args.log_max_size * 1024 * 1024,
+        keep_files: args.log_keep_files,
+        compress: args.log_compress,
+    };
     Ok(Logger::try_with_str(&args.log_level)?
-        .log_to_file(FileSpec::default().directory(log_dir).basename("energia"))
-        .format(flexi_logger::opt_format)
+        .log_to_file(
+            FileSpec::default()
+                .directory(get_log_directory(args))
+                .basename(logging::LOG_BASENAME),
+        )
+        .rotate(rotation.criterion(), Naming::Numbers, rotation.cleanup())
+        .format(args.log_format.format_function())
         .print_message()
         .duplicate_to_stderr(flexi_logger::Duplicate::Debug)
         .start()?)
 }
 
-async fn parse_config(args: &Args) -> anyhow::Result<toml::Value> {
-    let default_path = format!("{}/.config/energia/config.toml", get_user_home());
-    let config_path = args.config_file.as_ref().unwrap_or(&default_path);
-    Ok(toml::from_slice(&fs::read(config_path).await?)?)
+fn get_config_sources(args: &Args) -> ConfigSources {
+    let user_file = args
+        .config_file
+        .clone()
+        .unwrap_or_else(|| format!("{}/.config/energia/config.toml", get_user_home()));
+    ConfigSources {
+        system_file: if args.no_system_config {
+            None
+        } else {
+            Some(PathBuf::from(config::SYSTEM_CONFIG_PATH))
+        },
+        user_file: PathBuf::from(user_file),
+        user_file_required: args.config_file.is_some(),
+        drop_in_dir: args.config_dir.as_ref().map(PathBuf::from),
+    }
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let args = Args::parse();
+    #[cfg(feature = "builtin-locker")]
+    if let Some(Command::Lock) = args.command {
+        std::process::exit(system::builtin_locker::run());
+    }
+    #[cfg(feature = "simulation")]
+    if args.simulate {
+        // The simulation needs its own runtime with a paused clock
+        std::process::exit(simulation::run(&get_config_sources(&args)));
+    }
+    // Forking is only safe before the runtime's threads are started
+    let daemon = if args.daemonize && args.command.is_none() {
+        let path = args
+            .pid_file
+            .clone()
+            .unwrap_or_else(|| PidFile::default_path(&env::var("DISPLAY").unwrap_or_default()));
+        match daemon::daemonize(&path) {
+            Ok(daemon) => Some(daemon),
+            Err(e) => {
+                eprintln!("Couldn't daemonize: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    // The environment mustn't be changed once the runtime's threads are running
+    let systemd_notifier = SystemdNotifier::from_env();
+    run(args, systemd_notifier, daemon);
+}
+
+#[tokio::main]
+async fn run(args: Args, systemd_notifier: Option<SystemdNotifier>, mut daemon: Option<Daemon>) {
+    if let Some(Command::Check) = args.command {
+        std::process::exit(check::run(&get_config_sources(&args)).await);
+    }
+    if let Some(Command::Doctor) = args.command {
+        std::process::exit(doctor::run(&get_config_sources(&args)).await);
+    }
+    if let Some(Command::Stats { days }) = args.command {
+        std::process::exit(statistics::print_report(
+            &StatisticsStore::default_path(),
+            days,
+        ));
+    }
+
     let log_handle = initialize_logging(&args);
     if let Err(e) = log_handle.as_ref() {
         println!("Failed to initialize logging system: {}", e);
     }
     log_panics::init();
+    crash::set_log_directory(get_log_directory(&args));
+    crash::install_panic_hook();
+    armaf::set_give_up_hook(crash::report_supervisor_give_up);
 
-    let config = parse_config(&args)
-        .await
-        .expect("Couldn't read configuration");
+    // Must be held before the display's state journal is opened, so that the
+    // changes of another instance on the same display aren't restored
+    let display = env::var("DISPLAY").unwrap_or_default();
+    let instance_lock = match InstanceLock::acquire(&display) {
+        Ok(lock) => lock,
+        Err(e) => {
+            log::error!("{:#}", e);
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let config_sources = get_config_sources(&args);
+    let config = Arc::new(
+        config_sources
+            .load()
+            .await
+            .expect("Couldn't read configuration"),
+    );
     log::info!("Parsed config is: {:?}", config);
+    crash::set_config(&config);
 
-    let mut system_dependencies = DependencyProvider::make_system()
+    for plugin in register_plugins(&config).expect("Couldn't register plugins") {
+        log::info!("Registered plugin {}", plugin);
+    }
+    let backends = parse_backends(&config).expect("Invalid backends configuration");
+    let startup_retry_policy = startup_retry_policy();
+    let mut attempts = 0;
+    let system_dependencies =
+        retry_with_backoff("Display server or logind", &startup_retry_policy, || {
+            attempts += 1;
+            // The last attempt continues without logind
+            let wait_for_logind = attempts <= startup_retry_policy.max_restarts;
+            DependencyProvider::make_system(&backends, None, wait_for_logind)
+        })
         .await
         .expect("Couldn't construct dependency provider");
+    let state_journal = open_state_journal(&system_dependencies, &display).await;
+    let mut system_dependencies = system_dependencies.with_state_journal(state_journal.clone());
 
     let ds_controller = system_dependencies.get_display_controller();
-    let idleness_channel = system_dependencies.get_idleness_channel();
+    let idleness_debounce = parse_idleness_debounce(&config).unwrap_or_else(|e| {
+        log::error!("{:#}, idleness changes won't be debounced", e);
+        Duration::ZERO
+    });
+    let idleness_broker = IdlenessBroker::new(system_dependencies.get_idleness_channel())
+        .with_display_controller(ds_controller.clone())
+        .with_debounce(idleness_debounce);
+    let idleness_channel = idleness_broker.subscribe();
+    let activity_reporter = idleness_broker.activity_reporter();
+    let idleness_broker_handle = idleness_broker.spawn();
     let dbus_connection = system_dependencies
         .get_dbus_system_connection()
         .await
         .expect("Couldn't get connection to system D-Bus");
 
-    let inhibition_sensor = spawn_server(InhibitionSensor::new(dbus_connection.clone()))
-        .await
-        .expect("Couldn't start inhibition sensor");
+    let application_inhibitions = ApplicationInhibitions::new();
+    let inhibition_sensor = retry_with_backoff("InhibitionSensor", &startup_retry_policy, || {
+        spawn_supervised("InhibitionSensor", RestartPolicy::default(), {
+            let dbus_connection = dbus_connection.clone();
+            let application_inhibitions = application_inhibitions.clone();
+            move || {
+                spawn_server(
+                    InhibitionSensor::new(dbus_connection.clone())
+                        .with_application_inhibitions(application_inhibitions.clone()),
+                )
+            }
+        })
+    })
+    .await
+    .expect("Couldn't start inhibition sensor");
 
-    let upower_channel = UPowerSensor::new(dbus_connection.clone())
-        .await
-        .expect("Couldn't start UPower sensor");
+    let tick_service = TickService::new();
+    let upower_channel = match retry_with_backoff("UPower", &startup_retry_policy, || {
+        UPowerSensor::new(dbus_connection.clone())
+    })
+    .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            log::warn!(
+                "Couldn't start UPower sensor, reading {} instead: {}",
+                POWER_SUPPLY_DIRECTORY,
+                e
+            );
+            SysfsPowerSensor::spawn(
+                Path::new(POWER_SUPPLY_DIRECTORY),
+                &tick_service,
+                POWER_SUPPLY_POLLING_PERIOD,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("{:#}, the power source won't be detected", e);
+                // The dropped sender tells the receivers that there's no sensor
+                watch::channel(PowerStatus::External).1
+            })
+        }
+    };
 
-    let sleep_sensor = SleepSensor::new(dbus_connection);
-    let (sleep_sensor_handle, sleep_sensor_channel) = sleep_sensor
-        .spawn()
-        .await
-        .expect("Sleep sensor failed to start");
+    let events = EventBus::new(32);
+    let sleep_sensor_handle = retry_with_backoff("SleepSensor", &startup_retry_policy, || {
+        SleepSensor::new(dbus_connection.clone()).spawn(&events)
+    })
+    .await
+    .expect("Sleep sensor failed to start");
 
+    let brightness_adjuster = BrightnessAdjuster::new(
+        system_dependencies.get_brightness_controller(),
+        system_dependencies.get_dim_state(),
+    )
+    .with_state_journal(state_journal.clone());
     let effector_inventory =
         spawn_server(EffectorInventory::new(config.clone(), system_dependencies))
             .await
             .expect("Couldn't spawn EffectorInventory");
+    if effector_inventory::parse_eager_spawning(&config).expect("Invalid effectors configuration") {
+        if let Err(e) =
+            effector_inventory::spawn_scheduled_effectors(&effector_inventory, &config).await
+        {
+            log::error!("{:#}", e);
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        log::info!("All scheduled effectors have been spawned");
+    }
 
-    let environment_controller = EnvironmentController::new(
-        &config,
+    let clock_sensor_handle = ClockSensor::new(&events, &tick_service).spawn();
+    let connected_outputs = match spawn_output_sensor(None) {
+        Ok(outputs) => Some(outputs),
+        Err(e) => {
+            log::error!("{:#}, monitor hotplug won't be detected", e);
+            None
+        }
+    };
+    let rules = parse_rules(&config).expect("Invalid rules configuration");
+    let environment_facts = if rules_need_facts(&rules) {
+        EnvironmentSensor::spawn(
+            dbus_connection.clone(),
+            &tick_service,
+            ENVIRONMENT_SAMPLING_PERIOD,
+            connected_outputs.clone(),
+        )
+        .await
+        .map_err(|e| log::error!("{:#}, rules can't depend on docking or temperature", e))
+        .ok()
+    } else {
+        None
+    };
+
+    let (state_reporter, state_receiver) = StateReporter::new();
+    let event_history = EventHistory::new(DEFAULT_HISTORY_SIZE);
+    let (idleness_port_sender, idleness_ports) = watch::channel(None);
+    let mut environment_controller = EnvironmentController::new(
+        config.clone(),
         effector_inventory.clone(),
-        inhibition_sensor,
+        inhibition_sensor.clone(),
         ds_controller.clone(),
-        idleness_channel,
-        upower_channel,
-    );
+        idleness_channel.clone(),
+        upower_channel.clone(),
+    )
+    .with_state_reporter(state_reporter)
+    .with_effect_events(events.sender())
+    .with_sleep_updates(events.sender())
+    .with_clock_jumps(events.subscribe())
+    .with_event_history(event_history.clone())
+    .with_idleness_ports(idleness_port_sender)
+    .with_state_journal(state_journal.clone());
+    if let Some(environment_facts) = environment_facts {
+        environment_controller = environment_controller.with_environment_facts(environment_facts);
+    }
 
-    let environment_controller_handle = environment_controller
+    let environment_controller_port = environment_controller
         .spawn()
         .await
         .expect("Couldn't spawn environment controller");
 
-    let lock_effector = effector_inventory
-        .request(GetEffectorPort("lock".to_string()))
+    let mut display_trees = Vec::new();
+    for display in parse_displays(&config).expect("Invalid displays configuration") {
+        let tree =
+            match DependencyProvider::make_system(&backends, Some(&display.display), false).await {
+                Ok(dependencies) => {
+                    DisplayTree::spawn(
+                        &display,
+                        dependencies,
+                        inhibition_sensor.clone(),
+                        upower_channel.clone(),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+        match tree {
+            Ok(tree) => {
+                log::info!("Managing display {} ({})", display.name, display.display);
+                display_trees.push(tree);
+            }
+            Err(e) => log::error!(
+                "Couldn't start managing display {} ({}): {:#}",
+                display.name,
+                display.display,
+                e
+            ),
+        }
+    }
+
+    let mut signal_handler = match SignalHandler::new(
+        config_sources.clone(),
+        environment_controller_port.clone(),
+        state_receiver.clone(),
+    ) {
+        Ok(handler) => Some(handler),
+        Err(e) => {
+            log::error!("{:#}, only Ctrl-C will shut Energia down gracefully", e);
+            None
+        }
+    };
+
+    let config_watcher_handle =
+        match ConfigWatcher::new(config_sources, environment_controller_port.clone()).spawn() {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                log::error!(
+                    "Couldn't watch configuration file, it will not be reloaded on change: {}",
+                    e
+                );
+                None
+            }
+        };
+
+    let wake_controller_handle = match parse_wake_triggers(&config) {
+        Ok(Some(triggers)) => {
+            Some(WakeController::new(triggers, environment_controller_port.clone()).spawn())
+        }
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("{:#}, nothing will wake the screen", e);
+            None
+        }
+    };
+
+    let triggerable_effects = parse_triggerable_effects(&config).unwrap_or_else(|e| {
+        log::error!("{:#}, no effects can be triggered remotely", e);
+        HashSet::new()
+    });
+
+    let lock_effector = effector_inventory::get_effector_port(&effector_inventory, "lock")
         .await
-        .map(Some)
-        .unwrap_or(None);
+        .ok();
+
+    let battery_samples = match BatterySampler::spawn(
+        dbus_connection.clone(),
+        &tick_service,
+        Duration::from_secs(30),
+    )
+    .await
+    {
+        Ok(samples) => Some(samples),
+        Err(e) => {
+            log::error!("{:#}, battery power draw won't be measured", e);
+            None
+        }
+    };
 
-    let dbus_controller_handle = DBusController::new(
+    let mut dbus_controller = DBusController::new(
         "/org/energia/Manager",
         "org.energia.Manager",
-        lock_effector.clone(),
+        None,
+        state_receiver.clone(),
     )
-    .spawn()
-    .await
-    .expect("Failed to start D-Bus controller");
+    .with_effector_inventory(effector_inventory.clone())
+    .with_power_status(upower_channel.clone())
+    .with_environment_controller(environment_controller_port.clone())
+    .with_idleness_ports(idleness_ports)
+    .with_effect_events(events.subscribe())
+    .with_event_history(event_history.clone())
+    .with_effect_trigger(EffectTrigger::new(
+        effector_inventory.clone(),
+        triggerable_effects.clone(),
+    ))
+    .with_brightness(brightness_adjuster)
+    .with_power_management_inhibit(application_inhibitions.clone())
+    .with_screensaver(
+        application_inhibitions.clone(),
+        Arc::new(move || activity_reporter.report()),
+    );
+    if let Some(samples) = battery_samples.as_ref() {
+        dbus_controller = dbus_controller.with_battery_samples(samples.clone());
+    }
+    let dbus_controller_handle = dbus_controller.spawn().await;
+    let dbus_controller_handle = match dbus_controller_handle {
+        Ok(handle) => Some(handle),
+        Err(e) if args.control_socket.is_some() => {
+            log::error!(
+                "Couldn't start D-Bus controller, only the control socket will be available: {}",
+                e
+            );
+            None
+        }
+        Err(e) => panic!("Failed to start D-Bus controller: {}", e),
+    };
+
+    let socket_controller_handle = match args.control_socket.as_ref() {
+        Some(path) => Some(
+            SocketController::new(
+                &PathBuf::from(path),
+                lock_effector.clone(),
+                state_receiver.clone(),
+            )
+            .with_environment_controller(environment_controller_port.clone())
+            .with_effect_trigger(EffectTrigger::new(
+                effector_inventory.clone(),
+                triggerable_effects,
+            ))
+            .with_application_inhibitions(application_inhibitions)
+            .spawn()
+            .await
+            .expect("Failed to start control socket"),
+        ),
+        None => None,
+    };
+
+    let effector_health_check_handle = EffectorHealthCheck::new(
+        effector_inventory.clone(),
+        &tick_service,
+        HEALTH_CHECK_PERIOD,
+    )
+    .spawn();
+    let hotplug_controller_handle = connected_outputs.map(|outputs| {
+        HotplugController::new(outputs, effector_inventory.clone(), state_receiver.clone()).spawn()
+    });
+    let keepalive_handle = match parse_keepalive_interval(&config) {
+        Ok(Some(interval)) => Some(
+            Keepalive::new(
+                ds_controller.clone(),
+                &tick_service,
+                interval,
+                state_receiver.clone(),
+                inhibition_sensor.clone(),
+            )
+            .with_inhibitor_policy(parse_inhibitor_policy(&config).unwrap_or_default())
+            .spawn(),
+        ),
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("{:#}, the display server won't be kept active", e);
+            None
+        }
+    };
 
-    let sleep_controller_handle = SleepController::new(
-        sleep_sensor_channel.subscribe(),
-        lock_effector,
-        ds_controller,
+    let sleep_hooks = parse_sleep_hooks(&config).unwrap_or_else(|e| {
+        log::error!("{:#}, the computer will only be locked before sleep", e);
+        SleepHooks::default()
+    });
+    let sleep_controller_handle =
+        SleepController::new(events.subscribe(), lock_effector, ds_controller)
+            .with_hooks(sleep_hooks, effector_inventory.clone())
+            .with_environment_controller(environment_controller_port.clone())
+            .spawn()
+            .await;
+
+    let metrics_exporter_handle = match args.metrics_address {
+        Some(address) => MetricsExporter::new(address)
+            .with_idleness_channel(idleness_channel.clone())
+            .with_effect_events(events.subscribe())
+            .with_power_status(upower_channel)
+            .with_state(state_receiver.clone())
+            .with_mailbox("EnvironmentController", environment_controller_port.clone())
+            .with_mailbox("EffectorInventory", effector_inventory.clone())
+            .with_mailbox("InhibitionSensor", inhibition_sensor.clone())
+            .spawn()
+            .await
+            .map_err(|e| log::error!("{:#}, metrics won't be available", e))
+            .ok(),
+        None => None,
+    };
+
+    let history_recorder_handle = HistoryRecorder::new(
+        event_history,
+        idleness_channel.clone(),
+        events.subscribe(),
+        state_receiver.clone(),
     )
-    .spawn()
-    .await;
+    .spawn();
+
+    let statistics_handle = match StatisticsStore::open(&StatisticsStore::default_path()) {
+        Ok(store) => {
+            let mut collector = StatisticsCollector::new(
+                store,
+                idleness_channel,
+                events.subscribe(),
+                &tick_service,
+            )
+            .with_sleep_updates(events.subscribe())
+            .with_clock_jumps(events.subscribe());
+            if let Some(samples) = battery_samples {
+                collector = collector.with_battery_samples(samples);
+            }
+            Some(collector.spawn())
+        }
+        Err(e) => {
+            log::error!("{:#}, statistics won't be collected", e);
+            None
+        }
+    };
+
+    let log_cleaner_handle = match args.log_max_age_days {
+        _ if args.systemd => None,
+        0 => None,
+        days => Some(
+            LogCleaner::new(
+                get_log_directory(&args),
+                Duration::from_secs(days * 24 * 60 * 60),
+                &tick_service,
+            )
+            .spawn(),
+        ),
+    };
 
-    tokio::signal::ctrl_c().await.expect("Signal wait failed");
-    environment_controller_handle.await_shutdown().await;
-    sleep_controller_handle.await_shutdown().await;
-    sleep_sensor_handle.await_shutdown().await;
-    dbus_controller_handle.await_shutdown().await;
-    effector_inventory.await_shutdown().await;
+    if let Some(daemon) = daemon.as_mut() {
+        daemon.report_started();
+    }
+    let watchdog_handle = systemd_notifier.as_ref().and_then(|notifier| {
+        if let Err(e) = notifier.ready() {
+            log::error!("Couldn't notify systemd about finished startup: {:?}", e);
+        }
+        notifier.watchdog_timeout().map(|timeout| {
+            Watchdog::new(notifier.clone(), timeout, &tick_service)
+                .with_port_check(
+                    "EnvironmentController",
+                    environment_controller_port.clone(),
+                    EnvironmentCommand::Ping,
+                )
+                .with_port_check("InhibitionSensor", inhibition_sensor, GetInhibitions)
+                .spawn()
+        })
+    });
+
+    match signal_handler.as_mut() {
+        Some(handler) => handler.wait_for_termination().await,
+        None => tokio::signal::ctrl_c().await.expect("Signal wait failed"),
+    }
+    if let Some(notifier) = systemd_notifier.as_ref() {
+        if let Err(e) = notifier.stopping() {
+            log::error!("Couldn't notify systemd about shutdown: {:?}", e);
+        }
+    }
+    let mut shutdown = ShutdownCoordinator::new(Duration::from_secs(5));
+    // Rolling effects back may run external commands
+    shutdown.add_with_timeout(
+        "EffectorInventory",
+        &[],
+        Duration::from_secs(15),
+        effector_inventory.await_shutdown(),
+    );
+    shutdown.add("SleepSensor", &[], sleep_sensor_handle.await_shutdown());
+    shutdown.add("ClockSensor", &[], clock_sensor_handle.await_shutdown());
+    shutdown.add(
+        "IdlenessBroker",
+        &[],
+        idleness_broker_handle.await_shutdown(),
+    );
+    for tree in display_trees {
+        tree.add_to_shutdown(&mut shutdown);
+    }
+    shutdown.add(
+        "EffectorHealthCheck",
+        &["EffectorInventory"],
+        effector_health_check_handle.await_shutdown(),
+    );
+    shutdown.add(
+        "EnvironmentController",
+        &["EffectorInventory"],
+        environment_controller_port.await_shutdown(),
+    );
+    if let Some(handle) = config_watcher_handle {
+        shutdown.add(
+            "ConfigWatcher",
+            &["EnvironmentController"],
+            handle.await_shutdown(),
+        );
+    }
+    if let Some(handle) = wake_controller_handle {
+        shutdown.add(
+            "WakeController",
+            &["EnvironmentController"],
+            handle.await_shutdown(),
+        );
+    }
+    if let Some(handle) = dbus_controller_handle {
+        shutdown.add(
+            "DBusController",
+            &["EnvironmentController", "EffectorInventory"],
+            handle.await_shutdown(),
+        );
+    }
+    if let Some(handle) = socket_controller_handle {
+        shutdown.add(
+            "SocketController",
+            &["EnvironmentController", "EffectorInventory"],
+            handle.await_shutdown(),
+        );
+    }
+    if let Some(handle) = watchdog_handle {
+        shutdown.add("Watchdog", &[], handle.await_shutdown());
+    }
+    if let Some(handle) = keepalive_handle {
+        shutdown.add("Keepalive", &[], handle.await_shutdown());
+    }
+    if let Some(handle) = hotplug_controller_handle {
+        shutdown.add(
+            "HotplugController",
+            &["EffectorInventory"],
+            handle.await_shutdown(),
+        );
+    }
+    shutdown.add(
+        "HistoryRecorder",
+        &[],
+        history_recorder_handle.await_shutdown(),
+    );
+    if let Some(handle) = statistics_handle {
+        shutdown.add("StatisticsCollector", &[], handle.await_shutdown());
+    }
+    if let Some(handle) = log_cleaner_handle {
+        shutdown.add("LogCleaner", &[], handle.await_shutdown());
+    }
+    if let Some(handle) = metrics_exporter_handle {
+        shutdown.add("MetricsExporter", &[], handle.await_shutdown());
+    }
+    shutdown.add(
+        "SleepController",
+        &["SleepSensor", "EffectorInventory", "EnvironmentController"],
+        sleep_controller_handle.await_shutdown(),
+    );
+    if !shutdown.shutdown().await {
+        // Tasks stuck e.g. in blocking calls would keep the runtime from
+        // shutting down
+        log::error!("Not all parts of Energia have terminated, exiting forcibly");
+        if let Ok(handle) = log_handle.as_ref() {
+            handle.flush();
+        }
+        drop(daemon);
+        std::process::exit(1);
+    }
+    // All the changes have been undone by the actors' tear downs
+    state_journal.remove();
+    drop(instance_lock);
+    drop(daemon);
+}
 
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+/// How the services Energia depends on are waited for when it starts, e.g.
+/// when it's autostarted early in the session, about 8 seconds in total
+fn startup_retry_policy() -> RestartPolicy {
+    RestartPolicy {
+        max_restarts: 4,
+        initial_backoff: Duration::from_millis(500),
+        max_backoff: Duration::from_secs(5),
+        ..RestartPolicy::default()
+    }
+}
+
+/// Open the state journal, restoring the settings left changed by a previous
+/// run which didn't terminate cleanly. Must be called before any actor
+/// records the current settings as the original ones.
+async fn open_state_journal(dependencies: &DependencyProvider, display: &str) -> StateJournal {
+    let path = StateJournal::default_path(display);
+    let (journal, leftover) = match StateJournal::open(&path) {
+        Ok(opened) => opened,
+        Err(e) => {
+            log::error!(
+                "{:#}, changes to system settings won't be restored after a crash",
+                e
+            );
+            return StateJournal::in_memory();
+        }
+    };
+    if !leftover.is_clean() {
+        log::warn!(
+            "Previous run didn't terminate cleanly, restoring system settings from {:?}",
+            leftover
+        );
+        if let Err(e) = leftover
+            .restore(
+                &dependencies.get_brightness_controller(),
+                &dependencies.get_display_controller(),
+            )
+            .await
+        {
+            log::error!("Couldn't restore system settings: {:?}", e);
+        }
+    }
+    journal
 }
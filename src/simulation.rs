@@ -0,0 +1,265 @@
+//! Simulation of the configured schedules on a virtual clock
+//!
+//! The whole actor tree is started with mock dependencies and effectors which
+//! only record the effects they would apply. Time is paused and automatically
+//! advanced whenever all the actors are waiting for a timer, so a schedule
+//! spanning hours is simulated in an instant.
+
+use crate::{
+    armaf::{spawn_server, Server},
+    config::ConfigSources,
+    control::{
+        effector_inventory::EffectorInventory,
+        environment_controller::{
+            effective_schedule_type, format_duration, has_builtin_schedule, has_day_variants,
+            parse_fallbacks, parse_grace_period, parse_low_battery_threshold, parse_schedules,
+            schedule_types, schedules_for_day, DayType, EnvironmentController, ScheduleType,
+        },
+        schedule_rules::RULES_SECTION,
+    },
+    external::{
+        dependency_provider::DependencyProvider,
+        display_server::{DisplayServer, DisplayServerController, SystemState},
+    },
+    system::{
+        inhibition_sensor::{ActiveInhibitor, GetInhibitions},
+        plugin_effector::register_plugins,
+        simulated_effector::{SimulatedAction, SimulatedEvent},
+        upower_sensor::PowerStatus,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{sleep, Instant},
+};
+
+/// Time given to the actors to react to a change of the environment
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Simulate the configuration merged from the given sources, print the
+/// timeline of the effects for each power source and return the exit code with
+/// which the process should end
+pub fn run(sources: &ConfigSources) -> i32 {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            println!("error: Couldn't start the simulation runtime: {}", e);
+            return 1;
+        }
+    };
+    match runtime.block_on(simulate(sources)) {
+        Ok(timeline) => {
+            for line in timeline {
+                println!("{}", line);
+            }
+            0
+        }
+        Err(e) => {
+            println!("error: {:?}", e);
+            1
+        }
+    }
+}
+
+async fn simulate(sources: &ConfigSources) -> Result<Vec<String>> {
+    let config = sources.load().await?;
+    register_plugins(&config)?;
+    simulate_config(config).await
+}
+
+/// Simulate an already parsed configuration, returning the lines of the
+/// timeline
+async fn simulate_config(config: toml::Value) -> Result<Vec<String>> {
+    let mut timeline = Vec::new();
+    // The simulated power sources choose the built-in schedules directly
+    let mut config = config;
+    if let Some(table) = config.as_table_mut() {
+        table.remove(RULES_SECTION);
+    }
+    let config = Arc::new(if has_day_variants(&config) {
+        let (day_type, _) = DayType::today();
+        timeline.push(format!(
+            "Using the schedules for {}",
+            day_type.config_name()
+        ));
+        schedules_for_day(&config, day_type)
+    } else {
+        config
+    });
+    let schedules = parse_schedules(&config)?;
+    if !has_builtin_schedule(&schedules) {
+        return Err(anyhow!(
+            "No schedule defined. Define either schedule.external or schedule.battery."
+        ));
+    }
+    let low_battery_threshold = parse_low_battery_threshold(&config).ok();
+    let fallbacks = parse_fallbacks(&config)?;
+
+    let (dependencies, display_server) = DependencyProvider::make_mock_with_display_server(None);
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let effector_inventory = spawn_server(
+        EffectorInventory::new(config.clone(), dependencies).with_simulation(events_sender),
+    )
+    .await?;
+    let inhibition_sensor = spawn_server(NoInhibitions).await?;
+    let (power_status_sender, power_status_receiver) = watch::channel(PowerStatus::External);
+    let environment_controller_port = EnvironmentController::new(
+        config.clone(),
+        effector_inventory.clone(),
+        inhibition_sensor,
+        display_server.get_controller(),
+        display_server.get_idleness_channel(),
+        power_status_receiver,
+    )
+    .spawn()
+    .await
+    .context("Couldn't start the environment controller")?;
+
+    for typ in ScheduleType::ALL {
+        let power_status = match (&typ, low_battery_threshold) {
+            (ScheduleType::ExternalPower, _) => PowerStatus::External,
+            (ScheduleType::Battery, Some(threshold)) => PowerStatus::Battery(threshold + 1),
+            (ScheduleType::Battery, None) => PowerStatus::Battery(100),
+            (ScheduleType::LowBattery, Some(threshold)) => PowerStatus::Battery(threshold),
+            (ScheduleType::LowBattery, None) => {
+                timeline.push("Power source low_battery: never used, battery.low_battery_percentage is not set".to_owned());
+                continue;
+            }
+            _ => unreachable!("{:?} is not a built-in schedule", typ),
+        };
+        let effective_type = effective_schedule_type(typ.clone(), &schedules, &fallbacks);
+        if effective_type == typ {
+            timeline.push(format!("Power source {}:", typ.config_name()));
+        } else {
+            timeline.push(format!(
+                "Power source {} (using schedule {}):",
+                typ.config_name(),
+                effective_type.config_name()
+            ));
+        }
+        power_status_sender.send(power_status)?;
+        sleep(SETTLE_TIME).await;
+        drain(&mut events);
+
+        let schedule_config = &config["schedule"][effective_type.config_name()];
+        let schedule_length = schedules[&effective_type]
+            .values()
+            .max()
+            .cloned()
+            .unwrap_or(Duration::ZERO)
+            + parse_grace_period(schedule_config)?;
+
+        // The display server's idleness detection is simulated by waiting
+        // for the timeout the sequencer set on it
+        let idleness_start = Instant::now();
+        let ds_timeout = display_server
+            .get_controller()
+            .get_idleness_timeout()
+            .await?;
+        sleep(Duration::from_secs(ds_timeout.max(0) as u64)).await;
+        display_server.notify_state_transition(SystemState::Idle)?;
+        sleep(schedule_length + SETTLE_TIME).await;
+        record_events(&mut events, idleness_start, &mut timeline);
+
+        timeline.push("  on activity:".to_owned());
+        display_server.notify_state_transition(SystemState::Awakened)?;
+        let activity_start = Instant::now();
+        sleep(SETTLE_TIME).await;
+        record_events(&mut events, activity_start, &mut timeline);
+    }
+
+    for typ in schedule_types(&schedules) {
+        if !typ.is_builtin() {
+            timeline.push(format!(
+                "Schedule {}: only chosen by rules, not simulated",
+                typ.config_name()
+            ));
+        }
+    }
+
+    environment_controller_port.await_shutdown().await;
+    effector_inventory.await_shutdown().await;
+    Ok(timeline)
+}
+
+fn drain(events: &mut mpsc::UnboundedReceiver<SimulatedEvent>) {
+    while events.try_recv().is_ok() {}
+}
+
+fn record_events(
+    events: &mut mpsc::UnboundedReceiver<SimulatedEvent>,
+    since: Instant,
+    timeline: &mut Vec<String>,
+) {
+    while let Ok(event) = events.try_recv() {
+        let action = match event.action {
+            SimulatedAction::Executed => "applied",
+            SimulatedAction::RolledBack => "rolled back",
+        };
+        timeline.push(format!(
+            "  {:>12}  {} {}",
+            format_duration(event.at.saturating_duration_since(since)),
+            event.effect_name,
+            action
+        ));
+    }
+}
+
+/// Inhibition sensor reporting that nothing inhibits idleness
+struct NoInhibitions;
+
+#[async_trait]
+impl Server<GetInhibitions, Vec<ActiveInhibitor>> for NoInhibitions {
+    fn get_name(&self) -> String {
+        "NoInhibitions".to_owned()
+    }
+
+    async fn handle_message(&mut self, _: GetInhibitions) -> Result<Vec<ActiveInhibitor>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_simulation() {
+        let config = toml::toml! {
+            [schedule.external]
+            screen_dim = "1m"
+            screen_off = "2m 30s"
+
+            [schedule.battery]
+            grace = "5s"
+            screen_dim = "30s"
+
+            [battery]
+            low_battery_percentage = 10
+        };
+        let timeline = simulate_config(config).await.unwrap();
+        let trimmed: Vec<&str> = timeline.iter().map(|line| line.trim()).collect();
+        assert_eq!(trimmed[0], "Power source external:");
+        assert!(trimmed.contains(&"1m  screen_dim applied"));
+        assert!(trimmed.contains(&"1m  idle_hint applied"));
+        assert!(trimmed.contains(&"2m 30s  screen_off applied"));
+        assert!(trimmed.contains(&"0s  screen_off rolled back"));
+        // Grace period delays the battery schedule
+        assert!(trimmed.contains(&"35s  screen_dim applied"));
+        assert!(trimmed.contains(&"Power source low_battery (using schedule battery):"));
+        assert_eq!(
+            trimmed
+                .iter()
+                .filter(|line| **line == "0s  screen_dim rolled back")
+                .count(),
+            3
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Covers the computer's screens with black, for hardware and virtual machines
+//! whose screens can't be turned off
+
+use crate::{
+    armaf::{
+        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, EffectorResponse,
+        RollbackStrategy, Server,
+    },
+    external::{
+        dependency_provider::DependencyProvider, display_server as ds,
+        display_server::DisplayServerController,
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use logind_zbus::manager::InhibitType;
+
+pub struct BlankEffector;
+
+#[async_trait]
+impl Effector for BlankEffector {
+    fn get_name(&self) -> String {
+        "blank".to_owned()
+    }
+
+    fn get_effects(&self) -> Vec<Effect> {
+        vec![Effect::new(
+            "screen_blank".to_owned(),
+            vec![InhibitType::Idle],
+            RollbackStrategy::OnActivity,
+        )]
+    }
+
+    async fn spawn(
+        &self,
+        _: Option<toml::Value>,
+        provider: &mut DependencyProvider,
+    ) -> Result<EffectorPort> {
+        spawn_server(BlankEffectorActor::new(provider.get_display_controller())).await
+    }
+}
+
+pub struct BlankEffectorActor<D: ds::DisplayServerController> {
+    blanked: bool,
+    ds_controller: D,
+}
+
+impl<D: ds::DisplayServerController> BlankEffectorActor<D> {
+    pub fn new(ds_controller: D) -> BlankEffectorActor<D> {
+        BlankEffectorActor {
+            blanked: false,
+            ds_controller,
+        }
+    }
+
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()> {
+        self.ds_controller.set_screen_blanked(blanked).await
+    }
+}
+
+#[async_trait]
+impl<D: ds::DisplayServerController> Server<EffectorMessage, EffectorResponse>
+    for BlankEffectorActor<D>
+{
+    fn get_name(&self) -> String {
+        "BlankEffector".to_owned()
+    }
+
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        match payload {
+            // Blanking again covers the windows and monitors that appeared
+            // since the screen was blanked
+            EffectorMessage::Execute | EffectorMessage::EnsureApplied => {
+                self.set_screen_blanked(true).await?;
+                self.blanked = true;
+            }
+            EffectorMessage::Rollback => {
+                self.set_screen_blanked(false).await?;
+                self.blanked = false;
+            }
+            EffectorMessage::EnsureRolledBack => {
+                if self.blanked {
+                    self.set_screen_blanked(false).await?;
+                }
+                self.blanked = false;
+            }
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {}
+        }
+        Ok(if self.blanked {
+            EffectorResponse::new(1).with_status("display blanked")
+        } else {
+            EffectorResponse::new(0)
+        })
+    }
+
+    async fn tear_down(&mut self) -> Result<()> {
+        if self.blanked {
+            self.set_screen_blanked(false).await?;
+        }
+        Ok(())
+    }
+}
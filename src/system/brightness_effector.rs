@@ -1,12 +1,15 @@
-//! Dims and undims the computer's screen
+//! Dims and undims the computer's screen and adjusts its brightness on the
+//! user's request
 
 use crate::{
     armaf::{
-        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, RollbackStrategy, Server,
+        spawn_server, ConfigSchema, Effect, Effector, EffectorMessage, EffectorPort,
+        EffectorResponse, RollbackStrategy, Server, ValueType,
     },
     external::{
-        brightness::BrightnessController, dependency_provider::DependencyProvider,
-        display_server as ds,
+        brightness::{BrightnessController, DimState},
+        dependency_provider::DependencyProvider,
+        state_journal::StateJournal,
     },
 };
 use anyhow::{anyhow, bail, Result};
@@ -15,8 +18,32 @@ use logind_zbus::manager::InhibitType;
 
 pub struct BrightnessEffector;
 
+impl BrightnessEffector {
+    /// Parse the `[brightness]` configuration section into the fraction of
+    /// the original brightness to which the screen should be dimmed
+    pub fn parse_config(config: Option<&toml::Value>) -> Result<f64> {
+        let some_config = match config {
+            Some(some_config) => some_config,
+            None => return Ok(0.5),
+        };
+        if let Some(toml::value::Value::Integer(dim_percentage)) = some_config.get("dim_percentage")
+        {
+            if !(0..=100).contains(dim_percentage) {
+                bail!("dim_percentage must be between 0 and 100");
+            }
+            Ok(*dim_percentage as f64 / 100f64)
+        } else {
+            bail!("Couldn't find dim_percentage in brightness config or it's not an integer");
+        }
+    }
+}
+
 #[async_trait]
 impl Effector for BrightnessEffector {
+    fn get_name(&self) -> String {
+        "brightness".to_owned()
+    }
+
     fn get_effects(&self) -> Vec<Effect> {
         vec![Effect::new(
             "screen_dim".to_owned(),
@@ -25,24 +52,20 @@ impl Effector for BrightnessEffector {
         )]
     }
 
-    async fn spawn<B: BrightnessController, D: ds::DisplayServer>(
+    fn get_config_schema(&self) -> ConfigSchema {
+        ConfigSchema::new().key("dim_percentage", ValueType::Integer { min: 0, max: 100 })
+    }
+
+    async fn spawn(
         &self,
         config: Option<toml::Value>,
-        provider: &mut DependencyProvider<B, D>,
+        provider: &mut DependencyProvider,
     ) -> Result<EffectorPort> {
-        let dim_fraction = if let Some(some_config) = config {
-            if let Some(toml::value::Value::Integer(dim_percentage)) =
-                some_config.get("dim_percentage")
-            {
-                *dim_percentage as f64 / 100f64
-            } else {
-                bail!("Couldn't find dim_percentage in brightness config or it's not an integer");
-            }
-        } else {
-            0.5
-        };
+        let dim_fraction = BrightnessEffector::parse_config(config.as_ref())?;
         let actor =
-            BrightnessEffectorActor::new(provider.get_brightness_controller(), dim_fraction);
+            BrightnessEffectorActor::new(provider.get_brightness_controller(), dim_fraction)
+                .with_dim_state(provider.get_dim_state())
+                .with_state_journal(provider.get_state_journal());
         spawn_server(actor).await
     }
 }
@@ -50,7 +73,8 @@ impl Effector for BrightnessEffector {
 pub struct BrightnessEffectorActor<B: BrightnessController> {
     dim_fraction: f64,
     brightness_controller: B,
-    original_brightness: Option<usize>,
+    dim_state: DimState,
+    state_journal: StateJournal,
 }
 
 impl<B: BrightnessController> BrightnessEffectorActor<B> {
@@ -58,10 +82,35 @@ impl<B: BrightnessController> BrightnessEffectorActor<B> {
         BrightnessEffectorActor {
             dim_fraction,
             brightness_controller,
-            original_brightness: None,
+            dim_state: DimState::default(),
+            state_journal: StateJournal::in_memory(),
         }
     }
 
+    /// Share the brightness to which the screen returns with the other
+    /// actors changing the brightness, see [BrightnessAdjuster]
+    pub fn with_dim_state(mut self, dim_state: DimState) -> Self {
+        self.dim_state = dim_state;
+        self
+    }
+
+    /// Record the original brightness in the given journal while the screen
+    /// is dimmed
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> Self {
+        self.state_journal = state_journal;
+        self
+    }
+
+    fn original_brightness(&self) -> Option<usize> {
+        self.dim_state.original_brightness()
+    }
+
+    fn set_original_brightness(&mut self, brightness: Option<usize>) {
+        self.dim_state.set_original_brightness(brightness);
+        self.state_journal
+            .update(|state| state.original_brightness = brightness);
+    }
+
     async fn dim_screen(&self) -> Result<usize> {
         let current_brightness = self.brightness_controller.get_brightness().await?;
         self.brightness_controller
@@ -69,46 +118,126 @@ impl<B: BrightnessController> BrightnessEffectorActor<B> {
             .await?;
         Ok(current_brightness)
     }
+
+    /// Dim the screen again if its brightness has been reset, e.g. when a
+    /// monitor was connected
+    async fn redim_screen(&self) -> Result<()> {
+        if let Some(original) = self.original_brightness() {
+            let dimmed = (original as f64 * self.dim_fraction) as usize;
+            if self.brightness_controller.get_brightness().await? > dimmed {
+                log::debug!("Brightness was reset, dimming to {} again", dimmed);
+                self.brightness_controller.set_brightness(dimmed).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn response(&self) -> EffectorResponse {
+        match self.original_brightness() {
+            Some(original) => EffectorResponse::new(1).with_status(format!(
+                "dimmed to {}% of brightness {}",
+                (self.dim_fraction * 100.0).round(),
+                original
+            )),
+            None => EffectorResponse::new(0),
+        }
+    }
 }
 
 #[async_trait]
-impl<B: BrightnessController> Server<EffectorMessage, usize> for BrightnessEffectorActor<B> {
+impl<B: BrightnessController> Server<EffectorMessage, EffectorResponse>
+    for BrightnessEffectorActor<B>
+{
     fn get_name(&self) -> String {
         "BrightnessEffector".to_owned()
     }
 
-    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<usize> {
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
         match payload {
-            EffectorMessage::Execute => {
-                if self.original_brightness.is_some() {
+            EffectorMessage::EnsureApplied if self.original_brightness().is_some() => {
+                self.redim_screen().await?;
+            }
+            EffectorMessage::EnsureRolledBack if self.original_brightness().is_none() => {}
+            EffectorMessage::Execute | EffectorMessage::EnsureApplied => {
+                if self.original_brightness().is_some() {
                     return Err(anyhow!("Trying to dim an already dimmed display."));
                 }
-                self.original_brightness = Some(self.dim_screen().await?);
-                Ok(1)
+                let original_brightness = self.dim_screen().await?;
+                self.set_original_brightness(Some(original_brightness));
             }
-            EffectorMessage::Rollback => {
-                if let Some(b) = self.original_brightness {
+            EffectorMessage::Rollback | EffectorMessage::EnsureRolledBack => {
+                if let Some(b) = self.original_brightness() {
                     self.brightness_controller.set_brightness(b).await?;
                 } else {
                     return Err(anyhow!("Rollback called without previous dimming."));
                 }
-                self.original_brightness = None;
-                Ok(0)
-            }
-            EffectorMessage::CurrentlyAppliedEffects => {
-                if self.original_brightness.is_some() {
-                    Ok(1)
-                } else {
-                    Ok(0)
-                }
+                self.set_original_brightness(None);
             }
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {}
         }
+        Ok(self.response())
     }
 
     async fn tear_down(&mut self) -> Result<()> {
-        if let Some(b) = self.original_brightness {
+        if let Some(b) = self.original_brightness() {
             self.brightness_controller.set_brightness(b).await?;
+            self.set_original_brightness(None);
         }
         Ok(())
     }
 }
+
+/// Changes the brightness on the user's request. While the screen is dimmed,
+/// the brightness to which it returns is changed as well, so that undimming
+/// doesn't revert the user's choice.
+#[derive(Clone)]
+pub struct BrightnessAdjuster<B: BrightnessController> {
+    brightness_controller: B,
+    dim_state: DimState,
+    state_journal: StateJournal,
+}
+
+impl<B: BrightnessController> BrightnessAdjuster<B> {
+    pub fn new(brightness_controller: B, dim_state: DimState) -> BrightnessAdjuster<B> {
+        BrightnessAdjuster {
+            brightness_controller,
+            dim_state,
+            state_journal: StateJournal::in_memory(),
+        }
+    }
+
+    /// Record the changed original brightness in the given journal
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> Self {
+        self.state_journal = state_journal;
+        self
+    }
+
+    /// Set the brightness to the given percentage, capped at 100
+    pub async fn set(&self, percentage: usize) -> Result<usize> {
+        let percentage = percentage.min(100);
+        self.brightness_controller
+            .set_brightness(percentage)
+            .await?;
+        if self.dim_state.original_brightness().is_some() {
+            log::debug!(
+                "Screen is dimmed, it will return to brightness {}",
+                percentage
+            );
+            self.dim_state.set_original_brightness(Some(percentage));
+            self.state_journal
+                .update(|state| state.original_brightness = Some(percentage));
+        }
+        Ok(percentage)
+    }
+
+    /// Change the brightness by the given number of percentage points,
+    /// starting from the brightness before dimming if the screen is dimmed
+    pub async fn step(&self, delta: i64) -> Result<usize> {
+        let current = match self.dim_state.original_brightness() {
+            Some(original) => original,
+            None => self.brightness_controller.get_brightness().await?,
+        };
+        self.set((current as i64 + delta).clamp(0, 100) as usize)
+            .await
+    }
+}
@@ -0,0 +1,240 @@
+//! A minimal screen locker, used by the lock effector when no locker command
+//! is configured.
+//!
+//! It covers the screen with a window, grabs the keyboard and the pointer and
+//! releases them once the user's password is verified by PAM. It runs in its
+//! own process, started as `energia lock`, so that the lock effector watches
+//! it the same way it watches external lockers.
+
+use anyhow::{anyhow, Context, Result};
+use std::{env, thread, time::Duration};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{
+            ChangeWindowAttributesAux, ConfigureWindowAux, ConnectionExt as _, CreateWindowAux,
+            EventMask, GrabMode, GrabStatus, KeyButMask, StackMode, Window, WindowClass,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+    COPY_DEPTH_FROM_PARENT, CURRENT_TIME, NONE,
+};
+
+/// PAM service used to verify the password, see packaging/pam.d/energia-lock
+const PAM_SERVICE: &str = "energia-lock";
+const IDLE_BACKGROUND: u32 = 0x000000;
+const TYPING_BACKGROUND: u32 = 0x1c1c1c;
+const FAILURE_BACKGROUND: u32 = 0x3c0000;
+/// How many times grabbing the input is tried, another program may be
+/// holding a grab for a short while, e.g. while a menu is open
+const GRAB_ATTEMPTS: usize = 100;
+const GRAB_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+const XK_BACKSPACE: u32 = 0xff08;
+const XK_RETURN: u32 = 0xff0d;
+const XK_ESCAPE: u32 = 0xff1b;
+const XK_KP_ENTER: u32 = 0xff8d;
+
+/// Lock the screen until the user enters their password, returning the exit
+/// code with which the process should end
+pub fn run() -> i32 {
+    match lock(None) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Couldn't lock the screen: {:#}", e);
+            1
+        }
+    }
+}
+
+fn lock(display_name: Option<&str>) -> Result<()> {
+    let user = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .context("Couldn't find out the name of the user")?;
+    let (connection, screen_num) = RustConnection::connect(display_name)?;
+    let screen = connection.setup().roots[screen_num].clone();
+    // The root window spans all the monitors, so a single window covers them
+    let window = connection.generate_id()?;
+    let aux_values = CreateWindowAux::default()
+        .background_pixel(IDLE_BACKGROUND)
+        .override_redirect(1)
+        .event_mask(EventMask::KEY_PRESS | EventMask::VISIBILITY_CHANGE);
+    connection
+        .create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            screen.width_in_pixels,
+            screen.height_in_pixels,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &aux_values,
+        )?
+        .check()
+        .context("Couldn't create the lock window")?;
+    connection.map_window(window)?;
+    connection.flush()?;
+    grab_input(&connection, window)?;
+    let keymap = Keymap::load(&connection)?;
+
+    let mut password = String::new();
+    loop {
+        match connection.wait_for_event()? {
+            Event::KeyPress(event) => {
+                let shifted = u16::from(event.state) & u16::from(KeyButMask::SHIFT) != 0;
+                match keymap.keysym(event.detail, shifted) {
+                    XK_RETURN | XK_KP_ENTER => {
+                        if authenticate(&user, &password) {
+                            return Ok(());
+                        }
+                        password.clear();
+                        set_background(&connection, window, FAILURE_BACKGROUND)?;
+                        continue;
+                    }
+                    XK_ESCAPE => password.clear(),
+                    XK_BACKSPACE => {
+                        password.pop();
+                    }
+                    keysym => {
+                        if let Some(character) = keysym_to_char(keysym) {
+                            password.push(character);
+                        }
+                    }
+                }
+                let background = if password.is_empty() {
+                    IDLE_BACKGROUND
+                } else {
+                    TYPING_BACKGROUND
+                };
+                set_background(&connection, window, background)?;
+            }
+            // Keep the window above the ones mapped after it
+            Event::VisibilityNotify(_) => {
+                connection.configure_window(
+                    window,
+                    &ConfigureWindowAux::default().stack_mode(StackMode::ABOVE),
+                )?;
+                connection.flush()?;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn grab_input(connection: &RustConnection, window: Window) -> Result<()> {
+    for _ in 0..GRAB_ATTEMPTS {
+        let keyboard = connection
+            .grab_keyboard(true, window, CURRENT_TIME, GrabMode::ASYNC, GrabMode::ASYNC)?
+            .reply()?;
+        let pointer = connection
+            .grab_pointer(
+                false,
+                window,
+                EventMask::NO_EVENT,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+                window,
+                NONE,
+                CURRENT_TIME,
+            )?
+            .reply()?;
+        if keyboard.status == GrabStatus::SUCCESS && pointer.status == GrabStatus::SUCCESS {
+            return Ok(());
+        }
+        thread::sleep(GRAB_RETRY_DELAY);
+    }
+    Err(anyhow!("Couldn't grab the keyboard and the pointer"))
+}
+
+fn set_background(connection: &RustConnection, window: Window, color: u32) -> Result<()> {
+    connection.change_window_attributes(
+        window,
+        &ChangeWindowAttributesAux::default().background_pixel(color),
+    )?;
+    connection.clear_area(false, window, 0, 0, 0, 0)?;
+    connection.flush()?;
+    Ok(())
+}
+
+fn authenticate(user: &str, password: &str) -> bool {
+    let mut authenticator = match pam::Authenticator::with_password(PAM_SERVICE) {
+        Ok(authenticator) => authenticator,
+        Err(e) => {
+            eprintln!("Couldn't start PAM conversation: {}", e);
+            return false;
+        }
+    };
+    authenticator.get_handler().set_credentials(user, password);
+    authenticator.authenticate().is_ok()
+}
+
+/// The keysyms bound to the keyboard's keycodes
+struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    fn load(connection: &RustConnection) -> Result<Keymap> {
+        let setup = connection.setup();
+        let min_keycode = setup.min_keycode;
+        let mapping = connection
+            .get_keyboard_mapping(min_keycode, setup.max_keycode - min_keycode + 1)?
+            .reply()?;
+        Ok(Keymap {
+            min_keycode,
+            keysyms_per_keycode: mapping.keysyms_per_keycode as usize,
+            keysyms: mapping.keysyms,
+        })
+    }
+
+    fn keysym(&self, keycode: u8, shifted: bool) -> u32 {
+        let index = (keycode.saturating_sub(self.min_keycode) as usize) * self.keysyms_per_keycode;
+        let unshifted = self.keysyms.get(index).copied().unwrap_or(0);
+        match self.keysyms.get(index + 1).copied() {
+            Some(shifted_keysym)
+                if shifted && self.keysyms_per_keycode > 1 && shifted_keysym != 0 =>
+            {
+                shifted_keysym
+            }
+            _ => unshifted,
+        }
+    }
+}
+
+/// The character typed by a key with the keysym, for Latin-1 and Unicode
+/// keysyms
+fn keysym_to_char(keysym: u32) -> Option<char> {
+    match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym),
+        0x0100_0000..=0x0110_ffff => char::from_u32(keysym - 0x0100_0000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keymap() {
+        let keymap = Keymap {
+            min_keycode: 8,
+            keysyms_per_keycode: 2,
+            keysyms: vec![0x61, 0x41, XK_RETURN, 0],
+        };
+        assert_eq!(keymap.keysym(8, false), 0x61);
+        assert_eq!(keymap.keysym(8, true), 0x41);
+        assert_eq!(keymap.keysym(9, true), XK_RETURN);
+        assert_eq!(keymap.keysym(200, false), 0);
+        assert_eq!(keysym_to_char(0x41), Some('A'));
+        assert_eq!(keysym_to_char(0xe9), Some('é'));
+        assert_eq!(keysym_to_char(0x0100_010d), Some('č'));
+        assert_eq!(keysym_to_char(XK_BACKSPACE), None);
+    }
+}
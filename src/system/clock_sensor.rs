@@ -0,0 +1,169 @@
+//! Detects jumps of the wall clock, e.g. when it's synchronized over NTP, set
+//! manually or the time zone changes
+//!
+//! The timeouts of the schedules are measured on the monotonic clock, which
+//! doesn't jump, so only the actors working with the time of day or with
+//! timestamps need to re-synchronize on a [ClockJump].
+
+use super::sleep_sensor::boot_time;
+use crate::armaf::{EventBus, Handle, HandleChild, TickService, Ticks};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+
+/// How often the clock is compared with the time since boot
+const CHECK_PERIOD: Duration = Duration::from_secs(30);
+
+/// Smaller differences are corrections of the clock's drift, not jumps
+const JUMP_THRESHOLD: i64 = 5;
+
+/// The wall clock or the local time zone changed by more than the clock's
+/// usual drift
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockJump {
+    /// How far the system clock moved, in seconds, negative if it moved back
+    pub offset: i64,
+    /// How much the local time zone's offset from UTC changed, in seconds
+    pub utc_offset_change: i64,
+}
+
+impl ClockJump {
+    /// How far the local time moved, in seconds
+    pub fn local_offset(&self) -> i64 {
+        self.offset + self.utc_offset_change
+    }
+}
+
+/// Reading of the clocks, taken to compare with the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockReading {
+    /// Seconds between the boot and the Unix epoch according to the system
+    /// clock, constant unless the clock is set
+    pub epoch_to_boot: i64,
+    /// Local time zone's offset from UTC in seconds
+    pub utc_offset: i64,
+}
+
+impl ClockReading {
+    pub fn now() -> ClockReading {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        ClockReading {
+            epoch_to_boot: since_epoch.as_secs() as i64 - boot_time().as_secs() as i64,
+            utc_offset: utc_offset(),
+        }
+    }
+
+    /// The jump between this and a later reading, if the clocks moved
+    /// noticeably
+    pub fn jump_to(&self, later: &ClockReading) -> Option<ClockJump> {
+        let jump = ClockJump {
+            offset: later.epoch_to_boot - self.epoch_to_boot,
+            utc_offset_change: later.utc_offset - self.utc_offset,
+        };
+        if jump.offset.abs() >= JUMP_THRESHOLD || jump.utc_offset_change != 0 {
+            Some(jump)
+        } else {
+            None
+        }
+    }
+}
+
+/// Get the local time zone's current offset from UTC in seconds
+fn utc_offset() -> i64 {
+    // SAFETY: localtime_r only writes into the given struct and time
+    // accepts a null pointer
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    tm.tm_gmtoff as i64
+}
+
+/// Periodically compares the clocks, publishing a [ClockJump] on the
+/// [EventBus] when they jump
+pub struct ClockSensor {
+    sender: broadcast::Sender<ClockJump>,
+    ticks: Ticks,
+    handle_child: Option<HandleChild>,
+}
+
+impl ClockSensor {
+    pub fn new(events: &EventBus, tick_service: &TickService) -> ClockSensor {
+        ClockSensor {
+            sender: events.sender(),
+            ticks: tick_service.subscribe(CHECK_PERIOD),
+            handle_child: None,
+        }
+    }
+
+    pub fn spawn(mut self) -> Handle {
+        let (handle, handle_child) = Handle::new();
+        self.handle_child = Some(handle_child);
+
+        tokio::spawn(async move {
+            self.main_loop().await;
+        });
+
+        handle
+    }
+
+    async fn main_loop(&mut self) {
+        let mut previous = ClockReading::now();
+        loop {
+            tokio::select! {
+                _ = self.handle_child.as_mut().unwrap().should_terminate() => return,
+                _ = self.ticks.tick() => {
+                    let current = ClockReading::now();
+                    if let Some(jump) = previous.jump_to(&current) {
+                        log::info!(
+                            "Clock jumped by {}s, time zone offset changed by {}s",
+                            jump.offset,
+                            jump.utc_offset_change
+                        );
+                        // Nobody may be interested, which is fine
+                        let _ = self.sender.send(jump);
+                    }
+                    previous = current;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reading(epoch_to_boot: i64, utc_offset: i64) -> ClockReading {
+        ClockReading {
+            epoch_to_boot,
+            utc_offset,
+        }
+    }
+
+    #[test]
+    fn test_jump_detection() {
+        let start = reading(1_700_000_000, 3600);
+        assert_eq!(start.jump_to(&reading(1_700_000_001, 3600)), None);
+        assert_eq!(start.jump_to(&reading(1_699_999_997, 3600)), None);
+        assert_eq!(
+            start.jump_to(&reading(1_700_000_600, 3600)),
+            Some(ClockJump {
+                offset: 600,
+                utc_offset_change: 0
+            })
+        );
+        let to_summer_time = start.jump_to(&reading(1_700_000_000, 7200)).unwrap();
+        assert_eq!(to_summer_time.offset, 0);
+        assert_eq!(to_summer_time.local_offset(), 3600);
+        assert_eq!(
+            start
+                .jump_to(&reading(1_699_999_000, 0))
+                .map(|jump| jump.local_offset()),
+            Some(-4600)
+        );
+    }
+}
@@ -1,13 +1,17 @@
 //! Turns the computer's screen on and off using DPMS
+//!
+//! If the display server doesn't support DPMS, the screen is blanked instead,
+//! the same way as by the blank effector.
 
 use crate::{
     armaf::{
-        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, RollbackStrategy, Server,
+        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, EffectorResponse,
+        RollbackStrategy, Server,
     },
     external::{
-        brightness::BrightnessController,
         dependency_provider::DependencyProvider,
         display_server::{self as ds, DisplayServerController},
+        state_journal::{DPMSConfiguration, StateJournal},
     },
 };
 use anyhow::Result;
@@ -18,6 +22,10 @@ pub struct DPMSEffector;
 
 #[async_trait]
 impl Effector for DPMSEffector {
+    fn get_name(&self) -> String {
+        "dpms".to_owned()
+    }
+
     fn get_effects(&self) -> Vec<Effect> {
         vec![Effect::new(
             "screen_off".to_owned(),
@@ -26,37 +34,69 @@ impl Effector for DPMSEffector {
         )]
     }
 
-    async fn spawn<B: BrightnessController, D: ds::DisplayServer>(
+    async fn spawn(
         &self,
         _: Option<toml::Value>,
-        provider: &mut DependencyProvider<B, D>,
+        provider: &mut DependencyProvider,
     ) -> Result<EffectorPort> {
-        let actor = DPMSEffectorActor::new(provider.get_display_controller());
+        let actor = DPMSEffectorActor::new(provider.get_display_controller())
+            .with_state_journal(provider.get_state_journal());
         spawn_server(actor).await
     }
 }
 
 pub struct DPMSEffectorActor<D: ds::DisplayServerController> {
     display_off: bool,
+    /// Whether the display server supports DPMS, the screen is blanked if it
+    /// doesn't
+    dpms_capable: bool,
     ds_controller: D,
     original_configuration: ServerConfiguration,
+    state_journal: StateJournal,
 }
 
 impl<D: ds::DisplayServerController> DPMSEffectorActor<D> {
     pub fn new(ds_controller: D) -> DPMSEffectorActor<D> {
         DPMSEffectorActor {
             display_off: false,
+            dpms_capable: true,
             ds_controller,
             original_configuration: ServerConfiguration {
                 level: Some(ds::DPMSLevel::On),
                 timeouts: ds::DPMSTimeouts::new(0, 0, 0),
             },
+            state_journal: StateJournal::in_memory(),
         }
     }
 
+    /// Record the original DPMS configuration in the given journal while the
+    /// actor is running
+    pub fn with_state_journal(mut self, state_journal: StateJournal) -> Self {
+        self.state_journal = state_journal;
+        self
+    }
+
     async fn set_dpms_level(&self, level: ds::DPMSLevel) -> Result<()> {
-        let sent_controller = self.ds_controller.clone();
-        tokio::task::spawn_blocking(move || sent_controller.set_dpms_level(level)).await?
+        self.ds_controller.set_dpms_level(level).await
+    }
+
+    async fn get_dpms_level(&self) -> Result<Option<ds::DPMSLevel>> {
+        self.ds_controller.get_dpms_level().await
+    }
+
+    async fn set_screen_blanked(&self, blanked: bool) -> Result<()> {
+        self.ds_controller.set_screen_blanked(blanked).await
+    }
+
+    /// Turn the display off or on, or blank it if DPMS isn't supported
+    async fn set_display_off(&self, off: bool) -> Result<()> {
+        if !self.dpms_capable {
+            self.set_screen_blanked(off).await
+        } else if off {
+            self.set_dpms_level(ds::DPMSLevel::Off).await
+        } else {
+            self.set_dpms_level(ds::DPMSLevel::On).await
+        }
     }
 
     async fn prepare_dpms(&self) {
@@ -71,43 +111,94 @@ impl<D: ds::DisplayServerController> DPMSEffectorActor<D> {
 }
 
 #[async_trait]
-impl<D: ds::DisplayServerController> Server<EffectorMessage, usize> for DPMSEffectorActor<D> {
+impl<D: ds::DisplayServerController> Server<EffectorMessage, EffectorResponse>
+    for DPMSEffectorActor<D>
+{
     fn get_name(&self) -> String {
         "DPMSEffector".to_owned()
     }
 
-    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<usize> {
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
         match payload {
             EffectorMessage::Execute => {
-                self.set_dpms_level(ds::DPMSLevel::Off).await?;
+                self.set_display_off(true).await?;
                 self.display_off = true;
-                Ok(1)
             }
             EffectorMessage::Rollback => {
-                self.set_dpms_level(ds::DPMSLevel::On).await?;
+                self.set_display_off(false).await?;
                 self.display_off = false;
-                Ok(0)
             }
-            EffectorMessage::CurrentlyAppliedEffects => {
+            // Blanking again covers the windows and monitors that appeared
+            // since the screen was blanked
+            EffectorMessage::EnsureApplied if !self.dpms_capable => {
+                self.set_screen_blanked(true).await?;
+                self.display_off = true;
+            }
+            EffectorMessage::EnsureRolledBack if !self.dpms_capable => {
                 if self.display_off {
-                    Ok(1)
-                } else {
-                    Ok(0)
+                    self.set_screen_blanked(false).await?;
                 }
+                self.display_off = false;
+            }
+            // The display may have been turned on or off by someone else.
+            // A newly connected monitor is on even though the server
+            // reports the level as off, so the level is forced again.
+            EffectorMessage::EnsureApplied => {
+                if self.display_off || self.get_dpms_level().await? != Some(ds::DPMSLevel::Off) {
+                    self.set_dpms_level(ds::DPMSLevel::Off).await?;
+                }
+                self.display_off = true;
             }
+            EffectorMessage::EnsureRolledBack => {
+                if self.get_dpms_level().await? != Some(ds::DPMSLevel::On) {
+                    self.set_dpms_level(ds::DPMSLevel::On).await?;
+                }
+                self.display_off = false;
+            }
+            // Without DPMS the screen can still be blanked
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {}
         }
+        Ok(match (self.display_off, self.dpms_capable) {
+            (true, true) => EffectorResponse::new(1).with_status("display turned off"),
+            (true, false) => {
+                EffectorResponse::new(1).with_status("display blanked, DPMS isn't supported")
+            }
+            (false, true) => EffectorResponse::new(0),
+            (false, false) => EffectorResponse::new(0)
+                .with_status("DPMS isn't supported, display will be blanked instead"),
+        })
     }
 
     async fn initialize(&mut self) -> Result<()> {
+        self.dpms_capable = self.ds_controller.is_dpms_capable().await?;
+        if !self.dpms_capable {
+            log::warn!("DPMS isn't supported, screen_off will blank the screen instead");
+            return Ok(());
+        }
         self.original_configuration = ServerConfiguration::fetch(&self.ds_controller).await?;
+        let original = self.original_configuration;
+        self.state_journal.update(|state| {
+            state.original_dpms = Some(DPMSConfiguration {
+                level: original.level,
+                timeouts: original.timeouts,
+            })
+        });
         self.prepare_dpms().await;
         Ok(())
     }
 
     async fn tear_down(&mut self) -> Result<()> {
+        if !self.dpms_capable {
+            if self.display_off {
+                self.set_screen_blanked(false).await?;
+            }
+            return Ok(());
+        }
         self.original_configuration
             .apply(&self.ds_controller)
             .await?;
+        self.state_journal
+            .update(|state| state.original_dpms = None);
         Ok(())
     }
 }
@@ -121,38 +212,29 @@ struct ServerConfiguration {
 }
 
 impl ServerConfiguration {
+    // The requests are made concurrently, so that the display server's
+    // worker can send them together
     async fn fetch<C: DisplayServerController>(controller: &C) -> Result<ServerConfiguration> {
-        let level_controller = controller.clone();
-        let level_handle = tokio::task::spawn_blocking(move || level_controller.get_dpms_level());
-
-        let timeouts_controller = controller.clone();
-        let timeouts_handle =
-            tokio::task::spawn_blocking(move || timeouts_controller.get_dpms_timeouts());
-
+        let (level, timeouts) =
+            tokio::join!(controller.get_dpms_level(), controller.get_dpms_timeouts());
         Ok(ServerConfiguration {
-            level: level_handle.await??,
-            timeouts: timeouts_handle.await??,
+            level: level?,
+            timeouts: timeouts?,
         })
     }
 
     async fn apply<C: ds::DisplayServerController>(self, controller: &C) -> Result<()> {
-        let level_controller = controller.clone();
-        let level_handle = if let Some(level) = self.level {
-            tokio::task::spawn_blocking(move || -> Result<()> {
-                level_controller.set_dpms_state(true)?;
-                level_controller.set_dpms_level(level)?;
-                Ok(())
-            })
-        } else {
-            tokio::task::spawn_blocking(move || level_controller.set_dpms_state(false))
+        let set_level = async {
+            if let Some(level) = self.level {
+                controller.set_dpms_state(true).await?;
+                controller.set_dpms_level(level).await
+            } else {
+                controller.set_dpms_state(false).await
+            }
         };
-
-        let timeouts_controller = controller.clone();
-        let timeouts_handle = tokio::task::spawn_blocking(move || {
-            timeouts_controller.set_dpms_timeouts(self.timeouts)
-        });
-
-        level_handle.await??; // Not exactly the most elegant error handling, but eh. If this fails, it's not a catastrophe, more like a bit annoying.
-        Ok(timeouts_handle.await??)
+        let (level_result, timeouts_result) =
+            tokio::join!(set_level, controller.set_dpms_timeouts(self.timeouts));
+        level_result?; // Not exactly the most elegant error handling, but eh. If this fails, it's not a catastrophe, more like a bit annoying.
+        timeouts_result
     }
 }
@@ -0,0 +1,145 @@
+//! Periodically gathers the facts about the computer's surroundings which
+//! [schedule rules](crate::control::schedule_rules) can depend on
+
+use super::output_sensor::{has_external_monitor, ConnectedOutputs};
+use crate::armaf::{TickService, Ticks};
+use anyhow::Result;
+use std::{path::Path, time::Duration};
+use tokio::sync::watch;
+
+/// How often the facts are gathered
+pub const ENVIRONMENT_SAMPLING_PERIOD: Duration = Duration::from_secs(30);
+
+const THERMAL_ZONES_DIRECTORY: &str = "/sys/class/thermal";
+
+/// Facts about the computer, [None] when they couldn't be determined
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvironmentFacts {
+    /// Whether logind considers the computer docked
+    pub docked: Option<bool>,
+    /// Temperature of the hottest thermal zone in degrees Celsius
+    pub temperature: Option<f64>,
+    /// Whether a monitor other than the built-in panel is connected
+    pub external_monitor: Option<bool>,
+}
+
+/// Reads the facts every period and publishes them when they change
+pub struct EnvironmentSensor {
+    logind: zbus::Proxy<'static>,
+    ticks: Ticks,
+    outputs: Option<watch::Receiver<ConnectedOutputs>>,
+    facts_sender: watch::Sender<EnvironmentFacts>,
+}
+
+impl EnvironmentSensor {
+    /// Start gathering the facts, terminating once all the receivers are
+    /// dropped. Whether an external monitor is connected is only known if
+    /// the connected outputs are given, and is published as soon as they
+    /// change.
+    pub async fn spawn(
+        system_connection: zbus::Connection,
+        tick_service: &TickService,
+        period: Duration,
+        outputs: Option<watch::Receiver<ConnectedOutputs>>,
+    ) -> Result<watch::Receiver<EnvironmentFacts>> {
+        let logind = zbus::Proxy::new(
+            &system_connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+        )
+        .await?;
+        let initial_facts = read_facts(&logind, outputs.as_ref()).await;
+        log::debug!("Environment facts on spawn: {:?}", initial_facts);
+        let (facts_sender, facts_receiver) = watch::channel(initial_facts);
+        let mut sensor = EnvironmentSensor {
+            logind,
+            ticks: tick_service.subscribe(period),
+            outputs,
+            facts_sender,
+        };
+        tokio::spawn(async move {
+            sensor.run().await;
+        });
+        Ok(facts_receiver)
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.facts_sender.closed() => {
+                    log::debug!("All receivers closed, terminating");
+                    return;
+                }
+                _ = self.ticks.tick() => self.update_facts().await,
+                changed = outputs_changed(self.outputs.as_mut()) => {
+                    if changed.is_err() {
+                        log::warn!("Output sensor has stopped, external monitors won't be detected");
+                        self.outputs = None;
+                    }
+                    self.update_facts().await;
+                }
+            }
+        }
+    }
+
+    async fn update_facts(&mut self) {
+        let facts = read_facts(&self.logind, self.outputs.as_ref()).await;
+        if *self.facts_sender.borrow() != facts {
+            log::debug!("Environment facts changed: {:?}", facts);
+            let _ = self.facts_sender.send(facts);
+        }
+    }
+}
+
+async fn outputs_changed(
+    receiver: Option<&mut watch::Receiver<ConnectedOutputs>>,
+) -> Result<(), watch::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn read_facts(
+    logind: &zbus::Proxy<'_>,
+    outputs: Option<&watch::Receiver<ConnectedOutputs>>,
+) -> EnvironmentFacts {
+    let docked = match logind.get_property::<bool>("Docked").await {
+        Ok(docked) => Some(docked),
+        Err(e) => {
+            log::warn!("Couldn't find out whether the computer is docked: {}", e);
+            None
+        }
+    };
+    EnvironmentFacts {
+        docked,
+        temperature: read_max_temperature(Path::new(THERMAL_ZONES_DIRECTORY)).await,
+        external_monitor: outputs.map(|outputs| has_external_monitor(&outputs.borrow())),
+    }
+}
+
+/// Read the highest temperature of the thermal zones in the directory, which
+/// report it in millidegrees Celsius
+pub async fn read_max_temperature(directory: &Path) -> Option<f64> {
+    let mut entries = tokio::fs::read_dir(directory).await.ok()?;
+    let mut max_temperature: Option<f64> = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("thermal_zone")
+        {
+            continue;
+        }
+        let temperature = tokio::fs::read_to_string(entry.path().join("temp"))
+            .await
+            .ok()
+            .and_then(|temp| temp.trim().parse::<i64>().ok())
+            .map(|millidegrees| millidegrees as f64 / 1000.0);
+        if let Some(temperature) = temperature {
+            max_temperature = Some(max_temperature.map_or(temperature, |max| max.max(temperature)));
+        }
+    }
+    max_temperature
+}
@@ -1,15 +1,240 @@
-//! A passive sensor for discovering inhibitors submitted to logind
+//! A passive sensor for discovering inhibitors submitted to logind and
+//! inhibitions requested by applications directly from Energia
 
-use crate::armaf::Server;
+use crate::armaf::{ActorPort, Server};
 use anyhow::Result;
 use async_trait::async_trait;
-use logind_zbus::manager::{self};
+use logind_zbus::manager::{self, InhibitType, InhibitTypes, Mode};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_stream::StreamExt;
+
+/// Time for which the inhibitors listed by logind are reused without asking
+/// it again, unless logind reports a change of the inhibitors
+const CACHE_TTL: Duration = Duration::from_secs(2);
+/// Time for which the last inhibitors listed by logind are used when logind
+/// can't be reached
+const STALE_LIMIT: Duration = Duration::from_secs(60);
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct GetInhibitions;
 
+/// An inhibitor together with the time for which it has been reported
+#[derive(Debug, Clone)]
+pub struct ActiveInhibitor {
+    pub inhibitor: manager::Inhibitor,
+    /// Time since the sensor first saw the inhibitor
+    pub age: Duration,
+}
+
+/// Port through which an [InhibitionSensor] is asked for the
+/// [ActiveInhibitor]s
+pub type InhibitionSensorPort = ActorPort<GetInhibitions, Vec<ActiveInhibitor>, anyhow::Error>;
+
+/// Identifies an inhibitor across the sensor's queries, logind doesn't give
+/// inhibitors any identifier
+type InhibitorKey = (String, String, u32, u32, String);
+
+fn inhibitor_key(inhibitor: &manager::Inhibitor) -> InhibitorKey {
+    (
+        inhibitor.who().to_owned(),
+        inhibitor.why().to_owned(),
+        inhibitor.uid(),
+        inhibitor.pid(),
+        format!("{:?} {:?}", inhibitor.what().types(), inhibitor.mode()),
+    )
+}
+
+/// Remembers when each inhibitor was first seen, forgetting the inhibitors
+/// once they're released
+#[derive(Debug, Default)]
+struct InhibitorAges {
+    first_seen: HashMap<InhibitorKey, Instant>,
+}
+
+impl InhibitorAges {
+    fn observe(&mut self, inhibitors: Vec<manager::Inhibitor>) -> Vec<ActiveInhibitor> {
+        let now = Instant::now();
+        let mut first_seen = HashMap::new();
+        let active = inhibitors
+            .into_iter()
+            .map(|inhibitor| {
+                let key = inhibitor_key(&inhibitor);
+                let seen = *self.first_seen.get(&key).unwrap_or(&now);
+                first_seen.insert(key, seen);
+                ActiveInhibitor {
+                    inhibitor,
+                    age: now - seen,
+                }
+            })
+            .collect();
+        self.first_seen = first_seen;
+        active
+    }
+}
+
+/// An idleness inhibition requested by an application over D-Bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationInhibition {
+    /// Unique D-Bus name of the connection which requested the inhibition
+    pub owner: String,
+    /// Name of the application, as given by the application
+    pub application: String,
+    /// Why the application inhibits idleness
+    pub reason: String,
+    /// What the inhibition blocks, depends on the interface through which it
+    /// was requested
+    pub inhibit_type: InhibitType,
+}
+
+#[derive(Debug, Default)]
+struct ApplicationInhibitionsState {
+    last_cookie: u32,
+    inhibitions: BTreeMap<u32, ApplicationInhibition>,
+}
+
+/// Inhibitions requested by applications through Energia's D-Bus APIs rather
+/// than through logind.
+///
+/// Clones share the same inhibitions, so the D-Bus interfaces can add them
+/// while the [InhibitionSensor] reports them.
+#[derive(Debug, Clone, Default)]
+pub struct ApplicationInhibitions(Arc<Mutex<ApplicationInhibitionsState>>);
+
+impl ApplicationInhibitions {
+    pub fn new() -> ApplicationInhibitions {
+        ApplicationInhibitions::default()
+    }
+
+    /// Add an inhibition and return the cookie identifying it
+    pub fn add(&self, inhibition: ApplicationInhibition) -> u32 {
+        let mut state = self.0.lock().unwrap();
+        // Cookie 0 is avoided, some applications treat it as a failure
+        state.last_cookie = state.last_cookie.checked_add(1).unwrap_or(1);
+        while state.inhibitions.contains_key(&state.last_cookie) {
+            state.last_cookie = state.last_cookie.checked_add(1).unwrap_or(1);
+        }
+        let cookie = state.last_cookie;
+        state.inhibitions.insert(cookie, inhibition);
+        cookie
+    }
+
+    /// Remove the inhibition with the given cookie, as long as it belongs to
+    /// the given owner. Returns the removed inhibition.
+    pub fn remove(&self, owner: &str, cookie: u32) -> Option<ApplicationInhibition> {
+        let mut state = self.0.lock().unwrap();
+        match state.inhibitions.get(&cookie) {
+            Some(inhibition) if inhibition.owner == owner => state.inhibitions.remove(&cookie),
+            _ => None,
+        }
+    }
+
+    /// Remove all the inhibitions of the given owner, e.g. because it has
+    /// disconnected from the bus. Returns the number of removed inhibitions.
+    pub fn remove_owner(&self, owner: &str) -> usize {
+        let mut state = self.0.lock().unwrap();
+        let count_before = state.inhibitions.len();
+        state
+            .inhibitions
+            .retain(|_, inhibition| inhibition.owner != owner);
+        count_before - state.inhibitions.len()
+    }
+
+    /// Get all the inhibitions together with their cookies
+    pub fn list(&self) -> Vec<(u32, ApplicationInhibition)> {
+        let state = self.0.lock().unwrap();
+        state
+            .inhibitions
+            .iter()
+            .map(|(cookie, inhibition)| (*cookie, inhibition.clone()))
+            .collect()
+    }
+
+    /// Whether any inhibition of the given type exists
+    pub fn has(&self, inhibit_type: InhibitType) -> bool {
+        let state = self.0.lock().unwrap();
+        state
+            .inhibitions
+            .values()
+            .any(|inhibition| inhibition.inhibit_type == inhibit_type)
+    }
+
+    /// Get the inhibitions in the form of logind's blocking inhibitors
+    pub fn to_inhibitors(&self) -> Vec<manager::Inhibitor> {
+        self.list()
+            .into_iter()
+            .map(|(_, inhibition)| {
+                manager::Inhibitor::new(
+                    InhibitTypes::new(&vec![inhibition.inhibit_type]),
+                    inhibition.application,
+                    inhibition.reason,
+                    Mode::Block,
+                    0,
+                    0,
+                )
+            })
+            .collect()
+    }
+}
+
+/// The inhibitors last listed by logind, which can be invalidated from
+/// another task
+#[derive(Debug, Default)]
+struct InhibitorCache {
+    entry: Option<(Vec<manager::Inhibitor>, Instant)>,
+    invalidated: Arc<AtomicBool>,
+}
+
+impl InhibitorCache {
+    /// Get the flag which invalidates the cache once it's set
+    fn invalidator(&self) -> Arc<AtomicBool> {
+        self.invalidated.clone()
+    }
+
+    /// Get the inhibitors if they were listed recently and haven't been
+    /// invalidated since. The invalidation is consumed, since the caller is
+    /// going to list the inhibitors again.
+    fn get_fresh(&self) -> Option<Vec<manager::Inhibitor>> {
+        if self.invalidated.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+        self.get_younger_than(CACHE_TTL)
+    }
+
+    /// Get the inhibitors, even if they're invalidated, as long as they're
+    /// not older than [STALE_LIMIT]
+    fn get_stale(&self) -> Option<Vec<manager::Inhibitor>> {
+        self.get_younger_than(STALE_LIMIT)
+    }
+
+    fn get_younger_than(&self, limit: Duration) -> Option<Vec<manager::Inhibitor>> {
+        match self.entry.as_ref() {
+            Some((inhibitors, listed_at)) if listed_at.elapsed() < limit => {
+                Some(inhibitors.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, inhibitors: Vec<manager::Inhibitor>) {
+        self.entry = Some((inhibitors, Instant::now()));
+    }
+}
+
 pub struct InhibitionSensor {
     connection: zbus::Connection,
     manager_proxy: Option<logind_zbus::manager::ManagerProxy<'static>>,
+    application_inhibitions: Option<ApplicationInhibitions>,
+    ages: InhibitorAges,
+    cache: InhibitorCache,
+    change_watcher: Option<JoinHandle<()>>,
 }
 
 impl InhibitionSensor {
@@ -17,27 +242,185 @@ impl InhibitionSensor {
         InhibitionSensor {
             connection,
             manager_proxy: None,
+            application_inhibitions: None,
+            ages: InhibitorAges::default(),
+            cache: InhibitorCache::default(),
+            change_watcher: None,
         }
     }
+
+    /// Report the given application inhibitions together with the ones from
+    /// logind
+    pub fn with_application_inhibitions(
+        mut self,
+        application_inhibitions: ApplicationInhibitions,
+    ) -> InhibitionSensor {
+        self.application_inhibitions = Some(application_inhibitions);
+        self
+    }
+
+    async fn list_logind_inhibitors(&mut self) -> Result<Vec<manager::Inhibitor>> {
+        if let Some(inhibitors) = self.cache.get_fresh() {
+            return Ok(inhibitors);
+        }
+        match self.manager_proxy.as_ref().unwrap().list_inhibitors().await {
+            Ok(inhibitors) => {
+                self.cache.store(inhibitors.clone());
+                Ok(inhibitors)
+            }
+            Err(e) => match self.cache.get_stale() {
+                Some(inhibitors) => {
+                    log::warn!("Couldn't list inhibitors, using the last known ones: {}", e);
+                    Ok(inhibitors)
+                }
+                None => Err(e.into()),
+            },
+        }
+    }
+
+    /// Invalidate the cache whenever logind reports a change of the
+    /// inhibited operations
+    async fn watch_changes(&mut self) {
+        let manager_proxy = self.manager_proxy.as_ref().unwrap();
+        let mut block_changes = manager_proxy.receive_block_inhibited_changed().await;
+        let mut delay_changes = manager_proxy.receive_delay_inhibited_changed().await;
+        let invalidated = self.cache.invalidator();
+        self.change_watcher = Some(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(_) = block_changes.next() => {},
+                    Some(_) = delay_changes.next() => {},
+                    else => return,
+                }
+                log::debug!("Inhibitors changed, invalidating the cache");
+                invalidated.store(true, Ordering::SeqCst);
+            }
+        }));
+    }
 }
 
 #[async_trait]
-impl Server<GetInhibitions, Vec<manager::Inhibitor>> for InhibitionSensor {
+impl Server<GetInhibitions, Vec<ActiveInhibitor>> for InhibitionSensor {
     fn get_name(&self) -> String {
         "InhibitionSensor".to_owned()
     }
 
-    async fn handle_message(&mut self, _: GetInhibitions) -> Result<Vec<manager::Inhibitor>> {
-        Ok(self
-            .manager_proxy
-            .as_ref()
-            .unwrap()
-            .list_inhibitors()
-            .await?)
+    fn get_default_deadline(&self) -> Option<Duration> {
+        Some(Duration::from_secs(5))
+    }
+
+    async fn handle_message(&mut self, _: GetInhibitions) -> Result<Vec<ActiveInhibitor>> {
+        let mut inhibitors = self.list_logind_inhibitors().await?;
+        if let Some(application_inhibitions) = self.application_inhibitions.as_ref() {
+            inhibitors.extend(application_inhibitions.to_inhibitors());
+        }
+        Ok(self.ages.observe(inhibitors))
     }
 
     async fn initialize(&mut self) -> Result<()> {
         self.manager_proxy = Some(logind_zbus::manager::ManagerProxy::new(&self.connection).await?);
+        self.watch_changes().await;
+        Ok(())
+    }
+
+    async fn tear_down(&mut self) -> Result<()> {
+        if let Some(change_watcher) = self.change_watcher.take() {
+            change_watcher.abort();
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inhibition(owner: &str, application: &str) -> ApplicationInhibition {
+        ApplicationInhibition {
+            owner: owner.to_owned(),
+            application: application.to_owned(),
+            reason: "Playing video".to_owned(),
+            inhibit_type: InhibitType::Idle,
+        }
+    }
+
+    #[test]
+    fn test_application_inhibitions() {
+        let inhibitions = ApplicationInhibitions::new();
+        let firefox = inhibitions.add(inhibition(":1.10", "firefox"));
+        let vlc = inhibitions.add(inhibition(":1.20", "vlc"));
+        inhibitions.add(ApplicationInhibition {
+            inhibit_type: InhibitType::Sleep,
+            ..inhibition(":1.20", "vlc")
+        });
+        assert_ne!(firefox, 0);
+        assert_ne!(firefox, vlc);
+
+        // Only the owner may remove the inhibition
+        assert!(inhibitions.remove(":1.20", firefox).is_none());
+        assert_eq!(inhibitions.list().len(), 3);
+        assert!(inhibitions.remove(":1.10", firefox).is_some());
+
+        let inhibitors = inhibitions.clone().to_inhibitors();
+        assert_eq!(inhibitors.len(), 2);
+        assert_eq!(inhibitors[0].who(), "vlc");
+        assert_eq!(inhibitors[0].mode(), Mode::Block);
+        assert_eq!(inhibitors[0].what().types(), &vec![InhibitType::Idle]);
+        assert_eq!(inhibitors[1].what().types(), &vec![InhibitType::Sleep]);
+        assert!(inhibitions.has(InhibitType::Sleep));
+
+        assert_eq!(inhibitions.remove_owner(":1.20"), 2);
+        assert!(inhibitions.list().is_empty());
+        assert!(!inhibitions.has(InhibitType::Sleep));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inhibitor_ages() {
+        let inhibitions = ApplicationInhibitions::new();
+        inhibitions.add(inhibition(":1.10", "firefox"));
+        let mut ages = InhibitorAges::default();
+        let active = ages.observe(inhibitions.to_inhibitors());
+        assert_eq!(active[0].age, Duration::ZERO);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let vlc = inhibitions.add(inhibition(":1.20", "vlc"));
+        let active = ages.observe(inhibitions.to_inhibitors());
+        assert_eq!(active[0].age, Duration::from_secs(60));
+        assert_eq!(active[1].age, Duration::ZERO);
+
+        // A released inhibitor starts aging again once it's taken again
+        inhibitions.remove(":1.20", vlc);
+        ages.observe(inhibitions.to_inhibitors());
+        tokio::time::advance(Duration::from_secs(60)).await;
+        inhibitions.add(inhibition(":1.20", "vlc"));
+        let active = ages.observe(inhibitions.to_inhibitors());
+        assert_eq!(active[0].age, Duration::from_secs(120));
+        assert_eq!(active[1].age, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_inhibitor_cache() {
+        let inhibitions = ApplicationInhibitions::new();
+        inhibitions.add(inhibition(":1.10", "firefox"));
+        let mut cache = InhibitorCache::default();
+        assert!(cache.get_fresh().is_none());
+        assert!(cache.get_stale().is_none());
+
+        cache.store(inhibitions.to_inhibitors());
+        assert_eq!(cache.get_fresh().unwrap().len(), 1);
+        tokio::time::advance(CACHE_TTL).await;
+        assert!(cache.get_fresh().is_none());
+        assert_eq!(cache.get_stale().unwrap().len(), 1);
+
+        cache.store(inhibitions.to_inhibitors());
+        cache.invalidator().store(true, Ordering::SeqCst);
+        assert!(cache.get_fresh().is_none());
+        // The invalidation is consumed by the caller, which lists the
+        // inhibitors again
+        assert!(cache.get_fresh().is_some());
+        assert!(cache.get_stale().is_some());
+
+        tokio::time::advance(STALE_LIMIT).await;
+        assert!(cache.get_stale().is_none());
+    }
+}
@@ -2,29 +2,97 @@
 
 use crate::{
     armaf::{
-        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, RollbackStrategy, Server,
+        spawn_server, ConfigSchema, Effect, Effector, EffectorCapabilities, EffectorMessage,
+        EffectorPort, EffectorResponse, RollbackStrategy, Server, ValueType,
     },
-    external::dependency_provider::DependencyProvider,
+    doctor::find_executable,
+    external::{dependency_provider::DependencyProvider, display_server::x11::X11Interface},
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use logind_zbus::{manager::InhibitType, session::SessionProxy};
 use serde::Deserialize;
+use std::{env, time::Duration};
 use tokio::{
-    process::Command,
-    sync::oneshot::{self, error::TryRecvError},
+    process::{Child, Command},
+    sync::{
+        oneshot::{self, error::TryRecvError},
+        watch,
+    },
+    time::Instant,
 };
 
+/// How many times in a row the locker may crash shortly after being started
+/// before it isn't started again
+const MAX_QUICK_CRASHES: u32 = 5;
+/// A locker crashing after running for at least this long is started again
+/// regardless of the previous crashes
+const QUICK_CRASH_PERIOD: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CommandStrings {
     command: String,
     args: Vec<String>,
+    /// X display on which the locker is started, the one given by the
+    /// environment if [None]
+    #[serde(skip)]
+    display: Option<String>,
+}
+
+impl CommandStrings {
+    /// The program started as the locker
+    pub fn program(&self) -> &str {
+        &self.command
+    }
+
+    /// Start the locker on the given X display
+    pub fn on_display(mut self, display: &str) -> CommandStrings {
+        self.display = Some(display.to_owned());
+        self
+    }
+
+    fn spawn(&self) -> std::io::Result<Child> {
+        let mut command = Command::new(&self.command);
+        command.args(&self.args);
+        if let Some(display) = self.display.as_ref() {
+            command.env("DISPLAY", display);
+        }
+        command.spawn()
+    }
+
+    /// Command starting the built-in locker in a new energia process
+    #[cfg(feature = "builtin-locker")]
+    fn builtin() -> Result<CommandStrings> {
+        Ok(CommandStrings {
+            command: std::env::current_exe()?.to_string_lossy().into_owned(),
+            args: vec!["lock".to_owned()],
+            display: None,
+        })
+    }
 }
 
 pub struct LockEffector;
 
+impl LockEffector {
+    /// Parse the `[lock]` configuration section into the locker command.
+    /// Without the section, the built-in locker is used if it's compiled in.
+    pub fn parse_config(config: Option<toml::Value>) -> Result<CommandStrings> {
+        match config {
+            #[cfg(feature = "builtin-locker")]
+            None => CommandStrings::builtin(),
+            #[cfg(not(feature = "builtin-locker"))]
+            None => bail!("When lock is in schedule, [lock] section must be provided in config"),
+            Some(config) => Ok(config.try_into()?),
+        }
+    }
+}
+
 #[async_trait]
 impl Effector for LockEffector {
+    fn get_name(&self) -> String {
+        "lock".to_owned()
+    }
+
     fn get_effects(&self) -> Vec<Effect> {
         vec![Effect::new(
             "lock".to_owned(),
@@ -33,20 +101,40 @@ impl Effector for LockEffector {
         )]
     }
 
-    async fn spawn<B, D>(
+    fn get_config_schema(&self) -> ConfigSchema {
+        let schema = ConfigSchema::new();
+        #[cfg(not(feature = "builtin-locker"))]
+        let schema = schema.required();
+        schema
+            .key("command", ValueType::String)
+            .key("args", ValueType::StringArray)
+    }
+
+    async fn spawn(
         &self,
         config: Option<toml::Value>,
-        dp: &mut DependencyProvider<B, D>,
-    ) -> Result<EffectorPort>
-    where
-        B: crate::external::brightness::BrightnessController,
-        D: crate::external::display_server::DisplayServer,
-    {
-        if config.is_none() {
-            bail!("When lock is in schedule, [lock] section must be provided in config");
-        }
-        let command_strings = config.unwrap().try_into()?;
-        let actor = LockEffectorActor::new(command_strings, dp.get_dbus_system_connection().await?);
+        dp: &mut DependencyProvider,
+    ) -> Result<EffectorPort> {
+        let mut command_strings = LockEffector::parse_config(config)?;
+        if let Some(display) = dp.get_display_name() {
+            command_strings = command_strings.on_display(display);
+        }
+        let mut actor =
+            LockEffectorActor::new(command_strings, dp.get_dbus_system_connection().await?);
+        if !dp.has_logind_session() {
+            log::warn!(
+                "{}, the session's lock hint won't be set",
+                dp.no_logind_session_reason()
+            );
+            actor = actor.without_session_hints();
+        }
+        match X11Interface::watch_screen_changes(dp.get_display_name()) {
+            Ok(screen_changes) => actor = actor.with_screen_changes(screen_changes),
+            Err(e) => log::warn!(
+                "{:#}, the locker won't be restarted when the monitors change",
+                e
+            ),
+        }
         spawn_server(actor).await
     }
 }
@@ -56,6 +144,8 @@ pub struct LockEffectorActor {
     status_receiver: Option<oneshot::Receiver<Result<()>>>,
     connection: zbus::Connection,
     session_proxy: Option<SessionProxy<'static>>,
+    session_hints: bool,
+    screen_changes: Option<watch::Receiver<u64>>,
 }
 
 impl LockEffectorActor {
@@ -65,9 +155,28 @@ impl LockEffectorActor {
             status_receiver: None,
             connection: system_connection,
             session_proxy: None,
+            session_hints: true,
+            screen_changes: None,
         }
     }
 
+    /// Lock without setting the hints of the session, e.g. when the process
+    /// doesn't belong to one. Locks by other programs aren't noticed then.
+    pub fn without_session_hints(mut self) -> LockEffectorActor {
+        self.session_hints = false;
+        self
+    }
+
+    /// Restart the running locker whenever a value is sent through the
+    /// channel, see [X11Interface::watch_screen_changes]
+    pub fn with_screen_changes(
+        mut self,
+        screen_changes: watch::Receiver<u64>,
+    ) -> LockEffectorActor {
+        self.screen_changes = Some(screen_changes);
+        self
+    }
+
     fn update_child_status(&mut self) {
         if let Some(receiver) = self.status_receiver.as_mut() {
             match receiver.try_recv() {
@@ -86,53 +195,158 @@ impl LockEffectorActor {
         }
     }
 
-    fn spawn_locker(&mut self) {
+    /// Whether the session is locked by another program, which can only be
+    /// told with the session hints
+    async fn is_locked_elsewhere(&self) -> Result<bool> {
+        match self.session_proxy.as_ref() {
+            Some(proxy) => Ok(proxy.locked_hint().await?),
+            None => Ok(false),
+        }
+    }
+
+    /// Start the locker and wait until it's running and the session's
+    /// LockedHint is set, so that the caller can rely on the session being
+    /// locked, e.g. before the computer goes to sleep.
+    ///
+    /// A locker which crashes is started again, so that the session doesn't
+    /// end up unlocked, and so is a locker running while the monitors change,
+    /// so that it covers the new ones as well.
+    async fn spawn_locker(&mut self) -> Result<()> {
         let (sender, receiver) = oneshot::channel();
+        let (ready_sender, ready_receiver) = oneshot::channel();
         self.status_receiver = Some(receiver);
-        let sent_command = self.command.clone();
-        let sent_proxy = self.session_proxy.as_ref().unwrap().clone();
+        let command = self.command.clone();
+        let proxy = self.session_proxy.clone();
+        let mut screen_changes = self.screen_changes.clone();
+        if let Some(screen_changes) = screen_changes.as_mut() {
+            screen_changes.borrow_and_update();
+        }
         tokio::spawn(async move {
-            let spawn_res = Command::new(sent_command.command)
-                .args(sent_command.args)
-                .spawn();
-            match spawn_res {
-                Err(e) => {
-                    let _ = sender.send(Err(anyhow::Error::new(e)));
+            let mut ready_sender = Some(ready_sender);
+            let result =
+                watch_locker(&command, proxy.as_ref(), &mut ready_sender, screen_changes).await;
+            log::debug!("Locker has quit");
+            // The hints were set only if the locker has started
+            if let (None, Some(proxy)) = (&ready_sender, &proxy) {
+                set_session_hints(proxy, false).await;
+                log::debug!("LockedHint unset");
+            }
+            if sender.send(result).is_err() {
+                log::error!("Failed to send locker termination notification to lock effector");
+            }
+            log::debug!("Lock watcher quitting");
+        });
+        if ready_receiver.await.is_err() {
+            // The ready sender is only dropped without sending if the
+            // locker couldn't be spawned
+            let status = self.status_receiver.take().unwrap().await;
+            return match status {
+                Ok(Err(e)) => Err(e.context("Couldn't start the locker")),
+                _ => Err(anyhow!("Locker watch task died")),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Run the locker until it exits successfully, i.e. the user unlocks the
+/// session, restarting it after crashes and screen changes. The ready sender
+/// is taken once the locker is started and the session's hints are set.
+async fn watch_locker(
+    command: &CommandStrings,
+    proxy: Option<&SessionProxy<'static>>,
+    ready_sender: &mut Option<oneshot::Sender<()>>,
+    mut screen_changes: Option<watch::Receiver<u64>>,
+) -> Result<()> {
+    let mut process = command.spawn()?;
+    log::debug!("Locker spawned");
+    if let Some(proxy) = proxy {
+        set_session_hints(proxy, true).await;
+        log::debug!("Lock hint set");
+    }
+    if let Some(ready_sender) = ready_sender.take() {
+        let _ = ready_sender.send(());
+    }
+    let mut started_at = Instant::now();
+    let mut quick_crashes = 0;
+    loop {
+        let status = tokio::select! {
+            status = process.wait() => status?,
+            changed = async { screen_changes.as_mut().unwrap().changed().await }, if screen_changes.is_some() => {
+                if changed.is_err() {
+                    screen_changes = None;
+                    continue;
                 }
-                Ok(mut process) => {
-                    log::debug!("Locker spawned");
-                    if let Err(e) = sent_proxy.set_locked_hint(true).await {
-                        log::error!("Failed to set locked hint on the session: {}", e);
-                    }
-                    log::debug!("Lock hint set");
-                    let res = process.wait().await;
-                    log::debug!("Locker has quit");
-                    if let Err(e) = sent_proxy.set_locked_hint(false).await {
-                        log::error!("Failed to unset locked hint on the session: {}", e);
-                    }
-                    log::debug!("LockedHint unset");
-                    if sender
-                        .send(res.map(|_| ()).map_err(anyhow::Error::new))
-                        .is_err()
-                    {
-                        log::error!(
-                            "Failed to send locker termination notification to lock effector"
-                        );
-                    }
-                    log::debug!("Lock watcher quitting");
+                log::info!("Screen configuration changed, restarting the locker");
+                // The new locker is started before the old one is stopped,
+                // so that the screen stays covered
+                let new_process = command.spawn()?;
+                if let Err(e) = process.kill().await {
+                    log::error!("Couldn't stop the previous locker: {}", e);
                 }
+                process = new_process;
+                started_at = Instant::now();
+                continue;
             }
-        });
+        };
+        if status.success() {
+            return Ok(());
+        }
+        if started_at.elapsed() < QUICK_CRASH_PERIOD {
+            quick_crashes += 1;
+        } else {
+            quick_crashes = 1;
+        }
+        if quick_crashes > MAX_QUICK_CRASHES {
+            bail!(
+                "Locker has crashed {} times in a row, giving up",
+                quick_crashes
+            );
+        }
+        log::error!("Locker exited with {}, starting it again", status);
+        process = command.spawn()?;
+        started_at = Instant::now();
+    }
+}
+
+/// Set or unset the LockedHint and the IdleHint of the session. A locked
+/// session is idle, whenever the idle hint effect is scheduled, and the
+/// session effector leaves the idle hint alone while the session is locked.
+async fn set_session_hints(proxy: &SessionProxy<'static>, locked: bool) {
+    if let Err(e) = proxy.set_locked_hint(locked).await {
+        log::error!(
+            "Failed to set locked hint on the session to {}: {}",
+            locked,
+            e
+        );
+    }
+    if let Err(e) = proxy.set_idle_hint(locked).await {
+        log::error!(
+            "Failed to set idle hint on the session to {}: {}",
+            locked,
+            e
+        );
     }
 }
 
 #[async_trait]
-impl Server<EffectorMessage, usize> for LockEffectorActor {
+impl Server<EffectorMessage, EffectorResponse> for LockEffectorActor {
     fn get_name(&self) -> String {
         "LockEffector".to_string()
     }
 
+    fn get_deadline(&self, payload: &EffectorMessage) -> Option<Duration> {
+        match payload {
+            // Waits for the user to unlock the computer
+            EffectorMessage::Rollback | EffectorMessage::EnsureRolledBack => None,
+            _ => Some(Duration::from_secs(10)),
+        }
+    }
+
     async fn initialize(&mut self) -> Result<()> {
+        if !self.session_hints {
+            return Ok(());
+        }
         let manager_proxy = logind_zbus::manager::ManagerProxy::new(&self.connection).await?;
         let path = manager_proxy.get_session_by_PID(std::process::id()).await?;
         self.session_proxy = Some(
@@ -144,7 +358,7 @@ impl Server<EffectorMessage, usize> for LockEffectorActor {
         Ok(())
     }
 
-    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<usize> {
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
         self.update_child_status();
         let is_locked = self.status_receiver.is_some();
         match payload {
@@ -152,22 +366,47 @@ impl Server<EffectorMessage, usize> for LockEffectorActor {
                 if is_locked {
                     bail!("System is already locked");
                 }
-                self.spawn_locker();
-                Ok(1)
+                self.spawn_locker().await?;
             }
-            EffectorMessage::Rollback => {
+            EffectorMessage::EnsureApplied => {
+                if is_locked {
+                    log::debug!("System is already locked");
+                } else if self.is_locked_elsewhere().await? {
+                    return Ok(locked_elsewhere_response());
+                } else {
+                    self.spawn_locker().await?;
+                }
+            }
+            EffectorMessage::Rollback | EffectorMessage::EnsureRolledBack => {
                 if is_locked {
                     self.status_receiver.take().unwrap().await??;
                 }
-                Ok(0)
+            }
+            EffectorMessage::CanExecute => {
+                if !is_locked {
+                    find_executable(self.command.program(), env::var_os("PATH").as_deref())?;
+                }
             }
             EffectorMessage::CurrentlyAppliedEffects => {
-                if is_locked {
-                    Ok(1)
-                } else {
-                    Ok(0)
+                // E.g. by the locker of a previous run which has crashed, so
+                // that the sequence can continue instead of locking again
+                if !is_locked && self.is_locked_elsewhere().await? {
+                    return Ok(locked_elsewhere_response());
                 }
             }
         }
+        let response = if self.status_receiver.is_some() {
+            EffectorResponse::new(1).with_status(format!("{} is running", self.command.command))
+        } else {
+            EffectorResponse::new(0)
+        };
+        Ok(response.with_capabilities(EffectorCapabilities::waiting_rollback()))
     }
 }
+
+/// The session is locked, but not by our locker, so it can't be waited for
+fn locked_elsewhere_response() -> EffectorResponse {
+    EffectorResponse::new(1)
+        .with_status("session is locked by another program")
+        .with_capabilities(EffectorCapabilities::waiting_rollback())
+}
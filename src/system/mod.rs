@@ -1,12 +1,23 @@
 //! System-layer actors - sensors and effectors
 
+pub mod blank_effector;
 pub mod brightness_effector;
+#[cfg(feature = "builtin-locker")]
+pub mod builtin_locker;
+pub mod clock_sensor;
 pub mod dpms_effector;
+pub mod environment_sensor;
 pub mod inhibition_sensor;
 pub mod lock_effector;
+pub mod output_sensor;
+pub mod plugin_effector;
 pub mod session_effector;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulated_effector;
 pub mod sleep_effector;
 pub mod sleep_sensor;
+pub mod sysfs_power_sensor;
+pub mod unavailable_effector;
 pub mod upower_sensor;
 
 #[cfg(test)]
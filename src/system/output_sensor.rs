@@ -0,0 +1,67 @@
+//! Detects monitors being connected to and disconnected from the computer
+
+use crate::external::display_server::x11::X11Interface;
+use anyhow::Result;
+use tokio::sync::watch;
+
+/// Prefixes of the names of outputs driving the computer's built-in panel
+const INTERNAL_OUTPUT_PREFIXES: [&str; 3] = ["eDP", "LVDS", "DSI"];
+
+/// Names of the outputs with a connected monitor
+pub type ConnectedOutputs = Vec<String>;
+
+/// Start watching the outputs of the X display, publishing the names of the
+/// connected ones whenever they change
+pub fn spawn_output_sensor(
+    display_name: Option<&str>,
+) -> Result<watch::Receiver<ConnectedOutputs>> {
+    X11Interface::watch_outputs(display_name)
+}
+
+/// Whether the output drives the computer's built-in panel
+pub fn is_internal_output(name: &str) -> bool {
+    INTERNAL_OUTPUT_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Whether a monitor other than the built-in panel is connected
+pub fn has_external_monitor(outputs: &[String]) -> bool {
+    outputs.iter().any(|output| !is_internal_output(output))
+}
+
+/// Outputs which are connected now, but weren't before
+pub fn newly_connected<'a>(previous: &[String], current: &'a [String]) -> Vec<&'a str> {
+    current
+        .iter()
+        .filter(|output| !previous.contains(output))
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outputs(names: &[&str]) -> ConnectedOutputs {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_external_monitors() {
+        assert!(!has_external_monitor(&outputs(&[])));
+        assert!(!has_external_monitor(&outputs(&["eDP-1"])));
+        assert!(!has_external_monitor(&outputs(&["LVDS1"])));
+        assert!(has_external_monitor(&outputs(&["eDP-1", "HDMI-1"])));
+        assert!(has_external_monitor(&outputs(&["DP-2"])));
+    }
+
+    #[test]
+    fn test_newly_connected() {
+        let laptop = outputs(&["eDP-1"]);
+        let docked = outputs(&["DP-1", "DP-2", "eDP-1"]);
+        assert_eq!(newly_connected(&laptop, &docked), vec!["DP-1", "DP-2"]);
+        assert!(newly_connected(&docked, &laptop).is_empty());
+        assert!(newly_connected(&docked, &docked).is_empty());
+    }
+}
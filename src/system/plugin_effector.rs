@@ -0,0 +1,514 @@
+//! Effectors provided by external programs, configured in the `[plugins]`
+//! section.
+//!
+//! A plugin is a program which reads requests from its standard input and
+//! writes a response to each of them to its standard output, both as JSON
+//! objects on a single line. When Energia starts, it runs each plugin to
+//! `describe` the effects it provides and registers it as an effector named
+//! after its entry in `[plugins]`. Each spawned instance of the effector then
+//! runs its own process, which gets the effector's configuration section in
+//! the `start` request and is told to close by its standard input being
+//! closed.
+
+use crate::{
+    armaf::{
+        inhibit_type_from_config_name, spawn_server, ConfigSchema, Effect, Effector,
+        EffectorMessage, EffectorPort, EffectorResponse, RollbackStrategy, Server,
+    },
+    control::effector_registry::register_effector,
+    external::dependency_provider::DependencyProvider,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use logind_zbus::manager::InhibitType;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::Stdio,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+
+/// Name of the configuration section listing the plugins
+pub const PLUGINS_SECTION: &str = "plugins";
+/// The longest time a plugin may take to describe itself or to respond to a
+/// request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time given to a plugin to exit once its standard input is closed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How a plugin is started
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PluginCommand {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum PluginRequest {
+    Describe,
+    Start { config: Option<serde_json::Value> },
+    Execute,
+    Rollback,
+    State,
+    EnsureApplied,
+    EnsureRolledBack,
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginResponse {
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    effects: Vec<PluginEffect>,
+    #[serde(default)]
+    applied_effects: usize,
+    #[serde(default)]
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginEffect {
+    name: String,
+    #[serde(default = "default_rollback")]
+    rollback: String,
+    #[serde(default = "default_inhibited_by")]
+    inhibited_by: Vec<String>,
+}
+
+fn default_rollback() -> String {
+    "on_activity".to_owned()
+}
+
+fn default_inhibited_by() -> Vec<String> {
+    vec!["idle".to_owned()]
+}
+
+impl PluginEffect {
+    fn to_effect(&self) -> Result<Effect> {
+        let rollback_strategy = match RollbackStrategy::from_config_name(&self.rollback) {
+            Some(rollback_strategy) => rollback_strategy,
+            None => bail!(
+                "unknown rollback {} of effect {}, expected on_activity, immediate or none",
+                self.rollback,
+                self.name
+            ),
+        };
+        let inhibited_by = self
+            .inhibited_by
+            .iter()
+            .map(|inhibit_type| {
+                inhibit_type_from_config_name(inhibit_type).ok_or_else(|| {
+                    anyhow!(
+                        "unknown inhibitor type {} of effect {}, expected idle, sleep or shutdown",
+                        inhibit_type,
+                        self.name
+                    )
+                })
+            })
+            .collect::<Result<Vec<InhibitType>>>()?;
+        Ok(Effect::new(
+            self.name.clone(),
+            inhibited_by,
+            rollback_strategy,
+        ))
+    }
+}
+
+impl PluginResponse {
+    fn parse(line: &str) -> Result<PluginResponse> {
+        let response: PluginResponse =
+            serde_json::from_str(line).with_context(|| format!("Invalid response {}", line))?;
+        match response.error {
+            Some(error) => Err(anyhow!("{}", error)),
+            None => Ok(response),
+        }
+    }
+}
+
+fn encode_request(request: &PluginRequest) -> Result<String> {
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Parse the plugins configured in the `[plugins]` section, by name
+pub fn parse_plugins(config: &toml::Value) -> Result<Vec<(String, PluginCommand)>> {
+    let section = match config.get(PLUGINS_SECTION) {
+        Some(toml::Value::Table(section)) => section,
+        Some(other) => bail!("[{}] must be a table, not {}", PLUGINS_SECTION, other),
+        None => return Ok(Vec::new()),
+    };
+    section
+        .iter()
+        .map(|(name, plugin)| {
+            let command: PluginCommand = plugin
+                .clone()
+                .try_into()
+                .with_context(|| format!("Invalid {}.{}", PLUGINS_SECTION, name))?;
+            Ok((name.clone(), command))
+        })
+        .collect()
+}
+
+/// Describe all the configured plugins and register them as effectors,
+/// returning their names
+pub fn register_plugins(config: &toml::Value) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for (name, command) in parse_plugins(config)? {
+        let effects = describe_plugin(&command)
+            .with_context(|| format!("Couldn't describe plugin {}", name))?;
+        register_effector(Arc::new(PluginEffector {
+            name: name.clone(),
+            command,
+            effects,
+        }))
+        .with_context(|| format!("Couldn't register plugin {}", name))?;
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Run the plugin to get the effects it provides.
+///
+/// This blocks the calling thread, so that it doesn't depend on the runtime's
+/// clock, which is paused in simulations.
+fn describe_plugin(command: &PluginCommand) -> Result<Vec<Effect>> {
+    let mut child = std::process::Command::new(&command.command)
+        .args(&command.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Couldn't start {}", command.command))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    stdin.write_all(encode_request(&PluginRequest::Describe)?.as_bytes())?;
+    drop(stdin);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = sender.send(BufReader::new(stdout).read_line(&mut line).map(|_| line));
+    });
+    let line = receiver.recv_timeout(RESPONSE_TIMEOUT);
+    let _ = child.kill();
+    let _ = child.wait();
+    let line = line
+        .map_err(|_| anyhow!("no response in {:?}", RESPONSE_TIMEOUT))?
+        .context("Couldn't read the response")?;
+    let response = PluginResponse::parse(&line)?;
+    if response.effects.is_empty() {
+        bail!("plugin doesn't provide any effects");
+    }
+    response
+        .effects
+        .iter()
+        .map(PluginEffect::to_effect)
+        .collect()
+}
+
+/// An effector provided by a plugin
+pub struct PluginEffector {
+    name: String,
+    command: PluginCommand,
+    effects: Vec<Effect>,
+}
+
+#[async_trait]
+impl Effector for PluginEffector {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_effects(&self) -> Vec<Effect> {
+        self.effects.clone()
+    }
+
+    fn get_config_schema(&self) -> ConfigSchema {
+        // The plugin validates its configuration itself when started
+        ConfigSchema::new().allow_any_keys()
+    }
+
+    async fn spawn(
+        &self,
+        config: Option<toml::Value>,
+        _: &mut DependencyProvider,
+    ) -> Result<EffectorPort> {
+        spawn_server(PluginEffectorActor::new(
+            &self.name,
+            self.command.clone(),
+            config,
+        ))
+        .await
+    }
+}
+
+struct PluginProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<tokio::io::BufReader<ChildStdout>>,
+    /// Set while a request is being processed. If it's still set when the
+    /// next request comes, the previous request has timed out and its
+    /// response may still arrive, so the process can't be used anymore.
+    awaiting_response: bool,
+}
+
+pub struct PluginEffectorActor {
+    name: String,
+    command: PluginCommand,
+    config: Option<toml::Value>,
+    process: Option<PluginProcess>,
+}
+
+impl PluginEffectorActor {
+    pub fn new(
+        name: &str,
+        command: PluginCommand,
+        config: Option<toml::Value>,
+    ) -> PluginEffectorActor {
+        PluginEffectorActor {
+            name: name.to_owned(),
+            command,
+            config,
+            process: None,
+        }
+    }
+
+    /// Start the plugin's process and send it the `start` request
+    async fn start_process(&mut self) -> Result<()> {
+        let mut child = Command::new(&self.command.command)
+            .args(&self.command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Couldn't start {}", self.command.command))?;
+        self.process = Some(PluginProcess {
+            stdin: child.stdin.take().unwrap(),
+            stdout: tokio::io::BufReader::new(child.stdout.take().unwrap()).lines(),
+            child,
+            awaiting_response: false,
+        });
+        let config = self.config.as_ref().map(serde_json::to_value).transpose()?;
+        tokio::time::timeout(
+            RESPONSE_TIMEOUT,
+            self.request(PluginRequest::Start { config }),
+        )
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "plugin {} didn't start in {:?}",
+                self.name,
+                RESPONSE_TIMEOUT
+            )
+        })??;
+        Ok(())
+    }
+
+    async fn request(&mut self, request: PluginRequest) -> Result<PluginResponse> {
+        if matches!(self.process.as_ref(), Some(process) if process.awaiting_response) {
+            log::warn!("Plugin {} didn't respond in time, restarting it", self.name);
+            // The process is killed when dropped
+            self.process = None;
+            self.start_process().await?;
+        }
+        let process = self
+            .process
+            .as_mut()
+            .ok_or_else(|| anyhow!("plugin {} isn't running", self.name))?;
+        process.awaiting_response = true;
+        process
+            .stdin
+            .write_all(encode_request(&request)?.as_bytes())
+            .await?;
+        process.stdin.flush().await?;
+        let line = process
+            .stdout
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("plugin {} has exited", self.name))?;
+        process.awaiting_response = false;
+        PluginResponse::parse(&line).with_context(|| format!("Plugin {} failed", self.name))
+    }
+}
+
+#[async_trait]
+impl Server<EffectorMessage, EffectorResponse> for PluginEffectorActor {
+    fn get_name(&self) -> String {
+        format!("PluginEffector({})", self.name)
+    }
+
+    fn get_default_deadline(&self) -> Option<Duration> {
+        Some(RESPONSE_TIMEOUT)
+    }
+
+    async fn initialize(&mut self) -> Result<()> {
+        self.start_process().await
+    }
+
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        let request = match payload {
+            EffectorMessage::Execute => PluginRequest::Execute,
+            EffectorMessage::Rollback => PluginRequest::Rollback,
+            // A plugin which answers can execute its effects
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {
+                PluginRequest::State
+            }
+            EffectorMessage::EnsureApplied => PluginRequest::EnsureApplied,
+            EffectorMessage::EnsureRolledBack => PluginRequest::EnsureRolledBack,
+        };
+        let response = self.request(request).await?;
+        let effector_response = EffectorResponse::new(response.applied_effects);
+        Ok(match response.status {
+            Some(status) => effector_response.with_status(status),
+            None => effector_response,
+        })
+    }
+
+    async fn tear_down(&mut self) -> Result<()> {
+        if let Some(PluginProcess {
+            mut child, stdin, ..
+        }) = self.process.take()
+        {
+            drop(stdin);
+            if tokio::time::timeout(EXIT_TIMEOUT, child.wait())
+                .await
+                .is_err()
+            {
+                log::warn!("Plugin {} didn't exit, killing it", self.name);
+                child.kill().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A plugin written in shell, providing a single effect
+    fn script_plugin() -> PluginCommand {
+        let script = r#"
+            applied=0
+            while read -r request; do
+                case "$request" in
+                    *describe*) echo '{"effects": [{"name": "plugin_test_effect", "rollback": "immediate"}]}' ;;
+                    *start*) echo '{}' ;;
+                    *'"execute"'*) applied=1; echo '{"applied_effects": 1, "status": "on"}' ;;
+                    *'"rollback"'*) applied=0; echo '{"applied_effects": 0}' ;;
+                    *state*) echo "{\"applied_effects\": $applied}" ;;
+                    *) echo '{"error": "unsupported"}' ;;
+                esac
+            done
+        "#;
+        PluginCommand {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_parse_plugins() {
+        let config: toml::Value = toml::from_str(
+            r#"
+            [plugins.keyboard]
+            command = "energia-keyboard"
+            args = ["--all"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_plugins(&config).unwrap(),
+            vec![(
+                "keyboard".to_owned(),
+                PluginCommand {
+                    command: "energia-keyboard".to_owned(),
+                    args: vec!["--all".to_owned()],
+                }
+            )]
+        );
+        let invalid: toml::Value = toml::from_str("[plugins.keyboard]\nargs = []").unwrap();
+        assert!(parse_plugins(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_describe_plugin() {
+        let effects = describe_plugin(&script_plugin()).unwrap();
+        assert_eq!(effects.len(), 1);
+        assert_eq!(effects[0].name, "plugin_test_effect");
+        assert!(matches!(
+            effects[0].rollback_strategy,
+            RollbackStrategy::Immediate
+        ));
+        assert_eq!(effects[0].inhibited_by, vec![InhibitType::Idle]);
+
+        let failing = PluginCommand {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), "echo '{\"error\": \"broken\"}'".to_owned()],
+        };
+        assert_eq!(describe_plugin(&failing).unwrap_err().to_string(), "broken");
+    }
+
+    #[tokio::test]
+    async fn test_plugin_effector() {
+        let port = spawn_server(PluginEffectorActor::new("test", script_plugin(), None))
+            .await
+            .unwrap();
+        let response = port.request(EffectorMessage::Execute).await.unwrap();
+        assert_eq!(response.applied_effects, 1);
+        assert_eq!(response.status.as_deref(), Some("on"));
+        assert_eq!(
+            port.request(EffectorMessage::CurrentlyAppliedEffects)
+                .await
+                .unwrap()
+                .applied_effects,
+            1
+        );
+        assert_eq!(
+            port.request(EffectorMessage::Rollback)
+                .await
+                .unwrap()
+                .applied_effects,
+            0
+        );
+        assert!(port.request(EffectorMessage::EnsureApplied).await.is_err());
+        port.await_shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_restart_after_timeout() {
+        let script = r#"
+            while read -r request; do
+                case "$request" in
+                    *start*) echo '{}' ;;
+                    *'"execute"'*) sleep 1; echo '{"applied_effects": 1, "status": "on"}' ;;
+                    *state*) echo '{"applied_effects": 0}' ;;
+                esac
+            done
+        "#;
+        let command = PluginCommand {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+        };
+        let mut actor = PluginEffectorActor::new("test", command, None);
+        actor.initialize().await.unwrap();
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            actor.request(PluginRequest::Execute)
+        )
+        .await
+        .is_err());
+        // The late response to execute mustn't be taken for the state
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let response = actor.request(PluginRequest::State).await.unwrap();
+        assert_eq!(response.applied_effects, 0);
+        assert_eq!(response.status, None);
+        actor.tear_down().await.unwrap();
+    }
+}
@@ -2,23 +2,26 @@
 
 use crate::{
     armaf::{
-        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, RollbackStrategy, Server,
-    },
-    external::{
-        brightness::BrightnessController, dependency_provider::DependencyProvider,
-        display_server as ds,
+        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, EffectorResponse,
+        RollbackStrategy, Server,
     },
+    external::dependency_provider::DependencyProvider,
+    system::unavailable_effector::UnavailableEffectorActor,
 };
 use anyhow::Result;
 use async_trait::async_trait;
 use log;
 use logind_zbus::{self, manager::InhibitType, session::SessionProxy};
-use std::process;
+use std::{process, time::Duration};
 
 pub struct SessionEffector;
 
 #[async_trait]
 impl Effector for SessionEffector {
+    fn get_name(&self) -> String {
+        "session".to_owned()
+    }
+
     fn get_effects(&self) -> Vec<Effect> {
         vec![Effect::new(
             "idle_hint".to_owned(),
@@ -27,11 +30,18 @@ impl Effector for SessionEffector {
         )]
     }
 
-    async fn spawn<B: BrightnessController, D: ds::DisplayServer>(
+    async fn spawn(
         &self,
         _: Option<toml::Value>,
-        provider: &mut DependencyProvider<B, D>,
+        provider: &mut DependencyProvider,
     ) -> Result<EffectorPort> {
+        if !provider.has_logind_session() {
+            return spawn_server(UnavailableEffectorActor::new(
+                "session",
+                provider.no_logind_session_reason(),
+            ))
+            .await;
+        }
         let actor = SessionEffectorActor::new(provider.get_dbus_system_connection().await?);
         spawn_server(actor).await
     }
@@ -53,14 +63,29 @@ impl SessionEffectorActor {
     fn get_session_proxy(&self) -> &SessionProxy<'static> {
         self.session_proxy.as_ref().unwrap()
     }
+
+    /// Set the idle hint to false, unless the session is locked. The hint of
+    /// a locked session is cleared by the lock effector once it's unlocked.
+    async fn clear_idle_hint(&self) -> Result<()> {
+        if self.get_session_proxy().locked_hint().await? {
+            log::debug!("Session is locked, leaving the idle hint to the lock effector");
+            return Ok(());
+        }
+        log::debug!("Setting idle hint to false");
+        Ok(self.get_session_proxy().set_idle_hint(false).await?)
+    }
 }
 
 #[async_trait]
-impl Server<EffectorMessage, usize> for SessionEffectorActor {
+impl Server<EffectorMessage, EffectorResponse> for SessionEffectorActor {
     fn get_name(&self) -> String {
         "SessionEffector".to_owned()
     }
 
+    fn get_default_deadline(&self) -> Option<Duration> {
+        Some(Duration::from_secs(10))
+    }
+
     async fn initialize(&mut self) -> Result<()> {
         let manager_proxy = logind_zbus::manager::ManagerProxy::new(&self.connection).await?;
         let path = manager_proxy.get_session_by_PID(process::id()).await?;
@@ -74,25 +99,38 @@ impl Server<EffectorMessage, usize> for SessionEffectorActor {
         Ok(())
     }
 
-    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<usize> {
-        match payload {
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        let idle_hint = match payload {
             EffectorMessage::Execute => {
                 log::debug!("Setting idle hint to true");
                 self.get_session_proxy().set_idle_hint(true).await?;
-                Ok(1)
+                true
             }
             EffectorMessage::Rollback => {
-                log::debug!("Setting idle hint to false");
-                self.get_session_proxy().set_idle_hint(false).await?;
-                Ok(0)
+                self.clear_idle_hint().await?;
+                false
+            }
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {
+                self.get_session_proxy().idle_hint().await?
             }
-            EffectorMessage::CurrentlyAppliedEffects => {
+            EffectorMessage::EnsureApplied => {
+                if !self.get_session_proxy().idle_hint().await? {
+                    log::debug!("Setting idle hint to true");
+                    self.get_session_proxy().set_idle_hint(true).await?;
+                }
+                true
+            }
+            EffectorMessage::EnsureRolledBack => {
                 if self.get_session_proxy().idle_hint().await? {
-                    Ok(1)
-                } else {
-                    Ok(0)
+                    self.clear_idle_hint().await?;
                 }
+                false
             }
-        }
+        };
+        Ok(if idle_hint {
+            EffectorResponse::new(1).with_status("session idle hint set")
+        } else {
+            EffectorResponse::new(0)
+        })
     }
 }
@@ -0,0 +1,88 @@
+//! Records the effects of another effector instead of applying them, used in
+//! simulation mode
+
+use crate::armaf::{Effect, EffectorMessage, EffectorResponse, Server};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::{sync::mpsc, time::Instant};
+
+/// What happened to a simulated effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedAction {
+    Executed,
+    RolledBack,
+}
+
+/// A single event recorded by a [SimulatedEffectorActor]
+#[derive(Debug, Clone)]
+pub struct SimulatedEvent {
+    pub at: Instant,
+    pub effect_name: String,
+    pub action: SimulatedAction,
+}
+
+pub struct SimulatedEffectorActor {
+    effector_name: String,
+    effects: Vec<Effect>,
+    applied: bool,
+    events: mpsc::UnboundedSender<SimulatedEvent>,
+}
+
+impl SimulatedEffectorActor {
+    pub fn new(
+        effector_name: &str,
+        effects: Vec<Effect>,
+        events: mpsc::UnboundedSender<SimulatedEvent>,
+    ) -> SimulatedEffectorActor {
+        SimulatedEffectorActor {
+            effector_name: effector_name.to_owned(),
+            effects,
+            applied: false,
+            events,
+        }
+    }
+
+    fn record(&self, action: SimulatedAction) {
+        for effect in self.effects.iter() {
+            // The receiver only goes away once the simulation is over
+            let _ = self.events.send(SimulatedEvent {
+                at: Instant::now(),
+                effect_name: effect.name.clone(),
+                action,
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl Server<EffectorMessage, EffectorResponse> for SimulatedEffectorActor {
+    fn get_name(&self) -> String {
+        format!("SimulatedEffector({})", self.effector_name)
+    }
+
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        match payload {
+            EffectorMessage::EnsureApplied if self.applied => Ok(EffectorResponse::new(1)),
+            EffectorMessage::EnsureRolledBack if !self.applied => Ok(EffectorResponse::new(0)),
+            EffectorMessage::Execute | EffectorMessage::EnsureApplied => {
+                if self.applied {
+                    return Err(anyhow!("Effect of {} already applied", self.effector_name));
+                }
+                self.applied = true;
+                self.record(SimulatedAction::Executed);
+                Ok(EffectorResponse::new(1))
+            }
+            EffectorMessage::Rollback | EffectorMessage::EnsureRolledBack => {
+                if !self.applied {
+                    return Err(anyhow!("Effect of {} is not applied", self.effector_name));
+                }
+                self.applied = false;
+                self.record(SimulatedAction::RolledBack);
+                Ok(EffectorResponse::new(0))
+            }
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::CanExecute => {
+                Ok(EffectorResponse::new(self.applied as usize))
+            }
+        }
+    }
+}
@@ -2,12 +2,11 @@
 
 use crate::{
     armaf::{
-        spawn_server, Effect, Effector, EffectorMessage, EffectorPort, RollbackStrategy, Server,
-    },
-    external::{
-        brightness::BrightnessController, dependency_provider::DependencyProvider,
-        display_server as ds,
+        spawn_server, Effect, Effector, EffectorCapabilities, EffectorMessage, EffectorPort,
+        EffectorResponse, RollbackStrategy, Server,
     },
+    external::dependency_provider::DependencyProvider,
+    system::unavailable_effector::UnavailableEffectorActor,
 };
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
@@ -19,6 +18,10 @@ pub struct SleepEffector;
 
 #[async_trait]
 impl Effector for SleepEffector {
+    fn get_name(&self) -> String {
+        "sleep".to_owned()
+    }
+
     fn get_effects(&self) -> Vec<Effect> {
         vec![Effect::new(
             "sleep".to_owned(),
@@ -27,11 +30,18 @@ impl Effector for SleepEffector {
         )]
     }
 
-    async fn spawn<B: BrightnessController, D: ds::DisplayServer>(
+    async fn spawn(
         &self,
         _: Option<toml::Value>,
-        provider: &mut DependencyProvider<B, D>,
+        provider: &mut DependencyProvider,
     ) -> Result<EffectorPort> {
+        if !provider.has_logind_session() {
+            return spawn_server(UnavailableEffectorActor::new(
+                "sleep",
+                provider.no_logind_session_reason(),
+            ))
+            .await;
+        }
         let actor = SleepEffectorActor::new(provider.get_dbus_system_connection().await?);
         spawn_server(actor).await
     }
@@ -54,11 +64,19 @@ impl SleepEffectorActor {
 }
 
 #[async_trait]
-impl Server<EffectorMessage, usize> for SleepEffectorActor {
+impl Server<EffectorMessage, EffectorResponse> for SleepEffectorActor {
     fn get_name(&self) -> String {
         "SleepEffector".to_owned()
     }
 
+    fn get_deadline(&self, payload: &EffectorMessage) -> Option<Duration> {
+        match payload {
+            // Waits for the computer to wake up
+            EffectorMessage::Rollback => None,
+            _ => Some(Duration::from_secs(10)),
+        }
+    }
+
     async fn initialize(&mut self) -> Result<()> {
         let manager_proxy = logind_zbus::manager::ManagerProxy::new(&self.connection).await?;
         self.sleep_signal_stream = Some(manager_proxy.receive_prepare_for_sleep().await?);
@@ -66,9 +84,17 @@ impl Server<EffectorMessage, usize> for SleepEffectorActor {
         Ok(())
     }
 
-    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<usize> {
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        let applied_effects = self.handle_effector_message(payload).await?;
+        Ok(EffectorResponse::new(applied_effects)
+            .with_capabilities(EffectorCapabilities::waiting_rollback()))
+    }
+}
+
+impl SleepEffectorActor {
+    async fn handle_effector_message(&mut self, payload: EffectorMessage) -> Result<usize> {
         match payload {
-            EffectorMessage::Execute => {
+            EffectorMessage::Execute | EffectorMessage::EnsureApplied => {
                 log::info!("Putting system to sleep");
                 self.manager_proxy.as_ref().unwrap().suspend(false).await?;
                 Ok(1)
@@ -93,7 +119,26 @@ impl Server<EffectorMessage, usize> for SleepEffectorActor {
                     }
                 }
             }
-            EffectorMessage::CurrentlyAppliedEffects => Ok(0),
+            EffectorMessage::CanExecute => {
+                let can_suspend: String = self
+                    .connection
+                    .call_method(
+                        Some("org.freedesktop.login1"),
+                        "/org/freedesktop/login1",
+                        Some("org.freedesktop.login1.Manager"),
+                        "CanSuspend",
+                        &(),
+                    )
+                    .await?
+                    .body()?;
+                // "challenge" means that the user will be asked to authenticate
+                match can_suspend.as_str() {
+                    "yes" | "challenge" => Ok(0),
+                    other => Err(anyhow!("logind doesn't allow suspending ({})", other)),
+                }
+            }
+            // The computer can't be asleep while we're handling the message
+            EffectorMessage::CurrentlyAppliedEffects | EffectorMessage::EnsureRolledBack => Ok(0),
         }
     }
 }
@@ -2,11 +2,14 @@
 
 use std::time::Duration;
 
-use crate::armaf::{Handle, HandleChild};
+use crate::armaf::{EventBus, Handle, HandleChild};
 use anyhow::Result;
 use logind_zbus::manager::{InhibitType, ManagerProxy, PrepareForSleepStream};
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
 use tokio_stream::StreamExt;
 
 #[derive(Debug, Clone, Copy)]
@@ -14,10 +17,52 @@ pub struct ReadyToSleep;
 
 #[derive(Debug, Clone)]
 pub enum SleepUpdate {
-    GoingToSleep(mpsc::Sender<ReadyToSleep>),
+    /// The system is preparing to go to sleep, which it will do once all the
+    /// subscribers confirm their readiness, but at the latest at the given
+    /// instant
+    GoingToSleep(mpsc::Sender<ReadyToSleep>, Instant),
     WokenUp,
 }
 
+/// Time since boot, including the time the system spent suspended, which
+/// the monotonic clock behind [Instant] and tokio's timers doesn't count
+pub fn boot_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // CLOCK_BOOTTIME is available since Linux 2.6.39, so this can't fail
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut time);
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Measures how long the system was suspended between a
+/// [SleepUpdate::GoingToSleep] and the following [SleepUpdate::WokenUp]
+#[derive(Debug, Default)]
+pub struct SuspendClock {
+    going_to_sleep_at: Option<(Duration, Instant)>,
+}
+
+impl SuspendClock {
+    /// Note the time when the system starts going to sleep
+    pub fn going_to_sleep(&mut self) {
+        self.going_to_sleep_at = Some((boot_time(), Instant::now()));
+    }
+
+    /// Return for how long the system was suspended since the last call to
+    /// [SuspendClock::going_to_sleep], zero if it wasn't called
+    pub fn woken_up(&mut self) -> Duration {
+        match self.going_to_sleep_at.take() {
+            Some((boot_time_then, instant_then)) => boot_time()
+                .saturating_sub(boot_time_then)
+                .saturating_sub(instant_then.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[non_exhaustive]
 enum SleepSensorError {
@@ -58,9 +103,9 @@ impl SleepSensor {
         }
     }
 
-    pub async fn spawn(mut self) -> Result<(Handle, broadcast::Sender<SleepUpdate>)> {
-        let (sender, _) = broadcast::channel(3);
-        let returned_sender = sender.clone();
+    /// Spawn the sensor, publishing [SleepUpdate]s on the given bus
+    pub async fn spawn(mut self, events: &EventBus) -> Result<Handle> {
+        let sender = events.sender();
         let manager_proxy = logind_zbus::manager::ManagerProxy::new(&self.connection).await?;
         self.max_delay_time = Duration::from_micros(manager_proxy.inhibit_delay_max_USec().await?);
         let (handle, handle_child) = Handle::new();
@@ -71,7 +116,7 @@ impl SleepSensor {
         tokio::spawn(async move {
             self.main_loop().await;
         });
-        Ok((handle, returned_sender))
+        Ok(handle)
     }
 
     async fn main_loop(mut self) {
@@ -129,8 +174,9 @@ impl SleepSensor {
                 log::info!("System is preparing to go to sleep, notifying actors");
                 let subscriber_count = self.sender.as_ref().unwrap().receiver_count();
                 let (confirmation_sender, confirmation_receiver) = mpsc::channel(subscriber_count);
-                self.sender.as_ref().unwrap().send(SleepUpdate::GoingToSleep(confirmation_sender))?;
-                self.wait_for_confirmations(confirmation_receiver, subscriber_count).await
+                let deadline = Instant::now() + self.max_delay_time;
+                self.sender.as_ref().unwrap().send(SleepUpdate::GoingToSleep(confirmation_sender, deadline))?;
+                self.wait_for_confirmations(confirmation_receiver, subscriber_count, deadline).await
             }
         }
     }
@@ -139,9 +185,10 @@ impl SleepSensor {
         &mut self,
         mut receiver: mpsc::Receiver<ReadyToSleep>,
         expected_confirmations: usize,
+        deadline: Instant,
     ) -> Result<(), SleepSensorError> {
         let mut received_confirmations = 0;
-        let timeout = tokio::time::sleep(self.max_delay_time);
+        let timeout = tokio::time::sleep_until(deadline);
         tokio::pin!(timeout);
         while received_confirmations < expected_confirmations {
             tokio::select! {
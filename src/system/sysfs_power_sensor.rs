@@ -0,0 +1,132 @@
+//! Detects the computer's power source and battery percentage by reading the
+//! power supplies in sysfs periodically, used when UPower isn't running
+
+use super::upower_sensor::PowerStatus;
+use crate::armaf::{TickService, Ticks};
+use anyhow::{anyhow, Result};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+/// How often the power supplies are read
+pub const POWER_SUPPLY_POLLING_PERIOD: Duration = Duration::from_secs(30);
+
+/// Directory in which the kernel lists the power supplies
+pub const POWER_SUPPLY_DIRECTORY: &str = "/sys/class/power_supply";
+
+/// Reads the power supplies every period and publishes the power status when
+/// it changes
+pub struct SysfsPowerSensor {
+    directory: PathBuf,
+    ticks: Ticks,
+    updates_sender: watch::Sender<PowerStatus>,
+}
+
+impl SysfsPowerSensor {
+    /// Start reading the power supplies in the directory, terminating once
+    /// all the receivers are dropped. Fails if the directory doesn't contain
+    /// any power supplies.
+    pub async fn spawn(
+        directory: &Path,
+        tick_service: &TickService,
+        period: Duration,
+    ) -> Result<watch::Receiver<PowerStatus>> {
+        let initial_status = read_power_status(directory).await?;
+        log::debug!(
+            "Power source on spawn of SysfsPowerSensor is {:?}",
+            initial_status
+        );
+        let (updates_sender, updates_receiver) = watch::channel(initial_status);
+        let mut sensor = SysfsPowerSensor {
+            directory: directory.to_owned(),
+            ticks: tick_service.subscribe(period),
+            updates_sender,
+        };
+        tokio::spawn(async move {
+            sensor.run().await;
+        });
+        Ok(updates_receiver)
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.updates_sender.closed() => {
+                    log::info!("All receivers closed, terminating");
+                    return;
+                }
+                _ = self.ticks.tick() => match read_power_status(&self.directory).await {
+                    Ok(status) => {
+                        if *self.updates_sender.borrow() != status {
+                            log::debug!("Updating power status: {:?}", status);
+                            let _ = self.updates_sender.send(status);
+                        }
+                    }
+                    Err(e) => log::error!("Couldn't read power supplies: {}", e),
+                },
+            }
+        }
+    }
+}
+
+/// Determine the power status from the power supplies in the directory.
+///
+/// The computer runs on battery if none of its mains or USB supplies is
+/// online or, if it doesn't have any, when a battery is discharging. The
+/// percentage is averaged over the system's batteries, the batteries of
+/// peripherals are ignored.
+pub async fn read_power_status(directory: &Path) -> Result<PowerStatus> {
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    let mut has_external = false;
+    let mut external_online = false;
+    let mut discharging = false;
+    let mut capacities = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        match read_attribute(&path, "type").await.as_deref() {
+            Some("Mains") | Some("USB") => {
+                has_external = true;
+                external_online |= read_attribute(&path, "online").await.as_deref() == Some("1");
+            }
+            Some("Battery") => {
+                if read_attribute(&path, "scope").await.as_deref() == Some("Device") {
+                    continue;
+                }
+                if let Some(capacity) = read_attribute(&path, "capacity")
+                    .await
+                    .and_then(|capacity| capacity.parse::<u64>().ok())
+                {
+                    capacities.push(capacity);
+                }
+                discharging |=
+                    read_attribute(&path, "status").await.as_deref() == Some("Discharging");
+            }
+            _ => {}
+        }
+    }
+    if !has_external && capacities.is_empty() {
+        return Err(anyhow!(
+            "no power supplies found in {}",
+            directory.display()
+        ));
+    }
+    let on_battery = if has_external {
+        !external_online
+    } else {
+        discharging
+    };
+    if !on_battery || capacities.is_empty() {
+        return Ok(PowerStatus::External);
+    }
+    let percentage = capacities.iter().sum::<u64>() / capacities.len() as u64;
+    Ok(PowerStatus::Battery(percentage))
+}
+
+async fn read_attribute(supply: &Path, attribute: &str) -> Option<String> {
+    tokio::fs::read_to_string(supply.join(attribute))
+        .await
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
@@ -0,0 +1,42 @@
+use crate::{
+    armaf::{spawn_server, EffectorMessage},
+    external::{display_server as ds, display_server::DisplayServer},
+    system::blank_effector::BlankEffectorActor,
+};
+
+#[tokio::test]
+async fn test_blanking() {
+    let display = ds::mock::Interface::new(-1);
+    let port = spawn_server(BlankEffectorActor::new(display.get_controller()))
+        .await
+        .expect("Actor initialization failed");
+
+    let res = port
+        .request(EffectorMessage::Execute)
+        .await
+        .expect("Failed to blank the display");
+    assert_eq!(res.applied_effects, 1);
+    assert!(display.is_screen_blanked());
+
+    let res = port
+        .request(EffectorMessage::EnsureApplied)
+        .await
+        .expect("Ensuring the display is blanked isn't idempotent");
+    assert_eq!(res.applied_effects, 1);
+    assert!(display.is_screen_blanked());
+
+    for _ in 0..2 {
+        let res = port
+            .request(EffectorMessage::EnsureRolledBack)
+            .await
+            .expect("Failed to ensure the display is unblanked");
+        assert_eq!(res.applied_effects, 0);
+        assert!(!display.is_screen_blanked());
+    }
+
+    port.request(EffectorMessage::Execute)
+        .await
+        .expect("Failed to blank the display");
+    port.await_shutdown().await;
+    assert!(!display.is_screen_blanked());
+}
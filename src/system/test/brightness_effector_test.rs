@@ -1,9 +1,13 @@
 use crate::{
     armaf::{spawn_server, Effector, EffectorMessage},
     external::{
-        brightness as bs, brightness::BrightnessController, dependency_provider::DependencyProvider,
+        brightness as bs,
+        brightness::{BrightnessController, DimState},
+        dependency_provider::DependencyProvider,
+    },
+    system::brightness_effector::{
+        BrightnessAdjuster, BrightnessEffector, BrightnessEffectorActor,
     },
-    system::brightness_effector::{BrightnessEffector, BrightnessEffectorActor},
 };
 use std::time::Duration;
 
@@ -19,14 +23,74 @@ async fn test_basic_flow() {
         .await
         .expect("Failed to dim display");
     assert_eq!(brightness.get_brightness().await.unwrap(), 40);
-    assert_eq!(res, 1);
+    assert_eq!(res.applied_effects, 1);
+    assert_eq!(
+        res.status.as_deref(),
+        Some("dimmed to 50% of brightness 80")
+    );
 
     let res = port
         .request(EffectorMessage::Rollback)
         .await
         .expect("Failed to undim display");
     assert_eq!(brightness.get_brightness().await.unwrap(), 80);
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
+    assert_eq!(res.status, None);
+}
+
+#[tokio::test]
+async fn test_ensured_state() {
+    let brightness = bs::mock::MockBrightnessController::new(80);
+    let port = spawn_server(BrightnessEffectorActor::new(brightness.clone(), 0.5))
+        .await
+        .expect("Actor initialization failed");
+    port.request(EffectorMessage::EnsureRolledBack)
+        .await
+        .expect("Ensuring an undimmed display failed");
+    assert_eq!(brightness.get_brightness().await.unwrap(), 80);
+    for _ in 0..2 {
+        let res = port
+            .request(EffectorMessage::EnsureApplied)
+            .await
+            .expect("Ensuring a dimmed display failed");
+        assert_eq!(brightness.get_brightness().await.unwrap(), 40);
+        assert_eq!(res.applied_effects, 1);
+    }
+    port.request(EffectorMessage::Execute)
+        .await
+        .expect_err("Dimming an already dimmed display succeeded");
+    for _ in 0..2 {
+        let res = port
+            .request(EffectorMessage::EnsureRolledBack)
+            .await
+            .expect("Ensuring an undimmed display failed");
+        assert_eq!(brightness.get_brightness().await.unwrap(), 80);
+        assert_eq!(res.applied_effects, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_redim_after_reset() {
+    let brightness = bs::mock::MockBrightnessController::new(80);
+    let port = spawn_server(BrightnessEffectorActor::new(brightness.clone(), 0.5))
+        .await
+        .expect("Actor initialization failed");
+    port.request(EffectorMessage::Execute)
+        .await
+        .expect("Failed to dim display");
+    // E.g. a monitor was connected
+    brightness.set_brightness(100).await.unwrap();
+    let res = port
+        .request(EffectorMessage::EnsureApplied)
+        .await
+        .expect("Failed to dim display again");
+    assert_eq!(brightness.get_brightness().await.unwrap(), 40);
+    assert_eq!(res.applied_effects, 1);
+
+    port.request(EffectorMessage::Rollback)
+        .await
+        .expect("Failed to undim display");
+    assert_eq!(brightness.get_brightness().await.unwrap(), 80);
 }
 
 #[tokio::test]
@@ -86,14 +150,14 @@ async fn test_default_config() {
         .await
         .expect("Failed to dim display");
     assert_eq!(brightness.get_brightness().await.unwrap(), 25);
-    assert_eq!(res, 1);
+    assert_eq!(res.applied_effects, 1);
 
     let res = port
         .request(EffectorMessage::Rollback)
         .await
         .expect("Failed to undim display");
     assert_eq!(brightness.get_brightness().await.unwrap(), 50);
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
 }
 
 #[tokio::test]
@@ -105,3 +169,34 @@ async fn test_broken_config() {
         .await
         .expect_err("Actor initialization succeeded");
 }
+
+#[tokio::test]
+async fn test_adjustment_while_dimmed() {
+    let brightness = bs::mock::MockBrightnessController::new(80);
+    let dim_state = DimState::default();
+    let adjuster = BrightnessAdjuster::new(brightness.clone(), dim_state.clone());
+    let port = spawn_server(
+        BrightnessEffectorActor::new(brightness.clone(), 0.5).with_dim_state(dim_state.clone()),
+    )
+    .await
+    .expect("Actor initialization failed");
+
+    assert_eq!(adjuster.step(-10).await.unwrap(), 70);
+    assert_eq!(dim_state.original_brightness(), None);
+    port.request(EffectorMessage::Execute)
+        .await
+        .expect("Failed to dim display");
+    assert_eq!(brightness.get_brightness().await.unwrap(), 35);
+
+    // Steps start from the brightness before dimming
+    assert_eq!(adjuster.step(20).await.unwrap(), 90);
+    assert_eq!(brightness.get_brightness().await.unwrap(), 90);
+    assert_eq!(dim_state.original_brightness(), Some(90));
+    assert_eq!(adjuster.set(150).await.unwrap(), 100);
+
+    port.request(EffectorMessage::Rollback)
+        .await
+        .expect("Failed to undim display");
+    assert_eq!(brightness.get_brightness().await.unwrap(), 100);
+    assert_eq!(adjuster.step(-120).await.unwrap(), 0);
+}
@@ -11,12 +11,14 @@ use crate::{
 async fn test_original_config_saving() {
     let display = ds::mock::Interface::new(-1);
     let ds_controller = display.get_controller();
-    ds_controller.set_dpms_state(true).unwrap();
+    ds_controller.set_dpms_state(true).await.unwrap();
     ds_controller
         .set_dpms_level(ds::DPMSLevel::Standby)
+        .await
         .unwrap();
     ds_controller
         .set_dpms_timeouts(ds::DPMSTimeouts::new(42, 43, 44))
+        .await
         .unwrap();
     let port = spawn_server(DPMSEffectorActor::new(display.get_controller()))
         .await
@@ -24,22 +26,22 @@ async fn test_original_config_saving() {
 
     // Test if the display effector sets its own state when it's initialized
     assert_eq!(
-        ds_controller.get_dpms_level().unwrap(),
+        ds_controller.get_dpms_level().await.unwrap(),
         Some(ds::DPMSLevel::On)
     );
     assert_eq!(
-        ds_controller.get_dpms_timeouts().unwrap(),
+        ds_controller.get_dpms_timeouts().await.unwrap(),
         ds::DPMSTimeouts::new(0, 0, 0)
     );
 
     // Test if the display effector resets the state to original when it's terminated
     port.await_shutdown().await;
     assert_eq!(
-        ds_controller.get_dpms_level().unwrap(),
+        ds_controller.get_dpms_level().await.unwrap(),
         Some(ds::DPMSLevel::Standby)
     );
     assert_eq!(
-        ds_controller.get_dpms_timeouts().unwrap(),
+        ds_controller.get_dpms_timeouts().await.unwrap(),
         ds::DPMSTimeouts::new(42, 43, 44)
     );
 }
@@ -53,38 +55,50 @@ async fn test_basic_flow() {
         .await
         .expect("Actor initialization failed");
 
+    let res = port
+        .request(EffectorMessage::CanExecute)
+        .await
+        .expect("Display can't be turned off");
+    assert_eq!(res.applied_effects, 0);
+
     let res = port
         .request(EffectorMessage::Execute)
         .await
         .expect("Failed to turn display off");
     assert_eq!(
-        ds_controller.get_dpms_level().unwrap(),
+        ds_controller.get_dpms_level().await.unwrap(),
         Some(ds::DPMSLevel::Off)
     );
-    assert_eq!(res, 1);
+    assert_eq!(res.applied_effects, 1);
 
     let res = port
         .request(EffectorMessage::Rollback)
         .await
         .expect("Failed to turn display on");
     assert_eq!(
-        ds_controller.get_dpms_level().unwrap(),
+        ds_controller.get_dpms_level().await.unwrap(),
         Some(ds::DPMSLevel::On)
     );
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
 }
 
 #[tokio::test]
 async fn test_failing_display_server() {
     let display = ds::mock::Interface::new(-1);
     let ds_controller = display.get_controller();
-    ds_controller.set_dpms_level(ds::DPMSLevel::On).unwrap();
+    ds_controller
+        .set_dpms_level(ds::DPMSLevel::On)
+        .await
+        .unwrap();
     let port = spawn_server(DPMSEffectorActor::new(display.get_controller()))
         .await
         .expect("Actor initialization failed");
 
     display.set_failure_mode(true);
 
+    port.request(EffectorMessage::CanExecute)
+        .await
+        .expect_err("No error reported on failing display server controller");
     port.request(EffectorMessage::Execute)
         .await
         .expect_err("No error reported on failing display server controller");
@@ -93,5 +107,94 @@ async fn test_failing_display_server() {
         .request(EffectorMessage::CurrentlyAppliedEffects)
         .await
         .expect("Failed to get applied effect count");
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
+}
+
+#[tokio::test]
+async fn test_ensured_state() {
+    let display = ds::mock::Interface::new(-1);
+    let ds_controller = display.get_controller();
+
+    let port = spawn_server(DPMSEffectorActor::new(display.get_controller()))
+        .await
+        .expect("Actor initialization failed");
+
+    // Someone else has turned the display off
+    ds_controller
+        .set_dpms_level(ds::DPMSLevel::Off)
+        .await
+        .unwrap();
+    let res = port
+        .request(EffectorMessage::EnsureApplied)
+        .await
+        .expect("Failed to ensure the display is off");
+    assert_eq!(res.applied_effects, 1);
+    let res = port
+        .request(EffectorMessage::EnsureApplied)
+        .await
+        .expect("Ensuring the display is off isn't idempotent");
+    assert_eq!(res.applied_effects, 1);
+    assert_eq!(
+        ds_controller.get_dpms_level().await.unwrap(),
+        Some(ds::DPMSLevel::Off)
+    );
+
+    for _ in 0..2 {
+        let res = port
+            .request(EffectorMessage::EnsureRolledBack)
+            .await
+            .expect("Failed to ensure the display is on");
+        assert_eq!(res.applied_effects, 0);
+        assert_eq!(
+            ds_controller.get_dpms_level().await.unwrap(),
+            Some(ds::DPMSLevel::On)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_blanking_without_dpms() {
+    let display = ds::mock::Interface::new(-1);
+    display.set_dpms_capability(false);
+    let ds_controller = display.get_controller();
+
+    let port = spawn_server(DPMSEffectorActor::new(display.get_controller()))
+        .await
+        .expect("Actor initialization failed");
+
+    let res = port
+        .request(EffectorMessage::CanExecute)
+        .await
+        .expect("Display can't be blanked");
+    assert_eq!(res.applied_effects, 0);
+    assert!(res.status.is_some());
+
+    let res = port
+        .request(EffectorMessage::Execute)
+        .await
+        .expect("Failed to blank the display");
+    assert_eq!(res.applied_effects, 1);
+    assert!(display.is_screen_blanked());
+    // DPMS configuration is left alone
+    assert_eq!(
+        ds_controller.get_dpms_level().await.unwrap(),
+        Some(ds::DPMSLevel::On)
+    );
+    assert_eq!(
+        ds_controller.get_dpms_timeouts().await.unwrap(),
+        ds::DPMSTimeouts::new(10, 20, 30)
+    );
+
+    let res = port
+        .request(EffectorMessage::Rollback)
+        .await
+        .expect("Failed to unblank the display");
+    assert_eq!(res.applied_effects, 0);
+    assert!(!display.is_screen_blanked());
+
+    port.request(EffectorMessage::EnsureApplied)
+        .await
+        .expect("Failed to ensure the display is blanked");
+    port.await_shutdown().await;
+    assert!(!display.is_screen_blanked());
 }
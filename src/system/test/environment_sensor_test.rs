@@ -0,0 +1,18 @@
+use std::fs;
+
+use crate::system::environment_sensor::read_max_temperature;
+
+#[tokio::test]
+async fn test_read_max_temperature() {
+    let directory = std::env::temp_dir().join(format!("energia-thermal-{}", std::process::id()));
+    for (zone, temperature) in [("thermal_zone0", "45000\n"), ("thermal_zone1", "71500\n")] {
+        fs::create_dir_all(directory.join(zone)).unwrap();
+        fs::write(directory.join(zone).join("temp"), temperature).unwrap();
+    }
+    fs::create_dir_all(directory.join("cooling_device0")).unwrap();
+    fs::write(directory.join("cooling_device0").join("temp"), "99000").unwrap();
+
+    assert_eq!(read_max_temperature(&directory).await, Some(71.5));
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(read_max_temperature(&directory).await, None);
+}
@@ -0,0 +1,253 @@
+//! A fake logind served on a private peer-to-peer D-Bus connection, so that
+//! the components talking to logind can be tested without a real one
+
+use crate::external::dbus::ConnectionFactory;
+use std::{
+    os::unix::{
+        io::{FromRawFd, IntoRawFd},
+        net::UnixStream,
+    },
+    sync::{Arc, Mutex},
+};
+use zbus::{
+    dbus_interface,
+    zvariant::{OwnedFd, OwnedObjectPath},
+    ConnectionBuilder, Guid, SignalContext,
+};
+
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const SESSION_PATH: &str = "/org/freedesktop/login1/session/fake";
+/// Value of logind's InhibitDelayMaxUSec
+const INHIBIT_DELAY_MAX: u64 = 5_000_000;
+
+/// What the fake logind has been asked to do
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogindState {
+    pub idle_hint: bool,
+    pub locked_hint: bool,
+    /// Number of Suspend calls
+    pub suspends: usize,
+    /// Inhibitors as returned by ListInhibitors: what, who, why, mode, UID
+    /// and PID
+    pub inhibitors: Vec<(String, String, String, String, u32, u32)>,
+    /// The last brightness set through SetBrightness
+    pub brightness: Option<(String, String, u32)>,
+}
+
+type SharedState = Arc<Mutex<LogindState>>;
+
+fn lock(state: &SharedState) -> std::sync::MutexGuard<'_, LogindState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct Manager {
+    state: SharedState,
+    /// Our ends of the file descriptors handed out by Inhibit
+    inhibitor_fds: Mutex<Vec<UnixStream>>,
+}
+
+#[dbus_interface(name = "org.freedesktop.login1.Manager")]
+impl Manager {
+    #[dbus_interface(name = "GetSessionByPID")]
+    async fn get_session_by_pid(&self, _pid: u32) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+    }
+
+    /// Pretend that the computer went to sleep and woke up right away
+    async fn suspend(
+        &self,
+        #[zbus(signal_context)] context: SignalContext<'_>,
+        _interactive: bool,
+    ) -> zbus::fdo::Result<()> {
+        lock(&self.state).suspends += 1;
+        Self::prepare_for_sleep(&context, true).await?;
+        Self::prepare_for_sleep(&context, false).await?;
+        Ok(())
+    }
+
+    async fn inhibit(
+        &self,
+        what: String,
+        who: String,
+        why: String,
+        mode: String,
+    ) -> zbus::fdo::Result<OwnedFd> {
+        let (ours, theirs) =
+            UnixStream::pair().map_err(|e| zbus::fdo::Error::IOError(e.to_string()))?;
+        lock(&self.state)
+            .inhibitors
+            .push((what, who, why, mode, 1000, std::process::id()));
+        self.inhibitor_fds
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(ours);
+        // SAFETY: the descriptor has just been released by the stream
+        Ok(unsafe { OwnedFd::from_raw_fd(theirs.into_raw_fd()) })
+    }
+
+    async fn list_inhibitors(&self) -> Vec<(String, String, String, String, u32, u32)> {
+        lock(&self.state).inhibitors.clone()
+    }
+
+    #[dbus_interface(property)]
+    async fn block_inhibited(&self) -> String {
+        self.inhibited("block")
+    }
+
+    #[dbus_interface(property)]
+    async fn delay_inhibited(&self) -> String {
+        self.inhibited("delay")
+    }
+
+    #[dbus_interface(property, name = "InhibitDelayMaxUSec")]
+    async fn inhibit_delay_max_usec(&self) -> u64 {
+        INHIBIT_DELAY_MAX
+    }
+
+    #[dbus_interface(signal)]
+    async fn prepare_for_sleep(context: &SignalContext<'_>, start: bool) -> zbus::Result<()>;
+}
+
+impl Manager {
+    fn inhibited(&self, mode: &str) -> String {
+        let state = lock(&self.state);
+        let mut types: Vec<&str> = state
+            .inhibitors
+            .iter()
+            .filter(|inhibitor| inhibitor.3 == mode)
+            .flat_map(|inhibitor| inhibitor.0.split(':'))
+            .collect();
+        types.sort_unstable();
+        types.dedup();
+        types.join(":")
+    }
+}
+
+struct Session {
+    state: SharedState,
+}
+
+#[dbus_interface(name = "org.freedesktop.login1.Session")]
+impl Session {
+    async fn set_idle_hint(&self, idle: bool) {
+        lock(&self.state).idle_hint = idle;
+    }
+
+    async fn set_locked_hint(&self, locked: bool) {
+        lock(&self.state).locked_hint = locked;
+    }
+
+    async fn set_brightness(&self, subsystem: String, name: String, brightness: u32) {
+        lock(&self.state).brightness = Some((subsystem, name, brightness));
+    }
+
+    #[dbus_interface(property)]
+    async fn idle_hint(&self) -> bool {
+        lock(&self.state).idle_hint
+    }
+
+    #[dbus_interface(property)]
+    async fn locked_hint(&self) -> bool {
+        lock(&self.state).locked_hint
+    }
+}
+
+/// The fake logind, which keeps serving until it's dropped
+pub struct FakeLogind {
+    state: SharedState,
+    server: zbus::Connection,
+    client: zbus::Connection,
+}
+
+impl FakeLogind {
+    pub async fn start() -> zbus::Result<FakeLogind> {
+        let state = SharedState::default();
+        let (server_stream, client_stream) = tokio::net::UnixStream::pair()?;
+        let guid = Guid::generate();
+        let server = ConnectionBuilder::unix_stream(server_stream)
+            .server(&guid)
+            .p2p()
+            .serve_at(
+                MANAGER_PATH,
+                Manager {
+                    state: state.clone(),
+                    inhibitor_fds: Mutex::default(),
+                },
+            )?
+            .serve_at(
+                SESSION_PATH,
+                Session {
+                    state: state.clone(),
+                },
+            )?
+            .build();
+        let client = ConnectionBuilder::unix_stream(client_stream).p2p().build();
+        let (server, client) = tokio::try_join!(server, client)?;
+        Ok(FakeLogind {
+            state,
+            server,
+            client,
+        })
+    }
+
+    /// A connection factory whose system bus is connected to the fake
+    pub fn connection_factory(&self) -> ConnectionFactory {
+        ConnectionFactory::with_connections(Some(self.client.clone()), None)
+    }
+
+    pub fn state(&self) -> LogindState {
+        lock(&self.state).clone()
+    }
+
+    pub fn set_locked_hint(&self, locked: bool) {
+        lock(&self.state).locked_hint = locked;
+    }
+
+    /// Add an inhibitor and announce the change of the inhibited operations
+    pub async fn add_inhibitor(&self, what: &str, who: &str, why: &str, mode: &str) {
+        lock(&self.state).inhibitors.push((
+            what.to_owned(),
+            who.to_owned(),
+            why.to_owned(),
+            mode.to_owned(),
+            1000,
+            std::process::id(),
+        ));
+        self.announce_inhibitor_change().await;
+    }
+
+    /// Remove all the inhibitors added by the given application
+    pub async fn remove_inhibitors(&self, who: &str) {
+        lock(&self.state)
+            .inhibitors
+            .retain(|inhibitor| inhibitor.1 != who);
+        self.announce_inhibitor_change().await;
+    }
+
+    /// Emit PrepareForSleep, as logind does before the computer goes to
+    /// sleep (`start` being true) and after it wakes up
+    pub async fn prepare_for_sleep(&self, start: bool) {
+        let interface_ref = self
+            .server
+            .object_server()
+            .interface::<_, Manager>(MANAGER_PATH)
+            .await
+            .unwrap();
+        Manager::prepare_for_sleep(interface_ref.signal_context(), start)
+            .await
+            .unwrap();
+    }
+
+    async fn announce_inhibitor_change(&self) {
+        let interface_ref = self
+            .server
+            .object_server()
+            .interface::<_, Manager>(MANAGER_PATH)
+            .await
+            .unwrap();
+        let context = interface_ref.signal_context();
+        let interface = interface_ref.get().await;
+        interface.block_inhibited_changed(context).await.unwrap();
+        interface.delay_inhibited_changed(context).await.unwrap();
+    }
+}
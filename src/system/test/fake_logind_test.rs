@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use super::fake_logind::FakeLogind;
+use crate::{
+    armaf::{spawn_server, Effector, EffectorMessage, EventBus},
+    external::dependency_provider::DependencyProvider,
+    system::{
+        inhibition_sensor::{GetInhibitions, InhibitionSensor},
+        lock_effector::LockEffector,
+        session_effector::SessionEffector,
+        sleep_effector::SleepEffectorActor,
+        sleep_sensor::{ReadyToSleep, SleepSensor, SleepUpdate},
+    },
+};
+use logind_zbus::manager::Mode;
+
+#[tokio::test]
+async fn test_session_effector() {
+    let logind = FakeLogind::start().await.unwrap();
+    let mut di = DependencyProvider::make_mock(Some(logind.connection_factory()));
+    let port = SessionEffector.spawn(None, &mut di).await.unwrap();
+
+    let response = port.request(EffectorMessage::Execute).await.unwrap();
+    assert_eq!(response.applied_effects, 1);
+    assert!(logind.state().idle_hint);
+    port.request(EffectorMessage::Rollback).await.unwrap();
+    assert!(!logind.state().idle_hint);
+
+    // The hint of a locked session is left to the lock effector
+    port.request(EffectorMessage::Execute).await.unwrap();
+    logind.set_locked_hint(true);
+    port.request(EffectorMessage::Rollback).await.unwrap();
+    assert!(logind.state().idle_hint);
+}
+
+#[tokio::test]
+#[cfg(not(tarpaulin))] // Cannot run Tarpaulin test with external commands, see https://github.com/xd009642/tarpaulin/issues/971
+async fn test_lock_effector() {
+    let logind = FakeLogind::start().await.unwrap();
+    let mut di = DependencyProvider::make_mock(Some(logind.connection_factory()));
+    let config = toml::toml! {
+        command = "sleep"
+        args = ["1"]
+    };
+    let port = LockEffector.spawn(Some(config), &mut di).await.unwrap();
+
+    port.request(EffectorMessage::Execute).await.unwrap();
+    let state = logind.state();
+    assert!(state.locked_hint);
+    assert!(state.idle_hint);
+    port.request(EffectorMessage::Rollback).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let state = logind.state();
+    assert!(!state.locked_hint);
+    assert!(!state.idle_hint);
+
+    // Locked by another program
+    logind.set_locked_hint(true);
+    let response = port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(response.applied_effects, 1);
+}
+
+#[tokio::test]
+async fn test_sleep_effector() {
+    let logind = FakeLogind::start().await.unwrap();
+    let mut factory = logind.connection_factory();
+    let port = spawn_server(SleepEffectorActor::new(factory.get_system().await.unwrap()))
+        .await
+        .unwrap();
+
+    port.request(EffectorMessage::Execute).await.unwrap();
+    assert_eq!(logind.state().suspends, 1);
+    let response = port.request(EffectorMessage::Rollback).await.unwrap();
+    assert_eq!(response.applied_effects, 0);
+}
+
+#[tokio::test]
+async fn test_inhibition_sensor() {
+    let logind = FakeLogind::start().await.unwrap();
+    let mut factory = logind.connection_factory();
+    let port = spawn_server(InhibitionSensor::new(factory.get_system().await.unwrap()))
+        .await
+        .unwrap();
+    assert!(port.request(GetInhibitions).await.unwrap().is_empty());
+
+    logind
+        .add_inhibitor("idle:sleep", "mpv", "Playing video", "block")
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let inhibitors = port.request(GetInhibitions).await.unwrap();
+    assert_eq!(inhibitors.len(), 1);
+    assert_eq!(inhibitors[0].inhibitor.who(), "mpv");
+    assert_eq!(inhibitors[0].inhibitor.why(), "Playing video");
+    assert_eq!(inhibitors[0].inhibitor.mode(), Mode::Block);
+
+    logind.remove_inhibitors("mpv").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(port.request(GetInhibitions).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_sleep_sensor() {
+    let logind = FakeLogind::start().await.unwrap();
+    let mut factory = logind.connection_factory();
+    let sensor = SleepSensor::new(factory.get_system().await.unwrap());
+    let events = EventBus::new(3);
+    let mut receiver = events.subscribe::<SleepUpdate>();
+    let handle = sensor.spawn(&events).await.expect("Sensor failed to start");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let inhibitors = logind.state().inhibitors;
+    assert_eq!(inhibitors.len(), 1);
+    assert_eq!(inhibitors[0].0, "sleep");
+    assert_eq!(inhibitors[0].3, "delay");
+
+    logind.prepare_for_sleep(true).await;
+    match receiver.recv().await.unwrap() {
+        SleepUpdate::GoingToSleep(confirmation, _) => {
+            confirmation.send(ReadyToSleep).await.unwrap()
+        }
+        SleepUpdate::WokenUp => panic!("Expected a notification about going to sleep"),
+    }
+    logind.prepare_for_sleep(false).await;
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        SleepUpdate::WokenUp
+    ));
+    handle.await_shutdown().await;
+}
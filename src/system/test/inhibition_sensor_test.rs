@@ -28,6 +28,7 @@ async fn test_inhibition_sensor() {
     let inhibitor_count = inhibitors.len();
     let our_inhibitor = inhibitors
         .iter()
+        .map(|i| &i.inhibitor)
         .find(|i| i.who() == "energia tests")
         .unwrap();
     assert_eq!(
@@ -34,13 +34,15 @@ async fn test_basic_flow() {
     assert_eq!(
         port.request(EffectorMessage::CurrentlyAppliedEffects)
             .await
-            .expect("Couldn't get number of effects"),
+            .expect("Couldn't get number of effects")
+            .applied_effects,
         0
     );
     assert_eq!(
         port.request(EffectorMessage::Execute)
             .await
-            .expect("Couldn't lock system"),
+            .expect("Couldn't lock system")
+            .applied_effects,
         1
     );
     let start = Instant::now();
@@ -48,7 +50,8 @@ async fn test_basic_flow() {
     assert_eq!(
         port.request(EffectorMessage::CurrentlyAppliedEffects)
             .await
-            .expect("Couldn't get number of effects"),
+            .expect("Couldn't get number of effects")
+            .applied_effects,
         1
     );
     port.request(EffectorMessage::Execute)
@@ -57,7 +60,8 @@ async fn test_basic_flow() {
     assert_eq!(
         port.request(EffectorMessage::Rollback)
             .await
-            .expect("Couldn't await finish of locker"),
+            .expect("Couldn't await finish of locker")
+            .applied_effects,
         0
     );
     assert!(start.elapsed() > std::time::Duration::from_secs(5));
@@ -70,3 +74,38 @@ async fn test_error_without_config() {
     let mut di = DependencyProvider::make_mock(None);
     assert!(LockEffector.spawn(None, &mut di).await.is_err());
 }
+
+#[tokio::test]
+#[cfg(not(tarpaulin))]
+async fn test_crashing_locker_restarted() {
+    // Exits with an error on the first start, successfully on the second one
+    let marker = std::env::temp_dir().join(format!("energia-locker-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let script = format!(
+        "if [ -e {0} ]; then sleep 1; else touch {0}; exit 1; fi",
+        marker.display()
+    );
+    let mut config = toml::value::Table::new();
+    config.insert("command".to_owned(), "sh".into());
+    config.insert("args".to_owned(), vec!["-c".to_owned(), script].into());
+
+    let mut di =
+        DependencyProvider::make_mock(Some(crate::external::dbus::ConnectionFactory::new()));
+    let port = LockEffector
+        .spawn(Some(toml::Value::Table(config)), &mut di)
+        .await
+        .unwrap();
+    port.request(EffectorMessage::Execute)
+        .await
+        .expect("Couldn't lock system");
+    let start = Instant::now();
+    assert_eq!(
+        port.request(EffectorMessage::Rollback)
+            .await
+            .expect("Crashed locker wasn't restarted")
+            .applied_effects,
+        0
+    );
+    assert!(start.elapsed() > std::time::Duration::from_secs(1));
+    std::fs::remove_file(&marker).unwrap();
+}
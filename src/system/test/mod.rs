@@ -1,8 +1,13 @@
+mod blank_effector_test;
 mod brightness_effector_test;
 mod dpms_effector_test;
+mod environment_sensor_test;
+mod fake_logind;
+mod fake_logind_test;
 mod inhibition_sensor_test;
 mod lock_effector_test;
 mod session_effector_test;
 mod sleep_effector_test;
 mod sleep_sensor_test;
+mod sysfs_power_sensor_test;
 mod upower_sensor_test;
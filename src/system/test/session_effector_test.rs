@@ -1,6 +1,6 @@
 use crate::{
-    armaf::{spawn_server, EffectorMessage},
-    external::dbus,
+    armaf::{spawn_server, Effector, EffectorMessage},
+    external::{dbus, dependency_provider::DependencyProvider},
     system::session_effector,
 };
 use anyhow::Result;
@@ -24,29 +24,29 @@ async fn test_happy_path() {
         .request(EffectorMessage::CurrentlyAppliedEffects)
         .await
         .expect("Couldn't get current effect count");
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
 
     let res = port.request(EffectorMessage::Execute).await.unwrap();
     sleep(Duration::from_millis(200)); // See the comment in SessionEffector#handle_message
     assert_eq!(session_proxy.idle_hint().await.unwrap(), true);
-    assert_eq!(res, 1);
+    assert_eq!(res.applied_effects, 1);
 
     let res = port
         .request(EffectorMessage::CurrentlyAppliedEffects)
         .await
         .expect("Couldn't get current effect count");
-    assert_eq!(res, 1);
+    assert_eq!(res.applied_effects, 1);
 
     let res = port.request(EffectorMessage::Rollback).await.unwrap();
     sleep(Duration::from_millis(200)); // See the comment in SessionEffector#handle_message
     assert_eq!(session_proxy.idle_hint().await.unwrap(), false);
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
 
     let res = port
         .request(EffectorMessage::CurrentlyAppliedEffects)
         .await
         .expect("Couldn't get current effect count");
-    assert_eq!(res, 0);
+    assert_eq!(res.applied_effects, 0);
 }
 
 async fn get_session_proxy<'c>(
@@ -59,3 +59,36 @@ async fn get_session_proxy<'c>(
         .build()
         .await?)
 }
+
+#[tokio::test]
+async fn test_without_logind_session() {
+    let mut provider = DependencyProvider::make_mock(None).without_logind_session();
+    let port = session_effector::SessionEffector
+        .spawn(None, &mut provider)
+        .await
+        .expect("Effector should be spawned even without a session");
+    let error = port
+        .request(EffectorMessage::CanExecute)
+        .await
+        .expect_err("Idle hint can't be set without a session");
+    assert!(error.to_string().contains("logind session"));
+    let res = port
+        .request(EffectorMessage::CurrentlyAppliedEffects)
+        .await
+        .unwrap();
+    assert_eq!(res.applied_effects, 0);
+}
+
+#[tokio::test]
+async fn test_additional_display() {
+    let mut provider = DependencyProvider::make_mock(None).for_additional_display();
+    let port = session_effector::SessionEffector
+        .spawn(None, &mut provider)
+        .await
+        .unwrap();
+    let error = port
+        .request(EffectorMessage::CanExecute)
+        .await
+        .expect_err("The session's idle hint mustn't be set for another display");
+    assert!(error.to_string().contains("main display"));
+}
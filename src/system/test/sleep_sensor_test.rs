@@ -4,7 +4,7 @@ use logind_zbus::manager::{InhibitType, Mode};
 use tokio::time::sleep;
 
 use crate::{
-    armaf::{spawn_server, EffectorMessage},
+    armaf::{spawn_server, EffectorMessage, EventBus},
     external::dbus::ConnectionFactory,
     system::{
         sleep_effector::SleepEffectorActor,
@@ -26,7 +26,8 @@ async fn test_happy_path() {
     ))
     .await
     .unwrap();
-    let (handle, sender) = sensor.spawn().await.expect("Sensor failed to start");
+    let events = EventBus::new(3);
+    let handle = sensor.spawn(&events).await.expect("Sensor failed to start");
     sleep(Duration::from_secs(1)).await;
     let inhibitors = manager_proxy.list_inhibitors().await.unwrap();
     println!("{:?}", inhibitors);
@@ -36,14 +37,17 @@ async fn test_happy_path() {
         .contains(&InhibitType::Sleep)
         && inhibitor.who() == "Energia Power Manager"
         && inhibitor.mode() == Mode::Delay));
-    let mut receivers = vec![sender.subscribe(), sender.subscribe()];
+    let mut receivers = vec![
+        events.subscribe::<SleepUpdate>(),
+        events.subscribe::<SleepUpdate>(),
+    ];
     sleep_effector
         .request(EffectorMessage::Execute)
         .await
         .unwrap();
     for receiver in receivers.iter_mut() {
         let message = receiver.recv().await.unwrap();
-        if let SleepUpdate::GoingToSleep(c) = message {
+        if let SleepUpdate::GoingToSleep(c, _) = message {
             c.send(ReadyToSleep).await.unwrap();
         } else {
             unreachable!();
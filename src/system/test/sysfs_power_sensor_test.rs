@@ -0,0 +1,81 @@
+use std::{fs, path::Path};
+
+use crate::system::{sysfs_power_sensor::read_power_status, upower_sensor::PowerStatus};
+
+fn write_supply(directory: &Path, name: &str, attributes: &[(&str, &str)]) {
+    fs::create_dir_all(directory.join(name)).unwrap();
+    for (attribute, value) in attributes {
+        fs::write(directory.join(name).join(attribute), format!("{}\n", value)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_read_power_status() {
+    let directory = std::env::temp_dir().join(format!("energia-power-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    read_power_status(&directory)
+        .await
+        .expect_err("Power status read from an empty directory");
+
+    write_supply(&directory, "AC", &[("type", "Mains"), ("online", "1")]);
+    write_supply(
+        &directory,
+        "BAT0",
+        &[
+            ("type", "Battery"),
+            ("capacity", "80"),
+            ("status", "Charging"),
+        ],
+    );
+    write_supply(
+        &directory,
+        "BAT1",
+        &[
+            ("type", "Battery"),
+            ("capacity", "60"),
+            ("status", "Charging"),
+        ],
+    );
+    // A wireless mouse's battery isn't the computer's
+    write_supply(
+        &directory,
+        "hid-mouse-battery",
+        &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+    );
+    assert_eq!(
+        read_power_status(&directory).await.unwrap(),
+        PowerStatus::External
+    );
+
+    write_supply(&directory, "AC", &[("online", "0")]);
+    assert_eq!(
+        read_power_status(&directory).await.unwrap(),
+        PowerStatus::Battery(70)
+    );
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn test_read_power_status_without_mains() {
+    let directory =
+        std::env::temp_dir().join(format!("energia-power-nomains-{}", std::process::id()));
+    write_supply(
+        &directory,
+        "BAT0",
+        &[
+            ("type", "Battery"),
+            ("capacity", "42"),
+            ("status", "Discharging"),
+        ],
+    );
+    assert_eq!(
+        read_power_status(&directory).await.unwrap(),
+        PowerStatus::Battery(42)
+    );
+    write_supply(&directory, "BAT0", &[("status", "Full")]);
+    assert_eq!(
+        read_power_status(&directory).await.unwrap(),
+        PowerStatus::External
+    );
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,5 +1,5 @@
 use crate::{
-    external::dbus::ConnectionFactory,
+    external::dbus::{mock::MockUPower, ConnectionFactory},
     system::upower_sensor::{PowerStatus, UPowerSensor},
 };
 
@@ -23,3 +23,22 @@ async fn interactive_upower_test() {
     receive_channel.changed().await.unwrap();
     assert_eq!(*receive_channel.borrow_and_update(), PowerStatus::External);
 }
+
+#[tokio::test]
+async fn test_mock_upower() {
+    let upower = MockUPower::start().await.unwrap();
+    let mut receive_channel =
+        UPowerSensor::new(upower.connection_factory().get_system().await.unwrap())
+            .await
+            .unwrap();
+    assert_eq!(*receive_channel.borrow_and_update(), PowerStatus::External);
+    upower.set_power(true, 80.0).await;
+    receive_channel.changed().await.unwrap();
+    assert!(matches!(
+        *receive_channel.borrow_and_update(),
+        PowerStatus::Battery(_)
+    ));
+    upower.set_power(false, 80.0).await;
+    receive_channel.changed().await.unwrap();
+    assert_eq!(*receive_channel.borrow_and_update(), PowerStatus::External);
+}
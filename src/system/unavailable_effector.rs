@@ -0,0 +1,42 @@
+//! Stands in for an effector whose dependencies aren't available, e.g. the
+//! session effector outside of a logind session, so that the schedules using
+//! its effects skip them instead of failing
+
+use crate::armaf::{EffectorMessage, EffectorResponse, Server};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+pub struct UnavailableEffectorActor {
+    effector_name: String,
+    reason: String,
+}
+
+impl UnavailableEffectorActor {
+    /// Create an actor refusing to execute the effects of the named effector
+    /// for the given reason
+    pub fn new(effector_name: &str, reason: &str) -> UnavailableEffectorActor {
+        UnavailableEffectorActor {
+            effector_name: effector_name.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl Server<EffectorMessage, EffectorResponse> for UnavailableEffectorActor {
+    fn get_name(&self) -> String {
+        format!("UnavailableEffector({})", self.effector_name)
+    }
+
+    async fn handle_message(&mut self, payload: EffectorMessage) -> Result<EffectorResponse> {
+        match payload {
+            EffectorMessage::Execute
+            | EffectorMessage::EnsureApplied
+            | EffectorMessage::CanExecute => Err(anyhow!("{}", self.reason)),
+            // Nothing could have been applied
+            EffectorMessage::Rollback
+            | EffectorMessage::EnsureRolledBack
+            | EffectorMessage::CurrentlyAppliedEffects => Ok(EffectorResponse::new(0)),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 //! Detects the computer's power source and battery percentage and notifies
 //! other actors about changes to them
 
+use crate::armaf::{TickService, Ticks};
 use anyhow::Result;
+use std::time::Duration;
 use tokio::sync::watch;
 use tokio_stream::StreamExt;
 use upower_dbus::{DeviceProxy, UPowerProxy};
@@ -23,6 +25,40 @@ impl PowerStatus {
     }
 }
 
+/// A reading of the battery, taken periodically since UPower doesn't
+/// announce changes of the energy rate reliably
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct BatterySample {
+    pub on_battery: bool,
+    pub percentage: f64,
+    /// Power drawn from or charged into the battery, in watts
+    pub energy_rate: f64,
+    /// Estimated time until the battery is empty, zero if it's unknown
+    pub time_to_empty: Duration,
+}
+
+impl BatterySample {
+    /// Power drawn from the battery in watts, [None] when the computer
+    /// doesn't run on battery or the rate is unknown
+    pub fn power_draw(&self) -> Option<f64> {
+        if self.on_battery && self.energy_rate > 0.0 {
+            Some(self.energy_rate)
+        } else {
+            None
+        }
+    }
+
+    /// Estimated time until the battery is empty, [None] when the computer
+    /// doesn't run on battery or the estimate is unknown
+    pub fn remaining_time(&self) -> Option<Duration> {
+        if self.on_battery && !self.time_to_empty.is_zero() {
+            Some(self.time_to_empty)
+        } else {
+            None
+        }
+    }
+}
+
 pub struct UPowerSensor {
     battery_percentage: u64,
     on_battery: bool,
@@ -108,3 +144,69 @@ impl UPowerSensor {
         }
     }
 }
+
+/// Reads the battery's state every period and publishes the readings
+pub struct BatterySampler {
+    upower: UPowerProxy<'static>,
+    display_device: DeviceProxy<'static>,
+    ticks: Ticks,
+    samples_sender: watch::Sender<BatterySample>,
+}
+
+impl BatterySampler {
+    /// Start sampling, terminating once all the receivers of the samples are
+    /// dropped
+    pub async fn spawn(
+        system_connection: zbus::Connection,
+        tick_service: &TickService,
+        period: Duration,
+    ) -> Result<watch::Receiver<BatterySample>> {
+        let upower = UPowerProxy::new(&system_connection).await?;
+        let display_device =
+            UPowerSensor::get_display_device_proxy(&system_connection, &upower).await?;
+        let initial_sample = read_battery_sample(&upower, &display_device).await?;
+        let (samples_sender, samples_receiver) = watch::channel(initial_sample);
+        let mut sampler = BatterySampler {
+            upower,
+            display_device,
+            ticks: tick_service.subscribe(period),
+            samples_sender,
+        };
+        tokio::spawn(async move {
+            sampler.run().await;
+        });
+        Ok(samples_receiver)
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.samples_sender.closed() => {
+                    log::debug!("All receivers closed, terminating");
+                    return;
+                }
+                _ = self.ticks.tick() => match read_battery_sample(&self.upower, &self.display_device).await {
+                    Ok(sample) => {
+                        if *self.samples_sender.borrow() != sample {
+                            log::trace!("Battery sample: {:?}", sample);
+                            let _ = self.samples_sender.send(sample);
+                        }
+                    }
+                    Err(e) => log::error!("Couldn't sample the battery: {}", e),
+                },
+            }
+        }
+    }
+}
+
+async fn read_battery_sample(
+    upower: &UPowerProxy<'_>,
+    display_device: &DeviceProxy<'_>,
+) -> Result<BatterySample> {
+    Ok(BatterySample {
+        on_battery: upower.on_battery().await?,
+        percentage: display_device.percentage().await?,
+        energy_rate: display_device.energy_rate().await?,
+        time_to_empty: Duration::from_secs(display_device.time_to_empty().await?.max(0) as u64),
+    })
+}